@@ -0,0 +1,219 @@
+//! Configuration of the chats this instance of the bot reports to.
+//!
+//! Loaded once on startup from a TOML file, `anti_nft_spam_bot.toml` in the working
+//! directory by default or whatever `ANTI_CONFIG_PATH` points to, like this:
+//!
+//! ```toml
+//! control_chat_ids = [-1002065680710, -1001234567890]
+//! review_log_channel_id = -1002128704357
+//! ```
+//!
+//! Then `ANTI_CONTROL_CHAT_IDS` (comma separated) and `ANTI_REVIEW_LOG_CHANNEL_ID`
+//! environment variables override values in it, if set. Anything not set
+//! anywhere falls back to the values used by [@Anti_NFT_Spam_Bot](https://t.me/Anti_NFT_Spam_Bot),
+//! see [`DEFAULT_CONTROL_CHAT_ID`] and [`DEFAULT_REVIEW_LOG_CHANNEL_ID`].
+
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+/// Default ID of a private chat with the developers of the bot,
+/// as well as volunteers who partake in manual review of links for spam.
+pub const DEFAULT_CONTROL_CHAT_ID: ChatId = ChatId(-1002065680710);
+
+/// Default ID of a private channel used for logging manual reviews of URLs.
+/// This is primarily to spot abuse and to note which URLs the bot
+/// could have caught automatically but did not.
+pub const DEFAULT_REVIEW_LOG_CHANNEL_ID: ChatId = ChatId(-1002128704357);
+
+/// Path of the config file to use if `ANTI_CONFIG_PATH` isn't set.
+const DEFAULT_CONFIG_PATH: &str = "anti_nft_spam_bot.toml";
+
+static CONFIG: OnceLock<BotConfig> = OnceLock::new();
+
+/// The config file as written, before applying environment variables and defaults.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    control_chat_ids: Option<Vec<i64>>,
+    /// 0 to not log reviews.
+    review_log_channel_id: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BotConfig {
+    /// Chats of people who can do reviews. Never empty.
+    control_chat_ids: Vec<ChatId>,
+    review_log_channel_id: Option<ChatId>,
+}
+
+impl BotConfig {
+    /// Load the config from the config file and environment variables,
+    /// and make it available through [`BotConfig::get`].
+    ///
+    /// # Panics
+    /// Panics if the config can't be loaded or has no control chats.
+    pub fn init() {
+        let config = match Self::load() {
+            Ok(config) => config,
+            Err(e) => panic!("Failed to load the bot config: {}", e),
+        };
+
+        log::info!(
+            "Control chats: {:?}, review log channel: {:?}",
+            config.control_chat_ids,
+            config.review_log_channel_id
+        );
+
+        if CONFIG.set(config).is_err() {
+            log::warn!("Tried to load the bot config twice.");
+        }
+    }
+
+    /// Make the default config available through [`BotConfig::get`],
+    /// for tests that get to code using it.
+    #[cfg(test)]
+    pub fn init_default() {
+        let _ = CONFIG.set(Self::from_sources(None, None, None).unwrap());
+    }
+
+    /// Get the config loaded by [`BotConfig::init`].
+    ///
+    /// # Panics
+    /// Panics if it wasn't loaded yet.
+    pub fn get() -> &'static BotConfig {
+        CONFIG.get().expect("The bot config is loaded on startup")
+    }
+
+    fn load() -> Result<BotConfig, String> {
+        let (path, path_is_explicit) = match std::env::var("ANTI_CONFIG_PATH") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_PATH.to_string(), false),
+        };
+
+        let file = match std::fs::read_to_string(&path) {
+            Ok(file) => Some(file),
+            // Not having the default config file is fine, but not having
+            // the one specifically asked for is not.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !path_is_explicit => None,
+            Err(e) => return Err(format!("can't read {}: {}", path, e)),
+        };
+
+        let control_chat_ids = std::env::var("ANTI_CONTROL_CHAT_IDS").ok();
+        let review_log_channel_id = std::env::var("ANTI_REVIEW_LOG_CHANNEL_ID").ok();
+
+        Self::from_sources(
+            file.as_deref(),
+            control_chat_ids.as_deref(),
+            review_log_channel_id.as_deref(),
+        )
+        .map_err(|e| format!("{} (config file: {})", e, path))
+    }
+
+    /// Make the config out of the contents of the config file and values
+    /// of the environment variables, if present.
+    fn from_sources(
+        file: Option<&str>,
+        control_chat_ids_env: Option<&str>,
+        review_log_channel_id_env: Option<&str>,
+    ) -> Result<BotConfig, String> {
+        let file: ConfigFile = match file {
+            Some(file) => toml::from_str(file).map_err(|e| e.to_string())?,
+            None => ConfigFile::default(),
+        };
+
+        let parse_id = |id: &str| {
+            id.trim()
+                .parse::<i64>()
+                .map_err(|_| format!("{:?} is not a valid chat ID", id))
+        };
+
+        let control_chat_ids = match control_chat_ids_env {
+            Some(ids) => ids
+                .split(',')
+                .filter(|id| !id.trim().is_empty())
+                .map(parse_id)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => file
+                .control_chat_ids
+                .unwrap_or(vec![DEFAULT_CONTROL_CHAT_ID.0]),
+        };
+
+        if control_chat_ids.is_empty() {
+            return Err(
+                "no control chats are configured, so nobody could review links".to_string(),
+            );
+        }
+
+        let review_log_channel_id = match review_log_channel_id_env {
+            Some(id) if id.trim().is_empty() => 0,
+            Some(id) => parse_id(id)?,
+            None => file
+                .review_log_channel_id
+                .unwrap_or(DEFAULT_REVIEW_LOG_CHANNEL_ID.0),
+        };
+
+        Ok(BotConfig {
+            control_chat_ids: control_chat_ids.into_iter().map(ChatId).collect(),
+            review_log_channel_id: (review_log_channel_id != 0)
+                .then_some(ChatId(review_log_channel_id)),
+        })
+    }
+
+    /// Chats whose members can do reviews.
+    pub fn control_chats(&self) -> &[ChatId] {
+        &self.control_chat_ids
+    }
+
+    /// Check if members of this chat can do reviews.
+    pub fn is_control_chat(&self, chat: ChatId) -> bool {
+        self.control_chat_ids.contains(&chat)
+    }
+
+    /// Chats to notify about new links to review and other things reviewers should know.
+    pub fn review_notify_targets(&self) -> &[ChatId] {
+        &self.control_chat_ids
+    }
+
+    /// Channel to log manual reviews to, if any.
+    pub fn review_log_channel(&self) -> Option<ChatId> {
+        self.review_log_channel_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_sources() {
+        let defaults = BotConfig::from_sources(None, None, None).unwrap();
+        assert_eq!(defaults.control_chats(), &[DEFAULT_CONTROL_CHAT_ID]);
+        assert_eq!(
+            defaults.review_log_channel(),
+            Some(DEFAULT_REVIEW_LOG_CHANNEL_ID)
+        );
+
+        let file = "control_chat_ids = [-100, -200]\nreview_log_channel_id = 0\n";
+        let from_file = BotConfig::from_sources(Some(file), None, None).unwrap();
+        assert_eq!(from_file.control_chats(), &[ChatId(-100), ChatId(-200)]);
+        assert!(from_file.is_control_chat(ChatId(-200)));
+        assert!(!from_file.is_control_chat(DEFAULT_CONTROL_CHAT_ID));
+        assert_eq!(from_file.review_log_channel(), None);
+
+        // Environment variables win.
+        let from_env =
+            BotConfig::from_sources(Some(file), Some("-300, -400"), Some("-500")).unwrap();
+        assert_eq!(from_env.control_chats(), &[ChatId(-300), ChatId(-400)]);
+        assert_eq!(from_env.review_log_channel(), Some(ChatId(-500)));
+
+        // Nobody to review links is not okay.
+        assert!(BotConfig::from_sources(None, Some(""), None).is_err());
+        assert!(BotConfig::from_sources(Some("control_chat_ids = []"), None, None).is_err());
+
+        // Neither is garbage.
+        assert!(BotConfig::from_sources(None, Some("-100,lol"), None).is_err());
+        assert!(BotConfig::from_sources(Some("control_chat_id = -100"), None, None).is_err());
+    }
+}
@@ -9,7 +9,7 @@ use std::{
 use notify::{RecursiveMode, Watcher};
 use teloxide::Bot;
 
-use crate::types::IsSpam;
+use crate::{config::BotConfig, types::IsSpam};
 use parser::Line;
 
 static LIST_FILE: &str = "spam_website_list.txt";
@@ -101,9 +101,9 @@ async fn ingest_list_to_database(bot: &Bot, database: &super::Database) -> std::
 
                     // Don't care if this fails. What can we do, log it?
                     // The error above will show up in the log anyway lol
-                    let _ = bot
-                        .send_message(crate::CONTROL_CHAT_ID, error_message)
-                        .await;
+                    for chat in BotConfig::get().control_chats() {
+                        let _ = bot.send_message(*chat, error_message.clone()).await;
+                    }
                 }
                 continue;
             }
@@ -121,13 +121,17 @@ async fn ingest_list_to_database(bot: &Bot, database: &super::Database) -> std::
 
         if database_result.is_ok() {
             database_result = match line {
-                Line::Url(url) => database.add_url(&url, IsSpam::Yes, true, false).await,
+                Line::Url(url) => {
+                    database
+                        .add_url(&url, IsSpam::Yes, true, false, None, None)
+                        .await
+                }
                 Line::Domain {
                     domain,
                     example_url,
                 } => {
                     database
-                        .add_domain(&domain, Some(&example_url), IsSpam::Yes, true, false)
+                        .add_domain(&domain, Some(&example_url), IsSpam::Yes, true, false, None)
                         .await
                 }
             };
@@ -138,9 +142,9 @@ async fn ingest_list_to_database(bot: &Bot, database: &super::Database) -> std::
             log::warn!("{}", error_message);
             // Don't care if this fails. What can we do, log it?
             // The error above will show up in the log anyway lol
-            let _ = bot
-                .send_message(crate::CONTROL_CHAT_ID, error_message)
-                .await;
+            for chat in BotConfig::get().control_chats() {
+                let _ = bot.send_message(*chat, error_message.clone()).await;
+            }
             return Err(Error::new(ErrorKind::BrokenPipe, e));
         }
     }
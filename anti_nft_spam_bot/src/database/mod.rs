@@ -1,26 +1,37 @@
 mod list_watcher;
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     str::FromStr,
     sync::{atomic::AtomicBool, Arc},
 };
 
-use chrono::Utc;
+use arch_bot_commons::admin_cache::AdminCache;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
 pub use sqlx::Error;
 use sqlx::{
     migrate::MigrateDatabase,
     sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
     Executor, Row, Sqlite,
 };
-use teloxide::{types::ChatId, Bot};
-use tokio::sync::{watch, Mutex, Notify};
+use teloxide::{
+    types::{ChatId, MessageId, UserId},
+    Bot,
+};
+use tokio::{
+    sync::{mpsc, watch, Mutex, Notify},
+    task::AbortHandle,
+};
 use url::Url;
 
 use crate::{
     parse_url_like_telegram,
-    spam_checker::SPAM_CHECKER_VERSION,
-    types::{MarkSusResult, ReviewResponse},
+    spam_checker::{telegram_bot_url, SPAM_CHECKER_VERSION},
+    types::{
+        DeletionNotice, HostPattern, MarkSusResult, MatchReason, PendingDeletion, ReviewKeyboard,
+        ReviewNotice, ReviewResponse, ReviewerStats, SpamEntry, StoredUrl,
+    },
 };
 
 use super::types::{Domain, IsSpam};
@@ -28,6 +39,72 @@ use super::types::{Domain, IsSpam};
 type Pool = sqlx::Pool<Sqlite>;
 const DB_PATH: &str = "sqlite:spam_domains.sqlite";
 static WAS_CONSTRUCTED: AtomicBool = AtomicBool::new(false);
+/// How many different chats need to keep messages with an automatically designated spam URL
+/// for it to be sent back to review. See [`Database::record_spam_override`].
+pub const OVERRIDES_BEFORE_REVIEW: i64 = 3;
+/// How long messages of albums are remembered for, to be deleted along with
+/// another message of the same album that turned out to be spam.
+const ALBUM_MEMORY_TIME: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+
+/// How long a message hidden by the first link guard can be posted again for.
+/// See [`Database::add_quarantined_message`].
+pub const QUARANTINE_TIME: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+
+/// How many characters of text of the message a link was found in are stored
+/// to show to reviewers. See [`review_context`].
+pub const MAX_REVIEW_CONTEXT_LENGTH: usize = 200;
+
+/// Tables that store something about a chat in their `chatid` column.
+/// See [`Database::migrate_chat_id`].
+const CHAT_TABLES: [&str; 15] = [
+    "hide_deletes",
+    "grace_periods",
+    "moderate_channel_posts",
+    "spam_overrides",
+    "delete_albums",
+    "album_messages",
+    "last_deleted_albums",
+    "first_link_guard",
+    "chat_members",
+    "quarantine",
+    "chat_progress",
+    "report_only",
+    "strict_mode",
+    "appeal_hint",
+    "review_keyboards",
+];
+
+/// How long to wait between handling messages that were sent while the bot was down,
+/// not to run into Telegram's rate limits all at once after a long downtime.
+/// See [`Database::wait_for_replay_turn`].
+const REPLAY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// How long to remember that a domain is spam without asking the database again.
+/// Short, so that reviews from other instances of the bot get picked up soon.
+/// See [`Database::is_cached_spam_domain`].
+const SPAM_DOMAIN_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How many spam domains to remember at most. See [`Database::is_cached_spam_domain`].
+const SPAM_DOMAIN_CACHE_SIZE: usize = 10000;
+
+/// How many messages that were reported as spam without deleting them to remember at most.
+/// See [`Database::should_report_spam`].
+const REPORTED_SPAM_MEMORY_SIZE: usize = 10000;
+
+/// How long to remember that a message was reported as spam, if there's too many.
+/// See [`Database::should_report_spam`].
+const REPORTED_SPAM_MEMORY_TIME: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24);
+
+/// How many messages kept by admins to remember as such before trying to forget about old ones.
+/// See [`Database::cancel_pending_deletion`].
+const KEPT_MESSAGES_MEMORY_SIZE: usize = 10000;
+
+/// How long to remember that admins kept a message, if there's too many.
+/// See [`Database::cancel_pending_deletion`].
+const KEPT_MESSAGES_MEMORY_TIME: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24);
+
+/// How often to delete URLs whose expiry date has passed.
+const EXPIRED_URLS_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
 
 pub struct Database {
     pool: Pool,
@@ -42,6 +119,36 @@ pub struct Database {
     domains_currently_being_visited: Mutex<HashSet<Domain>>,
     /// A [`Notify`] used to wake up tasks waiting on other tasks to visit some domain.
     domains_visit_notify: Notify,
+    /// Spam messages that are scheduled to be deleted after a grace period.
+    /// See [`Self::add_pending_deletion`].
+    pending_deletions: Mutex<HashMap<(ChatId, MessageId), PendingDeletion>>,
+    /// Messages whose deletion admins cancelled, and when, so that they aren't
+    /// scheduled for deletion again. See [`Self::cancel_pending_deletion`].
+    kept_messages: Mutex<HashMap<(ChatId, MessageId), tokio::time::Instant>>,
+    /// Latest spam deletion notification in each chat. See [`Self::deletion_notice`].
+    deletion_notices: Mutex<HashMap<ChatId, Arc<Mutex<Option<DeletionNotice>>>>>,
+    /// Where to send notifications for reviewers, to have them sent in batches.
+    /// See [`Self::review_notifier`].
+    review_notifier: Mutex<Option<mpsc::UnboundedSender<ReviewNotice>>>,
+    /// Chats that were already warned about their linked channel posting spam.
+    /// See [`Self::should_warn_about_channel_spam`].
+    channel_spam_warned: Mutex<HashSet<ChatId>>,
+    /// Spam messages that were reported without being deleted, and when.
+    /// See [`Self::should_report_spam`].
+    reported_spam: Mutex<HashMap<(ChatId, MessageId), tokio::time::Instant>>,
+    /// Who's an admin where, so that Telegram isn't asked about it on every message.
+    admin_cache: AdminCache,
+    /// When the bot started. Messages sent before that were missed while it was down.
+    started_at: DateTime<Utc>,
+    /// When the last missed message was let through to be handled.
+    /// See [`Self::wait_for_replay_turn`].
+    last_replay: Mutex<Option<tokio::time::Instant>>,
+    /// Parsed contents of the `url_patterns` table, loaded when first needed,
+    /// and reset to [`None`] when it changes. See [`Self::matching_url_pattern`].
+    url_patterns: Mutex<Option<Arc<Vec<HostPattern>>>>,
+    /// Domains recently found to be marked as spam as a whole, and when.
+    /// See [`Self::is_cached_spam_domain`].
+    spam_domains: Mutex<HashMap<Domain, tokio::time::Instant>>,
 }
 
 impl Database {
@@ -52,7 +159,7 @@ impl Database {
     /// Create a new database with specified path. Will check if it's a unique database if `unique`
     /// is set. If `bot` is provided, it will also ingest the `spam_website_list.txt` file and
     /// watch it for changes.
-    async fn new_by_path(
+    pub(crate) async fn new_by_path(
         bot: impl Into<Option<Bot>>,
         path: &str,
         unique: bool,
@@ -83,11 +190,14 @@ impl Database {
         // DOMAINS:
         // domain (unique primary key, string)
         // example_url (string)
-        // is_spam (0 for no, 1 for yes, 2 for unknown and needs review)
+        // is_spam (0 for no, 1 for yes, 2 for unknown and needs review,
+        //     3 for likely spam, 4 for likely not spam)
         // last_sent_to_review (date+time in UTC timezone in ISO 8601 format)
         // manually_reviewed (0 for no, 1 for yes)
         // from_spam_list (0 for no, 1 for yes)
         // spam_checker_version (version of this program this was determined at)
+        // match_reason (MatchReason of the automatic check that added this, if any)
+        // context (text of the message this was marked as maybe spam from, if any)
         pool.execute(sqlx::query(
             "
                 CREATE TABLE IF NOT EXISTS domains (
@@ -97,18 +207,28 @@ impl Database {
                     last_sent_to_review TEXT NULL,
                     manually_reviewed INTEGER NOT NULL DEFAULT 0,
                     from_spam_list INTEGER NOT NULL DEFAULT 0,
-                    spam_checker_version INTEGER NOT NULL DEFAULT 0
+                    spam_checker_version INTEGER NOT NULL DEFAULT 0,
+                    match_reason INTEGER NULL,
+                    context TEXT NULL
                 ) STRICT;",
         ))
         .await?;
 
         // URLS:
         // url (unique primary key, string)
-        // is_spam (0 for no, 1 for yes, 2 for unknown and needs review)
+        // is_spam (0 for no, 1 for yes, 2 for unknown and needs review,
+        //     3 for likely spam, 4 for likely not spam)
         // last_sent_to_review (date+time in UTC timezone in ISO 8601 format)
         // manually_reviewed (0 for no, 1 for yes)
         // from_spam_list (0 for no, 1 for yes)
         // spam_checker_version (version of this program this was determined at)
+        // match_reason (MatchReason of the automatic check that added this, if any)
+        // expires_at (date+time in UTC timezone after which this entry is ignored, if any)
+        // context (text of the message this was marked as maybe spam from, if any)
+        // first_seen (date+time in UTC timezone this was added or first matched a message,
+        //      NULL if it's from before that was tracked)
+        // last_seen (date+time in UTC timezone this last matched a message, or was added)
+        // scope (0 for just this URL, 1 for every link to the Telegram bot this URL is for)
         pool.execute(sqlx::query(
             "
                 CREATE TABLE IF NOT EXISTS urls (
@@ -117,7 +237,13 @@ impl Database {
                     last_sent_to_review TEXT NULL,
                     manually_reviewed INTEGER NOT NULL DEFAULT 0,
                     from_spam_list INTEGER NOT NULL DEFAULT 0,
-                    spam_checker_version INTEGER NOT NULL DEFAULT 0
+                    spam_checker_version INTEGER NOT NULL DEFAULT 0,
+                    match_reason INTEGER NULL,
+                    expires_at TEXT NULL,
+                    context TEXT NULL,
+                    first_seen TEXT NULL,
+                    last_seen TEXT NULL,
+                    scope INTEGER NOT NULL DEFAULT 0
                 ) STRICT;",
         ))
         .await?;
@@ -134,6 +260,267 @@ impl Database {
         ))
         .await?;
 
+        // GRACE_PERIODS:
+        //      An admin of chats listed here asked to give admins some
+        //      time to cancel deletion of spam messages before it happens.
+        // chatid (unique primary key, i64)
+        // seconds (how long to wait before deleting, u32)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS grace_periods (
+                    chatid INTEGER PRIMARY KEY NOT NULL,
+                    seconds INTEGER NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // MODERATE_CHANNEL_POSTS:
+        //      An admin of chats listed here asked to delete spam in posts
+        //      automatically forwarded from the channel linked to the chat.
+        // chatid (unique primary key, i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS moderate_channel_posts (
+                    chatid INTEGER PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // SPAM_OVERRIDES:
+        //      Admins of chats listed here kept a message that was going to be deleted
+        //      for matching this entry. Used to catch wrong automatic spam designations.
+        // entry_table (string, "urls" or "domains")
+        // entry (string, the URL or domain in that table)
+        // chatid (i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS spam_overrides (
+                    entry_table TEXT NOT NULL,
+                    entry TEXT NOT NULL COLLATE NOCASE,
+                    chatid INTEGER NOT NULL,
+                    PRIMARY KEY (entry_table, entry, chatid)
+                ) STRICT;",
+        ))
+        .await?;
+
+        // DELETE_ALBUMS:
+        //      An admin of chats listed here asked to delete whole albums
+        //      when a message in one of them contains spam.
+        // chatid (unique primary key, i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS delete_albums (
+                    chatid INTEGER PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // ALBUM_MESSAGES:
+        //      Recently seen messages that are a part of an album, in chats that
+        //      have DELETE_ALBUMS enabled. Forgotten after ALBUM_MEMORY_TIME.
+        // chatid (i64)
+        // album_id (string, the media group ID)
+        // messageid (i32)
+        // seen_at (date+time in UTC timezone in ISO 8601 format)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS album_messages (
+                    chatid INTEGER NOT NULL,
+                    album_id TEXT NOT NULL,
+                    messageid INTEGER NOT NULL,
+                    seen_at TEXT NOT NULL,
+                    PRIMARY KEY (chatid, album_id, messageid)
+                ) STRICT;",
+        ))
+        .await?;
+
+        // LAST_DELETED_ALBUMS:
+        //      The latest album in each chat that had a spam message deleted,
+        //      so that its messages arriving later are deleted too.
+        // chatid (unique primary key, i64)
+        // album_id (string, the media group ID)
+        // deleted_at (date+time in UTC timezone in ISO 8601 format)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS last_deleted_albums (
+                    chatid INTEGER PRIMARY KEY NOT NULL,
+                    album_id TEXT NOT NULL,
+                    deleted_at TEXT NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // FIRST_LINK_GUARD:
+        //      An admin of chats listed here asked to hide first messages
+        //      of users if they contain links, until they confirm they're human.
+        // chatid (unique primary key, i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS first_link_guard (
+                    chatid INTEGER PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // CHAT_MEMBERS:
+        //      Users that joined or sent messages in chats that have FIRST_LINK_GUARD enabled.
+        // chatid (i64)
+        // userid (i64 because sqlite doesn't support u64)
+        // first_seen_at (date+time in UTC timezone in ISO 8601 format)
+        // verified (0 for no, 1 for yes; if the user is known to not be a spammer,
+        //           or was in the chat from before they could be seen joining)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS chat_members (
+                    chatid INTEGER NOT NULL,
+                    userid INTEGER NOT NULL,
+                    first_seen_at TEXT NOT NULL,
+                    verified INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY (chatid, userid)
+                ) STRICT;",
+        ))
+        .await?;
+
+        // QUARANTINE:
+        //      Messages hidden by the first link guard, which their senders
+        //      can post again. Forgotten after QUARANTINE_TIME.
+        // chatid (i64)
+        // userid (i64 because sqlite doesn't support u64)
+        // text (string, text of the message with links defanged;
+        //       empty if it had media, which isn't posted again)
+        // hidden_at (date+time in UTC timezone in ISO 8601 format)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS quarantine (
+                    chatid INTEGER NOT NULL,
+                    userid INTEGER NOT NULL,
+                    text TEXT NOT NULL,
+                    hidden_at TEXT NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // DISABLED_HEURISTICS:
+        //      Automatic spam checking heuristics turned off from the control chat.
+        //      See `spam_checker::heuristics`.
+        // name (unique primary key, string)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS disabled_heuristics (
+                    name TEXT PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // REPORT_ONLY:
+        //      An admin of chats listed here asked to only report spam, not delete it.
+        // chatid (unique primary key, i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS report_only (
+                    chatid INTEGER PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // STRICT_MODE:
+        //      An admin of chats listed here asked to also delete messages with
+        //      links that are only likely spam.
+        // chatid (unique primary key, i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS strict_mode (
+                    chatid INTEGER PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // APPEAL_HINT:
+        //      An admin of chats listed here asked for spam deletion notifications
+        //      to say what to do if a message was deleted by mistake.
+        // chatid (unique primary key, i64)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS appeal_hint (
+                    chatid INTEGER PRIMARY KEY NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // CHAT_PROGRESS:
+        //      An admin of chats listed here asked to catch up on messages
+        //      sent while the bot was down.
+        // chatid (unique primary key, i64)
+        // last_messageid (i32, ID of the latest message handled in this chat, if any)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS chat_progress (
+                    chatid INTEGER PRIMARY KEY NOT NULL,
+                    last_messageid INTEGER NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // REVIEWER_ACTIONS:
+        //      Every review applied by a reviewer, to see who does how many of them.
+        //      Can be pruned with `prune_reviewer_actions`.
+        // reviewer_id (i64 because sqlite doesn't support u64)
+        // reviewer_username (string, username without the @ at the time, if any)
+        // action (string, like in review callbacks, e.g. "URL_SPAM")
+        // url (string)
+        // reviewed_at (date+time in UTC timezone in ISO 8601 format)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS reviewer_actions (
+                    reviewer_id INTEGER NOT NULL,
+                    reviewer_username TEXT NULL,
+                    action TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    reviewed_at TEXT NOT NULL
+                ) STRICT;
+                CREATE INDEX IF NOT EXISTS reviewer_actions_by_reviewer
+                    ON reviewer_actions (reviewer_id, reviewed_at);",
+        ))
+        .await?;
+
+        // URL_PATTERNS:
+        //      Wildcard patterns of hosts that are all spam, like `*.claim-nft-*.xyz`.
+        //      See `HostPattern`.
+        // pattern (unique primary key, string)
+        // added_by (i64 because sqlite doesn't support u64, user ID of the control chat member)
+        // added_at (date+time in UTC timezone in ISO 8601 format)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS url_patterns (
+                    pattern TEXT PRIMARY KEY NOT NULL,
+                    added_by INTEGER NOT NULL,
+                    added_at TEXT NOT NULL
+                ) STRICT;",
+        ))
+        .await?;
+
+        // REVIEW_KEYBOARDS:
+        //      Messages that currently have a review keyboard on them,
+        //      to tidy up ones left over from before a restart.
+        // chatid, messageid (unique primary key together)
+        // table_name (string, "urls" or "domains", like in review callbacks)
+        // entry_rowid (i64, rowid of the entry in that table the keyboard is about)
+        // was_reviewed (0 or 1, whether the entry was manually reviewed when it was shown)
+        // sent_at (date+time in UTC timezone in ISO 8601 format)
+        pool.execute(sqlx::query(
+            "
+                CREATE TABLE IF NOT EXISTS review_keyboards (
+                    chatid INTEGER NOT NULL,
+                    messageid INTEGER NOT NULL,
+                    table_name TEXT NOT NULL,
+                    entry_rowid INTEGER NOT NULL,
+                    was_reviewed INTEGER NOT NULL,
+                    sent_at TEXT NOT NULL,
+                    PRIMARY KEY (chatid, messageid)
+                ) STRICT;",
+        ))
+        .await?;
+
         // Transparent database migration lololol
         // Will fail harmlessly if the column already exists.
         let _ = sqlx::query(
@@ -166,6 +553,63 @@ impl Database {
         )
         .execute(&pool)
         .await;
+        let _ = sqlx::query(
+            "ALTER TABLE domains
+        ADD COLUMN match_reason INTEGER NULL;",
+        )
+        .execute(&pool)
+        .await;
+        let _ = sqlx::query(
+            "ALTER TABLE urls
+        ADD COLUMN match_reason INTEGER NULL;",
+        )
+        .execute(&pool)
+        .await;
+        let _ = sqlx::query(
+            "ALTER TABLE urls
+        ADD COLUMN expires_at TEXT NULL;",
+        )
+        .execute(&pool)
+        .await;
+        let _ = sqlx::query(
+            "ALTER TABLE domains
+        ADD COLUMN context TEXT NULL;",
+        )
+        .execute(&pool)
+        .await;
+        let _ = sqlx::query(
+            "ALTER TABLE urls
+        ADD COLUMN context TEXT NULL;",
+        )
+        .execute(&pool)
+        .await;
+        let _ = sqlx::query(
+            "ALTER TABLE urls
+        ADD COLUMN first_seen TEXT NULL;",
+        )
+        .execute(&pool)
+        .await;
+        let added_last_seen = sqlx::query(
+            "ALTER TABLE urls
+        ADD COLUMN last_seen TEXT NULL;",
+        )
+        .execute(&pool)
+        .await
+        .is_ok();
+        if added_last_seen {
+            // Nobody knows when URLs from before this was tracked were last seen.
+            // Count them as seen now, so that they aren't all pruned right away.
+            sqlx::query("UPDATE urls SET last_seen=? WHERE last_seen IS NULL;")
+                .bind(Utc::now())
+                .execute(&pool)
+                .await?;
+        }
+        let _ = sqlx::query(
+            "ALTER TABLE urls
+        ADD COLUMN scope INTEGER NOT NULL DEFAULT 0;",
+        )
+        .execute(&pool)
+        .await;
 
         let db_arc = Arc::new(Database {
             pool,
@@ -173,11 +617,24 @@ impl Database {
             drop_watch: watch::channel(()),
             domains_currently_being_visited: Mutex::new(HashSet::with_capacity(4)),
             domains_visit_notify: Notify::new(),
+            pending_deletions: Mutex::new(HashMap::new()),
+            kept_messages: Mutex::new(HashMap::new()),
+            deletion_notices: Mutex::new(HashMap::new()),
+            review_notifier: Mutex::new(None),
+            channel_spam_warned: Mutex::new(HashSet::new()),
+            reported_spam: Mutex::new(HashMap::new()),
+            admin_cache: AdminCache::default(),
+            started_at: Utc::now(),
+            last_replay: Mutex::new(None),
+            url_patterns: Mutex::new(None),
+            spam_domains: Mutex::new(HashMap::new()),
         });
 
         if let Some(bot) = bot.into() {
             // Spawn the watcher.
             tokio::spawn(list_watcher::watch_list(bot, db_arc.clone()));
+            // And the sweeper of expired URLs.
+            tokio::spawn(Self::sweep_expired_urls(db_arc.clone()));
         }
 
         Ok(db_arc)
@@ -235,11 +692,91 @@ impl Database {
     /// "No" and "Maybe" results that were automatically determined by
     /// an old spam checker are ignored unless `return_old_checker_results` is set to true.
     ///
+    /// If there's no exact match and the URL has a query, URLs in the database
+    /// that are the same but have a subset of its query parameters are considered
+    /// too. This way, a spam link still matches if spammers tack extra parameters on it.
+    ///
+    /// If the URL is a link to a Telegram bot that was marked as a whole,
+    /// that takes priority over everything else. See [`Self::is_bot_spam`].
+    ///
     /// Also returns a boolean that is true if this result is manually reviewed.
     pub async fn is_url_spam(
         &self,
         url: &Url,
         return_old_checker_results: bool,
+    ) -> Result<Option<(IsSpam, bool)>, Error> {
+        Ok(self
+            .is_url_spam_with_entry(url, return_old_checker_results)
+            .await?
+            .map(|(is_spam, manually_reviewed, _)| (is_spam, manually_reviewed)))
+    }
+
+    /// Same as [`Self::is_url_spam`], but also returns the URL in the database
+    /// the result is for.
+    async fn is_url_spam_with_entry(
+        &self,
+        url: &Url,
+        return_old_checker_results: bool,
+    ) -> Result<Option<(IsSpam, bool, String)>, Error> {
+        if let Some(bot) = telegram_bot_url(url) {
+            if let Some((is_spam, manually_reviewed)) = self
+                .is_url_spam_exact_with_scope(&bot, true, return_old_checker_results)
+                .await?
+            {
+                return Ok(Some((is_spam, manually_reviewed, bot.into())));
+            }
+        }
+
+        let exact = self
+            .is_url_spam_exact(url, return_old_checker_results)
+            .await?;
+
+        if let Some((is_spam, manually_reviewed)) = exact {
+            return Ok(Some((is_spam, manually_reviewed, url.to_string())));
+        }
+        if url.query().is_none() {
+            return Ok(None);
+        }
+
+        self.is_url_spam_inexact_with_query(url, return_old_checker_results)
+            .await
+    }
+
+    /// Check if a given URL is spam or not, matching the URL exactly.
+    /// See [`Self::is_url_spam`].
+    async fn is_url_spam_exact(
+        &self,
+        url: &Url,
+        return_old_checker_results: bool,
+    ) -> Result<Option<(IsSpam, bool)>, Error> {
+        self.is_url_spam_exact_with_scope(url, false, return_old_checker_results)
+            .await
+    }
+
+    /// Check if every link to the Telegram bot this URL leads to, like
+    /// `t.me/SomeBot?start=ref123`, was marked as spam or not, regardless of
+    /// the start parameter or anything else in it. See [`telegram_bot_url`].
+    ///
+    /// Returns [`None`] if it's not a link to a bot, or if the bot wasn't marked as a whole.
+    pub async fn is_bot_spam(
+        &self,
+        url: &Url,
+        return_old_checker_results: bool,
+    ) -> Result<Option<(IsSpam, bool)>, Error> {
+        let Some(bot) = telegram_bot_url(url) else {
+            return Ok(None);
+        };
+
+        self.is_url_spam_exact_with_scope(&bot, true, return_old_checker_results)
+            .await
+    }
+
+    /// See [`Self::is_url_spam_exact`] and [`Self::is_bot_spam`].
+    async fn is_url_spam_exact_with_scope(
+        &self,
+        url: &Url,
+        bot_wide: bool,
+        return_old_checker_results: bool,
     ) -> Result<Option<(IsSpam, bool)>, Error> {
         // The "NOT" condition is to exclude results that says anything other than `IsSpam::Yes`
         // and are automatically determined by an older spam check version.
@@ -247,6 +784,8 @@ impl Database {
         sqlx::query(
             "SELECT is_spam, manually_reviewed FROM urls
             WHERE url=? AND
+                (? OR scope=1) AND
+                (expires_at IS NULL OR expires_at>?) AND
                 NOT (
                     is_spam!=1 AND
                     from_spam_list=0 AND
@@ -254,6 +793,8 @@ impl Database {
                     );",
         )
         .bind(url.as_str())
+        .bind(!bot_wide)
+        .bind(Utc::now())
         .bind(if return_old_checker_results {
             0
         } else {
@@ -269,66 +810,241 @@ impl Database {
         .await
     }
 
-    /// Check if a given URL (or its domain) is spam or not, according to the database.
-    /// Convenience method for [`Self::is_domain_spam`] and [`Self::is_url_spam`]
-    /// Returns [`None`] if it's not in the database.
-    ///
-    /// Argument `domain` is optional and, if `url` check is indecisive,
-    /// is used if provided, or extracted from URL if not.
-    ///
-    /// "No" and "Maybe" results that were automatically determined by
-    /// an old spam checker are ignored unless `return_old_checker_results` is set to true.
-    ///
-    /// Also returns a boolean that is true if this result is manually reviewed.
-    pub async fn is_spam(
+    /// Check if a given URL is spam or not, matching URLs in the database that
+    /// only differ by having a subset of its query parameters.
+    /// Picks the most condemning result out of those, and also returns the URL it's for.
+    /// See [`Self::is_url_spam`].
+    async fn is_url_spam_inexact_with_query(
         &self,
         url: &Url,
-        domain: impl Into<Option<&Domain>>,
         return_old_checker_results: bool,
-    ) -> Result<Option<(IsSpam, bool)>, Error> {
-        let mut domain = domain.into();
-        // Look for URL match...
-        let url_result = self.is_url_spam(url, return_old_checker_results).await?;
+    ) -> Result<Option<(IsSpam, bool, String)>, Error> {
+        let mut result: Option<(IsSpam, bool, String)> = None;
 
-        if let Some((IsSpam::Yes, _)) = url_result {
-            return Ok(url_result);
+        for candidate in self
+            .url_entries_with_fewer_params(url, return_old_checker_results)
+            .await?
+        {
+            result =
+                match IsSpam::pick_most_condemning(result.as_ref().map(|x| x.0), Some(candidate.0))
+                {
+                    Some((_, true)) => Some(candidate),
+                    _ => result,
+                };
         }
 
-        // If no provided domain, try to get one from the URL.
-        // Otherwise, use provided domain, to not do an extraneous allocation.
-        let domain_inner;
-        if domain.is_none() {
-            domain_inner = Domain::from_url(url);
-            domain = domain_inner.as_ref();
-        }
+        Ok(result)
+    }
 
-        // Look for domain match...
-        let domain_result = if let Some(domain) = domain {
-            self.is_domain_spam(domain, return_old_checker_results)
-                .await?
+    /// Get all URLs in the database that only differ from this one by having
+    /// a subset of its query parameters, including itself, along with what they are
+    /// designated as and whether that was manually reviewed.
+    /// See [`Self::is_url_spam_inexact_with_query`].
+    async fn url_entries_with_fewer_params(
+        &self,
+        url: &Url,
+        return_old_checker_results: bool,
+    ) -> Result<Vec<(IsSpam, bool, String)>, Error> {
+        let mut base = url.clone();
+        base.set_query(None);
+        base.set_fragment(None);
+        let base = base.as_str();
+
+        let params: HashSet<(String, String)> = url.query_pairs().into_owned().collect();
+
+        // Look for URLs starting with the base as a range, so that the index on
+        // the URL column is used. URLs are always ASCII after parsing, and '@' comes
+        // right after '?' in it, so this catches the base with any query on it.
+        // It catches some longer paths too, which are filtered out below.
+        let candidates = sqlx::query(
+            "SELECT url, is_spam, manually_reviewed FROM urls
+            WHERE url>=? AND url<? AND
+                (expires_at IS NULL OR expires_at>?) AND
+                NOT (
+                    is_spam!=1 AND
+                    from_spam_list=0 AND
+                    spam_checker_version<?
+                    );",
+        )
+        .bind(base)
+        .bind(format!("{}@", base))
+        .bind(Utc::now())
+        .bind(if return_old_checker_results {
+            0
         } else {
-            None
-        };
+            SPAM_CHECKER_VERSION
+        })
+        .fetch_all(&self.pool)
+        .await?;
 
-        // Pick the most condemning one.
-        let most_condeming =
-            IsSpam::pick_most_condemning(url_result.map(|x| x.0), domain_result.map(|x| x.0));
+        let mut entries = Vec::new();
 
-        if let Some(most_condeming) = most_condeming {
-            let manually_reviewed = if most_condeming.1 {
+        for row in candidates {
+            let candidate_url: String = row.get("url");
+            let Ok(candidate) = Url::parse(&candidate_url) else {
+                continue;
+            };
+
+            // The range can catch longer paths too; filter those out.
+            if candidate.path() != url.path() || candidate.fragment().is_some() {
+                continue;
+            }
+
+            if !candidate
+                .query_pairs()
+                .into_owned()
+                .all(|pair| params.contains(&pair))
+            {
+                continue;
+            }
+
+            entries.push((
+                IsSpam::from(row.get::<u8, _>("is_spam")),
+                row.get::<bool, _>("manually_reviewed"),
+                candidate_url,
+            ));
+        }
+
+        Ok(entries)
+    }
+
+    /// Record that a message had this URL in it, for the entry that [`Self::is_url_spam`]
+    /// matches it to, if any. See [`Self::prune_unseen_urls`].
+    pub async fn mark_url_seen(&self, url: &Url) -> Result<(), Error> {
+        let now = Utc::now();
+        let query = "UPDATE urls SET first_seen=COALESCE(first_seen, ?), last_seen=? WHERE url=?;";
+
+        let updated = sqlx::query(query)
+            .bind(now)
+            .bind(now)
+            .bind(url.as_str())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        if updated == 0 && url.query().is_some() {
+            if let Some((_, _, matched)) = self.is_url_spam_inexact_with_query(url, true).await? {
+                sqlx::query(query)
+                    .bind(now)
+                    .bind(now)
+                    .bind(matched)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like [`Self::mark_url_seen`], but done in the background, to not slow down
+    /// handling the message the URL is from.
+    pub fn url_seen(self: &Arc<Self>, url: &Url) {
+        let database = self.clone();
+        let url = url.clone();
+        tokio::spawn(async move {
+            if let Err(e) = database.mark_url_seen(&url).await {
+                log::error!("Failed to mark {} as seen: {}", url, e);
+            }
+        });
+    }
+
+    /// Check if a given URL (or its domain) is spam or not, according to the database.
+    /// Convenience method for [`Self::is_domain_spam`] and [`Self::is_url_spam`]
+    /// Returns [`None`] if it's not in the database.
+    ///
+    /// Argument `domain` is optional and, if `url` check is indecisive,
+    /// is used if provided, or extracted from URL if not.
+    ///
+    /// "No" and "Maybe" results that were automatically determined by
+    /// an old spam checker are ignored unless `return_old_checker_results` is set to true.
+    ///
+    /// Also returns a boolean that is true if this result is manually reviewed.
+    pub async fn is_spam(
+        &self,
+        url: &Url,
+        domain: impl Into<Option<&Domain>>,
+        return_old_checker_results: bool,
+    ) -> Result<Option<(IsSpam, bool)>, Error> {
+        Ok(self
+            .is_spam_with_pattern(url, domain, return_old_checker_results)
+            .await?
+            .0)
+    }
+
+    /// Same as [`Self::is_spam`], but also returns the pattern from `url_patterns`
+    /// the result was decided by, if it was. See [`Self::matching_url_pattern`].
+    ///
+    /// Patterns are only looked at if neither the URL nor its domain were manually reviewed,
+    /// and neither of them is spam already. Results by patterns count as manually reviewed,
+    /// as only members of the control chat can add them.
+    pub async fn is_spam_with_pattern(
+        &self,
+        url: &Url,
+        domain: impl Into<Option<&Domain>>,
+        return_old_checker_results: bool,
+    ) -> Result<(Option<(IsSpam, bool)>, Option<String>), Error> {
+        let mut domain = domain.into();
+        // Look for URL match...
+        let url_result = self.is_url_spam(url, return_old_checker_results).await?;
+
+        if let Some((IsSpam::Yes, _)) = url_result {
+            return Ok((url_result, None));
+        }
+
+        // If no provided domain, try to get one from the URL.
+        // Otherwise, use provided domain, to not do an extraneous allocation.
+        let domain_inner;
+        if domain.is_none() {
+            domain_inner = Domain::from_url(url);
+            domain = domain_inner.as_ref();
+        }
+
+        // Look for domain match...
+        let domain_result = if let Some(domain) = domain {
+            self.is_domain_spam(domain, return_old_checker_results)
+                .await?
+        } else {
+            None
+        };
+
+        // Pick the most condemning one.
+        let most_condeming =
+            IsSpam::pick_most_condemning(url_result.map(|x| x.0), domain_result.map(|x| x.0));
+
+        let result = most_condeming.map(|most_condeming| {
+            let manually_reviewed = if most_condeming.1 {
                 domain_result.unwrap().1
             } else {
                 url_result.unwrap().1
             };
 
-            Ok(Some((most_condeming.0, manually_reviewed)))
-        } else {
-            Ok(None)
+            (most_condeming.0, manually_reviewed)
+        });
+
+        let reviewed = |x: Option<(IsSpam, bool)>| x.is_some_and(|x| x.1);
+        if reviewed(url_result)
+            || reviewed(domain_result)
+            || matches!(result, Some((IsSpam::Yes, _)))
+        {
+            return Ok((result, None));
+        }
+
+        // Nothing decisive is known about this link specifically. Try the patterns.
+        if let Some(domain) = domain {
+            if let Some(pattern) = self.matching_url_pattern(domain).await? {
+                return Ok((Some((IsSpam::Yes, true)), Some(pattern)));
+            }
         }
+
+        Ok((result, None))
     }
 
     /// Inserts a domain into the database and tags it as spam or not.
-    /// Overwrites the domain if it already exists.
+    /// Overwrites the domain if it already exists, except that a [likely](IsSpam::is_likely)
+    /// designation never overwrites a firm one that was manually reviewed.
+    ///
+    /// `match_reason` should be set if this was determined by the automatic spam checker.
+    /// If it's [`None`], the previously stored reason is kept, if any.
     pub async fn add_domain(
         &self,
         domain: &Domain,
@@ -336,8 +1052,10 @@ impl Database {
         is_spam: IsSpam,
         from_spam_list: bool,
         manually_reviewed: bool,
+        match_reason: Option<MatchReason>,
     ) -> Result<(), Error> {
         let example_url = example_url.into();
+        let match_reason = match_reason.map(u8::from);
         sqlx::query(
             "INSERT INTO domains(
                 domain,
@@ -345,14 +1063,17 @@ impl Database {
                 is_spam,
                 from_spam_list,
                 manually_reviewed,
-                spam_checker_version)
-            VALUES (?, ?, ?, ?, ?, ?)
+                spam_checker_version,
+                match_reason)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT DO UPDATE SET
                 example_url=COALESCE(?, example_url),
                 is_spam=?,
                 from_spam_list=?,
                 manually_reviewed=?,
-                spam_checker_version=?;",
+                spam_checker_version=?,
+                match_reason=COALESCE(?, match_reason)
+            WHERE NOT (? AND manually_reviewed=1 AND is_spam IN (0, 1));",
         )
         .bind(domain.as_str())
         .bind(example_url.map(Url::as_str))
@@ -360,12 +1081,15 @@ impl Database {
         .bind(from_spam_list)
         .bind(manually_reviewed)
         .bind(SPAM_CHECKER_VERSION)
+        .bind(match_reason)
         // On conflict...
         .bind(example_url.map(Url::as_str))
         .bind::<u8>(is_spam.into())
         .bind(from_spam_list)
         .bind(manually_reviewed)
         .bind(SPAM_CHECKER_VERSION)
+        .bind(match_reason)
+        .bind(is_spam.is_likely())
         .execute(&self.pool)
         .await?;
 
@@ -390,27 +1114,33 @@ impl Database {
         &self,
         domain: &Domain,
         example_url: Option<&Url>,
+        context: Option<&str>,
     ) -> Result<bool, Error> {
+        let context = context.and_then(review_context);
         let result = sqlx::query(
             "
             INSERT INTO domains(
                 domain,
                 example_url,
                 is_spam,
-                spam_checker_version
-            ) VALUES (?, ?, 2, ?)
+                spam_checker_version,
+                context
+            ) VALUES (?, ?, 2, ?, ?)
             ON CONFLICT DO
             UPDATE SET
                 example_url=COALESCE(?, example_url),
                 is_spam=2,
-                spam_checker_version=?
-            WHERE is_spam=0 AND manually_reviewed=0;",
+                spam_checker_version=?,
+                context=COALESCE(?, context)
+            WHERE is_spam IN (0, 4) AND manually_reviewed=0;",
         )
         .bind(domain.as_str())
         .bind(example_url.map(Url::as_str))
         .bind(SPAM_CHECKER_VERSION)
+        .bind(&context)
         .bind(example_url.map(Url::as_str))
         .bind(SPAM_CHECKER_VERSION)
+        .bind(&context)
         .execute(&self.pool)
         .await?
         .rows_affected()
@@ -419,184 +1149,911 @@ impl Database {
     }
 
     /// Inserts a URL into the database and tags it as spam or not.
-    /// Overwrites the URL if it already exists.
+    /// Overwrites the URL if it already exists, except that a [likely](IsSpam::is_likely)
+    /// designation never overwrites a firm one that was manually reviewed.
+    ///
+    /// `match_reason` should be set if this was determined by the automatic spam checker.
+    /// If it's [`None`], the previously stored reason is kept, if any.
+    ///
+    /// If `expires_at` is set, the entry is ignored after that moment and later
+    /// purged from the database. Otherwise the entry is kept indefinitely,
+    /// replacing any expiry date it had before.
+    ///
+    /// This is a verdict on just this URL, even if it was one on a whole bot before.
+    /// See [`Self::add_bot`].
     pub async fn add_url(
         &self,
         url: &Url,
         is_spam: IsSpam,
         from_spam_list: bool,
         manually_reviewed: bool,
+        match_reason: Option<MatchReason>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        self.add_url_with_scope(
+            url,
+            false,
+            is_spam,
+            from_spam_list,
+            manually_reviewed,
+            match_reason,
+            expires_at,
+        )
+        .await
+    }
+
+    /// See [`Self::add_url`] and [`Self::add_bot`].
+    #[allow(clippy::too_many_arguments)]
+    async fn add_url_with_scope(
+        &self,
+        url: &Url,
+        bot_wide: bool,
+        is_spam: IsSpam,
+        from_spam_list: bool,
+        manually_reviewed: bool,
+        match_reason: Option<MatchReason>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), Error> {
+        let match_reason = match_reason.map(u8::from);
         sqlx::query(
             "INSERT INTO urls(
                 url,
                 is_spam,
                 from_spam_list,
                 manually_reviewed,
-                spam_checker_version)
-            VALUES (?, ?, ?, ?, ?)
+                spam_checker_version,
+                match_reason,
+                expires_at,
+                scope,
+                first_seen,
+                last_seen)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT DO UPDATE SET
-                is_spam=?,
-                from_spam_list=?,
-                manually_reviewed=?,
-                spam_checker_version=?;",
+                is_spam=excluded.is_spam,
+                from_spam_list=excluded.from_spam_list,
+                manually_reviewed=excluded.manually_reviewed,
+                spam_checker_version=excluded.spam_checker_version,
+                match_reason=COALESCE(excluded.match_reason, match_reason),
+                expires_at=excluded.expires_at,
+                scope=excluded.scope
+            WHERE NOT (? AND manually_reviewed=1 AND is_spam IN (0, 1));",
         )
         .bind(url.as_str())
         .bind::<u8>(is_spam.into())
         .bind(from_spam_list)
         .bind(manually_reviewed)
         .bind(SPAM_CHECKER_VERSION)
+        .bind(match_reason)
+        .bind(expires_at)
+        .bind(bot_wide)
+        .bind(Utc::now())
+        .bind(Utc::now())
         // On conflict...
-        .bind::<u8>(is_spam.into())
-        .bind(from_spam_list)
-        .bind(manually_reviewed)
-        .bind(SPAM_CHECKER_VERSION)
+        .bind(is_spam.is_likely())
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
-    /// Mark a URL as maybe spam, if it's not already marked as spam
-    /// and wasn't manually reviewed. Returns true if anything is actually done.
+    /// Find the automatic spam designation that makes this link spam, if that's what
+    /// decides it, the same way [`Self::is_spam_with_pattern`] would.
     ///
-    /// Note that this adds a URL entry if one doesn't exist,
-    /// even if there's a meaningful domain entry.
-    async fn mark_url_sus(&self, url: &Url) -> Result<bool, Error> {
-        let result = sqlx::query(
-            "
-            INSERT INTO urls(
-                    url,
-                    is_spam,
-                    spam_checker_version
-            ) VALUES (?, 2, ?)
-            ON CONFLICT DO
-                UPDATE SET is_spam=2, spam_checker_version=?
-                WHERE is_spam=0 AND manually_reviewed=0;",
-        )
-        .bind(url.as_str())
-        .bind(SPAM_CHECKER_VERSION)
-        .bind(SPAM_CHECKER_VERSION)
-        .execute(&self.pool)
-        .await?
-        .rows_affected()
-            > 0;
-        Ok(result)
-    }
-
-    /// Convenience function to mark both a URL and its domain as maybe spam.
-    pub async fn mark_sus(
+    /// Returns [`None`] if the link isn't spam, or is spam because of something manually
+    /// reviewed. Patterns count as manually reviewed, so nothing is returned for them.
+    pub async fn automatic_spam_entry(
         &self,
         url: &Url,
-        mut domain: Option<&Domain>,
-    ) -> Result<MarkSusResult, Error> {
-        // We only want to deal with entries in the database that exist.
-
-        // Check the URL one.
-        if let Some(is_spam_url) = self.is_url_spam(url, false).await? {
-            let result = match is_spam_url.0 {
-                IsSpam::Yes => MarkSusResult::AlreadyMarkedSpam,
-                IsSpam::Maybe => MarkSusResult::AlreadyMarkedSus,
-                IsSpam::No => {
-                    if is_spam_url.1 {
-                        MarkSusResult::ManuallyReviewedNotSpam
-                    } else {
-                        let mark_result = self.mark_url_sus(url).await?;
-                        if mark_result {
-                            MarkSusResult::Marked
-                        } else {
-                            MarkSusResult::ManuallyReviewedNotSpam
-                        }
-                    }
-                }
-            };
+        domain: impl Into<Option<&Domain>>,
+    ) -> Result<Option<SpamEntry>, Error> {
+        let url_result = self.is_url_spam_with_entry(url, false).await?;
 
-            return Ok(result);
+        if let Some((IsSpam::Yes, manually_reviewed, matched)) = url_result {
+            return Ok((!manually_reviewed).then_some(SpamEntry::Url(matched)));
         }
 
-        // If no provided domain, try to get one from the URL.
-        // Otherwise, use provided domain, to not do an extraneous allocation.
         let domain_inner;
-        if domain.is_none() {
-            domain_inner = Domain::from_url(url);
-            domain = domain_inner.as_ref();
-        }
-        if let Some(domain) = domain {
-            // Check the domain one.
-            if let Some(is_spam_domain) = self.is_domain_spam(domain, false).await? {
-                if is_spam_domain.1 {
-                    return Ok(MarkSusResult::ManuallyReviewedNotSpam);
-                }
-                let result = match is_spam_domain.0 {
-                    IsSpam::Yes => MarkSusResult::AlreadyMarkedSpam,
-                    IsSpam::Maybe => MarkSusResult::AlreadyMarkedSus,
-                    IsSpam::No => {
-                        let mark_result = self.mark_domain_sus(domain, Some(url)).await?;
-                        if mark_result {
-                            MarkSusResult::Marked
-                        } else {
-                            MarkSusResult::ManuallyReviewedNotSpam
-                        }
-                    }
+        let domain = match domain.into() {
+            Some(domain) => domain,
+            None => {
+                let Some(domain) = Domain::from_url(url) else {
+                    return Ok(None);
                 };
+                domain_inner = domain;
+                &domain_inner
+            }
+        };
 
-                return Ok(result);
+        Ok(match self.is_domain_spam(domain, false).await? {
+            Some((IsSpam::Yes, false)) => Some(SpamEntry::Domain(domain.clone())),
+            _ => None,
+        })
+    }
+
+    /// Record that admins of this chat kept a message that was going to be deleted
+    /// for matching this entry. See [`Self::automatic_spam_entry`].
+    ///
+    /// If the entry is automatically designated as spam, and admins of
+    /// [`OVERRIDES_BEFORE_REVIEW`] different chats did this, it's marked as maybe spam
+    /// and sent back to review, and true is returned.
+    /// Manually reviewed entries and ones from the spam list are never changed.
+    pub async fn record_spam_override(
+        &self,
+        entry: &SpamEntry,
+        chatid: ChatId,
+    ) -> Result<bool, Error> {
+        // All in one transaction, so that two chats overriding at once
+        // can't both miss or both trigger the downgrade.
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO spam_overrides(entry_table, entry, chatid) VALUES (?, ?, ?);",
+        )
+        .bind(entry.table())
+        .bind(entry.as_str())
+        .bind(chatid.0)
+        .execute(&mut *transaction)
+        .await?;
+
+        let overrides: i64 =
+            sqlx::query("SELECT COUNT(*) FROM spam_overrides WHERE entry_table=? AND entry=?;")
+                .bind(entry.table())
+                .bind(entry.as_str())
+                .map(|row: SqliteRow| row.get(0))
+                .fetch_one(&mut *transaction)
+                .await?;
+
+        if overrides < OVERRIDES_BEFORE_REVIEW {
+            transaction.commit().await?;
+            return Ok(false);
+        }
+
+        // Clearing last_sent_to_review puts it at the front of the review queue.
+        let downgrade = match entry {
+            SpamEntry::Url(_) => {
+                "UPDATE urls SET is_spam=2, last_sent_to_review=NULL, spam_checker_version=?
+                WHERE url=? AND is_spam=1 AND manually_reviewed=0 AND from_spam_list=0;"
             }
+            SpamEntry::Domain(_) => {
+                "UPDATE domains SET is_spam=2, last_sent_to_review=NULL, spam_checker_version=?
+                WHERE domain=? AND is_spam=1 AND manually_reviewed=0 AND from_spam_list=0;"
+            }
+        };
+        let downgraded = sqlx::query(downgrade)
+            .bind(SPAM_CHECKER_VERSION)
+            .bind(entry.as_str())
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+            > 0;
+
+        if downgraded {
+            // Start counting anew, in case the review decides it's spam after all.
+            sqlx::query("DELETE FROM spam_overrides WHERE entry_table=? AND entry=?;")
+                .bind(entry.table())
+                .bind(entry.as_str())
+                .execute(&mut *transaction)
+                .await?;
         }
 
-        // It is in neither URL nor Domain tables.
-        // Add it in as a URL entry.
-        self.mark_url_sus(url).await?;
-        Ok(MarkSusResult::Marked)
+        transaction.commit().await?;
+        Ok(downgraded)
     }
 
-    /// Delete all entries added from the spam list.
-    pub async fn clean_all_from_spam_list(&self) -> Result<(), Error> {
-        sqlx::query("DELETE FROM domains WHERE from_spam_list=1")
-            .execute(&self.pool)
-            .await?;
-        sqlx::query("DELETE FROM urls WHERE from_spam_list=1")
-            .execute(&self.pool)
+    /// Delete URLs whose expiry date has passed. Returns how many were deleted.
+    pub async fn purge_expired_urls(&self) -> Result<u64, Error> {
+        let result =
+            sqlx::query("DELETE FROM urls WHERE expires_at IS NOT NULL AND expires_at<=?;")
+                .bind(Utc::now())
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Remove automatically added URLs that weren't seen in any message since `cutoff`,
+    /// or only count them if `dry_run` is set. Returns how many there are of each designation.
+    ///
+    /// Manually reviewed URLs and ones from the spam list are never removed,
+    /// and neither are ones without a known last time they were seen.
+    pub async fn prune_unseen_urls(
+        &self,
+        cutoff: DateTime<Utc>,
+        dry_run: bool,
+    ) -> Result<Vec<(IsSpam, u64)>, Error> {
+        // A NULL last_seen is never less than anything, so those are left alone.
+        let query = if dry_run {
+            "SELECT is_spam FROM urls
+            WHERE manually_reviewed=0 AND from_spam_list=0 AND last_seen<?;"
+        } else {
+            "DELETE FROM urls
+            WHERE manually_reviewed=0 AND from_spam_list=0 AND last_seen<?
+            RETURNING is_spam;"
+        };
+
+        let designations: Vec<u8> = sqlx::query(query)
+            .bind(cutoff)
+            .map(|row: SqliteRow| row.get("is_spam"))
+            .fetch_all(&self.pool)
             .await?;
+
+        let mut counts: Vec<(IsSpam, u64)> = Vec::new();
+        for is_spam in [
+            IsSpam::Yes,
+            IsSpam::Likely,
+            IsSpam::No,
+            IsSpam::Unlikely,
+            IsSpam::Maybe,
+        ] {
+            let count = designations
+                .iter()
+                .filter(|x| IsSpam::from(**x) == is_spam)
+                .count();
+            if count > 0 {
+                counts.push((is_spam, count as u64));
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Remember that this reviewer applied this review response, for [`Self::reviewer_leaderboard`]
+    /// and [`Self::reviewer_history`]. Does nothing for [`ReviewResponse::Skip`].
+    pub async fn record_reviewer_action(
+        &self,
+        reviewer: UserId,
+        reviewer_username: Option<&str>,
+        response: &ReviewResponse,
+    ) -> Result<(), Error> {
+        let (Some(action), Some(url)) = (response.action(), response.url()) else {
+            return Ok(());
+        };
+
+        sqlx::query(
+            "INSERT INTO reviewer_actions
+            (reviewer_id, reviewer_username, action, url, reviewed_at)
+            VALUES (?, ?, ?, ?, ?);",
+        )
+        .bind(reviewer.0 as i64)
+        .bind(reviewer_username)
+        .bind(action)
+        .bind(url.as_str())
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
 
-    /// Count and return the amount of links left to review.
-    pub async fn get_review_count(&self) -> Result<u32, Error> {
+    /// Get up to `limit` reviewers with the most reviews applied since `since`,
+    /// from most to least.
+    pub async fn reviewer_leaderboard(
+        &self,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<ReviewerStats>, Error> {
+        let rows: Vec<(i64, Option<String>, String, i64)> = sqlx::query(
+            "SELECT reviewer_id, action, COUNT(*) AS count,
+                (SELECT latest.reviewer_username FROM reviewer_actions latest
+                    WHERE latest.reviewer_id=reviewer_actions.reviewer_id
+                    ORDER BY latest.reviewed_at DESC LIMIT 1) AS username
+            FROM reviewer_actions
+            WHERE reviewed_at>=?
+            GROUP BY reviewer_id, action
+            ORDER BY action;",
+        )
+        .bind(since)
+        .map(|row: SqliteRow| {
+            (
+                row.get("reviewer_id"),
+                row.get("username"),
+                row.get("action"),
+                row.get("count"),
+            )
+        })
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats: Vec<ReviewerStats> = Vec::new();
+        for (reviewer_id, username, action, count) in rows {
+            let reviewer = UserId(reviewer_id as u64);
+            let index = match stats.iter().position(|x| x.reviewer == reviewer) {
+                Some(index) => index,
+                None => {
+                    stats.push(ReviewerStats {
+                        reviewer,
+                        username,
+                        total: 0,
+                        actions: Vec::new(),
+                    });
+                    stats.len() - 1
+                }
+            };
+            stats[index].total += count as u64;
+            stats[index].actions.push((action, count as u64));
+        }
+
+        stats.sort_by(|a, b| b.total.cmp(&a.total).then(a.reviewer.0.cmp(&b.reviewer.0)));
+        stats.truncate(limit);
+        Ok(stats)
+    }
+
+    /// Find the ID of a reviewer by the username they had during their latest review.
+    /// The username is matched case-insensitively, and may start with an @.
+    pub async fn find_reviewer_by_username(&self, username: &str) -> Result<Option<UserId>, Error> {
+        let username = username.strip_prefix('@').unwrap_or(username);
         sqlx::query(
-            "SELECT SUM(A) FROM
-                (
-                    SELECT COUNT(*) AS A FROM urls WHERE is_spam=2
-                UNION ALL
-                    SELECT COUNT(*) AS A FROM domains WHERE is_spam=2
-                );",
+            "SELECT reviewer_id FROM reviewer_actions
+            WHERE reviewer_username=? COLLATE NOCASE
+            ORDER BY reviewed_at DESC LIMIT 1;",
         )
-        .map(|x: SqliteRow| x.get(0))
-        .fetch_one(&self.pool)
+        .bind(username)
+        .map(|row: SqliteRow| UserId(row.get::<i64, _>("reviewer_id") as u64))
+        .fetch_optional(&self.pool)
         .await
     }
 
-    /// Get a URL, and its database table and ID, for review, and its state in the database.
-    pub async fn get_url_for_review(&self) -> Result<Option<(Url, &str, i64, IsSpam)>, Error> {
+    /// Get up to `limit` latest reviews applied by this reviewer, from newest to oldest,
+    /// as the action, the URL, and when it was applied.
+    pub async fn reviewer_history(
+        &self,
+        reviewer: UserId,
+        limit: u32,
+    ) -> Result<Vec<(String, String, DateTime<Utc>)>, Error> {
+        sqlx::query(
+            "SELECT action, url, reviewed_at FROM reviewer_actions
+            WHERE reviewer_id=?
+            ORDER BY reviewed_at DESC LIMIT ?;",
+        )
+        .bind(reviewer.0 as i64)
+        .bind(limit)
+        .map(|row: SqliteRow| (row.get("action"), row.get("url"), row.get("reviewed_at")))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Find URLs whose host contains `host_part`, ignoring case, most recently added first.
+    /// Returns at most `limit` of them.
+    ///
+    /// `host_part` is a literal substring: `%` and `_` in it are not wildcards.
+    pub async fn search_urls(&self, host_part: &str, limit: u32) -> Result<Vec<StoredUrl>, Error> {
+        let host_part = host_part.to_lowercase();
+
+        // Narrow it down to URLs with the substring anywhere in them,
+        // then check that it's in the host specifically. They're streamed
+        // to stop reading once there's enough of them.
+        let mut candidates = sqlx::query(
+            "SELECT url, is_spam, manually_reviewed, match_reason, last_seen FROM urls
+            WHERE url LIKE ? ESCAPE '\\'
+            ORDER BY rowid DESC;",
+        )
+        .bind(format!("%{}%", escape_like(&host_part)))
+        .map(|row: SqliteRow| StoredUrl {
+            url: row.get("url"),
+            is_spam: IsSpam::from(row.get::<u8, _>("is_spam")),
+            manually_reviewed: row.get("manually_reviewed"),
+            match_reason: row
+                .get::<Option<u8>, _>("match_reason")
+                .map(MatchReason::from),
+            last_seen: row.get("last_seen"),
+        })
+        .fetch(&self.pool);
+
+        let mut found = Vec::new();
+        while found.len() < limit as usize {
+            let Some(candidate) = candidates.next().await.transpose()? else {
+                break;
+            };
+
+            let in_host = Url::parse(&candidate.url)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.contains(&host_part)))
+                .unwrap_or(false);
+            if in_host {
+                found.push(candidate);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Forget reviews applied before `cutoff`. Returns how many were forgotten.
+    pub async fn prune_reviewer_actions(&self, cutoff: DateTime<Utc>) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM reviewer_actions WHERE reviewed_at<?;")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Remove URLs waiting for review that don't need it anymore. Returns how many were removed.
+    ///
+    /// That's ones whose domain was designated as spam since, and ones that match another
+    /// URL entry that's designated as spam or was manually reviewed. Those are the same
+    /// URL with fewer or no query parameters, and links to a Telegram bot marked as a whole.
+    ///
+    /// Domains designated as not spam don't count, as a specific page
+    /// of an otherwise fine website can still be spam.
+    pub async fn prune_stale_review_entries(&self) -> Result<u64, Error> {
+        let _the_mutex = self.review_lock.lock().await;
+
+        let urls: Vec<String> = sqlx::query(
+            "SELECT url FROM urls WHERE is_spam=2 AND manually_reviewed=0 AND from_spam_list=0;",
+        )
+        .map(|row: SqliteRow| row.get("url"))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut pruned = 0;
+
+        for url in urls {
+            let Ok(parsed) = parse_url_like_telegram(&url) else {
+                continue;
+            };
+
+            if self.is_review_entry_stale(&parsed).await? {
+                // Not using `Self::remove_url` to keep the spelling from the database.
+                pruned += sqlx::query("DELETE FROM urls WHERE url=? AND is_spam=2;")
+                    .bind(&url)
+                    .execute(&self.pool)
+                    .await?
+                    .rows_affected();
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Check if this URL waiting for review doesn't need it anymore.
+    /// See [`Self::prune_stale_review_entries`].
+    async fn is_review_entry_stale(&self, url: &Url) -> Result<bool, Error> {
+        let decided = |result: Option<(IsSpam, bool)>| {
+            result.is_some_and(|(is_spam, manually_reviewed)| {
+                is_spam == IsSpam::Yes || manually_reviewed
+            })
+        };
+
+        if let Some(domain) = Domain::from_url(url) {
+            if let Some((IsSpam::Yes, _)) = self.is_domain_spam(&domain, true).await? {
+                return Ok(true);
+            }
+        }
+
+        if decided(self.is_bot_spam(url, true).await?) {
+            return Ok(true);
+        }
+
+        if url.query().is_some() {
+            let entries = self.url_entries_with_fewer_params(url, true).await?;
+            if entries
+                .into_iter()
+                .any(|(is_spam, manually_reviewed, entry)| {
+                    entry != url.as_str() && decided(Some((is_spam, manually_reviewed)))
+                })
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Get names of automatic spam checking heuristics that were turned off.
+    pub async fn get_disabled_heuristics(&self) -> Result<HashSet<String>, Error> {
+        sqlx::query("SELECT name FROM disabled_heuristics;")
+            .map(|row: SqliteRow| row.get("name"))
+            .fetch_all(&self.pool)
+            .await
+            .map(|x| x.into_iter().collect())
+    }
+
+    /// Turn an automatic spam checking heuristic on or off by its name.
+    /// Returns whether it was enabled before.
+    pub async fn set_heuristic_enabled(&self, name: &str, enabled: bool) -> Result<bool, Error> {
+        let changed = if enabled {
+            sqlx::query("DELETE FROM disabled_heuristics WHERE name=?;")
+                .bind(name)
+                .execute(&self.pool)
+                .await?
+        } else {
+            sqlx::query(
+                "INSERT INTO disabled_heuristics (name)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(name)
+            .execute(&self.pool)
+            .await?
+        }
+        .rows_affected()
+            > 0;
+
+        // If turning it on changed something, it was off, and vice versa.
+        Ok(changed != enabled)
+    }
+
+    /// Add a pattern of spam hosts, made by a member of the control chat.
+    /// Returns false if it was already added.
+    pub async fn add_url_pattern(
+        &self,
+        pattern: &HostPattern,
+        added_by: UserId,
+    ) -> Result<bool, Error> {
+        let added = sqlx::query(
+            "INSERT INTO url_patterns (pattern, added_by, added_at)
+                VALUES (?, ?, ?)
+                ON CONFLICT DO NOTHING;",
+        )
+        .bind(pattern.as_str())
+        .bind(added_by.0 as i64)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        *self.url_patterns.lock().await = None;
+        Ok(added)
+    }
+
+    /// Remove a pattern of spam hosts. Returns false if there was no such pattern.
+    pub async fn remove_url_pattern(&self, pattern: &str) -> Result<bool, Error> {
+        let removed = sqlx::query("DELETE FROM url_patterns WHERE pattern=?;")
+            .bind(pattern)
+            .execute(&self.pool)
+            .await?
+            .rows_affected()
+            > 0;
+
+        *self.url_patterns.lock().await = None;
+        Ok(removed)
+    }
+
+    /// Check if this domain was recently found to be marked as spam as a whole,
+    /// not to look it up in the database over and over during a spam wave.
+    pub async fn is_cached_spam_domain(&self, domain: &Domain) -> bool {
+        let mut cache = self.spam_domains.lock().await;
+        let Some(cached_at) = cache.get(domain) else {
+            return false;
+        };
+        if cached_at.elapsed() < SPAM_DOMAIN_CACHE_TTL {
+            true
+        } else {
+            cache.remove(domain);
+            false
+        }
+    }
+
+    /// Remember for a bit that this domain is marked as spam as a whole.
+    /// See [`Self::is_cached_spam_domain`].
+    pub async fn cache_spam_domain(&self, domain: &Domain) {
+        let mut cache = self.spam_domains.lock().await;
+        if cache.len() >= SPAM_DOMAIN_CACHE_SIZE {
+            cache.retain(|_, cached_at| cached_at.elapsed() < SPAM_DOMAIN_CACHE_TTL);
+        }
+        // If it's still full, it's a really big spam wave. It'll have room soon enough.
+        if cache.len() < SPAM_DOMAIN_CACHE_SIZE {
+            cache.insert(domain.clone(), tokio::time::Instant::now());
+        }
+    }
+
+    /// Forget whether this domain is spam, such as when it was just reviewed.
+    /// See [`Self::is_cached_spam_domain`].
+    pub async fn forget_cached_spam_domain(&self, domain: &Domain) {
+        self.spam_domains.lock().await.remove(domain);
+    }
+
+    /// Get all patterns of spam hosts, along with who added them and when, oldest first.
+    pub async fn list_url_patterns(&self) -> Result<Vec<(String, UserId, DateTime<Utc>)>, Error> {
+        sqlx::query("SELECT pattern, added_by, added_at FROM url_patterns ORDER BY added_at;")
+            .map(|row: SqliteRow| {
+                (
+                    row.get("pattern"),
+                    UserId(row.get::<i64, _>("added_by") as u64),
+                    row.get("added_at"),
+                )
+            })
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Get the first pattern of spam hosts that matches this domain, if any.
+    ///
+    /// Patterns are parsed once and kept in memory until they're changed,
+    /// as this is checked for every link that isn't known otherwise.
+    pub async fn matching_url_pattern(&self, domain: &Domain) -> Result<Option<String>, Error> {
+        let patterns = {
+            let mut cached = self.url_patterns.lock().await;
+            match &*cached {
+                Some(patterns) => patterns.clone(),
+                None => {
+                    let patterns = Arc::new(
+                        sqlx::query("SELECT pattern FROM url_patterns;")
+                            .map(|row: SqliteRow| row.get::<String, _>("pattern"))
+                            .fetch_all(&self.pool)
+                            .await?
+                            .into_iter()
+                            .filter_map(|pattern| match HostPattern::parse(&pattern) {
+                                Ok(x) => Some(x),
+                                Err(e) => {
+                                    log::error!(
+                                        "Invalid URL pattern {} in database: {}",
+                                        pattern,
+                                        e
+                                    );
+                                    None
+                                }
+                            })
+                            .collect::<Vec<_>>(),
+                    );
+                    *cached = Some(patterns.clone());
+                    patterns
+                }
+            }
+        };
+
+        Ok(patterns
+            .iter()
+            .find(|x| x.matches(domain.as_str()))
+            .map(|x| x.as_str().to_string()))
+    }
+
+    /// Count domains in the database this pattern matches, and get up to `limit` of them,
+    /// most recently added first, to show what the pattern would do before adding it.
+    pub async fn domains_matching_pattern(
+        &self,
+        pattern: &HostPattern,
+        limit: usize,
+    ) -> Result<(usize, Vec<String>), Error> {
+        let mut count = 0;
+        let mut examples = Vec::with_capacity(limit);
+
+        // Patterns can't be turned into SQL, so just go through all of them.
+        let domains = sqlx::query("SELECT domain FROM domains ORDER BY rowid DESC;")
+            .map(|row: SqliteRow| row.get::<String, _>("domain"))
+            .fetch_all(&self.pool)
+            .await?;
+
+        for domain in domains {
+            if pattern.matches(&domain.to_lowercase()) {
+                count += 1;
+                if examples.len() < limit {
+                    examples.push(domain);
+                }
+            }
+        }
+
+        Ok((count, examples))
+    }
+
+    /// Periodically purge expired URLs and stale review entries,
+    /// until the database is dropped.
+    async fn sweep_expired_urls(db_arc: Arc<Self>) {
+        let mut receiver = db_arc.drop_watch.0.subscribe();
+        let database = Arc::downgrade(&db_arc);
+        drop(db_arc);
+
+        let mut interval = tokio::time::interval(EXPIRED_URLS_SWEEP_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let Some(database) = database.upgrade() else {
+                        // This means the database was dropped.
+                        break;
+                    };
+
+                    match database.purge_expired_urls().await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("Purged {} expired URLs.", count),
+                        Err(e) => log::error!("Failed to purge expired URLs: {}", e),
+                    }
+
+                    match database.prune_stale_review_entries().await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("Pruned {} stale review entries.", count),
+                        Err(e) => log::error!("Failed to prune stale review entries: {}", e),
+                    }
+                },
+                e = receiver.changed() => {
+                    // This means that the database was dropped.
+                    let Err(_e) = e else {
+                        unreachable!();
+                    };
+
+                    break;
+                }
+            };
+        }
+    }
+
+    /// Mark a URL as maybe spam, if it's not already marked as spam
+    /// and wasn't manually reviewed. Returns true if anything is actually done.
+    ///
+    /// Note that this adds a URL entry if one doesn't exist,
+    /// even if there's a meaningful domain entry.
+    ///
+    /// `match_reason` should be set if this is done by the automatic spam checker.
+    /// `context` is the text of the message the URL was found in, if any,
+    /// and is shown to reviewers, see [`review_context`].
+    pub async fn mark_url_sus(
+        &self,
+        url: &Url,
+        match_reason: Option<MatchReason>,
+        context: Option<&str>,
+    ) -> Result<bool, Error> {
+        let match_reason = match_reason.map(u8::from);
+        let context = context.and_then(review_context);
+        let result = sqlx::query(
+            "
+            INSERT INTO urls(
+                    url,
+                    is_spam,
+                    spam_checker_version,
+                    match_reason,
+                    context,
+                    first_seen,
+                    last_seen
+            ) VALUES (?, 2, ?, ?, ?, ?, ?)
+            ON CONFLICT DO
+                UPDATE SET
+                    is_spam=2,
+                    spam_checker_version=?,
+                    match_reason=COALESCE(?, match_reason),
+                    context=COALESCE(?, context)
+                WHERE is_spam IN (0, 4) AND manually_reviewed=0;",
+        )
+        .bind(url.as_str())
+        .bind(SPAM_CHECKER_VERSION)
+        .bind(match_reason)
+        .bind(&context)
+        .bind(Utc::now())
+        .bind(Utc::now())
+        .bind(SPAM_CHECKER_VERSION)
+        .bind(match_reason)
+        .bind(&context)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+        Ok(result)
+    }
+
+    /// Convenience function to mark both a URL and its domain as maybe spam.
+    ///
+    /// `context` is the text of the message the URL was found in, if any,
+    /// and is shown to reviewers, see [`review_context`].
+    pub async fn mark_sus(
+        &self,
+        url: &Url,
+        mut domain: Option<&Domain>,
+        context: Option<&str>,
+    ) -> Result<MarkSusResult, Error> {
+        // We only want to deal with entries in the database that exist.
+
+        // Check the URL one.
+        if let Some(is_spam_url) = self.is_url_spam(url, false).await? {
+            let result = match is_spam_url.0 {
+                IsSpam::Yes => MarkSusResult::AlreadyMarkedSpam,
+                IsSpam::Maybe | IsSpam::Likely => MarkSusResult::AlreadyMarkedSus,
+                IsSpam::No | IsSpam::Unlikely => {
+                    if is_spam_url.1 {
+                        MarkSusResult::ManuallyReviewedNotSpam
+                    } else {
+                        let mark_result = self.mark_url_sus(url, None, context).await?;
+                        if mark_result {
+                            MarkSusResult::Marked
+                        } else {
+                            MarkSusResult::ManuallyReviewedNotSpam
+                        }
+                    }
+                }
+            };
+
+            return Ok(result);
+        }
+
+        // If no provided domain, try to get one from the URL.
+        // Otherwise, use provided domain, to not do an extraneous allocation.
+        let domain_inner;
+        if domain.is_none() {
+            domain_inner = Domain::from_url(url);
+            domain = domain_inner.as_ref();
+        }
+        if let Some(domain) = domain {
+            // Check the domain one.
+            if let Some(is_spam_domain) = self.is_domain_spam(domain, false).await? {
+                if is_spam_domain.1 {
+                    return Ok(MarkSusResult::ManuallyReviewedNotSpam);
+                }
+                let result = match is_spam_domain.0 {
+                    IsSpam::Yes => MarkSusResult::AlreadyMarkedSpam,
+                    IsSpam::Maybe | IsSpam::Likely => MarkSusResult::AlreadyMarkedSus,
+                    IsSpam::No | IsSpam::Unlikely => {
+                        let mark_result = self.mark_domain_sus(domain, Some(url), context).await?;
+                        if mark_result {
+                            MarkSusResult::Marked
+                        } else {
+                            MarkSusResult::ManuallyReviewedNotSpam
+                        }
+                    }
+                };
+
+                return Ok(result);
+            }
+        }
+
+        // It is in neither URL nor Domain tables.
+        // Add it in as a URL entry.
+        self.mark_url_sus(url, None, context).await?;
+        Ok(MarkSusResult::Marked)
+    }
+
+    /// Returns true if the database responds to queries.
+    pub async fn is_alive(&self) -> bool {
+        sqlx::query("SELECT 1;").execute(&self.pool).await.is_ok()
+    }
+
+    /// Delete all entries added from the spam list.
+    pub async fn clean_all_from_spam_list(&self) -> Result<(), Error> {
+        sqlx::query("DELETE FROM domains WHERE from_spam_list=1")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM urls WHERE from_spam_list=1")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Count and return the amount of links left to review.
+    pub async fn get_review_count(&self) -> Result<u32, Error> {
+        sqlx::query(
+            "SELECT SUM(A) FROM
+                (
+                    SELECT COUNT(*) AS A FROM urls WHERE is_spam=2
+                UNION ALL
+                    SELECT COUNT(*) AS A FROM domains WHERE is_spam=2
+                );",
+        )
+        .map(|x: SqliteRow| x.get(0))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Get a URL, and its database table and ID, for review, its state in the database,
+    /// the automatic spam checker rule that determined that state, if any, and text of
+    /// the message it was marked as maybe spam from, if any.
+    #[allow(clippy::type_complexity)]
+    pub async fn get_url_for_review(
+        &self,
+    ) -> Result<Option<(Url, &str, i64, IsSpam, Option<MatchReason>, Option<String>)>, Error> {
         // Get the mutex. It'll be unlocked at the end of the function
         // automatically due to RAII.
         let _the_mutex = self.review_lock.lock();
 
         // We heard you like database queries UwU
-        let db_result: Option<(Url, IsSpam, i64, bool)> = sqlx::query(
+        #[allow(clippy::type_complexity)]
+        let db_result: Option<(
+            Url,
+            IsSpam,
+            i64,
+            bool,
+            Option<MatchReason>,
+            Option<String>,
+        )> = sqlx::query(
             "SELECT * FROM
                 (
                     SELECT url, is_spam, rowid, 1 AS from_urls_table,
-                    manually_reviewed, last_sent_to_review
+                    manually_reviewed, last_sent_to_review, match_reason, context
                     FROM urls
                     WHERE from_spam_list=0
                 UNION
                     SELECT COALESCE(example_url, domain) AS url, is_spam,
                     rowid, 0 AS from_urls_table,
-                    manually_reviewed, last_sent_to_review
+                    manually_reviewed, last_sent_to_review, match_reason, context
                     FROM domains
                     WHERE from_spam_list=0
                 )
-            ORDER BY manually_reviewed, is_spam DESC, last_sent_to_review, rowid DESC LIMIT 1;",
+            ORDER BY manually_reviewed,
+                -- Maybe, then likely, then unlikely, then spam, then not spam.
+                CASE is_spam WHEN 2 THEN 4 WHEN 3 THEN 3 WHEN 4 THEN 2 ELSE is_spam END DESC,
+                last_sent_to_review, rowid DESC LIMIT 1;",
         )
         .map(|row: SqliteRow| {
             (
@@ -604,12 +2061,15 @@ impl Database {
                 IsSpam::from(row.get::<u8, _>("is_spam")),
                 row.get::<i64, _>("rowid"),
                 row.get::<bool, _>("from_urls_table"),
+                row.get::<Option<u8>, _>("match_reason")
+                    .map(MatchReason::from),
+                row.get::<Option<String>, _>("context"),
             )
         })
         .fetch_optional(&self.pool)
         .await?;
 
-        let Some((url, is_spam, rowid, from_urls_table)) = db_result else {
+        let Some((url, is_spam, rowid, from_urls_table, match_reason, context)) = db_result else {
             // Well dang.
             return Ok(None);
         };
@@ -638,26 +2098,107 @@ impl Database {
         };
 
         // Pass it on.
-        Ok(Some((url, table_name, rowid, is_spam)))
+        Ok(Some((
+            url,
+            table_name,
+            rowid,
+            is_spam,
+            match_reason,
+            context,
+        )))
     }
 
-    /// Get a URL from a database table name and rowid.
-    pub async fn get_url_from_table_and_rowid(
+    /// Get the automatic spam checker rule that determined the state of this URL
+    /// in the database, or of its domain if the URL itself doesn't have one.
+    ///
+    /// Returns [`None`] if neither is in the database or was checked automatically.
+    pub async fn get_match_reason(
         &self,
-        table: &str,
-        rowid: i64,
-    ) -> Result<Option<(Url, Option<Domain>)>, Error> {
-        match table {
-            "domains" => {
-                sqlx::query("SELECT domain, example_url FROM domains WHERE rowid=?")
-                    .bind(rowid)
-                    .map(|row: SqliteRow| {
-                        let example_url: Option<&str> = row.get("example_url");
-                        let example_url = example_url.map(|x| {
-                            parse_url_like_telegram(x).expect("Unparsable example URL in database!")
-                        });
-
-                        let domain: &str = row.get("domain");
+        url: &Url,
+        domain: impl Into<Option<&Domain>>,
+    ) -> Result<Option<MatchReason>, Error> {
+        let url_reason: Option<Option<u8>> =
+            sqlx::query("SELECT match_reason FROM urls WHERE url=?;")
+                .bind(url.as_str())
+                .map(|row: SqliteRow| row.get("match_reason"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+        if let Some(Some(reason)) = url_reason {
+            return Ok(Some(reason.into()));
+        }
+
+        let domain_inner;
+        let domain = match domain.into() {
+            Some(domain) => domain,
+            None => {
+                let Some(domain) = Domain::from_url(url) else {
+                    return Ok(None);
+                };
+                domain_inner = domain;
+                &domain_inner
+            }
+        };
+
+        let domain_reason: Option<Option<u8>> =
+            sqlx::query("SELECT match_reason FROM domains WHERE domain=?;")
+                .bind(domain.as_str())
+                .map(|row: SqliteRow| row.get("match_reason"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(domain_reason.flatten().map(MatchReason::from))
+    }
+
+    /// Get the context a URL, or else its domain, was sent to review with,
+    /// see [`review_context`].
+    pub async fn get_review_context(
+        &self,
+        url: &Url,
+        domain: Option<&Domain>,
+    ) -> Result<Option<String>, Error> {
+        let url_context: Option<Option<String>> =
+            sqlx::query("SELECT context FROM urls WHERE url=?;")
+                .bind(url.as_str())
+                .map(|row: SqliteRow| row.get("context"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+        if let Some(Some(context)) = url_context {
+            return Ok(Some(context));
+        }
+
+        let Some(domain) = domain else {
+            return Ok(None);
+        };
+
+        let domain_context: Option<Option<String>> =
+            sqlx::query("SELECT context FROM domains WHERE domain=?;")
+                .bind(domain.as_str())
+                .map(|row: SqliteRow| row.get("context"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(domain_context.flatten())
+    }
+
+    /// Get a URL from a database table name and rowid.
+    pub async fn get_url_from_table_and_rowid(
+        &self,
+        table: &str,
+        rowid: i64,
+    ) -> Result<Option<(Url, Option<Domain>)>, Error> {
+        match table {
+            "domains" => {
+                sqlx::query("SELECT domain, example_url FROM domains WHERE rowid=?")
+                    .bind(rowid)
+                    .map(|row: SqliteRow| {
+                        let example_url: Option<&str> = row.get("example_url");
+                        let example_url = example_url.map(|x| {
+                            parse_url_like_telegram(x).expect("Unparsable example URL in database!")
+                        });
+
+                        let domain: &str = row.get("domain");
                         let domain_url = parse_url_like_telegram(domain)
                             .expect("Unparsable domain as URL in database!");
                         let domain =
@@ -684,6 +2225,92 @@ impl Database {
         }
     }
 
+    /// Remember that this message now has a review keyboard for this entry,
+    /// replacing whatever keyboard it had before.
+    pub async fn remember_review_keyboard(
+        &self,
+        chatid: ChatId,
+        messageid: MessageId,
+        table: &str,
+        rowid: i64,
+    ) -> Result<(), Error> {
+        let was_reviewed = match table {
+            "urls" => "SELECT manually_reviewed FROM urls WHERE rowid=?",
+            "domains" => "SELECT manually_reviewed FROM domains WHERE rowid=?",
+            _ => return Ok(()),
+        };
+        let Some(was_reviewed) = sqlx::query(was_reviewed)
+            .bind(rowid)
+            .map(|row: SqliteRow| row.get::<bool, _>("manually_reviewed"))
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        sqlx::query(
+            "INSERT INTO review_keyboards
+                (chatid, messageid, table_name, entry_rowid, was_reviewed, sent_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                ON CONFLICT(chatid, messageid) DO UPDATE SET
+                table_name=excluded.table_name,
+                entry_rowid=excluded.entry_rowid,
+                was_reviewed=excluded.was_reviewed,
+                sent_at=excluded.sent_at;",
+        )
+        .bind(chatid.0)
+        .bind(messageid.0)
+        .bind(table)
+        .bind(rowid)
+        .bind(was_reviewed)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Forget that this message has a review keyboard, if it had one.
+    pub async fn forget_review_keyboard(
+        &self,
+        chatid: ChatId,
+        messageid: MessageId,
+    ) -> Result<(), Error> {
+        sqlx::query("DELETE FROM review_keyboards WHERE chatid=? AND messageid=?;")
+            .bind(chatid.0)
+            .bind(messageid.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Get all remembered review keyboards, oldest first.
+    pub async fn get_review_keyboards(&self) -> Result<Vec<ReviewKeyboard>, Error> {
+        sqlx::query(
+            "SELECT k.chatid, k.messageid, k.was_reviewed, k.sent_at,
+                CASE k.table_name
+                    WHEN 'urls' THEN u.manually_reviewed
+                    WHEN 'domains' THEN d.manually_reviewed
+                END AS now_reviewed
+            FROM review_keyboards k
+            LEFT JOIN urls u ON k.table_name='urls' AND u.rowid=k.entry_rowid
+            LEFT JOIN domains d ON k.table_name='domains' AND d.rowid=k.entry_rowid
+            ORDER BY k.sent_at;",
+        )
+        .map(|row: SqliteRow| {
+            let was_reviewed: bool = row.get("was_reviewed");
+            let now_reviewed: Option<bool> = row.get("now_reviewed");
+            ReviewKeyboard {
+                chat: ChatId(row.get("chatid")),
+                message: MessageId(row.get("messageid")),
+                sent_at: row.get("sent_at"),
+                // Gone from the database, or reviewed by someone since it was shown.
+                resolved: now_reviewed.is_none_or(|now| now && !was_reviewed),
+            }
+        })
+        .fetch_all(&self.pool)
+        .await
+    }
+
     /// Remove a domain from the database, if it exists.
     #[allow(dead_code)]
     pub async fn remove_domain(&self, domain: &Domain) -> Result<(), Error> {
@@ -703,38 +2330,139 @@ impl Database {
         Ok(())
     }
 
+    /// Mark every link to the Telegram bot this URL leads to as spam or not,
+    /// regardless of the start parameter or anything else in it.
+    /// Does nothing if it's not a link to a bot. See [`telegram_bot_url`].
+    pub async fn add_bot(
+        &self,
+        url: &Url,
+        is_spam: IsSpam,
+        manually_reviewed: bool,
+    ) -> Result<(), Error> {
+        let Some(bot) = telegram_bot_url(url) else {
+            return Ok(());
+        };
+
+        self.add_url_with_scope(&bot, true, is_spam, false, manually_reviewed, None, None)
+            .await
+    }
+
     pub async fn read_review_response(&self, response: &ReviewResponse) -> Result<(), Error> {
         match response {
             ReviewResponse::Skip => (),
             ReviewResponse::UrlSpam(_domain, url) => {
-                self.add_url(url, IsSpam::Yes, false, true).await?;
+                self.add_url(url, IsSpam::Yes, false, true, None, None)
+                    .await?;
             }
             ReviewResponse::DomainSpam(domain, url) => {
-                self.add_domain(domain, Some(url), IsSpam::Yes, false, true)
+                self.add_domain(domain, Some(url), IsSpam::Yes, false, true, None)
                     .await?;
                 // Implicitly this means that this specific URL is also spam,
                 // as part of this domain.
                 self.remove_url(url).await?;
             }
+            ReviewResponse::BotSpam(_domain, url) => {
+                self.add_bot(url, IsSpam::Yes, true).await?;
+                // Same as with domains, this specific URL is spam as part of the bot.
+                if telegram_bot_url(url).as_ref() != Some(url) {
+                    self.remove_url(url).await?;
+                }
+            }
             ReviewResponse::NotSpam(domain, url) => {
                 // Neither domain nor URL are spam.
 
                 // Write the result about the URL unconditionally.
-                self.add_url(url, IsSpam::No, false, true).await?;
+                self.add_url(url, IsSpam::No, false, true, None, None)
+                    .await?;
 
                 if let Some(domain) = domain {
                     // But only write about the domain if it's already in the database lol
                     if self.is_domain_spam(domain, true).await?.is_some() {
-                        self.add_domain(domain, Some(url), IsSpam::No, false, true)
+                        self.add_domain(domain, Some(url), IsSpam::No, false, true, None)
+                            .await?;
+                    }
+                }
+            }
+            ReviewResponse::LikelySpam(_domain, url) => {
+                self.add_url(url, IsSpam::Likely, false, true, None, None)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ingest a review response that came from another instance of this bot.
+    ///
+    /// Unlike [`Self::read_review_response`], this doesn't mark anything as manually
+    /// reviewed, and doesn't touch entries that were manually reviewed here,
+    /// so that local reviews always win.
+    pub async fn read_synced_review_response(
+        &self,
+        response: &ReviewResponse,
+    ) -> Result<(), Error> {
+        match response {
+            ReviewResponse::Skip => (),
+            ReviewResponse::UrlSpam(_domain, url) => {
+                if !self.url_manually_reviewed(url).await? {
+                    self.add_url(url, IsSpam::Yes, false, false, None, None)
+                        .await?;
+                }
+            }
+            ReviewResponse::DomainSpam(domain, url) => {
+                if !self.domain_manually_reviewed(domain).await? {
+                    self.add_domain(domain, Some(url), IsSpam::Yes, false, false, None)
+                        .await?;
+                }
+            }
+            ReviewResponse::BotSpam(_domain, url) => {
+                if !matches!(self.is_bot_spam(url, true).await?, Some((_, true))) {
+                    self.add_bot(url, IsSpam::Yes, false).await?;
+                }
+            }
+            ReviewResponse::NotSpam(domain, url) => {
+                if !self.url_manually_reviewed(url).await? {
+                    self.add_url(url, IsSpam::No, false, false, None, None)
+                        .await?;
+                }
+
+                if let Some(domain) = domain {
+                    // Same as with manual reviews, only if it's already in the database.
+                    if self.is_domain_spam(domain, true).await?.is_some()
+                        && !self.domain_manually_reviewed(domain).await?
+                    {
+                        self.add_domain(domain, Some(url), IsSpam::No, false, false, None)
                             .await?;
                     }
                 }
             }
+            ReviewResponse::LikelySpam(_domain, url) => {
+                if !self.url_manually_reviewed(url).await? {
+                    self.add_url(url, IsSpam::Likely, false, false, None, None)
+                        .await?;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Returns true if this exact URL was manually reviewed.
+    async fn url_manually_reviewed(&self, url: &Url) -> Result<bool, Error> {
+        Ok(matches!(
+            self.is_url_spam_exact(url, true).await?,
+            Some((_, true))
+        ))
+    }
+
+    /// Returns true if this domain was manually reviewed.
+    async fn domain_manually_reviewed(&self, domain: &Domain) -> Result<bool, Error> {
+        Ok(matches!(
+            self.is_domain_spam(domain, true).await?,
+            Some((_, true))
+        ))
+    }
+
     /// Gets whether or not admins of this chat want the bot to not show
     /// notifications about deleting a message.
     pub async fn get_hide_deletes(&self, chatid: ChatId) -> Result<bool, Error> {
@@ -775,332 +2503,2176 @@ impl Database {
 
         Ok(old_state)
     }
-}
 
-pub struct DomainVisitDebounceGuard {
-    database: Arc<Database>,
-    domain: Domain,
-}
+    /// Gets all chats whose admins asked the bot to not show notifications
+    /// about deleting a message.
+    pub async fn list_hide_delete_chats(&self) -> Result<Vec<ChatId>, Error> {
+        sqlx::query("SELECT chatid FROM hide_deletes ORDER BY chatid;")
+            .map(|row: SqliteRow| ChatId(row.get("chatid")))
+            .fetch_all(&self.pool)
+            .await
+    }
 
-impl Drop for DomainVisitDebounceGuard {
-    fn drop(&mut self) {
-        let tokio_handle = tokio::runtime::Handle::current();
-        let database = self.database.clone();
-        let mut domain = Domain::new_invalid_unchecked();
+    /// Gets whether or not admins of this chat want the bot to delete spam in posts
+    /// automatically forwarded from the channel linked to the chat.
+    pub async fn get_moderate_channel_posts(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM moderate_channel_posts WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
 
-        std::mem::swap(&mut self.domain, &mut domain);
+    /// Sets whether or not admins of this chat want the bot to delete spam in posts
+    /// automatically forwarded from the channel linked to the chat.
+    /// Returns the previous state.
+    pub async fn set_moderate_channel_posts(
+        &self,
+        chatid: ChatId,
+        moderate: bool,
+    ) -> Result<bool, Error> {
+        let old_state = self.get_moderate_channel_posts(chatid).await?;
 
-        tokio_handle.spawn(async move {
-            database
-                .domains_currently_being_visited
-                .lock()
-                .await
-                .remove(&domain);
-            database.domains_visit_notify.notify_waiters();
-        });
+        if old_state == moderate {
+            return Ok(moderate);
+        }
+
+        if moderate {
+            sqlx::query(
+                "INSERT INTO moderate_channel_posts (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM moderate_channel_posts WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
     }
-}
 
-impl Database {
-    /// Returns [`DomainVisitDebounceGuard`] if this domain isn't being visited,
-    /// or, if it is, blocks until that is done and then returns [`None`].
-    pub async fn domain_visit_debounce(
-        self: &Arc<Database>,
-        domain: Domain,
-    ) -> Option<DomainVisitDebounceGuard> {
-        // This is set to true if this domain was spotted to be in the process of being visited.
-        let mut was_visited = false;
+    /// Gets whether or not admins of this chat want the bot to only report spam,
+    /// without deleting it.
+    pub async fn get_report_only(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM report_only WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
 
-        // Check if it's already being visited on loop until it's no longer being visited.
-        loop {
-            // Code below looks a bit weird, but it's to avoid a race condition.
-            // Consider the following scenario:
-            // 1. Task A is currently visiting a domain.
-            // 2. Task B runs this function to check it.
-            // 3. Task B finds the domain in the hash set in the check below.
-            // 4. Task A finishes visiting the domain and removes it from the
-            // hash set.
-            // 5. Task A then punts the Notify.
-            // 6. Task B, awaits the Notify *after* that.
-            // 7. Task B locks up until something else happens to punt the Notify.
-            //
-            // For this reason, task B has to ensure that task A can't punt the Notify
-            // after task B checked the hash set but before it awaits the Notify.
-            //
-            // This is accomplished with the visited_lock: the hash set is locked
-            // for checking, then Notify is awaited on, and only then it's unlocked.
+    /// Sets whether or not admins of this chat want the bot to only report spam,
+    /// without deleting it. Returns the previous state.
+    pub async fn set_report_only(&self, chatid: ChatId, report_only: bool) -> Result<bool, Error> {
+        let old_state = self.get_report_only(chatid).await?;
 
-            let mut visited_lock = self.domains_currently_being_visited.lock().await;
+        if old_state == report_only {
+            return Ok(report_only);
+        }
 
-            let contains = visited_lock.contains(&domain);
-            if contains {
-                // It's being visited. Wait on notify and check again.
-                was_visited = true;
-                // Notified immediately starts listening as it is created.
-                let notify_waiter = self.domains_visit_notify.notified();
-                drop(visited_lock);
-                notify_waiter.await;
-            } else {
-                // It is not or no longer being visited.
-                // Add it to the list and return the guard.
+        if report_only {
+            sqlx::query(
+                "INSERT INTO report_only (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM report_only WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
+    }
+
+    /// Gets whether or not admins of this chat want the bot to also delete messages
+    /// with links that are only likely spam. See [`IsSpam::Likely`].
+    pub async fn get_strict_mode(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM strict_mode WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
+
+    /// Sets whether or not admins of this chat want the bot to also delete messages
+    /// with links that are only likely spam. Returns the previous state.
+    pub async fn set_strict_mode(&self, chatid: ChatId, strict: bool) -> Result<bool, Error> {
+        let old_state = self.get_strict_mode(chatid).await?;
+
+        if old_state == strict {
+            return Ok(strict);
+        }
+
+        if strict {
+            sqlx::query(
+                "INSERT INTO strict_mode (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM strict_mode WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
+    }
+
+    /// Gets whether or not admins of this chat want spam deletion notifications
+    /// to say what to do about a false positive.
+    pub async fn get_appeal_hint(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM appeal_hint WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
+
+    /// Sets whether or not admins of this chat want spam deletion notifications
+    /// to say what to do about a false positive. Returns the previous state.
+    pub async fn set_appeal_hint(&self, chatid: ChatId, show: bool) -> Result<bool, Error> {
+        let old_state = self.get_appeal_hint(chatid).await?;
+
+        if old_state == show {
+            return Ok(show);
+        }
+
+        if show {
+            sqlx::query(
+                "INSERT INTO appeal_hint (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM appeal_hint WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
+    }
+
+    /// Gets whether or not admins of this chat want the bot to delete the whole album
+    /// when a message in it contains spam.
+    pub async fn get_delete_albums(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM delete_albums WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
+
+    /// Sets whether or not admins of this chat want the bot to delete the whole album
+    /// when a message in it contains spam. Returns the previous state.
+    pub async fn set_delete_albums(&self, chatid: ChatId, delete: bool) -> Result<bool, Error> {
+        let old_state = self.get_delete_albums(chatid).await?;
+
+        if old_state == delete {
+            return Ok(delete);
+        }
+
+        if delete {
+            sqlx::query(
+                "INSERT INTO delete_albums (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM delete_albums WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+            sqlx::query("DELETE FROM album_messages WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
+    }
+
+    /// Remember that this message is a part of this album, so that it can be
+    /// deleted with [`Self::take_album_messages`] if another message in it is spam.
+    /// Also forgets messages seen longer than [`ALBUM_MEMORY_TIME`] ago.
+    pub async fn record_album_message(
+        &self,
+        chatid: ChatId,
+        album_id: &str,
+        messageid: MessageId,
+    ) -> Result<(), Error> {
+        let now = Utc::now();
+
+        sqlx::query("DELETE FROM album_messages WHERE seen_at<?;")
+            .bind(now - ALBUM_MEMORY_TIME)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO album_messages (chatid, album_id, messageid, seen_at)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT DO NOTHING;",
+        )
+        .bind(chatid.0)
+        .bind(album_id)
+        .bind(messageid.0)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get and forget all recently seen messages of this album.
+    pub async fn take_album_messages(
+        &self,
+        chatid: ChatId,
+        album_id: &str,
+    ) -> Result<Vec<MessageId>, Error> {
+        sqlx::query(
+            "DELETE FROM album_messages WHERE chatid=? AND album_id=? AND seen_at>=?
+                RETURNING messageid;",
+        )
+        .bind(chatid.0)
+        .bind(album_id)
+        .bind(Utc::now() - ALBUM_MEMORY_TIME)
+        .map(|row: SqliteRow| MessageId(row.get("messageid")))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Remember that a spam message in this album was deleted.
+    pub async fn set_last_deleted_album_id(
+        &self,
+        chatid: ChatId,
+        album_id: &str,
+    ) -> Result<(), Error> {
+        let now = Utc::now();
+        sqlx::query(
+            "INSERT INTO last_deleted_albums (chatid, album_id, deleted_at)
+                VALUES (?, ?, ?)
+                ON CONFLICT DO UPDATE SET album_id=?, deleted_at=?;",
+        )
+        .bind(chatid.0)
+        .bind(album_id)
+        .bind(now)
+        .bind(album_id)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the latest album in this chat that had a spam message deleted,
+    /// if that happened within the last [`ALBUM_MEMORY_TIME`].
+    pub async fn get_last_deleted_album_id(&self, chatid: ChatId) -> Result<Option<String>, Error> {
+        sqlx::query("SELECT album_id FROM last_deleted_albums WHERE chatid=? AND deleted_at>=?;")
+            .bind(chatid.0)
+            .bind(Utc::now() - ALBUM_MEMORY_TIME)
+            .map(|row: SqliteRow| row.get("album_id"))
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Gets whether or not admins of this chat want the bot to hide first messages
+    /// of users if they contain links, until they confirm they're human.
+    pub async fn get_first_link_guard(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM first_link_guard WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
+
+    /// Sets whether or not admins of this chat want the bot to hide first messages
+    /// of users if they contain links, until they confirm they're human.
+    /// Returns the previous state.
+    ///
+    /// Users seen in the chat are only tracked while this is enabled,
+    /// and are forgotten when it's disabled.
+    pub async fn set_first_link_guard(&self, chatid: ChatId, guard: bool) -> Result<bool, Error> {
+        let old_state = self.get_first_link_guard(chatid).await?;
+
+        if old_state == guard {
+            return Ok(guard);
+        }
+
+        if guard {
+            sqlx::query(
+                "INSERT INTO first_link_guard (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM first_link_guard WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+            sqlx::query("DELETE FROM chat_members WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
+    }
+
+    /// Remember that this user just joined this chat, so that the first link guard
+    /// hides their first message with links, unless they're already verified.
+    pub async fn add_new_member(&self, chatid: ChatId, userid: UserId) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO chat_members (chatid, userid, first_seen_at)
+                VALUES (?, ?, ?)
+                ON CONFLICT DO NOTHING;",
+        )
+        .bind(chatid.0)
+        .bind(userid.0 as i64)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Return whether this user is verified to not be a spammer in this chat.
+    ///
+    /// Users that weren't seen joining with [`Self::add_new_member`] have been
+    /// in the chat since before the first link guard was enabled, so they're
+    /// remembered as verified the first time they're seen here.
+    pub async fn check_member_verified(
+        &self,
+        chatid: ChatId,
+        userid: UserId,
+    ) -> Result<bool, Error> {
+        sqlx::query(
+            "INSERT INTO chat_members (chatid, userid, first_seen_at, verified)
+                VALUES (?, ?, ?, 1)
+                ON CONFLICT DO NOTHING;",
+        )
+        .bind(chatid.0)
+        .bind(userid.0 as i64)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query("SELECT verified FROM chat_members WHERE chatid=? AND userid=?;")
+            .bind(chatid.0)
+            .bind(userid.0 as i64)
+            .map(|row: SqliteRow| row.get("verified"))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Remember that this user is not a spammer, so the first link guard
+    /// doesn't hide their messages in this chat anymore.
+    pub async fn set_member_verified(&self, chatid: ChatId, userid: UserId) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO chat_members (chatid, userid, first_seen_at, verified)
+                VALUES (?, ?, ?, 1)
+                ON CONFLICT DO UPDATE SET verified=1;",
+        )
+        .bind(chatid.0)
+        .bind(userid.0 as i64)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Hold on to the text of a message from this user that was hidden by the first link guard,
+    /// so that it can be posted again with [`Self::take_quarantined_message`].
+    /// Also forgets messages hidden longer than [`QUARANTINE_TIME`] ago.
+    ///
+    /// Returns the ID of the held message.
+    pub async fn add_quarantined_message(
+        &self,
+        chatid: ChatId,
+        userid: UserId,
+        text: &str,
+    ) -> Result<i64, Error> {
+        let now = Utc::now();
+
+        sqlx::query("DELETE FROM quarantine WHERE hidden_at<?;")
+            .bind(now - QUARANTINE_TIME)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO quarantine (chatid, userid, text, hidden_at)
+                VALUES (?, ?, ?, ?)
+                RETURNING rowid;",
+        )
+        .bind(chatid.0)
+        .bind(userid.0 as i64)
+        .bind(text)
+        .bind(now)
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Get and forget the text of this hidden message, if it was sent by this user
+    /// within the last [`QUARANTINE_TIME`].
+    pub async fn take_quarantined_message(
+        &self,
+        id: i64,
+        userid: UserId,
+    ) -> Result<Option<String>, Error> {
+        sqlx::query(
+            "DELETE FROM quarantine WHERE rowid=? AND userid=? AND hidden_at>=?
+                RETURNING text;",
+        )
+        .bind(id)
+        .bind(userid.0 as i64)
+        .bind(Utc::now() - QUARANTINE_TIME)
+        .map(|row: SqliteRow| row.get("text"))
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Forget this hidden message, for when nobody posted it again in time.
+    /// Returns false if it was already posted again or forgotten.
+    pub async fn forget_quarantined_message(&self, id: i64) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM quarantine WHERE rowid=?;")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|x| x.rows_affected() > 0)
+    }
+
+    /// Returns `true` only the first time it's called for this chat since the bot started,
+    /// so that its admins are warned about its linked channel posting spam only once.
+    pub async fn should_warn_about_channel_spam(&self, chatid: ChatId) -> bool {
+        self.channel_spam_warned.lock().await.insert(chatid)
+    }
+
+    /// Returns `true` only the first time it's called for this message, so that spam
+    /// left alone in chats that only report it isn't reported again when it's edited
+    /// or replied to.
+    ///
+    /// If a lot of messages were reported, the oldest ones are forgotten after a while.
+    pub async fn should_report_spam(&self, chatid: ChatId, messageid: MessageId) -> bool {
+        let mut reported = self.reported_spam.lock().await;
+        if reported.contains_key(&(chatid, messageid)) {
+            return false;
+        }
+        if reported.len() >= REPORTED_SPAM_MEMORY_SIZE {
+            reported.retain(|_, reported_at| reported_at.elapsed() < REPORTED_SPAM_MEMORY_TIME);
+        }
+        // If it's still full, better to maybe report something twice than to not report it.
+        if reported.len() < REPORTED_SPAM_MEMORY_SIZE {
+            reported.insert((chatid, messageid), tokio::time::Instant::now());
+        }
+        true
+    }
+
+    /// Admin statuses of users in chats, and channels linked to chats, as last seen.
+    pub fn admin_cache(&self) -> &AdminCache {
+        &self.admin_cache
+    }
+
+    /// Returns `true` if this message was sent before the bot started,
+    /// i.e. it was missed while the bot was down and is only delivered now.
+    pub fn is_missed_message(&self, message: &teloxide::types::Message) -> bool {
+        message.date < self.started_at
+    }
+
+    /// Wait until it's time to handle another missed message.
+    /// Those are let through one per [`REPLAY_INTERVAL`] across all chats.
+    pub async fn wait_for_replay_turn(&self) {
+        let mut last_replay = self.last_replay.lock().await;
+        if let Some(last_replay) = *last_replay {
+            tokio::time::sleep_until(last_replay + REPLAY_INTERVAL).await;
+        }
+        *last_replay = Some(tokio::time::Instant::now());
+    }
+
+    /// Gets whether or not admins of this chat want the bot to catch up
+    /// on messages sent while it was down.
+    pub async fn get_backfill(&self, chatid: ChatId) -> Result<bool, Error> {
+        sqlx::query("SELECT 1 FROM chat_progress WHERE chatid=?")
+            .bind(chatid.0)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|x| x.is_some())
+    }
+
+    /// Sets whether or not admins of this chat want the bot to catch up
+    /// on messages sent while it was down. Returns the previous state.
+    ///
+    /// Handled messages are only tracked while this is enabled.
+    pub async fn set_backfill(&self, chatid: ChatId, backfill: bool) -> Result<bool, Error> {
+        let old_state = self.get_backfill(chatid).await?;
+
+        if backfill {
+            sqlx::query(
+                "INSERT INTO chat_progress (chatid)
+                    VALUES (?)
+                    ON CONFLICT DO NOTHING;",
+            )
+            .bind(chatid.0)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM chat_progress WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(old_state)
+    }
+
+    /// Remember that this message was handled, if this chat catches up
+    /// on messages sent while the bot was down.
+    ///
+    /// Returns `false` if a message at least as new was handled already,
+    /// i.e. this one was delivered again and shouldn't be handled twice.
+    /// Always returns `true` in chats that don't catch up.
+    pub async fn record_chat_progress(
+        &self,
+        chatid: ChatId,
+        messageid: MessageId,
+    ) -> Result<bool, Error> {
+        let last: Option<Option<i32>> =
+            sqlx::query("SELECT last_messageid FROM chat_progress WHERE chatid=?")
+                .bind(chatid.0)
+                .map(|row: SqliteRow| row.get("last_messageid"))
+                .fetch_optional(&self.pool)
+                .await?;
+
+        match last {
+            None => Ok(true),
+            Some(Some(last)) if last >= messageid.0 => Ok(false),
+            Some(_) => {
+                sqlx::query("UPDATE chat_progress SET last_messageid=? WHERE chatid=?;")
+                    .bind(messageid.0)
+                    .bind(chatid.0)
+                    .execute(&self.pool)
+                    .await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Gets how many seconds admins of this chat want the bot to wait before
+    /// deleting a spam message. 0 means deleting immediately.
+    pub async fn get_grace_period(&self, chatid: ChatId) -> Result<u32, Error> {
+        sqlx::query("SELECT seconds FROM grace_periods WHERE chatid=?")
+            .bind(chatid.0)
+            .map(|row: SqliteRow| row.get::<u32, _>("seconds"))
+            .fetch_optional(&self.pool)
+            .await
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Sets how many seconds admins of this chat want the bot to wait before
+    /// deleting a spam message. 0 means deleting immediately.
+    pub async fn set_grace_period(&self, chatid: ChatId, seconds: u32) -> Result<(), Error> {
+        if seconds == 0 {
+            sqlx::query("DELETE FROM grace_periods WHERE chatid=?;")
+                .bind(chatid.0)
+                .execute(&self.pool)
+                .await?;
+        } else {
+            sqlx::query(
+                "INSERT INTO grace_periods (chatid, seconds)
+                    VALUES (?, ?)
+                    ON CONFLICT DO UPDATE SET seconds=?;",
+            )
+            .bind(chatid.0)
+            .bind(seconds)
+            .bind(seconds)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Schedule a deletion of a message by calling `spawn` and remembering the task it returns.
+    ///
+    /// Returns false without calling `spawn` if a deletion of this message
+    /// is already scheduled, or was cancelled before.
+    pub async fn add_pending_deletion(
+        &self,
+        chatid: ChatId,
+        messageid: MessageId,
+        spawn: impl FnOnce() -> AbortHandle,
+    ) -> bool {
+        let mut pending = self.pending_deletions.lock().await;
+
+        if pending.contains_key(&(chatid, messageid))
+            || self
+                .kept_messages
+                .lock()
+                .await
+                .contains_key(&(chatid, messageid))
+        {
+            return false;
+        }
+
+        pending.insert(
+            (chatid, messageid),
+            PendingDeletion {
+                handle: spawn(),
+                notice: None,
+            },
+        );
+        true
+    }
+
+    /// Remember the message that tells the chat about a deletion scheduled with
+    /// [`Self::add_pending_deletion`], so that it can be edited if it's cancelled.
+    pub async fn set_pending_deletion_notice(
+        &self,
+        chatid: ChatId,
+        messageid: MessageId,
+        notice: MessageId,
+    ) {
+        if let Some(pending) = self
+            .pending_deletions
+            .lock()
+            .await
+            .get_mut(&(chatid, messageid))
+        {
+            pending.notice = Some(notice);
+        }
+    }
+
+    /// To be called by the task scheduled with [`Self::add_pending_deletion`] right before
+    /// it deletes the message, or if it fails before that, to forget about the deletion.
+    /// Returns false if the deletion was cancelled in the meantime.
+    pub async fn take_pending_deletion(&self, chatid: ChatId, messageid: MessageId) -> bool {
+        self.pending_deletions
+            .lock()
+            .await
+            .remove(&(chatid, messageid))
+            .is_some()
+    }
+
+    /// Get the latest spam deletion notification sent in this chat, if any.
+    ///
+    /// It's behind its own [`Mutex`] per chat, so that it can be held while
+    /// sending or editing the notification without blocking other chats.
+    pub async fn deletion_notice(&self, chatid: ChatId) -> Arc<Mutex<Option<DeletionNotice>>> {
+        self.deletion_notices
+            .lock()
+            .await
+            .entry(chatid)
+            .or_default()
+            .clone()
+    }
+
+    /// Get where to send notifications for reviewers to have them batched,
+    /// calling `spawn` to start the task that sends them the first time.
+    ///
+    /// `spawn` is called again if that task has stopped.
+    pub async fn review_notifier(
+        &self,
+        spawn: impl FnOnce() -> mpsc::UnboundedSender<ReviewNotice>,
+    ) -> mpsc::UnboundedSender<ReviewNotice> {
+        let mut notifier = self.review_notifier.lock().await;
+
+        match &*notifier {
+            Some(sender) if !sender.is_closed() => sender.clone(),
+            _ => notifier.insert(spawn()).clone(),
+        }
+    }
+
+    /// Cancel a deletion scheduled with [`Self::add_pending_deletion`], and make sure
+    /// this message won't be scheduled for deletion again.
+    ///
+    /// Returns [`None`] if there was no such deletion pending, or the message telling
+    /// the chat about it if it was already sent.
+    pub async fn cancel_pending_deletion(
+        &self,
+        chatid: ChatId,
+        messageid: MessageId,
+    ) -> Option<Option<MessageId>> {
+        let pending = self
+            .pending_deletions
+            .lock()
+            .await
+            .remove(&(chatid, messageid))?;
+        pending.handle.abort();
+
+        let mut kept = self.kept_messages.lock().await;
+        if kept.len() >= KEPT_MESSAGES_MEMORY_SIZE {
+            kept.retain(|_, kept_at| kept_at.elapsed() < KEPT_MESSAGES_MEMORY_TIME);
+        }
+        // If it's still full, forget about the oldest one. Worst case, admins have to
+        // cancel the deletion of that message again if it's edited.
+        if kept.len() >= KEPT_MESSAGES_MEMORY_SIZE {
+            if let Some(oldest) = kept.iter().min_by_key(|(_, x)| **x).map(|(k, _)| *k) {
+                kept.remove(&oldest);
+            }
+        }
+        kept.insert((chatid, messageid), tokio::time::Instant::now());
+
+        Some(pending.notice)
+    }
+
+    /// Move everything stored about a chat to its new ID, for when a group is
+    /// upgraded to a supergroup. If the new chat already has something stored
+    /// in some table, that is kept instead.
+    ///
+    /// Pending deletions in the old chat are cancelled, as they can't be done anymore.
+    pub async fn migrate_chat_id(&self, old: ChatId, new: ChatId) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        for table in CHAT_TABLES {
+            // Rows that would conflict with ones of the new chat are left behind...
+            sqlx::query(&format!(
+                "UPDATE OR IGNORE {} SET chatid=? WHERE chatid=?;",
+                table
+            ))
+            .bind(new.0)
+            .bind(old.0)
+            .execute(&mut *transaction)
+            .await?;
+            // ...and then dropped.
+            sqlx::query(&format!("DELETE FROM {} WHERE chatid=?;", table))
+                .bind(old.0)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        self.pending_deletions
+            .lock()
+            .await
+            .retain(|(chatid, _), pending| {
+                if *chatid != old {
+                    return true;
+                }
+                pending.handle.abort();
+                false
+            });
+
+        Ok(())
+    }
+}
+
+pub struct DomainVisitDebounceGuard {
+    database: Arc<Database>,
+    domain: Domain,
+}
+
+impl Drop for DomainVisitDebounceGuard {
+    fn drop(&mut self) {
+        let tokio_handle = tokio::runtime::Handle::current();
+        let database = self.database.clone();
+        let mut domain = Domain::new_invalid_unchecked();
+
+        std::mem::swap(&mut self.domain, &mut domain);
+
+        tokio_handle.spawn(async move {
+            database
+                .domains_currently_being_visited
+                .lock()
+                .await
+                .remove(&domain);
+            database.domains_visit_notify.notify_waiters();
+        });
+    }
+}
+
+impl Database {
+    /// Returns [`DomainVisitDebounceGuard`] if this domain isn't being visited,
+    /// or, if it is, blocks until that is done and then returns [`None`].
+    pub async fn domain_visit_debounce(
+        self: &Arc<Database>,
+        domain: Domain,
+    ) -> Option<DomainVisitDebounceGuard> {
+        // This is set to true if this domain was spotted to be in the process of being visited.
+        let mut was_visited = false;
+
+        // Check if it's already being visited on loop until it's no longer being visited.
+        loop {
+            // Code below looks a bit weird, but it's to avoid a race condition.
+            // Consider the following scenario:
+            // 1. Task A is currently visiting a domain.
+            // 2. Task B runs this function to check it.
+            // 3. Task B finds the domain in the hash set in the check below.
+            // 4. Task A finishes visiting the domain and removes it from the
+            // hash set.
+            // 5. Task A then punts the Notify.
+            // 6. Task B, awaits the Notify *after* that.
+            // 7. Task B locks up until something else happens to punt the Notify.
+            //
+            // For this reason, task B has to ensure that task A can't punt the Notify
+            // after task B checked the hash set but before it awaits the Notify.
+            //
+            // This is accomplished with the visited_lock: the hash set is locked
+            // for checking, then Notify is awaited on, and only then it's unlocked.
+
+            let mut visited_lock = self.domains_currently_being_visited.lock().await;
+
+            let contains = visited_lock.contains(&domain);
+            if contains {
+                // It's being visited. Wait on notify and check again.
+                was_visited = true;
+                // Notified immediately starts listening as it is created.
+                let notify_waiter = self.domains_visit_notify.notified();
+                drop(visited_lock);
+                notify_waiter.await;
+            } else {
+                // It is not or no longer being visited.
+                // Add it to the list and return the guard.
+
+                if was_visited {
+                    break None;
+                }
+
+                visited_lock.insert(domain.clone());
+                drop(visited_lock);
+
+                break Some(DomainVisitDebounceGuard {
+                    database: self.clone(),
+                    domain,
+                });
+            }
+        }
+    }
+}
+
+/// Make text of a message into context to store along with links from it for reviewers:
+/// all whitespace collapsed into single spaces, and cut to [`MAX_REVIEW_CONTEXT_LENGTH`]
+/// characters with an ellipsis. It's stored unescaped.
+///
+/// Returns [`None`] if there's no text.
+pub fn review_context(text: &str) -> Option<String> {
+    let mut context = String::new();
+
+    for (i, word) in text.split_whitespace().enumerate() {
+        if i > 0 {
+            context.push(' ');
+        }
+        context.push_str(word);
+    }
+
+    if context.is_empty() {
+        return None;
+    }
+
+    if let Some((cut, _)) = context.char_indices().nth(MAX_REVIEW_CONTEXT_LENGTH) {
+        context.truncate(cut);
+        context.push('…');
+    }
+
+    Some(context)
+}
+
+/// Escape `text` to match it literally in a `LIKE` pattern with `ESCAPE '\'`.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Ret = Result<(), Error>;
+
+    pub fn new_temp() -> impl std::future::Future<Output = Result<Arc<Database>, Error>> + Send {
+        Database::new_by_path(None, "sqlite::memory:", false)
+    }
+
+    #[tokio::test]
+    async fn create_db() -> Ret {
+        new_temp().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_url_spam() -> Ret {
+        let db = new_temp().await?;
+        let spam: Url = parse_url_like_telegram("amogus.com/badspam").unwrap();
+
+        assert_eq!(db.is_url_spam(&spam, false).await?, None);
+        assert_eq!(db.is_spam(&spam, None, false).await?, None);
+
+        db.add_url(&spam, IsSpam::Yes, false, false, None, None)
+            .await?;
+        assert_eq!(
+            db.is_url_spam(&spam, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+
+        let other: Url = parse_url_like_telegram("amogus.com/otherurl").unwrap();
+        assert_eq!(db.is_url_spam(&other, false).await?, None);
+
+        let spamdomain: Domain = Domain::from_url(&spam).unwrap();
+        // This checks if the domain specifically is a spam, so it will return None.
+        assert_eq!(db.is_domain_spam(&spamdomain, false).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_url_spam_with_extra_query() -> Ret {
+        let db = new_temp().await?;
+        let spam: Url = parse_url_like_telegram("amogus.com/badspam?ref=123&lang=en").unwrap();
+        db.add_url(&spam, IsSpam::Yes, false, false, None, None)
+            .await?;
+
+        // Same parameters in a different order, plus some extra ones.
+        let with_extra: Url =
+            parse_url_like_telegram("amogus.com/badspam?utm_source=tg&lang=en&ref=123").unwrap();
+        assert_eq!(
+            db.is_url_spam(&with_extra, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        assert_eq!(
+            db.is_spam(&with_extra, None, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+
+        // Missing a parameter the spam URL has.
+        let missing: Url = parse_url_like_telegram("amogus.com/badspam?ref=123").unwrap();
+        assert_eq!(db.is_url_spam(&missing, false).await?, None);
+
+        // Different value of a parameter.
+        let different: Url = parse_url_like_telegram("amogus.com/badspam?ref=456&lang=en").unwrap();
+        assert_eq!(db.is_url_spam(&different, false).await?, None);
+
+        // Longer path that happens to share the prefix.
+        let longer: Url = parse_url_like_telegram("amogus.com/badspamlol?ref=123&lang=en").unwrap();
+        assert_eq!(db.is_url_spam(&longer, false).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ip_and_port_urls() -> Ret {
+        let db = new_temp().await?;
+
+        let ip: Url = parse_url_like_telegram("91.203.1.2/claim?ref=123").unwrap();
+        let ip_domain = Domain::from_url(&ip).unwrap();
+        assert_eq!(ip_domain.as_str(), "91.203.1.2");
+
+        let ipv6: Url = parse_url_like_telegram("http://[2001:DB8::1]/claim").unwrap();
+        assert_eq!(Domain::from_url(&ipv6).unwrap().as_str(), "[2001:db8::1]");
+
+        let port: Url = parse_url_like_telegram("spam.site:8443/x").unwrap();
+        assert_eq!(port.as_str(), "http://spam.site:8443/x");
+        let port_domain = Domain::from_url(&port).unwrap();
+        assert_eq!(port_domain.as_str(), "spam.site");
+
+        db.add_url(&ip, IsSpam::Yes, false, true, None, None)
+            .await?;
+        db.add_url(&port, IsSpam::Yes, false, true, None, None)
+            .await?;
+
+        assert_eq!(db.is_url_spam(&ip, false).await?, Some((IsSpam::Yes, true)));
+        assert_eq!(
+            db.is_url_spam(&port, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        // Extra query parameters still match.
+        let ip_extra: Url = parse_url_like_telegram("91.203.1.2/claim?lang=en&ref=123").unwrap();
+        assert_eq!(
+            db.is_url_spam(&ip_extra, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+        let port_extra: Url = parse_url_like_telegram("spam.site:8443/x?ref=123").unwrap();
+        assert_eq!(
+            db.is_url_spam(&port_extra, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        // The port is part of the URL.
+        let other_port: Url = parse_url_like_telegram("spam.site:8080/x").unwrap();
+        assert_eq!(db.is_url_spam(&other_port, false).await?, None);
+        let no_port: Url = parse_url_like_telegram("spam.site/x").unwrap();
+        assert_eq!(db.is_url_spam(&no_port, false).await?, None);
+
+        // But not of the domain, which covers all ports.
+        db.add_domain(&ip_domain, &ip, IsSpam::Yes, false, true, None)
+            .await?;
+        let ip_other: Url = parse_url_like_telegram("http://91.203.1.2:8080/other").unwrap();
+        assert_eq!(
+            db.is_spam(&ip_other, None, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        // Reviews can find their way back to it.
+        let rowid: i64 = sqlx::query("SELECT rowid FROM domains WHERE domain=?")
+            .bind(ip_domain.as_str())
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_one(&db.pool)
+            .await?;
+        let (url, domain) = db
+            .get_url_from_table_and_rowid("domains", rowid)
+            .await?
+            .unwrap();
+        assert_eq!(url, ip);
+        assert_eq!(domain, Some(ip_domain));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn url_expiry() -> Ret {
+        let db = new_temp().await?;
+        let expired: Url = parse_url_like_telegram("amogus.com/expired").unwrap();
+        let expiring: Url = parse_url_like_telegram("amogus.com/expiring").unwrap();
+        let hour = chrono::Duration::hours(1);
+
+        db.add_url(
+            &expired,
+            IsSpam::Yes,
+            false,
+            true,
+            None,
+            Some(Utc::now() - hour),
+        )
+        .await?;
+        db.add_url(
+            &expiring,
+            IsSpam::Yes,
+            false,
+            true,
+            None,
+            Some(Utc::now() + hour),
+        )
+        .await?;
+
+        // Expired entries are as good as not there.
+        assert_eq!(db.is_url_spam(&expired, false).await?, None);
+        let with_query: Url = parse_url_like_telegram("amogus.com/expired?lol=1").unwrap();
+        assert_eq!(db.is_url_spam(&with_query, false).await?, None);
+        assert_eq!(
+            db.is_url_spam(&expiring, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        // Only the expired one is purged.
+        assert_eq!(db.purge_expired_urls().await?, 1);
+        assert_eq!(db.purge_expired_urls().await?, 0);
+
+        // Marking it again without an expiry date makes it permanent.
+        db.add_url(
+            &expiring,
+            IsSpam::Yes,
+            false,
+            true,
+            None,
+            Some(Utc::now() - hour),
+        )
+        .await?;
+        db.add_url(&expiring, IsSpam::Yes, false, true, None, None)
+            .await?;
+        assert_eq!(db.purge_expired_urls().await?, 0);
+        assert_eq!(
+            db.is_url_spam(&expiring, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_domain_spam() -> Ret {
+        let db = new_temp().await?;
+        let spamurl: Url = parse_url_like_telegram("amogus.com/badspam").unwrap();
+        let spamdomain: Domain = Domain::from_url(&spamurl).unwrap();
+
+        assert_eq!(db.is_url_spam(&spamurl, false).await?, None);
+        assert_eq!(db.is_domain_spam(&spamdomain, false).await?, None);
+        assert_eq!(db.is_spam(&spamurl, None, false).await?, None);
+        assert_eq!(db.is_spam(&spamurl, Some(&spamdomain), false).await?, None);
+
+        db.add_domain(&spamdomain, Some(&spamurl), IsSpam::Yes, false, false, None)
+            .await?;
+        // This checks if the URL specifically is a spam, so it will return None.
+        assert_eq!(db.is_url_spam(&spamurl, false).await?, None);
+        assert_eq!(
+            db.is_domain_spam(&spamdomain, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        assert_eq!(
+            db.is_spam(&spamurl, None, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        assert_eq!(
+            db.is_spam(&spamurl, Some(&spamdomain), false).await?,
+            Some((IsSpam::Yes, false))
+        );
+
+        let other: Url = parse_url_like_telegram("amogus.com/otherurl").unwrap();
+        let otherdomain: Domain = Domain::from_url(&other).unwrap();
+        assert_eq!(spamdomain, otherdomain);
+        // This checks if the URL specifically is a spam, so it will return None.
+        assert_eq!(db.is_url_spam(&other, false).await?, None);
+        assert_eq!(
+            db.is_spam(&other, None, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        assert_eq!(
+            db.is_spam(&other, Some(&otherdomain), false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_sus_workflow() -> Ret {
+        let db = new_temp().await?;
+        let link = parse_url_like_telegram("example.com/notspam").unwrap();
+        let domain = Domain::from_url(&link).unwrap();
+
+        // Let's say the link is posted in a chat.
+        // First, check `crate::spam_checker::check` is run, which defers to the db.
+        assert_eq!(db.is_spam(&link, None, false).await?, None);
+
+        // Then, the checker determines it as not spam and adds it to
+        // the database.
+        db.add_domain(&domain, &link, IsSpam::No, false, false, None)
+            .await
+            .expect("Database died!");
+
+        // Then, someone marks it as sus.
+        assert_eq!(db.mark_sus(&link, None, None).await?, MarkSusResult::Marked);
+
+        // Check if this is what it is in the database.
+        assert_eq!(
+            db.is_spam(&link, None, false).await?,
+            Some((IsSpam::Maybe, false))
+        );
+
+        // Someone gets it in review...
+        let (review_url, review_table, review_id, db_state, _, _) =
+            db.get_url_for_review().await?.unwrap();
+        assert_eq!(review_url, link);
+        assert_eq!(db_state, IsSpam::Maybe);
+
+        // They mark it as not spam...
+        let from_db = db
+            .get_url_from_table_and_rowid(review_table, review_id)
+            .await?
+            .unwrap();
+        assert_eq!(from_db.0, link);
+        let response = ReviewResponse::NotSpam(from_db.1, from_db.0);
+        db.read_review_response(&response).await?;
+
+        // Someone later posts the link again.
+        // Check if this is what it is in the database.
+        assert_eq!(
+            db.is_spam(&link, None, false).await?,
+            Some((IsSpam::No, true))
+        );
+
+        // Someone marks it as sus again...
+        assert_eq!(
+            db.mark_sus(&link, None, None).await?,
+            MarkSusResult::ManuallyReviewedNotSpam
+        );
+        //db.mark_url_sus(&link).await?;
+
+        //// It should still be not spam.
+        //assert_eq!(db.is_spam(&link, None, false).await?, Some(IsSpam::No));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pruning_stale_review_entries() -> Ret {
+        let db = new_temp().await?;
+        let stale = parse_url_like_telegram("spam.example/claim").unwrap();
+        let fine = parse_url_like_telegram("fine.example/claim").unwrap();
+        let unknown = parse_url_like_telegram("unknown.example/claim").unwrap();
+
+        for url in [&stale, &fine, &unknown] {
+            db.add_url(url, IsSpam::Maybe, false, false, None, None)
+                .await?;
+        }
+        assert_eq!(db.prune_stale_review_entries().await?, 0);
+        assert_eq!(db.get_review_count().await?, 3);
+
+        db.add_domain(
+            &Domain::from_url(&stale).unwrap(),
+            None,
+            IsSpam::Yes,
+            false,
+            false,
+            None,
+        )
+        .await?;
+        db.add_domain(
+            &Domain::from_url(&fine).unwrap(),
+            None,
+            IsSpam::No,
+            false,
+            true,
+            None,
+        )
+        .await?;
+
+        assert_eq!(db.prune_stale_review_entries().await?, 1);
+        assert_eq!(db.get_review_count().await?, 2);
+        assert_eq!(db.is_url_spam(&stale, true).await?, None);
+        // Still spam through its domain.
+        assert_eq!(
+            db.is_spam(&stale, None, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        assert_eq!(
+            db.is_url_spam(&fine, true).await?,
+            Some((IsSpam::Maybe, false))
+        );
+
+        assert_eq!(db.prune_stale_review_entries().await?, 0);
+
+        // Same URLs with fewer query parameters count if they're spam or were reviewed.
+        let tracked_spam = parse_url_like_telegram("spam.page/claim?utm=1").unwrap();
+        let tracked_fine = parse_url_like_telegram("fine.page/claim?ref=2&utm=1").unwrap();
+        let tracked_unknown = parse_url_like_telegram("unknown.page/claim?utm=1").unwrap();
+        let referral = parse_url_like_telegram("t.me/SomeSpamBot?start=ref123").unwrap();
+        for url in [&tracked_spam, &tracked_fine, &tracked_unknown, &referral] {
+            db.add_url(url, IsSpam::Maybe, false, false, None, None)
+                .await?;
+        }
+        assert_eq!(db.prune_stale_review_entries().await?, 0);
+
+        db.add_url(
+            &parse_url_like_telegram("spam.page/claim").unwrap(),
+            IsSpam::Yes,
+            false,
+            false,
+            None,
+            None,
+        )
+        .await?;
+        db.add_url(
+            &parse_url_like_telegram("fine.page/claim?ref=2").unwrap(),
+            IsSpam::No,
+            false,
+            true,
+            None,
+            None,
+        )
+        .await?;
+        // Automatically deciding it's not spam isn't enough.
+        db.add_url(
+            &parse_url_like_telegram("unknown.page/claim").unwrap(),
+            IsSpam::No,
+            false,
+            false,
+            None,
+            None,
+        )
+        .await?;
+        db.add_bot(&referral, IsSpam::Yes, true).await?;
+
+        assert_eq!(db.prune_stale_review_entries().await?, 3);
+        assert_eq!(db.is_url_spam_exact(&tracked_spam, true).await?, None);
+        assert_eq!(db.is_url_spam_exact(&tracked_fine, true).await?, None);
+        assert_eq!(db.is_url_spam_exact(&referral, true).await?, None);
+        assert_eq!(
+            db.is_url_spam_exact(&tracked_unknown, true).await?,
+            Some((IsSpam::Maybe, false))
+        );
+        // Still judged by what they matched.
+        assert_eq!(
+            db.is_url_spam(&tracked_spam, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        assert_eq!(
+            db.is_url_spam(&referral, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn review_contexts() -> Ret {
+        let db = new_temp().await?;
+        let link = parse_url_like_telegram("example.com/sus").unwrap();
+
+        db.mark_sus(&link, None, Some("  free   NFT\nhere:\texample.com/sus "))
+            .await?;
+        let (review_url, .., context) = db.get_url_for_review().await?.unwrap();
+        assert_eq!(review_url, link);
+        assert_eq!(context.as_deref(), Some("free NFT here: example.com/sus"));
+
+        // Marking it again without context keeps the old one.
+        db.add_url(&link, IsSpam::No, false, false, None, None)
+            .await?;
+        db.mark_sus(&link, None, None).await?;
+        let (.., context) = db.get_url_for_review().await?.unwrap();
+        assert_eq!(context.as_deref(), Some("free NFT here: example.com/sus"));
+        assert_eq!(
+            db.get_review_context(&link, None).await?.as_deref(),
+            Some("free NFT here: example.com/sus")
+        );
+        let other = parse_url_like_telegram("example.org/fine").unwrap();
+        assert_eq!(
+            db.get_review_context(&other, Domain::from_url(&other).as_ref())
+                .await?,
+            None
+        );
+
+        // Long texts are cut.
+        let long = review_context(&"🦀".repeat(MAX_REVIEW_CONTEXT_LENGTH * 2)).unwrap();
+        assert_eq!(long.chars().count(), MAX_REVIEW_CONTEXT_LENGTH + 1);
+        assert!(long.ends_with('…'));
+        assert_eq!(review_context(" \n "), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn adding_links_actually_adds() -> Ret {
+        let url = parse_url_like_telegram("example.com/notspam").unwrap();
+        let domain = Domain::from_url(&url).unwrap();
+
+        for spam_status in [IsSpam::No, IsSpam::Maybe, IsSpam::Yes] {
+            let db = new_temp().await?;
+            db.add_domain(&domain, &url, spam_status, false, false, None)
+                .await?;
+            assert_eq!(
+                db.is_spam(&url, &domain, true).await?,
+                Some((spam_status, false))
+            );
+            let db = new_temp().await?;
+            db.add_url(&url, spam_status, false, false, None, None)
+                .await?;
+            assert_eq!(
+                db.is_spam(&url, &domain, true).await?,
+                Some((spam_status, false))
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn review_response_conflicts_with_db() -> Ret {
+        // Check all possible cases of `ReviewResponse` in
+        // relation to the database's state.
+        let url = parse_url_like_telegram("example.com/notspam").unwrap();
+        let domain = Domain::from_url(&url).unwrap();
+
+        // Test cases.
+        let skip = ReviewResponse::Skip;
+        let notspam = ReviewResponse::NotSpam(Some(domain.clone()), url.clone());
+        let urlspam = ReviewResponse::UrlSpam(Some(domain.clone()), url.clone());
+        let domainspam = ReviewResponse::DomainSpam(domain.clone(), url.clone());
+
+        // Neither URL nor domain is in the database.
+        let db = new_temp().await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(notspam.conflicts_with_db(&db).await?);
+        assert!(urlspam.conflicts_with_db(&db).await?);
+        assert!(domainspam.conflicts_with_db(&db).await?);
+
+        //
+
+        // The URL is marked as not spam.
+        let db = new_temp().await?;
+        db.add_url(&url, IsSpam::No, false, true, None, None)
+            .await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(!notspam.conflicts_with_db(&db).await?);
+        assert!(urlspam.conflicts_with_db(&db).await?);
+        assert!(domainspam.conflicts_with_db(&db).await?);
+
+        // The URL is marked as maybe spam.
+        let db = new_temp().await?;
+        db.add_url(&url, IsSpam::Maybe, false, true, None, None)
+            .await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(notspam.conflicts_with_db(&db).await?);
+        assert!(urlspam.conflicts_with_db(&db).await?);
+        assert!(domainspam.conflicts_with_db(&db).await?);
+
+        // The URL is marked as yes spam.
+        let db = new_temp().await?;
+        db.add_url(&url, IsSpam::Yes, false, true, None, None)
+            .await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(notspam.conflicts_with_db(&db).await?);
+        assert!(!urlspam.conflicts_with_db(&db).await?);
+        assert!(domainspam.conflicts_with_db(&db).await?);
+
+        //
+
+        // The domain is marked as not spam.
+        let db = new_temp().await?;
+        db.add_domain(&domain, &url, IsSpam::No, false, true, None)
+            .await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(!notspam.conflicts_with_db(&db).await?);
+        assert!(urlspam.conflicts_with_db(&db).await?);
+        assert!(domainspam.conflicts_with_db(&db).await?);
+
+        // The domain is marked as maybe spam.
+        let db = new_temp().await?;
+        db.add_domain(&domain, &url, IsSpam::Maybe, false, true, None)
+            .await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(notspam.conflicts_with_db(&db).await?);
+        assert!(urlspam.conflicts_with_db(&db).await?);
+        assert!(domainspam.conflicts_with_db(&db).await?);
 
-                if was_visited {
-                    break None;
-                }
+        // The domain is marked as yes spam.
+        let db = new_temp().await?;
+        db.add_domain(&domain, &url, IsSpam::Yes, false, true, None)
+            .await?;
+        assert!(!skip.conflicts_with_db(&db).await?);
+        assert!(notspam.conflicts_with_db(&db).await?);
+        assert!(urlspam.conflicts_with_db(&db).await?);
+        assert!(!domainspam.conflicts_with_db(&db).await?);
 
-                visited_lock.insert(domain.clone());
-                drop(visited_lock);
+        Ok(())
+    }
 
-                break Some(DomainVisitDebounceGuard {
-                    database: self.clone(),
-                    domain,
-                });
-            }
-        }
+    #[tokio::test]
+    async fn channel_post_moderation() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
+        let other_chat = ChatId(-200);
+
+        assert!(!db.get_moderate_channel_posts(chat).await?);
+        assert!(!db.set_moderate_channel_posts(chat, true).await?);
+        assert!(db.set_moderate_channel_posts(chat, true).await?);
+        assert!(db.get_moderate_channel_posts(chat).await?);
+        assert!(!db.get_moderate_channel_posts(other_chat).await?);
+        assert!(db.set_moderate_channel_posts(chat, false).await?);
+        assert!(!db.get_moderate_channel_posts(chat).await?);
+
+        // Only one warning per chat.
+        assert!(db.should_warn_about_channel_spam(chat).await);
+        assert!(!db.should_warn_about_channel_spam(chat).await);
+        assert!(db.should_warn_about_channel_spam(other_chat).await);
+
+        // Only one report per message.
+        assert!(db.should_report_spam(chat, MessageId(1)).await);
+        assert!(!db.should_report_spam(chat, MessageId(1)).await);
+        assert!(db.should_report_spam(chat, MessageId(2)).await);
+        assert!(db.should_report_spam(other_chat, MessageId(1)).await);
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn report_only() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
 
-    type Ret = Result<(), Error>;
+        assert!(!db.get_report_only(chat).await?);
+        assert!(!db.set_report_only(chat, true).await?);
+        assert!(db.set_report_only(chat, true).await?);
+        assert!(db.get_report_only(chat).await?);
+        assert!(!db.get_report_only(ChatId(-200)).await?);
+        assert!(db.set_report_only(chat, false).await?);
+        assert!(!db.get_report_only(chat).await?);
 
-    pub fn new_temp() -> impl std::future::Future<Output = Result<Arc<Database>, Error>> + Send {
-        Database::new_by_path(None, "sqlite::memory:", false)
+        Ok(())
     }
 
     #[tokio::test]
-    async fn create_db() -> Ret {
-        new_temp().await?;
+    async fn strict_mode() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
+        assert!(!db.get_strict_mode(chat).await?);
+        assert!(!db.set_strict_mode(chat, true).await?);
+        assert!(db.set_strict_mode(chat, true).await?);
+        assert!(db.get_strict_mode(chat).await?);
+        assert!(!db.get_strict_mode(ChatId(-200)).await?);
+        assert!(db.set_strict_mode(chat, false).await?);
+        assert!(!db.get_strict_mode(chat).await?);
         Ok(())
     }
 
     #[tokio::test]
-    async fn is_url_spam() -> Ret {
+    async fn hide_delete_chats_listing() -> Ret {
         let db = new_temp().await?;
-        let spam: Url = parse_url_like_telegram("amogus.com/badspam").unwrap();
 
-        assert_eq!(db.is_url_spam(&spam, false).await?, None);
-        assert_eq!(db.is_spam(&spam, None, false).await?, None);
+        assert!(db.list_hide_delete_chats().await?.is_empty());
+        db.set_hide_deletes(ChatId(-200), true).await?;
+        db.set_hide_deletes(ChatId(-100), true).await?;
+        db.set_hide_deletes(ChatId(-300), true).await?;
+        db.set_hide_deletes(ChatId(-300), false).await?;
+        assert_eq!(
+            db.list_hide_delete_chats().await?,
+            [ChatId(-200), ChatId(-100)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn album_deletion() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
+        let other_chat = ChatId(-200);
+
+        assert!(!db.get_delete_albums(chat).await?);
+        assert!(!db.set_delete_albums(chat, true).await?);
+        assert!(db.get_delete_albums(chat).await?);
+        assert!(!db.get_delete_albums(other_chat).await?);
+
+        for id in 1..=3 {
+            db.record_album_message(chat, "album", MessageId(id))
+                .await?;
+        }
+        db.record_album_message(chat, "other album", MessageId(4))
+            .await?;
+        db.record_album_message(other_chat, "album", MessageId(5))
+            .await?;
 
-        db.add_url(&spam, IsSpam::Yes, false, false).await?;
+        let mut taken = db.take_album_messages(chat, "album").await?;
+        taken.sort_by_key(|x| x.0);
+        assert_eq!(taken, [MessageId(1), MessageId(2), MessageId(3)]);
+        // Forgotten after being taken.
+        assert!(db.take_album_messages(chat, "album").await?.is_empty());
         assert_eq!(
-            db.is_url_spam(&spam, false).await?,
-            Some((IsSpam::Yes, false))
+            db.take_album_messages(other_chat, "album").await?,
+            [MessageId(5)]
         );
 
-        let other: Url = parse_url_like_telegram("amogus.com/otherurl").unwrap();
-        assert_eq!(db.is_url_spam(&other, false).await?, None);
+        assert_eq!(db.get_last_deleted_album_id(chat).await?, None);
+        db.set_last_deleted_album_id(chat, "album").await?;
+        db.set_last_deleted_album_id(chat, "other album").await?;
+        assert_eq!(
+            db.get_last_deleted_album_id(chat).await?.as_deref(),
+            Some("other album")
+        );
+        assert_eq!(db.get_last_deleted_album_id(other_chat).await?, None);
+
+        // Turning it off forgets everything seen.
+        assert!(db.set_delete_albums(chat, false).await?);
+        assert!(db
+            .take_album_messages(chat, "other album")
+            .await?
+            .is_empty());
 
-        let spamdomain: Domain = Domain::from_url(&spam).unwrap();
-        // This checks if the domain specifically is a spam, so it will return None.
-        assert_eq!(db.is_domain_spam(&spamdomain, false).await?, None);
         Ok(())
     }
 
     #[tokio::test]
-    async fn is_domain_spam() -> Ret {
+    async fn spam_overrides() -> Ret {
         let db = new_temp().await?;
-        let spamurl: Url = parse_url_like_telegram("amogus.com/badspam").unwrap();
-        let spamdomain: Domain = Domain::from_url(&spamurl).unwrap();
+        let automatic: Url = parse_url_like_telegram("amogus.com/automatic").unwrap();
+        let manual: Url = parse_url_like_telegram("amogus.com/manual").unwrap();
+        db.add_url(&automatic, IsSpam::Yes, false, false, None, None)
+            .await?;
+        db.add_url(&manual, IsSpam::Yes, false, true, None, None)
+            .await?;
 
-        assert_eq!(db.is_url_spam(&spamurl, false).await?, None);
-        assert_eq!(db.is_domain_spam(&spamdomain, false).await?, None);
-        assert_eq!(db.is_spam(&spamurl, None, false).await?, None);
-        assert_eq!(db.is_spam(&spamurl, Some(&spamdomain), false).await?, None);
+        let automatic_entry = db.automatic_spam_entry(&automatic, None).await?.unwrap();
+        assert_eq!(automatic_entry, SpamEntry::Url(automatic.to_string()));
+        assert_eq!(db.automatic_spam_entry(&manual, None).await?, None);
 
-        db.add_domain(&spamdomain, Some(&spamurl), IsSpam::Yes, false, false)
-            .await?;
-        // This checks if the URL specifically is a spam, so it will return None.
-        assert_eq!(db.is_url_spam(&spamurl, false).await?, None);
+        // The same chat overriding again doesn't count twice.
+        assert!(!db.record_spam_override(&automatic_entry, ChatId(1)).await?);
+        assert!(!db.record_spam_override(&automatic_entry, ChatId(1)).await?);
+        assert!(!db.record_spam_override(&automatic_entry, ChatId(2)).await?);
         assert_eq!(
-            db.is_domain_spam(&spamdomain, false).await?,
+            db.is_url_spam(&automatic, false).await?,
             Some((IsSpam::Yes, false))
         );
+        assert!(db.record_spam_override(&automatic_entry, ChatId(3)).await?);
         assert_eq!(
-            db.is_spam(&spamurl, None, false).await?,
-            Some((IsSpam::Yes, false))
+            db.is_url_spam(&automatic, false).await?,
+            Some((IsSpam::Maybe, false))
         );
+        assert_eq!(db.automatic_spam_entry(&automatic, None).await?, None);
+
+        // Manual reviews are left alone.
+        let manual_entry = SpamEntry::Url(manual.to_string());
+        for chat in 1..=5 {
+            assert!(!db.record_spam_override(&manual_entry, ChatId(chat)).await?);
+        }
         assert_eq!(
-            db.is_spam(&spamurl, Some(&spamdomain), false).await?,
-            Some((IsSpam::Yes, false))
+            db.is_url_spam(&manual, false).await?,
+            Some((IsSpam::Yes, true))
         );
 
-        let other: Url = parse_url_like_telegram("amogus.com/otherurl").unwrap();
-        let otherdomain: Domain = Domain::from_url(&other).unwrap();
-        assert_eq!(spamdomain, otherdomain);
-        // This checks if the URL specifically is a spam, so it will return None.
-        assert_eq!(db.is_url_spam(&other, false).await?, None);
+        // Overrides of links with extra query parameters count for the entry they matched.
+        let base: Url = parse_url_like_telegram("amogus.com/claim?id=1").unwrap();
+        db.add_url(&base, IsSpam::Yes, false, false, None, None)
+            .await?;
+        for (chat, query) in [(1, "&a=1"), (2, "&b=2"), (3, "&c=3")] {
+            let url = parse_url_like_telegram(&format!("{}{}", base, query)).unwrap();
+            let entry = db.automatic_spam_entry(&url, None).await?.unwrap();
+            assert_eq!(entry, SpamEntry::Url(base.to_string()));
+            assert_eq!(
+                db.record_spam_override(&entry, ChatId(chat)).await?,
+                chat == 3
+            );
+        }
         assert_eq!(
-            db.is_spam(&other, None, false).await?,
-            Some((IsSpam::Yes, false))
+            db.is_url_spam(&base, false).await?,
+            Some((IsSpam::Maybe, false))
         );
+
+        // Same for different links on an automatically designated domain.
+        let domain = Domain::from_str("sus.example").unwrap();
+        db.add_domain(&domain, None, IsSpam::Yes, false, false, None)
+            .await?;
+        for chat in 1..=3 {
+            let url = parse_url_like_telegram(&format!("sus.example/{}", chat)).unwrap();
+            let entry = db.automatic_spam_entry(&url, None).await?.unwrap();
+            assert_eq!(entry, SpamEntry::Domain(domain.clone()));
+            assert_eq!(
+                db.record_spam_override(&entry, ChatId(chat)).await?,
+                chat == 3
+            );
+        }
         assert_eq!(
-            db.is_spam(&other, Some(&otherdomain), false).await?,
-            Some((IsSpam::Yes, false))
+            db.is_domain_spam(&domain, false).await?,
+            Some((IsSpam::Maybe, false))
         );
+
+        // Links only spam by a pattern were decided by reviewers.
+        db.add_url_pattern(&HostPattern::parse("*.pattern.example").unwrap(), UserId(1))
+            .await?;
+        let url = parse_url_like_telegram("sub.pattern.example/x").unwrap();
+        assert_eq!(db.automatic_spam_entry(&url, None).await?, None);
+
         Ok(())
     }
 
     #[tokio::test]
-    async fn mark_sus_workflow() -> Ret {
+    async fn grace_period_and_pending_deletions() -> Ret {
         let db = new_temp().await?;
-        let link = parse_url_like_telegram("example.com/notspam").unwrap();
-        let domain = Domain::from_url(&link).unwrap();
+        let chat = ChatId(-100);
+        let message = MessageId(5);
+
+        assert_eq!(db.get_grace_period(chat).await?, 0);
+        db.set_grace_period(chat, 30).await?;
+        assert_eq!(db.get_grace_period(chat).await?, 30);
+        db.set_grace_period(chat, 0).await?;
+        assert_eq!(db.get_grace_period(chat).await?, 0);
+
+        let spawn = || tokio::spawn(std::future::pending::<()>()).abort_handle();
+
+        // Nothing to cancel yet.
+        assert!(db.cancel_pending_deletion(chat, message).await.is_none());
+
+        // Scheduling works once, and then the message is taken for deletion.
+        assert!(db.add_pending_deletion(chat, message, spawn).await);
+        assert!(!db.add_pending_deletion(chat, message, spawn).await);
+        assert!(db.take_pending_deletion(chat, message).await);
+        assert!(!db.take_pending_deletion(chat, message).await);
+        assert!(db.pending_deletions.lock().await.is_empty());
+
+        // Once cancelled, it can't be taken nor scheduled again.
+        assert!(db.add_pending_deletion(chat, message, spawn).await);
+        db.set_pending_deletion_notice(chat, message, MessageId(6))
+            .await;
+        assert_eq!(
+            db.cancel_pending_deletion(chat, message).await,
+            Some(Some(MessageId(6)))
+        );
+        assert!(db.cancel_pending_deletion(chat, message).await.is_none());
+        assert!(!db.take_pending_deletion(chat, message).await);
+        assert!(!db.add_pending_deletion(chat, message, spawn).await);
+        assert!(db.pending_deletions.lock().await.is_empty());
+
+        // Cancelling before the notice is sent works too.
+        let other = MessageId(7);
+        assert!(db.add_pending_deletion(chat, other, spawn).await);
+        assert_eq!(db.cancel_pending_deletion(chat, other).await, Some(None));
+
+        // Only so many kept messages are remembered.
+        for id in 0..KEPT_MESSAGES_MEMORY_SIZE as i32 + 10 {
+            let id = MessageId(1000 + id);
+            assert!(db.add_pending_deletion(chat, id, spawn).await);
+            assert!(db.cancel_pending_deletion(chat, id).await.is_some());
+        }
+        assert_eq!(
+            db.kept_messages.lock().await.len(),
+            KEPT_MESSAGES_MEMORY_SIZE
+        );
 
-        // Let's say the link is posted in a chat.
-        // First, check `crate::spam_checker::check` is run, which defers to the db.
-        assert_eq!(db.is_spam(&link, None, false).await?, None);
+        Ok(())
+    }
 
-        // Then, the checker determines it as not spam and adds it to
-        // the database.
-        db.add_domain(&domain, &link, IsSpam::No, false, false)
-            .await
-            .expect("Database died!");
+    #[tokio::test]
+    async fn chat_id_migration() -> Ret {
+        let db = new_temp().await?;
+        let old = ChatId(-123);
+        let new = ChatId(-100123);
+        let url = Url::parse("https://spam.example/lol").unwrap();
+
+        db.set_hide_deletes(old, true).await?;
+        db.set_moderate_channel_posts(old, true).await?;
+        db.set_delete_albums(old, true).await?;
+        db.set_grace_period(old, 60).await?;
+        db.record_spam_override(&SpamEntry::Url(url.to_string()), old)
+            .await?;
+        db.record_album_message(old, "album", MessageId(1)).await?;
+        db.set_last_deleted_album_id(old, "album").await?;
+        db.add_url(&url, IsSpam::Maybe, false, false, None, None)
+            .await?;
+        let (_, table, rowid, ..) = db.get_url_for_review().await?.unwrap();
+        db.remember_review_keyboard(old, MessageId(2), table, rowid)
+            .await?;
 
-        // Then, someone marks it as sus.
-        assert_eq!(db.mark_sus(&link, None).await?, MarkSusResult::Marked);
+        // The new chat already has its own grace period, which should be kept.
+        db.set_grace_period(new, 30).await?;
 
-        // Check if this is what it is in the database.
+        db.migrate_chat_id(old, new).await?;
+
+        assert!(db.get_hide_deletes(new).await?);
+        assert!(db.get_moderate_channel_posts(new).await?);
+        assert!(db.get_delete_albums(new).await?);
+        assert_eq!(db.get_grace_period(new).await?, 30);
+        assert_eq!(db.take_album_messages(new, "album").await?, [MessageId(1)]);
         assert_eq!(
-            db.is_spam(&link, None, false).await?,
-            Some((IsSpam::Maybe, false))
+            db.get_last_deleted_album_id(new).await?.as_deref(),
+            Some("album")
         );
+        let overrides: i64 = sqlx::query("SELECT COUNT(*) FROM spam_overrides WHERE chatid=?;")
+            .bind(new.0)
+            .fetch_one(&db.pool)
+            .await?
+            .get(0);
+        assert_eq!(overrides, 1);
+        let keyboards = db.get_review_keyboards().await?;
+        assert_eq!(keyboards.len(), 1);
+        assert_eq!(keyboards[0].chat, new);
+
+        // Nothing is left of the old chat.
+        assert!(!db.get_hide_deletes(old).await?);
+        assert!(!db.get_moderate_channel_posts(old).await?);
+        assert!(!db.get_delete_albums(old).await?);
+        assert_eq!(db.get_grace_period(old).await?, 0);
+        assert_eq!(db.get_last_deleted_album_id(old).await?, None);
+        for table in CHAT_TABLES {
+            let count: i64 =
+                sqlx::query(&format!("SELECT COUNT(*) FROM {} WHERE chatid=?;", table))
+                    .bind(old.0)
+                    .fetch_one(&db.pool)
+                    .await?
+                    .get(0);
+            assert_eq!(count, 0, "table: {}", table);
+        }
 
-        // Someone gets it in review...
-        let (review_url, review_table, review_id, db_state) =
-            db.get_url_for_review().await?.unwrap();
-        assert_eq!(review_url, link);
-        assert_eq!(db_state, IsSpam::Maybe);
+        Ok(())
+    }
 
-        // They mark it as not spam...
-        let from_db = db
-            .get_url_from_table_and_rowid(review_table, review_id)
-            .await?
-            .unwrap();
-        assert_eq!(from_db.0, link);
-        let response = ReviewResponse::NotSpam(from_db.1, from_db.0);
-        db.read_review_response(&response).await?;
+    #[tokio::test]
+    async fn first_link_guard() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
+        let user = UserId(42);
+        let other_user = UserId(43);
 
-        // Someone later posts the link again.
-        // Check if this is what it is in the database.
+        assert!(!db.set_first_link_guard(chat, true).await?);
+        assert!(db.get_first_link_guard(chat).await?);
+
+        // Those that were here before aren't guarded.
+        assert!(db.check_member_verified(chat, other_user).await?);
+        db.add_new_member(chat, other_user).await?;
+        assert!(db.check_member_verified(chat, other_user).await?);
+
+        db.add_new_member(chat, user).await?;
+        assert!(!db.check_member_verified(chat, user).await?);
+        assert!(!db.check_member_verified(chat, user).await?);
+
+        let id = db
+            .add_quarantined_message(chat, user, "hi example[.]com")
+            .await?;
+        // Only the sender can post it again, and only once.
+        assert_eq!(db.take_quarantined_message(id, other_user).await?, None);
         assert_eq!(
-            db.is_spam(&link, None, false).await?,
-            Some((IsSpam::No, true))
+            db.take_quarantined_message(id, user).await?.as_deref(),
+            Some("hi example[.]com")
         );
+        assert_eq!(db.take_quarantined_message(id, user).await?, None);
+        assert!(!db.forget_quarantined_message(id).await?);
 
-        // Someone marks it as sus again...
+        db.set_member_verified(chat, user).await?;
+        assert!(db.check_member_verified(chat, user).await?);
+        db.add_new_member(ChatId(-101), user).await?;
+        assert!(!db.check_member_verified(ChatId(-101), user).await?);
+
+        // Unclaimed messages are forgotten.
+        let id = db.add_quarantined_message(chat, other_user, "lol").await?;
+        assert!(db.forget_quarantined_message(id).await?);
+        assert_eq!(db.take_quarantined_message(id, other_user).await?, None);
+
+        // Turning it off forgets everyone.
+        assert!(db.set_first_link_guard(chat, false).await?);
+        db.add_new_member(chat, user).await?;
+        assert!(!db.check_member_verified(chat, user).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_progress() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
+
+        // Not tracked until asked for.
+        assert!(db.record_chat_progress(chat, MessageId(5)).await?);
+        assert!(db.record_chat_progress(chat, MessageId(5)).await?);
+
+        assert!(!db.set_backfill(chat, true).await?);
+        assert!(db.get_backfill(chat).await?);
+
+        assert!(db.record_chat_progress(chat, MessageId(5)).await?);
+        assert!(db.record_chat_progress(chat, MessageId(7)).await?);
+        // Delivered again, or older than what was handled already.
+        assert!(!db.record_chat_progress(chat, MessageId(7)).await?);
+        assert!(!db.record_chat_progress(chat, MessageId(6)).await?);
+
+        // Turning it on again keeps the progress.
+        assert!(db.set_backfill(chat, true).await?);
+        assert!(!db.record_chat_progress(chat, MessageId(7)).await?);
+
+        assert!(db.set_backfill(chat, false).await?);
+        assert!(!db.get_backfill(chat).await?);
+        assert!(db.record_chat_progress(chat, MessageId(7)).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn match_reasons() -> Ret {
+        let db = new_temp().await?;
+        let url: Url = parse_url_like_telegram("https://amogus.com/sus").unwrap();
+        let domain = Domain::from_url(&url).unwrap();
+        let other: Url = parse_url_like_telegram("https://amogus.com/other").unwrap();
+
+        assert_eq!(db.get_match_reason(&url, &domain).await?, None);
+
+        // Domain's reason is used if the URL doesn't have one...
+        db.add_domain(
+            &domain,
+            &url,
+            IsSpam::Yes,
+            false,
+            false,
+            Some(MatchReason::NftHtml),
+        )
+        .await?;
         assert_eq!(
-            db.mark_sus(&link, None).await?,
-            MarkSusResult::ManuallyReviewedNotSpam
+            db.get_match_reason(&other, None).await?,
+            Some(MatchReason::NftHtml)
         );
-        //db.mark_url_sus(&link).await?;
 
-        //// It should still be not spam.
-        //assert_eq!(db.is_spam(&link, None, false).await?, Some(IsSpam::No));
+        // ...and the URL's own one takes priority.
+        db.add_url(
+            &url,
+            IsSpam::Yes,
+            false,
+            false,
+            Some(MatchReason::TelegramUrlHeuristic),
+            None,
+        )
+        .await?;
+        assert_eq!(
+            db.get_match_reason(&url, &domain).await?,
+            Some(MatchReason::TelegramUrlHeuristic)
+        );
+
+        // Manual reviews keep the reason, to tell what the checker got wrong.
+        db.add_url(&url, IsSpam::No, false, true, None, None)
+            .await?;
+        assert_eq!(
+            db.get_match_reason(&url, &domain).await?,
+            Some(MatchReason::TelegramUrlHeuristic)
+        );
+
+        // Heuristics sending a link to review record why they did.
+        let invite = parse_url_like_telegram("https://t.me/+AbCdEfGh123").unwrap();
+        assert!(
+            db.mark_url_sus(&invite, Some(MatchReason::InviteLinkBait), None)
+                .await?
+        );
+        assert_eq!(
+            db.is_spam(&invite, None, false).await?,
+            Some((IsSpam::Maybe, false))
+        );
+        assert_eq!(
+            db.get_match_reason(&invite, None).await?,
+            Some(MatchReason::InviteLinkBait)
+        );
+        // But not for manually reviewed ones.
+        assert!(
+            !db.mark_url_sus(&url, Some(MatchReason::InviteLinkBait), None)
+                .await?
+        );
+
+        // Values written by some future version don't break reading.
+        sqlx::query("UPDATE urls SET match_reason=200 WHERE url=?;")
+            .bind(url.as_str())
+            .execute(&db.pool)
+            .await?;
+        assert_eq!(
+            db.get_match_reason(&url, &domain).await?,
+            Some(MatchReason::Unknown)
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn adding_links_actually_adds() -> Ret {
-        let url = parse_url_like_telegram("example.com/notspam").unwrap();
+    async fn synced_reviews_dont_override_local_ones() -> Ret {
+        let db = new_temp().await?;
+        let url: Url = parse_url_like_telegram("https://amogus.com/sus").unwrap();
         let domain = Domain::from_url(&url).unwrap();
+        let other: Url = parse_url_like_telegram("https://amogus.com/other").unwrap();
 
-        for spam_status in [IsSpam::No, IsSpam::Maybe, IsSpam::Yes] {
-            let db = new_temp().await?;
-            db.add_domain(&domain, &url, spam_status, false, false)
-                .await?;
-            assert_eq!(
-                db.is_spam(&url, &domain, true).await?,
-                Some((spam_status, false))
-            );
-            let db = new_temp().await?;
-            db.add_url(&url, spam_status, false, false).await?;
-            assert_eq!(
-                db.is_spam(&url, &domain, true).await?,
-                Some((spam_status, false))
-            );
+        // Synced reviews get in, but not as manually reviewed.
+        db.read_synced_review_response(&ReviewResponse::UrlSpam(Some(domain.clone()), url.clone()))
+            .await?;
+        assert_eq!(
+            db.is_url_spam(&url, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+
+        // A local review wins over that...
+        db.read_review_response(&ReviewResponse::NotSpam(Some(domain.clone()), url.clone()))
+            .await?;
+        assert_eq!(db.is_url_spam(&url, false).await?, Some((IsSpam::No, true)));
+
+        // ...and isn't overridden by synced ones after.
+        db.read_synced_review_response(&ReviewResponse::UrlSpam(Some(domain.clone()), url.clone()))
+            .await?;
+        assert_eq!(db.is_url_spam(&url, false).await?, Some((IsSpam::No, true)));
+
+        // Same for domains.
+        db.read_review_response(&ReviewResponse::DomainSpam(domain.clone(), other.clone()))
+            .await?;
+        db.read_synced_review_response(&ReviewResponse::NotSpam(Some(domain.clone()), other))
+            .await?;
+        assert_eq!(
+            db.is_domain_spam(&domain, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn likely_designation_precedence() -> Ret {
+        let db = new_temp().await?;
+
+        let all = [
+            IsSpam::No,
+            IsSpam::Yes,
+            IsSpam::Maybe,
+            IsSpam::Likely,
+            IsSpam::Unlikely,
+        ];
+
+        let mut i = 0;
+        for old in all {
+            for old_manual in [false, true] {
+                for new in all {
+                    for new_manual in [false, true] {
+                        i += 1;
+                        let url: Url =
+                            parse_url_like_telegram(&format!("https://amogus{}.com/sus", i))
+                                .unwrap();
+                        let domain = Domain::from_url(&url).unwrap();
+
+                        db.add_url(&url, old, false, old_manual, None, None).await?;
+                        db.add_domain(&domain, None, old, false, old_manual, None)
+                            .await?;
+                        db.add_url(&url, new, false, new_manual, None, None).await?;
+                        db.add_domain(&domain, None, new, false, new_manual, None)
+                            .await?;
+
+                        // Only a manually reviewed firm designation is kept over a likely one.
+                        let kept = old_manual && !old.is_likely() && old != IsSpam::Maybe;
+                        let expected = if new.is_likely() && kept {
+                            (old, old_manual)
+                        } else {
+                            (new, new_manual)
+                        };
+
+                        let case = (old, old_manual, new, new_manual);
+                        assert_eq!(
+                            db.is_url_spam(&url, true).await?,
+                            Some(expected),
+                            "{:?}",
+                            case
+                        );
+                        assert_eq!(
+                            db.is_domain_spam(&domain, true).await?,
+                            Some(expected),
+                            "{:?}",
+                            case
+                        );
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn review_response_conflicts_with_db() -> Ret {
-        // Check all possible cases of `ReviewResponse` in
-        // relation to the database's state.
-        let url = parse_url_like_telegram("example.com/notspam").unwrap();
+    async fn likely_spam_reviews() -> Ret {
+        let db = new_temp().await?;
+        let url: Url = parse_url_like_telegram("https://amogus.com/sus").unwrap();
         let domain = Domain::from_url(&url).unwrap();
+        let likely = ReviewResponse::LikelySpam(Some(domain.clone()), url.clone());
 
-        // Test cases.
-        let skip = ReviewResponse::Skip;
-        let notspam = ReviewResponse::NotSpam(Some(domain.clone()), url.clone());
-        let urlspam = ReviewResponse::UrlSpam(Some(domain.clone()), url.clone());
-        let domainspam = ReviewResponse::DomainSpam(domain.clone(), url.clone());
+        // Unknown and automatically determined URLs become likely spam.
+        assert!(likely.conflicts_with_db(&db).await?);
+        db.mark_url_sus(&url, None, None).await?;
+        assert!(likely.conflicts_with_db(&db).await?);
+        db.read_review_response(&likely).await?;
+        assert_eq!(
+            db.is_url_spam(&url, false).await?,
+            Some((IsSpam::Likely, true))
+        );
+        assert!(!likely.conflicts_with_db(&db).await?);
 
-        // Neither URL nor domain is in the database.
-        let db = new_temp().await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(notspam.conflicts_with_db(&db).await?);
-        assert!(urlspam.conflicts_with_db(&db).await?);
-        assert!(domainspam.conflicts_with_db(&db).await?);
+        // Those are sus, but not already marked as spam.
+        assert_eq!(
+            db.mark_sus(&url, None, None).await?,
+            MarkSusResult::AlreadyMarkedSus
+        );
 
-        //
+        // A firm review wins over that...
+        db.read_review_response(&ReviewResponse::UrlSpam(Some(domain.clone()), url.clone()))
+            .await?;
+        assert_eq!(
+            db.is_url_spam(&url, false).await?,
+            Some((IsSpam::Yes, true))
+        );
 
-        // The URL is marked as not spam.
-        let db = new_temp().await?;
-        db.add_url(&url, IsSpam::No, false, true).await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(!notspam.conflicts_with_db(&db).await?);
-        assert!(urlspam.conflicts_with_db(&db).await?);
-        assert!(domainspam.conflicts_with_db(&db).await?);
+        // ...and isn't overridden by a likely one after.
+        assert!(!likely.conflicts_with_db(&db).await?);
+        db.read_review_response(&likely).await?;
+        assert_eq!(
+            db.is_url_spam(&url, false).await?,
+            Some((IsSpam::Yes, true))
+        );
 
-        // The URL is marked as maybe spam.
-        let db = new_temp().await?;
-        db.add_url(&url, IsSpam::Maybe, false, true).await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(notspam.conflicts_with_db(&db).await?);
-        assert!(urlspam.conflicts_with_db(&db).await?);
-        assert!(domainspam.conflicts_with_db(&db).await?);
+        // A likely spam URL is less condemning than a spam domain.
+        let other: Url = parse_url_like_telegram("https://amogus.com/other").unwrap();
+        db.read_review_response(&ReviewResponse::LikelySpam(
+            Some(domain.clone()),
+            other.clone(),
+        ))
+        .await?;
+        assert_eq!(
+            db.is_spam(&other, None, false).await?,
+            Some((IsSpam::Likely, true))
+        );
+        db.add_domain(&domain, None, IsSpam::Yes, false, true, None)
+            .await?;
+        assert_eq!(
+            db.is_spam(&other, None, false).await?,
+            Some((IsSpam::Yes, true))
+        );
 
-        // The URL is marked as yes spam.
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bot_wide_spam() -> Ret {
         let db = new_temp().await?;
-        db.add_url(&url, IsSpam::Yes, false, true).await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(notspam.conflicts_with_db(&db).await?);
-        assert!(!urlspam.conflicts_with_db(&db).await?);
-        assert!(domainspam.conflicts_with_db(&db).await?);
+        let referral: Url = parse_url_like_telegram("t.me/SomeBot?start=ref123").unwrap();
+        let other_referral: Url = parse_url_like_telegram("t.me/SomeBot?start=ref456").unwrap();
+        let app: Url = parse_url_like_telegram("t.me/somebot/app").unwrap();
+        let bare: Url = parse_url_like_telegram("t.me/SomeBot").unwrap();
+        let domain = Domain::from_url(&referral);
+
+        // Marking a specific start parameter only covers that one.
+        db.read_review_response(&ReviewResponse::UrlSpam(domain.clone(), referral.clone()))
+            .await?;
+        assert_eq!(
+            db.is_url_spam(&referral, false).await?,
+            Some((IsSpam::Yes, true))
+        );
+        assert_eq!(db.is_url_spam(&other_referral, false).await?, None);
+        assert_eq!(db.is_url_spam(&app, false).await?, None);
 
-        //
+        // A link to the bot that's not spam on its own...
+        db.add_url(&other_referral, IsSpam::No, false, true, None, None)
+            .await?;
+        assert_eq!(
+            db.is_url_spam(&other_referral, false).await?,
+            Some((IsSpam::No, true))
+        );
 
-        // The domain is marked as not spam.
-        let db = new_temp().await?;
-        db.add_domain(&domain, &url, IsSpam::No, false, true)
+        // ...is overridden by marking the whole bot.
+        db.read_review_response(&ReviewResponse::BotSpam(domain.clone(), referral.clone()))
             .await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(!notspam.conflicts_with_db(&db).await?);
-        assert!(urlspam.conflicts_with_db(&db).await?);
-        assert!(domainspam.conflicts_with_db(&db).await?);
+        for url in [&referral, &other_referral, &app, &bare] {
+            assert_eq!(
+                db.is_url_spam(url, false).await?,
+                Some((IsSpam::Yes, true)),
+                "{url}"
+            );
+            assert_eq!(db.is_bot_spam(url, false).await?, Some((IsSpam::Yes, true)));
+        }
 
-        // The domain is marked as maybe spam.
-        let db = new_temp().await?;
-        db.add_domain(&domain, &url, IsSpam::Maybe, false, true)
+        // Other bots and non-bot links aren't affected.
+        let other_bot: Url = parse_url_like_telegram("t.me/OtherBot?start=ref123").unwrap();
+        let channel: Url = parse_url_like_telegram("t.me/somechannel").unwrap();
+        assert_eq!(db.is_url_spam(&other_bot, false).await?, None);
+        assert_eq!(db.is_url_spam(&channel, false).await?, None);
+        assert_eq!(db.is_bot_spam(&channel, false).await?, None);
+
+        // Synced responses don't override the manually reviewed entry.
+        db.read_synced_review_response(&ReviewResponse::BotSpam(domain.clone(), app.clone()))
             .await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(notspam.conflicts_with_db(&db).await?);
-        assert!(urlspam.conflicts_with_db(&db).await?);
-        assert!(domainspam.conflicts_with_db(&db).await?);
+        assert_eq!(
+            db.is_bot_spam(&app, false).await?,
+            Some((IsSpam::Yes, true))
+        );
 
-        // The domain is marked as yes spam.
-        let db = new_temp().await?;
-        db.add_domain(&domain, &url, IsSpam::Yes, false, true)
+        // Reviewing just the link the whole bot is stored as afterwards
+        // is no longer about the whole bot.
+        let stored: Url = parse_url_like_telegram("https://t.me/somebot").unwrap();
+        db.read_review_response(&ReviewResponse::NotSpam(domain, stored.clone()))
             .await?;
-        assert!(!skip.conflicts_with_db(&db).await?);
-        assert!(notspam.conflicts_with_db(&db).await?);
-        assert!(urlspam.conflicts_with_db(&db).await?);
-        assert!(!domainspam.conflicts_with_db(&db).await?);
+        assert_eq!(
+            db.is_url_spam(&stored, false).await?,
+            Some((IsSpam::No, true))
+        );
+        for url in [&referral, &other_referral, &app] {
+            assert_eq!(db.is_bot_spam(url, false).await?, None, "{url}");
+        }
 
         Ok(())
     }
@@ -1131,10 +4703,13 @@ mod tests {
             // Let's say someone marks Telegram itself as spam on accident.
 
             // Someone gets the normal link in review...
-            assert_eq!(db.mark_sus(&normal, None).await?, MarkSusResult::Marked);
+            assert_eq!(
+                db.mark_sus(&normal, None, None).await?,
+                MarkSusResult::Marked
+            );
 
             // Someone gets it in review...
-            let (_, review_table, review_id, _) = db.get_url_for_review().await?.unwrap();
+            let (_, review_table, review_id, _, _, _) = db.get_url_for_review().await?.unwrap();
 
             // They mark it as not spam...
             let from_db = db
@@ -1146,10 +4721,10 @@ mod tests {
             db.read_review_response(&response).await?;
 
             // Someone gets the spam link in review...
-            assert_eq!(db.mark_sus(&spam, None).await?, MarkSusResult::Marked);
+            assert_eq!(db.mark_sus(&spam, None, None).await?, MarkSusResult::Marked);
 
             // Someone gets it in review...
-            let (_, review_table, review_id, _) = db.get_url_for_review().await?.unwrap();
+            let (_, review_table, review_id, _, _, _) = db.get_url_for_review().await?.unwrap();
 
             // They mark the DOMAIN as spam on accident...
             let from_db = db
@@ -1207,4 +4782,415 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn url_with_many_params() -> Ret {
+        // Query parameters are compared in memory rather than bound one by one,
+        // so there's no SQLite variable limit to run into with absurd query strings.
+        let db = new_temp().await?;
+        let query: Vec<String> = (0..2000).map(|x| format!("p{}={}", x, x)).collect();
+        let spam: Url =
+            parse_url_like_telegram(&format!("amogus.com/badspam?{}", query.join("&"))).unwrap();
+
+        db.add_url(&spam, IsSpam::Yes, false, false, None, None)
+            .await?;
+        assert_eq!(
+            db.is_url_spam(&spam, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+
+        // Some more on top still match inexactly.
+        let more: Url = parse_url_like_telegram(&format!("{}&sus=1", spam)).unwrap();
+        assert_eq!(
+            db.is_url_spam(&more, false).await?,
+            Some((IsSpam::Yes, false))
+        );
+        db.mark_url_seen(&more).await?;
+
+        Ok(())
+    }
+
+    /// Get the first and last seen timestamps of this exact URL.
+    async fn seen(db: &Database, url: &str) -> Result<(Option<String>, Option<String>), Error> {
+        sqlx::query_as("SELECT first_seen, last_seen FROM urls WHERE url=?;")
+            .bind(url)
+            .fetch_one(&db.pool)
+            .await
+    }
+
+    #[tokio::test]
+    async fn mark_url_seen() -> Ret {
+        let db = new_temp().await?;
+        let spam: Url = parse_url_like_telegram("amogus.com/badspam").unwrap();
+
+        db.add_url(&spam, IsSpam::Yes, false, false, None, None)
+            .await?;
+        let (first, last) = seen(&db, spam.as_str()).await?;
+        assert!(first.is_some());
+        assert!(last.is_some());
+
+        // Pretend it's an old entry from before timestamps were tracked.
+        sqlx::query("UPDATE urls SET first_seen=NULL, last_seen='2000-01-01T00:00:00Z';")
+            .execute(&db.pool)
+            .await?;
+        db.mark_url_seen(&spam).await?;
+        let (first, last) = seen(&db, spam.as_str()).await?;
+        assert!(first.is_some());
+        assert_ne!(last.as_deref(), Some("2000-01-01T00:00:00Z"));
+
+        // First seen stays put once it's known.
+        db.mark_url_seen(&spam).await?;
+        assert_eq!(seen(&db, spam.as_str()).await?.0, first);
+
+        // Links matched inexactly touch the entry they matched.
+        sqlx::query("UPDATE urls SET last_seen='2000-01-01T00:00:00Z';")
+            .execute(&db.pool)
+            .await?;
+        let extra: Url = parse_url_like_telegram("amogus.com/badspam?ref=sus").unwrap();
+        db.mark_url_seen(&extra).await?;
+        let (_, last) = seen(&db, spam.as_str()).await?;
+        assert_ne!(last.as_deref(), Some("2000-01-01T00:00:00Z"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prune_unseen_urls() -> Ret {
+        let db = new_temp().await?;
+        let url = |x: &str| parse_url_like_telegram(x).unwrap();
+
+        db.add_url(&url("old.com/spam"), IsSpam::Yes, false, false, None, None)
+            .await?;
+        db.add_url(&url("old.com/fine"), IsSpam::No, false, false, None, None)
+            .await?;
+        db.mark_url_sus(&url("old.com/sus"), None, None).await?;
+        db.add_url(
+            &url("old.com/reviewed"),
+            IsSpam::Yes,
+            false,
+            true,
+            None,
+            None,
+        )
+        .await?;
+        db.add_url(&url("old.com/listed"), IsSpam::Yes, true, false, None, None)
+            .await?;
+        db.add_url(
+            &url("old.com/unknown"),
+            IsSpam::Yes,
+            false,
+            false,
+            None,
+            None,
+        )
+        .await?;
+        db.add_url(&url("new.com/spam"), IsSpam::Yes, false, false, None, None)
+            .await?;
+
+        sqlx::query(
+            "UPDATE urls SET last_seen='2000-01-01T00:00:00Z' WHERE url LIKE 'http://old.com/%';",
+        )
+        .execute(&db.pool)
+        .await?;
+        sqlx::query("UPDATE urls SET last_seen=NULL WHERE url='http://old.com/unknown';")
+            .execute(&db.pool)
+            .await?;
+
+        let cutoff = Utc::now() - chrono::TimeDelta::days(365);
+        let expected = vec![(IsSpam::Yes, 1), (IsSpam::No, 1), (IsSpam::Maybe, 1)];
+
+        // Dry runs don't remove anything.
+        assert_eq!(db.prune_unseen_urls(cutoff, true).await?, expected);
+        assert_eq!(db.prune_unseen_urls(cutoff, true).await?, expected);
+
+        assert_eq!(db.prune_unseen_urls(cutoff, false).await?, expected);
+        assert_eq!(db.prune_unseen_urls(cutoff, false).await?, vec![]);
+
+        assert_eq!(db.is_url_spam(&url("old.com/spam"), false).await?, None);
+        for kept in [
+            "old.com/reviewed",
+            "old.com/listed",
+            "old.com/unknown",
+            "new.com/spam",
+        ] {
+            assert!(
+                db.is_url_spam(&url(kept), false).await?.is_some(),
+                "{}",
+                kept
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reviewer_actions() -> Ret {
+        let db = new_temp().await?;
+        let url = |x| parse_url_like_telegram(x).unwrap();
+        let spam = |x| ReviewResponse::UrlSpam(None, url(x));
+
+        db.record_reviewer_action(UserId(1), Some("Amogus"), &spam("a.com/1"))
+            .await?;
+        db.record_reviewer_action(UserId(1), Some("Amogus"), &spam("a.com/2"))
+            .await?;
+        db.record_reviewer_action(
+            UserId(1),
+            Some("Amogus"),
+            &ReviewResponse::NotSpam(None, url("a.com/3")),
+        )
+        .await?;
+        db.record_reviewer_action(UserId(2), None, &spam("b.com/1"))
+            .await?;
+        // Skips aren't reviews.
+        db.record_reviewer_action(UserId(2), None, &ReviewResponse::Skip)
+            .await?;
+
+        let week_ago = Utc::now() - chrono::TimeDelta::days(7);
+        let leaderboard = db.reviewer_leaderboard(week_ago, 10).await?;
+        assert_eq!(
+            leaderboard,
+            vec![
+                ReviewerStats {
+                    reviewer: UserId(1),
+                    username: Some("Amogus".to_string()),
+                    total: 3,
+                    actions: vec![("NOT_SPAM".to_string(), 1), ("URL_SPAM".to_string(), 2)],
+                },
+                ReviewerStats {
+                    reviewer: UserId(2),
+                    username: None,
+                    total: 1,
+                    actions: vec![("URL_SPAM".to_string(), 1)],
+                },
+            ]
+        );
+        assert_eq!(db.reviewer_leaderboard(week_ago, 1).await?.len(), 1);
+
+        assert_eq!(
+            db.find_reviewer_by_username("@amogus").await?,
+            Some(UserId(1))
+        );
+        assert_eq!(db.find_reviewer_by_username("nobody").await?, None);
+
+        let history = db.reviewer_history(UserId(1), 2).await?;
+        assert_eq!(history.len(), 2);
+        assert!(history
+            .iter()
+            .all(|(_, url, _)| url.starts_with("http://a.com/")));
+        assert!(history[0].2 >= history[1].2);
+
+        // Old reviews don't count, and can be pruned.
+        sqlx::query(
+            "UPDATE reviewer_actions SET reviewed_at='2000-01-01T00:00:00Z' WHERE reviewer_id=2;",
+        )
+        .execute(&db.pool)
+        .await?;
+        assert_eq!(db.reviewer_leaderboard(week_ago, 10).await?.len(), 1);
+        let year_ago = Utc::now() - chrono::TimeDelta::days(365);
+        assert_eq!(db.prune_reviewer_actions(year_ago).await?, 1);
+        assert_eq!(db.prune_reviewer_actions(year_ago).await?, 0);
+        assert!(db.reviewer_history(UserId(2), 10).await?.is_empty());
+        assert_eq!(db.reviewer_history(UserId(1), 10).await?.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn url_patterns() -> Ret {
+        let db = new_temp().await?;
+        let spam: Url = parse_url_like_telegram("www.claim-nft-42.xyz/drain").unwrap();
+        let spam_domain = Domain::from_url(&spam).unwrap();
+        let reviewed: Url = parse_url_like_telegram("claim-nft-legit.xyz/about").unwrap();
+        let reviewed_domain = Domain::from_url(&reviewed).unwrap();
+        let other: Url = parse_url_like_telegram("claim-nft-42.com").unwrap();
+
+        let pattern = HostPattern::parse("*.claim-nft-*.xyz").unwrap();
+
+        // Seen before the pattern existed: automatically not spam, and manually not spam.
+        db.add_domain(&spam_domain, &spam, IsSpam::No, false, false, None)
+            .await?;
+        db.add_domain(&reviewed_domain, &reviewed, IsSpam::No, false, true, None)
+            .await?;
+
+        assert_eq!(
+            db.domains_matching_pattern(&pattern, 1).await?,
+            (2, vec![reviewed_domain.as_str().to_string()])
+        );
+
+        assert!(db.add_url_pattern(&pattern, UserId(1)).await?);
+        assert!(!db.add_url_pattern(&pattern, UserId(2)).await?);
+        let listed = db.list_url_patterns().await?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0, "*.claim-nft-*.xyz");
+        assert_eq!(listed[0].1, UserId(1));
+
+        // The pattern beats the automatic result, but not the manual review.
+        assert_eq!(
+            db.is_spam_with_pattern(&spam, None, false).await?,
+            (Some((IsSpam::Yes, true)), Some(pattern.to_string()))
+        );
+        assert_eq!(
+            db.is_spam(&reviewed, None, false).await?,
+            Some((IsSpam::No, true))
+        );
+        assert_eq!(db.is_spam(&other, None, false).await?, None);
+
+        // Removing it takes effect right away, despite the cache.
+        assert!(db.remove_url_pattern(pattern.as_str()).await?);
+        assert!(!db.remove_url_pattern(pattern.as_str()).await?);
+        assert_eq!(
+            db.is_spam(&spam, None, false).await?,
+            Some((IsSpam::No, false))
+        );
+        assert_eq!(db.matching_url_pattern(&spam_domain).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_urls() -> Ret {
+        let db = new_temp().await?;
+        for url in [
+            "https://claim-nft.ru/drain",
+            "https://www.Claim-NFT.ru/other",
+            "https://example.com/claim-nft.ru",
+            "https://under_score.com/",
+            "https://underXscore.com/",
+            "https://percent.com/",
+        ] {
+            let url = parse_url_like_telegram(url).unwrap();
+            db.add_url(&url, IsSpam::Yes, false, true, None, None)
+                .await?;
+        }
+        let auto = parse_url_like_telegram("https://auto.claim-nft.ru/").unwrap();
+        db.add_url(
+            &auto,
+            IsSpam::Maybe,
+            false,
+            false,
+            Some(MatchReason::NftHtml),
+            None,
+        )
+        .await?;
+
+        // Only the host counts, newest first.
+        let found = db.search_urls("CLAIM-nft.ru", 10).await?;
+        let urls: Vec<&str> = found.iter().map(|x| x.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://auto.claim-nft.ru/",
+                "https://www.claim-nft.ru/other",
+                "https://claim-nft.ru/drain"
+            ]
+        );
+        assert_eq!(found[0].is_spam, IsSpam::Maybe);
+        assert!(!found[0].manually_reviewed);
+        assert_eq!(found[0].match_reason, Some(MatchReason::NftHtml));
+        assert!(found[0].last_seen.is_some());
+        assert!(found[1].manually_reviewed);
+
+        assert_eq!(db.search_urls("claim-nft", 2).await?.len(), 2);
+
+        // Wildcards are taken literally.
+        let found = db.search_urls("under_score", 10).await?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].url, "https://under_score.com/");
+        assert!(db.search_urls("%", 10).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn like_escaping() {
+        assert_eq!(escape_like("a_b%c\\d"), "a\\_b\\%c\\\\d");
+        assert_eq!(escape_like("plain.ru"), "plain.ru");
+    }
+
+    #[tokio::test]
+    async fn appeal_hint() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(-100);
+        assert!(!db.get_appeal_hint(chat).await?);
+        assert!(!db.set_appeal_hint(chat, true).await?);
+        assert!(db.set_appeal_hint(chat, true).await?);
+        assert!(db.get_appeal_hint(chat).await?);
+        assert!(!db.get_appeal_hint(ChatId(-200)).await?);
+
+        db.migrate_chat_id(chat, ChatId(-300)).await?;
+        assert!(!db.get_appeal_hint(chat).await?);
+        assert!(db.get_appeal_hint(ChatId(-300)).await?);
+
+        assert!(db.set_appeal_hint(ChatId(-300), false).await?);
+        assert!(!db.get_appeal_hint(ChatId(-300)).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn review_keyboards() -> Ret {
+        let db = new_temp().await?;
+        let chat = ChatId(5);
+        let reviewed = parse_url_like_telegram("spam.example/claim").unwrap();
+        let gone = parse_url_like_telegram("gone.example/claim").unwrap();
+
+        // Newer entries are sent to review first.
+        db.add_url(&gone, IsSpam::Maybe, false, false, None, None)
+            .await?;
+        db.add_url(&reviewed, IsSpam::Maybe, false, false, None, None)
+            .await?;
+        let (_, table, reviewed_rowid, ..) = db.get_url_for_review().await?.unwrap();
+        let (_, _, gone_rowid, ..) = db.get_url_for_review().await?.unwrap();
+        assert_ne!(reviewed_rowid, gone_rowid);
+
+        db.remember_review_keyboard(chat, MessageId(1), table, reviewed_rowid)
+            .await?;
+        db.remember_review_keyboard(chat, MessageId(2), table, gone_rowid)
+            .await?;
+        // Not remembered for things that aren't there.
+        db.remember_review_keyboard(chat, MessageId(3), table, 1000)
+            .await?;
+        db.remember_review_keyboard(chat, MessageId(4), "amogus", reviewed_rowid)
+            .await?;
+
+        let keyboards = db.get_review_keyboards().await?;
+        assert_eq!(keyboards.len(), 2);
+        assert!(keyboards.iter().all(|x| !x.resolved));
+
+        // Reviewed by someone, and removed altogether.
+        db.add_url(&reviewed, IsSpam::Yes, false, true, None, None)
+            .await?;
+        db.remove_url(&gone).await?;
+        let keyboards = db.get_review_keyboards().await?;
+        assert_eq!(keyboards.len(), 2);
+        assert!(keyboards.iter().all(|x| x.resolved));
+
+        // Showing an already reviewed entry again isn't resolved by that review.
+        db.remember_review_keyboard(chat, MessageId(1), table, reviewed_rowid)
+            .await?;
+        db.forget_review_keyboard(chat, MessageId(2)).await?;
+        let keyboards = db.get_review_keyboards().await?;
+        assert_eq!(keyboards.len(), 1);
+        assert_eq!(keyboards[0].message, MessageId(1));
+        assert!(!keyboards[0].resolved);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spam_domain_cache() -> Ret {
+        let db = new_temp().await?;
+        let domain = Domain::from_url(&parse_url_like_telegram("spam.example").unwrap()).unwrap();
+        let other = Domain::from_url(&parse_url_like_telegram("sus.example").unwrap()).unwrap();
+
+        assert!(!db.is_cached_spam_domain(&domain).await);
+        db.cache_spam_domain(&domain).await;
+        db.cache_spam_domain(&other).await;
+        assert!(db.is_cached_spam_domain(&domain).await);
+
+        db.forget_cached_spam_domain(&domain).await;
+        assert!(!db.is_cached_spam_domain(&domain).await);
+        assert!(db.is_cached_spam_domain(&other).await);
+
+        Ok(())
+    }
 }
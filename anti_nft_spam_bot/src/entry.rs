@@ -1,16 +1,20 @@
-use std::{fs, sync::Arc};
+use arch_bot_commons::watchdog::Heartbeat;
+use std::{fs, sync::Arc, time::Duration};
 use teloxide::{dptree::deps, prelude::*};
 
 use crate::{
+    config::BotConfig,
     database::Database,
     handlers::{generate_bot_commands, reviews::parse_callback_query},
 };
 
 /// # Panics
 ///
-/// Panics if there's no key file
+/// Panics if there's no key file or the config is broken, see [`crate::config`].
 pub async fn entry() {
     log::info!("ASYNC WOOOO");
+    BotConfig::init();
+
     let key = fs::read_to_string(match cfg!(debug_assertions) {
         true => "key_debug",
         false => "key",
@@ -26,24 +30,41 @@ pub async fn entry() {
 
     let db: Arc<Database> = Database::new(bot.clone()).await.unwrap();
 
+    tokio::spawn(crate::handlers::reviews::sweep_review_keyboards(
+        bot.clone(),
+        db.clone(),
+    ));
+
     log::info!("Creating the handler...");
 
+    // A minute without the runtime getting to a 10 second tick means that it's stuck.
+    let heartbeat = Heartbeat::new(Duration::from_secs(60));
+    tokio::spawn(heartbeat.clone().keep_beating(Duration::from_secs(10)));
+
     let handler = dptree::entry()
         .branch(Update::filter_message().branch(dptree::endpoint(crate::handlers::handle_message)))
         .branch(
             Update::filter_edited_message()
                 .branch(dptree::endpoint(crate::handlers::handle_message)),
         )
-        .branch(Update::filter_callback_query().endpoint(parse_callback_query));
+        .branch(Update::filter_callback_query().endpoint(parse_callback_query))
+        .branch(Update::filter_chat_member().endpoint(crate::handlers::handle_chat_member))
+        .branch(Update::filter_channel_post().endpoint(crate::handlers::sync::handle_channel_post));
 
     log::info!("Dispatching the dispatcher!");
 
-    Dispatcher::builder(bot, handler)
-        .dependencies(deps![db])
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
+        .dependencies(deps![db.clone()])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    let _watchdog = arch_bot_commons::watchdog::start(move || {
+        let db = db.clone();
+        let heartbeat = heartbeat.clone();
+        async move { heartbeat.is_fresh() && db.is_alive().await }
+    });
+
+    arch_bot_commons::dispatch(&mut dispatcher, bot).await;
 
     log::info!("it appears we have been bonked.");
 }
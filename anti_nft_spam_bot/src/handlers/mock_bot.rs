@@ -0,0 +1,276 @@
+//! A pretend bot for testing handlers, which records what they ask Telegram to do
+//! and answers with made up responses.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use arch_bot_commons::bot_api::{BotApi, MessageOptions};
+use serde_json::json;
+use teloxide::{
+    types::{
+        Chat, ChatId, ChatMember, InlineKeyboardButtonKind, InlineKeyboardMarkup, Message,
+        MessageId, Recipient, UserId,
+    },
+    ApiError, RequestError,
+};
+
+/// A call made to [`MockBot`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    SendMessage {
+        chat: ChatId,
+        text: String,
+        reply_to: Option<MessageId>,
+    },
+    EditMessageText {
+        chat: ChatId,
+        message: MessageId,
+        text: String,
+    },
+    EditMessageReplyMarkup {
+        chat: ChatId,
+        message: MessageId,
+        removed: bool,
+        /// Callback data of buttons that are left.
+        buttons: Vec<String>,
+    },
+    DeleteMessage {
+        chat: ChatId,
+        message: MessageId,
+    },
+    GetChatMember {
+        chat: ChatId,
+        user: UserId,
+    },
+    GetChatAdministrators {
+        chat: ChatId,
+    },
+    GetChat {
+        chat: ChatId,
+    },
+    AnswerCallbackQuery {
+        text: String,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    calls: Vec<Call>,
+    admins: HashSet<(ChatId, UserId)>,
+    linked_chats: HashMap<ChatId, ChatId>,
+    /// What to fail deleting messages with, if anything.
+    delete_error: Option<ApiError>,
+    /// What to fail asking about members of these chats with.
+    member_errors: HashMap<ChatId, ApiError>,
+    last_message_id: i32,
+}
+
+#[derive(Clone, Default)]
+pub struct MockBot {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockBot {
+    /// Make this user an admin of this chat.
+    pub fn add_admin(&self, chat: ChatId, user: UserId) {
+        self.state.lock().unwrap().admins.insert((chat, user));
+    }
+
+    /// Make this channel linked to this chat.
+    pub fn link_chat(&self, chat: ChatId, channel: ChatId) {
+        self.state
+            .lock()
+            .unwrap()
+            .linked_chats
+            .insert(chat, channel);
+    }
+
+    /// Make deleting messages fail with this error.
+    pub fn fail_deletes(&self, error: ApiError) {
+        self.state.lock().unwrap().delete_error = Some(error);
+    }
+
+    /// Make asking about members of this chat fail with this error.
+    pub fn fail_members(&self, chat: ChatId, error: ApiError) {
+        self.state.lock().unwrap().member_errors.insert(chat, error);
+    }
+
+    /// All calls made so far, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Calls made so far, other than ones that only ask Telegram about something.
+    pub fn actions(&self) -> Vec<Call> {
+        self.calls()
+            .into_iter()
+            .filter(|x| {
+                !matches!(
+                    x,
+                    Call::GetChatMember { .. }
+                        | Call::GetChatAdministrators { .. }
+                        | Call::GetChat { .. }
+                )
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) {
+        self.state.lock().unwrap().calls.push(call);
+    }
+
+    /// Make up what Telegram says about this user in this chat.
+    fn member(&self, chat: ChatId, user: UserId) -> ChatMember {
+        let mut member = json!({
+            "user": {"id": user.0, "is_bot": false, "first_name": "Amogus"},
+            "status": "member",
+        });
+        if self.state.lock().unwrap().admins.contains(&(chat, user)) {
+            member["status"] = json!("administrator");
+            member["can_be_edited"] = json!(false);
+            member["is_anonymous"] = json!(false);
+            member["can_manage_chat"] = json!(true);
+            member["can_delete_messages"] = json!(true);
+            member["can_manage_video_chats"] = json!(false);
+            member["can_restrict_members"] = json!(true);
+            member["can_promote_members"] = json!(false);
+            member["can_change_info"] = json!(false);
+            member["can_invite_users"] = json!(true);
+        }
+        serde_json::from_value(member).unwrap()
+    }
+
+    /// Make up a message sent by this bot.
+    fn message(&self, chat: ChatId, id: Option<MessageId>, text: &str) -> Message {
+        let id = id.unwrap_or_else(|| {
+            let mut state = self.state.lock().unwrap();
+            state.last_message_id += 1;
+            MessageId(state.last_message_id)
+        });
+        let chat = if chat.is_user() {
+            json!({"id": chat.0, "type": "private", "first_name": "Amogus"})
+        } else {
+            json!({"id": chat.0, "type": "supergroup", "title": "Chat"})
+        };
+
+        serde_json::from_value(json!({
+            "message_id": id.0,
+            "date": 0,
+            "chat": chat,
+            "from": {"id": 1, "is_bot": true, "first_name": "Bot", "username": "bot"},
+            "text": text,
+        }))
+        .unwrap()
+    }
+}
+
+impl BotApi for MockBot {
+    async fn send_message(
+        &self,
+        chat: impl Into<Recipient> + Send,
+        text: impl Into<String> + Send,
+        options: MessageOptions,
+    ) -> Result<Message, RequestError> {
+        let Recipient::Id(chat) = chat.into() else {
+            panic!("Only sending messages to chat IDs is pretended.");
+        };
+        let text = text.into();
+        let message = self.message(chat, None, &text);
+        self.record(Call::SendMessage {
+            chat,
+            text,
+            reply_to: options.reply_to,
+        });
+        Ok(message)
+    }
+
+    async fn edit_message_text(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+        text: impl Into<String> + Send,
+        _options: MessageOptions,
+    ) -> Result<Message, RequestError> {
+        let text = text.into();
+        let edited = self.message(chat, Some(message), &text);
+        self.record(Call::EditMessageText {
+            chat,
+            message,
+            text,
+        });
+        Ok(edited)
+    }
+
+    async fn edit_message_reply_markup(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+        reply_markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        self.record(Call::EditMessageReplyMarkup {
+            chat,
+            message,
+            removed: reply_markup.is_none(),
+            buttons: (reply_markup.iter())
+                .flat_map(|x| x.inline_keyboard.iter().flatten())
+                .filter_map(|x| match &x.kind {
+                    InlineKeyboardButtonKind::CallbackData(data) => Some(data.clone()),
+                    _ => None,
+                })
+                .collect(),
+        });
+        Ok(self.message(chat, Some(message), ""))
+    }
+
+    async fn delete_message(&self, chat: ChatId, message: MessageId) -> Result<(), RequestError> {
+        self.record(Call::DeleteMessage { chat, message });
+        match self.state.lock().unwrap().delete_error.clone() {
+            Some(error) => Err(RequestError::Api(error)),
+            None => Ok(()),
+        }
+    }
+
+    async fn get_chat_member(
+        &self,
+        chat: ChatId,
+        user: UserId,
+    ) -> Result<ChatMember, RequestError> {
+        self.record(Call::GetChatMember { chat, user });
+        if let Some(error) = self.state.lock().unwrap().member_errors.get(&chat) {
+            return Err(RequestError::Api(error.clone()));
+        }
+        Ok(self.member(chat, user))
+    }
+
+    async fn get_chat_administrators(&self, chat: ChatId) -> Result<Vec<ChatMember>, RequestError> {
+        self.record(Call::GetChatAdministrators { chat });
+
+        let mut admins: Vec<UserId> = (self.state.lock().unwrap().admins.iter())
+            .filter(|x| x.0 == chat)
+            .map(|x| x.1)
+            .collect();
+        admins.sort_by_key(|x| x.0);
+        Ok(admins.into_iter().map(|x| self.member(chat, x)).collect())
+    }
+
+    async fn get_chat(&self, chat: ChatId) -> Result<Chat, RequestError> {
+        self.record(Call::GetChat { chat });
+
+        let mut full = json!({"id": chat.0, "type": "supergroup", "title": "Chat"});
+        if let Some(linked) = self.state.lock().unwrap().linked_chats.get(&chat) {
+            full["linked_chat_id"] = json!(linked.0);
+        }
+        Ok(serde_json::from_value(full).unwrap())
+    }
+
+    async fn answer_callback_query(
+        &self,
+        _query_id: impl Into<String> + Send,
+        text: impl Into<String> + Send,
+    ) -> Result<(), RequestError> {
+        self.record(Call::AnswerCallbackQuery { text: text.into() });
+        Ok(())
+    }
+}
@@ -1,24 +1,227 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use arch_bot_commons::useful_methods::BotArchSendMsg;
+use arch_bot_commons::{
+    bot_api::{BotApi, MessageOptions},
+    strip_command,
+    useful_methods::BotArchSendMsg,
+};
+use chrono::{DateTime, NaiveDate, Utc};
 use html_escape::encode_text;
 use teloxide::{
     prelude::*,
-    types::{BotCommand, ChatMember, Me, MessageEntityKind, MessageEntityRef},
+    types::{
+        BotCommand, CallbackQuery, Chat, ChatMemberUpdated, ForwardedFrom, InlineKeyboardButton,
+        InlineKeyboardButtonKind, InlineKeyboardMarkup, Me, MessageEntityKind, MessageEntityRef,
+        MessageId, User,
+    },
     ApiError, RequestError,
 };
 use url::Url;
 
 use crate::{
-    database::Database,
+    config::BotConfig,
+    database::{review_context, Database, OVERRIDES_BEFORE_REVIEW, QUARANTINE_TIME},
+    misc::recover_defanged_urls,
     parse_url_like_telegram,
-    types::{Domain, IsSpam, ReviewResponse},
-    CONTROL_CHAT_ID,
+    spam_checker::heuristics::HEURISTICS,
+    types::{
+        DeletionNotice, Domain, HostPattern, IsSpam, MatchReason, ReviewNotice, ReviewResponse,
+        ReviewerStats,
+    },
 };
 
+#[cfg(test)]
+mod mock_bot;
+pub mod review_notify;
 pub mod reviews;
+pub mod sync;
 use self::reviews::handle_review_command;
 
+/// Maximum amount of seconds admins can ask to wait before deleting spam.
+const MAX_GRACE_PERIOD: u32 = 600;
+
+/// Parse the expiry date given to `/mark_url_spam_until`, either as an RFC 3339
+/// timestamp or as a plain `YYYY-MM-DD` date, in which case the whole day is included.
+///
+/// Returns [`None`] if it can't be parsed or isn't after `now`.
+fn parse_expiry_date(text: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let expires_at = if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        date.with_timezone(&Utc)
+    } else {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()?
+            .succ_opt()?
+            .and_hms_opt(0, 0, 0)?
+            .and_utc()
+    };
+
+    (expires_at > now).then_some(expires_at)
+}
+
+/// How long ago URLs have to have been last seen for `/prune_urls` to remove them, by default.
+const DEFAULT_PRUNE_URLS_AGE: chrono::TimeDelta = chrono::TimeDelta::days(365);
+
+/// Parse the parameters of `/prune_urls`: `older_than:` with an age like `365d`, `8w` or `12h`,
+/// and `dry_run:` with `true` or `false`. Not actually removing anything is the default.
+///
+/// Returns [`None`] if anything can't be parsed.
+fn parse_prune_urls_params(params: &str) -> Option<(chrono::TimeDelta, bool)> {
+    let mut age = DEFAULT_PRUNE_URLS_AGE;
+    let mut dry_run = true;
+
+    for param in params.split_whitespace() {
+        let (key, value) = param.split_once(':')?;
+        match key {
+            "older_than" => {
+                let split = value.len().checked_sub(1)?;
+                let (amount, unit) = value.split_at_checked(split)?;
+                let amount: i64 = amount.parse().ok().filter(|x| *x > 0)?;
+                age = match unit {
+                    "h" => chrono::TimeDelta::try_hours(amount)?,
+                    "d" => chrono::TimeDelta::try_days(amount)?,
+                    "w" => chrono::TimeDelta::try_weeks(amount)?,
+                    _ => return None,
+                };
+            }
+            "dry_run" => dry_run = value.parse().ok()?,
+            _ => return None,
+        }
+    }
+
+    Some((age, dry_run))
+}
+
+/// How many reviewers `/leaderboard` shows for each period.
+const LEADERBOARD_SIZE: usize = 10;
+/// Periods, in days, that `/leaderboard` shows top reviewers for.
+const LEADERBOARD_PERIODS: [i64; 2] = [7, 30];
+/// How many reviews `/reviewer_history` shows if not told otherwise, and at most.
+const DEFAULT_REVIEWER_HISTORY: u32 = 20;
+const MAX_REVIEWER_HISTORY: u32 = 100;
+/// How long reviews are kept for `/leaderboard` and `/reviewer_history`
+/// before `/prune_reviewer_history` forgets them.
+const REVIEWER_ACTIONS_KEEP_TIME: chrono::TimeDelta = chrono::TimeDelta::days(365);
+
+/// How many URLs `/search` shows at most.
+const SEARCH_RESULTS: u32 = 30;
+
+/// How many known domains `/mark_pattern_spam` shows as examples of what a pattern matches.
+const PATTERN_EXAMPLES: usize = 10;
+
+/// Added to spam deletion notifications in chats that asked for it with `/show_appeal_hint`.
+const APPEAL_HINT: &str = concat!(
+    "\n\nIf this was a mistake, an admin can use <code>/grace_period</code> to get time ",
+    "to keep such messages with <code>/keep</code>, which also sends their links back for review."
+);
+
+/// Parse the parameters of `/reviewer_history`: a reviewer, as a @username or a user ID,
+/// and optionally how many of their reviews to show.
+///
+/// Returns [`None`] if anything can't be parsed.
+fn parse_reviewer_history_params(params: &str) -> Option<(&str, u32)> {
+    let mut words = params.split_whitespace();
+    let reviewer = words.next()?;
+    let count = match words.next() {
+        Some(count) => count
+            .parse()
+            .ok()
+            .filter(|x| (1..=MAX_REVIEWER_HISTORY).contains(x))?,
+        None => DEFAULT_REVIEWER_HISTORY,
+    };
+    if words.next().is_some() {
+        return None;
+    }
+    Some((reviewer, count))
+}
+
+/// Describe this reviewer for `/leaderboard`, with their username if known, and ID.
+fn describe_reviewer(reviewer: UserId, username: Option<&str>) -> String {
+    match username {
+        Some(username) => format!(
+            "@{} (userid <code>{}</code>)",
+            encode_text(username),
+            reviewer
+        ),
+        None => format!("userid <code>{}</code>", reviewer),
+    }
+}
+
+/// Make the `/leaderboard` section for reviews applied in the last `days` days.
+fn describe_leaderboard(days: i64, since: DateTime<Utc>, stats: &[ReviewerStats]) -> String {
+    use std::fmt::Write;
+    let mut text = format!(
+        "<b>Last {} days</b> (since {} UTC):\n",
+        days,
+        since.format("%Y-%m-%d %H:%M")
+    );
+
+    if stats.is_empty() {
+        text.push_str("Nobody applied any reviews.\n");
+    }
+
+    for (place, stats) in stats.iter().enumerate() {
+        let actions: Vec<String> = stats
+            .actions
+            .iter()
+            .map(|(action, count)| format!("{} {}", action, count))
+            .collect();
+        let _ = writeln!(
+            text,
+            "{}. {}: {} ({})",
+            place + 1,
+            describe_reviewer(stats.reviewer, stats.username.as_deref()),
+            stats.total,
+            actions.join(", ")
+        );
+    }
+
+    text
+}
+
+/// Get the reason given to a spam marking command: whatever is left of its `params`
+/// after taking out the `links` and then the first `skip_words` words,
+/// like the date of `/mark_url_spam_until`.
+fn parse_mark_reason(params: &str, links: &[&str], skip_words: usize) -> String {
+    let mut remainder = params.to_string();
+    for link in links {
+        if let Some(start) = remainder.find(link) {
+            remainder.replace_range(start..start + link.len(), " ");
+        }
+    }
+
+    remainder
+        .split_whitespace()
+        .skip(skip_words)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// If spam is deleted within this long of the last deletion notification in a chat,
+/// that notification is edited to count it in instead of sending a new one.
+const DELETION_NOTICE_COALESCE_TIME: Duration = Duration::from_secs(60);
+
+/// How many messages up a reply chain to look for links in when handling /spam.
+const MAX_REPLY_CHAIN_DEPTH: usize = 3;
+
+/// Get links written so Telegram doesn't see them out of this text, with their domains,
+/// skipping ones with domains already seen in `seen_domains`.
+fn recovered_url_domains(text: Option<&str>, seen_domains: &[Domain]) -> Vec<(Url, Domain)> {
+    let Some(text) = text else {
+        return Vec::new();
+    };
+
+    recover_defanged_urls(text)
+        .into_iter()
+        .filter_map(|url| {
+            let domain = Domain::from_url(&url)?;
+            (!seen_domains.contains(&domain)).then_some((url, domain))
+        })
+        .collect()
+}
+
 /// Get a domain and a URL from this entity, if available.
 fn get_entity_url_domain(entity: &MessageEntityRef) -> Option<(Url, Domain)> {
     let mut url = match entity.kind() {
@@ -61,19 +264,57 @@ fn get_entity_url_domain(entity: &MessageEntityRef) -> Option<(Url, Domain)> {
     // Some telegram spam (like telegram bots) use queries a lot,
     // especially referral links in spammed "games".
     // Strip those just from telegram URLs.
-    if crate::spam_checker::is_telegram_url(&url) {
-        url.set_query(None);
-    }
+    crate::spam_checker::strip_telegram_query(&mut url);
 
     let Some(domain) = Domain::from_url(&url) else {
-        // Does not have a domain. An IP address link?
-        log::warn!("Received a URL without a domain: {}", entity.text());
+        // Does not have a host. A "mailto:" link?
+        log::warn!("Received a URL without a host: {}", entity.text());
         return None;
     };
 
     Some((url, domain))
 }
 
+/// Describe how this link, as it was written in a message, is seen by the bot
+/// when checking and storing it, for `/sanitize`.
+fn describe_link(original: &str, url: &Url, domain: &Domain) -> String {
+    use std::fmt::Write;
+    let mut response = String::new();
+
+    let _ = writeln!(response, "Original: <code>{}</code>", encode_text(original));
+    let _ = writeln!(
+        response,
+        "Sanitized: <code>{}</code>",
+        encode_text(url.as_str())
+    );
+    let _ = writeln!(
+        response,
+        "Domain: <code>{}</code>",
+        encode_text(domain.as_str())
+    );
+    if let Some(port) = url.port() {
+        let _ = writeln!(response, "Port: <code>{}</code>", port);
+    }
+    let _ = writeln!(response, "Path: <code>{}</code>", encode_text(url.path()));
+
+    match url.query() {
+        Some(query) => {
+            let _ = writeln!(response, "Query: <code>{}</code>", encode_text(query));
+            for (key, value) in url.query_pairs() {
+                let _ = writeln!(
+                    response,
+                    "• <code>{}</code> = <code>{}</code>",
+                    encode_text(&key),
+                    encode_text(&value)
+                );
+            }
+        }
+        None => response.push_str("Query: none\n"),
+    }
+
+    response
+}
+
 /// Get a domain and a URL from this button, if available.
 fn get_button_url_domain(button: &teloxide::types::InlineKeyboardButton) -> Option<(&Url, Domain)> {
     use teloxide::types::InlineKeyboardButtonKind as Kind;
@@ -90,16 +331,82 @@ fn get_button_url_domain(button: &teloxide::types::InlineKeyboardButton) -> Opti
     };
 
     let Some(domain) = Domain::from_url(url) else {
-        // Does not have a domain. An IP address link?
-        log::warn!("Received a URL in a button without a domain: {}", url);
+        // Does not have a host. A "mailto:" link?
+        log::warn!("Received a URL in a button without a host: {}", url);
         return None;
     };
 
     Some((url, domain))
 }
 
+/// Get a link to the inline bot this message was sent "via", if it's not a trusted one.
+/// See [`crate::spam_checker::inline_bot_url`].
+fn get_via_bot_url_domain(message: &Message) -> Option<(Url, Domain)> {
+    let username = message.via_bot.as_ref()?.username.as_deref()?;
+    let url = crate::spam_checker::inline_bot_url(username)?;
+    let domain = Domain::from_url(&url)?;
+    Some((url, domain))
+}
+
+/// Get all links in this message's text, caption and buttons, and to the inline bot
+/// it was sent via, in order
+/// they would be checked by [`handle_message_inner`].
+fn get_message_links(message: &Message) -> Vec<(Url, Domain)> {
+    let mut links: Vec<(Url, Domain)> = Vec::new();
+
+    if let Some(entities) = message
+        .parse_entities()
+        .or_else(|| message.parse_caption_entities())
+    {
+        for entity in &entities {
+            if let Some(link) = get_entity_url_domain(entity) {
+                links.push(link);
+            }
+        }
+    }
+
+    if let Some(markup) = message.reply_markup() {
+        for row in &markup.inline_keyboard {
+            for button in row {
+                if let Some((url, domain)) = get_button_url_domain(button) {
+                    links.push((url.clone(), domain));
+                }
+            }
+        }
+    }
+
+    if let Some(link) = get_via_bot_url_domain(message) {
+        links.push(link);
+    }
+
+    links
+}
+
+/// Iterate over messages this message replies to, going up the reply chain,
+/// up to [`MAX_REPLY_CHAIN_DEPTH`] messages.
+///
+/// Realistically, this yields one message at most: Telegram's Bot API doesn't include
+/// `reply_to_message` in replied-to messages, and bots can't fetch messages by ID
+/// to walk further. Should Telegram ever include more, this will follow it.
+fn reply_chain(message: &Message) -> impl Iterator<Item = &Message> {
+    std::iter::successors(message.reply_to_message(), |x| x.reply_to_message())
+        .take(MAX_REPLY_CHAIN_DEPTH)
+}
+
+/// Same as [`reply_chain`], but skipping posts automatically forwarded from the channel
+/// linked to the chat, as those are made by its admins and shouldn't be marked as sus.
+fn suspicion_reply_chain(message: &Message) -> impl Iterator<Item = &Message> {
+    reply_chain(message).filter(|x| !x.is_automatic_forward())
+}
+
 /// Returns `true` if this chat is private.
-async fn is_sender_admin(bot: &Bot, message: &Message) -> Result<bool, RequestError> {
+///
+/// Admin statuses are remembered for a while, see [`Database::admin_cache`].
+async fn is_sender_admin(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<bool, RequestError> {
     if message.chat.is_private() {
         return Ok(true);
     }
@@ -113,13 +420,19 @@ async fn is_sender_admin(bot: &Bot, message: &Message) -> Result<bool, RequestEr
         } else {
             // It may have been sent by the channel linked to this chat, then.
             // Check for that.
-            let chat_full = bot.get_chat(message.chat.id).await?;
+            let linked_chat = database
+                .admin_cache()
+                .linked_chat_of(bot, message.chat.id)
+                .await?;
 
-            chat_full.linked_chat_id() == Some(sender_chat.id.0)
+            linked_chat == Some(sender_chat.id)
         }
     } else if let Some(user) = message.from() {
-        let ChatMember { kind, .. } = bot.get_chat_member(message.chat.id, user.id).await?;
-        kind.is_privileged()
+        database
+            .admin_cache()
+            .get_admin_of(bot, user.id, message.chat.id)
+            .await?
+            .is_some()
     } else {
         false
     };
@@ -133,11 +446,37 @@ pub async fn handle_message(
     message: Message,
     database: Arc<Database>,
 ) -> Result<(), RequestError> {
-    handle_message_inner(&bot, &me, &message, &database, false).await?;
+    // Messages sent while the bot was down are still delivered after it starts,
+    // as pending updates are not dropped. In chats that asked to catch up on those,
+    // they're let through slowly, not to hit rate limits after a long downtime,
+    // and handled quietly.
+    let is_replayed = !message.chat.is_private()
+        && database.is_missed_message(&message)
+        && database
+            .get_backfill(message.chat.id)
+            .await
+            .expect("Database died!");
+
+    if !message.chat.is_private()
+        && message.edit_date().is_none()
+        && !database
+            .record_chat_progress(message.chat.id, message.id)
+            .await
+            .expect("Database died!")
+    {
+        // Handled already before the bot went down.
+        return Ok(());
+    }
+
+    if is_replayed {
+        database.wait_for_replay_turn().await;
+    }
+
+    handle_message_inner(&bot, &me, &message, &database, false, is_replayed).await?;
 
     // Also handle the message it's a reply to.
     if let Some(replied_to) = message.reply_to_message() {
-        handle_message_inner(&bot, &me, replied_to, &database, true).await?;
+        handle_message_inner(&bot, &me, replied_to, &database, true, is_replayed).await?;
     }
 
     Ok(())
@@ -146,12 +485,16 @@ pub async fn handle_message(
 /// Set `is_replied_to` to true if this message is being handled in context of being an older
 /// message that was replied to and is being checked again. If so, this handler will ignore
 /// commands and such.
+///
+/// Set `is_replayed` to true if this message was sent while the bot was down. Then, spam is
+/// still deleted as usual, but nothing else is responded to, as it's too late for that.
 async fn handle_message_inner(
-    bot: &Bot,
+    bot: &impl BotApi,
     me: &Me,
     message: &Message,
     database: &Arc<Database>,
     is_replied_to: bool,
+    is_replayed: bool,
 ) -> Result<(), RequestError> {
     if let Some(sender) = message.from() {
         if sender.id == me.id {
@@ -160,6 +503,26 @@ async fn handle_message_inner(
         }
     }
 
+    // A group upgraded to a supergroup gets a new ID. Both the old and the new chat
+    // get a message about that; migrating twice does nothing the second time.
+    let migration = message
+        .migrate_to_chat_id()
+        .map(|new| (message.chat.id, new))
+        .or_else(|| {
+            message
+                .migrate_from_chat_id()
+                .map(|old| (old, message.chat.id))
+        });
+    if let Some((old, new)) = migration {
+        if !is_replied_to {
+            log::info!("Chat {} was migrated to {}.", old, new);
+            if let Err(e) = database.migrate_chat_id(old, new).await {
+                log::error!("Failed to migrate chat {} to {}: {}", old, new, e);
+            }
+        }
+        return Ok(());
+    }
+
     let is_edited = message.edit_date().is_some();
 
     // First check if it's a private message.
@@ -173,6 +536,16 @@ async fn handle_message_inner(
         return Ok(());
     }
 
+    // Posts automatically forwarded from the channel linked to this chat are made by
+    // that channel's admins. Those are only checked for spam, and only deleted if
+    // this chat's admins asked for it.
+    let is_channel_post = message.is_automatic_forward();
+
+    if !is_replied_to && !is_edited && handle_album_message(bot, message, database).await? {
+        // A part of an album that was already deleted for spam. It's gone now too.
+        return Ok(());
+    }
+
     // Check if it has any links we want to ban.
 
     // Get message "entities".
@@ -182,6 +555,11 @@ async fn handle_message_inner(
         .unwrap_or_default();
 
     let mut bad_links_present = false;
+    // The first spam link found, to tell about it if it's only reported.
+    let mut bad_link: Option<Url> = None;
+    // Whether links that are only likely spam should be deleted too,
+    // looked up once there is such a link.
+    let mut strict_mode: Option<bool> = None;
 
     // Two loops below iterate over links, but need to do the same thing.
     // Rather than duplicate the code inside the loops, I'm defining a macro
@@ -193,12 +571,24 @@ async fn handle_message_inner(
         ($url: expr, $domain: expr, $loop_to_break: tt) => {
             log::debug!("Spotted URL with domain {}", $domain);
 
-            let Some(is_spam) = crate::spam_checker::check(database, $domain, $url).await else {
+            let Some((is_spam, reason)) = crate::spam_checker::check(database, $domain, $url).await
+            else {
                 continue;
             };
+            log::debug!("{} is {:?} by {}", $url, is_spam, reason);
+
+            if is_spam == IsSpam::Likely && strict_mode.is_none() {
+                strict_mode = Some(
+                    database
+                        .get_strict_mode(message.chat.id)
+                        .await
+                        .expect("Database died!"),
+                );
+            }
 
-            if is_spam == IsSpam::Yes {
+            if is_spam.should_delete(strict_mode.unwrap_or(false)) {
                 bad_links_present = true;
+                bad_link = Some(Url::clone($url));
                 break $loop_to_break;
             }
         };
@@ -226,15 +616,34 @@ async fn handle_message_inner(
         }
     }
 
+    // Spammers also post games and articles with spam buttons "via" their own inline bots.
+    if !bad_links_present {
+        let via_bot = get_via_bot_url_domain(message);
+        'via: for (url, domain) in via_bot.iter() {
+            check_url!(url, domain, 'via);
+        }
+    }
+
     // We may need to check if the sender is an admin in two different places in this function.
     // If that happens, store the result determined first and reuse.
     let mut sent_by_admin: Option<bool> = None;
 
-    let should_delete = if bad_links_present {
+    let should_delete = if bad_links_present && is_channel_post {
+        let moderate = database
+            .get_moderate_channel_posts(message.chat.id)
+            .await
+            .expect("Database died!");
+
+        if !moderate && !is_replayed {
+            warn_about_channel_spam(bot, message, database).await?;
+        }
+
+        moderate
+    } else if bad_links_present {
         // oh no!
         // Check if this is an admin of the chat or not.
 
-        sent_by_admin = Some(is_sender_admin(bot, message).await?);
+        sent_by_admin = Some(is_sender_admin(bot, message, database).await?);
 
         if sent_by_admin == Some(true) {
             log::debug!("Skipping deleting message from an admin.");
@@ -248,83 +657,44 @@ async fn handle_message_inner(
         false
     };
 
+    if should_delete
+        && database
+            .get_report_only(message.chat.id)
+            .await
+            .expect("Database died!")
+    {
+        // Admins of this chat want to see what the bot would do before trusting it.
+        // Edits of and replies to a message that was already reported don't count.
+        if !is_replayed
+            && database
+                .should_report_spam(message.chat.id, message.id)
+                .await
+        {
+            report_spam(bot, message, bad_link.as_ref()).await?;
+        }
+        return Ok(());
+    }
+
     if should_delete {
-        // Try up to 3 times in case a fail happens lol
-        for _ in 0..3 {
-            match bot.delete_message(message.chat.id, message.id).await {
-                Ok(_) => {
-                    // Make a string, either a @username or full name,
-                    // describing the offending user.
-                    let offending_user_name = {
-                        if let Some(user) = message.from() {
-                            if let Some(username) = &user.username {
-                                format!("@{}", username)
-                            } else {
-                                user.full_name()
-                            }
-                        } else if let Some(chat) = message.sender_chat() {
-                            if let Some(username) = chat.username() {
-                                format!("@{}", username)
-                            } else if let Some(title) = chat.title() {
-                                title.to_string()
-                            } else {
-                                // Shouldn't happen, but eh.
-                                "a private user".to_string()
-                            }
-                        } else {
-                            // Shouldn't happen either, but eh.
-                            "a private user".to_string()
-                        }
-                    };
+        let grace_period = database
+            .get_grace_period(message.chat.id)
+            .await
+            .expect("Database died!");
 
-                    if !database
-                        .get_hide_deletes(message.chat.id)
-                        .await
-                        .expect("Database died!")
-                    {
-                        bot.archsendmsg(
-                            message.chat.id,
-                            format!(
-                                "Removed a message from <code>{}</code> containing a spam link.",
-                                encode_text(&offending_user_name)
-                            )
-                            .as_str(),
-                            None,
-                        )
-                        .await?;
-                    }
-                    break;
-                }
-                Err(RequestError::Api(
-                    ApiError::MessageIdInvalid | ApiError::MessageToDeleteNotFound,
-                )) => {
-                    // Someone else probably has already deleted it. That's fine.
-                    break;
-                }
-                Err(RequestError::Api(ApiError::MessageCantBeDeleted)) => {
-                    // No rights?
-                    bot.archsendmsg(
-                        message.chat.id,
-                        concat!(
-                            "Tried to remove a message containing a spam link, but failed. ",
-                            "Is this bot an admin with ability to remove messages?\n\n",
-                            "If so, this may also be a Telegram bug, and an admin ",
-                            "has to remove the message manually."
-                        ),
-                        None,
-                    )
-                    .await?;
-                    break;
-                }
-                Err(_) => {
-                    // Random network error or whatever, possibly.
-                    // Try again by letting the loop roll.
-                }
-            }
+        if grace_period == 0 {
+            delete_spam_message(bot, message, database).await?;
+        } else {
+            schedule_spam_deletion(bot, message, database, grace_period).await;
         }
     } else {
-        // It's not spam. Do the other things, if it's not an edit nor a replied-to message
-        if !is_replied_to && !is_edited {
+        // It's not spam. Do the other things, if it's not an edit nor a replied-to message,
+        // nor a channel post, nor a message that's too late to respond to.
+        if !is_replied_to && !is_edited && !is_channel_post && !is_replayed {
+            if guard_first_link(bot, message, database, &mut sent_by_admin).await? {
+                // It's hidden now.
+                return Ok(());
+            }
+            review_invite_bait(bot, message, database, &mut sent_by_admin).await?;
             gather_suspicion(bot, message, database).await?;
 
             if handle_command(bot, me, message, database, sent_by_admin).await? {
@@ -336,466 +706,4003 @@ async fn handle_message_inner(
     Ok(())
 }
 
-/// Handler to intuit suspicious links based on them being replied to.
-/// For example, if someone replies "spam" or "admin" to a message
-/// with links, then those links may be spam. Send them to the database lol
-async fn gather_suspicion(
-    bot: &Bot,
+/// If admins of this chat asked for it, and this is the first message of its sender
+/// since they joined this chat and has links, hide it until the sender presses a button
+/// to confirm they're human. Returns `true` if the message was hidden.
+///
+/// Messages with spam links don't get here, and are deleted as usual.
+async fn guard_first_link(
+    bot: &impl BotApi,
     message: &Message,
-    database: &Database,
-) -> Result<(), RequestError> {
-    let Some(text) = message.text() else {
-        return Ok(());
-    };
+    database: &Arc<Database>,
+    sent_by_admin: &mut Option<bool>,
+) -> Result<bool, RequestError> {
+    if !database
+        .get_first_link_guard(message.chat.id)
+        .await
+        .expect("Database died!")
+    {
+        return Ok(false);
+    }
 
-    let text = text.to_lowercase();
+    // Only those that join while this is enabled are guarded.
+    // Everyone else is verified the first time they're seen.
+    if let Some(members) = message.new_chat_members() {
+        for member in members.iter().filter(|x| !x.is_bot) {
+            database
+                .add_new_member(message.chat.id, member.id)
+                .await
+                .expect("Database died!");
+        }
+        return Ok(false);
+    }
 
-    // Old check that captured links in a wider net.
-    // Now our review queue is too big, so this is scaled
-    // down to only handling the explicit /spam and /scam commands.
+    // Anonymous admins and channels are sent by a chat instead.
+    let Some(user) = message.from() else {
+        return Ok(false);
+    };
+    if message.sender_chat().is_some() {
+        return Ok(false);
+    }
 
-    //if text.contains("spam")
-    //    || text.contains("scam")
-    //    || text.contains("admin")
-    //    || text.contains("begone")
-    if text.starts_with("/spam") | text.starts_with("/scam") {
-        // This or replied-to message may have sus links.
+    if database
+        .check_member_verified(message.chat.id, user.id)
+        .await
+        .expect("Database died!")
+    {
+        return Ok(false);
+    }
 
-        // First, check and skip if this is a reply to a post by
-        // the channel that is linked to this chat.
-        // This is worth doing because such messages are sanctioned by
-        // the chat's admins, and are most often just a misclick of
-        // the blue /spam command in comments to channel posts.
-        //
-        // The check for above is accomplished by is_sender_admin function,
-        // but there are other corner cases to consider too.
+    let links = get_message_links(message);
 
-        'reject_from_admin: {
-            let Some(reply_to) = message.reply_to_message() else {
-                // This isn't a reply to anything.
-                break 'reject_from_admin;
-            };
+    if links.is_empty() {
+        // A first message without links. They're fine.
+        database
+            .set_member_verified(message.chat.id, user.id)
+            .await
+            .expect("Database died!");
+        return Ok(false);
+    }
 
-            if message
-                .from()
-                .is_some_and(|x| Some(x.id) == reply_to.from().map(|x| x.id))
-                || message
-                    .sender_chat()
-                    .is_some_and(|x| Some(x.id) == reply_to.sender_chat().map(|x| x.id))
-            {
-                // The sender is replying to themselves and knows what they're doing.
-                break 'reject_from_admin;
-            }
+    if sent_by_admin.is_none() {
+        *sent_by_admin = Some(is_sender_admin(bot, message, database).await?);
+    }
+    if *sent_by_admin == Some(true) {
+        database
+            .set_member_verified(message.chat.id, user.id)
+            .await
+            .expect("Database died!");
+        return Ok(false);
+    }
 
-            let Ok(true) = is_sender_admin(bot, reply_to).await else {
-                // The sender of the replied-to message isn't an admin.
-                break 'reject_from_admin;
-            };
+    if bot
+        .delete_message(message.chat.id, message.id)
+        .await
+        .is_err()
+    {
+        // No rights, or it's already gone. Nothing to hide then.
+        return Ok(false);
+    }
 
-            let Ok(false) = is_sender_admin(bot, message).await else {
-                // The sender of this message *is* an admin.
-                break 'reject_from_admin;
-            };
+    // Media can't be posted again by the bot, so they'll have to send it again themselves.
+    let held = match message.text() {
+        Some(text) => defang_links(text, &message.parse_entities().unwrap_or_default()),
+        None => String::new(),
+    };
 
-            // The two checks above will return true for private chats without extra
-            // Telegram API queries.
+    let id = database
+        .add_quarantined_message(message.chat.id, user.id, &held)
+        .await
+        .expect("Database died!");
 
-            // This is an applicable situation.
-            let response = concat!(
-                "Sorry, but the message you're replying to is posted by an admin of this chat, ",
-                "so it is ignored. If you believe it should be marked, ",
-                "DM this bot with the command <code>/spam badlink.com</code> to submit it anyway."
-            );
-            bot.archsendmsg(message.chat.id, response, message.id)
-                .await?;
-            return Ok(());
+    let name = encode_text(&offending_user_name(message, false)).into_owned();
+    let (then, button) = if held.is_empty() {
+        ("to be able to send it again", "I'm human")
+    } else {
+        ("to post it again", "I'm human — repost my message")
+    };
+    let notice = bot
+        .send_message(
+            message.chat.id,
+            format!(
+                concat!(
+                    "Hid a message from <code>{}</code>, as it's their first one here ",
+                    "and it has links. If you're a human, press the button below ",
+                    "within {} minutes {}."
+                ),
+                name,
+                QUARANTINE_TIME.num_minutes(),
+                then
+            ),
+            MessageOptions::html().reply_markup(InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback(button.to_string(), format!("REPOST {}", id)),
+            ]])),
+        )
+        .await?;
+
+    // If nobody claims it in time, clean up.
+    let bot = bot.clone();
+    let database = database.clone();
+    let chatid = message.chat.id;
+    tokio::spawn(async move {
+        tokio::time::sleep(QUARANTINE_TIME.to_std().unwrap()).await;
+        if database
+            .forget_quarantined_message(id)
+            .await
+            .expect("Database died!")
+        {
+            let _ = bot.delete_message(chatid, notice.id).await;
         }
+    });
 
-        // Find and tag the sus links.
+    Ok(true)
+}
 
-        let mut had_links = false;
+/// Make links in this text unclickable, by replacing dots in them with `[.]`.
+fn defang_links(text: &str, entities: &[MessageEntityRef]) -> String {
+    let link_ranges: Vec<_> = entities
+        .iter()
+        .filter(|x| matches!(x.kind(), MessageEntityKind::Url))
+        .map(|x| x.range())
+        .collect();
 
-        let mut marked_count = 0u32;
-        let mut already_marked_sus_count = 0u32;
-        let mut already_marked_spam_count = 0u32;
-        let mut manually_reviewed_not_spam_count = 0u32;
+    let mut defanged = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        if c == '.' && link_ranges.iter().any(|x| x.contains(&i)) {
+            defanged.push_str("[.]");
+        } else {
+            defanged.push(c);
+        }
+    }
 
-        use std::fmt::Write;
-        let mut links_marked = String::new();
+    defanged
+}
 
-        macro_rules! marksus {
-            ($url: expr, $domain: expr) => {
-                log::debug!("Marking {} and its domain as sus...", $url);
+/// Handle a user pressing the button to post their message hidden by the first link guard
+/// again. `data` is the callback data with the "REPOST " prefix stripped.
+pub async fn handle_repost_query(
+    bot: &impl BotApi,
+    query: &CallbackQuery,
+    database: &Database,
+    data: &str,
+) -> Result<(), RequestError> {
+    macro_rules! goodbye {
+        ($text:expr) => {
+            bot.answer_callback_query(&query.id, $text).await?;
+            return Ok(());
+        };
+    }
 
-                had_links = true;
+    let Some(notice) = &query.message else {
+        goodbye!("This message is too old.");
+    };
+    let Ok(id) = data.parse::<i64>() else {
+        goodbye!("Invalid query data.");
+    };
 
-                let result = database
-                    .mark_sus($url, Some($domain))
-                    .await
-                    .expect("Database died!");
+    let Some(text) = database
+        .take_quarantined_message(id, query.from.id)
+        .await
+        .expect("Database died!")
+    else {
+        goodbye!("This is not your message, or it's too late to post it again.");
+    };
+
+    database
+        .set_member_verified(notice.chat.id, query.from.id)
+        .await
+        .expect("Database died!");
+
+    let name = encode_text(&query.from.full_name()).into_owned();
+    let text = if text.is_empty() {
+        // It had media, see [`guard_first_link`].
+        format!(
+            "<b>{}</b> is a human, and can send their message again.",
+            name
+        )
+    } else {
+        format!("<b>{}</b> said:\n{}", name, encode_text(&text))
+    };
+    bot.edit_message_text(
+        notice.chat.id,
+        notice.id,
+        text,
+        MessageOptions::html()
+            .disable_web_page_preview()
+            .reply_markup(InlineKeyboardMarkup::default()),
+    )
+    .await?;
+
+    goodbye!("Thanks! Your messages won't be hidden here anymore.");
+}
+
+/// Make a list of automatic spam checking heuristics, with buttons to turn each on or off.
+async fn describe_heuristics(database: &Database) -> (String, InlineKeyboardMarkup) {
+    let disabled = database
+        .get_disabled_heuristics()
+        .await
+        .expect("Database died!");
+
+    let mut text =
+        String::from("Automatic spam checking heuristics, in the order they're run:\n\n");
+    let mut buttons = Vec::with_capacity(HEURISTICS.len());
+
+    for heuristic in HEURISTICS {
+        let enabled = !disabled.contains(heuristic.name());
+        text.push_str(&format!(
+            "<b>{}</b> ({}): {}\n",
+            heuristic.name(),
+            if enabled { "on" } else { "off" },
+            encode_text(heuristic.description())
+        ));
+        buttons.push([InlineKeyboardButton::callback(
+            format!(
+                "{} {}",
+                if enabled { "Turn off" } else { "Turn on" },
+                heuristic.name()
+            ),
+            format!("HEURISTIC {}", heuristic.name()),
+        )]);
+    }
+
+    (text, InlineKeyboardMarkup::new(buttons))
+}
+
+/// Handle someone in the control chat pressing a button to turn a heuristic on or off.
+/// `data` is the callback data with the "HEURISTIC " prefix stripped.
+pub async fn handle_heuristic_query(
+    bot: &impl BotApi,
+    query: &CallbackQuery,
+    database: &Database,
+    data: &str,
+) -> Result<(), RequestError> {
+    macro_rules! goodbye {
+        ($text:expr) => {
+            bot.answer_callback_query(&query.id, $text).await?;
+            return Ok(());
+        };
+    }
+
+    if !reviews::authenticate_control(bot, &query.from).await? {
+        goodbye!("Access denied.");
+    }
+    let Some(heuristic) = HEURISTICS.iter().find(|x| x.name() == data) else {
+        goodbye!("Invalid query data.");
+    };
+
+    let was_enabled = !database
+        .get_disabled_heuristics()
+        .await
+        .expect("Database died!")
+        .contains(heuristic.name());
+    database
+        .set_heuristic_enabled(heuristic.name(), !was_enabled)
+        .await
+        .expect("Database died!");
+    log::info!(
+        "{} turned the {} heuristic {}.",
+        query.from.id,
+        heuristic.name(),
+        if was_enabled { "off" } else { "on" }
+    );
+
+    if let Some(message) = &query.message {
+        let (text, keyboard) = describe_heuristics(database).await;
+        bot.edit_message_text(
+            message.chat.id,
+            message.id,
+            text,
+            MessageOptions::html().reply_markup(keyboard),
+        )
+        .await?;
+    }
+
+    let response = format!(
+        "Turned {} {}.",
+        if was_enabled { "off" } else { "on" },
+        heuristic.name()
+    );
+    goodbye!(response);
+}
+
+/// Short description of a verdict on a link, with an emoji to tell them apart at a glance.
+fn describe_is_spam(is_spam: IsSpam) -> &'static str {
+    match is_spam {
+        IsSpam::No => "✅ not spam",
+        IsSpam::Unlikely => "✅ likely not spam",
+        IsSpam::Maybe => "⚠️ under review",
+        IsSpam::Likely => "⚠️ likely spam",
+        IsSpam::Yes => "⛔ spam",
+    }
+}
+
+/// Handle someone in the control chat confirming or cancelling `/mark_pattern_spam`.
+/// `data` is the callback data with the "PATTERN " prefix stripped.
+pub async fn handle_pattern_query(
+    bot: &impl BotApi,
+    query: &CallbackQuery,
+    database: &Database,
+    data: &str,
+) -> Result<(), RequestError> {
+    macro_rules! goodbye {
+        ($text:expr) => {
+            bot.answer_callback_query(&query.id, $text).await?;
+            return Ok(());
+        };
+    }
+
+    if !reviews::authenticate_control(bot, &query.from).await? {
+        goodbye!("Access denied.");
+    }
+
+    let text = if data == "CANCEL" {
+        "Cancelled.".to_string()
+    } else {
+        let Some(Ok(pattern)) = data.strip_prefix("SPAM ").map(HostPattern::parse) else {
+            goodbye!("Invalid query data.");
+        };
+
+        let added = database
+            .add_url_pattern(&pattern, query.from.id)
+            .await
+            .expect("Database died!");
+        if added {
+            log::info!("{} added the spam host pattern {}.", query.from.id, pattern);
+            format!(
+                "Marked every host matching <code>{}</code> as spam.",
+                pattern
+            )
+        } else {
+            format!("The pattern <code>{}</code> was already added.", pattern)
+        }
+    };
+
+    if let Some(message) = &query.message {
+        bot.edit_message_text(message.chat.id, message.id, &text, MessageOptions::html())
+            .await?;
+    }
+
+    goodbye!("");
+}
+
+/// Tell this chat and reviewers about a spam message that's left alone,
+/// as admins of this chat asked to only report spam with `/report_only`.
+async fn report_spam(
+    bot: &impl BotApi,
+    message: &Message,
+    link: Option<&Url>,
+) -> Result<(), RequestError> {
+    bot.archsendmsg(
+        message.chat.id,
+        concat!(
+            "This message contains a spam link. It was not deleted, ",
+            "as admins of this chat asked to only report spam.\n\n",
+            "Admins can send <code>/report_only off</code> to have spam deleted."
+        ),
+        message.id,
+    )
+    .await?;
+
+    let notice = format!(
+        "Spam from <code>{}</code> was left alone in {}, as it only reports spam:\n<code>{}</code>",
+        encode_text(&offending_user_name(message, true)),
+        describe_chat(&message.chat),
+        encode_text(link.map_or("?", Url::as_str))
+    );
+    for chat in BotConfig::get().review_notify_targets() {
+        // We don't care if this fails lmao
+        let _ = bot.archsendmsg(*chat, notice.as_str(), None).await;
+    }
+
+    Ok(())
+}
+
+/// Tell admins of the chat that a post from its linked channel contains spam
+/// and wasn't deleted. Only done once per chat, so as to not nag.
+async fn warn_about_channel_spam(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<(), RequestError> {
+    if !database
+        .should_warn_about_channel_spam(message.chat.id)
+        .await
+    {
+        return Ok(());
+    }
+
+    bot.archsendmsg(
+        message.chat.id,
+        concat!(
+            "This post from the channel linked to this chat contains a spam link. ",
+            "It was not deleted, as channel posts are made by the channel's admins.\n\n",
+            "If the channel keeps posting spam, it may have been compromised. ",
+            "Admins can send <code>/moderate_channel_posts on</code> ",
+            "to have such posts deleted too."
+        ),
+        message.id,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Text of this message to send to review along with links from it, see [`review_context`].
+///
+/// Forwarded messages also say who sent the original, as that's where forwarded spam
+/// comes from, see [`offending_user_name`].
+fn link_context(message: &Message) -> Option<String> {
+    let text = message.text().or_else(|| message.caption());
+    if message.forward_from().is_none() {
+        return text.map(str::to_string);
+    }
+
+    let origin = offending_user_name(message, true);
+    Some(match text {
+        Some(text) => format!("Forwarded from {}: {}", origin, text),
+        None => format!("Forwarded from {}", origin),
+    })
+}
+
+/// Make a string, either a @username or full name,
+/// describing the sender of this message.
+///
+/// If `prefer_forward_origin` is set and the message is forwarded, describe
+/// whoever sent the original message instead, as that's where forwarded spam comes from.
+fn offending_user_name(message: &Message, prefer_forward_origin: bool) -> String {
+    let (user, chat) = match message.forward_from() {
+        Some(ForwardedFrom::User(user)) if prefer_forward_origin => (Some(user), None),
+        Some(ForwardedFrom::Chat(chat)) if prefer_forward_origin => (None, Some(chat)),
+        Some(ForwardedFrom::SenderName(name)) if prefer_forward_origin => return name.clone(),
+        _ => (message.from(), message.sender_chat()),
+    };
+
+    if let Some(user) = user {
+        if let Some(username) = &user.username {
+            format!("@{}", username)
+        } else {
+            user.full_name()
+        }
+    } else if let Some(chat) = chat {
+        if let Some(username) = chat.username() {
+            format!("@{}", username)
+        } else if let Some(title) = chat.title() {
+            title.to_string()
+        } else {
+            // Shouldn't happen, but eh.
+            "a private user".to_string()
+        }
+    } else {
+        // Shouldn't happen either, but eh.
+        "a private user".to_string()
+    }
+}
 
+/// If this message is a part of an album and admins of this chat asked to delete
+/// whole albums, remember it in case another message of the album is spam.
+///
+/// Telegram sends messages of an album one by one, so some may arrive only after
+/// another one was already deleted for spam. Those are deleted right away,
+/// in which case this returns `true`.
+async fn handle_album_message(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<bool, RequestError> {
+    let Some(album_id) = message.media_group_id() else {
+        return Ok(false);
+    };
+
+    if !database
+        .get_delete_albums(message.chat.id)
+        .await
+        .expect("Database died!")
+    {
+        return Ok(false);
+    }
+
+    let last_deleted = database
+        .get_last_deleted_album_id(message.chat.id)
+        .await
+        .expect("Database died!");
+
+    if last_deleted.as_deref() == Some(album_id) {
+        log::debug!("Deleting a late message of spam album {}.", album_id);
+        // It's fine if it's already gone.
+        let _ = bot.delete_message(message.chat.id, message.id).await;
+        return Ok(true);
+    }
+
+    database
+        .record_album_message(message.chat.id, album_id, message.id)
+        .await
+        .expect("Database died!");
+
+    Ok(false)
+}
+
+/// Delete the rest of the album this spam message is a part of,
+/// if admins of this chat asked for that.
+async fn delete_rest_of_album(bot: &impl BotApi, message: &Message, database: &Database) {
+    let Some(album_id) = message.media_group_id() else {
+        return;
+    };
+
+    if !database
+        .get_delete_albums(message.chat.id)
+        .await
+        .expect("Database died!")
+    {
+        return;
+    }
+
+    // Set first, so that messages of this album that arrive
+    // while the others are being deleted are deleted too.
+    database
+        .set_last_deleted_album_id(message.chat.id, album_id)
+        .await
+        .expect("Database died!");
+
+    let siblings = database
+        .take_album_messages(message.chat.id, album_id)
+        .await
+        .expect("Database died!");
+
+    for sibling in siblings {
+        if sibling != message.id {
+            // It's fine if some are already gone.
+            let _ = bot.delete_message(message.chat.id, sibling).await;
+        }
+    }
+}
+
+/// Delete a message containing spam, along with the rest of its album if asked to,
+/// and notify the chat about it unless its admins asked not to.
+async fn delete_spam_message(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<(), RequestError> {
+    // Try up to 3 times in case a fail happens lol
+    for _ in 0..3 {
+        match bot.delete_message(message.chat.id, message.id).await {
+            Ok(_) => {
+                delete_rest_of_album(bot, message, database).await;
+                if !database
+                    .get_hide_deletes(message.chat.id)
+                    .await
+                    .expect("Database died!")
                 {
-                    use crate::types::MarkSusResult::*;
+                    notify_about_deletion(bot, message, database).await?;
+                }
+                break;
+            }
+            Err(RequestError::Api(
+                ApiError::MessageIdInvalid | ApiError::MessageToDeleteNotFound,
+            )) => {
+                // Someone else probably has already deleted it. That's fine.
+                // The rest of the album may still be there though.
+                delete_rest_of_album(bot, message, database).await;
+                break;
+            }
+            Err(RequestError::Api(ApiError::MessageCantBeDeleted)) => {
+                // No rights?
+                bot.archsendmsg(
+                    message.chat.id,
+                    concat!(
+                        "Tried to remove a message containing a spam link, but failed. ",
+                        "Is this bot an admin with ability to remove messages?\n\n",
+                        "If so, this may also be a Telegram bug, and an admin ",
+                        "has to remove the message manually."
+                    ),
+                    None,
+                )
+                .await?;
+                break;
+            }
+            Err(_) => {
+                // Random network error or whatever, possibly.
+                // Try again by letting the loop roll.
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a notification about deleting this spam message. If there was one sent recently,
+/// edit that one to count this deletion in instead, to not flood the chat during a raid.
+async fn notify_about_deletion(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<(), RequestError> {
+    let notice = database.deletion_notice(message.chat.id).await;
+    // Held until the end, so that concurrent deletions in this chat count up correctly.
+    let mut notice = notice.lock().await;
+
+    let name = encode_text(&offending_user_name(message, false)).into_owned();
+    let hint = if database
+        .get_appeal_hint(message.chat.id)
+        .await
+        .expect("Database died!")
+    {
+        APPEAL_HINT
+    } else {
+        ""
+    };
+
+    if let Some(previous) = notice.as_mut() {
+        if previous.updated_at.elapsed() < DELETION_NOTICE_COALESCE_TIME {
+            let count = previous.count + 1;
+            let edit_result = bot
+                .edit_message_text(
+                    message.chat.id,
+                    previous.message_id,
+                    format!(
+                        "Removed {} messages containing spam links (latest from <code>{}</code>).{}",
+                        count, name, hint
+                    ),
+                    MessageOptions::html(),
+                )
+                .await;
+
+            if edit_result.is_ok() {
+                previous.count = count;
+                previous.updated_at = Instant::now();
+                return Ok(());
+            }
+            // Couldn't edit it, maybe someone deleted it. Send a new one then.
+        }
+    }
+
+    let sent = bot
+        .archsendmsg(
+            message.chat.id,
+            format!(
+                "Removed a message from <code>{}</code> containing a spam link.{}",
+                name, hint
+            )
+            .as_str(),
+            None,
+        )
+        .await?;
+
+    *notice = sent.last().map(|sent| DeletionNotice {
+        message_id: sent.id,
+        updated_at: Instant::now(),
+        count: 1,
+    });
+
+    Ok(())
+}
+
+/// Tell the chat that this spam message will be deleted in `grace_period` seconds
+/// unless an admin cancels it, and delete it once that time passes.
+///
+/// Does nothing if the deletion of this message is already pending or was cancelled.
+async fn schedule_spam_deletion(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Arc<Database>,
+    grace_period: u32,
+) {
+    let bot = bot.clone();
+    let message_clone = message.clone();
+    let database_clone = database.clone();
+
+    database
+        .add_pending_deletion(message.chat.id, message.id, move || {
+            tokio::spawn(async move {
+                let result =
+                    spam_deletion_countdown(&bot, &message_clone, &database_clone, grace_period)
+                        .await;
+                if let Err(e) = result {
+                    log::error!("Failed deleting a spam message after a grace period: {}", e);
+                    // Don't keep it around as pending forever.
+                    database_clone
+                        .take_pending_deletion(message_clone.chat.id, message_clone.id)
+                        .await;
+                }
+            })
+            .abort_handle()
+        })
+        .await;
+}
+
+async fn spam_deletion_countdown(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+    grace_period: u32,
+) -> Result<(), RequestError> {
+    let notice = bot
+        .send_message(
+            message.chat.id,
+            format!(
+                concat!(
+                    "Deleting a message from <code>{}</code> suspected to contain a spam link ",
+                    "in {} seconds. Admins can press the button below to keep it."
+                ),
+                encode_text(&offending_user_name(message, false)),
+                grace_period
+            ),
+            MessageOptions::html()
+                .reply_to(message.id)
+                .reply_markup(InlineKeyboardMarkup::new([[
+                    InlineKeyboardButton::callback(
+                        "❌ Don't delete".to_string(),
+                        format!("CANCEL_DELETE {}", message.id.0),
+                    ),
+                ]])),
+        )
+        .await?;
+    database
+        .set_pending_deletion_notice(message.chat.id, message.id, notice.id)
+        .await;
+
+    tokio::time::sleep(Duration::from_secs(grace_period.into())).await;
+
+    if !database
+        .take_pending_deletion(message.chat.id, message.id)
+        .await
+    {
+        return Ok(());
+    }
+
+    // The notice is pointless now. It's fine if it's already gone.
+    let _ = bot.delete_message(message.chat.id, notice.id).await;
+
+    delete_spam_message(bot, message, database).await
+}
+
+/// Handle an admin pressing the button to cancel a scheduled deletion.
+/// `data` is the callback data with the "CANCEL_DELETE " prefix stripped.
+pub async fn handle_cancel_deletion_query(
+    bot: &impl BotApi,
+    query: &CallbackQuery,
+    database: &Database,
+    data: &str,
+) -> Result<(), RequestError> {
+    macro_rules! goodbye {
+        ($text:expr) => {
+            bot.answer_callback_query(&query.id, $text).await?;
+            return Ok(());
+        };
+    }
+
+    let Some(notice) = &query.message else {
+        goodbye!("This message is too old.");
+    };
+    let Ok(messageid) = data.parse::<i32>() else {
+        goodbye!("Invalid query data.");
+    };
+
+    let admin = database
+        .admin_cache()
+        .get_admin_of(bot, query.from.id, notice.chat.id)
+        .await?;
+    if admin.is_none() {
+        goodbye!("Only admins can do this.");
+    }
+
+    if database
+        .cancel_pending_deletion(notice.chat.id, MessageId(messageid))
+        .await
+        .is_none()
+    {
+        goodbye!("This message is not pending deletion anymore.");
+    }
+
+    mark_deletion_notice_kept(bot, notice.chat.id, notice.id, &query.from).await?;
+
+    if let Some(kept) = notice.reply_to_message() {
+        record_spam_overrides(bot, kept, database).await?;
+    }
+
+    goodbye!("Okay, not deleting it.");
+}
+
+/// Edit the notice about a pending deletion to say that `by` cancelled it,
+/// removing the button to cancel it.
+async fn mark_deletion_notice_kept(
+    bot: &impl BotApi,
+    chatid: ChatId,
+    notice: MessageId,
+    by: &User,
+) -> Result<(), RequestError> {
+    bot.edit_message_text(
+        chatid,
+        notice,
+        format!(
+            "Okay, not deleting this message, as asked by {}.",
+            encode_text(&by.full_name())
+        ),
+        MessageOptions::default().reply_markup(InlineKeyboardMarkup::default()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Admins kept this message instead of letting it be deleted. Count that against
+/// automatic spam designations of links in it, and tell the control chat about
+/// links that were sent back to review because of that.
+async fn record_spam_overrides(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<(), RequestError> {
+    for (url, domain) in get_message_links(message) {
+        // Only automatic designations can be wrong like that.
+        let Some(entry) = database
+            .automatic_spam_entry(&url, &domain)
+            .await
+            .expect("Database died!")
+        else {
+            continue;
+        };
+
+        if !database
+            .record_spam_override(&entry, message.chat.id)
+            .await
+            .expect("Database died!")
+        {
+            continue;
+        }
+
+        log::info!(
+            "Sent {} back to review after admins kept {}.",
+            entry.as_str(),
+            url
+        );
+
+        let notice = format!(
+            concat!(
+                "Admins of {} chats kept messages with links matching this, which was ",
+                "automatically marked as spam, so it was sent back to review. ",
+                "The spam checker is likely wrong about it:\n{}"
+            ),
+            OVERRIDES_BEFORE_REVIEW,
+            encode_text(entry.as_str())
+        );
+
+        for chat in BotConfig::get().review_notify_targets() {
+            // We don't care if this fails lmao
+            let _ = bot.archsendmsg(*chat, notice.as_str(), None).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handler to intuit suspicious links based on them being replied to.
+/// For example, if someone replies "spam" or "admin" to a message
+/// with links, then those links may be spam. Send them to the database lol
+async fn gather_suspicion(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+) -> Result<(), RequestError> {
+    let Some(text) = message.text() else {
+        return Ok(());
+    };
+
+    let text = text.to_lowercase();
+
+    // Old check that captured links in a wider net.
+    // Now our review queue is too big, so this is scaled
+    // down to only handling the explicit /spam and /scam commands.
+
+    //if text.contains("spam")
+    //    || text.contains("scam")
+    //    || text.contains("admin")
+    //    || text.contains("begone")
+    if text.starts_with("/spam") | text.starts_with("/scam") {
+        // This or replied-to message may have sus links.
+
+        // First, check and skip if this is a reply to a post by
+        // the channel that is linked to this chat.
+        // This is worth doing because such messages are sanctioned by
+        // the chat's admins, and are most often just a misclick of
+        // the blue /spam command in comments to channel posts.
+        //
+        // The check for above is accomplished by is_sender_admin function,
+        // but there are other corner cases to consider too.
+
+        'reject_from_admin: {
+            let Some(reply_to) = message.reply_to_message() else {
+                // This isn't a reply to anything.
+                break 'reject_from_admin;
+            };
+
+            if message
+                .from()
+                .is_some_and(|x| Some(x.id) == reply_to.from().map(|x| x.id))
+                || message
+                    .sender_chat()
+                    .is_some_and(|x| Some(x.id) == reply_to.sender_chat().map(|x| x.id))
+            {
+                // The sender is replying to themselves and knows what they're doing.
+                break 'reject_from_admin;
+            }
+
+            let Ok(true) = is_sender_admin(bot, reply_to, database).await else {
+                // The sender of the replied-to message isn't an admin.
+                break 'reject_from_admin;
+            };
+
+            let Ok(false) = is_sender_admin(bot, message, database).await else {
+                // The sender of this message *is* an admin.
+                break 'reject_from_admin;
+            };
+
+            // The two checks above will return true for private chats without extra
+            // Telegram API queries.
+
+            // This is an applicable situation.
+            let response = concat!(
+                "Sorry, but the message you're replying to is posted by an admin of this chat, ",
+                "so it is ignored. If you believe it should be marked, ",
+                "DM this bot with the command <code>/spam badlink.com</code> to submit it anyway."
+            );
+            bot.archsendmsg(message.chat.id, response, message.id)
+                .await?;
+            return Ok(());
+        }
+
+        // Find and tag the sus links.
+
+        // Admins can ask for a per-link breakdown with "/spam verbose".
+        let verbose = text.split_whitespace().nth(1) == Some("verbose")
+            && is_sender_admin(bot, message, database).await?;
+
+        let mut had_links = false;
+
+        let mut marked_count = 0u32;
+        let mut already_marked_sus_count = 0u32;
+        let mut already_marked_spam_count = 0u32;
+        let mut manually_reviewed_not_spam_count = 0u32;
+
+        use std::fmt::Write;
+        let mut links_marked = String::new();
+        let mut links_detail = String::new();
+        // Text of the message the first marked link is from, to show to reviewers.
+        let mut marked_context: Option<String> = None;
+        // Usernames of inline bots the replied-to messages were sent via, see
+        // [`get_via_bot_url_domain`]. Reviewers get a button to mark those as spam as a whole.
+        let mut via_bots: Vec<String> = Vec::new();
+
+        macro_rules! marksus {
+            ($url: expr, $domain: expr, $context: expr) => {
+                log::debug!("Marking {} and its domain as sus...", $url);
+
+                had_links = true;
+
+                let result = database
+                    .mark_sus($url, Some($domain), $context)
+                    .await
+                    .expect("Database died!");
+
+                let _ = writeln!(
+                    links_detail,
+                    "<code>{}</code>: {}",
+                    encode_text($url.as_str()),
+                    result
+                );
+
+                {
+                    use crate::types::MarkSusResult::*;
+
+                    match result {
+                        Marked => {
+                            let _ = writeln!(links_marked, "<code>{}</code>", $url);
+                            marked_count += 1;
+                            if marked_context.is_none() {
+                                marked_context = $context.and_then(review_context);
+                            }
+                        }
+                        AlreadyMarkedSus => already_marked_sus_count += 1,
+                        AlreadyMarkedSpam => already_marked_spam_count += 1,
+                        ManuallyReviewedNotSpam => manually_reviewed_not_spam_count += 1,
+                    }
+                }
+            };
+        }
+
+        // Domains of links Telegram did see in a message, to not mark them again
+        // when also recovering obfuscated links from its text.
+        let mut entity_domains: Vec<Domain> = Vec::new();
+
+        if let Some(entities) = message
+            .parse_entities()
+            .or_else(|| message.parse_caption_entities())
+        {
+            for entity in &entities {
+                let Some((url, domain)) = get_entity_url_domain(entity) else {
+                    continue;
+                };
+
+                // The /spam command itself is no context for reviewers.
+                marksus!(&url, &domain, None);
+                entity_domains.push(domain);
+            }
+        };
+
+        // People may also write the link out in a way that Telegram doesn't see, to not
+        // make it clickable. Only done here, as this can find links where there are none.
+        for (url, domain) in recovered_url_domains(message.text(), &entity_domains) {
+            marksus!(&url, &domain, None);
+        }
+
+        // Get replied-to message "entities", if any.
+        // See `suspicion_reply_chain` for which messages are considered.
+        for replied_message in suspicion_reply_chain(message) {
+            let text = replied_message.text().or_else(|| replied_message.caption());
+            let context = link_context(replied_message);
+            let context = context.as_deref();
+            entity_domains.clear();
+
+            if let Some(replied_entities) = replied_message
+                .parse_entities()
+                .or_else(|| replied_message.parse_caption_entities())
+            {
+                for entity in &replied_entities {
+                    let Some((url, domain)) = get_entity_url_domain(entity) else {
+                        continue;
+                    };
+
+                    marksus!(&url, &domain, context);
+                    entity_domains.push(domain);
+                }
+            }
+
+            for (url, domain) in recovered_url_domains(text, &entity_domains) {
+                marksus!(&url, &domain, context);
+            }
+
+            // While we're here, check for links in buttons on the replied-to message.
+            if let Some(markup) = replied_message.reply_markup() {
+                for row in &markup.inline_keyboard {
+                    for button in row {
+                        let Some((url, domain)) = get_button_url_domain(button) else {
+                            continue;
+                        };
+                        marksus!(url, &domain, context);
+                    }
+                }
+            }
+
+            if let Some((url, domain)) = get_via_bot_url_domain(replied_message) {
+                marksus!(&url, &domain, context);
+                if let Some(username) = replied_message
+                    .via_bot
+                    .as_ref()
+                    .and_then(|x| x.username.clone())
+                {
+                    via_bots.push(username);
+                }
+            }
+        }
+
+        // We assume there would be no buttons on the /spam message we're
+        // working for that we need to check. That'd be kind of ridiculous lol
+
+        let mut response;
+        let marked = marked_count > 0;
+        let already_marked_sus = already_marked_sus_count > 0;
+        let already_marked_spam = already_marked_spam_count > 0;
+        let manually_reviewed_not_spam = manually_reviewed_not_spam_count > 0;
+
+        //if text.starts_with("/spam") | text.starts_with("/scam")
+        // This condition is moved to the top of the function now.
+        {
+            // That's the bot command, most likely. Users like indication that it does things.
+
+            // Purposefully ambiguous message wording, where "this" both refers to the
+            // message we're replying to and the message they replied to lol
+
+            let response = if marked {
+                "Thank you, links in this message will be reviewed for spam."
+            } else if had_links {
+                // Didn't mark anything as sus, but the message had links.
+
+                response = String::from("Thank you, but the links in this message are ");
+
+                if already_marked_sus {
+                    response.push_str("already marked for review");
+                    if already_marked_spam | manually_reviewed_not_spam {
+                        response.push_str(", and some are ");
+                    }
+                }
+
+                if already_marked_spam {
+                    response.push_str("already marked for spam");
+                    if manually_reviewed_not_spam {
+                        response.push_str(", and some are ");
+                    }
+                }
+
+                if manually_reviewed_not_spam {
+                    response.push_str("manually reviewed and were determined to be not spam");
+                }
+
+                response.push('.');
+
+                response.as_str()
+            } else {
+                // No links at all.
+                "Sorry, but I could not find any links."
+            };
+
+            if verbose && had_links {
+                let response = format!("{}\n\n{}", response, links_detail);
+                bot.archsendmsg(message.chat.id, response.as_str(), message.id)
+                    .await?;
+            } else {
+                bot.archsendmsg(message.chat.id, response, message.id)
+                    .await?;
+            }
+        }
+
+        if marked {
+            // We marked something. In this case, notify reviewers to review.
+            let username = if let Some(user) = message.from() {
+                if let Some(username) = &user.username {
+                    format!("@{} (userid <code>{}</code>)", username, user.id)
+                } else {
+                    format!("{} (userid <code>{}</code>)", user.full_name(), user.id)
+                }
+            } else {
+                "Anonymous".to_string()
+            };
+
+            let notice = format!(
+                "New link(s) were added to review pool by {} in {}:\n{}{}",
+                username,
+                describe_chat(&message.chat),
+                links_marked,
+                context_line(marked_context.as_deref())
+            );
+            notify_reviewers(bot, database, &notice, via_bots).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Mark every link to this inline bot, and so every message sent via it, as spam,
+/// as a review from this user. Returns [`None`] if it's not a bot, or a trusted one.
+/// See [`crate::spam_checker::inline_bot_url`].
+async fn mark_via_bot_spam(
+    bot: &impl BotApi,
+    user: &User,
+    database: &Database,
+    username: &str,
+    reason: &str,
+) -> Result<Option<Url>, RequestError> {
+    let Some(url) = crate::spam_checker::inline_bot_url(username) else {
+        return Ok(None);
+    };
+
+    let action = ReviewResponse::BotSpam(Domain::from_url(&url), url.clone());
+    reviews::apply_review_unverified(bot, user, database, &action, reason).await?;
+    Ok(Some(url))
+}
+
+/// Handle a reviewer pressing a button sent by [`review_notify`] for an inline bot
+/// that a message sent to review was sent via.
+/// `data` is the callback data with the "VIABOT " prefix stripped.
+pub async fn handle_via_bot_query(
+    bot: &impl BotApi,
+    query: &CallbackQuery,
+    database: &Database,
+    data: &str,
+) -> Result<(), RequestError> {
+    macro_rules! goodbye {
+        ($text:expr) => {
+            bot.answer_callback_query(&query.id, $text).await?;
+            return Ok(());
+        };
+    }
+
+    if !reviews::authenticate_control(bot, &query.from).await? {
+        goodbye!("Access denied.");
+    }
+
+    let Some(username) = data.strip_prefix("SPAM ") else {
+        goodbye!("Invalid query data.");
+    };
+
+    let Some(url) = mark_via_bot_spam(bot, &query.from, database, username, "").await? else {
+        goodbye!("This is not a bot that can be marked.");
+    };
+    log::info!("{} marked the inline bot {} as spam.", query.from.id, url);
+
+    bot.answer_callback_query(
+        &query.id,
+        format!("Marked every message sent via @{} as spam.", username),
+    )
+    .await?;
+
+    // The notice may be about other links and bots too, so only take away this button.
+    if let Some(message) = &query.message {
+        let data = format!("VIABOT SPAM {}", username);
+        let is_pressed = |button: &InlineKeyboardButton| match &button.kind {
+            InlineKeyboardButtonKind::CallbackData(x) => *x == data,
+            _ => false,
+        };
+        let keyboard: Vec<Vec<InlineKeyboardButton>> = (message.reply_markup().into_iter())
+            .flat_map(|x| &x.inline_keyboard)
+            .map(|row| {
+                row.iter()
+                    .filter(|x| !is_pressed(x))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .filter(|row| !row.is_empty())
+            .collect();
+        let keyboard = (!keyboard.is_empty()).then(|| InlineKeyboardMarkup::new(keyboard));
+
+        bot.edit_message_reply_markup(message.chat.id, message.id, keyboard)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Describe this chat for reviewers, with its username or title and ID.
+fn describe_chat(chat: &Chat) -> String {
+    if let Some(username) = chat.username() {
+        format!("@{} (chatid <code>{}</code>)", username, chat.id)
+    } else if let Some(title) = chat.title() {
+        format!("{} (chatid <code>{}</code>)", title, chat.id)
+    } else {
+        format!("Unknown (chatid <code>{}</code>)", chat.id)
+    }
+}
+
+/// Format context stored with a link, see [`review_context`], as a line to show reviewers.
+/// Empty if there's no context.
+fn context_line(context: Option<&str>) -> String {
+    match context {
+        Some(context) => format!("Context: <i>{}</i>\n", encode_text(context)),
+        None => String::new(),
+    }
+}
+
+/// Tell reviewers that new links were added to the review pool, with this explanation
+/// of how, followed by how many links there are to review now.
+///
+/// Sent along with others that come shortly after, see [`review_notify`].
+/// `via_bots` are usernames of inline bots the messages with the links were sent via.
+async fn notify_reviewers(
+    bot: &impl BotApi,
+    database: &Database,
+    notice: &str,
+    via_bots: Vec<String>,
+) {
+    let to_review = database.get_review_count().await.expect("Database died!");
+    // Should always be true, considering callers have just added links, but eh.
+    if to_review == 0 {
+        return;
+    }
+
+    let notice = ReviewNotice {
+        text: notice.to_string(),
+        to_review,
+        via_bots,
+    };
+    review_notify::queue(bot, database, notice).await;
+}
+
+/// Send Telegram invite links in this message to review if the message looks like
+/// bait for them, see [`crate::spam_checker::check_invite_bait`].
+///
+/// Never done for messages from admins or in control chats. `sent_by_admin` is used
+/// if already known, and filled in if it had to be found out.
+async fn review_invite_bait(
+    bot: &impl BotApi,
+    message: &Message,
+    database: &Database,
+    sent_by_admin: &mut Option<bool>,
+) -> Result<(), RequestError> {
+    if BotConfig::get().is_control_chat(message.chat.id) {
+        return Ok(());
+    }
+
+    let Some(text) = message.text().or_else(|| message.caption()) else {
+        return Ok(());
+    };
+
+    let invite_links: Vec<Url> = get_message_links(message)
+        .into_iter()
+        .map(|(url, _)| url)
+        .filter(crate::spam_checker::is_invite_link)
+        .collect();
+
+    if invite_links.is_empty() {
+        return Ok(());
+    }
+
+    let entities = message
+        .parse_entities()
+        .or_else(|| message.parse_caption_entities())
+        .unwrap_or_default();
+
+    let Some(trigger) = crate::spam_checker::check_invite_bait(text, &entities) else {
+        return Ok(());
+    };
+
+    // Checked last, as it may take a request to Telegram.
+    let is_admin = match *sent_by_admin {
+        Some(is_admin) => is_admin,
+        None => *sent_by_admin.insert(is_sender_admin(bot, message, database).await?),
+    };
+    if is_admin {
+        return Ok(());
+    }
+
+    log::info!(
+        "Message {} in chat {} looks like invite link bait: {}",
+        message.id,
+        message.chat.id,
+        trigger
+    );
+
+    use std::fmt::Write;
+    let mut links_marked = String::new();
+    let context = link_context(message);
+
+    for url in &invite_links {
+        let marked = database
+            .mark_url_sus(url, Some(MatchReason::InviteLinkBait), context.as_deref())
+            .await
+            .expect("Database died!");
+
+        if marked {
+            let _ = writeln!(links_marked, "<code>{}</code>", encode_text(url.as_str()));
+        }
+    }
+
+    if !links_marked.is_empty() {
+        let notice = format!(
+            "New link(s) were automatically added to review pool for {} in {}:\n{}{}",
+            trigger,
+            describe_chat(&message.chat),
+            links_marked,
+            context_line(context.as_deref().and_then(review_context).as_deref())
+        );
+        notify_reviewers(bot, database, &notice, Vec::new()).await;
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if a command was parsed and responded to.
+async fn handle_command(
+    bot: &impl BotApi,
+    me: &Me,
+    message: &Message,
+    database: &Arc<Database>,
+    mut sent_by_admin: Option<bool>,
+) -> Result<bool, RequestError> {
+    if message.edit_date().is_some() {
+        // Ignore message edits here.
+        return Ok(false);
+    }
+
+    macro_rules! byadmin {
+        () => {{
+            if sent_by_admin.is_none() {
+                sent_by_admin = Some(is_sender_admin(bot, message, database).await?);
+            }
+            sent_by_admin.unwrap()
+        }};
+    }
+    macro_rules! respond {
+        ($text:expr) => {
+            bot.archsendmsg(message.chat.id, $text, message.id).await?;
+        };
+    }
+
+    macro_rules! goodbye {
+        ($text:expr) => {{
+            respond!($text);
+            return Ok(true);
+        }};
+    }
+    let is_private = message.chat.is_private();
+
+    let Some(text) = message.text() else {
+        return Ok(false);
+    };
+    let Some((command, params)) = strip_command(text, me) else {
+        return Ok(false);
+    };
+
+    let command_processed: bool = match command.as_str() {
+        "/review" if is_private => handle_review_command(bot, message, database).await?,
+        "/spam" | "/scam" if is_private => {
+            // This is a private messages only handler. This is already run for public messages
+            // differently, to catch non-command suspicions, so running it here would run it twice.
+            gather_suspicion(bot, message, database).await?;
+            true
+        }
+        "/hide_deletes" | "/show_deletes" => {
+            if message.chat.is_private() || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = command.as_str() == "/hide_deletes";
+
+            let old_state = database
+                .set_hide_deletes(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat doesn't hide spam deletion notifications already.",
+                (false, true) => concat!(
+                    "I will no longer notify about messages being deleted. ",
+                    "Note that this may lead to confusion in case I delete a ",
+                    "message with a legitimate link due to a false positive. "
+                ),
+                (true, false) => "From now on I will notify about spam messages being deleted.",
+                (true, true) => "This chat has spam delete notifications hidden already.",
+            };
+
+            goodbye!(response);
+        }
+        "/moderate_channel_posts" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_moderate_channel_posts(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Deleting spam in posts from the channel linked to this chat is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_moderate_channel_posts(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "Posts from the linked channel are not moderated already.",
+                (false, true) => concat!(
+                    "From now on I will delete spam in posts from the channel ",
+                    "linked to this chat too."
+                ),
+                (true, false) => concat!(
+                    "I will no longer delete posts from the channel linked to this chat, ",
+                    "and will only warn about spam in them once."
+                ),
+                (true, true) => "Posts from the linked channel are moderated already.",
+            };
+
+            goodbye!(response);
+        }
+        "/delete_albums" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_delete_albums(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Deleting whole albums when a message in one contains spam is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_delete_albums(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat doesn't delete whole albums already.",
+                (false, true) => concat!(
+                    "From now on, if a message in an album contains spam, ",
+                    "I will delete the whole album."
+                ),
+                (true, false) => "I will no longer delete whole albums, only messages with spam.",
+                (true, true) => "This chat deletes whole albums already.",
+            };
+
+            goodbye!(response);
+        }
+        "/first_link_guard" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_first_link_guard(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Hiding first messages of users if they have links is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_first_link_guard(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat doesn't hide first messages with links already.",
+                (false, true) => concat!(
+                    "From now on, if the first message of someone who joins here has links, ",
+                    "I will hide it until they press a button to confirm they're human."
+                ),
+                (true, false) => "I will no longer hide first messages with links.",
+                (true, true) => "This chat hides first messages with links already.",
+            };
+
+            goodbye!(response);
+        }
+        "/backfill" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_backfill(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Catching up on messages sent while I was down is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_backfill(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat doesn't catch up on missed messages already.",
+                (false, true) => concat!(
+                    "From now on, if I'm down for a while, I will quietly check messages ",
+                    "sent meanwhile once I'm back, and delete the spam among them. ",
+                    "Telegram only keeps those for a day."
+                ),
+                (true, false) => "I will no longer catch up on messages sent while I was down.",
+                (true, true) => "This chat catches up on missed messages already.",
+            };
+
+            goodbye!(response);
+        }
+        "/report_only" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_report_only(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Only reporting spam without deleting it is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_report_only(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat has spam deleted already.",
+                (false, true) => concat!(
+                    "From now on, I will only point out spam messages here ",
+                    "and tell my reviewers about them, without deleting them."
+                ),
+                (true, false) => "From now on I will delete spam messages.",
+                (true, true) => "This chat only has spam reported already.",
+            };
+
+            goodbye!(response);
+        }
+        "/strict_mode" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_strict_mode(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Deleting links that are only likely spam is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_strict_mode(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat only has links that are surely spam deleted already.",
+                (false, true) => concat!(
+                    "From now on, I will also delete messages with links that my ",
+                    "reviewers think are likely spam, but aren't sure about."
+                ),
+                (true, false) => concat!(
+                    "From now on, I will only delete messages with links ",
+                    "that are surely spam."
+                ),
+                (true, true) => "This chat has likely spam deleted already.",
+            };
+
+            goodbye!(response);
+        }
+        "/show_appeal_hint" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let new_state = match params.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    let current = database
+                        .get_appeal_hint(message.chat.id)
+                        .await
+                        .expect("Database died!");
+                    goodbye!(format!(
+                        concat!(
+                            "Saying what to do about a mistaken deletion is {}.\n\n",
+                            "Specify <code>on</code> or <code>off</code> to change that."
+                        ),
+                        if current { "on" } else { "off" }
+                    )
+                    .as_str());
+                }
+            };
+
+            let old_state = database
+                .set_appeal_hint(message.chat.id, new_state)
+                .await
+                .expect("Database died!");
+            let hidden = database
+                .get_hide_deletes(message.chat.id)
+                .await
+                .expect("Database died!");
+
+            let response = match (old_state, new_state) {
+                (false, false) => "This chat has deletion notifications kept short already.",
+                (false, true) => concat!(
+                    "From now on, deletion notifications will say what to do ",
+                    "if a message was deleted by mistake."
+                ),
+                (true, false) => "From now on, deletion notifications will be kept short.",
+                (true, true) => "This chat has deletion notifications saying that already.",
+            };
+
+            if new_state && hidden {
+                goodbye!(format!(
+                    "{}\n\nNote that deletion notifications are hidden in this chat. \
+                    Use /show_deletes to see them.",
+                    response
+                )
+                .as_str());
+            }
+            goodbye!(response);
+        }
+        "/grace_period" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+
+            let Ok(seconds) = params.parse::<u32>() else {
+                let current = database
+                    .get_grace_period(message.chat.id)
+                    .await
+                    .expect("Database died!");
+                goodbye!(format!(
+                    concat!(
+                        "Current grace period is {} seconds.\n\n",
+                        "Specify a number of seconds, up to {}, to wait before deleting spam, ",
+                        "giving admins a chance to cancel it. 0 means deleting immediately."
+                    ),
+                    current, MAX_GRACE_PERIOD
+                )
+                .as_str());
+            };
+
+            if seconds > MAX_GRACE_PERIOD {
+                goodbye!(format!(
+                    "The grace period can be at most {} seconds.",
+                    MAX_GRACE_PERIOD
+                )
+                .as_str());
+            }
+
+            database
+                .set_grace_period(message.chat.id, seconds)
+                .await
+                .expect("Database died!");
+
+            if seconds == 0 {
+                goodbye!("From now on I will delete spam messages immediately.");
+            } else {
+                goodbye!(format!(
+                    concat!(
+                        "From now on I will wait {} seconds before deleting spam messages. ",
+                        "During that time, admins can cancel the deletion with a button, ",
+                        "or by replying /keep to the message."
+                    ),
+                    seconds
+                )
+                .as_str());
+            }
+        }
+        "/keep" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+            let Some(reply_to) = message.reply_to_message() else {
+                goodbye!("Reply to a message pending deletion with this command to keep it.");
+            };
+
+            let Some(notice) = database
+                .cancel_pending_deletion(message.chat.id, reply_to.id)
+                .await
+            else {
+                goodbye!("That message is not pending deletion.");
+            };
+
+            if let (Some(notice), Some(sender)) = (notice, message.from()) {
+                // It's fine if the notice is gone.
+                let _ = mark_deletion_notice_kept(bot, message.chat.id, notice, sender).await;
+            }
+            record_spam_overrides(bot, reply_to, database).await?;
+            goodbye!("Okay, not deleting that message.");
+        }
+        "/refresh_admins" => {
+            // Someone who was just promoted is not an admin as far as the bot remembers,
+            // so check the sender again first.
+            if let Some(sender) = message.from() {
+                database
+                    .admin_cache()
+                    .invalidate(message.chat.id, sender.id);
+            }
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+            database.admin_cache().invalidate_chat(message.chat.id);
+            goodbye!("Okay, I will check again who the admins of this chat are.");
+        }
+        "/rescan" => {
+            if is_private || !byadmin!() {
+                goodbye!("This command can only be used by admins in group chats.");
+            }
+            let Some(reply_to) = message.reply_to_message() else {
+                goodbye!("Reply to a message with this command to check it for spam again.");
+            };
+
+            // Handled as if it was just sent, so that its album is dealt with too, but
+            // quietly like a replayed one, so that an old message isn't hidden or responded to.
+            // It being spam is announced as usual when its reply is handled after this.
+            // Boxed, as this is called from that same function.
+            Box::pin(handle_message_inner(
+                bot, me, reply_to, database, false, true,
+            ))
+            .await?;
+
+            let links = get_message_links(reply_to);
+            if links.is_empty() {
+                goodbye!("Sorry, but I could not find any links.");
+            }
+
+            let strict_mode = database
+                .get_strict_mode(message.chat.id)
+                .await
+                .expect("Database died!");
+
+            let mut has_spam = false;
+            for (url, domain) in &links {
+                let verdict = database
+                    .is_spam(url, Some(domain), false)
+                    .await
+                    .expect("Database died!");
+                if verdict.is_some_and(|x| x.0.should_delete(strict_mode)) {
+                    has_spam = true;
+                    break;
+                }
+            }
+
+            if has_spam {
+                goodbye!("Checked that message again, and it has a spam link.");
+            } else {
+                goodbye!("Checked that message again, and found no spam links in it.");
+            }
+        }
+        "/check" => {
+            // In private chats, links are taken from the command message itself.
+            // In groups, this is admin-only and checks the replied-to message.
+            let target = if is_private {
+                message
+            } else {
+                if !byadmin!() {
+                    goodbye!("This command can only be used by admins.");
+                }
+                let Some(reply_to) = message.reply_to_message() else {
+                    goodbye!("Reply to a message with this command to check links in it.");
+                };
+                reply_to
+            };
+
+            let links = get_message_links(target);
+
+            if links.is_empty() {
+                if is_private {
+                    goodbye!("Please specify links to check.");
+                } else {
+                    goodbye!("Sorry, but I could not find any links.");
+                }
+            }
+
+            use std::fmt::Write;
+            let mut response = String::new();
+
+            for (url, domain) in &links {
+                // Only look at what the database already knows. Unknown links are
+                // intentionally not sent to review from here, nor visited.
+                let (verdict, pattern) = database
+                    .is_spam_with_pattern(url, Some(domain), true)
+                    .await
+                    .expect("Database died!");
+
+                let verdict = match verdict {
+                    Some((is_spam, _)) => describe_is_spam(is_spam),
+                    None => "❔ unknown",
+                };
+
+                let reason = database
+                    .get_match_reason(url, domain)
+                    .await
+                    .expect("Database died!");
+
+                let _ = write!(
+                    response,
+                    "{}: <code>{}</code>",
+                    verdict,
+                    encode_text(url.as_str())
+                );
+                if let Some(pattern) = pattern {
+                    let _ = write!(response, " (by pattern <code>{}</code>)", pattern);
+                } else if let Some(reason) = reason {
+                    let _ = write!(response, " (automatically, by {})", reason);
+                }
+                response.push('\n');
+            }
+
+            goodbye!(response.as_str());
+        }
+        "/explain" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            // Either explain a replied-to (likely forwarded) message, or links in this one.
+            let target = message.reply_to_message().unwrap_or(message);
+            let links = get_message_links(target);
+
+            if links.is_empty() {
+                goodbye!(concat!(
+                    "Please specify links, or reply with this command ",
+                    "to a message forwarded from a chat."
+                ));
+            }
+
+            use std::fmt::Write;
+            let mut response = String::new();
+            let mut any_spam = false;
+
+            for (url, domain) in &links {
+                let db_result = database
+                    .is_spam(url, Some(domain), false)
+                    .await
+                    .expect("Database died!");
+
+                let known = match db_result {
+                    Some((is_spam, true)) => format!("manually reviewed as {:?}", is_spam),
+                    Some((is_spam, false)) => format!("in database as {:?}", is_spam),
+                    None => "not in database".to_string(),
+                };
+
+                // Checking stops at the first spam link, so mirror that.
+                let decision = if any_spam {
+                    "not checked, an earlier link is spam".to_string()
+                } else {
+                    // Only going by what's known, as this shouldn't change anything.
+                    match crate::spam_checker::check_read_only(database, domain, url).await {
+                        Some((IsSpam::Yes, reason)) => {
+                            any_spam = true;
+                            format!("marked spam by {} → would delete", reason)
+                        }
+                        Some((IsSpam::Maybe, reason)) => {
+                            format!("maybe spam by {} → sent to review, not deleted", reason)
+                        }
+                        Some((IsSpam::Likely, reason)) => format!(
+                            "likely spam by {} → would delete only in chats with strict mode",
+                            reason
+                        ),
+                        Some((IsSpam::No | IsSpam::Unlikely, reason)) => {
+                            format!("not spam by {} → ignored", reason)
+                        }
+                        None => "not known yet → would be checked by visiting it".to_string(),
+                    }
+                };
+
+                let _ = writeln!(
+                    response,
+                    "<code>{}</code>\ndomain <code>{}</code>, {}: {}\n",
+                    encode_text(url.as_str()),
+                    encode_text(&domain.to_string()),
+                    known,
+                    decision
+                );
+            }
+
+            if any_spam {
+                response.push_str(concat!(
+                    "In a group, this message would be deleted, unless it was sent by an admin, ",
+                    "an anonymous admin or the channel linked to that chat ",
+                    "(admin sender → skipped)."
+                ));
+            } else {
+                response.push_str("In a group, this message would not be deleted.");
+            }
+
+            goodbye!(response.as_str());
+        }
+        "/sanitize" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let Some(entities) = message.parse_entities() else {
+                goodbye!("Please specify links.");
+            };
+
+            let mut response = String::new();
+
+            for entity in &entities {
+                let Some((url, domain)) = get_entity_url_domain(entity) else {
+                    continue;
+                };
+                let original = match entity.kind() {
+                    MessageEntityKind::TextLink { url } => url.as_str(),
+                    _ => entity.text(),
+                };
+
+                response.push_str(&describe_link(original, &url, &domain));
+                response.push('\n');
+            }
+
+            if response.is_empty() {
+                goodbye!("Please specify links.");
+            }
+
+            goodbye!(response.as_str());
+        }
+        "/search" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let Some(host_part) = params.split_whitespace().next() else {
+                goodbye!(concat!(
+                    "Please specify a part of a host to look for, like ",
+                    "<code>/search .ru</code> or <code>/search claim-nft</code>."
+                ));
+            };
+
+            let found = database
+                .search_urls(host_part, SEARCH_RESULTS)
+                .await
+                .expect("Database died!");
+
+            if found.is_empty() {
+                goodbye!(format!(
+                    "No URLs with <code>{}</code> in their host.",
+                    encode_text(host_part)
+                )
+                .as_str());
+            }
+
+            use std::fmt::Write;
+            let mut response = format!(
+                "Latest {} URLs with <code>{}</code> in their host:\n\n",
+                found.len(),
+                encode_text(host_part)
+            );
+            for stored in found {
+                let _ = write!(
+                    response,
+                    "{}: <code>{}</code>",
+                    describe_is_spam(stored.is_spam),
+                    encode_text(&stored.url)
+                );
+                if stored.manually_reviewed {
+                    response.push_str(" (manually reviewed)");
+                } else if let Some(reason) = stored.match_reason {
+                    let _ = write!(response, " (automatically, by {})", reason);
+                }
+                response.push('\n');
+            }
+
+            goodbye!(response.as_str());
+        }
+        "/prune_reviews" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let pruned = database
+                .prune_stale_review_entries()
+                .await
+                .expect("Database died!");
+            log::info!("{} pruned {} stale review entries.", sender.id, pruned);
+
+            let response = format!(
+                "Removed {} links from review, as their domains are already marked as spam.",
+                pruned
+            );
+            goodbye!(response.as_str());
+        }
+        "/prune_urls" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let Some((age, dry_run)) = parse_prune_urls_params(params) else {
+                goodbye!(
+                    "Usage: <code>/prune_urls older_than:365d dry_run:true</code>\n\
+                    Age can be in hours, days or weeks, like 12h, 30d or 8w. \
+                    Nothing is removed unless dry_run is false."
+                );
+            };
+
+            let Some(cutoff) = Utc::now().checked_sub_signed(age) else {
+                goodbye!("That age goes further back than dates can.");
+            };
+
+            let counts = database
+                .prune_unseen_urls(cutoff, dry_run)
+                .await
+                .expect("Database died!");
+            let total: u64 = counts.iter().map(|x| x.1).sum();
+
+            if !dry_run {
+                log::info!(
+                    "{} pruned {} URLs not seen for {} days.",
+                    sender.id,
+                    total,
+                    age.num_days()
+                );
+            }
+
+            let mut response = format!(
+                "{} {} automatically added links not seen in a message for {} days.",
+                if dry_run { "Would remove" } else { "Removed" },
+                total,
+                age.num_days()
+            );
+            for (is_spam, count) in counts {
+                let designation = match is_spam {
+                    IsSpam::Yes => "spam",
+                    IsSpam::No => "not spam",
+                    IsSpam::Maybe => "waiting for review",
+                    IsSpam::Likely => "likely spam",
+                    IsSpam::Unlikely => "likely not spam",
+                };
+                response.push_str(&format!("\n{}: {}", designation, count));
+            }
+            if dry_run && total > 0 {
+                response.push_str("\n\nAdd <code>dry_run:false</code> to actually remove them.");
+            }
+
+            goodbye!(response.as_str());
+        }
+        "/heuristics" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let (text, keyboard) = describe_heuristics(database).await;
+            bot.send_message(
+                message.chat.id,
+                text,
+                MessageOptions::html()
+                    .reply_markup(keyboard)
+                    .reply_to(message.id),
+            )
+            .await?;
+            true
+        }
+        "/mark_pattern_spam" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let Some(pattern) = params.split_whitespace().next() else {
+                goodbye!(concat!(
+                    "Please specify a pattern, like <code>*.claim-nft-*.xyz</code>, ",
+                    "or <code>.xyz</code> for a whole TLD."
+                ));
+            };
+            let pattern = match HostPattern::parse(pattern) {
+                Ok(x) => x,
+                Err(e) => {
+                    goodbye!(format!("Sorry, {}", e).as_str());
+                }
+            };
+
+            let (count, examples) = database
+                .domains_matching_pattern(&pattern, PATTERN_EXAMPLES)
+                .await
+                .expect("Database died!");
+
+            let mut text = format!(
+                "Mark every host matching <code>{}</code> as spam?\n\n",
+                pattern
+            );
+            if count == 0 {
+                text.push_str("It matches no domains the bot has seen so far.");
+            } else {
+                text.push_str(&format!(
+                    "It matches {} domains the bot has seen, like:\n",
+                    count
+                ));
+                for example in examples {
+                    text.push_str(&format!("<code>{}</code>\n", encode_text(&example)));
+                }
+            }
+
+            let keyboard = InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback("Mark as spam", format!("PATTERN SPAM {}", pattern)),
+                InlineKeyboardButton::callback("Cancel", "PATTERN CANCEL"),
+            ]]);
+            bot.send_message(
+                message.chat.id,
+                text,
+                MessageOptions::html()
+                    .reply_markup(keyboard)
+                    .reply_to(message.id),
+            )
+            .await?;
+            true
+        }
+        "/mark_viabot_spam" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let (username, reason) = params
+                .trim()
+                .split_once(char::is_whitespace)
+                .unwrap_or((params.trim(), ""));
+            if username.is_empty() {
+                goodbye!(
+                    "Please specify an inline bot, like <code>/mark_viabot_spam @SomeSpamBot</code>"
+                );
+            }
+
+            let Some(url) =
+                mark_via_bot_spam(bot, sender, database, username, reason.trim()).await?
+            else {
+                goodbye!(format!(
+                    "<code>{}</code> is not a bot username, or it's a trusted inline bot.",
+                    encode_text(username)
+                )
+                .as_str());
+            };
+            log::info!(
+                "{} marked the inline bot {} as spam. Reason: {:?}",
+                sender.id,
+                url,
+                reason.trim()
+            );
+
+            goodbye!(format!(
+                "Marked every message sent via <code>{}</code> as spam.",
+                encode_text(username)
+            )
+            .as_str());
+        }
+        "/unmark_pattern_spam" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let Some(pattern) = params.split_whitespace().next() else {
+                let patterns = database.list_url_patterns().await.expect("Database died!");
+                if patterns.is_empty() {
+                    goodbye!("No patterns of spam hosts were added.");
+                }
+
+                let mut response = String::from("Patterns of spam hosts:\n");
+                for (pattern, added_by, added_at) in patterns {
+                    response.push_str(&format!(
+                        "<code>{}</code>, added by <code>{}</code> on {}\n",
+                        pattern,
+                        added_by,
+                        added_at.format("%Y-%m-%d")
+                    ));
+                }
+                response
+                    .push_str("\nSend <code>/unmark_pattern_spam</code> with one to remove it.");
+                goodbye!(response.as_str());
+            };
+
+            // The same normalization as when adding, so that `.xyz` removes `*.xyz`.
+            let pattern = HostPattern::parse(pattern)
+                .map(|x| x.as_str().to_string())
+                .unwrap_or_else(|_| pattern.to_string());
+
+            if database
+                .remove_url_pattern(&pattern)
+                .await
+                .expect("Database died!")
+            {
+                log::info!("{} removed the spam host pattern {}.", sender.id, pattern);
+                goodbye!(format!("Removed the pattern <code>{}</code>.", pattern).as_str());
+            } else {
+                goodbye!(
+                    format!("There's no pattern <code>{}</code>.", encode_text(&pattern)).as_str()
+                );
+            }
+        }
+        "/hidden_chats" => {
+            // Same gatekeeping as the marking commands below.
+            if !is_private {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            let chats = database
+                .list_hide_delete_chats()
+                .await
+                .expect("Database died!");
+
+            if chats.is_empty() {
+                goodbye!("No chats have deletion notifications hidden.");
+            }
+
+            // Asking Telegram about each chat is slow, so only do it if asked to.
+            let with_titles = params.split_whitespace().next() == Some("titles");
+
+            let mut response = format!(
+                "{} chats have deletion notifications hidden:\n",
+                chats.len()
+            );
+            for chat in chats {
+                let full_chat = if with_titles {
+                    // The bot may have been kicked from it since.
+                    bot.get_chat(chat).await.ok()
+                } else {
+                    None
+                };
+                let line = match full_chat {
+                    Some(full_chat) => describe_chat(&full_chat),
+                    None => format!("chatid <code>{}</code>", chat),
+                };
+                response.push_str(&line);
+                response.push('\n');
+            }
+            if !with_titles {
+                response.push_str("\nSend <code>/hidden_chats titles</code> to see their names.");
+            }
+
+            goodbye!(response.as_str());
+        }
+        "/leaderboard" | "/reviewer_history" | "/prune_reviewer_history" => {
+            // Same gatekeeping as the marking commands below,
+            // except that these can also be used in the control chats themselves.
+            if !BotConfig::get().is_control_chat(message.chat.id) {
+                if !is_private {
+                    return Ok(false);
+                }
+                let Some(sender) = message.from() else {
+                    return Ok(false);
+                };
+                if !reviews::authenticate_control(bot, sender).await? {
+                    return Ok(false);
+                }
+            }
+
+            let now = Utc::now();
+
+            match command.as_str() {
+                "/leaderboard" => {
+                    let mut response = String::from("Top reviewers. Times are in UTC.\n");
+                    for days in LEADERBOARD_PERIODS {
+                        let since = now - chrono::TimeDelta::days(days);
+                        let stats = database
+                            .reviewer_leaderboard(since, LEADERBOARD_SIZE)
+                            .await
+                            .expect("Database died!");
+                        response.push('\n');
+                        response.push_str(&describe_leaderboard(days, since, &stats));
+                    }
+                    goodbye!(response.as_str());
+                }
+                "/reviewer_history" => {
+                    let Some((reviewer, count)) = parse_reviewer_history_params(params) else {
+                        goodbye!(format!(
+                            concat!(
+                                "Usage: <code>/reviewer_history @username 20</code>\n",
+                                "A user ID works instead of the username too. ",
+                                "Up to {} reviews can be shown."
+                            ),
+                            MAX_REVIEWER_HISTORY
+                        )
+                        .as_str());
+                    };
+
+                    let reviewer = match reviewer.parse::<u64>() {
+                        Ok(id) => Some(UserId(id)),
+                        Err(_) => database
+                            .find_reviewer_by_username(reviewer)
+                            .await
+                            .expect("Database died!"),
+                    };
+                    let Some(reviewer) = reviewer else {
+                        goodbye!("Sorry, but I don't know of any reviews by that user.");
+                    };
+
+                    let history = database
+                        .reviewer_history(reviewer, count)
+                        .await
+                        .expect("Database died!");
+                    if history.is_empty() {
+                        goodbye!("Sorry, but I don't know of any reviews by that user.");
+                    }
+
+                    use std::fmt::Write;
+                    let mut response = format!(
+                        "Latest {} reviews by userid <code>{}</code>. Times are in UTC.\n\n",
+                        history.len(),
+                        reviewer
+                    );
+                    for (action, url, reviewed_at) in history {
+                        let _ = writeln!(
+                            response,
+                            "{} {} <code>{}</code>",
+                            reviewed_at.format("%Y-%m-%d %H:%M"),
+                            action,
+                            encode_text(&url)
+                        );
+                    }
+                    goodbye!(response.as_str());
+                }
+                _ => {
+                    let pruned = database
+                        .prune_reviewer_actions(now - REVIEWER_ACTIONS_KEEP_TIME)
+                        .await
+                        .expect("Database died!");
+                    log::info!(
+                        "{:?} pruned {} old reviewer actions.",
+                        message.from().map(|x| x.id),
+                        pruned
+                    );
+                    goodbye!(format!(
+                        "Forgot {} reviews applied more than {} days ago.",
+                        pruned,
+                        REVIEWER_ACTIONS_KEEP_TIME.num_days()
+                    )
+                    .as_str());
+                }
+            }
+        }
+        "/mark_not_spam" | "/mark_url_spam" | "/mark_url_spam_until" | "/mark_domain_spam" => {
+            // If it's not a private chat, or no sender,or they're not
+            // in control chat, pretend we do not see it.
+            if !message.chat.is_private() {
+                return Ok(false);
+            }
+            let Some(sender) = message.from() else {
+                return Ok(false);
+            };
+            if !reviews::authenticate_control(bot, sender).await? {
+                return Ok(false);
+            }
+
+            // Get message "entities".
+            let Some(entities) = message
+                .parse_entities()
+                .or_else(|| message.parse_caption_entities())
+            else {
+                goodbye!("Please specify links. Replies don't count to avoid accidents.");
+            };
+
+            let expires_at = if command == "/mark_url_spam_until" {
+                let Some(expires_at) = params
+                    .split_whitespace()
+                    .next()
+                    .and_then(|date| parse_expiry_date(date, Utc::now()))
+                else {
+                    goodbye!(
+                        "Please specify a date in the future first, like \
+                        <code>/mark_url_spam_until 2030-01-31 https://example.com</code>"
+                    );
+                };
+                Some(expires_at)
+            } else {
+                None
+            };
+
+            let links: Vec<&str> = entities
+                .iter()
+                .filter(|x| get_entity_url_domain(x).is_some())
+                .map(|x| x.text())
+                .collect();
+            let reason = parse_mark_reason(params, &links, usize::from(expires_at.is_some()));
+
+            let mut response = String::new();
+            let mut wrote_header = false;
+
+            // Scan all URLs in the message...
+            for entity in &entities {
+                let Some((mut url, domain)) = get_entity_url_domain(entity) else {
+                    continue;
+                };
+
+                match command.as_str() {
+                    "/mark_not_spam" => {
+                        let action = ReviewResponse::NotSpam(Some(domain), url);
+                        reviews::apply_review_unverified(bot, sender, database, &action, &reason)
+                            .await?;
+                        // Get the URL back lol
+                        url = action.deconstruct().unwrap().1;
+
+                        if !wrote_header {
+                            response.push_str("Marked as not spam:\n");
+                            wrote_header = true;
+                        }
+                    }
+                    "/mark_url_spam" => {
+                        let action = ReviewResponse::UrlSpam(Some(domain), url);
+                        reviews::apply_review_unverified(bot, sender, database, &action, &reason)
+                            .await?;
+                        // Get the URL back lol
+                        url = action.deconstruct().unwrap().1;
+
+                        if !wrote_header {
+                            response.push_str("Marked these URLs as spam:\n");
+                            wrote_header = true;
+                        }
+                    }
+                    "/mark_url_spam_until" => {
+                        let expires_at = expires_at.expect("Checked above");
+                        // Not a review, as those are permanent and shared with other instances.
+                        database
+                            .add_url(&url, IsSpam::Yes, false, true, None, Some(expires_at))
+                            .await
+                            .expect("Database died!");
+                        log::info!(
+                            "{} marked {} as spam until {}. Reason: {:?}",
+                            sender.id,
+                            url,
+                            expires_at,
+                            reason
+                        );
+
+                        if !wrote_header {
+                            response.push_str(&format!(
+                                "Marked these URLs as spam until {}:\n",
+                                expires_at.format("%Y-%m-%d %H:%M UTC")
+                            ));
+                            wrote_header = true;
+                        }
+                    }
+                    "/mark_domain_spam" => {
+                        let action = ReviewResponse::DomainSpam(domain, url);
+                        reviews::apply_review_unverified(bot, sender, database, &action, &reason)
+                            .await?;
+                        // Get the URL back lol
+                        url = action.deconstruct().unwrap().1;
 
-                    match result {
-                        Marked => {
-                            let _ = writeln!(links_marked, "<code>{}</code>", $url);
-                            marked_count += 1
+                        if !wrote_header {
+                            response.push_str("Marked domains of these URLs as spam:\n");
+                            wrote_header = true;
                         }
-                        AlreadyMarkedSus => already_marked_sus_count += 1,
-                        AlreadyMarkedSpam => already_marked_spam_count += 1,
-                        ManuallyReviewedNotSpam => manually_reviewed_not_spam_count += 1,
                     }
+                    _ => unreachable!(),
                 }
-            };
+
+                response.push_str(url.as_str());
+                response.push('\n');
+            }
+
+            if response.is_empty() {
+                goodbye!("Please specify links. Replies don't count to avoid accidents.");
+            }
+
+            if !reason.is_empty() {
+                response.push_str(&format!("Reason: {}\n", encode_text(&reason)));
+            }
+
+            goodbye!(response.as_str());
         }
+        // Any kind of "/start", "/help" commands would yield false and
+        // hence cause the help message to be printed if this is a private chat.
+        // See definition of handle_private_message.
+        _ => false,
+    };
 
-        if let Some(entities) = message
-            .parse_entities()
-            .or_else(|| message.parse_caption_entities())
-        {
-            for entity in &entities {
-                let Some((url, domain)) = get_entity_url_domain(entity) else {
-                    continue;
-                };
+    Ok(command_processed)
+}
+
+/// Forget admin statuses of members that were just promoted or demoted, and note who joined.
+pub async fn handle_chat_member(
+    update: ChatMemberUpdated,
+    database: Arc<Database>,
+) -> Result<(), RequestError> {
+    database.admin_cache().observe_member_update(&update);
+
+    // Also see joins here, in case the chat hides join messages. See [`guard_first_link`].
+    let joined = !update.old_chat_member.is_present() && update.new_chat_member.is_present();
+    if joined
+        && !update.new_chat_member.user.is_bot
+        && database
+            .get_first_link_guard(update.chat.id)
+            .await
+            .expect("Database died!")
+    {
+        database
+            .add_new_member(update.chat.id, update.new_chat_member.user.id)
+            .await
+            .expect("Database died!");
+    }
+
+    Ok(())
+}
+
+pub fn generate_bot_commands() -> Vec<BotCommand> {
+    vec![
+        BotCommand::new("/hide_deletes", "Hide spam deletion notification messages."),
+        BotCommand::new(
+            "/show_deletes",
+            "Don't hide spam deletion notification messages.",
+        ),
+        BotCommand::new(
+            "/grace_period",
+            "Set how many seconds to wait before deleting spam.",
+        ),
+        BotCommand::new(
+            "/report_only",
+            "Set whether to only point out spam without deleting it.",
+        ),
+        BotCommand::new(
+            "/strict_mode",
+            "Set whether to also delete links that are only likely spam.",
+        ),
+        BotCommand::new(
+            "/show_appeal_hint",
+            "Set whether deletion notifications say what to do about a mistake.",
+        ),
+        BotCommand::new("/keep", "Cancel deleting the replied-to message."),
+        BotCommand::new(
+            "/rescan",
+            "Check the replied-to message for spam again, as if it was just sent.",
+        ),
+        BotCommand::new(
+            "/refresh_admins",
+            "Forget who the admins of this chat are, in case they changed.",
+        ),
+        BotCommand::new(
+            "/moderate_channel_posts",
+            "Set whether to delete spam posted by the linked channel.",
+        ),
+        BotCommand::new(
+            "/delete_albums",
+            "Set whether to delete the whole album if a message in it is spam.",
+        ),
+        BotCommand::new(
+            "/first_link_guard",
+            "Set whether to hide first messages of users if they have links.",
+        ),
+        BotCommand::new(
+            "/backfill",
+            "Set whether to check messages sent while the bot was down.",
+        ),
+        BotCommand::new("/spam", "Mark links in a message for review as spam."),
+        BotCommand::new(
+            "/check",
+            "Check links in the replied-to message without deleting anything.",
+        ),
+    ]
+}
+
+pub async fn handle_private_message(
+    bot: &impl BotApi,
+    message: &Message,
+) -> Result<(), RequestError> {
+    if message.edit_date().is_some() {
+        // Ignore message edits here.
+        return Ok(());
+    }
+    // Nothing much to do here lol
+
+    bot.send_message(
+        message.chat.id,
+        "
+This bot is made to combat the currently ongoing wave of NFT spam experienced by chats with linked channels.
+
+To use this bot, add it to a chat and give it administrator status with \"Remove messages\" permission.
+
+No further setup is required. A message will be sent when spam is removed. If you'd rather have a chance to intervene first, admins can use /grace_period to delay deletions.
+
+To see how the bot does before trusting it, admins can use /report_only on to have spam pointed out instead of deleted.
+
+Admins can use /show_appeal_hint on to have deletion notifications say what to do if a message was deleted by mistake.
+
+Reviewers sometimes aren't sure if a link is spam. Such links are left alone, unless admins use /strict_mode on to have them deleted too.
+
+Posts from the chat's linked channel are not deleted, as they're made by the channel's admins; a warning is sent instead. Admins can use /moderate_channel_posts on to change that.
+
+Spammers sometimes put the link on just one photo of an album. Admins can use /delete_albums on to have the whole album deleted then.
+
+Admins can use /first_link_guard on to have first messages of users hidden if they have links, until they press a button to confirm they're human.
+
+If the bot is down for a while, it can check messages sent meanwhile once it's back, deleting spam without responding to anything else. Admins can use /backfill on to enable that.
+
+The bot only checks messages as they're sent. To check an older message, for example one sent before the bot was added, admins can reply /rescan to it.
+
+The bot remembers who the admins of a chat are for a few minutes. If that changed and the bot didn't notice, an admin can use /refresh_admins.
+
+To see what this bot thinks of some links, send /check with them here, or reply /check to a message in a chat you're an admin of.
+
+For available commands, type / into the message text box below and see the previews.
+
+If you're in the group for volunteers to manually review chats, you can also use commands here in private chat:
+
+/mark_not_spam, /mark_url_spam and /mark_domain_spam
+
+Anything after the links in those is taken as the reason, and is shown in the review log.
+
+/mark_url_spam_until 2030-01-31 followed by links marks them as spam only until that date.
+
+To see how the bot reads a link when matching it against the database, send /sanitize with it.
+
+/prune_reviews removes links waiting for review whose domains were marked as spam since.
+
+/prune_urls older_than:365d dry_run:false removes automatically added links that weren't seen in a message for that long. Without dry_run:false, it only says how many it would remove.
+
+/heuristics lists automatic spam checks, with buttons to turn misbehaving ones off.
+
+/search claim-nft lists the latest links in the database with that in their host.
+
+/mark_viabot_spam @SomeSpamBot marks every message sent via that inline bot as spam, whatever is in it. Popular inline bots like @gif are trusted and can't be marked.
+
+/mark_pattern_spam *.claim-nft-*.xyz marks every host matching a pattern as spam, after showing which known domains it matches. A * matches anything within one part of the host, and .xyz marks a whole TLD. /unmark_pattern_spam lists patterns, or removes the one given to it.
+
+/hidden_chats lists chats that hid deletion notifications with /hide_deletes. Add titles to it to see their names.
+
+/leaderboard shows who applied the most reviews in the last 7 and 30 days. /reviewer_history @username 20 lists the latest reviews of a reviewer. /prune_reviewer_history forgets reviews applied more than a year ago. These can be used in the control chat too.
+
+To see why a message was or wasn't deleted, reply /explain to it after forwarding it here, or send /explain with links.",
+        MessageOptions::default(),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a message with this ID, replying to the given one, if any.
+    fn message(id: i32, reply_to: Option<serde_json::Value>) -> serde_json::Value {
+        let mut message = serde_json::json!({
+            "message_id": id,
+            "date": 0,
+            "chat": { "id": -100, "type": "supergroup", "title": "amogus" },
+            "text": "sus",
+        });
+        if let Some(reply_to) = reply_to {
+            message["reply_to_message"] = reply_to;
+        }
+        message
+    }
+
+    fn chain_ids(message: serde_json::Value) -> Vec<i32> {
+        let message: Message = serde_json::from_value(message).unwrap();
+        reply_chain(&message).map(|x| x.id.0).collect()
+    }
+
+    #[test]
+    fn defanging_links() {
+        use teloxide::types::MessageEntity;
+
+        let text = "Hi. Check out example.com/claim. Thanks.";
+        let entities = [MessageEntity::new(MessageEntityKind::Url, 14, 17)];
+        assert_eq!(
+            defang_links(text, &MessageEntityRef::parse(text, &entities)),
+            "Hi. Check out example[.]com/claim. Thanks."
+        );
+        assert_eq!(defang_links(text, &[]), text);
+    }
+
+    #[test]
+    fn link_descriptions() {
+        let url = Url::parse("https://Example.com:8443/claim?ref=1&lang=en").unwrap();
+        let domain = Domain::from_url(&url).unwrap();
+        let description = describe_link("Example.com:8443/claim?ref=1&lang=en", &url, &domain);
+
+        assert!(description.contains("Domain: <code>example.com</code>"));
+        assert!(description.contains("Port: <code>8443</code>"));
+        assert!(description.contains("Path: <code>/claim</code>"));
+        assert!(description.contains("• <code>lang</code> = <code>en</code>"));
+
+        let url = Url::parse("https://t.me/amogus").unwrap();
+        let description = describe_link("@amogus", &url, &Domain::from_url(&url).unwrap());
+        assert!(description.contains("Query: none"));
+        assert!(!description.contains("Port"));
+    }
+
+    #[test]
+    fn reply_chain_depth() {
+        assert!(chain_ids(message(1, None)).is_empty());
+
+        // What Telegram actually sends: only one level of replies.
+        assert_eq!(chain_ids(message(2, Some(message(1, None)))), [1]);
+
+        // Deeper chains are followed if present, up to a limit.
+        let mut deep = message(0, None);
+        for id in 1..=MAX_REPLY_CHAIN_DEPTH as i32 + 2 {
+            deep = message(id, Some(deep));
+        }
+        let ids = chain_ids(deep);
+        assert_eq!(ids.len(), MAX_REPLY_CHAIN_DEPTH);
+        assert_eq!(ids[0], MAX_REPLY_CHAIN_DEPTH as i32 + 1);
+    }
+
+    /// Make a post automatically forwarded from a linked channel with this ID.
+    fn channel_post(id: i32) -> serde_json::Value {
+        let mut message = message(id, None);
+        message["is_automatic_forward"] = true.into();
+        message["sender_chat"] =
+            serde_json::json!({ "id": -200, "type": "channel", "title": "sus channel" });
+        message["forward_from_chat"] = message["sender_chat"].clone();
+        message["forward_from_message_id"] = 1.into();
+        message["forward_date"] = 0.into();
+        message
+    }
+
+    #[test]
+    fn forwarded_spam_names() {
+        let mut forward = message(1, None);
+        forward["from"] = serde_json::json!({
+            "id": 5, "is_bot": false, "first_name": "Forwarder", "username": "forwarder"
+        });
+        assert_eq!(
+            offending_user_name(&serde_json::from_value(forward.clone()).unwrap(), true),
+            "@forwarder"
+        );
+
+        forward["forward_date"] = 0.into();
+        forward["forward_from"] = serde_json::json!({ "id": 6, "is_bot": false, "first_name": "Spam", "last_name": "Source" });
+        let message: Message = serde_json::from_value(forward.clone()).unwrap();
+        assert_eq!(offending_user_name(&message, false), "@forwarder");
+        assert_eq!(offending_user_name(&message, true), "Spam Source");
+
+        forward.as_object_mut().unwrap().remove("forward_from");
+        forward["forward_sender_name"] = "Hidden Spammer".into();
+        let message: Message = serde_json::from_value(forward.clone()).unwrap();
+        assert_eq!(offending_user_name(&message, true), "Hidden Spammer");
+
+        let post: Message = serde_json::from_value(channel_post(2)).unwrap();
+        assert_eq!(offending_user_name(&post, true), "sus channel");
+    }
+
+    #[test]
+    fn channel_posts_are_not_suspected() {
+        let post: Message = serde_json::from_value(channel_post(1)).unwrap();
+        assert!(post.is_automatic_forward());
+
+        // Replying to a channel post doesn't gather suspicion from it...
+        let reply: Message = serde_json::from_value(message(2, Some(channel_post(1)))).unwrap();
+        assert_eq!(reply_chain(&reply).count(), 1);
+        assert_eq!(suspicion_reply_chain(&reply).count(), 0);
+
+        // ...but replying to anything else still does.
+        let reply: Message = serde_json::from_value(message(2, Some(message(1, None)))).unwrap();
+        assert!(!reply.reply_to_message().unwrap().is_automatic_forward());
+        assert_eq!(suspicion_reply_chain(&reply).count(), 1);
+    }
+
+    #[test]
+    fn mark_reason_parsing() {
+        let links = ["https://example.com", "sus.io"];
+
+        assert_eq!(parse_mark_reason("https://example.com", &links, 0), "");
+        assert_eq!(
+            parse_mark_reason("https://example.com sus.io  wallet   drainer", &links, 0),
+            "wallet drainer"
+        );
+        assert_eq!(
+            parse_mark_reason("fake giveaway: https://example.com", &links, 0),
+            "fake giveaway:"
+        );
+
+        // The date of /mark_url_spam_until isn't a part of the reason.
+        assert_eq!(
+            parse_mark_reason("2030-01-31 https://example.com promo", &links, 1),
+            "promo"
+        );
+    }
+
+    #[test]
+    fn expiry_date_parsing() {
+        let now = DateTime::parse_from_rfc3339("2025-06-15T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        // Plain dates include the whole day.
+        assert_eq!(
+            parse_expiry_date("2025-06-15", now),
+            DateTime::parse_from_rfc3339("2025-06-16T00:00:00Z")
+                .ok()
+                .map(|x| x.with_timezone(&Utc))
+        );
+        assert_eq!(
+            parse_expiry_date("2025-06-20T10:00:00+02:00", now),
+            DateTime::parse_from_rfc3339("2025-06-20T08:00:00Z")
+                .ok()
+                .map(|x| x.with_timezone(&Utc))
+        );
+
+        // Past dates and garbage don't count.
+        assert_eq!(parse_expiry_date("2025-06-14", now), None);
+        assert_eq!(parse_expiry_date("2025-06-15T11:00:00Z", now), None);
+        assert_eq!(parse_expiry_date("tomorrow", now), None);
+        assert_eq!(parse_expiry_date("https://example.com", now), None);
+    }
+
+    #[test]
+    fn prune_urls_params_parsing() {
+        assert_eq!(
+            parse_prune_urls_params(""),
+            Some((DEFAULT_PRUNE_URLS_AGE, true))
+        );
+        assert_eq!(
+            parse_prune_urls_params("older_than:30d dry_run:false"),
+            Some((chrono::TimeDelta::days(30), false))
+        );
+        assert_eq!(
+            parse_prune_urls_params("dry_run:true older_than:8w"),
+            Some((chrono::TimeDelta::weeks(8), true))
+        );
+        assert_eq!(
+            parse_prune_urls_params("older_than:12h"),
+            Some((chrono::TimeDelta::hours(12), true))
+        );
+
+        for garbage in [
+            "older_than:0d",
+            "older_than:-5d",
+            "older_than:5y",
+            "older_than:d",
+            "older_than:",
+            "dry_run:yes",
+            "sus:true",
+            "365d",
+        ] {
+            assert_eq!(parse_prune_urls_params(garbage), None, "{}", garbage);
+        }
+    }
+
+    #[test]
+    fn reviewer_history_params_parsing() {
+        assert_eq!(
+            parse_reviewer_history_params("@amogus"),
+            Some(("@amogus", DEFAULT_REVIEWER_HISTORY))
+        );
+        assert_eq!(
+            parse_reviewer_history_params(" 12345  50 "),
+            Some(("12345", 50))
+        );
+
+        for garbage in [
+            "",
+            "@amogus 0",
+            "@amogus 1000",
+            "@amogus lots",
+            "@amogus 5 6",
+        ] {
+            assert_eq!(parse_reviewer_history_params(garbage), None, "{}", garbage);
+        }
+    }
+
+    #[test]
+    fn leaderboard_description() {
+        let since = DateTime::parse_from_rfc3339("2030-01-31T12:34:56Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let stats = [ReviewerStats {
+            reviewer: UserId(5),
+            username: Some("amogus".to_string()),
+            total: 3,
+            actions: vec![("NOT_SPAM".to_string(), 1), ("URL_SPAM".to_string(), 2)],
+        }];
+
+        assert_eq!(
+            describe_leaderboard(7, since, &stats),
+            concat!(
+                "<b>Last 7 days</b> (since 2030-01-31 12:34 UTC):\n",
+                "1. @amogus (userid <code>5</code>): 3 (NOT_SPAM 1, URL_SPAM 2)\n"
+            )
+        );
+        assert!(describe_leaderboard(30, since, &[]).contains("Nobody applied any reviews."));
+    }
+
+    const CHAT: ChatId = ChatId(-100);
+    const SPAM: &str = "https://spam.example/claim";
+
+    fn me() -> Me {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": "Bot",
+            "username": "bot",
+            "can_join_groups": true,
+            "can_read_all_group_messages": true,
+            "supports_inline_queries": false,
+        }))
+        .unwrap()
+    }
+
+    /// Make a message in [`CHAT`] from this user, with links in the text marked as such.
+    fn user_message(id: i32, user: u64, text: &str) -> serde_json::Value {
+        let mut message = message(id, None);
+        message["from"] =
+            serde_json::json!({ "id": user, "is_bot": false, "first_name": "Amogus" });
+        message["text"] = text.into();
+        message["entities"] = text
+            .match_indices("https://")
+            .map(|(offset, _)| {
+                let length = text[offset..].find(' ').unwrap_or(text.len() - offset);
+                serde_json::json!({ "type": "url", "offset": offset, "length": length })
+            })
+            .collect();
+        message
+    }
+
+    /// A pretend bot, and a database that knows [`SPAM`] is spam.
+    async fn setup() -> (mock_bot::MockBot, Arc<Database>) {
+        BotConfig::init_default();
+        let database = Database::new_by_path(None, "sqlite::memory:", false)
+            .await
+            .unwrap();
+        database
+            .add_url(
+                &Url::parse(SPAM).unwrap(),
+                IsSpam::Yes,
+                false,
+                true,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        (mock_bot::MockBot::default(), database)
+    }
+
+    async fn handle(bot: &mock_bot::MockBot, database: &Arc<Database>, message: serde_json::Value) {
+        let message: Message = serde_json::from_value(message).unwrap();
+        handle_message_inner(bot, &me(), &message, database, false, false)
+            .await
+            .unwrap();
+    }
+
+    /// Wait for notices for reviewers to be sent, which happens in batches.
+    /// Sending them doesn't touch the database, so time can be paused meanwhile.
+    async fn wait_for_review_notices() {
+        tokio::time::pause();
+        tokio::time::sleep(review_notify::BATCH_WINDOW * 2).await;
+        tokio::time::resume();
+    }
+
+    #[tokio::test]
+    async fn spam_from_non_admin_is_deleted() {
+        use mock_bot::Call;
 
-                marksus!(&url, &domain);
+        let (bot, database) = setup().await;
+        handle(
+            &bot,
+            &database,
+            user_message(5, 10, &format!("free nft {}", SPAM)),
+        )
+        .await;
+
+        let actions = bot.actions();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(
+            actions[0],
+            Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(5)
             }
+        );
+        let Call::SendMessage { chat, text, .. } = &actions[1] else {
+            panic!("Expected a deletion notice, got {:?}", actions[1]);
         };
+        assert_eq!(*chat, CHAT);
+        assert!(text.contains("Removed a message from <code>Amogus</code>"));
+    }
 
-        // Get replied-to message "entities", if any.
-        if let Some(replied_message) = message.reply_to_message() {
-            if let Some(replied_entities) = replied_message
-                .parse_entities()
-                .or_else(|| replied_message.parse_caption_entities())
-            {
-                for entity in &replied_entities {
-                    let Some((url, domain)) = get_entity_url_domain(entity) else {
-                        continue;
-                    };
+    #[tokio::test]
+    async fn spam_with_extra_query_parameters_is_deleted() {
+        use mock_bot::Call;
 
-                    marksus!(&url, &domain);
-                }
+        let (bot, database) = setup().await;
+        let with_ref = "https://spam.example/claim?ref=1";
+        database
+            .add_url(
+                &Url::parse(with_ref).unwrap(),
+                IsSpam::Yes,
+                false,
+                true,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        // Both the link without a query and the one with a referral match these.
+        handle(
+            &bot,
+            &database,
+            user_message(5, 10, &format!("{}&utm_source=amogus", with_ref)),
+        )
+        .await;
+        handle(
+            &bot,
+            &database,
+            user_message(6, 10, &format!("{}?utm_source=amogus#sus", SPAM)),
+        )
+        .await;
+
+        let deleted: Vec<Call> = bot
+            .actions()
+            .into_iter()
+            .filter(|x| matches!(x, Call::DeleteMessage { .. }))
+            .collect();
+        assert_eq!(
+            deleted,
+            [5, 6].map(|id| Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(id)
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn deletion_notices_are_coalesced() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        handle(&bot, &database, user_message(5, 10, SPAM)).await;
+        handle(&bot, &database, user_message(6, 11, SPAM)).await;
+
+        let actions = bot.actions();
+        assert_eq!(actions.len(), 4);
+        assert!(matches!(actions[2], Call::DeleteMessage { .. }));
+        let Call::EditMessageText { message, text, .. } = &actions[3] else {
+            panic!("Expected the notice to be edited, got {:?}", actions[3]);
+        };
+        // The notice is the first message the pretend bot sent.
+        assert_eq!(*message, MessageId(1));
+        assert!(text.contains("Removed 2 messages"));
+    }
+
+    #[tokio::test]
+    async fn appeal_hint_is_only_shown_when_asked_for() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        handle(&bot, &database, user_message(5, 10, SPAM)).await;
+        handle(&bot, &database, user_message(6, 11, SPAM)).await;
+
+        database.set_appeal_hint(CHAT, true).await.unwrap();
+        handle(&bot, &database, user_message(7, 12, SPAM)).await;
+        // A fresh notice in another chat.
+        let other = ChatId(-200);
+        let mut message = user_message(8, 13, SPAM);
+        message["chat"]["id"] = other.0.into();
+        database.set_appeal_hint(other, true).await.unwrap();
+        handle(&bot, &database, message).await;
+
+        let notices: Vec<(ChatId, String)> =
+            bot.actions()
+                .into_iter()
+                .filter_map(|x| match x {
+                    Call::SendMessage { chat, text, .. }
+                    | Call::EditMessageText { chat, text, .. } => Some((chat, text)),
+                    _ => None,
+                })
+                .collect();
+        assert_eq!(notices.len(), 4);
+        assert!(!notices[0].1.contains(APPEAL_HINT));
+        assert!(!notices[1].1.contains(APPEAL_HINT));
+        assert!(notices[2].1.contains("Removed 3 messages"));
+        assert!(notices[2].1.contains(APPEAL_HINT));
+        assert_eq!(notices[3].0, other);
+        assert!(notices[3].1.contains("Removed a message"));
+        assert!(notices[3].1.contains(APPEAL_HINT));
+    }
+
+    #[tokio::test]
+    async fn spam_from_admin_is_left_alone() {
+        let (bot, database) = setup().await;
+        bot.add_admin(CHAT, UserId(10));
+        handle(&bot, &database, user_message(5, 10, SPAM)).await;
+
+        assert_eq!(bot.actions(), []);
+    }
+
+    #[tokio::test]
+    async fn admins_are_found_among_chat_administrators() {
+        let bot = mock_bot::MockBot::default();
+        bot.add_admin(CHAT, UserId(10));
+        bot.add_admin(ChatId(-200), UserId(11));
+
+        let admin = arch_bot_commons::get_admin_of(&bot, UserId(10), CHAT)
+            .await
+            .unwrap();
+        assert_eq!(admin.map(|x| x.user.id), Some(UserId(10)));
+        let admin = arch_bot_commons::get_admin_of(&bot, UserId(11), CHAT)
+            .await
+            .unwrap();
+        assert!(admin.is_none());
+        assert!(bot
+            .calls()
+            .iter()
+            .all(|x| *x == mock_bot::Call::GetChatAdministrators { chat: CHAT }));
+    }
+
+    #[tokio::test]
+    async fn spam_from_linked_channel_is_left_alone() {
+        let (bot, database) = setup().await;
+        bot.link_chat(CHAT, ChatId(-200));
+
+        let mut post = user_message(5, 10, SPAM);
+        post["sender_chat"] =
+            serde_json::json!({ "id": -200, "type": "channel", "title": "sus channel" });
+        handle(&bot, &database, post.clone()).await;
+        assert_eq!(bot.actions(), []);
+
+        // Some other channel is not so lucky.
+        post["sender_chat"]["id"] = (-300).into();
+        handle(&bot, &database, post).await;
+        assert_eq!(bot.actions().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn hidden_deletions_are_not_announced() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        database.set_hide_deletes(CHAT, true).await.unwrap();
+        handle(&bot, &database, user_message(5, 10, SPAM)).await;
+
+        assert_eq!(
+            bot.actions(),
+            [Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(5)
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_to_delete_is_announced() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        bot.fail_deletes(ApiError::MessageCantBeDeleted);
+        handle(&bot, &database, user_message(5, 10, SPAM)).await;
+
+        let actions = bot.actions();
+        assert_eq!(actions.len(), 2);
+        let Call::SendMessage { text, .. } = &actions[1] else {
+            panic!("Expected a warning, got {:?}", actions[1]);
+        };
+        assert!(text.contains("Is this bot an admin"));
+    }
+
+    #[tokio::test]
+    async fn report_only_spam_is_not_deleted() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        database.set_report_only(CHAT, true).await.unwrap();
+        handle(&bot, &database, user_message(5, 10, SPAM)).await;
+
+        let actions = bot.actions();
+        assert!(!actions
+            .iter()
+            .any(|x| matches!(x, Call::DeleteMessage { .. })));
+        assert!(matches!(
+            actions[0],
+            Call::SendMessage {
+                chat: CHAT,
+                reply_to: Some(MessageId(5)),
+                ..
             }
+        ));
+        // And reviewers are told too.
+        assert!(actions[1..].iter().all(|x| matches!(
+            x,
+            Call::SendMessage { chat, text, .. }
+                if BotConfig::get().review_notify_targets().contains(chat) && text.contains(SPAM)
+        )));
 
-            // While we're here, check for links in buttons on the replied-to message.
-            if let Some(markup) = replied_message.reply_markup() {
-                for row in &markup.inline_keyboard {
-                    for button in row {
-                        let Some((url, domain)) = get_button_url_domain(button) else {
-                            continue;
-                        };
-                        marksus!(url, &domain);
-                    }
-                }
+        // Editing it or replying to it doesn't report it again.
+        let calls = bot.calls().len();
+        let mut edited = user_message(5, 10, &format!("{} edited", SPAM));
+        edited["edit_date"] = 1.into();
+        handle(&bot, &database, edited).await;
+        let mut reply = user_message(6, 11, "sus");
+        reply["reply_to_message"] = user_message(5, 10, SPAM);
+        handle(&bot, &database, reply).await;
+        assert_eq!(bot.calls().len(), calls);
+    }
+
+    #[tokio::test]
+    async fn likely_spam_is_deleted_only_in_strict_mode() {
+        use mock_bot::Call;
+
+        const LIKELY: &str = "https://likely.example/nft";
+
+        let (bot, database) = setup().await;
+        database
+            .add_url(
+                &Url::parse(LIKELY).unwrap(),
+                IsSpam::Likely,
+                false,
+                true,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        handle(&bot, &database, user_message(5, 10, LIKELY)).await;
+        assert!(!bot
+            .actions()
+            .iter()
+            .any(|x| matches!(x, Call::DeleteMessage { .. })));
+
+        database.set_strict_mode(CHAT, true).await.unwrap();
+        handle(&bot, &database, user_message(6, 10, LIKELY)).await;
+        assert!(bot.actions().iter().any(|x| matches!(
+            x,
+            Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(6)
             }
-        }
+        )));
+    }
 
-        // We assume there would be no buttons on the /spam message we're
-        // working for that we need to check. That'd be kind of ridiculous lol
+    #[tokio::test]
+    async fn rescanning_old_messages() {
+        use mock_bot::Call;
 
-        let mut response;
-        let marked = marked_count > 0;
-        let already_marked_sus = already_marked_sus_count > 0;
-        let already_marked_spam = already_marked_spam_count > 0;
-        let manually_reviewed_not_spam = manually_reviewed_not_spam_count > 0;
+        let (bot, database) = setup().await;
+        bot.add_admin(CHAT, UserId(20));
 
-        //if text.starts_with("/spam") | text.starts_with("/scam")
-        // This condition is moved to the top of the function now.
-        {
-            // That's the bot command, most likely. Users like indication that it does things.
+        // Not for just anyone.
+        let mut rescan = user_message(6, 10, "/rescan");
+        rescan["reply_to_message"] = user_message(5, 11, SPAM);
+        handle(&bot, &database, rescan.clone()).await;
+        let actions = bot.actions();
+        assert_eq!(actions.len(), 1);
+        assert!(
+            matches!(&actions[0], Call::SendMessage { text, .. } if text.contains("only be used by admins"))
+        );
 
-            // Purposefully ambiguous message wording, where "this" both refers to the
-            // message we're replying to and the message they replied to lol
+        // An admin gets the old spam deleted.
+        let bot = mock_bot::MockBot::default();
+        bot.add_admin(CHAT, UserId(20));
+        rescan["from"]["id"] = 20.into();
+        handle(&bot, &database, rescan.clone()).await;
+        let actions = bot.actions();
+        assert_eq!(
+            actions[0],
+            Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(5)
+            }
+        );
+        let Call::SendMessage { text, reply_to, .. } = actions.last().unwrap() else {
+            panic!("Expected a response, got {:?}", actions.last());
+        };
+        assert_eq!(*reply_to, Some(MessageId(6)));
+        assert!(text.contains("it has a spam link"));
 
-            let response = if marked {
-                "Thank you, links in this message will be reviewed for spam."
-            } else if had_links {
-                // Didn't mark anything as sus, but the message had links.
+        // Fine messages are left alone, and not hidden or responded to otherwise,
+        // even if the chat hides first links.
+        let bot = mock_bot::MockBot::default();
+        bot.add_admin(CHAT, UserId(20));
+        database.set_first_link_guard(CHAT, true).await.unwrap();
+        database
+            .add_url(
+                &Url::parse("https://totally.legit/").unwrap(),
+                IsSpam::No,
+                false,
+                true,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        rescan["reply_to_message"] = user_message(5, 11, "https://totally.legit/");
+        handle(&bot, &database, rescan).await;
+        let actions = bot.actions();
+        assert_eq!(actions.len(), 1);
+        assert!(
+            matches!(&actions[0], Call::SendMessage { text, .. } if text.contains("found no spam links"))
+        );
+    }
 
-                response = String::from("Thank you, but the links in this message are ");
+    #[tokio::test]
+    async fn spam_command_replying_to_admin_is_rejected() {
+        use mock_bot::Call;
 
-                if already_marked_sus {
-                    response.push_str("already marked for review");
-                    if already_marked_spam | manually_reviewed_not_spam {
-                        response.push_str(", and some are ");
-                    }
-                }
+        let (bot, database) = setup().await;
+        bot.add_admin(CHAT, UserId(20));
 
-                if already_marked_spam {
-                    response.push_str("already marked for spam");
-                    if manually_reviewed_not_spam {
-                        response.push_str(", and some are ");
-                    }
-                }
+        let mut report = user_message(6, 10, "/spam");
+        report["reply_to_message"] = user_message(5, 20, "https://totally.legit/");
+        handle(&bot, &database, report).await;
 
-                if manually_reviewed_not_spam {
-                    response.push_str("manually reviewed and were determined to be not spam");
-                }
+        let actions = bot.actions();
+        assert_eq!(actions.len(), 1);
+        let Call::SendMessage { text, reply_to, .. } = &actions[0] else {
+            panic!("Expected a rejection, got {:?}", actions[0]);
+        };
+        assert_eq!(*reply_to, Some(MessageId(6)));
+        assert!(text.contains("posted by an admin of this chat"));
+        assert_eq!(
+            database
+                .is_url_spam(&Url::parse("https://totally.legit/").unwrap(), false)
+                .await
+                .unwrap(),
+            None
+        );
+    }
 
-                response.push('.');
+    #[tokio::test]
+    async fn broken_control_chat_does_not_lock_reviewers_out() {
+        let bot = mock_bot::MockBot::default();
+        let (broken, fine) = (ChatId(-200), ChatId(-300));
+        bot.fail_members(broken, ApiError::ChatNotFound);
 
-                response.as_str()
-            } else {
-                // No links at all.
-                "Sorry, but I could not find any links."
-            };
-            bot.archsendmsg(message.chat.id, response, message.id)
-                .await?;
-        }
+        assert!(reviews::is_member_of_any(&bot, &[broken, fine], UserId(10)).await);
+        assert!(!reviews::is_member_of_any(&bot, &[broken], UserId(10)).await);
+        assert!(!reviews::is_member_of_any(&bot, &[], UserId(10)).await);
+    }
 
-        if marked {
-            // We marked something. In this case, notify reviewers to review.
+    #[tokio::test]
+    async fn refreshing_admins_is_for_admins() {
+        use mock_bot::Call;
 
-            let to_review = database.get_review_count().await.expect("Database died!");
-            // Should always be true, considering context above, but eh.
-            if to_review > 0 {
-                let username = if let Some(user) = message.from() {
-                    if let Some(username) = &user.username {
-                        format!("@{} (userid <code>{}</code>)", username, user.id)
-                    } else {
-                        format!("{} (userid <code>{}</code>)", user.full_name(), user.id)
-                    }
-                } else {
-                    "Anonymous".to_string()
-                };
+        let (bot, database) = setup().await;
+        let reply = |bot: &mock_bot::MockBot| {
+            let actions = bot.actions();
+            let Some(Call::SendMessage { text, .. }) = actions.last() else {
+                panic!("Expected a reply, got {:?}", actions);
+            };
+            text.clone()
+        };
 
-                let chatname = if let Some(username) = message.chat.username() {
-                    format!("@{} (chatid <code>{}</code>)", username, message.chat.id)
-                } else if let Some(title) = message.chat.title() {
-                    format!("{} (chatid <code>{}</code>)", title, message.chat.id)
-                } else {
-                    format!("Unknown (chatid <code>{}</code>)", message.chat.id)
-                };
+        handle(&bot, &database, user_message(5, 10, "/refresh_admins")).await;
+        assert!(reply(&bot).contains("can only be used by admins"));
 
-                // We don't care if this fails lmao
-                let _ = bot
-                    .archsendmsg(
-                        CONTROL_CHAT_ID,
-                        format!(
-                            concat!(
-                                "New link(s) were added to review pool by {} in {}:\n{}",
-                                "There are {} links to review."
-                            ),
-                            username, chatname, links_marked, to_review
-                        )
-                        .as_str(),
-                        None,
-                    )
-                    .await;
-            }
-        }
+        // Someone just promoted can use it, even though the bot remembers them as not an admin.
+        bot.add_admin(CHAT, UserId(10));
+        handle(&bot, &database, user_message(6, 10, "/refresh_admins")).await;
+        assert!(reply(&bot).contains("I will check again"));
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn keeping_a_message_edits_its_countdown() {
+        use mock_bot::Call;
 
-/// Returns `true` if a command was parsed and responded to.
-async fn handle_command(
-    bot: &Bot,
-    me: &Me,
-    message: &Message,
-    database: &Database,
-    mut sent_by_admin: Option<bool>,
-) -> Result<bool, RequestError> {
-    if message.edit_date().is_some() {
-        // Ignore message edits here.
-        return Ok(false);
-    }
+        let (bot, database) = setup().await;
+        database.set_grace_period(CHAT, 30).await.unwrap();
+        bot.add_admin(CHAT, UserId(20));
 
-    macro_rules! byadmin {
-        () => {{
-            if sent_by_admin.is_none() {
-                sent_by_admin = Some(is_sender_admin(bot, message).await?);
-            }
-            sent_by_admin.unwrap()
-        }};
+        let spam = user_message(5, 10, SPAM);
+        handle(&bot, &database, spam.clone()).await;
+        // Let the countdown send its notice. It doesn't touch the database before then,
+        // so time can be paused, and the grace period can't run out in the meantime.
+        tokio::time::pause();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tokio::time::resume();
+        let Call::SendMessage { text, .. } = &bot.actions()[0] else {
+            panic!("Expected a countdown, got {:?}", bot.actions());
+        };
+        assert!(text.contains("Deleting a message"));
+        // The notice is the first message the pretend bot sent.
+        let notice = MessageId(1);
+
+        let mut keep = user_message(6, 20, "/keep");
+        keep["reply_to_message"] = spam;
+        handle(&bot, &database, keep).await;
+
+        let actions = bot.actions();
+        assert!(actions.contains(&Call::EditMessageText {
+            chat: CHAT,
+            message: notice,
+            text: "Okay, not deleting this message, as asked by Amogus.".to_string(),
+        }));
+        let Some(Call::SendMessage { text, .. }) = actions.last() else {
+            panic!("Expected a reply, got {:?}", actions);
+        };
+        assert!(text.contains("Okay, not deleting that message."));
+        assert!(!actions
+            .iter()
+            .any(|x| matches!(x, Call::DeleteMessage { .. })));
     }
-    macro_rules! respond {
-        ($text:expr) => {
-            bot.archsendmsg(message.chat.id, $text, message.id).await?;
+
+    #[tokio::test]
+    async fn spam_command_marks_defanged_links() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+
+        let mut report = user_message(6, 10, "/spam");
+        report["reply_to_message"] = user_message(5, 20, "free nft at sus(dot)site/claim");
+        handle(&bot, &database, report).await;
+        wait_for_review_notices().await;
+
+        let actions = bot.actions();
+        // A thank you, and a notice for reviewers.
+        assert_eq!(actions.len(), 2);
+        let Call::SendMessage { text, .. } = &actions[0] else {
+            panic!("Expected a thank you, got {:?}", actions[0]);
         };
+        assert!(text.contains("will be reviewed"));
+        let Call::SendMessage { text, .. } = &actions[1] else {
+            panic!("Expected a review notice, got {:?}", actions[1]);
+        };
+        assert!(text.contains("<code>http://sus.site/claim</code>"));
+        assert_eq!(
+            database
+                .is_url_spam(&Url::parse("http://sus.site/claim").unwrap(), false)
+                .await
+                .unwrap(),
+            Some((IsSpam::Maybe, false))
+        );
     }
 
-    macro_rules! goodbye {
-        ($text:expr) => {{
-            respond!($text);
-            return Ok(true);
-        }};
+    /// Make a message like [`user_message`], but sent via this inline bot.
+    fn via_bot_message(id: i32, user: u64, text: &str, via_bot: &str) -> serde_json::Value {
+        let mut message = user_message(id, user, text);
+        message["via_bot"] = serde_json::json!({ "id": 50, "is_bot": true, "first_name": "Bot", "username": via_bot });
+        message
     }
-    let is_private = message.chat.is_private();
 
-    let Some(text) = message.text() else {
-        return Ok(false);
-    };
-    // Check if it starts with "/", like how a command should.
-    if !text.starts_with('/') {
-        return Ok(false);
+    /// Make a private message from this user to the bot.
+    fn private_message(id: i32, user: u64, text: &str) -> serde_json::Value {
+        let mut message = user_message(id, user, text);
+        message["chat"] =
+            serde_json::json!({ "id": user, "type": "private", "first_name": "Amogus" });
+        message
     }
-    // Get first word in the message, the command itself.
-    let Some(command) = text.split_whitespace().next() else {
-        return Ok(false);
-    };
 
-    let command_full_len = command.len();
+    #[tokio::test]
+    async fn messages_via_marked_inline_bots_are_deleted() {
+        use mock_bot::Call;
 
-    // Trim the bot's username from the command and convert to lowercase.
-    let username = format!("@{}", me.username());
-    let command = command.trim_end_matches(username.as_str()).to_lowercase();
-    let _params = &text[command_full_len..].trim_start();
+        let (bot, database) = setup().await;
+        let spam_bot = crate::spam_checker::inline_bot_url("SpamDropBot").unwrap();
+        database
+            .add_bot(&spam_bot, IsSpam::Yes, true)
+            .await
+            .unwrap();
 
-    let command_processed: bool = match command.as_str() {
-        "/review" if is_private => handle_review_command(bot, message, database).await?,
-        "/spam" | "/scam" if is_private => {
-            // This is a private messages only handler. This is already run for public messages
-            // differently, to catch non-command suspicions, so running it here would run it twice.
-            gather_suspicion(bot, message, database).await?;
-            true
-        }
-        "/hide_deletes" | "/show_deletes" => {
-            if message.chat.is_private() || !byadmin!() {
-                goodbye!("This command can only be used by admins in group chats.");
-            }
+        handle(
+            &bot,
+            &database,
+            via_bot_message(5, 10, "free nft", "SpamDropBot"),
+        )
+        .await;
+        handle(
+            &bot,
+            &database,
+            via_bot_message(6, 10, "a game", "FineGameBot"),
+        )
+        .await;
 
-            let new_state = command.as_str() == "/hide_deletes";
+        let deleted: Vec<Call> = bot
+            .actions()
+            .into_iter()
+            .filter(|x| matches!(x, Call::DeleteMessage { .. }))
+            .collect();
+        assert_eq!(
+            deleted,
+            [Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(5)
+            }]
+        );
+    }
 
-            let old_state = database
-                .set_hide_deletes(message.chat.id, new_state)
-                .await
-                .expect("Database died!");
+    #[tokio::test]
+    async fn spam_command_offers_marking_inline_bots() {
+        use mock_bot::Call;
 
-            let response = match (old_state, new_state) {
-                (false, false) => "This chat doesn't hide spam deletion notifications already.",
-                (false, true) => concat!(
-                    "I will no longer notify about messages being deleted. ",
-                    "Note that this may lead to confusion in case I delete a ",
-                    "message with a legitimate link due to a false positive. "
-                ),
-                (true, false) => "From now on I will notify about spam messages being deleted.",
-                (true, true) => "This chat has spam delete notifications hidden already.",
-            };
+        let (bot, database) = setup().await;
 
-            goodbye!(response);
+        for (id, via_bot) in [(6, "SpamDropBot"), (8, "SpamDropBot"), (10, "OtherSpamBot")] {
+            let mut report = user_message(id, 10, "/spam");
+            report["reply_to_message"] =
+                via_bot_message(id - 1, 20, &format!("https://sus{}.site/", id), via_bot);
+            handle(&bot, &database, report).await;
         }
-        "/mark_not_spam" | "/mark_url_spam" | "/mark_domain_spam" => {
-            // If it's not a private chat, or no sender,or they're not
-            // in control chat, pretend we do not see it.
-            if !message.chat.is_private() {
-                return Ok(false);
-            }
-            let Some(sender) = message.from() else {
-                return Ok(false);
+        wait_for_review_notices().await;
+
+        let actions = bot.actions();
+        let targets = BotConfig::get().review_notify_targets();
+        // Thank yous for each report, then one notice for every report
+        // and one keyboard with every bot added to it, for each reviewer chat.
+        assert_eq!(actions.len(), 3 + targets.len() * 2);
+        for (i, chat) in targets.iter().enumerate() {
+            let Call::SendMessage { text, chat: to, .. } = &actions[3 + i * 2] else {
+                panic!("Expected a review notice, got {:?}", actions);
             };
-            if !reviews::authenticate_control(bot, sender).await? {
-                return Ok(false);
-            }
+            assert_eq!(to, chat);
+            assert_eq!(text.matches("New link(s)").count(), 3);
+            assert!(text.contains("sent via inline bots: @SpamDropBot, @OtherSpamBot"));
+            assert!(matches!(
+                actions[3 + i * 2 + 1],
+                Call::EditMessageReplyMarkup { removed: false, .. }
+            ));
+        }
+    }
 
-            // Get message "entities".
-            let Some(entities) = message
-                .parse_entities()
-                .or_else(|| message.parse_caption_entities())
-            else {
-                goodbye!("Please specify links. Replies don't count to avoid accidents.");
+    #[tokio::test]
+    async fn marking_inline_bots_as_spam() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        let is_bot_spam = |username: &str| {
+            let url = crate::spam_checker::inline_bot_url(username).unwrap();
+            let database = database.clone();
+            async move { database.is_bot_spam(&url, false).await.unwrap() }
+        };
+        let reply = |bot: &mock_bot::MockBot| {
+            let actions = bot.actions();
+            let Some(Call::SendMessage { text, .. }) = actions.last() else {
+                panic!("Expected a reply, got {:?}", actions);
             };
+            text.clone()
+        };
 
-            let mut response = String::new();
-            let mut wrote_header = false;
+        handle(
+            &bot,
+            &database,
+            private_message(5, 20, "/mark_viabot_spam @SpamDropBot drops spam"),
+        )
+        .await;
+        assert!(reply(&bot).contains("Marked every message sent via"));
+        assert_eq!(is_bot_spam("SpamDropBot").await, Some((IsSpam::Yes, true)));
 
-            // Scan all URLs in the message...
-            for entity in &entities {
-                let Some((mut url, domain)) = get_entity_url_domain(entity) else {
-                    continue;
-                };
+        // Trusted ones can't be marked.
+        handle(
+            &bot,
+            &database,
+            private_message(6, 20, "/mark_viabot_spam @gif"),
+        )
+        .await;
+        assert!(reply(&bot).contains("trusted inline bot"));
 
-                match command.as_str() {
-                    "/mark_not_spam" => {
-                        let action = ReviewResponse::NotSpam(Some(domain), url);
-                        reviews::apply_review_unverified(bot, sender, database, &action).await?;
-                        // Get the URL back lol
-                        url = action.deconstruct().unwrap().1;
+        // Pressing the button sent to reviewers does the same.
+        let query: CallbackQuery = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "from": { "id": 20, "is_bot": false, "first_name": "Amogus" },
+            "chat_instance": "1",
+            "data": "VIABOT SPAM OtherSpamBot",
+            "message": message(7, None),
+        }))
+        .unwrap();
+        assert_eq!(is_bot_spam("OtherSpamBot").await, None);
+        handle_via_bot_query(&bot, &query, &database, "SPAM OtherSpamBot")
+            .await
+            .unwrap();
+        assert_eq!(is_bot_spam("OtherSpamBot").await, Some((IsSpam::Yes, true)));
 
-                        if !wrote_header {
-                            response.push_str("Marked as not spam:\n");
-                            wrote_header = true;
-                        }
-                    }
-                    "/mark_url_spam" => {
-                        let action = ReviewResponse::UrlSpam(Some(domain), url);
-                        reviews::apply_review_unverified(bot, sender, database, &action).await?;
-                        // Get the URL back lol
-                        url = action.deconstruct().unwrap().1;
+        let actions = bot.actions();
+        assert_eq!(
+            actions[actions.len() - 2..],
+            [
+                Call::AnswerCallbackQuery {
+                    text: "Marked every message sent via @OtherSpamBot as spam.".to_string()
+                },
+                Call::EditMessageReplyMarkup {
+                    chat: CHAT,
+                    message: MessageId(7),
+                    removed: true,
+                    buttons: Vec::new(),
+                },
+            ]
+        );
+    }
 
-                        if !wrote_header {
-                            response.push_str("Marked these URLs as spam:\n");
-                            wrote_header = true;
-                        }
-                    }
-                    "/mark_domain_spam" => {
-                        let action = ReviewResponse::DomainSpam(domain, url);
-                        reviews::apply_review_unverified(bot, sender, database, &action).await?;
-                        // Get the URL back lol
-                        url = action.deconstruct().unwrap().1;
+    #[tokio::test]
+    async fn marking_one_of_several_inline_bots_keeps_the_notice() {
+        use mock_bot::Call;
 
-                        if !wrote_header {
-                            response.push_str("Marked domains of these URLs as spam:\n");
-                            wrote_header = true;
-                        }
-                    }
-                    _ => unreachable!(),
-                }
+        let (bot, database) = setup().await;
+        let mut notice = message(7, None);
+        notice["text"] = serde_json::json!("New link(s): a\n\nNew link(s): b");
+        notice["reply_markup"] = serde_json::json!({ "inline_keyboard": [
+            [{ "text": "Mark @SpamDropBot as spam", "callback_data": "VIABOT SPAM SpamDropBot" }],
+            [{ "text": "Mark @OtherSpamBot as spam", "callback_data": "VIABOT SPAM OtherSpamBot" }],
+        ]});
+        let query: CallbackQuery = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "from": { "id": 20, "is_bot": false, "first_name": "Amogus" },
+            "chat_instance": "1",
+            "data": "VIABOT SPAM OtherSpamBot",
+            "message": notice,
+        }))
+        .unwrap();
 
-                response.push_str(url.as_str());
-                response.push('\n');
-            }
+        handle_via_bot_query(&bot, &query, &database, "SPAM OtherSpamBot")
+            .await
+            .unwrap();
 
-            if response.is_empty() {
-                goodbye!("Please specify links. Replies don't count to avoid accidents.");
-            }
+        // The text is left alone, and only the pressed button is gone.
+        let actions = bot.actions();
+        assert!(!actions
+            .iter()
+            .any(|x| matches!(x, Call::EditMessageText { .. })));
+        assert_eq!(
+            actions.last(),
+            Some(&Call::EditMessageReplyMarkup {
+                chat: CHAT,
+                message: MessageId(7),
+                removed: false,
+                buttons: vec!["VIABOT SPAM SpamDropBot".to_string()],
+            })
+        );
+    }
 
-            goodbye!(response.as_str());
-        }
-        // Any kind of "/start", "/help" commands would yield false and
-        // hence cause the help message to be printed if this is a private chat.
-        // See definition of handle_private_message.
-        _ => false,
-    };
+    #[tokio::test]
+    async fn first_link_guard_only_hides_links_of_new_members() {
+        use mock_bot::Call;
 
-    Ok(command_processed)
-}
+        let (bot, database) = setup().await;
+        database.set_first_link_guard(CHAT, true).await.unwrap();
+        let legit = "https://totally.legit/";
+        database
+            .add_url(
+                &Url::parse(legit).unwrap(),
+                IsSpam::No,
+                false,
+                true,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let joined = |id: i32, user: u64| {
+            let mut message = user_message(id, user, "");
+            message.as_object_mut().unwrap().remove("text");
+            message["new_chat_members"] = serde_json::json!([
+                { "id": user, "is_bot": false, "first_name": "Amogus" }
+            ]);
+            message
+        };
+        let hidden = |bot: &mock_bot::MockBot, id: i32| {
+            bot.actions().contains(&Call::DeleteMessage {
+                chat: CHAT,
+                message: MessageId(id),
+            })
+        };
 
-pub fn generate_bot_commands() -> Vec<BotCommand> {
-    vec![
-        BotCommand::new("/hide_deletes", "Hide spam deletion notification messages."),
-        BotCommand::new(
-            "/show_deletes",
-            "Don't hide spam deletion notification messages.",
-        ),
-        BotCommand::new("/spam", "Mark links in a message for review as spam."),
-    ]
-}
+        // Someone from before it was enabled.
+        handle(&bot, &database, user_message(5, 10, legit)).await;
+        assert!(!hidden(&bot, 5));
 
-pub async fn handle_private_message(bot: &Bot, message: &Message) -> Result<(), RequestError> {
-    if message.edit_date().is_some() {
-        // Ignore message edits here.
-        return Ok(());
+        handle(&bot, &database, joined(6, 11)).await;
+        handle(&bot, &database, user_message(7, 11, legit)).await;
+        assert!(hidden(&bot, 7));
+
+        // Media can't be posted again, so they're asked to send it again themselves.
+        handle(&bot, &database, joined(8, 12)).await;
+        let mut photo = user_message(9, 12, legit);
+        let object = photo.as_object_mut().unwrap();
+        let entities = object.remove("entities").unwrap();
+        object.remove("text");
+        object.insert("caption".to_string(), legit.into());
+        object.insert("caption_entities".to_string(), entities);
+        object.insert(
+            "photo".to_string(),
+            serde_json::json!([
+                { "file_id": "a", "file_unique_id": "a", "width": 1, "height": 1 }
+            ]),
+        );
+        handle(&bot, &database, photo).await;
+        assert!(hidden(&bot, 9));
+        let actions = bot.actions();
+        let Some(Call::SendMessage { text, .. }) = actions.last() else {
+            panic!("Expected a notice, got {:?}", actions);
+        };
+        assert!(text.contains("to be able to send it again"));
+
+        let query: CallbackQuery = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "from": { "id": 12, "is_bot": false, "first_name": "Amogus" },
+            "chat_instance": "1",
+            "data": "REPOST 2",
+            "message": message(10, None),
+        }))
+        .unwrap();
+        handle_repost_query(&bot, &query, &database, "2")
+            .await
+            .unwrap();
+        assert!(bot.actions().iter().any(|x| matches!(
+            x,
+            Call::EditMessageText { text, .. } if text.contains("can send their message again")
+        )));
     }
-    // Nothing much to do here lol
 
-    bot.send_message(
-        message.chat.id,
-        "
-This bot is made to combat the currently ongoing wave of NFT spam experienced by chats with linked channels.
+    #[tokio::test]
+    async fn stale_review_keyboards_are_tidied_up() {
+        use mock_bot::Call;
 
-To use this bot, add it to a chat and give it administrator status with \"Remove messages\" permission.
+        let (bot, database) = setup().await;
+        let chat = ChatId(5);
+        let url = Url::parse("http://sus.site/claim").unwrap();
+        database
+            .add_url(&url, IsSpam::Maybe, false, false, None, None)
+            .await
+            .unwrap();
+        let (_, table, rowid, ..) = database.get_url_for_review().await.unwrap().unwrap();
+        database
+            .remember_review_keyboard(chat, MessageId(1), table, rowid)
+            .await
+            .unwrap();
 
-No further setup is required. A message will be sent when spam is removed.
+        // Still up for review, and not that old.
+        reviews::sweep_review_keyboards(bot.clone(), database.clone()).await;
+        assert_eq!(bot.actions(), []);
 
-For available commands, type / into the message text box below and see the previews.
+        database.remove_url(&url).await.unwrap();
+        reviews::sweep_review_keyboards(bot.clone(), database.clone()).await;
+        assert_eq!(
+            bot.actions(),
+            [Call::EditMessageReplyMarkup {
+                chat,
+                message: MessageId(1),
+                removed: true,
+                buttons: Vec::new(),
+            }]
+        );
+        assert_eq!(database.get_review_keyboards().await.unwrap(), []);
+    }
 
-If you're in the group for volunteers to manually review chats, you can also use commands here in private chat:
+    #[tokio::test]
+    async fn explaining_changes_nothing() {
+        use mock_bot::Call;
 
-/mark_not_spam, /mark_url_spam and /mark_domain_spam"
-    )
-    .await?;
-    Ok(())
+        let (bot, database) = setup().await;
+        let unknown = Url::parse("https://example.com/some/page").unwrap();
+
+        handle(
+            &bot,
+            &database,
+            private_message(5, 20, &format!("/explain {} {}", unknown, SPAM)),
+        )
+        .await;
+
+        let actions = bot.actions();
+        let Some(Call::SendMessage { text, .. }) = actions.last() else {
+            panic!("No explanation sent: {:?}", actions);
+        };
+        assert!(text.contains("would be checked by visiting it"), "{}", text);
+        assert!(text.contains("would delete"), "{}", text);
+
+        // Nothing was visited or stored for the unknown link.
+        assert_eq!(database.is_spam(&unknown, None, false).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn forwarded_spam_origin_is_shown_to_reviewers() {
+        use mock_bot::Call;
+
+        let (bot, database) = setup().await;
+        let mut forward = user_message(5, 20, "free NFT https://sus5.site/");
+        forward["forward_date"] = 0.into();
+        forward["forward_from"] = serde_json::json!({ "id": 6, "is_bot": false, "first_name": "Spam", "last_name": "Source" });
+        let mut report = user_message(6, 10, "/spam");
+        report["reply_to_message"] = forward;
+        handle(&bot, &database, report).await;
+        wait_for_review_notices().await;
+
+        let notices: Vec<String> = bot
+            .actions()
+            .into_iter()
+            .filter_map(|x| match x {
+                Call::SendMessage { text, .. } if text.contains("New link(s)") => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert!(!notices.is_empty());
+        for notice in notices {
+            assert!(
+                notice.contains("Forwarded from Spam Source: free NFT"),
+                "{}",
+                notice
+            );
+        }
+
+        // It's also in the review log once it's marked as spam.
+        let url = Url::parse("https://sus5.site/").unwrap();
+        let reviewer: User = serde_json::from_value(
+            serde_json::json!({ "id": 30, "is_bot": false, "first_name": "Reviewer" }),
+        )
+        .unwrap();
+        let action = ReviewResponse::UrlSpam(Domain::from_url(&url), url);
+        reviews::apply_review_unverified(&bot, &reviewer, &database, &action, "")
+            .await
+            .unwrap();
+        let Some(Call::SendMessage { chat, text, .. }) = bot.actions().last().cloned() else {
+            panic!("Nothing was logged");
+        };
+        assert_eq!(Some(chat), BotConfig::get().review_log_channel());
+        assert!(
+            text.contains("Context: Forwarded from Spam Source: free NFT"),
+            "{}",
+            text
+        );
+    }
 }
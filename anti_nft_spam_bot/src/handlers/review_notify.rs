@@ -0,0 +1,221 @@
+//! Sending notifications about links added to the review pool in batches.
+//!
+//! During a spam wave, links can be added to the review pool many times a second.
+//! Sending a message to reviewers for each of those would flood their chats
+//! and run into Telegram's rate limits, so notifications are collected for
+//! [`BATCH_WINDOW`] after the first one, and then sent as one message.
+
+use std::time::Duration;
+
+use arch_bot_commons::{bot_api::BotApi, useful_methods::BotArchSendMsg};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use tokio::sync::mpsc;
+
+use crate::{config::BotConfig, database::Database, types::ReviewNotice};
+
+/// How long to wait for more notifications after the first one before sending them.
+pub const BATCH_WINDOW: Duration = Duration::from_secs(5);
+
+/// How many notifications to send at most in one batch. Once there's this many,
+/// they're sent right away instead of waiting for the rest of [`BATCH_WINDOW`].
+const MAX_BATCH_SIZE: usize = 20;
+
+/// Queue this notification to be sent to reviewers along with others that come
+/// shortly after it, starting the task that does that if it isn't running yet.
+pub async fn queue(bot: &impl BotApi, database: &Database, notice: ReviewNotice) {
+    let sender = database
+        .review_notifier(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(send_batches(bot.clone(), receiver));
+            sender
+        })
+        .await;
+
+    // Can only fail if the task died, in which case it's restarted next time.
+    if sender.send(notice).is_err() {
+        log::error!("Failed to queue a notification for reviewers.");
+    }
+}
+
+/// Receive notifications and send them to reviewers in batches,
+/// until every sender is dropped.
+async fn send_batches(bot: impl BotApi, mut receiver: mpsc::UnboundedReceiver<ReviewNotice>) {
+    while let Some(first) = receiver.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::Instant::now() + BATCH_WINDOW;
+
+        while batch.len() < MAX_BATCH_SIZE {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(notice)) => batch.push(notice),
+                // Either the window has passed, or there's nothing more to come.
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let text = combine(&batch);
+        let keyboard = via_bot_keyboard(&batch);
+
+        for chat in BotConfig::get().review_notify_targets() {
+            // We don't care if this fails lmao
+            let Ok(sent) = bot.archsendmsg(*chat, text.as_str(), None).await else {
+                continue;
+            };
+            if let (Some(keyboard), Some(last)) = (&keyboard, sent.last()) {
+                let _ = bot
+                    .edit_message_reply_markup(*chat, last.id, Some(keyboard.clone()))
+                    .await;
+            }
+        }
+    }
+}
+
+/// Make one message out of these notifications, with how many links there
+/// are to review after the latest of them.
+fn combine(batch: &[ReviewNotice]) -> String {
+    let mut text = String::new();
+
+    for notice in batch {
+        text.push_str(&notice.text);
+        // An empty line between notifications, so that long batches are split
+        // into several messages between them rather than in the middle of one.
+        if batch.len() > 1 {
+            text.push('\n');
+        }
+    }
+
+    let via_bots = via_bots(batch);
+    if !via_bots.is_empty() {
+        let via_bots: Vec<String> = via_bots.iter().map(|x| format!("@{}", x)).collect();
+        text.push_str(&format!(
+            "Some of these were sent via inline bots: {}\n",
+            via_bots.join(", ")
+        ));
+    }
+
+    let to_review = batch.last().map_or(0, |x| x.to_review);
+    text.push_str(&format!("There are {} links to review.", to_review));
+    text
+}
+
+/// Usernames of inline bots these notifications mention, without repeats.
+fn via_bots(batch: &[ReviewNotice]) -> Vec<&str> {
+    let mut via_bots: Vec<&str> = Vec::new();
+    for username in batch.iter().flat_map(|x| &x.via_bots) {
+        if !via_bots.contains(&username.as_str()) {
+            via_bots.push(username);
+        }
+    }
+    via_bots
+}
+
+/// Buttons to mark each inline bot these notifications mention as spam, if there are any.
+/// Handled by [`super::handle_via_bot_query`].
+fn via_bot_keyboard(batch: &[ReviewNotice]) -> Option<InlineKeyboardMarkup> {
+    let via_bots = via_bots(batch);
+    if via_bots.is_empty() {
+        return None;
+    }
+
+    Some(InlineKeyboardMarkup::new(via_bots.into_iter().map(
+        |username| {
+            [InlineKeyboardButton::callback(
+                format!("Mark @{} as spam", username),
+                format!("VIABOT SPAM {}", username),
+            )]
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::mock_bot::{Call, MockBot};
+
+    fn notice(text: &str, to_review: u32) -> ReviewNotice {
+        ReviewNotice {
+            text: format!("{}\n", text),
+            to_review,
+            via_bots: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn combining_notices() {
+        // A single one looks the same as before batching.
+        assert_eq!(
+            combine(&[notice("New link(s): a", 1)]),
+            "New link(s): a\nThere are 1 links to review."
+        );
+
+        assert_eq!(
+            combine(&[notice("New link(s): a", 1), notice("New link(s): b", 2)]),
+            "New link(s): a\n\nNew link(s): b\n\nThere are 2 links to review."
+        );
+        assert_eq!(via_bot_keyboard(&[notice("New link(s): a", 1)]), None);
+
+        // Inline bots are mentioned once each, with a button for each.
+        let batch = [
+            ReviewNotice {
+                via_bots: vec!["SpamBot".to_string()],
+                ..notice("New link(s): a", 1)
+            },
+            notice("New link(s): b", 2),
+            ReviewNotice {
+                via_bots: vec!["OtherBot".to_string(), "SpamBot".to_string()],
+                ..notice("New link(s): c", 3)
+            },
+        ];
+        assert!(combine(&batch).ends_with(concat!(
+            "Some of these were sent via inline bots: @SpamBot, @OtherBot\n",
+            "There are 3 links to review."
+        )));
+        let keyboard = via_bot_keyboard(&batch).unwrap();
+        let data: Vec<_> = keyboard
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .map(|x| match &x.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => data.as_str(),
+                _ => panic!("Expected a callback button"),
+            })
+            .collect();
+        assert_eq!(data, ["VIABOT SPAM SpamBot", "VIABOT SPAM OtherBot"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notices_are_batched() {
+        BotConfig::init_default();
+        let bot = MockBot::default();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(send_batches(bot.clone(), receiver));
+
+        for i in 1..=3 {
+            sender.send(notice(&format!("link {}", i), i)).unwrap();
+        }
+        tokio::time::sleep(BATCH_WINDOW * 2).await;
+
+        // Too many at once are split into batches.
+        for i in 0..=MAX_BATCH_SIZE as u32 {
+            sender.send(notice("flood", i)).unwrap();
+        }
+        drop(sender);
+        task.await.unwrap();
+
+        let targets = BotConfig::get().review_notify_targets().len();
+        assert!(targets > 0);
+        let texts: Vec<String> = bot
+            .actions()
+            .into_iter()
+            .filter_map(|x| match x {
+                Call::SendMessage { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(texts.len(), targets * 3);
+        assert!(texts[0].contains("link 1") && texts[0].contains("link 3"));
+        assert!(texts[0].ends_with("There are 3 links to review."));
+        assert_eq!(texts[targets].matches("flood").count(), MAX_BATCH_SIZE);
+        assert_eq!(texts[targets * 2].matches("flood").count(), 1);
+    }
+}
@@ -1,25 +1,33 @@
 use std::sync::Arc;
 
+use arch_bot_commons::bot_api::{BotApi, MessageOptions};
+use chrono::Utc;
 use teloxide::{
-    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
-    requests::Requester,
-    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message, User},
+    types::{
+        CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId,
+        User, UserId,
+    },
     ApiError, Bot, RequestError,
 };
 
 use crate::{
+    config::BotConfig,
     database::Database,
+    spam_checker::telegram_bot_url,
     types::{IsSpam, ReviewResponse},
-    CONTROL_CHAT_ID, REVIEW_LOG_CHANNEL_ID,
 };
 
-/// Check if this user is in the control chat and can do reviews, and
+/// How old a review keyboard has to be to be given a fresh entry
+/// by [`sweep_review_keyboards`], if nobody got to it.
+const REVIEW_KEYBOARD_TIMEOUT: chrono::TimeDelta = chrono::TimeDelta::hours(12);
+
+/// How long to wait between edits in [`sweep_review_keyboards`].
+const REVIEW_KEYBOARD_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Check if this user is in any of the control chats and can do reviews, and
 /// delay their requests if appropriate.
-pub async fn authenticate_control(bot: &Bot, user: &User) -> Result<bool, RequestError> {
-    let control = bot
-        .get_chat_member(CONTROL_CHAT_ID, user.id)
-        .await?
-        .is_present();
+pub async fn authenticate_control(bot: &impl BotApi, user: &User) -> Result<bool, RequestError> {
+    let control = is_member_of_any(bot, BotConfig::get().control_chats(), user.id).await;
     if !control {
         let name = if let Some(username) = &user.username {
             format!("@{}", username)
@@ -61,9 +69,24 @@ pub async fn authenticate_control(bot: &Bot, user: &User) -> Result<bool, Reques
     Ok(control)
 }
 
+/// Check if this user is in any of these chats.
+///
+/// Failing to find out about one chat counts as not being in it, so that
+/// one broken chat doesn't lock out members of the others.
+pub(super) async fn is_member_of_any(bot: &impl BotApi, chats: &[ChatId], user: UserId) -> bool {
+    for chat in chats {
+        match bot.get_chat_member(*chat, user).await {
+            Ok(member) if member.is_present() => return true,
+            Ok(_) => (),
+            Err(e) => log::warn!("Failed to check if {} is in chat {}: {}", user, chat, e),
+        }
+    }
+    false
+}
+
 /// Returns true if the command was processed, or false if it was ignored.
 pub async fn handle_review_command(
-    bot: &Bot,
+    bot: &impl BotApi,
     message: &Message,
     database: &Database,
 ) -> Result<bool, RequestError> {
@@ -81,38 +104,46 @@ pub async fn handle_review_command(
     // Spawn a review keyboard.
 
     let message = bot
-        .send_message(message.chat.id, "Loading review keyboard...")
-        .reply_to_message_id(message.id)
+        .send_message(
+            message.chat.id,
+            "Loading review keyboard...",
+            MessageOptions::default().reply_to(message.id),
+        )
         .await?;
 
-    edit_message_into_a_review(bot, database, &message).await?;
+    edit_message_into_a_review(bot, database, message.chat.id, message.id).await?;
 
     Ok(true)
 }
 
 async fn edit_message_into_a_review(
-    bot: &Bot,
+    bot: &impl BotApi,
     database: &Database,
-    message: &Message,
+    chat: ChatId,
+    message: MessageId,
 ) -> Result<(), RequestError> {
-    let Some((url, table_name, rowid, is_spam)) =
+    let Some((url, table_name, rowid, is_spam, match_reason, context)) =
         database.get_url_for_review().await.expect("Database died!")
     else {
+        database
+            .forget_review_keyboard(chat, message)
+            .await
+            .expect("Database died!");
         bot.edit_message_text(
-            message.chat.id,
-            message.id,
+            chat,
+            message,
             "There are no more URLs to review.",
+            MessageOptions::default().reply_markup(InlineKeyboardMarkup {
+                inline_keyboard: Vec::new(),
+            }),
         )
-        .reply_markup(InlineKeyboardMarkup {
-            inline_keyboard: Vec::new(),
-        })
         .await?;
         return Ok(());
     };
 
     let title = match is_spam {
         IsSpam::Maybe => "<b>REVIEW:</b>\n\n",
-        IsSpam::No | IsSpam::Yes => concat!(
+        IsSpam::No | IsSpam::Yes | IsSpam::Likely | IsSpam::Unlikely => concat!(
             "<b>REHASHING: </b>\n",
             "There are no more URLs to review right now, ",
             "so existing entries are shown to weed out ",
@@ -129,23 +160,57 @@ async fn edit_message_into_a_review(
             "This URL is currently <b>considered as spam</b>, ",
             "but is presented for review in case it's a false positive.\n\n"
         ),
+        IsSpam::Likely => concat!(
+            "This URL is currently considered as <b>likely spam</b>, ",
+            "but is presented for review in case someone is sure about it.\n\n"
+        ),
+        IsSpam::Unlikely => concat!(
+            "This URL is currently considered as <b>likely NOT spam</b>, ",
+            "but is presented for review in case someone is sure about it.\n\n"
+        ),
         IsSpam::Maybe => "",
     };
 
-    let text = format!("{}{}{}\n\nWhat is spam here?", title, considered, url);
+    let reason = match match_reason {
+        Some(reason) => format!("Automatic checker rule: {}\n\n", reason),
+        None => String::new(),
+    };
+
+    let context = match context {
+        Some(_) => format!("{}\n", super::context_line(context.as_deref())),
+        None => String::new(),
+    };
+
+    let text = format!(
+        "{}{}{}{}{}\n\nWhat is spam here?",
+        title, considered, reason, context, url
+    );
+
+    let mut spam_buttons = vec![
+        InlineKeyboardButton::callback(
+            "Just the URL".to_string(),
+            format!("URL_SPAM {} {}", table_name, rowid),
+        ),
+        InlineKeyboardButton::callback(
+            "Entire DOMAIN".to_string(),
+            format!("DOMAIN_SPAM {} {}", table_name, rowid),
+        ),
+    ];
+    // Links to Telegram bots can be marked regardless of their start parameter.
+    if telegram_bot_url(&url).is_some() {
+        spam_buttons.push(InlineKeyboardButton::callback(
+            "Entire BOT".to_string(),
+            format!("BOT_SPAM {} {}", table_name, rowid),
+        ));
+    }
 
     let keyboard = InlineKeyboardMarkup::new(vec![
+        spam_buttons,
         vec![
             InlineKeyboardButton::callback(
-                "Just the URL".to_string(),
-                format!("URL_SPAM {} {}", table_name, rowid),
+                "Likely spam".to_string(),
+                format!("LIKELY_SPAM {} {}", table_name, rowid),
             ),
-            InlineKeyboardButton::callback(
-                "Entire DOMAIN".to_string(),
-                format!("DOMAIN_SPAM {} {}", table_name, rowid),
-            ),
-        ],
-        vec![
             InlineKeyboardButton::callback(
                 "Not spam".to_string(),
                 format!("NOT_SPAM {} {}", table_name, rowid),
@@ -155,30 +220,99 @@ async fn edit_message_into_a_review(
     ]);
 
     let edit_result = bot
-        .edit_message_text(message.chat.id, message.id, text)
-        .parse_mode(teloxide::types::ParseMode::Html)
-        .reply_markup(keyboard)
+        .edit_message_text(
+            chat,
+            message,
+            text,
+            MessageOptions::html().reply_markup(keyboard),
+        )
         .await;
 
     // If we get this error, that means that the message was modified to the
     // exact same thing as it was before. This means we're getting the same thing.
     if let Err(RequestError::Api(ApiError::MessageNotModified)) = edit_result {
+        database
+            .forget_review_keyboard(chat, message)
+            .await
+            .expect("Database died!");
         bot.edit_message_text(
-            message.chat.id,
-            message.id,
+            chat,
+            message,
             "There are no more URLs to review.",
+            MessageOptions::default().reply_markup(InlineKeyboardMarkup {
+                inline_keyboard: Vec::new(),
+            }),
         )
-        .reply_markup(InlineKeyboardMarkup {
-            inline_keyboard: Vec::new(),
-        })
         .await?;
         return Ok(());
     };
 
     edit_result?;
+    database
+        .remember_review_keyboard(chat, message, table_name, rowid)
+        .await
+        .expect("Database died!");
     Ok(())
 }
 
+/// Tidy up review keyboards left over from before the bot was restarted.
+///
+/// Keyboards about entries that were since reviewed or removed get their
+/// buttons taken away, as pressing them would only give a confusing error
+/// or redo a review. Ones older than [`REVIEW_KEYBOARD_TIMEOUT`] are given
+/// a fresh entry to review instead.
+pub async fn sweep_review_keyboards(bot: impl BotApi, database: Arc<Database>) {
+    let keyboards = database
+        .get_review_keyboards()
+        .await
+        .expect("Database died!");
+    let cutoff = Utc::now() - REVIEW_KEYBOARD_TIMEOUT;
+
+    let mut edited = 0;
+    for keyboard in keyboards {
+        if !keyboard.resolved && keyboard.sent_at > cutoff {
+            continue;
+        }
+
+        // Not to run into Telegram's rate limits with lots of these.
+        if edited > 0 {
+            tokio::time::sleep(REVIEW_KEYBOARD_SWEEP_INTERVAL).await;
+        }
+        edited += 1;
+
+        let result = if keyboard.resolved {
+            database
+                .forget_review_keyboard(keyboard.chat, keyboard.message)
+                .await
+                .expect("Database died!");
+            bot.edit_message_reply_markup(keyboard.chat, keyboard.message, None)
+                .await
+                .map(|_| ())
+        } else {
+            edit_message_into_a_review(&bot, &database, keyboard.chat, keyboard.message).await
+        };
+
+        if let Err(e) = result {
+            // The message may have been deleted, or be too old to edit. Either way,
+            // there's nothing more to do about it.
+            log::warn!(
+                "Failed to tidy up review keyboard {} in chat {}: {}",
+                keyboard.message,
+                keyboard.chat,
+                e
+            );
+            database
+                .forget_review_keyboard(keyboard.chat, keyboard.message)
+                .await
+                .expect("Database died!");
+        }
+    }
+
+    if edited > 0 {
+        log::info!("Tidied up {} stale review keyboards.", edited);
+    }
+}
+
 pub async fn parse_callback_query(
     bot: Bot,
     query: CallbackQuery,
@@ -186,19 +320,35 @@ pub async fn parse_callback_query(
 ) -> Result<(), RequestError> {
     macro_rules! goodbye {
         ($text:expr) => {
-            bot.answer_callback_query(query.id).text($text).await?;
+            bot.answer_callback_query(query.id, $text).await?;
             return Ok(());
         };
         () => {
-            bot.answer_callback_query(query.id).await?;
+            bot.answer_callback_query(query.id, "").await?;
             return Ok(());
         };
     }
 
-    let Some(query_data) = query.data else {
+    let Some(query_data) = query.data.clone() else {
         goodbye!("No query data.");
     };
 
+    if let Some(data) = query_data.strip_prefix("CANCEL_DELETE ") {
+        return super::handle_cancel_deletion_query(&bot, &query, &db, data).await;
+    }
+    if let Some(data) = query_data.strip_prefix("REPOST ") {
+        return super::handle_repost_query(&bot, &query, &db, data).await;
+    }
+    if let Some(data) = query_data.strip_prefix("HEURISTIC ") {
+        return super::handle_heuristic_query(&bot, &query, &db, data).await;
+    }
+    if let Some(data) = query_data.strip_prefix("PATTERN ") {
+        return super::handle_pattern_query(&bot, &query, &db, data).await;
+    }
+    if let Some(data) = query_data.strip_prefix("VIABOT ") {
+        return super::handle_via_bot_query(&bot, &query, &db, data).await;
+    }
+
     let user = query.from;
 
     let response = match ReviewResponse::from_str(query_data.as_str(), &db).await {
@@ -217,7 +367,7 @@ pub async fn parse_callback_query(
         goodbye!("Review taken. Please send /review to perform more reviews.");
     };
 
-    edit_message_into_a_review(&bot, &db, &message).await?;
+    edit_message_into_a_review(&bot, &db, message.chat.id, message.id).await?;
     goodbye!();
 }
 
@@ -227,7 +377,7 @@ pub async fn parse_callback_query(
 ///
 /// If `verify_user` is set to `false`, it will always return true.
 pub async fn apply_review(
-    bot: &Bot,
+    bot: &impl BotApi,
     user: &User,
     db: &Database,
     response: &ReviewResponse,
@@ -236,18 +386,20 @@ pub async fn apply_review(
         return Ok(false);
     }
 
-    apply_review_unverified(bot, user, db, response).await?;
+    apply_review_unverified(bot, user, db, response, "").await?;
     Ok(true)
 }
 
 /// Apply this review response as coming from this user.
+/// A non-empty `reason` is included in the review log.
 ///
 /// Will not check if this user actually is in control chat.
 pub async fn apply_review_unverified(
-    bot: &Bot,
+    bot: &impl BotApi,
     user: &User,
     db: &Database,
     response: &ReviewResponse,
+    reason: &str,
 ) -> Result<(), RequestError> {
     // See if it should be written into the log...
     let should_be_logged = response
@@ -255,15 +407,71 @@ pub async fn apply_review_unverified(
         .await
         .expect("Database died!");
 
+    // Before the review overwrites it, see if the automatic checker had a say in this.
+    let match_reason = match response {
+        ReviewResponse::UrlSpam(domain, url)
+        | ReviewResponse::BotSpam(domain, url)
+        | ReviewResponse::NotSpam(domain, url)
+        | ReviewResponse::LikelySpam(domain, url) => db
+            .get_match_reason(url, domain.as_ref())
+            .await
+            .expect("Database died!"),
+        ReviewResponse::DomainSpam(domain, url) => db
+            .get_match_reason(url, domain)
+            .await
+            .expect("Database died!"),
+        ReviewResponse::Skip => None,
+    };
+
+    // Along with what it was sent to review with, like who forwarded spam came from.
+    let context = match response {
+        ReviewResponse::UrlSpam(domain, url)
+        | ReviewResponse::BotSpam(domain, url)
+        | ReviewResponse::NotSpam(domain, url)
+        | ReviewResponse::LikelySpam(domain, url) => db
+            .get_review_context(url, domain.as_ref())
+            .await
+            .expect("Database died!"),
+        ReviewResponse::DomainSpam(domain, url) => db
+            .get_review_context(url, Some(domain))
+            .await
+            .expect("Database died!"),
+        ReviewResponse::Skip => None,
+    };
+
     // Ingest it into the database...
     db.read_review_response(response)
         .await
         .expect("Database died!");
 
+    // Don't let the spam checker go off of what it knew before this review.
+    let domain = match response {
+        ReviewResponse::UrlSpam(domain, _)
+        | ReviewResponse::BotSpam(domain, _)
+        | ReviewResponse::NotSpam(domain, _)
+        | ReviewResponse::LikelySpam(domain, _) => domain.as_ref(),
+        ReviewResponse::DomainSpam(domain, _) => Some(domain),
+        ReviewResponse::Skip => None,
+    };
+    if let Some(domain) = domain {
+        db.forget_cached_spam_domain(domain).await;
+    }
+    db.record_reviewer_action(user.id, user.username.as_deref(), response)
+        .await
+        .expect("Database died!");
+
+    // Share it with other instances of the bot. Not a big deal if this fails.
+    if let Err(e) = super::sync::publish_review(bot, response).await {
+        log::error!("Failed to publish a review to the sync channel: {}", e);
+    }
+
     // Write it to the log...
     if should_be_logged {
         // Something wasn't marked as spam, but now will be.
-        // This warrants logging.
+        // This warrants logging, if there's somewhere to log to.
+        let Some(log_channel) = BotConfig::get().review_log_channel() else {
+            return Ok(());
+        };
 
         let name = if let Some(username) = &user.username {
             format!("@{}", username)
@@ -271,11 +479,26 @@ pub async fn apply_review_unverified(
             user.full_name()
         };
 
-        let log_message = format!("{} (userid {})\n{}", name, user.id, response);
+        let mut log_message = format!("{} (userid {})\n{}", name, user.id, response);
+
+        if let Some(match_reason) = match_reason {
+            log_message.push_str(&format!("\nAutomatic checker rule was: {}", match_reason));
+        }
+
+        if let Some(context) = context {
+            log_message.push_str(&format!("\nContext: {}", context));
+        }
 
-        bot.send_message(REVIEW_LOG_CHANNEL_ID, log_message)
-            .disable_web_page_preview(true)
-            .await?;
+        if !reason.is_empty() {
+            log_message.push_str(&format!("\nReason: {}", reason));
+        }
+
+        bot.send_message(
+            log_channel,
+            log_message,
+            MessageOptions::default().disable_web_page_preview(),
+        )
+        .await?;
     }
     Ok(())
 }
@@ -0,0 +1,226 @@
+//! Sharing manual reviews of URLs between instances of this bot.
+//!
+//! If [`SYNC_CHANNEL_ID`] is set, every manual review is posted to that channel
+//! in a machine-readable format, and reviews posted there by other instances
+//! are ingested as if they were determined automatically, so local manual
+//! reviews always take priority.
+//!
+//! A post looks like this:
+//!
+//! ```text
+//! ANTISYNC 0123456789abcdef
+//! URL_SPAM https://example.com/spam
+//! ```
+//!
+//! The second word of the first line is the ID of the instance that posted it,
+//! used to not ingest its own posts. Actions are the same as in review callbacks.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, OnceLock},
+};
+
+use arch_bot_commons::bot_api::{BotApi, MessageOptions};
+use html_escape::encode_text;
+use teloxide::{prelude::*, RequestError};
+
+use crate::{
+    database::Database,
+    parse_url_like_telegram,
+    types::{Domain, ReviewResponse},
+    SYNC_CHANNEL_ID,
+};
+
+/// First word of every sync post.
+const SYNC_HEADER: &str = "ANTISYNC";
+
+/// A random ID of this running instance of the bot.
+fn instance_id() -> &'static str {
+    static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+    INSTANCE_ID.get_or_init(|| {
+        // Randomly seeded by the standard library. Good enough for this.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        format!("{:016x}", hasher.finish())
+    })
+}
+
+/// Make a line describing this review response, or [`None`] if there's nothing to share.
+fn encode_review(response: &ReviewResponse) -> Option<String> {
+    Some(format!("{} {}", response.action()?, response.url()?))
+}
+
+/// Parse a sync post into the ID of the instance that posted it and review responses in it.
+///
+/// # Errors
+/// Returns a description of what's wrong if the post is malformed.
+fn parse_post(text: &str) -> Result<(&str, Vec<ReviewResponse>), String> {
+    let mut lines = text.lines();
+
+    let header = lines.next().ok_or("Empty post")?;
+    let mut header = header.split_ascii_whitespace();
+    if header.next() != Some(SYNC_HEADER) {
+        Err("No sync header")?;
+    }
+    let instance = header.next().ok_or("No instance ID")?;
+    if header.next().is_some() {
+        Err("Extraneous data in header")?;
+    }
+
+    let mut responses = Vec::new();
+
+    for line in lines.filter(|x| !x.trim().is_empty()) {
+        let mut words = line.split_ascii_whitespace();
+        let action = words.next().ok_or("Empty line")?;
+        let url = words
+            .next()
+            .ok_or_else(|| format!("No URL in line: {}", line))?;
+        if words.next().is_some() {
+            Err(format!("Extraneous data in line: {}", line))?;
+        }
+
+        let url = parse_url_like_telegram(url).map_err(|_| format!("Bad URL: {}", url))?;
+        let domain = Domain::from_url(&url);
+
+        let response = match action {
+            "URL_SPAM" => ReviewResponse::UrlSpam(domain, url),
+            "DOMAIN_SPAM" => ReviewResponse::DomainSpam(
+                domain.ok_or_else(|| format!("No domain in URL: {}", url))?,
+                url,
+            ),
+            "BOT_SPAM" => ReviewResponse::BotSpam(domain, url),
+            "NOT_SPAM" => ReviewResponse::NotSpam(domain, url),
+            "LIKELY_SPAM" => ReviewResponse::LikelySpam(domain, url),
+            _ => Err(format!("Unknown action: {}", action))?,
+        };
+
+        responses.push(response);
+    }
+
+    if responses.is_empty() {
+        Err("No reviews in post")?;
+    }
+
+    Ok((instance, responses))
+}
+
+/// Post this review response to the sync channel, if there is one.
+pub async fn publish_review(
+    bot: &impl BotApi,
+    response: &ReviewResponse,
+) -> Result<(), RequestError> {
+    let Some(channel) = SYNC_CHANNEL_ID else {
+        return Ok(());
+    };
+    let Some(line) = encode_review(response) else {
+        return Ok(());
+    };
+
+    let text = format!("{} {}\n{}", SYNC_HEADER, instance_id(), line);
+
+    bot.send_message(
+        channel,
+        format!("<pre>{}</pre>", encode_text(&text)),
+        MessageOptions::html().disable_web_page_preview(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Ingest review responses posted to the sync channel by other instances.
+pub async fn handle_channel_post(
+    message: Message,
+    database: Arc<Database>,
+) -> Result<(), RequestError> {
+    if Some(message.chat.id) != SYNC_CHANNEL_ID {
+        return Ok(());
+    }
+    let Some(text) = message.text() else {
+        return Ok(());
+    };
+
+    let (instance, responses) = match parse_post(text) {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Ignoring malformed sync post {}: {}", message.id, e);
+            return Ok(());
+        }
+    };
+
+    if instance == instance_id() {
+        // That's us lol
+        return Ok(());
+    }
+
+    for response in &responses {
+        log::debug!("Ingesting synced review from {}: {}", instance, response);
+        database
+            .read_synced_review_response(response)
+            .await
+            .expect("Database died!");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_post_round_trip() {
+        let url = parse_url_like_telegram("https://amogus.com/sus").unwrap();
+        let domain = Domain::from_url(&url);
+        let response = ReviewResponse::DomainSpam(domain.clone().unwrap(), url.clone());
+
+        let text = format!(
+            "{} {}\n{}",
+            SYNC_HEADER,
+            instance_id(),
+            encode_review(&response).unwrap()
+        );
+
+        let (instance, responses) = parse_post(&text).unwrap();
+        assert_eq!(instance, instance_id());
+        assert_eq!(responses.len(), 1);
+        let ReviewResponse::DomainSpam(parsed_domain, parsed_url) = &responses[0] else {
+            panic!("Wrong action parsed: {:?}", responses[0]);
+        };
+        assert_eq!(Some(parsed_domain), domain.as_ref());
+        assert_eq!(parsed_url, &url);
+
+        assert!(encode_review(&ReviewResponse::Skip).is_none());
+
+        let url = parse_url_like_telegram("https://t.me/SomeBot?start=ref123").unwrap();
+        let response = ReviewResponse::BotSpam(Domain::from_url(&url), url.clone());
+        let text = format!(
+            "{} {}\n{}",
+            SYNC_HEADER,
+            instance_id(),
+            encode_review(&response).unwrap()
+        );
+        let (_, responses) = parse_post(&text).unwrap();
+        let [ReviewResponse::BotSpam(_, parsed_url)] = &responses[..] else {
+            panic!("Wrong action parsed: {:?}", responses);
+        };
+        assert_eq!(parsed_url, &url);
+    }
+
+    #[test]
+    fn malformed_sync_posts() {
+        for text in [
+            "",
+            "hello everyone",
+            "ANTISYNC",
+            "ANTISYNC abc",
+            "ANTISYNC abc\nURL_SPAM",
+            "ANTISYNC abc\nURL_SPAM https://a.com extra",
+            "ANTISYNC abc\nMAYBE_SPAM https://a.com",
+            "ANTISYNC abc\nURL_SPAM https://a.com\nNOT_SPAM",
+        ] {
+            assert!(parse_post(text).is_err(), "{:?} was parsed", text);
+        }
+    }
+}
@@ -1,6 +1,8 @@
+mod config;
 mod database;
 mod entry;
 mod handlers;
+mod misc;
 mod spam_checker;
 mod types;
 
@@ -9,14 +11,9 @@ pub use entry::*;
 use teloxide::types::ChatId;
 use url::Url;
 
-/// An ID of a private chat with the developers of the bot,
-/// as well as volunteers who partake in manual review of links for spam.
-pub static CONTROL_CHAT_ID: ChatId = ChatId(-1002065680710);
-
-/// An ID of a private channel used for logging manual reviews of URLs.
-/// This is primarily to spot abuse and to note which URLs the bot
-/// could have caught automatically but did not.
-pub static REVIEW_LOG_CHANNEL_ID: ChatId = ChatId(-1002128704357);
+/// An ID of a channel used to share manual reviews of URLs with other instances
+/// of this bot, or [`None`] to not do that. See [`handlers::sync`] for details.
+pub static SYNC_CHANNEL_ID: Option<ChatId> = None;
 
 /// Try to parse a string as a [`Url`] in a way that telegram parses it,
 /// with allowing an implicit `http://` prefix.
@@ -25,6 +22,20 @@ pub static REVIEW_LOG_CHANNEL_ID: ChatId = ChatId(-1002128704357);
 /// Errors if it fails to parse either way.
 pub fn parse_url_like_telegram(string: &str) -> Result<Url, url::ParseError> {
     match Url::parse(string) {
+        // Something like "example.com:8443/path" parses with "example.com" as the scheme,
+        // and "user:pass@example.com" parses with "user" as the scheme.
+        Ok(url)
+            if url.cannot_be_a_base()
+                && !matches!(url.scheme(), "mailto" | "tel")
+                && (url.path().starts_with(|x: char| x.is_ascii_digit())
+                    || url
+                        .path()
+                        .split('/')
+                        .next()
+                        .is_some_and(|x| x.contains('@'))) =>
+        {
+            Ok(Url::parse(&format!("http://{}", string)).unwrap_or(url))
+        }
         Ok(url) => Ok(url),
         Err(e) => {
             // We want to return this original error if the next step fails.
@@ -0,0 +1,235 @@
+//! Bits and bobs that don't fit anywhere else.
+
+use url::Url;
+
+use crate::parse_url_like_telegram;
+
+/// Characters that don't show up in text, but break up links so Telegram doesn't see them.
+const INVISIBLE_CHARS: &[char] = &[
+    '\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}', '\u{AD}',
+];
+
+/// Ways of writing a dot so Telegram doesn't see a link.
+const DOT_OBFUSCATIONS: &[&str] = &["(dot)", "[dot]", "{dot}", "<dot>", "(.)", "[.]", "{.}"];
+
+/// Other ways of breaking up links, and what they stand for.
+const OTHER_OBFUSCATIONS: &[(&str, &str)] = &[("hxxp", "http"), ("[:]", ":"), ("[/]", "/")];
+
+/// Top level domains common in spam and unlikely to start a sentence, to recognize links split
+/// by spaces around a dot, like "example .com", without seeing links in "It. Then".
+const SPLIT_LINK_TLDS: &[&str] = &[
+    "com", "net", "org", "io", "xyz", "ru", "gg", "cc", "biz", "info", "site", "online", "app",
+    "vip",
+];
+
+/// Characters around a link that aren't a part of it.
+const SURROUNDING_PUNCTUATION: &[char] = &[
+    '"', '\'', '“', '”', '‘', '’', '«', '»', '(', ')', '[', ']', '{', '}', '<', '>', ',', ';', '!',
+    '?', '*', '_', '~',
+];
+
+/// Replace all occurrences of an ASCII `pattern` in `text`, ignoring case.
+fn replace_ignore_ascii_case(text: &str, pattern: &str, with: &str) -> String {
+    // ASCII lowercasing keeps byte offsets the same.
+    let lowercase = text.to_ascii_lowercase();
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in lowercase.match_indices(pattern) {
+        result.push_str(&text[last..start]);
+        result.push_str(with);
+        last = start + pattern.len();
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// Undo obfuscations within a single word. Returns it as is if there were none.
+fn deobfuscate_word(word: &str) -> String {
+    let mut word: String = word
+        .chars()
+        .filter(|x| !INVISIBLE_CHARS.contains(x))
+        .collect();
+    for pattern in DOT_OBFUSCATIONS {
+        word = replace_ignore_ascii_case(&word, pattern, ".");
+    }
+    for (pattern, with) in OTHER_OBFUSCATIONS {
+        word = replace_ignore_ascii_case(&word, pattern, with);
+    }
+    word
+}
+
+/// Returns `true` if this starts with one of [`SPLIT_LINK_TLDS`], and nothing else
+/// that could be a part of a domain name follows it.
+fn starts_with_split_link_tld(text: &str) -> bool {
+    let end = text
+        .find(|x: char| !x.is_ascii_alphanumeric() && x != '-')
+        .unwrap_or(text.len());
+    SPLIT_LINK_TLDS.contains(&&text[..end])
+}
+
+/// Returns `true` if these two words are likely halves of a link split by a space around a dot.
+/// `obfuscated` is whether either of them had obfuscations undone.
+fn is_split_link(left: &str, right: &str, obfuscated: bool) -> bool {
+    let ends_in_name = |x: &str| x.ends_with(|x: char| x.is_alphanumeric());
+    let starts_with_name = |x: &str| x.starts_with(|x: char| x.is_alphanumeric());
+
+    if let Some(left) = left.strip_suffix('.') {
+        // "example. com"
+        ends_in_name(left)
+            && starts_with_name(right)
+            && (obfuscated || starts_with_split_link_tld(right))
+    } else if let Some(right) = right.strip_prefix('.') {
+        // "example .com", or "example (dot) com", where the dot is a word of its own.
+        ends_in_name(left)
+            && ((obfuscated && right.is_empty())
+                || (starts_with_name(right) && (obfuscated || starts_with_split_link_tld(right))))
+    } else {
+        false
+    }
+}
+
+/// Find links in this text that were written so Telegram doesn't see them, like
+/// `example(dot)com`, `hxxps://example[.]com` or `example .com`.
+///
+/// Links written plainly are not returned, as Telegram sees those already.
+/// This can see links where there are none, so it's only for when someone
+/// says the text is spam, and not to delete anything by.
+pub fn recover_defanged_urls(text: &str) -> Vec<Url> {
+    // Each word, and whether obfuscations were undone in it.
+    let mut words: Vec<(String, bool)> = Vec::new();
+
+    for word in text.split_whitespace() {
+        let deobfuscated = deobfuscate_word(word);
+        let obfuscated = deobfuscated != word;
+        if deobfuscated.is_empty() {
+            continue;
+        }
+
+        if let Some((last, last_obfuscated)) = words.last_mut() {
+            if is_split_link(last, &deobfuscated, obfuscated || *last_obfuscated) {
+                last.push_str(&deobfuscated);
+                // Joining the halves is undoing an obfuscation too. But a dot on its own
+                // is only a link if what comes after it looks like a domain name.
+                if deobfuscated != "." {
+                    *last_obfuscated = true;
+                }
+                continue;
+            }
+        }
+        words.push((deobfuscated, obfuscated));
+    }
+
+    let mut urls: Vec<Url> = Vec::new();
+
+    for (word, obfuscated) in words {
+        if !obfuscated {
+            continue;
+        }
+
+        let word =
+            word.trim_matches(|x| SURROUNDING_PUNCTUATION.contains(&x) || x == '.' || x == ':');
+
+        let Ok(url) = parse_url_like_telegram(word) else {
+            continue;
+        };
+
+        if !matches!(url.scheme(), "http" | "https") || !url.username().is_empty() {
+            continue;
+        }
+
+        // Needs to look like a domain name, with a top level domain made of letters.
+        let Some(host) = url.host_str() else {
+            continue;
+        };
+        let Some((name, tld)) = host.rsplit_once('.') else {
+            continue;
+        };
+        if name.is_empty()
+            || name.split('.').any(str::is_empty)
+            || tld.chars().count() < 2
+            || !tld.chars().all(char::is_alphabetic)
+        {
+            continue;
+        }
+
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recovered(text: &str) -> Vec<String> {
+        recover_defanged_urls(text)
+            .into_iter()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn obfuscated_spam() {
+        let corpus = [
+            ("visit foo(dot)com now", "http://foo.com/"),
+            ("visit foo (dot) com now", "http://foo.com/"),
+            ("claim at FOO[DOT]COM!!", "http://foo.com/"),
+            ("hxxps://bad.site/claim", "https://bad.site/claim"),
+            ("HXXP://bad[.]site", "http://bad.site/"),
+            ("go to bad .site for free nft", "http://bad.site/"),
+            ("go to bad. site for free nft", "http://bad.site/"),
+            ("airdrop on bad\u{200B}.site", "http://bad.site/"),
+            (
+                "\"free-nft[.]xyz/mint?ref=1\"",
+                "http://free-nft.xyz/mint?ref=1",
+            ),
+            ("(sus{.}io).", "http://sus.io/"),
+            (
+                "https[:]//wallet-connect(.)app/sync",
+                "https://wallet-connect.app/sync",
+            ),
+            ("sub.bad(dot)ru/a/b", "http://sub.bad.ru/a/b"),
+        ];
+
+        for (text, url) in corpus {
+            assert_eq!(recovered(text), [url], "{}", text);
+        }
+
+        assert_eq!(
+            recovered("foo(dot)com and bar[.]net, also foo(dot)com"),
+            ["http://foo.com/", "http://bar.net/"]
+        );
+    }
+
+    #[test]
+    fn legitimate_sentences() {
+        let corpus = [
+            "I liked it. Then we left.",
+            "See you at 5. Com'on, don't be late",
+            "Check out example.com, it's nice",
+            "https://example.com/path",
+            "Version 1.2 is out (.) lol",
+            "The [.] operator and the (dot) product",
+            "hxxp isn't a word",
+            "mail me at someone@example.com",
+            "Wait... what.",
+            "e.g. this, i.e. that",
+            "3 . 14 is pi",
+            "dots (.) and [dot] and {dot}",
+            "\u{200B}hello\u{200B} world",
+            "it's a . b test",
+        ];
+
+        for text in corpus {
+            assert!(
+                recovered(text).is_empty(),
+                "{}: {:?}",
+                text,
+                recovered(text)
+            );
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Automatic checks of whether a link is spam, run in order when the database
+//! doesn't know about it. Each one can be turned off from the control chat
+//! with `/heuristics` in case it starts misbehaving.
+
+use std::collections::HashSet;
+
+use url::Url;
+
+use super::{american_groundhog_spam, is_telegram_url, nft_spam, IsSpamCheckResult};
+use crate::types::{IsSpam, MatchReason};
+
+/// What a visit to a page found.
+#[derive(Debug, Default, Clone)]
+pub struct VisitedPage {
+    pub text: String,
+    pub status_forbidden: bool,
+    pub header_powered_by: bool,
+    pub header_cf_ray: bool,
+    pub header_cache: bool,
+}
+
+/// What a heuristic gets to look at.
+pub struct HeuristicInput<'a> {
+    pub url: &'a Url,
+    /// [`None`] when heuristics are run before visiting the page.
+    pub page: Option<&'a VisitedPage>,
+}
+
+/// What a heuristic decided, and which rule it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeuristicVerdict {
+    pub result: IsSpamCheckResult,
+    pub reason: MatchReason,
+}
+
+impl HeuristicVerdict {
+    fn new(result: IsSpamCheckResult, reason: MatchReason) -> Option<Self> {
+        Some(Self { result, reason })
+    }
+}
+
+pub trait SpamHeuristic: Send + Sync {
+    /// Short name to refer to this heuristic by in the control chat and the database.
+    fn name(&self) -> &'static str;
+    /// What this heuristic looks for, for the control chat.
+    fn description(&self) -> &'static str;
+    /// Returns [`None`] if this heuristic has nothing to say about this link.
+    fn evaluate(&self, input: &HeuristicInput) -> Option<HeuristicVerdict>;
+}
+
+/// All heuristics, in the order they are evaluated.
+pub static HEURISTICS: &[&dyn SpamHeuristic] = &[
+    &TelegramBotUrl,
+    &CloudflareCaptcha,
+    &NftHtml,
+    &AmericanGroundhogHtml,
+];
+
+/// Get the verdict of the first heuristic that has one, skipping the `disabled` ones.
+pub fn evaluate(disabled: &HashSet<String>, input: &HeuristicInput) -> Option<HeuristicVerdict> {
+    HEURISTICS
+        .iter()
+        .filter(|x| !disabled.contains(x.name()))
+        .find_map(|x| x.evaluate(input))
+}
+
+/// Links to Telegram bots that look like referral spam. Doesn't need a visit.
+pub struct TelegramBotUrl;
+
+impl SpamHeuristic for TelegramBotUrl {
+    fn name(&self) -> &'static str {
+        "telegram_bot_url"
+    }
+    fn description(&self) -> &'static str {
+        "Links to Telegram bots that look like referral spam."
+    }
+    fn evaluate(&self, input: &HeuristicInput) -> Option<HeuristicVerdict> {
+        if input.page.is_some() {
+            return None;
+        }
+
+        let result = match nft_spam::is_spam_telegram_url(input.url)? {
+            IsSpam::Yes => IsSpamCheckResult::YesUrl,
+            IsSpam::Maybe | IsSpam::Likely => IsSpamCheckResult::Maybe,
+            IsSpam::No | IsSpam::Unlikely => return None,
+        };
+        HeuristicVerdict::new(result, MatchReason::TelegramUrlHeuristic)
+    }
+}
+
+/// Pages showing a Cloudflare captcha. Real ones mean the page couldn't be checked,
+/// and fake ones are a favorite of scam pages.
+pub struct CloudflareCaptcha;
+
+impl SpamHeuristic for CloudflareCaptcha {
+    fn name(&self) -> &'static str {
+        "cloudflare_captcha"
+    }
+    fn description(&self) -> &'static str {
+        "Pages with a fake Cloudflare captcha, or a real one that can't be looked past."
+    }
+    fn evaluate(&self, input: &HeuristicInput) -> Option<HeuristicVerdict> {
+        let page = input.page?;
+        let text = &page.text;
+
+        if !((text.contains("<title>Just a moment...</title>")
+            && text.contains("Enable JavaScript and cookies to continue"))
+            || text.contains("Attention Required! | Cloudflare")
+            || (text.contains("cloudflare") && text.contains("erify that you are a human")))
+        {
+            return None;
+        }
+
+        // Check validity of it being a *real* cloudflare captcha.
+        if page.status_forbidden
+            && !page.header_powered_by
+            && !page.header_cache
+            && page.header_cf_ray
+        {
+            // Good enough lol
+            return HeuristicVerdict::new(IsSpamCheckResult::Maybe, MatchReason::RealCaptcha);
+        }
+
+        // Fake cloudflare captcha.
+        // Can't believe we got lied to. So sad :(
+        HeuristicVerdict::new(IsSpamCheckResult::YesUrl, MatchReason::FakeCaptcha)
+    }
+}
+
+/// Pages with crypto wallet drainer scripts. Marks the whole domain.
+pub struct NftHtml;
+
+impl SpamHeuristic for NftHtml {
+    fn name(&self) -> &'static str {
+        "nft_html"
+    }
+    fn description(&self) -> &'static str {
+        "Pages with crypto wallet drainer scripts. Marks the whole domain."
+    }
+    fn evaluate(&self, input: &HeuristicInput) -> Option<HeuristicVerdict> {
+        if !nft_spam::is_spam_html(&input.page?.text) {
+            return None;
+        }
+        HeuristicVerdict::new(IsSpamCheckResult::YesDomain, MatchReason::NftHtml)
+    }
+}
+
+/// Telegram pages of channels known to spread spam.
+pub struct AmericanGroundhogHtml;
+
+impl SpamHeuristic for AmericanGroundhogHtml {
+    fn name(&self) -> &'static str {
+        "telegram_html"
+    }
+    fn description(&self) -> &'static str {
+        "Telegram pages of channels known to spread spam."
+    }
+    fn evaluate(&self, input: &HeuristicInput) -> Option<HeuristicVerdict> {
+        let page = input.page?;
+        if !is_telegram_url(input.url)
+            || !american_groundhog_spam::check_spam_telegram_html(&page.text)
+        {
+            return None;
+        }
+        HeuristicVerdict::new(IsSpamCheckResult::YesUrl, MatchReason::TelegramHtml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(text: &str) -> VisitedPage {
+        VisitedPage {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn heuristic_names_are_unique() {
+        let names: HashSet<&str> = HEURISTICS.iter().map(|x| x.name()).collect();
+        assert_eq!(names.len(), HEURISTICS.len());
+    }
+
+    #[test]
+    fn telegram_bot_url() {
+        let spam = url("https://t.me/FawunBot/claim");
+        let input = HeuristicInput {
+            url: &spam,
+            page: None,
+        };
+        assert_eq!(
+            TelegramBotUrl.evaluate(&input),
+            HeuristicVerdict::new(IsSpamCheckResult::YesUrl, MatchReason::TelegramUrlHeuristic)
+        );
+
+        let sus = url("https://t.me/SomeBot/app");
+        let input = HeuristicInput {
+            url: &sus,
+            page: None,
+        };
+        assert_eq!(
+            TelegramBotUrl.evaluate(&input).map(|x| x.result),
+            Some(IsSpamCheckResult::Maybe)
+        );
+
+        let fine = url("https://t.me/Architector_4_Channel");
+        let input = HeuristicInput {
+            url: &fine,
+            page: None,
+        };
+        assert_eq!(TelegramBotUrl.evaluate(&input), None);
+
+        // Only judges by the looks, so it's done by the time the page is visited.
+        let visited = page("");
+        let input = HeuristicInput {
+            url: &spam,
+            page: Some(&visited),
+        };
+        assert_eq!(TelegramBotUrl.evaluate(&input), None);
+    }
+
+    #[test]
+    fn cloudflare_captcha() {
+        let link = url("https://example.com/");
+        let mut captcha =
+            page("<title>Just a moment...</title> Enable JavaScript and cookies to continue");
+        let input = HeuristicInput {
+            url: &link,
+            page: Some(&captcha),
+        };
+        assert_eq!(
+            CloudflareCaptcha.evaluate(&input),
+            HeuristicVerdict::new(IsSpamCheckResult::YesUrl, MatchReason::FakeCaptcha)
+        );
+
+        captcha.status_forbidden = true;
+        captcha.header_cf_ray = true;
+        let input = HeuristicInput {
+            url: &link,
+            page: Some(&captcha),
+        };
+        assert_eq!(
+            CloudflareCaptcha.evaluate(&input),
+            HeuristicVerdict::new(IsSpamCheckResult::Maybe, MatchReason::RealCaptcha)
+        );
+
+        let fine = page("<title>Hello</title>");
+        let input = HeuristicInput {
+            url: &link,
+            page: Some(&fine),
+        };
+        assert_eq!(CloudflareCaptcha.evaluate(&input), None);
+    }
+
+    #[test]
+    fn nft_html() {
+        let link = url("https://example.com/");
+        let drainer = page("<script src=\"/web3.min.js\"></script>");
+        let input = HeuristicInput {
+            url: &link,
+            page: Some(&drainer),
+        };
+        assert_eq!(
+            NftHtml.evaluate(&input),
+            HeuristicVerdict::new(IsSpamCheckResult::YesDomain, MatchReason::NftHtml)
+        );
+
+        let input = HeuristicInput {
+            url: &link,
+            page: None,
+        };
+        assert_eq!(NftHtml.evaluate(&input), None);
+    }
+
+    #[test]
+    fn american_groundhog_html() {
+        let spam = page("<span dir=\"auto\">X Leaks</span>");
+        let telegram = url("https://t.me/+abcdef");
+        let input = HeuristicInput {
+            url: &telegram,
+            page: Some(&spam),
+        };
+        assert_eq!(
+            AmericanGroundhogHtml.evaluate(&input),
+            HeuristicVerdict::new(IsSpamCheckResult::YesUrl, MatchReason::TelegramHtml)
+        );
+
+        // Only trusted on Telegram's own pages.
+        let other = url("https://example.com/");
+        let input = HeuristicInput {
+            url: &other,
+            page: Some(&spam),
+        };
+        assert_eq!(AmericanGroundhogHtml.evaluate(&input), None);
+    }
+
+    #[test]
+    fn disabled_heuristics_are_skipped() {
+        let link = url("https://example.com/");
+        let both = page(concat!(
+            "<title>Just a moment...</title> Enable JavaScript and cookies to continue",
+            "<script src=\"/web3.min.js\"></script>"
+        ));
+        let input = HeuristicInput {
+            url: &link,
+            page: Some(&both),
+        };
+
+        let mut disabled = HashSet::new();
+        assert_eq!(
+            evaluate(&disabled, &input).map(|x| x.reason),
+            Some(MatchReason::FakeCaptcha)
+        );
+
+        disabled.insert(CloudflareCaptcha.name().to_string());
+        assert_eq!(
+            evaluate(&disabled, &input).map(|x| x.reason),
+            Some(MatchReason::NftHtml)
+        );
+
+        disabled.insert(NftHtml.name().to_string());
+        assert_eq!(evaluate(&disabled, &input), None);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt::Display;
+
+use teloxide::types::{MessageEntityKind, MessageEntityRef};
+use url::Url;
+
+/// A message with an invite link and more than this many custom emoji is bait.
+pub const MAX_CUSTOM_EMOJI: usize = 5;
+
+/// A message with an invite link and fewer than this many characters of text
+/// outside of links, emoji and other entities is bait.
+pub const MIN_TEXT_LENGTH: usize = 12;
+
+/// Why [`check_message`] thinks a message is bait for an invite link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteBaitTrigger {
+    /// The message has this many custom emoji.
+    CustomEmoji(usize),
+    /// The message has only this many characters of its own text.
+    ShortText(usize),
+}
+
+impl Display for InviteBaitTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InviteBaitTrigger::CustomEmoji(count) => write!(f, "{} custom emoji", count),
+            InviteBaitTrigger::ShortText(length) => {
+                write!(f, "only {} characters of text", length)
+            }
+        }
+    }
+}
+
+/// Returns true if this is a link to join a private Telegram chat,
+/// like `t.me/+AbCd` or `t.me/joinchat/AbCd`.
+pub fn is_invite_link(url: &Url) -> bool {
+    if !super::is_telegram_url(url) {
+        return false;
+    }
+
+    let path = url.path().trim_start_matches('/');
+    path.starts_with('+') || path.starts_with("%2B") || path.starts_with("joinchat/")
+}
+
+/// Check if a message with this text and entities, which is known to contain an invite link,
+/// consists of little more than premium custom emoji bait around it.
+///
+/// Returns [`None`] if it looks fine.
+pub fn check_message(text: &str, entities: &[MessageEntityRef]) -> Option<InviteBaitTrigger> {
+    let custom_emoji = entities
+        .iter()
+        .filter(|x| matches!(x.kind(), MessageEntityKind::CustomEmoji { .. }))
+        .count();
+
+    if custom_emoji > MAX_CUSTOM_EMOJI {
+        return Some(InviteBaitTrigger::CustomEmoji(custom_emoji));
+    }
+
+    let own_text_length = text
+        .char_indices()
+        .filter(|(i, c)| !c.is_whitespace() && !entities.iter().any(|x| x.range().contains(i)))
+        .count();
+
+    if own_text_length < MIN_TEXT_LENGTH {
+        return Some(InviteBaitTrigger::ShortText(own_text_length));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageEntity;
+    use url::Url;
+
+    use super::*;
+
+    /// Make entities for a message made of these parts, where `Some` parts are
+    /// wrapped in the given entity kind.
+    fn message(parts: &[(&str, Option<MessageEntityKind>)]) -> (String, Vec<MessageEntity>) {
+        let mut text = String::new();
+        let mut entities = Vec::new();
+
+        for (part, kind) in parts {
+            // Entity offsets are in UTF-16 code units.
+            let offset = text.encode_utf16().count();
+            let length = part.encode_utf16().count();
+            text.push_str(part);
+            if let Some(kind) = kind {
+                entities.push(MessageEntity::new(kind.clone(), offset, length));
+            }
+        }
+
+        (text, entities)
+    }
+
+    fn check(parts: &[(&str, Option<MessageEntityKind>)]) -> Option<InviteBaitTrigger> {
+        let (text, entities) = message(parts);
+        check_message(&text, &MessageEntityRef::parse(&text, &entities))
+    }
+
+    fn emoji() -> Option<MessageEntityKind> {
+        Some(MessageEntityKind::CustomEmoji {
+            custom_emoji_id: "5368324170671202286".to_string(),
+        })
+    }
+
+    #[test]
+    fn invite_links() {
+        let is = |x: &str| is_invite_link(&Url::parse(x).unwrap());
+        assert!(is("https://t.me/+AbCdEfGh123"));
+        assert!(is("https://t.me/joinchat/AbCdEfGh123"));
+        assert!(is("https://telegram.me/+AbCdEfGh123"));
+        assert!(!is("https://t.me/Architector_4_Channel"));
+        assert!(!is("https://t.me/"));
+        assert!(!is("https://example.com/+AbCdEfGh123"));
+    }
+
+    #[test]
+    fn bait_messages() {
+        let link = Some(MessageEntityKind::Url);
+
+        // Nothing but emoji and the link.
+        let mut parts = vec![("🔥", emoji()); 8];
+        parts.push(("https://t.me/+AbCdEfGh123", link.clone()));
+        assert_eq!(check(&parts), Some(InviteBaitTrigger::CustomEmoji(8)));
+
+        // Lots of emoji make it bait even with a long text.
+        parts.push((
+            " Join the best chat about anything and everything now!",
+            None,
+        ));
+        assert_eq!(check(&parts), Some(InviteBaitTrigger::CustomEmoji(8)));
+
+        // Barely any text of its own.
+        assert_eq!(
+            check(&[
+                ("💎", emoji()),
+                (" JOIN ", None),
+                ("https://t.me/+AbCdEfGh123", link.clone()),
+                (" 💎", emoji()),
+            ]),
+            Some(InviteBaitTrigger::ShortText(4))
+        );
+
+        // Text of a text link is part of the link, not of the message.
+        assert_eq!(
+            check(&[(
+                "click",
+                Some(MessageEntityKind::TextLink {
+                    url: Url::parse("https://t.me/+AbCdEfGh123").unwrap()
+                })
+            )]),
+            Some(InviteBaitTrigger::ShortText(0))
+        );
+
+        // Someone sharing a link to their chat like a normal person.
+        assert_eq!(
+            check(&[
+                ("Hey, we have a separate chat for offtopic: ", None),
+                ("https://t.me/+AbCdEfGh123", link.clone()),
+                (" ", None),
+                ("🙂", emoji()),
+            ]),
+            None
+        );
+    }
+}
@@ -1,10 +1,17 @@
-use std::{collections::HashSet, sync::Arc, time::Duration};
-use url::Url;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use url::{Host, Url};
 
 use crate::{
     database::Database,
-    types::{Domain, IsSpam},
+    types::{Domain, IsSpam, MatchReason},
 };
+use heuristics::{HeuristicInput, HeuristicVerdict, VisitedPage};
 
 /////// IMPORTANT!!
 /////// IMPORTANT!!
@@ -14,10 +21,14 @@ pub const SPAM_CHECKER_VERSION: u32 = 5;
 
 // Checkers
 mod american_groundhog_spam;
+pub mod heuristics;
+mod invite_bait;
 mod nft_spam;
 
+pub use invite_bait::InviteBaitTrigger;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum IsSpamCheckResult {
+pub enum IsSpamCheckResult {
     No,
     YesUrl,
     YesDomain,
@@ -35,22 +46,168 @@ impl From<IsSpamCheckResult> for IsSpam {
 }
 
 /// Check the link's domain against the database, or by visiting, as needed.
+/// Also returns which rule the result was determined by.
 ///
 /// Returns [`None`] if both checking methods failed.
 pub fn check<'a>(
     database: &'a Arc<Database>,
     domain: &'a Domain,
     url: &'a Url,
-) -> impl std::future::Future<Output = Option<IsSpam>> + 'a {
+) -> impl std::future::Future<Output = Option<(IsSpam, MatchReason)>> + 'a {
     check_inner(database, domain, url, 0)
 }
 
+/// Figure out if this result of [`Database::is_spam`] came from the entry
+/// for the URL itself, the one for its domain, or a pattern of spam hosts.
+async fn database_match_reason(
+    database: &Database,
+    url: &Url,
+    domain: &Domain,
+    is_spam: IsSpam,
+) -> MatchReason {
+    let url_result = database
+        .is_url_spam(url, false)
+        .await
+        .expect("Database died!");
+
+    if url_result.is_some_and(|x| x.0 == is_spam) {
+        return MatchReason::DatabaseUrl;
+    }
+
+    let domain_result = database
+        .is_domain_spam(domain, false)
+        .await
+        .expect("Database died!");
+
+    if is_spam == IsSpam::Yes
+        && domain_result.is_none_or(|x| x.0 != is_spam)
+        && database
+            .matching_url_pattern(domain)
+            .await
+            .expect("Database died!")
+            .is_some()
+    {
+        MatchReason::UrlPattern
+    } else {
+        MatchReason::DatabaseDomain
+    }
+}
+
+/// If this result from the database came from the whole domain being marked as spam,
+/// remember that for a bit. See [`Database::is_cached_spam_domain`].
+///
+/// A domain marked as spam trumps whatever is known about specific URLs on it,
+/// so this holds for all of them. Patterns of spam hosts don't, as they skip
+/// URLs that were manually reviewed, so those aren't remembered.
+async fn cache_if_spam_domain(
+    database: &Database,
+    domain: &Domain,
+    is_spam: IsSpam,
+    reason: MatchReason,
+) {
+    if is_spam == IsSpam::Yes
+        && reason == MatchReason::DatabaseDomain
+        && database
+            .is_domain_spam(domain, false)
+            .await
+            .expect("Database died!")
+            .is_some_and(|x| x.0 == IsSpam::Yes)
+    {
+        database.cache_spam_domain(domain).await;
+    }
+}
+
+/// Decide on this link by what the database already knows about it, as `db_result`
+/// from [`Database::is_spam`]. Returns [`None`] if that isn't enough.
+async fn known_verdict(
+    database: &Database,
+    domain: &Domain,
+    url: &Url,
+    db_result: Option<(IsSpam, bool)>,
+) -> Option<(IsSpam, MatchReason)> {
+    let (db_result, manually_reviewed) = db_result?;
+
+    if manually_reviewed || db_result == IsSpam::Yes {
+        // Manually reviewed, or confirmed spam. Go ahead.
+        let reason = database_match_reason(database, url, domain, db_result).await;
+        return Some((db_result, reason));
+    }
+
+    // It's marked as not spam or maybe spam.
+    // Is this specifically for this URL, or just the general domain result?
+    if let Some(db_result_for_url) = database
+        .is_url_spam(url, false)
+        .await
+        .expect("Database died!")
+    {
+        log::debug!(
+            "Checked {} URL specifically with database and got: {:?}",
+            url,
+            db_result_for_url
+        );
+        return Some((db_result_for_url.0, MatchReason::DatabaseUrl));
+    }
+
+    // No result for the URL specifically, but we are in this branch.
+    // This means `db_result` contains the result for the domain.
+
+    // Assumption: if a domain is marked as not spam or maybe spam,
+    // and a URL is just the domain without a path, then the domain's
+    // result is accurate for that specific URL too.
+
+    // URL crate's "empty path" seems to be just the slash,
+    // but also check for emptystring in case this isn't always true.
+    if url.path() == "/" || url.path().is_empty() {
+        return Some((db_result, MatchReason::DatabaseDomain));
+    }
+
+    None
+}
+
+/// Same as [`check`], but only going by what's already known, without visiting the link
+/// or storing anything about it, like for explaining a decision.
+///
+/// Returns [`None`] if [`check`] would visit it.
+pub async fn check_read_only(
+    database: &Database,
+    domain: &Domain,
+    url: &Url,
+) -> Option<(IsSpam, MatchReason)> {
+    let db_result = database
+        .is_spam(url, Some(domain), false)
+        .await
+        .expect("Database died!");
+    if let Some(verdict) = known_verdict(database, domain, url, db_result).await {
+        return Some(verdict);
+    }
+
+    let disabled_heuristics = database
+        .get_disabled_heuristics()
+        .await
+        .expect("Database died!");
+    let input = HeuristicInput { url, page: None };
+    match heuristics::evaluate(&disabled_heuristics, &input)? {
+        verdict @ HeuristicVerdict {
+            result: IsSpamCheckResult::YesUrl | IsSpamCheckResult::YesDomain,
+            ..
+        } => Some((IsSpam::Yes, verdict.reason)),
+        // Those are still visited to check properly.
+        _ => None,
+    }
+}
+
 async fn check_inner(
     database: &Arc<Database>,
     domain: &Domain,
     url: &Url,
     recursion_depth: u8,
-) -> Option<IsSpam> {
+) -> Option<(IsSpam, MatchReason)> {
+    // Popular spam domains get checked a lot during spam waves. No need to ask every time.
+    if database.is_cached_spam_domain(domain).await {
+        database.url_seen(url);
+        return Some((IsSpam::Yes, MatchReason::DatabaseDomain));
+    }
+
     // Check the database...
     let db_result = database
         .is_spam(url, Some(domain), false)
@@ -67,175 +224,230 @@ async fn check_inner(
         db_result
     );
 
+    if db_result.is_some() {
+        // Keep track of which entries are still in use. See `Database::prune_unseen_urls`.
+        database.url_seen(url);
+    }
+
     if recursion_depth > 1 {
         log::debug!("Recursion level in checker reached...");
         return None;
     }
 
-    if let Some((result, true)) = db_result {
-        // Manually reviewed. Go ahead.
-        return Some(result);
-    };
+    if let Some((is_spam, reason)) = known_verdict(database, domain, url, db_result).await {
+        cache_if_spam_domain(database, domain, is_spam, reason).await;
+        return Some((is_spam, reason));
+    }
 
-    // We now know it's not manually reviewed. Discard that flag.
-    let db_result = db_result.map(|x| x.0);
+    // Why the URL looks like maybe spam, if it does.
+    let mut url_maybe_spam = None;
 
-    if let Some(IsSpam::Yes) = db_result {
-        // Confirmed spam. Just return.
-        Some(IsSpam::Yes)
-    } else {
-        if let Some(db_result) = db_result {
-            // It's marked as not spam or maybe spam.
-            // Was this manually reviewed?
-
-            // Is this specifically for this URL, or just the general domain result?
-            if let Some(db_result_for_url) = database
-                .is_url_spam(url, false)
-                .await
-                .expect("Database died!")
-            {
-                log::debug!(
-                    "Checked {} URL specifically with database and got: {:?}",
-                    url,
-                    db_result_for_url
-                );
-                return Some(db_result_for_url.0);
-            }
+    let disabled_heuristics = database
+        .get_disabled_heuristics()
+        .await
+        .expect("Database died!");
 
-            // No result for the URL specifically, but we are in this branch.
-            // This means `db_result` contains the result for the domain.
+    // All stuff above did not answer anything. Vibe check just the link...
 
-            // Assumption: if a domain is marked as not spam or maybe spam,
-            // and a URL is just the domain without a path, then the domain's
-            // result is accurate for that specific URL too.
+    let input = HeuristicInput { url, page: None };
+    if let Some(verdict) = heuristics::evaluate(&disabled_heuristics, &input) {
+        // Add it to the database.
+        log::debug!(
+            "Checked if URL {} looks like a spam URL and got: {:?}",
+            url,
+            verdict
+        );
 
-            // URL crate's "empty path" seems to be just the slash,
-            // but also check for emptystring in case this isn't always true.
-            if url.path() == "/" || url.path().is_empty() {
-                return Some(db_result);
+        match verdict.result {
+            IsSpamCheckResult::YesUrl => {
+                database
+                    .add_url(
+                        &url_to_store(url),
+                        IsSpam::Yes,
+                        false,
+                        false,
+                        Some(verdict.reason),
+                        None,
+                    )
+                    .await
+                    .expect("Database died!");
+                return Some((IsSpam::Yes, verdict.reason));
             }
+            IsSpamCheckResult::YesDomain => {
+                database
+                    .add_domain(domain, url, IsSpam::Yes, false, false, Some(verdict.reason))
+                    .await
+                    .expect("Database died!");
+                return Some((IsSpam::Yes, verdict.reason));
+            }
+            // In case it's maybe spam or not spam, still check it properly.
+            IsSpamCheckResult::Maybe => url_maybe_spam = Some(verdict.reason),
+            IsSpamCheckResult::No => (),
         }
+    }
 
-        let mut url_maybe_spam = false;
+    log::debug!("{} Is not in the database. Debouncing...", url);
+    let mut visit_guard = None;
+    let has_visit_guard = if recursion_depth == 0 {
+        visit_guard = database.domain_visit_debounce(domain.clone()).await;
+        visit_guard.is_some()
+    } else {
+        true
+    };
 
-        // All stuff above did not answer anything. Vibe check just the link...
+    if !has_visit_guard {
+        log::debug!("{} was just visited. Trying the database.", url);
+        // Oh no nevermind, someone else visited it.
+        // Just get the database result.
+        drop(visit_guard);
+        let is_spam = database
+            .is_spam(url, domain, false)
+            .await
+            .expect("Database died!")?
+            .0;
+        Some((
+            is_spam,
+            database_match_reason(database, url, domain, is_spam).await,
+        ))
+    } else if !may_visit(url) {
+        log::debug!("{} points at a non-public address. Not visiting it.", url);
+        None
+    } else if let Ok((mut is_spam_check, mut reason)) =
+        visit_and_check_if_spam(database, &disabled_heuristics, domain, url, recursion_depth).await
+    {
+        // Add it to the database.
+        log::debug!("Visited {} and got: {:?} ({})", url, is_spam_check, reason);
+        match is_spam_check {
+            IsSpamCheckResult::YesUrl => {
+                database
+                    .add_url(
+                        &url_to_store(url),
+                        IsSpam::Yes,
+                        false,
+                        false,
+                        Some(reason),
+                        None,
+                    )
+                    .await
+                    .expect("Database died!");
+            }
+            // All the other cases effectively apply to the domains.
+            _ => {
+                if let (IsSpamCheckResult::No, Some(maybe_reason)) = (is_spam_check, url_maybe_spam)
+                {
+                    is_spam_check = IsSpamCheckResult::Maybe;
+                    reason = maybe_reason;
+                }
 
-        if let Some(url_looks_like_spam) = check_url_by_its_looks(url) {
-            // Add it to the database.
-            log::debug!(
-                "Checked if URL {} looks like a spam URL and got: {:?}",
-                url,
-                url_looks_like_spam
-            );
+                database
+                    .add_domain(
+                        domain,
+                        url,
+                        is_spam_check.into(),
+                        false,
+                        false,
+                        Some(reason),
+                    )
+                    .await
+                    .expect("Database died!");
+            }
+        };
 
-            match url_looks_like_spam {
-                IsSpam::Yes => {
-                    database
-                        .add_url(url, url_looks_like_spam, false, false)
-                        .await
-                        .expect("Database died!");
-                    return Some(url_looks_like_spam);
-                }
-                // In case it's maybe spam or not spam, still check it properly.
-                IsSpam::Maybe => url_maybe_spam = true,
-                IsSpam::No => (),
+        Some((is_spam_check.into(), reason))
+    } else {
+        // The visit probably timed out or something. Meh.
+        log::debug!("{} timed out", url);
+        None
+    }
+}
+
+/// Whether this IP address is one anyone on the internet could reach,
+/// as opposed to the bot's own machine, its local network and the like.
+///
+/// Visiting those would let anyone poke at them on the bot's behalf by sending links.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // "This network", shared address space (carrier-grade NAT), and reserved.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(ip.into());
             }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local and link local.
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
         }
+    }
+}
 
-        log::debug!("{} Is not in the database. Debouncing...", url);
-        let mut visit_guard = None;
-        let has_visit_guard = if recursion_depth == 0 {
-            visit_guard = database.domain_visit_debounce(domain.clone()).await;
-            visit_guard.is_some()
-        } else {
-            true
-        };
+/// Whether the host of this URL may be visited. IP addresses must be public,
+/// and domain names get their addresses checked by [`PublicOnlyResolver`].
+fn may_visit(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(_)) => true,
+        Some(Host::Ipv4(ip)) => is_public_ip(ip.into()),
+        Some(Host::Ipv6(ip)) => is_public_ip(ip.into()),
+        None => false,
+    }
+}
 
-        if !has_visit_guard {
-            log::debug!("{} was just visited. Trying the database.", url);
-            // Oh no nevermind, someone else visited it.
-            // Just get the database result.
-            drop(visit_guard);
-            database
-                .is_spam(url, domain, false)
-                .await
-                .expect("Database died!")
-                .map(|x| x.0)
-        } else if let Ok(mut is_spam_check) =
-            visit_and_check_if_spam(database, domain, url, recursion_depth).await
-        {
-            // Add it to the database.
-            log::debug!("Visited {} and got: {:?}", url, is_spam_check);
-            match is_spam_check {
-                IsSpamCheckResult::YesUrl => {
-                    database
-                        .add_url(url, IsSpam::Yes, false, false)
-                        .await
-                        .expect("Database died!");
-                }
-                // All the other cases effectively apply to the domains.
-                _ => {
-                    if is_spam_check == IsSpamCheckResult::No && url_maybe_spam {
-                        is_spam_check = IsSpamCheckResult::Maybe;
-                    }
+/// Resolves domain names like usual, but only lets through public addresses,
+/// so that a domain pointing at, say, `127.0.0.1` can't be visited.
+struct PublicOnlyResolver;
 
-                    database
-                        .add_domain(domain, url, is_spam_check.into(), false, false)
-                        .await
-                        .expect("Database died!");
-                }
-            };
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|x| is_public_ip(x.ip()))
+                .collect();
 
-            Some(is_spam_check.into())
-        } else {
-            // The visit probably timed out or something. Meh.
-            log::debug!("{} timed out", url);
-            None
-        }
+            if addrs.is_empty() {
+                return Err(format!("{} has no public addresses", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
     }
 }
 
 /// Check if a website served by the given URL is spam or not by visiting it.
+/// Also returns which rule the result was determined by.
+///
+/// Heuristics named in `disabled_heuristics` are skipped.
 async fn visit_and_check_if_spam(
     database: &Arc<Database>,
+    disabled_heuristics: &HashSet<String>,
     domain: &Domain,
     url: &Url,
     recursion_depth: u8,
-) -> Result<IsSpamCheckResult, reqwest::Error> {
-    // Default policy is to follow up to 10 redirects.
-    let client = reqwest::Client::builder()
-        .user_agent("GoogleOther")
-        .timeout(Duration::from_secs(7))
-        .connect_timeout(Duration::from_secs(7))
-        .build()?;
+) -> Result<(IsSpamCheckResult, MatchReason), reqwest::Error> {
+    let page = visit(url).await?;
+    let text = &page.text;
 
-    let result = client.get(url.as_str()).send().await?;
-
-    // Gather some specifics relevant to cloudflare captchas...
-    let header_powered_by = result.headers().get("x-powered-by").is_some();
-    let header_cf_ray = result.headers().get("cf-ray").is_some();
-    let header_cache = result.headers().get("cf-cache-status").is_some();
-    let status_code_forbidden = result.status() == reqwest::StatusCode::FORBIDDEN;
-
-    let text = result.text().await?;
-
-    if (text.contains("<title>Just a moment...</title>")
-        && text.contains("Enable JavaScript and cookies to continue"))
-        || text.contains("Attention Required! | Cloudflare")
-        || (text.contains("cloudflare") && text.contains("erify that you are a human"))
-    {
-        // Cloudflare captcha.
-
-        // Check validity of it being a *real* cloudflare captcha.
-        if status_code_forbidden && !header_powered_by && !header_cache && header_cf_ray {
-            // Good enough lol
-            return Ok(IsSpamCheckResult::Maybe);
-        }
-
-        // Fake cloudflare captcha.
-        // Can't believe we got lied to. So sad :(
-
-        return Ok(IsSpamCheckResult::YesUrl);
+    // Check the HTML...
+    let input = HeuristicInput {
+        url,
+        page: Some(&page),
+    };
+    if let Some(verdict) = heuristics::evaluate(disabled_heuristics, &input) {
+        return Ok((verdict.result, verdict.reason));
     }
 
     if domain.as_str().eq_ignore_ascii_case("telegra.ph")
@@ -245,7 +457,7 @@ async fn visit_and_check_if_spam(
         // Find links here and figure if they're spam themselves.
 
         let mut matches: HashSet<Url> = HashSet::with_capacity(20);
-        let mut html: &str = &text;
+        let mut html: &str = text;
         let mut current_consensus = IsSpamCheckResult::No;
 
         // Limit this to 20 matches
@@ -292,10 +504,12 @@ async fn visit_and_check_if_spam(
             ))
             .await
             {
-                match x {
-                    IsSpam::No => (),
-                    IsSpam::Yes => return Ok(IsSpamCheckResult::YesUrl),
-                    IsSpam::Maybe => current_consensus = IsSpamCheckResult::Maybe,
+                match x.0 {
+                    IsSpam::No | IsSpam::Unlikely => (),
+                    IsSpam::Yes => {
+                        return Ok((IsSpamCheckResult::YesUrl, MatchReason::LinkedPages))
+                    }
+                    IsSpam::Maybe | IsSpam::Likely => current_consensus = IsSpamCheckResult::Maybe,
                 }
             }
 
@@ -306,25 +520,66 @@ async fn visit_and_check_if_spam(
         }
 
         // Checked a telegra.ph link. Return results on that.
-        return Ok(current_consensus);
+        return Ok((current_consensus, MatchReason::LinkedPages));
     }
 
-    // Check the HTML...
-    if nft_spam::is_spam_html(&text) {
-        return Ok(IsSpamCheckResult::YesDomain);
-    }
+    // guess not.
+    Ok((IsSpamCheckResult::No, MatchReason::NothingFound))
+}
 
-    if is_telegram_url(url) && american_groundhog_spam::check_spam_telegram_html(&text) {
-        return Ok(IsSpamCheckResult::YesUrl);
+/// Visit this page, gathering what [`heuristics`] need to check it.
+async fn visit(url: &Url) -> Result<VisitedPage, reqwest::Error> {
+    #[cfg(test)]
+    if let Some(page) = tests::mocked_page(url) {
+        return Ok(page);
     }
 
-    // guess not.
-    Ok(IsSpamCheckResult::No)
+    // Follow up to 10 redirects like the default policy does,
+    // but not to addresses that can't be visited in the first place.
+    let redirect_policy = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= 10 {
+            attempt.error("too many redirects")
+        } else if !may_visit(attempt.url()) {
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    });
+
+    let client = reqwest::Client::builder()
+        .user_agent("GoogleOther")
+        .redirect(redirect_policy)
+        .dns_resolver(Arc::new(PublicOnlyResolver))
+        .timeout(Duration::from_secs(7))
+        .connect_timeout(Duration::from_secs(7))
+        .build()?;
+
+    let result = client.get(url.as_str()).send().await?;
+
+    // Gather some specifics relevant to cloudflare captchas...
+    Ok(VisitedPage {
+        header_powered_by: result.headers().get("x-powered-by").is_some(),
+        header_cf_ray: result.headers().get("cf-ray").is_some(),
+        header_cache: result.headers().get("cf-cache-status").is_some(),
+        status_forbidden: result.status() == reqwest::StatusCode::FORBIDDEN,
+        text: result.text().await?,
+    })
 }
 
-/// Check if this URL, just on its own, looks like spam.
-pub fn check_url_by_its_looks(url: &Url) -> Option<IsSpam> {
-    nft_spam::is_spam_telegram_url(url)
+/// Returns true if this URL is an invite link to a Telegram chat.
+pub fn is_invite_link(url: &Url) -> bool {
+    invite_bait::is_invite_link(url)
+}
+
+/// Check if a message with this text and entities is bait for an invite link in it,
+/// like a bunch of premium custom emoji with barely any text. Returns why if so.
+///
+/// Doesn't check for the invite link itself, see [`is_invite_link`].
+pub fn check_invite_bait(
+    text: &str,
+    entities: &[teloxide::types::MessageEntityRef],
+) -> Option<InviteBaitTrigger> {
+    invite_bait::check_message(text, entities)
 }
 
 /// Returns true if this URL's domain is Telegram.
@@ -337,3 +592,300 @@ pub fn is_telegram_url(url: &Url) -> bool {
         || domain.eq_ignore_ascii_case("telegram.me")
         || domain.eq_ignore_ascii_case("telegram.dog")
 }
+
+/// If this is a link to a Telegram bot, like `t.me/SomeBot?start=ref123` or
+/// `t.me/SomeBot/app`, get the link to the bot itself, like `https://t.me/somebot`.
+///
+/// Bot usernames always end with "bot", so that's what this goes by.
+pub fn telegram_bot_url(url: &Url) -> Option<Url> {
+    if !is_telegram_url(url) {
+        return None;
+    }
+
+    let username = url.path_segments()?.next()?.to_ascii_lowercase();
+    let is_bot = username.len() > "bot".len()
+        && username.ends_with("bot")
+        && username
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '_');
+
+    if !is_bot {
+        return None;
+    }
+
+    Url::parse(&format!("https://t.me/{}", username)).ok()
+}
+
+/// Inline bots popular enough that messages sent "via" them are never spam because of that.
+/// Most of these are Telegram's own, and don't end in "bot" anyway.
+pub const TRUSTED_INLINE_BOTS: &[&str] = &[
+    "gif",
+    "vid",
+    "pic",
+    "bing",
+    "wiki",
+    "imdb",
+    "bold",
+    "youtube",
+    "music",
+    "sticker",
+    "like",
+    "vote",
+    "foursquare",
+    "gamee",
+    "gamebot",
+    "quizbot",
+    "pollbot",
+];
+
+/// Get a link to the inline bot with this username, like `https://t.me/somebot`,
+/// so that messages sent "via" it can be checked like any other link to a bot.
+///
+/// Returns [`None`] for bots in [`TRUSTED_INLINE_BOTS`], or if it's not a bot username.
+pub fn inline_bot_url(username: &str) -> Option<Url> {
+    let username = username.trim_start_matches('@');
+    if TRUSTED_INLINE_BOTS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(username))
+    {
+        return None;
+    }
+
+    telegram_bot_url(&Url::parse(&format!("https://t.me/{}", username)).ok()?)
+}
+
+/// Queries of Telegram links are mostly referral codes that change from one spam message
+/// to the next, so they're stripped. Except for start parameters of links to bots,
+/// which are kept so that specific ones can be marked as spam. See [`telegram_bot_url`].
+pub fn strip_telegram_query(url: &mut Url) {
+    if !is_telegram_url(url) {
+        return;
+    }
+
+    let start: Vec<(String, String)> = if telegram_bot_url(url).is_some() {
+        url.query_pairs()
+            .into_owned()
+            .filter(|(key, _)| key == "start" || key == "startapp")
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    url.set_query(None);
+    if !start.is_empty() {
+        url.query_pairs_mut().extend_pairs(start);
+    }
+}
+
+/// Results of automatic checks of links to Telegram bots are stored without the start
+/// parameter, so that they cover every referral link to the bot at once.
+fn url_to_store(url: &Url) -> Cow<'_, Url> {
+    if is_telegram_url(url) && url.query().is_some() {
+        let mut url = url.clone();
+        url.set_query(None);
+        Cow::Owned(url)
+    } else {
+        Cow::Borrowed(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::HostPattern;
+    use heuristics::SpamHeuristic;
+    use std::sync::Mutex;
+    use teloxide::types::UserId;
+
+    /// Pages to pretend to find instead of visiting them, see [`mock_page`].
+    static MOCKED_PAGES: Mutex<Vec<(Url, VisitedPage)>> = Mutex::new(Vec::new());
+
+    /// Make [`check`] find this page at this URL, instead of visiting it.
+    fn mock_page(url: &Url, text: &str) {
+        let page = VisitedPage {
+            text: text.to_string(),
+            ..Default::default()
+        };
+        MOCKED_PAGES.lock().unwrap().push((url.clone(), page));
+    }
+
+    pub(super) fn mocked_page(url: &Url) -> Option<VisitedPage> {
+        let pages = MOCKED_PAGES.lock().unwrap();
+        pages.iter().find(|x| x.0 == *url).map(|x| x.1.clone())
+    }
+
+    #[test]
+    fn telegram_bot_urls() {
+        let bot = |x: &str| telegram_bot_url(&Url::parse(x).unwrap()).map(String::from);
+        let expected = Some("https://t.me/somebot".to_string());
+
+        assert_eq!(bot("https://t.me/SomeBot"), expected);
+        assert_eq!(bot("https://t.me/SomeBot?start=ref123"), expected);
+        assert_eq!(bot("https://telegram.me/somebot/app?startapp=x"), expected);
+        assert_eq!(bot("https://t.me/somechannel"), None);
+        assert_eq!(bot("https://t.me/bot"), None);
+        assert_eq!(bot("https://t.me/+SomeBot"), None);
+        assert_eq!(bot("https://example.com/SomeBot"), None);
+    }
+
+    #[test]
+    fn inline_bot_urls() {
+        let bot = |x: &str| inline_bot_url(x).map(String::from);
+
+        assert_eq!(
+            bot("SpamDropBot"),
+            Some("https://t.me/spamdropbot".to_string())
+        );
+        assert_eq!(
+            bot("@SpamDropBot"),
+            Some("https://t.me/spamdropbot".to_string())
+        );
+        assert_eq!(bot("gif"), None);
+        assert_eq!(bot("GameBot"), None);
+        assert_eq!(bot("some/path"), None);
+    }
+
+    #[test]
+    fn stripping_telegram_queries() {
+        let strip = |x: &str| {
+            let mut url = Url::parse(x).unwrap();
+            strip_telegram_query(&mut url);
+            String::from(url)
+        };
+
+        assert_eq!(
+            strip("https://t.me/SomeBot?ref=1&start=abc&startapp=def"),
+            "https://t.me/SomeBot?start=abc&startapp=def"
+        );
+        assert_eq!(strip("https://t.me/SomeBot?ref=1"), "https://t.me/SomeBot");
+        assert_eq!(
+            strip("https://t.me/somechannel?start=abc"),
+            "https://t.me/somechannel"
+        );
+        assert_eq!(
+            strip("https://example.com/SomeBot?start=abc"),
+            "https://example.com/SomeBot?start=abc"
+        );
+    }
+
+    #[test]
+    fn only_public_hosts_are_visited() {
+        let visit = |x: &str| may_visit(&Url::parse(x).unwrap());
+
+        assert!(visit("https://scam.com/claim"));
+        assert!(visit("http://91.203.1.2/claim"));
+        assert!(visit("http://[2001:4860::1]/claim"));
+
+        for url in [
+            "http://127.0.0.1/",
+            "http://2130706433/",
+            "http://10.1.2.3/",
+            "http://172.16.0.1/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[::]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+        ] {
+            assert!(!visit(url), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn reviewed_url_under_pattern_stays_not_spam() {
+        let db = Database::new_by_path(None, "sqlite::memory:", false)
+            .await
+            .unwrap();
+        let pattern = HostPattern::parse("*.spam.example").unwrap();
+        db.add_url_pattern(&pattern, UserId(1)).await.unwrap();
+
+        let reviewed = Url::parse("https://claim.spam.example/legit").unwrap();
+        db.add_url(&reviewed, IsSpam::No, false, true, None, None)
+            .await
+            .unwrap();
+
+        // Another URL on the same host matching the pattern first
+        // mustn't make the reviewed one spam.
+        let other = Url::parse("https://claim.spam.example/claim").unwrap();
+        let domain = Domain::from_url(&other).unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                check(&db, &domain, &other).await,
+                Some((IsSpam::Yes, MatchReason::UrlPattern))
+            );
+            assert_eq!(
+                check(&db, &domain, &reviewed).await,
+                Some((IsSpam::No, MatchReason::DatabaseUrl))
+            );
+        }
+        assert!(!db.is_cached_spam_domain(&domain).await);
+
+        // A domain marked as spam is remembered, and trumps it.
+        db.add_domain(&domain, None, IsSpam::Yes, false, true, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            check(&db, &domain, &other).await,
+            Some((IsSpam::Yes, MatchReason::DatabaseDomain))
+        );
+        assert!(db.is_cached_spam_domain(&domain).await);
+        assert_eq!(
+            check(&db, &domain, &reviewed).await,
+            Some((IsSpam::Yes, MatchReason::DatabaseDomain))
+        );
+    }
+
+    #[tokio::test]
+    async fn disabling_heuristic_changes_check() {
+        let spam = Url::parse("https://t.me/FawunBot/claim").unwrap();
+        let domain = Domain::from_url(&spam).unwrap();
+        let heuristic = heuristics::TelegramBotUrl.name();
+
+        let db = Database::new_by_path(None, "sqlite::memory:", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            check(&db, &domain, &spam).await,
+            Some((IsSpam::Yes, MatchReason::TelegramUrlHeuristic))
+        );
+
+        // A fresh database, as the result above was stored in the old one.
+        let db = Database::new_by_path(None, "sqlite::memory:", false)
+            .await
+            .unwrap();
+        assert!(db.set_heuristic_enabled(heuristic, false).await.unwrap());
+        assert!(!db.set_heuristic_enabled(heuristic, false).await.unwrap());
+
+        // Without the heuristic, the page gets visited instead, which finds nothing.
+        mock_page(
+            &spam,
+            "<html><title>Telegram: Contact @FawunBot</title></html>",
+        );
+        assert_eq!(
+            check(&db, &domain, &spam).await,
+            Some((IsSpam::No, MatchReason::NothingFound))
+        );
+    }
+
+    #[tokio::test]
+    async fn captchas_on_telegraph_are_caught() {
+        let url = Url::parse("https://telegra.ph/Claim-Your-NFT-10-17").unwrap();
+        let domain = Domain::from_url(&url).unwrap();
+        mock_page(
+            &url,
+            "<html><title>Attention Required! | Cloudflare</title></html>",
+        );
+
+        let db = Database::new_by_path(None, "sqlite::memory:", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            check(&db, &domain, &url).await,
+            Some((IsSpam::Yes, MatchReason::FakeCaptcha))
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Remembering who's an admin where for a while, because asking Telegram about it
+//! on every message is slow and eats into the API rate limits in busy chats.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    prelude::*,
+    types::{ChatMember, ChatMemberUpdated},
+    RequestError,
+};
+
+use crate::bot_api::BotApi;
+
+/// How long to trust a fetched admin status if none is specified.
+pub const DEFAULT_ADMIN_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// A map whose entries are forgotten after a while.
+struct TtlMap<K, V> {
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Hash + Eq, V: Clone> TtlMap<K, V> {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &K, now: Instant, ttl: Duration) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        // Only clean up once in a while, not to go through the whole map on each lookup.
+        if entries.len() > 1024 {
+            entries.retain(|_, (fetched_at, _)| now.duration_since(*fetched_at) < ttl);
+        }
+
+        match entries.get(key) {
+            Some((fetched_at, value)) if now.duration_since(*fetched_at) < ttl => {
+                Some(value.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key: K, value: V, now: Instant) {
+        self.entries.lock().unwrap().insert(key, (now, value));
+    }
+
+    fn remove(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn retain(&self, mut keep: impl FnMut(&K) -> bool) {
+        self.entries.lock().unwrap().retain(|key, _| keep(key));
+    }
+}
+
+/// Cache of admin statuses of users in chats, and of channels linked to chats.
+pub struct AdminCache {
+    ttl: Duration,
+    now: Clock,
+    /// The user's [`ChatMember`] if they're an admin of the chat, [`None`] otherwise.
+    admins: TtlMap<(ChatId, UserId), Option<ChatMember>>,
+    /// The channel linked to the chat, if any.
+    linked_chats: TtlMap<ChatId, Option<ChatId>>,
+}
+
+impl Default for AdminCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_ADMIN_CACHE_TTL)
+    }
+}
+
+impl AdminCache {
+    /// Make a cache that trusts what it fetched for `ttl`.
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_clock(ttl, Instant::now)
+    }
+
+    /// Same as [`Self::new`], but getting the current time from `now`.
+    #[must_use]
+    pub fn with_clock(ttl: Duration, now: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        Self {
+            ttl,
+            now: Arc::new(now),
+            admins: TtlMap::new(),
+            linked_chats: TtlMap::new(),
+        }
+    }
+
+    /// Same as [`crate::get_admin_of`], but only asking Telegram if it wasn't asked
+    /// about this user in this chat recently.
+    ///
+    /// # Errors
+    /// Errors if [`BotApi::get_chat_member`] fails.
+    pub async fn get_admin_of(
+        &self,
+        bot: &impl BotApi,
+        user: UserId,
+        chat: ChatId,
+    ) -> Result<Option<ChatMember>, RequestError> {
+        if let Some(admin) = self.admins.get(&(chat, user), (self.now)(), self.ttl) {
+            return Ok(admin);
+        }
+
+        let member = bot.get_chat_member(chat, user).await?;
+        let admin = member.kind.is_privileged().then_some(member);
+        self.admins
+            .insert((chat, user), admin.clone(), (self.now)());
+        Ok(admin)
+    }
+
+    /// Get the ID of the channel linked to this chat, only asking Telegram
+    /// if it wasn't asked about this chat recently.
+    ///
+    /// # Errors
+    /// Errors if [`BotApi::get_chat`] fails.
+    pub async fn linked_chat_of(
+        &self,
+        bot: &impl BotApi,
+        chat: ChatId,
+    ) -> Result<Option<ChatId>, RequestError> {
+        if let Some(linked) = self.linked_chats.get(&chat, (self.now)(), self.ttl) {
+            return Ok(linked);
+        }
+
+        let linked = bot.get_chat(chat).await?.linked_chat_id().map(ChatId);
+        self.linked_chats.insert(chat, linked, (self.now)());
+        Ok(linked)
+    }
+
+    /// Forget the admin status of this user in this chat.
+    pub fn invalidate(&self, chat: ChatId, user: UserId) {
+        self.admins.remove(&(chat, user));
+    }
+
+    /// Forget everything about this chat.
+    pub fn invalidate_chat(&self, chat: ChatId) {
+        self.admins.retain(|(x, _)| *x != chat);
+        self.linked_chats.remove(&chat);
+    }
+
+    /// Forget the admin status of a user if this update shows them being
+    /// promoted, demoted, or having their admin rights changed.
+    ///
+    /// Telegram only sends these updates to bots that are admins in the chat,
+    /// and only if asked for `chat_member` updates explicitly.
+    pub fn observe_member_update(&self, update: &ChatMemberUpdated) {
+        if update.old_chat_member.kind.is_privileged()
+            || update.new_chat_member.kind.is_privileged()
+        {
+            self.invalidate(update.chat.id, update.new_chat_member.user.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that only moves when told to.
+    fn mock_clock() -> (
+        Arc<Mutex<Instant>>,
+        impl Fn() -> Instant + Send + Sync + 'static,
+    ) {
+        let time = Arc::new(Mutex::new(Instant::now()));
+        let time_for_clock = time.clone();
+        (time, move || *time_for_clock.lock().unwrap())
+    }
+
+    const CHAT: ChatId = ChatId(-100);
+    const USER: UserId = UserId(42);
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let (time, clock) = mock_clock();
+        let cache = AdminCache::with_clock(Duration::from_secs(600), clock);
+
+        cache
+            .admins
+            .insert((CHAT, USER), None, *time.lock().unwrap());
+        cache.linked_chats.insert(CHAT, None, *time.lock().unwrap());
+        let now = || (cache.now)();
+
+        assert_eq!(
+            cache.admins.get(&(CHAT, USER), now(), cache.ttl),
+            Some(None)
+        );
+        assert_eq!(cache.linked_chats.get(&CHAT, now(), cache.ttl), Some(None));
+
+        *time.lock().unwrap() += Duration::from_secs(599);
+        assert_eq!(
+            cache.admins.get(&(CHAT, USER), now(), cache.ttl),
+            Some(None)
+        );
+
+        *time.lock().unwrap() += Duration::from_secs(1);
+        assert_eq!(cache.admins.get(&(CHAT, USER), now(), cache.ttl), None);
+        assert_eq!(cache.linked_chats.get(&CHAT, now(), cache.ttl), None);
+    }
+
+    #[test]
+    fn invalidation() {
+        let (time, clock) = mock_clock();
+        let cache = AdminCache::with_clock(DEFAULT_ADMIN_CACHE_TTL, clock);
+        let now = *time.lock().unwrap();
+        let other_user = UserId(43);
+        let other_chat = ChatId(-200);
+
+        for (chat, user) in [(CHAT, USER), (CHAT, other_user), (other_chat, USER)] {
+            cache.admins.insert((chat, user), None, now);
+        }
+        cache.linked_chats.insert(CHAT, Some(ChatId(-300)), now);
+
+        cache.invalidate(CHAT, USER);
+        assert_eq!(cache.admins.get(&(CHAT, USER), now, cache.ttl), None);
+        assert_eq!(
+            cache.admins.get(&(CHAT, other_user), now, cache.ttl),
+            Some(None)
+        );
+
+        cache.invalidate_chat(CHAT);
+        assert_eq!(cache.admins.get(&(CHAT, other_user), now, cache.ttl), None);
+        assert_eq!(cache.linked_chats.get(&CHAT, now, cache.ttl), None);
+        assert_eq!(
+            cache.admins.get(&(other_chat, USER), now, cache.ttl),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn member_updates_invalidate_on_promotion_and_demotion() {
+        use serde_json::json;
+
+        let (time, clock) = mock_clock();
+        let cache = AdminCache::with_clock(DEFAULT_ADMIN_CACHE_TTL, clock);
+        let now = *time.lock().unwrap();
+
+        let update = |old_status: &str, new_status: &str| -> ChatMemberUpdated {
+            let user = json!({"id": USER.0, "is_bot": false, "first_name": "Amogus"});
+            let member = |status: &str| {
+                let mut member = json!({"user": user, "status": status});
+                if status == "administrator" {
+                    member["can_be_edited"] = json!(false);
+                    member["is_anonymous"] = json!(false);
+                    member["can_manage_chat"] = json!(true);
+                    member["can_delete_messages"] = json!(true);
+                    member["can_manage_video_chats"] = json!(false);
+                    member["can_restrict_members"] = json!(true);
+                    member["can_promote_members"] = json!(false);
+                    member["can_change_info"] = json!(false);
+                    member["can_invite_users"] = json!(true);
+                }
+                member
+            };
+            serde_json::from_value(json!({
+                "chat": {"id": CHAT.0, "type": "supergroup", "title": "Chat"},
+                "from": user,
+                "date": 0,
+                "old_chat_member": member(old_status),
+                "new_chat_member": member(new_status),
+            }))
+            .unwrap()
+        };
+
+        // Someone just joining or leaving doesn't change anything.
+        cache.admins.insert((CHAT, USER), None, now);
+        cache.observe_member_update(&update("left", "member"));
+        assert_eq!(cache.admins.get(&(CHAT, USER), now, cache.ttl), Some(None));
+
+        cache.observe_member_update(&update("member", "administrator"));
+        assert_eq!(cache.admins.get(&(CHAT, USER), now, cache.ttl), None);
+
+        cache.admins.insert((CHAT, USER), None, now);
+        cache.observe_member_update(&update("administrator", "member"));
+        assert_eq!(cache.admins.get(&(CHAT, USER), now, cache.ttl), None);
+    }
+}
@@ -0,0 +1,207 @@
+//! The few Telegram Bot API calls that handlers tend to make, behind a trait,
+//! so that handler logic can be tested against a pretend bot instead of Telegram.
+
+use std::future::Future;
+
+use teloxide::{
+    payloads::{
+        AnswerCallbackQuerySetters, EditMessageReplyMarkupSetters, EditMessageTextSetters,
+        SendMessageSetters,
+    },
+    prelude::*,
+    types::{Chat, ChatMember, InlineKeyboardMarkup, MessageId, ParseMode, Recipient},
+    RequestError,
+};
+
+/// How to send or edit a message, beyond its text.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageOptions {
+    /// Parse the text as HTML.
+    pub html: bool,
+    pub disable_web_page_preview: bool,
+    /// Ignored when editing a message.
+    pub reply_to: Option<MessageId>,
+    /// When editing a message, an empty keyboard removes the buttons.
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+impl MessageOptions {
+    /// Options for a message with HTML markup and nothing else special.
+    #[must_use]
+    pub fn html() -> Self {
+        Self {
+            html: true,
+            ..Default::default()
+        }
+    }
+
+    #[must_use]
+    pub fn reply_to(self, reply_to: impl Into<Option<MessageId>>) -> Self {
+        Self {
+            reply_to: reply_to.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn reply_markup(self, reply_markup: InlineKeyboardMarkup) -> Self {
+        Self {
+            reply_markup: Some(reply_markup),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn disable_web_page_preview(self) -> Self {
+        Self {
+            disable_web_page_preview: true,
+            ..self
+        }
+    }
+}
+
+/// Things a bot can ask Telegram to do. Implemented for [`Bot`], which actually does them.
+pub trait BotApi: Clone + Send + Sync + 'static {
+    fn send_message(
+        &self,
+        chat: impl Into<Recipient> + Send,
+        text: impl Into<String> + Send,
+        options: MessageOptions,
+    ) -> impl Future<Output = Result<Message, RequestError>> + Send;
+
+    fn edit_message_text(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+        text: impl Into<String> + Send,
+        options: MessageOptions,
+    ) -> impl Future<Output = Result<Message, RequestError>> + Send;
+
+    /// Replace the inline keyboard of this message, or remove it if `None`.
+    fn edit_message_reply_markup(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+        reply_markup: Option<InlineKeyboardMarkup>,
+    ) -> impl Future<Output = Result<Message, RequestError>> + Send;
+
+    fn delete_message(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    fn get_chat_member(
+        &self,
+        chat: ChatId,
+        user: UserId,
+    ) -> impl Future<Output = Result<ChatMember, RequestError>> + Send;
+
+    /// Get all admins of this chat, other than bots.
+    fn get_chat_administrators(
+        &self,
+        chat: ChatId,
+    ) -> impl Future<Output = Result<Vec<ChatMember>, RequestError>> + Send;
+
+    fn get_chat(&self, chat: ChatId) -> impl Future<Output = Result<Chat, RequestError>> + Send;
+
+    /// Answer a callback query with a notification showing this text,
+    /// or with no notification if it's empty.
+    fn answer_callback_query(
+        &self,
+        query_id: impl Into<String> + Send,
+        text: impl Into<String> + Send,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+}
+
+impl BotApi for Bot {
+    async fn send_message(
+        &self,
+        chat: impl Into<Recipient> + Send,
+        text: impl Into<String> + Send,
+        options: MessageOptions,
+    ) -> Result<Message, RequestError> {
+        let mut request = Requester::send_message(self, chat, text);
+        if options.html {
+            request = request.parse_mode(ParseMode::Html);
+        }
+        if options.disable_web_page_preview {
+            request = request.disable_web_page_preview(true);
+        }
+        if let Some(reply_to) = options.reply_to {
+            request = request.reply_to_message_id(reply_to);
+        }
+        if let Some(reply_markup) = options.reply_markup {
+            request = request.reply_markup(reply_markup);
+        }
+        request.await
+    }
+
+    async fn edit_message_text(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+        text: impl Into<String> + Send,
+        options: MessageOptions,
+    ) -> Result<Message, RequestError> {
+        let mut request = Requester::edit_message_text(self, chat, message, text);
+        if options.html {
+            request = request.parse_mode(ParseMode::Html);
+        }
+        if options.disable_web_page_preview {
+            request = request.disable_web_page_preview(true);
+        }
+        if let Some(reply_markup) = options.reply_markup {
+            request = request.reply_markup(reply_markup);
+        }
+        request.await
+    }
+
+    async fn edit_message_reply_markup(
+        &self,
+        chat: ChatId,
+        message: MessageId,
+        reply_markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        let mut request = Requester::edit_message_reply_markup(self, chat, message);
+        if let Some(reply_markup) = reply_markup {
+            request = request.reply_markup(reply_markup);
+        }
+        request.await
+    }
+
+    async fn delete_message(&self, chat: ChatId, message: MessageId) -> Result<(), RequestError> {
+        Requester::delete_message(self, chat, message).await?;
+        Ok(())
+    }
+
+    async fn get_chat_member(
+        &self,
+        chat: ChatId,
+        user: UserId,
+    ) -> Result<ChatMember, RequestError> {
+        Requester::get_chat_member(self, chat, user).await
+    }
+
+    async fn get_chat_administrators(&self, chat: ChatId) -> Result<Vec<ChatMember>, RequestError> {
+        Requester::get_chat_administrators(self, chat).await
+    }
+
+    async fn get_chat(&self, chat: ChatId) -> Result<Chat, RequestError> {
+        Requester::get_chat(self, chat).await
+    }
+
+    async fn answer_callback_query(
+        &self,
+        query_id: impl Into<String> + Send,
+        text: impl Into<String> + Send,
+    ) -> Result<(), RequestError> {
+        let text = text.into();
+        let mut request = Requester::answer_callback_query(self, query_id);
+        if !text.is_empty() {
+            request = request.text(text);
+        }
+        request.await?;
+        Ok(())
+    }
+}
@@ -1,15 +1,23 @@
 //! This create houses common for me functions, because some things
 //! are just boilerplate and aaAAAAAAAAA
 
-use std::future::Future;
+use std::{future::Future, hash::Hash, net::SocketAddr};
 
 use teloxide::{
+    dispatching::Dispatcher,
+    error_handlers::LoggingErrorHandler,
+    payloads::SetWebhookSetters,
     prelude::*,
-    types::{Chat, MessageEntity, User},
+    stop::StopToken,
+    types::{AllowedUpdate, Chat, Me, MessageEntity, User},
+    update_listeners::{webhooks, AsUpdateStream, UpdateListener},
 };
 
+pub mod admin_cache;
+pub mod bot_api;
 pub mod useful_methods;
 pub mod user_resolving;
+pub mod watchdog;
 
 // this is cursed lol
 //pub async fn make_interruptible(f: impl Future) {
@@ -58,14 +66,134 @@ pub fn start_everything<T>(closure: impl Future<Output = T>) -> T {
         .block_on(closure)
 }
 
+/// Public URL for Telegram to send updates to. If set, [`dispatch`] uses a webhook.
+const WEBHOOK_URL_ENV_VAR: &str = "WEBHOOK_URL";
+/// Address for the webhook to listen on.
+const WEBHOOK_ADDRESS_ENV_VAR: &str = "WEBHOOK_ADDRESS";
+/// Address for the webhook to listen on if none is set.
+const DEFAULT_WEBHOOK_ADDRESS: &str = "0.0.0.0:8443";
+
+/// Wraps the update listener of a webhook to tell Telegram which updates to send
+/// when the dispatcher hints at them, like polling does. Telegram doesn't send some,
+/// such as `chat_member` ones, unless asked for explicitly.
+struct WebhookListener<L> {
+    inner: L,
+    bot: Bot,
+    url: url::Url,
+    secret_token: String,
+}
+
+impl<'a, L: UpdateListener> AsUpdateStream<'a> for WebhookListener<L> {
+    type StreamErr = L::Err;
+    type Stream = <L as AsUpdateStream<'a>>::Stream;
+
+    fn as_stream(&'a mut self) -> Self::Stream {
+        self.inner.as_stream()
+    }
+}
+
+impl<L: UpdateListener> UpdateListener for WebhookListener<L> {
+    type Err = L::Err;
+
+    fn stop_token(&mut self) -> StopToken {
+        self.inner.stop_token()
+    }
+
+    fn hint_allowed_updates(&mut self, hint: &mut dyn Iterator<Item = AllowedUpdate>) {
+        let allowed_updates: Vec<AllowedUpdate> = hint.collect();
+        self.inner
+            .hint_allowed_updates(&mut allowed_updates.iter().copied());
+
+        // The webhook is already set up by now. Setting it again with the same
+        // secret token only changes which updates are sent to it.
+        let request = self
+            .bot
+            .set_webhook(self.url.clone())
+            .secret_token(self.secret_token.clone())
+            .allowed_updates(allowed_updates);
+        tokio::spawn(async move {
+            if let Err(e) = request.await {
+                log::error!("Failed to set which updates the webhook gets: {}", e);
+            }
+        });
+    }
+
+    fn timeout_hint(&self) -> Option<std::time::Duration> {
+        self.inner.timeout_hint()
+    }
+}
+
+/// Run the dispatcher until it's shut down, getting updates with long polling by default.
+///
+/// If environment variable `WEBHOOK_URL` is set, a webhook is used instead:
+/// Telegram is told to send updates to that URL, and they're received on the address
+/// in `WEBHOOK_ADDRESS`, or `0.0.0.0:8443` if it's not set. A reverse proxy is expected
+/// to forward requests from the URL to that address. The webhook is removed on shutdown.
+///
+/// Either way, pending updates are not dropped: updates that arrived while the bot
+/// was down, up to a day old, are delivered first. Telegram is asked for all kinds
+/// of updates the dispatcher's handler filters for, including `chat_member` ones.
+///
+/// # Panics
+/// Panics if the environment variables are invalid, or if setting the webhook fails.
+pub async fn dispatch<Err, Key>(dispatcher: &mut Dispatcher<Bot, Err, Key>, bot: Bot)
+where
+    Err: Send + Sync + 'static,
+    Key: Hash + Eq + Clone + Send + 'static,
+{
+    let Some(url) = std::env::var_os(WEBHOOK_URL_ENV_VAR) else {
+        dispatcher.dispatch().await;
+        return;
+    };
+
+    let url = url
+        .to_str()
+        .and_then(|x| url::Url::parse(x).ok())
+        .unwrap_or_else(|| panic!("{} is not a valid URL!", WEBHOOK_URL_ENV_VAR));
+
+    let address: SocketAddr = std::env::var(WEBHOOK_ADDRESS_ENV_VAR)
+        .unwrap_or_else(|_| DEFAULT_WEBHOOK_ADDRESS.to_string())
+        .parse()
+        .unwrap_or_else(|_| panic!("{} is not a valid address!", WEBHOOK_ADDRESS_ENV_VAR));
+
+    log::info!(
+        "Receiving updates at {} with a webhook on {}.",
+        url,
+        address
+    );
+
+    let mut options = webhooks::Options::new(address, url.clone());
+    let secret_token = options.get_or_gen_secret_token().to_owned();
+
+    let listener = webhooks::axum(bot.clone(), options)
+        .await
+        .expect("Failed to set the webhook!");
+    let listener = WebhookListener {
+        inner: listener,
+        bot,
+        url,
+        secret_token,
+    };
+
+    dispatcher
+        .dispatch_with_listener(
+            listener,
+            LoggingErrorHandler::with_custom_text("An error from the update listener"),
+        )
+        .await;
+}
+
 /// Find out if a user of this ID is an admin of the specified chat of that ID.
 /// If so, returns the `ChatMember` object describing their permissions,
 /// otherwise `None`.
 ///
+/// This asks Telegram every time. See [`admin_cache::AdminCache`] for
+/// a version that remembers the answer for a while.
+///
 /// # Errors
-/// Errors if [`Bot::get_chat_administrators`] fails.
+/// Errors if [`bot_api::BotApi::get_chat_administrators`] fails.
 pub async fn get_admin_of(
-    bot: &Bot,
+    bot: &impl bot_api::BotApi,
     user: UserId,
     chat: ChatId,
 ) -> Result<Option<teloxide::types::ChatMember>, teloxide::RequestError> {
@@ -141,6 +269,37 @@ pub fn print_sender(message: &Message) -> (String, Option<MessageEntity>) {
     }
 }
 
+/// Split a bot command off the start of this text. Returns the command, lowercased and
+/// with the `@botname` suffix removed, like `/hello`, and parameters after it, trimmed.
+///
+/// Returns `None` if the text isn't a command, or if it's a command for another bot,
+/// like `/hello@SomeOtherBot`.
+#[must_use]
+pub fn strip_command<'a>(text: &'a str, me: &Me) -> Option<(String, &'a str)> {
+    let text = text.trim();
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let command = text.split_whitespace().next()?;
+    let params = text[command.len()..].trim();
+
+    // Telegram commands must be ASCII.
+    // See https://core.telegram.org/bots/api#botcommand
+    if !command.is_ascii() {
+        return None;
+    }
+
+    let command = match command.split_once('@') {
+        // Bot names are guaranteed ASCII, so ignore ASCII case specifically.
+        Some((command, username)) if username.eq_ignore_ascii_case(me.username()) => command,
+        Some(_) => return None,
+        None => command,
+    };
+
+    Some((command.to_ascii_lowercase(), params))
+}
+
 pub fn append_with_message_entities(
     (text, entities): (&mut String, &mut Vec<MessageEntity>),
     (text_to_append, entities_to_append): (&str, &Vec<MessageEntity>),
@@ -186,3 +345,52 @@ macro_rules! teloxide_retry {
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::strip_command;
+    use teloxide::types::{Me, User, UserId};
+
+    fn me() -> Me {
+        Me {
+            user: User {
+                id: UserId(1),
+                is_bot: true,
+                first_name: "Amogus".to_string(),
+                last_name: None,
+                username: Some("Amogus_Bot".to_string()),
+                language_code: None,
+                is_premium: false,
+                added_to_attachment_menu: false,
+            },
+            can_join_groups: true,
+            can_read_all_group_messages: false,
+            supports_inline_queries: false,
+        }
+    }
+
+    #[test]
+    fn command_stripping() {
+        let me = me();
+        let strip = |text| strip_command(text, &me);
+
+        assert_eq!(strip("/cmd"), Some(("/cmd".to_string(), "")));
+        assert_eq!(
+            strip("/CMD hi  there"),
+            Some(("/cmd".to_string(), "hi  there"))
+        );
+        assert_eq!(strip("/cmd@Amogus_Bot"), Some(("/cmd".to_string(), "")));
+        assert_eq!(strip("/cmd@amogus_bot 5"), Some(("/cmd".to_string(), "5")));
+        assert_eq!(
+            strip("  /cmd\tparams \n"),
+            Some(("/cmd".to_string(), "params"))
+        );
+
+        assert_eq!(strip("/cmd@WrongBot"), None);
+        assert_eq!(strip("/cmd@WrongBot params"), None);
+        assert_eq!(strip("cmd"), None);
+        assert_eq!(strip("hi /cmd"), None);
+        assert_eq!(strip("/амогус"), None);
+        assert_eq!(strip(""), None);
+    }
+}
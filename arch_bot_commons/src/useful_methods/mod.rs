@@ -21,7 +21,10 @@ pub struct MessageMediaInfo<'a> {
     pub is_image: bool,
     pub is_sound: bool,
     pub is_voice_or_video_note: bool,
+    /// Not made of pixels, so can't be worked with as an image or a video as is.
     pub is_vector_sticker: bool,
+    /// An animated sticker, which is a Lottie animation that can be rendered into frames.
+    pub is_animated_sticker: bool,
     pub file: &'a FileMeta,
 }
 
@@ -62,6 +65,7 @@ impl MessageStuff for Message {
                 is_sound: false,
                 is_voice_or_video_note: false,
                 is_vector_sticker: false,
+                is_animated_sticker: false,
                 file: &biggest.file,
             });
         }
@@ -77,6 +81,7 @@ impl MessageStuff for Message {
                 is_image: !sticker.is_video() && !sticker.is_animated(),
                 is_voice_or_video_note: false,
                 is_vector_sticker: sticker.is_animated(),
+                is_animated_sticker: sticker.is_animated(),
                 file: &sticker.file,
             });
         }
@@ -92,6 +97,7 @@ impl MessageStuff for Message {
                 is_sound: false,
                 is_voice_or_video_note: false,
                 is_vector_sticker: false,
+                is_animated_sticker: false,
                 file: &video.file,
             });
         }
@@ -107,6 +113,7 @@ impl MessageStuff for Message {
                 is_sound: false,
                 is_voice_or_video_note: false,
                 is_vector_sticker: false,
+                is_animated_sticker: false,
                 file: &animation.file,
             });
         }
@@ -123,11 +130,28 @@ impl MessageStuff for Message {
                     is_sound: false,
                     is_voice_or_video_note: true,
                     is_vector_sticker: false,
+                    is_animated_sticker: false,
                     file: &video_note.file,
                 });
             }
         }
 
+        if let Some(audio) = self.audio() {
+            return Some(MessageMediaInfo {
+                width: 0,
+                height: 0,
+                is_sticker: false,
+                is_video: false,
+                is_gif: false,
+                is_image: false,
+                is_sound: true,
+                is_voice_or_video_note: false,
+                is_vector_sticker: false,
+                is_animated_sticker: false,
+                file: &audio.file,
+            });
+        }
+
         if let Some(voice) = self.voice() {
             return Some(MessageMediaInfo {
                 width: 0,
@@ -139,6 +163,7 @@ impl MessageStuff for Message {
                 is_sound: true,
                 is_voice_or_video_note: true,
                 is_vector_sticker: false,
+                is_animated_sticker: false,
                 file: &voice.file,
             });
         }
@@ -1,12 +1,13 @@
 use futures::Future;
 use teloxide::{
-    payloads::SendMessageSetters,
-    requests::Requester,
     types::{Message, MessageId, Recipient},
-    Bot, RequestError,
+    RequestError,
 };
 
-use crate::teloxide_retry;
+use crate::{
+    bot_api::{BotApi, MessageOptions},
+    teloxide_retry,
+};
 
 pub trait BotArchSendMsg {
     /// Opinionated method to send a message, with HTML markup,
@@ -21,7 +22,7 @@ pub trait BotArchSendMsg {
     ) -> impl Future<Output = Result<Vec<Message>, RequestError>> + Send;
 }
 
-impl BotArchSendMsg for Bot {
+impl<B: BotApi> BotArchSendMsg for B {
     async fn archsendmsg<'a>(
         &'a self,
         to_where: impl Into<Recipient> + Send,
@@ -36,15 +37,14 @@ impl BotArchSendMsg for Bot {
         let iter = SplitOverLengthTokens::new(text, 4096);
 
         for text in iter {
-            let result = teloxide_retry!({
-                let mut request = self
-                    .send_message(to_where.clone(), text)
-                    .parse_mode(teloxide::types::ParseMode::Html);
-                if let Some(reply_to) = reply_to {
-                    request = request.reply_to_message_id(reply_to);
-                }
-                request.await
-            });
+            let result = teloxide_retry!(
+                self.send_message(
+                    to_where.clone(),
+                    text,
+                    MessageOptions::html().reply_to(reply_to)
+                )
+                .await
+            );
 
             match result {
                 Ok(message) => sent_messages.push(message),
@@ -0,0 +1,237 @@
+//! Integration with the systemd watchdog.
+//!
+//! If a service has `WatchdogSec=` set, systemd expects it to say it's alive
+//! at least that often, and restarts it otherwise. See `man systemd.service`
+//! and `man sd_notify` for details.
+
+use std::{
+    ffi::OsStr,
+    future::Future,
+    os::unix::net::UnixDatagram,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{task::JoinHandle, time::Instant};
+
+/// Environment variable systemd sets to the watchdog timeout, in microseconds.
+const WATCHDOG_USEC_ENV_VAR: &str = "WATCHDOG_USEC";
+/// Environment variable systemd sets to the PID of the process that should ping the watchdog.
+const WATCHDOG_PID_ENV_VAR: &str = "WATCHDOG_PID";
+/// Environment variable systemd sets to the socket to send notifications to.
+const NOTIFY_SOCKET_ENV_VAR: &str = "NOTIFY_SOCKET";
+
+/// Stops pinging the watchdog when dropped.
+pub struct WatchdogGuard(JoinHandle<()>);
+
+impl Drop for WatchdogGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Tracks when the async runtime last got around to running a task, to tell if it's stuck.
+///
+/// Clones share the same state, so one can be kept for the health check
+/// while another is moved into [`Heartbeat::keep_beating`].
+#[derive(Clone)]
+pub struct Heartbeat {
+    last_beat: Arc<Mutex<Instant>>,
+    max_silence: Duration,
+}
+
+impl Heartbeat {
+    /// Make a heartbeat that counts as fresh for `max_silence` after each beat,
+    /// starting with one now.
+    ///
+    /// This should be a few times longer than the interval given to
+    /// [`Heartbeat::keep_beating`], to not count a slow moment as being stuck.
+    pub fn new(max_silence: Duration) -> Self {
+        Heartbeat {
+            last_beat: Arc::new(Mutex::new(Instant::now())),
+            max_silence,
+        }
+    }
+
+    /// Note that the runtime is still running things.
+    pub fn beat(&self) {
+        *self.last_beat.lock().unwrap() = Instant::now();
+    }
+
+    /// Beat every `interval`, forever. Meant to be spawned as its own task, so that
+    /// beats stop if the runtime stops running tasks, without depending on
+    /// Telegram sending any updates, which it only does when something happens.
+    pub async fn keep_beating(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            self.beat();
+        }
+    }
+
+    /// Returns true if the last beat was no longer than `max_silence` ago.
+    pub fn is_fresh(&self) -> bool {
+        self.last_beat.lock().unwrap().elapsed() <= self.max_silence
+    }
+}
+
+/// Start pinging the systemd watchdog if it's enabled for this process, which is
+/// done for as long as the returned guard is kept, and only while `is_alive` returns true.
+///
+/// `is_alive` is run twice per watchdog timeout, and taking longer than half of it
+/// counts as not being alive. It should check that whatever the bot depends on,
+/// like its database, still works, and that the runtime isn't stuck,
+/// such as with a [`Heartbeat`].
+///
+/// Returns [`None`] and does nothing if the watchdog is not enabled,
+/// like when not running as a systemd service.
+///
+/// The guard should be held on to while [`crate::dispatch`] is running.
+pub fn start<F, Fut>(is_alive: F) -> Option<WatchdogGuard>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = bool> + Send,
+{
+    let interval = watchdog_interval(
+        std::env::var(WATCHDOG_USEC_ENV_VAR).ok().as_deref(),
+        std::env::var(WATCHDOG_PID_ENV_VAR).ok().as_deref(),
+        std::process::id(),
+    )?;
+
+    let socket = std::env::var_os(NOTIFY_SOCKET_ENV_VAR)?;
+
+    log::info!("Pinging the systemd watchdog every {:?}.", interval);
+
+    Some(WatchdogGuard(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            if !tokio::time::timeout(interval, is_alive())
+                .await
+                .unwrap_or(false)
+            {
+                log::warn!("Health check failed, not pinging the systemd watchdog.");
+                continue;
+            }
+
+            if let Err(e) = notify(&socket, "WATCHDOG=1") {
+                log::error!("Failed to ping the systemd watchdog: {}", e);
+            }
+        }
+    })))
+}
+
+/// How often to ping the watchdog, given values of `WATCHDOG_USEC` and `WATCHDOG_PID`
+/// environment variables and our own PID. Half of the timeout, as recommended by systemd.
+///
+/// Returns [`None`] if the watchdog is not enabled for this process.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    let usec: u64 = usec?.trim().parse().ok().filter(|x| *x > 0)?;
+
+    // If it's set, only that process should ping it.
+    if let Some(pid) = pid {
+        if pid.trim().parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Send this state to the systemd notification socket at this path.
+/// Paths starting with `@` are abstract sockets.
+fn notify(socket_path: &OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let socket = UnixDatagram::unbound()?;
+
+    match socket_path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            ));
+        }
+        None => {
+            socket.send_to(state.as_bytes(), socket_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_intervals() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 1),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("1"), 1),
+            Some(Duration::from_secs(15))
+        );
+
+        // Not enabled, or not for us.
+        assert_eq!(watchdog_interval(None, None, 1), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 1), None);
+        assert_eq!(watchdog_interval(Some("lol"), None, 1), None);
+        assert_eq!(watchdog_interval(Some("30000000"), Some("2"), 1), None);
+    }
+
+    #[test]
+    fn heartbeats() {
+        let heartbeat = Heartbeat::new(Duration::from_millis(50));
+        let handler_heartbeat = heartbeat.clone();
+        assert!(heartbeat.is_fresh());
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!heartbeat.is_fresh());
+
+        handler_heartbeat.beat();
+        assert!(heartbeat.is_fresh());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_heartbeats_keep_beating() {
+        let heartbeat = Heartbeat::new(Duration::from_secs(60));
+        let stale = Heartbeat::new(Duration::from_secs(60));
+        tokio::spawn(heartbeat.clone().keep_beating(Duration::from_secs(10)));
+
+        // Nothing else happens for a long while.
+        for _ in 0..60 {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            assert!(heartbeat.is_fresh());
+        }
+
+        assert!(!stale.is_fresh());
+    }
+
+    #[test]
+    fn notifying() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        notify(path.as_os_str(), "WATCHDOG=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+    }
+}
@@ -14,7 +14,7 @@ use arch_bot_commons::*;
 use once_cell::sync::Lazy;
 use tokio::time::{sleep, Duration};
 
-fn gen_password() -> String {
+fn gen_password(rng: &mut impl Rng) -> String {
     static ALPHABET: Lazy<Vec<char>> =
         Lazy::new(|| (b'a'..=b'z').map(|c| c as char).collect::<Vec<_>>());
     static RESPONSES: &[&str] = &[
@@ -28,15 +28,14 @@ fn gen_password() -> String {
         " aaaaaaaaaaaaaaaa",
         " pls 🥺",
     ];
-    let mut rng = rand::thread_rng();
     let length = rng.gen_range(8..69);
 
     let mut password = (0..length)
-        .map(|_| ALPHABET.choose(&mut rng).unwrap())
+        .map(|_| ALPHABET.choose(rng).unwrap())
         .collect::<String>();
 
     if rng.gen::<f64>() < 0.45 {
-        let response = RESPONSES.choose(&mut rng).unwrap();
+        let response = RESPONSES.choose(rng).unwrap();
         password.push_str(response);
     }
 
@@ -69,7 +68,7 @@ async fn lol() {
                 bot.answer_inline_query(&q.id, {
                     let mut results = (0..10)
                         .map(|i| {
-                            let p = gen_password();
+                            let p = gen_password(&mut rand::thread_rng());
                             InlineQueryResult::Article(InlineQueryResultArticle::new(
                                 i.to_string(),
                                 p.clone(),
@@ -95,7 +94,8 @@ async fn lol() {
                 static HELP: &str =
                     "(this bot answers to \"hi, cutie!\" messages in DMs and group chats)";
                 if let Some(text) = msg.text() {
-                    if msg.chat.is_private() && text == "/start" {
+                    let is_start = strip_command(text, &me).is_some_and(|(x, _)| x == "/start");
+                    if msg.chat.is_private() && is_start {
                         bot.send_message(msg.chat.id, HELP)
                             .reply_to_message_id(msg.id)
                             .await?;
@@ -110,7 +110,9 @@ async fn lol() {
 
                             sleep(Duration::from_secs_f64(rand::random::<f64>() * 3.0 + 2.0)).await;
 
-                            bot.send_message(msg.chat.id, gen_password())
+                            // Made before sending, as the thread RNG can't be held across awaits.
+                            let password = gen_password(&mut rand::thread_rng());
+                            bot.send_message(msg.chat.id, password)
                                 .reply_to_message_id(msg.id)
                                 .await?;
                         } else if REGEXMOMENT_HERBERT.is_match(text) {
@@ -126,11 +128,14 @@ async fn lol() {
 
     log::info!("Dispatching the dispatcher!");
 
-    Dispatcher::builder(bot, handler)
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    // Nothing to check other than that the runtime is alive to run this.
+    let _watchdog = arch_bot_commons::watchdog::start(|| async { true });
+
+    arch_bot_commons::dispatch(&mut dispatcher, bot).await;
 
     log::info!("it appears we have been bonked.");
 }
@@ -138,3 +143,32 @@ async fn lol() {
 fn main() {
     start_everything(lol());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn password_is_reproducible() {
+        let password = gen_password(&mut StdRng::seed_from_u64(69));
+        assert_eq!(password, "uzosgxls");
+        assert_eq!(password, gen_password(&mut StdRng::seed_from_u64(69)));
+    }
+
+    #[test]
+    fn password_length() {
+        let mut rng = StdRng::seed_from_u64(420);
+        for _ in 0..1000 {
+            let password = gen_password(&mut rng);
+            // The letters, without the response that may follow them.
+            let letters = password.split(' ').next().unwrap();
+            assert!((8..69).contains(&letters.len()), "{}", password);
+            assert!(
+                letters.chars().all(|x| x.is_ascii_lowercase()),
+                "{}",
+                password
+            );
+        }
+    }
+}
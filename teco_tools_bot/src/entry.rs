@@ -1,8 +1,10 @@
-use std::{fs, sync::Arc};
+use arch_bot_commons::watchdog::Heartbeat;
+use std::{fs, sync::Arc, time::Duration};
 use teloxide::{dptree::deps, prelude::*, RequestError};
 
 use crate::{
     handlers,
+    tasks::completion::media_processing,
     tasks::taskman::{database::Database, Taskman},
     USE_LOCAL_API,
 };
@@ -11,6 +13,20 @@ use crate::{
 /// Panics if the bot fails to start lol
 pub async fn entry() {
     magick_rust::magick_wand_genesis();
+    media_processing::init_magick_resource_limits();
+
+    for tool in media_processing::check_external_tools() {
+        log::warn!(
+            "No {} found, commands that need it are unavailable.",
+            tool.program()
+        );
+    }
+
+    if media_processing::check_animated_sticker_renderer() {
+        log::info!("Found lottie_convert.py, animated stickers can be resized and distorted.");
+    } else {
+        log::info!("No lottie_convert.py found, animated stickers can't be resized nor distorted.");
+    }
 
     log::info!("ASYNC WOOOO");
     let key = fs::read_to_string(match cfg!(debug_assertions) {
@@ -34,23 +50,34 @@ pub async fn entry() {
         .await
         .expect("Failed to set bot commands!");
 
-    let taskman = Taskman::new(db, bot.clone()).await;
+    let taskman = Taskman::new(db.clone(), bot.clone()).await;
 
     log::info!("Creating the handler...");
 
+    // A minute without the runtime getting to a 10 second tick means that it's stuck.
+    let heartbeat = Heartbeat::new(Duration::from_secs(60));
+    tokio::spawn(heartbeat.clone().keep_beating(Duration::from_secs(10)));
+
     let handler = dptree::entry()
         .branch(Update::filter_message().endpoint(handlers::handle_new_message))
         .branch(Update::filter_edited_message().endpoint(handlers::handle_edited_message))
+        .branch(Update::filter_callback_query().endpoint(handlers::handle_callback_query))
         .endpoint(|| async { Ok::<(), RequestError>(()) }); // bye lol
 
     log::info!("Dispatching the dispatcher!");
 
-    Dispatcher::builder(bot, handler)
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
         .dependencies(deps![taskman])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    let _watchdog = arch_bot_commons::watchdog::start(move || {
+        let db = db.clone();
+        let heartbeat = heartbeat.clone();
+        async move { heartbeat.is_fresh() && db.is_alive().await }
+    });
+
+    arch_bot_commons::dispatch(&mut dispatcher, bot).await;
 
     log::info!("it appears we have been bonked.");
 
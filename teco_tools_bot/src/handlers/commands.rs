@@ -1,6 +1,12 @@
-use std::{future::Future, io::Write, pin::Pin};
+use std::{
+    future::Future,
+    io::Write,
+    num::NonZeroU8,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
-use arch_bot_commons::{teloxide_retry, useful_methods::*};
+use arch_bot_commons::{strip_command, teloxide_retry, useful_methods::*};
 use html_escape::encode_text;
 
 use teloxide::{
@@ -12,9 +18,14 @@ use teloxide::{
 use tempfile::NamedTempFile;
 
 use crate::{
+    strings::{fill, localize_help, Key, Lang},
     tasks::{
-        completion::media_processing::count_video_frames_and_framerate_and_audio_and_length,
-        parsing::TaskError, taskman::Taskman, ImageFormat, ResizeType, Task, VideoTypePreference,
+        completion::media_processing::{
+            self, count_video_frames_and_framerate_and_audio_and_length, ExternalTool,
+        },
+        parsing::{has_size_param, scale_album_dimensions, TaskError},
+        taskman::{database::TASK_HISTORY_LENGTH, Taskman},
+        ImageFormat, ResizeType, Task, VideoClip, VideoTypePreference,
     },
     MAX_DOWNLOAD_SIZE_MEGABYTES, OWNER_ID,
 };
@@ -22,12 +33,29 @@ use crate::{
 pub const COMMANDS: &[Command] = &[
     START,
     HELP,
+    QUEUE,
+    HISTORY,
+    REDO,
+    LANG,
+    SETQUALITY_DEFAULT,
     AMOGUS,
     DISTORT,
     OCR,
+    PALETTE,
     AMENBREAK,
     RESIZE,
+    MIRROR,
+    FLIP,
+    KALEIDOSCOPE,
+    NEGATE,
+    SPOILER,
     REVERSE_TEXT,
+    SPEED,
+    TRIM,
+    CONCAT,
+    FRAME,
+    FRAMES,
+    BOOST,
     TO_CUSTOM_EMOJI,
     TO_STICKER,
     TO_VIDEO,
@@ -57,20 +85,24 @@ impl<'a> TaskParams<'a> {
         bot_me: &'new Me,
         message: &'new Message,
     ) -> Option<TaskParams<'new>> {
-        let message_text = message.text_full()?;
+        Self::with_text(taskman, bot, bot_me, message, message.text_full()?)
+    }
 
+    /// Same as [`Self::new`], but parsing `message_text` as the command
+    /// instead of the message's own text, like for `/redo`.
+    pub fn with_text<'new>(
+        taskman: &'new Taskman,
+        bot: &'new Bot,
+        bot_me: &'new Me,
+        message: &'new Message,
+        message_text: &'new str,
+    ) -> Option<TaskParams<'new>> {
         if !message_text.starts_with('/') {
             return None;
         }
 
         let command = message_text.split_whitespace().next()?;
 
-        if !command.is_ascii() {
-            // Telegram commands must be ASCII.
-            // See https://core.telegram.org/bots/api#botcommand
-            return None;
-        }
-
         let command_len = command.len();
 
         Some(TaskParams {
@@ -84,25 +116,19 @@ impl<'a> TaskParams<'a> {
     }
 
     pub fn make_task(self) -> Option<TaskFuture<'a>> {
-        // Commands shouldn't have an "@" in their callnames.
-        // If the command is "/distort@Teco_Tools_Bot",
-        // trim the "@" and everything after it.
-        let callname = if let Some(username_start) = self.command().find('@') {
-            // While we're here, also check if the username is actually ours.
-            // Bot names are guaranteed ASCII, so ignore ASCII case specifically.
-            if !self.command()[username_start + '@'.len_utf8()..]
-                .eq_ignore_ascii_case(self.bot_me.username())
-            {
-                // This command is not for us. Ignore.
-                return None;
-            }
-
-            &self.command()[0..username_start]
-        } else {
-            self.command()
-        };
+        // If the command is "/distort@Teco_Tools_Bot", this gets just "/distort",
+        // or nothing if the command is for some other bot.
+        let (callname, _) = strip_command(self.command(), self.bot_me)?;
         for command in COMMANDS {
-            if command.is_matching_callname(callname) {
+            if command.is_matching_callname(&callname) {
+                if !command.is_available() {
+                    let lang = self.lang();
+                    return Some(Box::pin(async move {
+                        Ok(Err(TaskError::Error(
+                            Key::CommandUnavailable.get(lang).to_string(),
+                        )))
+                    }));
+                }
                 return Some((command.function)(self));
             }
         }
@@ -127,6 +153,11 @@ impl<'a> TaskParams<'a> {
     pub fn get_params(&self) -> &str {
         self.message_text[self.command_len..].trim_start()
     }
+
+    /// Language to talk to whoever sent the command in. See [`Taskman::lang_for`].
+    pub fn lang(&self) -> Lang {
+        self.taskman.lang_for(self.message)
+    }
 }
 
 pub struct Command {
@@ -135,6 +166,8 @@ pub struct Command {
     pub function: fn(TaskParams) -> TaskFuture,
     //pub function: fn(TaskParams) -> Ret,
     hidden: bool,
+    /// External programs this command can't work without.
+    requires: &'static [ExternalTool],
 }
 
 impl Command {
@@ -145,6 +178,17 @@ impl Command {
             .is_some_and(|x| x.eq_ignore_ascii_case(command))
     }
 
+    /// Returns `false` if a program this command needs is missing on this instance.
+    /// See [`media_processing::check_external_tools`].
+    pub fn is_available(&self) -> bool {
+        self.is_available_with(media_processing::is_tool_available)
+    }
+
+    /// Same as [`Self::is_available`], with `is_tool_available` telling which tools are there.
+    fn is_available_with(&self, is_tool_available: impl Fn(ExternalTool) -> bool) -> bool {
+        self.requires.iter().all(|x| is_tool_available(*x))
+    }
+
     pub fn get_help(&self, mut output: impl std::fmt::Write) -> Result<(), std::fmt::Error> {
         // useful for the separator "command"
         if self.callname.is_empty() && self.description.is_empty() {
@@ -177,10 +221,18 @@ impl Command {
     }
 
     pub fn generate_bot_commands() -> Vec<BotCommand> {
+        Self::generate_bot_commands_with(media_processing::is_tool_available)
+    }
+
+    /// Same as [`Self::generate_bot_commands`], with `is_tool_available` telling
+    /// which tools are there.
+    fn generate_bot_commands_with(
+        is_tool_available: impl Fn(ExternalTool) -> bool + Copy,
+    ) -> Vec<BotCommand> {
         let mut output = Vec::new();
 
         for command in COMMANDS {
-            if command.hidden {
+            if command.hidden || !command.is_available_with(is_tool_available) {
                 continue;
             }
             let Some(callname) = command.callname.split_ascii_whitespace().next() else {
@@ -260,13 +312,12 @@ macro_rules! goodbye_cancel {
 }
 
 macro_rules! check_too_large {
-    ($media:expr) => {{
+    ($stuff:expr, $media:expr) => {{
         if $media.file.size > MAX_DOWNLOAD_SIZE_MEGABYTES * 1000 * 1000 {
-            goodbye_cancel!(format!(
-                "media is too large. The limit is {}MB.",
-                MAX_DOWNLOAD_SIZE_MEGABYTES
-            )
-            .as_str());
+            goodbye_cancel!(fill(
+                Key::MediaTooLarge.get($stuff.lang()),
+                &[&MAX_DOWNLOAD_SIZE_MEGABYTES]
+            ));
         }
     }};
 }
@@ -294,10 +345,11 @@ macro_rules! error {
 macro_rules! print_help {
     ($stuff: expr, $task: expr) => {
         if request_for_help($stuff.get_params()) {
-            let mut help = $task.param_help();
+            let lang = $stuff.lang();
+            let mut help = localize_help($task.param_help(), lang);
             // Some tasks have empty help.
             if help.is_empty() {
-                help = "This command has no parameters.";
+                help = Key::NoParameters.get(lang).to_string();
             }
             goodbye_desc!(help);
         }
@@ -309,6 +361,7 @@ pub const START: Command = Command {
     description: "",
     function: wrap!(start),
     hidden: true,
+    requires: &[],
 };
 async fn start(tp: TaskParams<'_>) -> Ret {
     if !tp.message.chat.is_private() {
@@ -333,6 +386,7 @@ pub const HELP: Command = Command {
     description: "Show this help.",
     function: wrap!(help),
     hidden: false,
+    requires: &[],
 };
 async fn help(tp: TaskParams<'_>) -> Ret {
     use std::fmt::Write;
@@ -376,11 +430,197 @@ async fn help(tp: TaskParams<'_>) -> Ret {
     goodbye_desc!(help);
 }
 
+pub const QUEUE: Command = Command {
+    callname: "/queue",
+    description: "Show your tasks that are waiting to be done.",
+    function: wrap!(queue),
+    hidden: false,
+    requires: &[],
+};
+async fn queue(tp: TaskParams<'_>) -> Ret {
+    use std::fmt::Write;
+    // Telegram doesn't allow longer messages.
+    const MAX_LENGTH: usize = 4096 - 100;
+
+    // Anonymous admins and channels all share the same user, so only look in this chat then.
+    let sender = tp.message.from();
+    let anonymous = sender.is_none_or(|x| x.is_anonymous() || x.is_channel());
+    let tasks = tp
+        .taskman
+        .db
+        .list_user_tasks(
+            sender.map(|x| x.id),
+            anonymous.then_some(tp.message.chat.id),
+        )
+        .await
+        .expect("Database died!");
+
+    if tasks.is_empty() {
+        goodbye_desc!("You have no tasks waiting to be done.");
+    }
+
+    let mut output = String::from("Your tasks:\n");
+    for (index, (position, info)) in tasks.iter().enumerate() {
+        let mut entry = String::from("\n");
+        let command = info
+            .message
+            .text_full()
+            .and_then(|x| x.split_whitespace().next())
+            .unwrap_or("?");
+        write!(entry, "<code>{}</code>: ", encode_text(command)).unwrap();
+        if info.in_progress {
+            writeln!(entry, "working on it now").unwrap();
+        } else if info
+            .delay_processing_until
+            .is_some_and(|x| x > chrono::Utc::now())
+        {
+            writeln!(entry, "waiting for this chat's slow mode").unwrap();
+        } else {
+            writeln!(entry, "position in queue: {}", position).unwrap();
+        }
+        info.task.write_params(&mut entry, false, false).unwrap();
+
+        if output.len() + entry.len() > MAX_LENGTH {
+            write!(output, "\n...and {} more.", tasks.len() - index).unwrap();
+            break;
+        }
+        output.push_str(&entry);
+    }
+
+    goodbye_desc!(output);
+}
+
+pub const HISTORY: Command = Command {
+    callname: "/history",
+    description: "Show your last few completed tasks.",
+    function: wrap!(history),
+    hidden: false,
+    requires: &[],
+};
+async fn history(tp: TaskParams<'_>) -> Ret {
+    use std::fmt::Write;
+
+    let Some(user) = tp
+        .message
+        .from()
+        .filter(|x| !x.is_anonymous() && !x.is_channel())
+    else {
+        goodbye_cancel!("tasks of anonymous users are not remembered.");
+    };
+
+    let tasks = tp
+        .taskman
+        .db
+        .get_recent_tasks_for_user(user.id, TASK_HISTORY_LENGTH)
+        .await
+        .expect("Database died!");
+
+    if tasks.is_empty() {
+        goodbye_desc!("You have no recently completed tasks.");
+    }
+
+    let mut output = String::from("Your last tasks, newest first:\n");
+    for (command, task) in &tasks {
+        let command = command.split_whitespace().next().unwrap_or("?");
+        writeln!(output, "\n<code>{}</code>:", encode_text(command)).unwrap();
+        task.write_params(&mut output, false, false).unwrap();
+    }
+
+    goodbye_desc!(output);
+}
+
+/// If this message's command `text` is a `/redo` for this bot, get the command it redoes.
+/// See [`last_redoable_command`].
+///
+/// Tasks are parsed from that instead, so that it's what they're remembered as
+/// in the history. Asking for help or redoing tasks of anonymous users is left to [`redo`].
+pub async fn redone_command(
+    taskman: &Taskman,
+    bot: &Bot,
+    bot_me: &Me,
+    message: &Message,
+    text: &str,
+) -> Option<String> {
+    let tp = TaskParams::with_text(taskman, bot, bot_me, message, text)?;
+    let (callname, _) = strip_command(tp.command(), bot_me)?;
+    if !REDO.is_matching_callname(&callname) || request_for_help(tp.get_params()) {
+        return None;
+    }
+
+    let user = message
+        .from()
+        .filter(|x| !x.is_anonymous() && !x.is_channel())?;
+    last_redoable_command(taskman, user.id).await
+}
+
+/// Get the command of the last completed task of this user that can be redone.
+async fn last_redoable_command(taskman: &Taskman, user: UserId) -> Option<String> {
+    let tasks = taskman
+        .db
+        .get_recent_tasks_for_user(user, TASK_HISTORY_LENGTH)
+        .await
+        .expect("Database died!");
+
+    // Redoing a redo is redoing what it redid. Those aren't remembered
+    // as `/redo` anymore, but may still be in the history from before.
+    tasks
+        .into_iter()
+        .map(|(command, _)| command)
+        .find(|command| !is_redo(command))
+}
+
+/// Returns true if this command text is a `/redo` itself.
+fn is_redo(command: &str) -> bool {
+    command
+        .split_whitespace()
+        .next()
+        .and_then(|x| x.split('@').next())
+        .is_some_and(|x| REDO.is_matching_callname(x))
+}
+
+pub const REDO: Command = Command {
+    callname: "/redo",
+    description: concat!(
+        "Run your last completed task again, with the same parameters, ",
+        "on the media this is a reply or caption to."
+    ),
+    function: wrap!(redo),
+    hidden: false,
+    requires: &[],
+};
+async fn redo(tp: TaskParams<'_>) -> Ret {
+    if request_for_help(tp.get_params()) {
+        goodbye_desc!("This command has no parameters.");
+    }
+
+    let Some(user) = tp
+        .message
+        .from()
+        .filter(|x| !x.is_anonymous() && !x.is_channel())
+    else {
+        goodbye_cancel!("tasks of anonymous users are not remembered.");
+    };
+
+    let Some(command) = last_redoable_command(tp.taskman, user.id).await else {
+        goodbye_cancel!("you have no recently completed tasks to redo.");
+    };
+
+    // Parse the command again, as dimensions and such depend on the new media.
+    let Some(task) = TaskParams::with_text(tp.taskman, tp.bot, tp.bot_me, tp.message, &command)
+        .and_then(TaskParams::make_task)
+    else {
+        goodbye_cancel!("the last task can't be redone.");
+    };
+
+    task.await
+}
+
 pub const ____SEPARATOR: Command = Command {
     callname: "",
     description: "",
     function: wrap!(do_nothing),
     hidden: false,
+    requires: &[],
 };
 async fn do_nothing(_: TaskParams<'_>) -> Ret {
     goodbye_err!("")
@@ -391,6 +631,7 @@ pub const REVERSE_TEXT: Command = Command {
     description: "Reverses text.",
     function: wrap!(reverse_text),
     hidden: false,
+    requires: &[],
 };
 #[allow(clippy::no_effect_underscore_binding)]
 async fn reverse_text(tp: TaskParams<'_>) -> Ret {
@@ -421,11 +662,86 @@ async fn reverse_text(tp: TaskParams<'_>) -> Ret {
     goodbye_desc!(response);
 }
 
+pub const SETQUALITY_DEFAULT: Command = Command {
+    callname: "/setquality_default [&lt;1-100&gt;/reset]",
+    description:
+        "Set the quality media resized in this chat gets if the command doesn't specify it.",
+    function: wrap!(setquality_default),
+    hidden: false,
+    requires: &[],
+};
+async fn setquality_default(tp: TaskParams<'_>) -> Ret {
+    let chat = tp.message.chat.id;
+    let current = match tp.taskman.default_quality_for(chat) {
+        Some(quality) => format!("The default quality in this chat is {}%.", quality),
+        None => "There's no default quality set in this chat.".to_string(),
+    };
+
+    let Some(param) = tp
+        .get_params()
+        .split_whitespace()
+        .next()
+        .filter(|x| !request_for_help(x))
+    else {
+        goodbye_desc!(format!(
+            concat!(
+                "{} Send <code>/setquality_default &lt;1-100&gt;</code> to set it, ",
+                "or <code>/setquality_default reset</code> to go back to the usual ones."
+            ),
+            current
+        ));
+    };
+
+    let quality = if param.eq_ignore_ascii_case("reset") {
+        None
+    } else {
+        let quality = param
+            .trim_end_matches('%')
+            .parse::<u8>()
+            .ok()
+            .filter(|x| (1..=100).contains(x))
+            .and_then(NonZeroU8::new);
+        if quality.is_none() {
+            goodbye_cancel!("the quality must be a number between 1 and 100.");
+        }
+        quality
+    };
+
+    // In groups, it changes things for everyone, so only admins can do that.
+    if !tp.message.chat.is_private() {
+        let is_admin = match tp.message.from() {
+            // Anonymous admins send messages as the chat itself.
+            _ if tp.message.sender_chat().map(|x| x.id) == Some(chat) => true,
+            Some(user) if !user.is_anonymous() && !user.is_channel() => {
+                tp.bot.get_chat_member(chat, user.id).await?.is_privileged()
+            }
+            _ => false,
+        };
+        if !is_admin {
+            goodbye_cancel!("only admins of this chat can change its default quality.");
+        }
+    }
+
+    tp.taskman
+        .set_chat_default_quality(chat, quality)
+        .await
+        .expect("Database died!");
+
+    match quality {
+        Some(quality) => goodbye_desc!(format!(
+            "Media resized in this chat will now have {}% quality, unless the command says otherwise.",
+            quality
+        )),
+        None => goodbye_desc!("The default quality of this chat was reset."),
+    }
+}
+
 pub const AMOGUS: Command = Command {
     callname: "/amogus &lt;amogus&gt;",
     description: "amogus",
     function: wrap!(amogus),
     hidden: false,
+    requires: &[],
 };
 async fn amogus(tp: TaskParams<'_>) -> Ret {
     let task = Task::default_amogus();
@@ -443,10 +759,19 @@ async fn resize_inner(tp: TaskParams<'_>, resize_type: ResizeType) -> Ret {
     let media = tp.message.get_media_info();
     let media = match media {
         Some(media) => {
-            if !media.is_raster() {
+            // Animated stickers are rendered into a video, if there's a way to.
+            let renderable =
+                media.is_animated_sticker && media_processing::can_render_animated_stickers();
+            if media.is_animated_sticker && !renderable {
+                goodbye_cancel!(concat!(
+                    "this instance can't render animated stickers, ",
+                    "and resizing just one frame of it would lose the animation."
+                ));
+            }
+            if !media.is_raster() && !renderable {
                 goodbye_cancel!("can't work with animated stickers nor voice messages.");
             }
-            check_too_large!(media);
+            check_too_large!(tp, media);
             media
         }
         None => goodbye_cancel!(concat!(
@@ -459,32 +784,103 @@ async fn resize_inner(tp: TaskParams<'_>, resize_type: ResizeType) -> Ret {
         goodbye_cancel!("media is too small.");
     }
 
+    // Media made by this bot starts off of the parameters of what made it,
+    // so that doing the same thing to it again compounds.
+    let previous = task_of_replied_result(&tp).await;
+    // Its size is repeated too, unless another one is asked for.
+    let parse = |task: Task| match previous.as_ref().and_then(|x| task.inherit_resize(x)) {
+        Some(inherited) if !has_size_param(tp.get_params()) => {
+            let size = previous.as_ref().and_then(Task::repeat_size_param);
+            let params = format!("{} {}", size.unwrap_or_default(), tp.get_params());
+            inherited.parse_inherited_params_text(&tp, params.trim())
+        }
+        Some(inherited) => inherited.parse_inherited_params_text(&tp, tp.get_params()),
+        None => task.parse_params(&tp),
+    };
+
     let task = if media.is_image() {
-        unfail!(Task::default_image_resize(
+        let mut task = unfail!(parse(Task::default_image_resize(
             media.width as i32,
             media.height as i32,
             resize_type,
             ImageFormat::Preserve
-        )
-        .parse_params(&tp))
+        )));
+
+        // If the photo is in an album, resize the whole album.
+        let photo_message = if tp.message.find_biggest_photo().is_some() {
+            Some(tp.message)
+        } else {
+            tp.message
+                .reply_to_message()
+                .filter(|x| x.find_biggest_photo().is_some())
+        };
+        if let (
+            Some(photo_message),
+            Task::ImageResize {
+                new_dimensions,
+                album,
+                ..
+            },
+        ) = (photo_message, &mut task)
+        {
+            tp.taskman.albums.remember(photo_message);
+            let mut photos = tp.taskman.albums.get(photo_message);
+            if photos.len() > 1 {
+                unfail!(scale_album_dimensions(
+                    &mut photos,
+                    (media.width, media.height),
+                    *new_dimensions
+                ));
+                *album = photos;
+            }
+        }
+
+        task
     } else {
-        unfail!(Task::default_video_resize(
+        let task = Task::default_video_resize(
             media.width as i32,
             media.height as i32,
             resize_type,
-            VideoTypePreference::Preserve
-        )
-        .parse_params(&tp))
+            VideoTypePreference::Preserve,
+        );
+        // Video stickers are VP9 with transparency, which MP4 would flatten.
+        let task = if media.is_sticker && !media.is_animated_sticker {
+            task.prefer_webm()
+        } else {
+            task
+        };
+        unfail!(parse(task))
     };
 
     Ok(Ok(task))
 }
 
+/// If this command is used on a result of a task this bot sent, get that task.
+async fn task_of_replied_result(tp: &TaskParams<'_>) -> Option<Task> {
+    let reply = tp.message.reply_to_message()?;
+    if reply.from().is_none_or(|x| x.id != tp.bot_me.id) {
+        return None;
+    }
+
+    // Media attached to the command itself is what's worked on instead.
+    let own = tp.message.get_media_info()?.file.unique_id.clone();
+    if reply.get_media_info()?.file.unique_id != own {
+        return None;
+    }
+
+    tp.taskman
+        .db
+        .get_task_by_result_message(reply)
+        .await
+        .expect("Database died!")
+}
+
 pub const TO_STICKER: Command = Command {
     callname: "/to_sticker &lt;image&gt;",
     description: "Converts the image into a 512x512 WEBP suitable for usage as a sticker.",
     function: wrap!(to_sticker),
     hidden: false,
+    requires: &[],
 };
 async fn to_sticker(tp: TaskParams<'_>) -> Ret {
     let task = Task::default_to_sticker();
@@ -495,7 +891,7 @@ async fn to_sticker(tp: TaskParams<'_>) -> Ret {
             if !photo.is_image() {
                 goodbye_cancel!("can't work with video nor animated nor video stickers.");
             }
-            check_too_large!(photo);
+            check_too_large!(tp, photo);
             photo
         }
         None => goodbye_cancel!(concat!(
@@ -508,10 +904,14 @@ async fn to_sticker(tp: TaskParams<'_>) -> Ret {
 }
 
 pub const TO_CUSTOM_EMOJI: Command = Command {
-    callname: "/to_custom_emoji &lt;image&gt;",
-    description: "Converts the image into a 100x100 WEBP suitable for usage as a custom emoji.",
+    callname: "/to_custom_emoji &lt;image&gt; [grid:&lt;columns&gt;x&lt;rows&gt;]",
+    description: concat!(
+        "Converts the image into a 100x100 WEBP suitable for usage as a custom emoji, ",
+        "or a grid of them."
+    ),
     function: wrap!(to_custom_emoji),
     hidden: false,
+    requires: &[],
 };
 async fn to_custom_emoji(tp: TaskParams<'_>) -> Ret {
     let task = Task::default_to_custom_emoji();
@@ -522,7 +922,7 @@ async fn to_custom_emoji(tp: TaskParams<'_>) -> Ret {
             if !photo.is_image() {
                 goodbye_cancel!("can't work with video nor animated nor video stickers.");
             }
-            check_too_large!(photo);
+            check_too_large!(tp, photo);
             photo
         }
         None => goodbye_cancel!(concat!(
@@ -550,14 +950,60 @@ pub const RESIZE: Command = Command {
     ),
     function: wrap!(resize),
     hidden: false,
+    requires: &[],
 };
 fn resize(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
     resize_inner(tp, ResizeType::Fit)
 }
 
+pub const MIRROR: Command = Command {
+    callname: "/mirror &lt;image/video&gt; [&lt;h/v/both&gt;]",
+    description: "Mirrors the media horizontally, or as specified.",
+    function: wrap!(mirror),
+    hidden: false,
+    requires: &[],
+};
+fn mirror(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
+    resize_inner(
+        tp,
+        ResizeType::Mirror {
+            horizontal: true,
+            vertical: false,
+        },
+    )
+}
+
+pub const FLIP: Command = Command {
+    callname: "/flip &lt;image/video&gt; [&lt;h/v/both&gt;]",
+    description: "Flips the media upside down, or as specified.",
+    function: wrap!(flip),
+    hidden: false,
+    requires: &[],
+};
+fn flip(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
+    resize_inner(
+        tp,
+        ResizeType::Mirror {
+            horizontal: false,
+            vertical: true,
+        },
+    )
+}
+
+pub const KALEIDOSCOPE: Command = Command {
+    callname: "/kaleidoscope &lt;image/video&gt; [&lt;segments&gt;]",
+    description: "Makes the media into a kaleidoscope of mirrored copies of a part of it.",
+    function: wrap!(kaleidoscope),
+    hidden: false,
+    requires: &[],
+};
+fn kaleidoscope(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
+    resize_inner(tp, ResizeType::Kaleidoscope { segments: 4 })
+}
+
 pub const DISTORT: Command = Command {
     callname: concat!(
-        "/distort &lt;image&gt; ",
+        "/distort &lt;image/audio&gt; ",
         "[&lt;WxH&gt; or &lt;size%&gt;] ",
         "[&lt;delta_x&gt;] ",
         "[&lt;rigidity&gt;] ",
@@ -565,13 +1011,26 @@ pub const DISTORT: Command = Command {
     ),
     description: concat!(
         "Distorts the media using seam carving and rotates it by \"rot\" degrees. ",
-        "By default will reduce the image/video's size in half on each side."
+        "By default will reduce the image/video's size in half on each side. ",
+        "Audio and voice messages get vibrato applied instead."
     ),
     function: wrap!(distort),
     hidden: false,
+    requires: &[],
 };
-fn distort(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
-    resize_inner(tp, ResizeType::default_seam_carve())
+async fn distort(tp: TaskParams<'_>) -> Ret {
+    // Audio can't be seam carved, but can still have vibrato applied.
+    if let Some(media) = tp.message.get_media_info() {
+        if media.is_sound {
+            let task = Task::default_distort_audio();
+            print_help!(tp, task);
+            check_too_large!(tp, media);
+            let task = unfail!(task.parse_params(&tp));
+            return Ok(Ok(task));
+        }
+    }
+
+    resize_inner(tp, ResizeType::default_seam_carve()).await
 }
 
 pub const OCR: Command = Command {
@@ -582,6 +1041,7 @@ pub const OCR: Command = Command {
     ),
     function: wrap!(ocr),
     hidden: false,
+    requires: &[ExternalTool::Tesseract],
 };
 async fn ocr(tp: TaskParams<'_>) -> Ret {
     let task = Task::default_ocr();
@@ -592,7 +1052,7 @@ async fn ocr(tp: TaskParams<'_>) -> Ret {
             if !photo.is_image() {
                 goodbye_cancel!("can't work with video nor animated nor video stickers.");
             }
-            check_too_large!(photo);
+            check_too_large!(tp, photo);
             photo
         }
         None => goodbye_cancel!(concat!(
@@ -604,6 +1064,34 @@ async fn ocr(tp: TaskParams<'_>) -> Ret {
     Ok(Ok(task))
 }
 
+pub const PALETTE: Command = Command {
+    callname: "/palette [&lt;count&gt;]",
+    description: "Get the most dominant colors of an image, as a swatch and hex codes.",
+    function: wrap!(palette),
+    hidden: false,
+    requires: &[],
+};
+async fn palette(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_palette();
+    print_help!(tp, temp_task);
+    let photo = tp.message.get_media_info();
+    let _photo = match photo {
+        Some(photo) => {
+            if !photo.is_image() {
+                goodbye_cancel!("can't work with video nor animated nor video stickers.");
+            }
+            check_too_large!(tp, photo);
+            photo
+        }
+        None => goodbye_cancel!(concat!(
+            "can't find an image. ",
+            "This command needs to be used as either a reply or caption to one."
+        )),
+    };
+
+    Ok(temp_task.parse_params(&tp))
+}
+
 async fn to_video_or_gif_inner(tp: TaskParams<'_>, to_gif: bool) -> Ret {
     let temp_task = Task::default_video_resize(
         1,
@@ -628,7 +1116,7 @@ async fn to_video_or_gif_inner(tp: TaskParams<'_>, to_gif: bool) -> Ret {
             if video.is_image() {
                 goodbye_cancel!("can't work with non-video images.");
             }
-            check_too_large!(video);
+            check_too_large!(tp, video);
             video
         }
         None => goodbye_cancel!(concat!(
@@ -653,8 +1141,14 @@ async fn to_video_or_gif_inner(tp: TaskParams<'_>, to_gif: bool) -> Ret {
                 let mut tempfile = NamedTempFile::new()?;
                 tempfile.write_all(&buf)?;
                 tempfile.flush()?;
-                let has_audio =
-                    count_video_frames_and_framerate_and_audio_and_length(tempfile.path(), false)?.2;
+                // This just reads the file through, so it should be quick.
+                let deadline = Instant::now() + Duration::from_secs(60);
+                let has_audio = count_video_frames_and_framerate_and_audio_and_length(
+                    tempfile.path(),
+                    false,
+                    deadline,
+                )?
+                .2;
                 Ok::<_, std::io::Error>(has_audio)
             };
 
@@ -725,6 +1219,7 @@ pub const TO_VIDEO: Command = Command {
     description: "Turn a GIF or a video sticker into a video.",
     function: wrap!(to_video),
     hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
 };
 fn to_video(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
     to_video_or_gif_inner(tp, false)
@@ -735,6 +1230,7 @@ pub const TO_GIF: Command = Command {
     description: "Turn a video into a GIF.",
     function: wrap!(to_gif),
     hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
 };
 fn to_gif(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
     to_video_or_gif_inner(tp, true)
@@ -774,6 +1270,7 @@ pub const PREMIUM: Command = Command {
     description: "premium",
     function: wrap!(premium),
     hidden: true,
+    requires: &[],
 };
 fn premium(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
     premium_inner(tp, true)
@@ -783,16 +1280,69 @@ pub const UNPREMIUM: Command = Command {
     description: "unpremium",
     function: wrap!(unpremium),
     hidden: true,
+    requires: &[],
 };
 fn unpremium(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
     premium_inner(tp, false)
 }
 
+pub const LANG: Command = Command {
+    callname: "/lang [en/ru/auto]",
+    description: "Change the language the bot speaks to you in.",
+    function: wrap!(lang),
+    hidden: false,
+    requires: &[],
+};
+async fn lang(tp: TaskParams<'_>) -> Ret {
+    let Some(user) = tp
+        .message
+        .from()
+        .filter(|x| !x.is_anonymous() && !x.is_channel())
+    else {
+        goodbye_cancel!(Key::LangAnonymous.get(tp.lang()));
+    };
+
+    let Some(param) = tp
+        .get_params()
+        .split_whitespace()
+        .next()
+        .filter(|x| !request_for_help(x))
+    else {
+        let lang = tp.lang();
+        goodbye_desc!(fill(Key::LangCurrent.get(lang), &[&lang.name()]));
+    };
+
+    let new_lang = if param.eq_ignore_ascii_case("auto") {
+        None
+    } else {
+        let Some(new_lang) = Lang::parse(param) else {
+            goodbye_desc!(fill(
+                Key::LangUnknown.get(tp.lang()),
+                &[&encode_text(param)]
+            ));
+        };
+        Some(new_lang)
+    };
+
+    tp.taskman
+        .set_user_language(user.id, new_lang)
+        .await
+        .expect("Database died!");
+
+    // Already in the new language.
+    let lang = tp.lang();
+    match new_lang {
+        Some(new_lang) => goodbye_desc!(fill(Key::LangSet.get(lang), &[&new_lang.name()])),
+        None => goodbye_desc!(Key::LangAuto.get(lang)),
+    }
+}
+
 pub const AMENBREAK: Command = Command {
-    callname: "/amenbreak",
+    callname: "/amenbreak [&lt;seed&gt;]",
     description: "Replace a video/gif's audio with an amen break.",
     function: wrap!(amenbreak),
     hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
 };
 async fn amenbreak(tp: TaskParams<'_>) -> Ret {
     let temp_task = Task::default_amenbreak();
@@ -806,7 +1356,7 @@ async fn amenbreak(tp: TaskParams<'_>) -> Ret {
             if media.is_sound {
                 goodbye_cancel!("can't work with audio messages.");
             }
-            check_too_large!(media);
+            check_too_large!(tp, media);
             media
         }
         None => goodbye_cancel!(concat!(
@@ -815,7 +1365,264 @@ async fn amenbreak(tp: TaskParams<'_>) -> Ret {
         )),
     };
 
-    Ok(Ok(temp_task))
+    Ok(temp_task.parse_params(&tp))
+}
+
+pub const SPEED: Command = Command {
+    callname: "/speed &lt;video/audio&gt; [&lt;factor&gt;]",
+    description: "Speed up or slow down a video, a GIF or audio.",
+    function: wrap!(speed),
+    hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
+};
+async fn speed(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_speed_change();
+    print_help!(tp, temp_task);
+    let media = tp.message.get_media_info();
+    let _media = match media {
+        Some(media) => {
+            let usable = media.is_sound || (media.is_video && media.is_raster());
+            if !usable {
+                goodbye_cancel!("can't work with images nor animated stickers.");
+            }
+            check_too_large!(tp, media);
+            media
+        }
+        None => goodbye_cancel!(concat!(
+            "can't find a video or audio. ",
+            "This command needs to be used as either a reply or caption to one."
+        )),
+    };
+
+    Ok(temp_task.parse_params(&tp))
+}
+
+pub const TRIM: Command = Command {
+    callname: "/trim &lt;video/audio&gt; &lt;START-END&gt;",
+    description: "Cut a range out of a video, a GIF or audio.",
+    function: wrap!(trim),
+    hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
+};
+async fn trim(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_trim();
+    print_help!(tp, temp_task);
+    let media = tp.message.get_media_info();
+    let _media = match media {
+        Some(media) => {
+            let usable = media.is_sound || (media.is_video && media.is_raster());
+            if !usable {
+                goodbye_cancel!("can't work with images nor animated stickers.");
+            }
+            check_too_large!(tp, media);
+            media
+        }
+        None => goodbye_cancel!(concat!(
+            "can't find a video or audio. ",
+            "This command needs to be used as either a reply or caption to one."
+        )),
+    };
+
+    Ok(temp_task.parse_params(&tp))
+}
+
+pub const FRAME: Command = Command {
+    callname: "/frame &lt;video&gt; [&lt;timestamp&gt;]",
+    description: "Grab a single frame of a video or a GIF as a photo.",
+    function: wrap!(frame),
+    hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
+};
+fn frame(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
+    frame_inner(tp, Task::default_frame())
+}
+
+pub const FRAMES: Command = Command {
+    callname: "/frames &lt;video&gt; [&lt;grid&gt;]",
+    description: "Make a contact sheet of evenly spaced frames of a video or a GIF.",
+    function: wrap!(frames),
+    hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
+};
+fn frames(tp: TaskParams<'_>) -> impl Future<Output = Ret> + '_ {
+    frame_inner(tp, Task::default_frames())
+}
+
+async fn frame_inner(tp: TaskParams<'_>, temp_task: Task) -> Ret {
+    print_help!(tp, temp_task);
+    let media = tp.message.get_media_info();
+    let _media = match media {
+        Some(media) => {
+            if !media.is_video || !media.is_raster() || media.is_sound {
+                goodbye_cancel!("can only work with videos and GIFs.");
+            }
+            check_too_large!(tp, media);
+            media
+        }
+        None => goodbye_cancel!(concat!(
+            "can't find a video. ",
+            "This command needs to be used as either a reply or caption to one."
+        )),
+    };
+
+    Ok(temp_task.parse_params(&tp))
+}
+
+pub const NEGATE: Command = Command {
+    callname: "/negate &lt;media&gt; [alpha]",
+    description: "Invert colors of an image, a video or a GIF.",
+    function: wrap!(negate),
+    hidden: false,
+    requires: &[],
+};
+async fn negate(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_negate();
+    print_help!(tp, temp_task);
+    let media = tp.message.get_media_info();
+    let _media = match media {
+        Some(media) => {
+            if !media.is_raster() || media.is_sound {
+                goodbye_cancel!("can't work with animated stickers nor audio.");
+            }
+            check_too_large!(tp, media);
+            media
+        }
+        None => goodbye_cancel!(concat!(
+            "can't find a video or an image. ",
+            "This command needs to be used as either a reply or caption to one."
+        )),
+    };
+
+    Ok(temp_task.parse_params(&tp))
+}
+
+pub const SPOILER: Command = Command {
+    callname: "/spoiler &lt;media&gt; [caption]",
+    description: "Send an image, a video or a GIF back hidden under a spoiler.",
+    function: wrap!(spoiler),
+    hidden: false,
+    requires: &[],
+};
+async fn spoiler(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_spoiler();
+    print_help!(tp, temp_task);
+    let media = tp.message.get_media_info();
+    let _media = match media {
+        Some(media) => {
+            if media.is_sticker {
+                goodbye_cancel!("stickers can't be hidden under a spoiler.");
+            }
+            if !media.is_raster() || media.is_voice_or_video_note {
+                goodbye_cancel!("only images, videos and GIFs can be hidden under a spoiler.");
+            }
+            media
+        }
+        None => {
+            let message = tp.message;
+            let reply = message.reply_to_message();
+            if message.document().is_some() || reply.is_some_and(|x| x.document().is_some()) {
+                goodbye_cancel!(concat!(
+                    "files can't be hidden under a spoiler. ",
+                    "Send it as a photo or a video instead."
+                ));
+            }
+            goodbye_cancel!(concat!(
+                "can't find an image or a video. ",
+                "This command needs to be used as either a reply or caption to one."
+            ))
+        }
+    };
+
+    Ok(temp_task.parse_params(&tp))
+}
+
+pub const CONCAT: Command = Command {
+    callname: "/concat &lt;videos&gt;",
+    description: "Join videos or GIFs of an album one after another.",
+    function: wrap!(concat),
+    hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
+};
+async fn concat(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_concat();
+    print_help!(tp, temp_task);
+
+    let own = VideoClip::from_message(tp.message);
+    let reply = tp.message.reply_to_message();
+
+    // Either every video of the album the command is used on...
+    let album_message = if own.is_some() {
+        Some(tp.message)
+    } else {
+        reply.filter(|x| VideoClip::from_message(x).is_some())
+    };
+    let mut clips = match album_message {
+        Some(album_message) => {
+            tp.taskman.albums.remember(album_message);
+            tp.taskman.albums.get_videos(album_message)
+        }
+        None => Vec::new(),
+    };
+
+    // ...or the video the command is attached to, after the one it replies to.
+    if clips.len() < 2 {
+        if let (Some(own), Some(replied)) = (&own, reply.and_then(VideoClip::from_message)) {
+            clips = vec![replied, own.clone()];
+        }
+    }
+
+    if clips.len() < 2 {
+        goodbye_cancel!(concat!(
+            "can't find two videos to join. Reply with this command to an album of videos, ",
+            "or attach a video to it while replying to another one."
+        ));
+    }
+
+    for clip in &clips {
+        if clip.file.size > MAX_DOWNLOAD_SIZE_MEGABYTES * 1000 * 1000 {
+            goodbye_cancel!(fill(
+                Key::MediaTooLarge.get(tp.lang()),
+                &[&MAX_DOWNLOAD_SIZE_MEGABYTES]
+            ));
+        }
+        if clip.width < 1 || clip.height < 1 {
+            goodbye_cancel!("media is too small.");
+        }
+    }
+
+    Ok(Ok(Task::Concat { clips }))
+}
+
+pub const BOOST: Command = Command {
+    callname: "/boost &lt;video/audio&gt; [&lt;gain&gt;]",
+    description: "Make a video or audio louder, or normalize its loudness.",
+    function: wrap!(boost),
+    hidden: false,
+    requires: &[ExternalTool::Ffmpeg],
+};
+async fn boost(tp: TaskParams<'_>) -> Ret {
+    let temp_task = Task::default_boost();
+    print_help!(tp, temp_task);
+    let media = tp.message.get_media_info();
+    let _media = match media {
+        Some(media) => {
+            if media.is_gif {
+                goodbye_cancel!("GIFs don't have any audio to boost.");
+            }
+            let usable = media.is_sound || (media.is_video && media.is_raster());
+            if !usable {
+                goodbye_cancel!("can't work with images nor animated stickers.");
+            }
+            check_too_large!(tp, media);
+            media
+        }
+        None => goodbye_cancel!(concat!(
+            "can't find a video or audio. ",
+            "This command needs to be used as either a reply or caption to one."
+        )),
+    };
+
+    Ok(temp_task.parse_params(&tp))
 }
 
 #[cfg(test)]
@@ -854,4 +1661,37 @@ mod tests {
             assert!(command.description.len() <= 256);
         }
     }
+
+    #[test]
+    fn commands_missing_tools_are_hidden() {
+        let names = |commands: Vec<BotCommand>| -> Vec<String> {
+            commands.into_iter().map(|x| x.command).collect()
+        };
+
+        let everything = names(Command::generate_bot_commands_with(|_| true));
+        assert!(everything.contains(&"ocr".to_string()));
+        assert!(OCR.is_available_with(|_| true));
+
+        let no_tesseract = |tool| tool != ExternalTool::Tesseract;
+        assert!(!OCR.is_available_with(no_tesseract));
+        assert!(RESIZE.is_available_with(no_tesseract));
+        let without_tesseract = names(Command::generate_bot_commands_with(no_tesseract));
+        assert!(!without_tesseract.contains(&"ocr".to_string()));
+        assert!(without_tesseract.contains(&"resize".to_string()));
+
+        // Everything that needs ffmpeg is gone without it, and nothing else.
+        let no_ffmpeg = |tool| tool != ExternalTool::Ffmpeg;
+        let without_ffmpeg = names(Command::generate_bot_commands_with(no_ffmpeg));
+        for command in COMMANDS.iter().filter(|x| !x.hidden) {
+            let Some(callname) = command.callname.split_ascii_whitespace().next() else {
+                continue;
+            };
+            assert_eq!(
+                without_ffmpeg.contains(&callname[1..].to_string()),
+                !command.requires.contains(&ExternalTool::Ffmpeg),
+                "{}",
+                callname
+            );
+        }
+    }
 }
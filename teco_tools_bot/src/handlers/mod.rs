@@ -5,21 +5,56 @@ use chrono::Utc;
 use std::sync::Arc;
 
 use teloxide::{
-    payloads::{EditMessageTextSetters, SendMessageSetters},
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
     requests::Requester,
-    types::{Me, Message},
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Me, Message, UserId},
     Bot, RequestError,
 };
 
-use crate::tasks::{parsing::TaskError, taskman::Taskman, Task};
+use crate::{
+    strings::Key,
+    tasks::{
+        parsing::{replace_size_params, TaskError, MAX_OUTPUT_MEDIA_DIMENSION_SIZE},
+        taskman::{database::PARAM_FIX_TIME, Taskman},
+        Task,
+    },
+};
+
+use self::commands::TaskParams;
 
+/// Also returns the text of the command the task was parsed from.
+/// That's the message's text, unless it's a `/redo`, see [`commands::redone_command`].
 pub async fn parse_command_into_task(
     taskman: &Taskman,
     bot: &Bot,
     bot_me: &Me,
     message: &Message,
-) -> Result<Result<Task, TaskError>, RequestError> {
+) -> Result<(Result<Task, TaskError>, String), RequestError> {
+    let text = message.text_full().unwrap_or_default();
+    if let Some(command) = commands::redone_command(taskman, bot, bot_me, message, text).await {
+        let task = parse_text_into_task(taskman, bot, bot_me, message, &command).await?;
+        return Ok((task, command));
+    }
+
     let Some(task) = Task::parse_task(taskman, bot, bot_me, message) else {
+        return Ok((Err(TaskError::Error(String::new())), text.to_string()));
+    };
+
+    Ok((task.await?, text.to_string()))
+}
+
+/// Same as [`parse_command_into_task`], but parsing `text` as the command
+/// instead of the message's own text.
+async fn parse_text_into_task(
+    taskman: &Taskman,
+    bot: &Bot,
+    bot_me: &Me,
+    message: &Message,
+    text: &str,
+) -> Result<Result<Task, TaskError>, RequestError> {
+    let Some(task) =
+        TaskParams::with_text(taskman, bot, bot_me, message, text).and_then(TaskParams::make_task)
+    else {
         return Ok(Err(TaskError::Error(String::new())));
     };
 
@@ -38,21 +73,230 @@ pub async fn handle_new_message(
         return Ok(());
     }
 
-    let task = match parse_command_into_task(&taskman, &bot, &me, &message).await? {
+    taskman.albums.remember(&message);
+
+    let (task, command) = parse_command_into_task(&taskman, &bot, &me, &message).await?;
+    let task = match task {
         Ok(t) => t,
         Err(e) => {
             if !e.is_empty() {
-                bot.send_message(message.chat.id, e.cancel_to_error().to_string())
+                let keyboard = param_fix_keyboard(&e, &message);
+                let lang = taskman.lang_for(&message);
+                let mut request = bot
+                    .send_message(message.chat.id, e.cancel_to_error().localized(lang))
                     .disable_web_page_preview(true)
                     .reply_to_message_id(message.id)
-                    .parse_mode(teloxide::types::ParseMode::Html)
-                    .await?;
+                    .parse_mode(teloxide::types::ParseMode::Html);
+                if let Some(keyboard) = &keyboard {
+                    request = request.reply_markup(keyboard.clone());
+                }
+                let response = request.await?;
+
+                if keyboard.is_some() {
+                    taskman
+                        .db
+                        .add_param_fix(&response, &message)
+                        .await
+                        .expect("Database died!");
+                    expire_param_fix_keyboard(bot.clone(), &response);
+                }
             }
             return Ok(());
         }
     };
 
-    // We got a task. Reply about it and push it to queue.
+    queue_task(&bot, &message, task, &taskman, false, &command).await
+}
+
+/// A quick fix offered with a button under an error response to a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ParamFix {
+    /// Resize to the biggest allowed size instead.
+    Max,
+    /// Resize to the biggest allowed size without stretching instead.
+    MaxFit,
+    /// Show the full list of parameters of the command.
+    Help,
+}
+
+impl ParamFix {
+    fn label(self) -> String {
+        match self {
+            ParamFix::Max => format!("Use max ({})", MAX_OUTPUT_MEDIA_DIMENSION_SIZE),
+            ParamFix::MaxFit => "Use maxfit".to_string(),
+            ParamFix::Help => "Show full help".to_string(),
+        }
+    }
+
+    fn callback_data(self) -> &'static str {
+        match self {
+            ParamFix::Max => "fix max",
+            ParamFix::MaxFit => "fix maxfit",
+            ParamFix::Help => "fix help",
+        }
+    }
+
+    fn from_callback_data(data: &str) -> Option<Self> {
+        [ParamFix::Max, ParamFix::MaxFit, ParamFix::Help]
+            .into_iter()
+            .find(|x| x.callback_data() == data)
+    }
+
+    /// Make the text of the command with this fix applied to its parameters.
+    fn apply(self, command: &str, params: &str) -> String {
+        match self {
+            ParamFix::Max => format!("{} {}", command, replace_size_params(params, "max")),
+            ParamFix::MaxFit => format!("{} {}", command, replace_size_params(params, "maxfit")),
+            ParamFix::Help => format!("{} help", command),
+        }
+    }
+}
+
+/// Check if this user may press the quick fix buttons for this request message.
+/// Only whoever sent the command can fix it, unless they're anonymous.
+fn may_use_param_fix(request: &Message, user: UserId) -> bool {
+    let sender = request
+        .from()
+        .filter(|x| !x.is_anonymous() && !x.is_channel());
+    sender.is_none_or(|x| x.id == user)
+}
+
+/// Make quick fix buttons for this error in response to this request message, if any fit.
+fn param_fix_keyboard(error: &TaskError, request: &Message) -> Option<InlineKeyboardMarkup> {
+    let fixes: &[ParamFix] = match error {
+        TaskError::TooBig(_) => &[ParamFix::Max, ParamFix::MaxFit],
+        // Private chats get the full list of parameters in the error already.
+        TaskError::BadParam(_) if !request.chat.is_private() => &[ParamFix::Help],
+        _ => return None,
+    };
+
+    Some(InlineKeyboardMarkup::new([fixes.iter().map(|x| {
+        InlineKeyboardButton::callback(x.label(), x.callback_data())
+    })]))
+}
+
+/// Remove the quick fix buttons from this error response once they stop working.
+fn expire_param_fix_keyboard(bot: Bot, response: &Message) {
+    let (chat_id, message_id) = (response.chat.id, response.id);
+    tokio::spawn(async move {
+        tokio::time::sleep(PARAM_FIX_TIME.to_std().unwrap()).await;
+        let _ = bot.edit_message_reply_markup(chat_id, message_id).await;
+    });
+}
+
+pub async fn handle_callback_query(
+    bot: Bot,
+    me: Me,
+    query: CallbackQuery,
+    taskman: Arc<Taskman>,
+) -> Result<(), RequestError> {
+    let (Some(fix), Some(response)) = (
+        query.data.as_deref().and_then(ParamFix::from_callback_data),
+        &query.message,
+    ) else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+
+    let Some(request) = taskman
+        .db
+        .get_param_fix(response)
+        .await
+        .expect("Database died!")
+    else {
+        let _ = bot
+            .edit_message_reply_markup(response.chat.id, response.id)
+            .await;
+        bot.answer_callback_query(query.id)
+            .text("These buttons don't work anymore. Please send the command again.")
+            .await?;
+        return Ok(());
+    };
+
+    if !may_use_param_fix(&request, query.from.id) {
+        bot.answer_callback_query(query.id)
+            .text("Only the person who sent the command can use these buttons.")
+            .await?;
+        return Ok(());
+    }
+
+    // Someone may have pressed a button at the same time.
+    if !taskman
+        .db
+        .take_param_fix(response)
+        .await
+        .expect("Database died!")
+    {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    }
+
+    bot.answer_callback_query(query.id).await?;
+
+    let Some(tp) = TaskParams::new(&taskman, &bot, &me, &request) else {
+        return Ok(());
+    };
+    let fixed_command = fix.apply(tp.command(), tp.get_params());
+
+    let lang = taskman.lang_for(&request);
+    let response_text =
+        match parse_text_into_task(&taskman, &bot, &me, &request, &fixed_command).await? {
+            Ok(task) => {
+                let _ = bot
+                    .edit_message_text(
+                        response.chat.id,
+                        response.id,
+                        format!(
+                            "Using <code>{}</code> instead.",
+                            html_escape::encode_text(&fixed_command)
+                        ),
+                    )
+                    .parse_mode(teloxide::types::ParseMode::Html)
+                    .await;
+
+                return queue_task(&bot, &request, task, &taskman, false, &fixed_command).await;
+            }
+            Err(help) if fix == ParamFix::Help => {
+                // Show the original error along with it.
+                let command = request.text_full().unwrap_or_default();
+                match parse_text_into_task(&taskman, &bot, &me, &request, command).await? {
+                    Err(error) if !error.is_empty() => {
+                        format!(
+                            "{}\n{}",
+                            error.cancel_to_error().localized(lang),
+                            help.localized(lang)
+                        )
+                    }
+                    _ => help.localized(lang),
+                }
+            }
+            Err(e) => e.cancel_to_error().localized(lang),
+        };
+
+    if !response_text.trim().is_empty() {
+        let _ = bot
+            .edit_message_text(response.chat.id, response.id, response_text)
+            .disable_web_page_preview(true)
+            .parse_mode(teloxide::types::ParseMode::Html)
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Reply to the message that requested this task and push it to queue.
+/// Set `rerun` if it's being done again due to that message being edited.
+/// `command` is the text of the command the task was parsed from.
+async fn queue_task(
+    bot: &Bot,
+    message: &Message,
+    task: Task,
+    taskman: &Taskman,
+    rerun: bool,
+    command: &str,
+) -> Result<(), RequestError> {
+    let sender_id = message.from().map(|from| from.id);
+    let lang = taskman.lang_for(message);
 
     let premium = if let Some(sender_id) = sender_id {
         taskman
@@ -74,15 +318,9 @@ pub async fn handle_new_message(
         bot.send_message(
             message.chat.id,
             if sender_id.is_some() {
-                concat!(
-                    "Sorry, but you have too many tasks queued up at the moment. ",
-                    "Please try again later."
-                )
+                Key::TooManyTasks.get(lang)
             } else {
-                concat!(
-                    "Sorry, but anonymous users have too many tasks queued up at the moment. ",
-                    "Please try again later."
-                )
+                Key::TooManyTasksAnonymous.get(lang)
             },
         )
         .reply_to_message_id(message.id)
@@ -120,8 +358,14 @@ pub async fn handle_new_message(
         .await
         .expect("Database died!");
 
-    let response =
-        task.produce_queue_message(delay_processing_until.is_none().then_some(queue_size), None);
+    let mut response = task.produce_queue_message(
+        delay_processing_until.is_none().then_some(queue_size),
+        None,
+        lang,
+    );
+    if rerun {
+        response.insert_str(0, &format!("{}\n", Key::RerunDueToEdit.get(lang)));
+    }
 
     let queue_response_message = teloxide_retry!(
         bot.send_message(message.chat.id, &response)
@@ -131,12 +375,18 @@ pub async fn handle_new_message(
             .await
     )?;
 
-    if message.media_group_id().is_some() {
+    let handles_album = match &task {
+        Task::ImageResize { album, .. } => !album.is_empty(),
+        Task::Concat { .. } => true,
+        _ => false,
+    };
+    if message.media_group_id().is_some() && !handles_album {
         bot.send_message(
             message.chat.id,
             concat!(
-                "<b>Important:</b> this bot does not support album uploads. ",
-                "Please reply with the command to each media separately."
+                "<b>Important:</b> a command in an album's caption only works on the media ",
+                "it's attached to. Reply to the album with the command instead, ",
+                "or to each media separately if it's not a photo."
             ),
         )
         .reply_to_message_id(message.id)
@@ -148,7 +398,8 @@ pub async fn handle_new_message(
         .add_task(
             sender_id,
             task,
-            &message,
+            command,
+            message,
             &queue_response_message,
             delay_processing_until,
         )
@@ -164,13 +415,50 @@ pub async fn handle_edited_message(
     message: Message,
     taskman: Arc<Taskman>,
 ) -> Result<(), RequestError> {
+    let lang = taskman.lang_for(&message);
+
     let Some(taskdata) = taskman
         .db
         .get_task_by_request_message(&message)
         .await
         .expect("Database died!")
     else {
-        return Ok(());
+        // It may be a task that was completed recently. If so, run it again.
+        if !taskman
+            .db
+            .was_task_recently_completed(&message)
+            .await
+            .expect("Database died!")
+        {
+            return Ok(());
+        }
+
+        let (task, command) = parse_command_into_task(&taskman, &bot, &me, &message).await?;
+        let task = match task {
+            Ok(t) => t,
+            Err(e) => {
+                if !e.is_empty() && !e.is_cancel() {
+                    bot.send_message(message.chat.id, e.localized(lang))
+                        .disable_web_page_preview(true)
+                        .reply_to_message_id(message.id)
+                        .parse_mode(teloxide::types::ParseMode::Html)
+                        .await?;
+                }
+                return Ok(());
+            }
+        };
+
+        // Another edit may have gotten here first.
+        if !taskman
+            .db
+            .take_recently_completed_task(&message)
+            .await
+            .expect("Database died!")
+        {
+            return Ok(());
+        }
+
+        return queue_task(&bot, &message, task, &taskman, true, &command).await;
     };
 
     if let (Some(edit_response_chat_id), Some(edit_response_message_id)) = (
@@ -182,68 +470,46 @@ pub async fn handle_edited_message(
             .await;
     }
 
-    if taskman
-        .db
-        .get_queue_size_for_task(taskdata.taskid)
-        .await
-        .expect("Database died!")
-        == Some(0)
-    {
-        // Task is currently being run.
-        let edit_response = bot
-            .send_message(
-                message.chat.id,
-                concat!(
-                    "Sorry, but the task is currently being run. ",
-                    "Canceling or editing parameters is not possible at the moment."
-                ),
-            )
-            .reply_to_message_id(message.id)
-            .parse_mode(teloxide::types::ParseMode::Html)
-            .await?;
-
-        taskman
-            .db
-            .task_edit_response(taskdata.taskid, &edit_response)
-            .await
-            .expect("Database died!");
-
-        return Ok(());
+    if taskdata.in_progress {
+        return respond_task_in_progress(&bot, &message, &taskman, taskdata.taskid).await;
     }
 
-    let task = match parse_command_into_task(&taskman, &bot, &me, &message).await? {
+    let (task, command) = parse_command_into_task(&taskman, &bot, &me, &message).await?;
+    let task = match task {
         Ok(t) => t,
         Err(e) => {
             let cancelling = e.is_cancel() || message.text_full().unwrap().starts_with("/cancel");
             if cancelling {
+                // Edit response message is deleted, and request message is bogus.
+                // Delete from the database lol
+                if !taskman
+                    .db
+                    .cancel_task(taskdata.taskid)
+                    .await
+                    .expect("Database died!")
+                {
+                    // It was grabbed for completion in the meantime.
+                    return respond_task_in_progress(&bot, &message, &taskman, taskdata.taskid)
+                        .await;
+                }
+
                 if let TaskError::Cancel(_) = e {
-                    bot.send_message(message.chat.id, e.to_string())
+                    bot.send_message(message.chat.id, e.localized(lang))
                         .reply_to_message_id(message.id)
                         .parse_mode(teloxide::types::ParseMode::Html)
                         .await?;
                 }
 
-                // Edit response message is deleted, and request message is bogus.
-                // Delete queue message and from the database lol
+                // And the queue message.
                 let _ = bot
                     .delete_message(taskdata.queue_message_chat_id, taskdata.queue_message_id)
                     .await;
-
-                taskman
-                    .db
-                    .delete_task(taskdata.taskid)
-                    .await
-                    .expect("Database died!");
             } else {
-                let mut e_txt = e.to_string();
+                let mut e_txt = e.localized(lang);
                 if e.is_empty() {
-                    e_txt.push_str("Failed to parse the command as a task.");
+                    e_txt.push_str(Key::EditFailedToParse.get(lang));
                 }
-                e_txt.push_str(concat!(
-                    "\n\nWill use the previous parameters for the task.\n",
-                    "If you wish to cancel the task, edit your message to say <code>/cancel</code>.\n",
-                    "(Telegram bots can't see message deletion events, by the way)",
-                ));
+                e_txt.push_str(Key::EditKeepsParams.get(lang));
                 let edit_response = bot
                     .send_message(message.chat.id, e_txt)
                     .reply_to_message_id(message.id)
@@ -261,11 +527,15 @@ pub async fn handle_edited_message(
         }
     };
 
-    taskman
+    if !taskman
         .db
-        .edit_task(taskdata.taskid, &task, &message)
+        .edit_task(taskdata.taskid, &task, &command, &message)
         .await
-        .expect("Database died!");
+        .expect("Database died!")
+    {
+        // It was grabbed for completion in the meantime.
+        return respond_task_in_progress(&bot, &message, &taskman, taskdata.taskid).await;
+    }
 
     let queue_size = taskman
         .db
@@ -279,7 +549,7 @@ pub async fn handle_edited_message(
         .map(|x| x > Utc::now())
         .unwrap_or(false);
 
-    let response = task.produce_queue_message(is_delayed.then_some(queue_size), None);
+    let response = task.produce_queue_message(is_delayed.then_some(queue_size), None, lang);
 
     let _ = bot
         .edit_message_text(
@@ -293,3 +563,131 @@ pub async fn handle_edited_message(
 
     Ok(())
 }
+
+/// Tell the user that the task requested by this edited message can't be changed anymore.
+async fn respond_task_in_progress(
+    bot: &Bot,
+    message: &Message,
+    taskman: &Taskman,
+    taskid: i64,
+) -> Result<(), RequestError> {
+    let lang = taskman.lang_for(message);
+    let edit_response = bot
+        .send_message(message.chat.id, Key::TaskInProgress.get(lang))
+        .reply_to_message_id(message.id)
+        .parse_mode(teloxide::types::ParseMode::Html)
+        .await?;
+
+    taskman
+        .db
+        .task_edit_response(taskid, &edit_response)
+        .await
+        .expect("Database died!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(chat: serde_json::Value, from: Option<serde_json::Value>) -> Message {
+        let mut message = serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": chat,
+            "text": "/resize 5000x5000",
+        });
+        if let Some(from) = from {
+            message["from"] = from;
+        }
+        serde_json::from_value(message).unwrap()
+    }
+
+    fn group() -> serde_json::Value {
+        serde_json::json!({ "id": -100, "type": "supergroup", "title": "amogus" })
+    }
+
+    fn user(id: u64) -> serde_json::Value {
+        serde_json::json!({ "id": id, "is_bot": false, "first_name": "Amogus" })
+    }
+
+    #[test]
+    fn param_fix_callback_data_round_trips() {
+        for fix in [ParamFix::Max, ParamFix::MaxFit, ParamFix::Help] {
+            assert_eq!(ParamFix::from_callback_data(fix.callback_data()), Some(fix));
+        }
+        assert_eq!(ParamFix::from_callback_data("fix amogus"), None);
+        assert_eq!(ParamFix::from_callback_data(""), None);
+    }
+
+    #[test]
+    fn param_fixes_are_applied() {
+        assert_eq!(
+            ParamFix::Max.apply("/resize", "webp 5000x5000"),
+            "/resize webp max"
+        );
+        assert_eq!(
+            ParamFix::MaxFit.apply("/resize@teco_tools_bot", "300%"),
+            "/resize@teco_tools_bot maxfit"
+        );
+        assert_eq!(ParamFix::Help.apply("/distort", "amogus"), "/distort help");
+    }
+
+    #[test]
+    fn param_fix_keyboard_fits_the_error() {
+        let buttons = |keyboard: Option<InlineKeyboardMarkup>| -> Vec<String> {
+            keyboard
+                .map(|x| x.inline_keyboard.concat())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|x| x.text)
+                .collect()
+        };
+
+        let in_group = message(group(), Some(user(1)));
+        let in_private = message(
+            serde_json::json!({ "id": 1, "type": "private", "first_name": "Amogus" }),
+            Some(user(1)),
+        );
+        let too_big = TaskError::TooBig("too big".to_string());
+        let bad_param = TaskError::BadParam("bad".to_string());
+
+        for request in [&in_group, &in_private] {
+            assert_eq!(
+                buttons(param_fix_keyboard(&too_big, request)),
+                [ParamFix::Max.label(), ParamFix::MaxFit.label()]
+            );
+        }
+        assert_eq!(
+            buttons(param_fix_keyboard(&bad_param, &in_group)),
+            [ParamFix::Help.label()]
+        );
+        assert!(param_fix_keyboard(&bad_param, &in_private).is_none());
+        assert!(param_fix_keyboard(&TaskError::Error("no".to_string()), &in_group).is_none());
+    }
+
+    #[test]
+    fn only_the_sender_may_use_param_fixes() {
+        let request = message(group(), Some(user(1)));
+        assert!(may_use_param_fix(&request, UserId(1)));
+        assert!(!may_use_param_fix(&request, UserId(2)));
+
+        // Anonymous admins and channels can't be told apart, so anyone can.
+        let anonymous = message(
+            group(),
+            Some(serde_json::json!({
+                "id": 1087968824, "is_bot": true, "first_name": "Group", "username": "GroupAnonymousBot"
+            })),
+        );
+        assert!(may_use_param_fix(&anonymous, UserId(2)));
+        let channel = message(
+            group(),
+            Some(serde_json::json!({
+                "id": 136817688, "is_bot": true, "first_name": "Channel", "username": "Channel_Bot"
+            })),
+        );
+        assert!(may_use_param_fix(&channel, UserId(2)));
+        assert!(may_use_param_fix(&message(group(), None), UserId(2)));
+    }
+}
@@ -1,5 +1,6 @@
 mod entry;
 mod handlers;
+mod strings;
 mod tasks;
 
 pub use entry::*;
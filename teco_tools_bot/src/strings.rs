@@ -0,0 +1,317 @@
+//! Translations of the things the bot says most often.
+//!
+//! Every [`Key`] has English text. Translations of it are optional: if there's none,
+//! the English text is used, so not everything has to be translated at once.
+
+use std::fmt::Display;
+
+/// A language the bot can speak.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Lang {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::Ru];
+
+    /// Pick a language for this IETF language tag, like the `language_code` of a Telegram user,
+    /// falling back to English.
+    pub fn from_code(code: Option<&str>) -> Lang {
+        code.and_then(|code| code.split(['-', '_']).next())
+            .and_then(Lang::parse)
+            .unwrap_or_default()
+    }
+
+    /// Parse a language by its code, like `ru`.
+    pub fn parse(code: &str) -> Option<Lang> {
+        Lang::ALL
+            .into_iter()
+            .find(|x| x.code().eq_ignore_ascii_case(code.trim()))
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Ru => "ru",
+        }
+    }
+
+    /// Name of the language in itself.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lang::En => "English",
+            Lang::Ru => "Русский",
+        }
+    }
+}
+
+/// Something the bot says. Texts with `{}` in them are meant to be filled in with [`fill`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    QueueWorking,
+    /// Position in queue.
+    QueuePosition,
+    QueueSlowMode,
+    SupportLink,
+    RerunDueToEdit,
+    TooManyTasks,
+    TooManyTasksAnonymous,
+    TaskInProgress,
+    EditFailedToParse,
+    EditKeepsParams,
+    TaskFailed,
+
+    ErrorPrefix,
+    CancelPrefix,
+    ParamsHeader,
+    ExamplesHeader,
+    /// Command name.
+    FullHelpHint,
+    NoParameters,
+    /// Size limit in megabytes.
+    MediaTooLarge,
+    CommandUnavailable,
+
+    /// What is too big, its size and the size limit, both in megabytes.
+    ResultTooBig,
+    ResultMedia,
+    ResultAlbum,
+    ProcessingTimedOut,
+    DownloadUnavailable,
+    DownloadNetworkError,
+    StatusWaitingForResources,
+    StatusDownloading,
+    StatusUploading,
+
+    /// Name of the current language.
+    LangCurrent,
+    /// Name of the new language.
+    LangSet,
+    LangAuto,
+    /// What the user asked for.
+    LangUnknown,
+    LangAnonymous,
+}
+
+impl Key {
+    /// Text of this in this language, or in English if it's not translated.
+    pub fn get(self, lang: Lang) -> &'static str {
+        let translation = match lang {
+            Lang::En => None,
+            Lang::Ru => self.russian(),
+        };
+        translation.unwrap_or_else(|| self.english())
+    }
+
+    pub fn english(self) -> &'static str {
+        use Key::*;
+        match self {
+            QueueWorking => "Working on your task now...",
+            QueuePosition => "Task accepted. Position in queue: {}",
+            QueueSlowMode => "Task accepted. Waiting for this chat's slow mode...",
+            SupportLink => "(Consider supporting? 👉👈)",
+            RerunDueToEdit => "(re-run due to edit)",
+            TooManyTasks => concat!(
+                "Sorry, but you have too many tasks queued up at the moment. ",
+                "Please try again later."
+            ),
+            TooManyTasksAnonymous => concat!(
+                "Sorry, but anonymous users have too many tasks queued up at the moment. ",
+                "Please try again later."
+            ),
+            TaskInProgress => concat!(
+                "Sorry, but the task is currently being run. ",
+                "Canceling or editing parameters is not possible at the moment."
+            ),
+            EditFailedToParse => "Failed to parse the command as a task.",
+            EditKeepsParams => concat!(
+                "\n\nWill use the previous parameters for the task.\n",
+                "If you wish to cancel the task, edit your message to say <code>/cancel</code>.\n",
+                "(Telegram bots can't see message deletion events, by the way)",
+            ),
+            TaskFailed => concat!(
+                "An error has occurred while processing this task. ",
+                "The bot's owner will be notified to fix this."
+            ),
+
+            ErrorPrefix => "Error: ",
+            CancelPrefix => "Cancelling task: ",
+            ParamsHeader => "<b>Possible parameters for this command:</b>",
+            ExamplesHeader => "<b>Examples:</b>",
+            FullHelpHint => {
+                "Send <code>{} help</code> for a full list of parameters for this command."
+            }
+            NoParameters => "This command has no parameters.",
+            MediaTooLarge => "media is too large. The limit is {}MB.",
+            CommandUnavailable => "this command is temporarily unavailable on this instance.",
+
+            ResultTooBig => "Error: the resulting {} is too big ({}MB, max is {}MB). Sorry!",
+            ResultMedia => "media",
+            ResultAlbum => "album",
+            ProcessingTimedOut => "Error: processing timed out. Sorry!",
+            DownloadUnavailable => concat!(
+                "Error: the media file is unavailable for the bot. ",
+                "This is likely a Telegram server issue. ",
+                "Try reuploading and performing the command again."
+            ),
+            DownloadNetworkError => concat!(
+                "Error: a networking error while downloading the file. ",
+                "This is likely a Telegram server issue. ",
+                "Try waiting some time, or reuploading the media, ",
+                "and performing the command again."
+            ),
+            StatusWaitingForResources => "Waiting for resources...",
+            StatusDownloading => "Downloading media...",
+            StatusUploading => "Uploading result...",
+
+            LangCurrent => concat!(
+                "The bot speaks {} to you.\n\n",
+                "Send <code>/lang en</code> or <code>/lang ru</code> to change that, ",
+                "or <code>/lang auto</code> to use the language of your Telegram app."
+            ),
+            LangSet => "The bot will now speak {} to you.",
+            LangAuto => "The bot will now use the language of your Telegram app.",
+            LangUnknown => "I don't know a language named <code>{}</code>.",
+            LangAnonymous => "languages of anonymous users can't be remembered.",
+        }
+    }
+
+    fn russian(self) -> Option<&'static str> {
+        use Key::*;
+        Some(match self {
+            QueueWorking => "Выполняю вашу задачу...",
+            QueuePosition => "Задача принята. Место в очереди: {}",
+            QueueSlowMode => "Задача принята. Жду окончания медленного режима в этом чате...",
+            SupportLink => "(Поддержите автора? 👉👈)",
+            RerunDueToEdit => "(перезапуск из-за изменения сообщения)",
+            TooManyTasks => concat!(
+                "Извините, но у вас сейчас слишком много задач в очереди. ",
+                "Попробуйте позже."
+            ),
+            TooManyTasksAnonymous => concat!(
+                "Извините, но у анонимных пользователей сейчас слишком много задач в очереди. ",
+                "Попробуйте позже."
+            ),
+            TaskInProgress => concat!(
+                "Извините, но задача уже выполняется. ",
+                "Отменить её или изменить параметры сейчас нельзя."
+            ),
+            EditFailedToParse => "Не удалось разобрать команду как задачу.",
+            EditKeepsParams => concat!(
+                "\n\nЗадача будет выполнена с прежними параметрами.\n",
+                "Чтобы отменить её, измените сообщение на <code>/cancel</code>.\n",
+                "(Кстати, боты в Telegram не видят удаление сообщений)",
+            ),
+            TaskFailed => concat!(
+                "При выполнении задачи произошла ошибка. ",
+                "Владелец бота получит уведомление, чтобы её исправить."
+            ),
+
+            ErrorPrefix => "Ошибка: ",
+            CancelPrefix => "Задача отменена: ",
+            ParamsHeader => "<b>Возможные параметры этой команды:</b>",
+            ExamplesHeader => "<b>Примеры:</b>",
+            FullHelpHint => {
+                "Отправьте <code>{} help</code>, чтобы увидеть все параметры этой команды."
+            }
+            NoParameters => "У этой команды нет параметров.",
+            MediaTooLarge => "файл слишком большой. Максимум — {}МБ.",
+            CommandUnavailable => "эта команда временно недоступна на этом сервере.",
+
+            ResultTooBig => "Ошибка: {} слишком большой ({}МБ, максимум — {}МБ). Извините!",
+            ResultMedia => "результат",
+            ResultAlbum => "получившийся альбом",
+            ProcessingTimedOut => "Ошибка: обработка заняла слишком много времени. Извините!",
+            DownloadUnavailable => concat!(
+                "Ошибка: файл недоступен для бота. ",
+                "Скорее всего, это проблема на серверах Telegram. ",
+                "Попробуйте загрузить его заново и повторить команду."
+            ),
+            DownloadNetworkError => concat!(
+                "Ошибка: сбой сети при скачивании файла. ",
+                "Скорее всего, это проблема на серверах Telegram. ",
+                "Попробуйте подождать или загрузить файл заново, ",
+                "а затем повторить команду."
+            ),
+            StatusWaitingForResources => "Жду освобождения ресурсов...",
+            StatusDownloading => "Скачиваю файл...",
+            StatusUploading => "Отправляю результат...",
+
+            LangCurrent => concat!(
+                "Бот говорит с вами на языке: {}.\n\n",
+                "Отправьте <code>/lang en</code> или <code>/lang ru</code>, чтобы это изменить, ",
+                "или <code>/lang auto</code>, чтобы использовать язык вашего приложения Telegram."
+            ),
+            LangSet => "Теперь бот будет говорить с вами на языке: {}.",
+            LangAuto => "Теперь бот будет использовать язык вашего приложения Telegram.",
+            LangUnknown => "Я не знаю языка <code>{}</code>.",
+            LangAnonymous => "язык анонимных пользователей нельзя запомнить.",
+        })
+    }
+}
+
+/// Fill in each `{}` in this text with the next of `args`.
+pub fn fill(text: &str, args: &[&dyn Display]) -> String {
+    use std::fmt::Write;
+
+    let mut output = String::with_capacity(text.len());
+    let mut args = args.iter();
+    let mut parts = text.split("{}");
+    output.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            let _ = write!(output, "{}", arg);
+        }
+        output.push_str(part);
+    }
+    output
+}
+
+/// Translate the headers of a parameter help text, like [`crate::tasks::Task::param_help`].
+/// The rest of it is left as is, as those are long and mostly parameter names anyway.
+pub fn localize_help(help: &str, lang: Lang) -> String {
+    [Key::ParamsHeader, Key::ExamplesHeader]
+        .into_iter()
+        .fold(help.to_string(), |help, key| {
+            help.replace(key.english(), key.get(lang))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_codes() {
+        assert_eq!(Lang::from_code(Some("ru")), Lang::Ru);
+        assert_eq!(Lang::from_code(Some("ru-RU")), Lang::Ru);
+        assert_eq!(Lang::from_code(Some("en-GB")), Lang::En);
+        assert_eq!(Lang::from_code(Some("de")), Lang::En);
+        assert_eq!(Lang::from_code(None), Lang::En);
+
+        assert_eq!(Lang::parse(" RU "), Some(Lang::Ru));
+        assert_eq!(Lang::parse("auto"), None);
+    }
+
+    #[test]
+    fn filling() {
+        assert_eq!(
+            fill(Key::QueuePosition.english(), &[&3]),
+            "Task accepted. Position in queue: 3"
+        );
+        assert_eq!(fill("{} and {}", &[&"a"]), "a and ");
+        assert_eq!(fill("no args", &[&1]), "no args");
+    }
+
+    #[test]
+    fn help_headers() {
+        let help = "<b>Possible parameters for this command:</b>\nstuff\n\n<b>Examples:</b>\n";
+        assert_eq!(localize_help(help, Lang::En), help);
+        assert_eq!(
+            localize_help(help, Lang::Ru),
+            "<b>Возможные параметры этой команды:</b>\nstuff\n\n<b>Примеры:</b>\n"
+        );
+    }
+}
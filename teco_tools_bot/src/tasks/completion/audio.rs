@@ -0,0 +1,156 @@
+//! Tasks that mess with the sound of audio and videos.
+
+use std::time::Instant;
+
+use crate::tasks::{completion::media_processing, PitchMode};
+
+use super::context::{CompletionResult, DownloadedMedia, OutputKind, TaskContext};
+
+/// Complete a [`Task::DistortAudio`](crate::tasks::Task::DistortAudio).
+pub async fn distort(
+    ctx: &TaskContext<'_>,
+    vibrato_hz: f64,
+    vibrato_depth: f64,
+    speed: f64,
+) -> CompletionResult {
+    let media = ctx.media("Error: can't find the audio.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    // Voice messages should come back as voice messages.
+    let as_voice = media.is_voice_or_video_note;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::distort_audio(
+                    status_report_for_processing,
+                    &path,
+                    vibrato_hz,
+                    vibrato_depth,
+                    speed,
+                    PitchMode::Preserve,
+                    as_voice,
+                    deadline,
+                )
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let audio_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when distorting audio: {}", e);
+            goodbye!("Error: failed to distort the audio.");
+        }
+    };
+
+    let kind = if as_voice {
+        OutputKind::Voice
+    } else {
+        OutputKind::Audio
+    };
+
+    ctx.upload(
+        &media,
+        kind,
+        &audio_data,
+        "Error: failed to distort the audio; got empty file as a result. Sorry!",
+    )
+    .await
+}
+
+/// Complete a [`Task::Boost`](crate::tasks::Task::Boost).
+pub async fn boost(ctx: &TaskContext<'_>, gain_db: Option<f64>) -> CompletionResult {
+    let media = ctx.media("Error: can't find the video or audio.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    let is_sound = media.is_sound;
+    // Voice messages should come back as voice messages.
+    let as_voice = media.is_sound && media.is_voice_or_video_note;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    let deadline = Instant::now() + ctx.task_timeout;
+
+    // Telegram doesn't tell if a video has audio, so check it ourselves.
+    if !is_sound {
+        ctx.status("Checking for audio...");
+
+        let path_for_counting = path.clone();
+        let counted = ctx
+            .await_processing(
+                deadline,
+                resources.spawn_blocking(move || {
+                    media_processing::count_video_frames_and_framerate_and_audio_and_length(
+                        &path_for_counting,
+                        is_sound,
+                        deadline,
+                    )
+                }),
+            )
+            .await?;
+
+        match counted {
+            Ok((_, _, true, _)) => (),
+            Ok((_, _, false, _)) => {
+                goodbye!("Error: this video has no audio to boost.");
+            }
+            Err(e) => {
+                log::error!("Failed to check media for audio: {}", e);
+                goodbye!("Error: failed to read the media.");
+            }
+        }
+    }
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::boost_audio(
+                    status_report_for_processing,
+                    &path,
+                    gain_db,
+                    is_sound,
+                    as_voice,
+                    deadline,
+                )
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let media_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when boosting audio: {}", e);
+            goodbye!("Error: failed to boost the audio.");
+        }
+    };
+
+    let kind = if as_voice {
+        OutputKind::Voice
+    } else if is_sound {
+        OutputKind::Audio
+    } else {
+        OutputKind::Video
+    };
+
+    ctx.upload(
+        &media,
+        kind,
+        &media_data,
+        "Error: failed to boost the audio; got empty file as a result. Sorry!",
+    )
+    .await
+}
@@ -0,0 +1,403 @@
+//! Everything a task needs to be completed, and helpers for the steps most tasks share:
+//! finding the media, downloading it, waiting for its processing and sending the result.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use arch_bot_commons::{teloxide_retry, useful_methods::*};
+use teloxide::{
+    payloads::{
+        SendAnimationSetters, SendAudioSetters, SendDocumentSetters, SendPhotoSetters,
+        SendStickerSetters, SendVideoSetters, SendVoiceSetters,
+    },
+    requests::Requester,
+    types::{FileMeta, InputFile, Message},
+    ApiError, Bot, RequestError,
+};
+use tokio::{sync::watch::Sender, task::JoinHandle};
+
+use crate::{
+    strings::{fill, Key, Lang},
+    tasks::{
+        taskman::{
+            database::{Database, TaskDatabaseInfo},
+            download_cache::{DownloadCache, DownloadLease},
+            governor::{MemoryReservation, ResourceGovernor},
+        },
+        Task,
+    },
+    MAX_UPLOAD_SIZE_MEGABYTES,
+};
+
+use super::stats_footer;
+
+/// Why a task stopped before it was completed.
+#[derive(Debug)]
+pub enum CompletionError {
+    /// The user should be told this message about why.
+    UserFacing(String),
+    /// Talking to Telegram failed.
+    Request(RequestError),
+}
+
+impl From<RequestError> for CompletionError {
+    fn from(value: RequestError) -> Self {
+        CompletionError::Request(value)
+    }
+}
+
+pub type CompletionResult<T = ()> = Result<T, CompletionError>;
+
+/// Media downloaded into a file to work on.
+pub struct DownloadedMedia<'a> {
+    pub path: PathBuf,
+    /// Keeps the file around until this is dropped.
+    pub lease: DownloadLease<'a>,
+}
+
+/// How a result is sent to the chat.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutputKind {
+    Photo,
+    /// Sent without a caption, as stickers can't have one.
+    Sticker,
+    Video,
+    /// An MP4 video to be shown as a GIF.
+    Animation,
+    Voice,
+    /// An MP3 file.
+    Audio,
+    /// A plain file with this name.
+    Document(String),
+}
+
+pub struct TaskContext<'a> {
+    pub task: &'a Task,
+    pub bot: &'a Bot,
+    pub status_report: Sender<String>,
+    pub governor: &'a ResourceGovernor,
+    pub download_cache: &'a DownloadCache,
+    pub task_timeout: Duration,
+    pub data: &'a TaskDatabaseInfo,
+    pub db: &'a Database,
+    /// Language to talk to the user in.
+    pub lang: Lang,
+}
+
+impl<'a> TaskContext<'a> {
+    /// Show this as the status of the task.
+    pub fn status(&self, text: &str) {
+        let _ = self.status_report.send(text.to_string());
+    }
+
+    /// Reply to the message of the task with this text.
+    pub async fn respond(&self, text: &str) -> Result<(), RequestError> {
+        self.bot
+            .archsendmsg(self.data.message.chat.id, text, self.data.message.id)
+            .await?;
+        Ok(())
+    }
+
+    /// Find the media of the task and check that the task can work with it.
+    /// If there is none, the user is told `not_found`.
+    pub fn media(&self, not_found: &str) -> CompletionResult<MessageMediaInfo<'a>> {
+        let Some(media) = self.data.message.get_media_info() else {
+            goodbye!(not_found);
+        };
+        if let Err(e) = self.task.accepts(&media) {
+            goodbye!(e);
+        }
+        Ok(media)
+    }
+
+    /// Wait until there's enough memory to work on this media.
+    /// Hold on to the returned reservation until the end of the task,
+    /// and spawn processing with it.
+    pub async fn reserve_memory(&self, media: &MessageMediaInfo<'_>) -> MemoryReservation {
+        self.governor
+            .acquire(self.task.memory_estimate(media), || {
+                self.status(Key::StatusWaitingForResources.get(self.lang))
+            })
+            .await
+    }
+
+    /// Download the media into a file, or find it on disk if the bot API server is local.
+    pub async fn download_media(
+        &self,
+        media: &MessageMediaInfo<'_>,
+    ) -> CompletionResult<DownloadedMedia<'a>> {
+        self.download_file(media.file).await
+    }
+
+    /// Same as [`Self::download_media`], but for any file, like one of several to work on.
+    pub async fn download_file(&self, file: &FileMeta) -> CompletionResult<DownloadedMedia<'a>> {
+        self.status(Key::StatusDownloading.get(self.lang));
+        let (path, lease) = unerror_download(
+            self.download_cache
+                .download_file_to_temp_or_directly(self.bot, file)
+                .await,
+            self.lang,
+        )?;
+        Ok(DownloadedMedia { path, lease })
+    }
+
+    /// Download the media into memory.
+    pub async fn download_media_to_vec(
+        &self,
+        media: &MessageMediaInfo<'_>,
+        to: &mut Vec<u8>,
+    ) -> CompletionResult {
+        self.status(Key::StatusDownloading.get(self.lang));
+        unerror_download(
+            self.download_cache
+                .download_file_to_vec(self.bot, media.file, to)
+                .await,
+            self.lang,
+        )
+    }
+
+    /// Wait for blocking media processing to finish, giving up on it once the deadline passes.
+    /// The blocking code can't be cancelled, so functions that spawn ffmpeg are also
+    /// given the deadline to kill it themselves, and it should be spawned with
+    /// [`MemoryReservation::spawn_blocking`] to keep its memory until it's done.
+    pub async fn await_processing<T>(
+        &self,
+        deadline: Instant,
+        handle: JoinHandle<T>,
+    ) -> CompletionResult<T> {
+        match tokio::time::timeout_at(deadline.into(), handle).await {
+            Ok(result) => Ok(result.expect("Worker died!")),
+            Err(_) => {
+                log::warn!("Task timed out: {:?}", self.task);
+                goodbye!(Key::ProcessingTimedOut.get(self.lang));
+            }
+        }
+    }
+
+    /// Make the line with sizes and time taken to put under a result made from this media.
+    pub fn stats(&self, media: &MessageMediaInfo, result_size: usize) -> String {
+        stats_footer(media.file.size, result_size, self.data.grabbed_at)
+    }
+
+    /// Send this result as a reply to the message of the task,
+    /// retrying on network errors and rate limits.
+    ///
+    /// The sent message is remembered as the result of the task,
+    /// so that commands used on it later can start off of this task's parameters.
+    pub async fn send_result(
+        &self,
+        kind: &OutputKind,
+        result: &[u8],
+        caption: &str,
+    ) -> Result<Message, RequestError> {
+        let chat_id = self.data.message.chat.id;
+        let reply_to = self.data.message.id;
+
+        let message = teloxide_retry!({
+            let send = InputFile::memory(result.to_vec());
+            match kind {
+                OutputKind::Photo => {
+                    self.bot
+                        .send_photo(chat_id, send)
+                        .caption(caption)
+                        .reply_to_message_id(reply_to)
+                        .await
+                }
+                OutputKind::Sticker => {
+                    self.bot
+                        .send_sticker(chat_id, send)
+                        .reply_to_message_id(reply_to.0)
+                        .await
+                }
+                OutputKind::Video => {
+                    self.bot
+                        .send_video(chat_id, send)
+                        .caption(caption)
+                        .reply_to_message_id(reply_to)
+                        .await
+                }
+                OutputKind::Animation => {
+                    // Sending as an "animation" requires that the file has a filename, else
+                    // it somehow ends up being a file document instead.
+                    self.bot
+                        .send_animation(chat_id, send.file_name("amogus.mp4"))
+                        .caption(caption)
+                        .reply_to_message_id(reply_to)
+                        .await
+                }
+                OutputKind::Voice => {
+                    self.bot
+                        .send_voice(chat_id, send)
+                        .caption(caption)
+                        .reply_to_message_id(reply_to)
+                        .await
+                }
+                OutputKind::Audio => {
+                    self.bot
+                        .send_audio(chat_id, send.file_name("amogus.mp3"))
+                        .caption(caption)
+                        .reply_to_message_id(reply_to)
+                        .await
+                }
+                OutputKind::Document(name) => {
+                    self.bot
+                        .send_document(chat_id, send.file_name(name.clone()))
+                        .caption(caption)
+                        .reply_to_message_id(reply_to)
+                        .await
+                }
+            }
+        })?;
+
+        self.db
+            .set_task_result_message(self.data.taskid, &message)
+            .await
+            .expect("Database died!");
+        Ok(message)
+    }
+
+    /// Check that the result of processing the media is fine to send, then send it
+    /// with the stats under it.
+    ///
+    /// If it's empty, the user is told `empty_error`.
+    pub async fn upload(
+        &self,
+        media: &MessageMediaInfo<'_>,
+        kind: OutputKind,
+        result: &[u8],
+        empty_error: &str,
+    ) -> CompletionResult {
+        check_result(result, empty_error, self.lang)?;
+
+        let stats = self.stats(media, result.len());
+
+        self.status(Key::StatusUploading.get(self.lang));
+
+        self.send_result(&kind, result, &stats).await?;
+        Ok(())
+    }
+}
+
+/// Check that the result of processing is not empty, telling the user `empty_error` if it is,
+/// and that it's not too big to upload.
+pub fn check_result(result: &[u8], empty_error: &str, lang: Lang) -> CompletionResult {
+    if result.is_empty() {
+        goodbye!(empty_error);
+    }
+
+    if result.len() > MAX_UPLOAD_SIZE_MEGABYTES as usize * 1000 * 1000 {
+        goodbye!(too_big_message(Key::ResultMedia, result.len(), lang));
+    }
+
+    Ok(())
+}
+
+/// Make a message to tell the user that the resulting `what` of this size can't be uploaded.
+pub fn too_big_message(what: Key, size: usize, lang: Lang) -> String {
+    fill(
+        Key::ResultTooBig.get(lang),
+        &[
+            &what.get(lang),
+            &format!("{:.3}", size as f64 / 1000.0 / 1000.0),
+            &MAX_UPLOAD_SIZE_MEGABYTES,
+        ],
+    )
+}
+
+/// Photos and stickers have stricter limits than plain files. Check if this is
+/// Telegram refusing to take a result as one of those, in which case it may take it as a file.
+pub fn refused_as_photo<T>(result: &Result<T, RequestError>) -> bool {
+    match result {
+        Err(RequestError::Api(ApiError::RequestEntityTooLarge)) => true,
+        Err(RequestError::Api(ApiError::Unknown(e))) => e.contains("PHOTO_INVALID_DIMENSIONS"),
+        _ => false,
+    }
+}
+
+/// Turn errors that happen when downloading because of Telegram into messages for the user.
+fn unerror_download<T>(result: Result<T, RequestError>, lang: Lang) -> CompletionResult<T> {
+    if let Err(RequestError::Api(ApiError::Unknown(text))) = &result {
+        if text.contains("file is temporarily unavailable") {
+            goodbye!(Key::DownloadUnavailable.get(lang));
+        }
+    };
+    if let Err(RequestError::Network(_)) = &result {
+        goodbye!(Key::DownloadNetworkError.get(lang));
+    };
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_facing(result: CompletionResult) -> String {
+        match result {
+            Err(CompletionError::UserFacing(text)) => text,
+            other => panic!("Expected a message for the user, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn result_size_messages() {
+        assert!(check_result(&[1, 2, 3], "empty", Lang::En).is_ok());
+        assert_eq!(
+            user_facing(check_result(&[], "Error: empty!", Lang::En)),
+            "Error: empty!"
+        );
+
+        let too_big = vec![0; MAX_UPLOAD_SIZE_MEGABYTES as usize * 1000 * 1000 + 1];
+        assert_eq!(
+            user_facing(check_result(&too_big, "empty", Lang::En)),
+            format!(
+                "Error: the resulting media is too big ({}.000MB, max is {}MB). Sorry!",
+                MAX_UPLOAD_SIZE_MEGABYTES, MAX_UPLOAD_SIZE_MEGABYTES
+            )
+        );
+
+        assert_eq!(
+            too_big_message(Key::ResultAlbum, 12_345_678, Lang::En),
+            format!(
+                "Error: the resulting album is too big (12.346MB, max is {}MB). Sorry!",
+                MAX_UPLOAD_SIZE_MEGABYTES
+            )
+        );
+        assert_eq!(
+            too_big_message(Key::ResultAlbum, 12_345_678, Lang::Ru),
+            format!(
+                "Ошибка: получившийся альбом слишком большой (12.346МБ, максимум — {}МБ). Извините!",
+                MAX_UPLOAD_SIZE_MEGABYTES
+            )
+        );
+    }
+
+    #[test]
+    fn download_error_messages() {
+        let unavailable: Result<(), _> = Err(RequestError::Api(ApiError::Unknown(
+            "Bad Request: file is temporarily unavailable".to_string(),
+        )));
+        assert!(user_facing(unerror_download(unavailable, Lang::En))
+            .starts_with("Error: the media file is unavailable for the bot."));
+
+        let other: Result<(), _> = Err(RequestError::Api(ApiError::BotBlocked));
+        assert!(matches!(
+            unerror_download(other, Lang::En),
+            Err(CompletionError::Request(_))
+        ));
+
+        assert!(matches!(unerror_download(Ok(5), Lang::Ru), Ok(5)));
+    }
+
+    #[test]
+    fn photo_refusals() {
+        let too_large: Result<(), _> = Err(RequestError::Api(ApiError::RequestEntityTooLarge));
+        assert!(refused_as_photo(&too_large));
+        let dimensions: Result<(), _> = Err(RequestError::Api(ApiError::Unknown(
+            "Bad Request: PHOTO_INVALID_DIMENSIONS".to_string(),
+        )));
+        assert!(refused_as_photo(&dimensions));
+        assert!(!refused_as_photo(&Ok(())));
+    }
+}
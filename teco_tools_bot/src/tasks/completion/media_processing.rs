@@ -4,19 +4,110 @@ use std::{
     ffi::OsStr,
     io::{Read, Write},
     num::NonZeroU8,
-    path::Path,
-    process::{ChildStdout, Command, Stdio},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, ExitStatus, Stdio},
     sync::OnceLock,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use tokio::sync::watch::Sender;
 
-use magick_rust::{AlphaChannelOption, FilterType, MagickError, MagickWand, PixelWand};
+use magick_rust::{
+    AlphaChannelOption, ChannelType, ColorspaceType, CompositeOperator, DitherMethod, DrawingWand,
+    FilterType, MagickError, MagickWand, PixelWand, ResourceType,
+};
 use regex::Regex;
 use tempfile::NamedTempFile;
 
-use crate::tasks::{ImageFormat, ResizeCurve, ResizeType};
+use crate::tasks::{
+    format_timestamp, parsing::MAX_OUTPUT_MEDIA_DIMENSION_SIZE, ChromaSubsampling,
+    ColorAdjustments, ImageFormat, PitchMode, ResizeCurve, ResizeType, VideoContainer,
+};
+
+/// Error message of media processing functions that ran past their deadline.
+pub const TIMED_OUT_ERROR: &str = "processing timed out";
+/// Error message of [`resize_video`] if it's asked to trim from after the end of the video.
+pub const TRIM_OUT_OF_RANGE_ERROR: &str = "trim starts after the end of the video";
+/// Error returned by [`resize_video`] if looping the video would make too much to process.
+pub const LOOPED_VIDEO_TOO_LONG_ERROR: &str = "looped video is too long";
+/// How many pixels, over all frames, [`resize_video`] is willing to make when looping a video.
+/// About a minute of 1080p at 30 FPS.
+const MAX_LOOPED_VIDEO_PIXELS: u64 = 1920 * 1080 * 30 * 60;
+/// Start of the error returned by image processing functions if the input image
+/// is bigger than [`MAX_INPUT_IMAGE_PIXELS`]. Followed by its dimensions, like `: 30000x30000`.
+pub const INPUT_IMAGE_TOO_BIG_ERROR: &str = "input image is too big";
+/// How many pixels an input image may have. A tiny file can claim to be huge,
+/// and ImageMagick would happily try to allocate all of that.
+pub const MAX_INPUT_IMAGE_PIXELS: u64 = 50_000_000;
+
+/// Environment variable to set how much memory ImageMagick may use with, in megabytes.
+const MAGICK_MEMORY_LIMIT_ENV_VAR: &str = "TECO_MAGICK_MEMORY_LIMIT_MB";
+/// How much memory ImageMagick may use if not set, in megabytes.
+const DEFAULT_MAGICK_MEMORY_LIMIT_MB: u64 = 1024;
+
+/// ImageMagick resource limits for this much memory, in megabytes.
+///
+/// Memory mapped files may take twice as much, and the disk four times as much,
+/// as ImageMagick falls back to those when out of memory. Images bigger than
+/// [`MAX_INPUT_IMAGE_PIXELS`] are kept on disk rather than in memory.
+fn magick_resource_limits(memory_mb: u64) -> [(ResourceType, u64); 4] {
+    // Absurdly big limits are as good as no limits, so don't overflow on them.
+    let memory = memory_mb.max(1).saturating_mul(1024 * 1024);
+    [
+        (ResourceType::Memory, memory),
+        (ResourceType::Map, memory.saturating_mul(2)),
+        (ResourceType::Disk, memory.saturating_mul(4)),
+        (ResourceType::Area, MAX_INPUT_IMAGE_PIXELS),
+    ]
+}
+
+/// Limit how much ImageMagick may use, so that a single image can't take the whole
+/// bot down. Past these it errors out instead. The memory limit is taken from the
+/// `TECO_MAGICK_MEMORY_LIMIT_MB` environment variable, or is 1024 megabytes if it's not set.
+///
+/// Should be called once, at startup.
+pub fn init_magick_resource_limits() {
+    let memory_mb = match std::env::var(MAGICK_MEMORY_LIMIT_ENV_VAR) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!(
+                "Bad value for {}: {:?}, using {}MB instead.",
+                MAGICK_MEMORY_LIMIT_ENV_VAR,
+                value,
+                DEFAULT_MAGICK_MEMORY_LIMIT_MB
+            );
+            DEFAULT_MAGICK_MEMORY_LIMIT_MB
+        }),
+        Err(_) => DEFAULT_MAGICK_MEMORY_LIMIT_MB,
+    };
+
+    log::info!("ImageMagick memory limit is {}MB.", memory_mb);
+    for (resource, limit) in magick_resource_limits(memory_mb) {
+        if let Err(e) = MagickWand::set_resource_limit(resource, limit) {
+            log::error!("Failed to set ImageMagick {:?} limit: {}", resource, e);
+        }
+    }
+}
+
+fn timed_out() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, TIMED_OUT_ERROR)
+}
+
+/// Wait for a child process to exit, killing it if `deadline` passes first.
+fn wait_until(child: &mut Child, deadline: Instant) -> std::io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
 
 /// Will error if [`ImageFormat::Preserve`] is sent.
 #[allow(clippy::too_many_arguments)]
@@ -32,7 +123,203 @@ pub fn resize_image(
     output_size: Option<(usize, usize, bool)>,
     crop_rotation: bool,
     quality: NonZeroU8,
+    color: ColorAdjustments,
+    chroma: ChromaSubsampling,
+    keep_metadata: bool,
+) -> Result<Vec<u8>, MagickError> {
+    match resize_image_inner(
+        data,
+        width,
+        height,
+        rotation,
+        resize_type,
+        format,
+        output_size,
+        crop_rotation,
+        quality,
+        color,
+        chroma,
+        keep_metadata,
+    )? {
+        ResizedImage::Encoded(blob) => Ok(blob),
+        ResizedImage::Wand(wand) => {
+            // The wand may have been remade since, so set this again.
+            apply_chroma_subsampling(&wand, format, chroma)?;
+            wand.write_image_blob(format.as_str())
+        }
+    }
+}
+
+/// Sets JPEG chroma subsampling on the wand, if asked for and if the format is JPEG.
+fn apply_chroma_subsampling(
+    wand: &MagickWand,
+    format: ImageFormat,
+    chroma: ChromaSubsampling,
+) -> Result<(), MagickError> {
+    if format != ImageFormat::Jpeg {
+        return Ok(());
+    }
+    match chroma.sampling_factors() {
+        Some(factors) => wand.set_sampling_factors(factors),
+        None => Ok(()),
+    }
+}
+
+/// Size of a single custom emoji, in pixels.
+const CUSTOM_EMOJI_SIZE: usize = 100;
+
+/// Crops the image to fill a grid of `columns` by `rows` custom emoji, and slices it
+/// into WEBP tiles, going left to right, then top to bottom.
+///
+/// Errors if any single tile fails, so that a partial grid is never produced.
+pub fn resize_image_into_custom_emoji_grid(
+    data: &[u8],
+    columns: u8,
+    rows: u8,
+    quality: NonZeroU8,
+    color: ColorAdjustments,
+) -> Result<Vec<Vec<u8>>, MagickError> {
+    let resize_type = ResizeType::ToCustomEmojiGrid { columns, rows };
+    let (columns, rows) = (columns as usize, rows as usize);
+
+    // Lossless here, the tiles are compressed on their own below.
+    let grid = resize_image(
+        data,
+        (CUSTOM_EMOJI_SIZE * columns) as isize,
+        (CUSTOM_EMOJI_SIZE * rows) as isize,
+        0.0,
+        resize_type,
+        ImageFormat::Png,
+        None,
+        false,
+        NonZeroU8::new(100).unwrap(),
+        color,
+        ChromaSubsampling::Auto,
+        false,
+    )?;
+
+    let mut tiles = Vec::with_capacity(columns * rows);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let tile = (|| {
+                let mut wand = MagickWand::new();
+                wand.read_image_blob(&grid)?;
+                wand.crop_image(
+                    CUSTOM_EMOJI_SIZE,
+                    CUSTOM_EMOJI_SIZE,
+                    (column * CUSTOM_EMOJI_SIZE) as isize,
+                    (row * CUSTOM_EMOJI_SIZE) as isize,
+                )?;
+                wand.reset_image_page("")?;
+                wand.set_image_compression_quality(quality.get() as usize)?;
+                wand.write_image_blob(ImageFormat::Webp.as_str())
+            })()
+            .map_err(|e| {
+                MagickError(format!(
+                    "failed to make tile at column {}, row {}: {}",
+                    column + 1,
+                    row + 1,
+                    e
+                ))
+            })?;
+
+            tiles.push(tile);
+        }
+    }
+
+    Ok(tiles)
+}
+
+/// Same as [`resize_image`], but outputs raw BGR24 pixels instead of an encoded image.
+///
+/// Used to feed video frames to ffmpeg as rawvideo, skipping the encoding and decoding
+/// of a BMP per frame. Will error if the result isn't exactly `expected_size`.
+#[allow(clippy::too_many_arguments)]
+fn resize_image_to_raw_bgr(
+    data: &[u8],
+    width: isize,
+    height: isize,
+    rotation: f64,
+    resize_type: ResizeType,
+    format: ImageFormat,
+    output_size: Option<(usize, usize, bool)>,
+    crop_rotation: bool,
+    quality: NonZeroU8,
+    color: ColorAdjustments,
+    expected_size: (usize, usize),
+) -> Result<Vec<u8>, MagickError> {
+    let wand = match resize_image_inner(
+        data,
+        width,
+        height,
+        rotation,
+        resize_type,
+        format,
+        output_size,
+        crop_rotation,
+        quality,
+        color,
+        ChromaSubsampling::Auto,
+        true,
+    )? {
+        ResizedImage::Wand(wand) => wand,
+        ResizedImage::Encoded(blob) => {
+            let wand = MagickWand::new();
+            wand.read_image_blob(blob)?;
+            wand
+        }
+    };
+
+    wand_to_raw_bgr(&wand, expected_size)
+}
+
+/// Decodes an image and outputs its raw BGR24 pixels.
+/// Will error if the image isn't exactly `expected_size`.
+fn image_to_raw_bgr(data: &[u8], expected_size: (usize, usize)) -> Result<Vec<u8>, MagickError> {
+    let wand = MagickWand::new();
+    wand.read_image_blob(data)?;
+    wand_to_raw_bgr(&wand, expected_size)
+}
+
+fn wand_to_raw_bgr(
+    wand: &MagickWand,
+    expected_size: (usize, usize),
 ) -> Result<Vec<u8>, MagickError> {
+    let size = (wand.get_image_width(), wand.get_image_height());
+    if size != expected_size {
+        return Err(MagickError(format!(
+            "raw frame is {}x{}, but {}x{} was expected",
+            size.0, size.1, expected_size.0, expected_size.1
+        )));
+    }
+
+    wand.export_image_pixels(0, 0, size.0, size.1, "BGR")
+        .ok_or_else(|| MagickError("failed to export pixels".to_string()))
+}
+
+/// Result of [`resize_image_inner`]. Compressed formats have to be encoded
+/// to get the compression artifacts in, so those may come out already encoded.
+enum ResizedImage {
+    Encoded(Vec<u8>),
+    Wand(MagickWand),
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resize_image_inner(
+    data: &[u8],
+    width: isize,
+    height: isize,
+    rotation: f64,
+    resize_type: ResizeType,
+    format: ImageFormat,
+    output_size: Option<(usize, usize, bool)>,
+    crop_rotation: bool,
+    quality: NonZeroU8,
+    color: ColorAdjustments,
+    chroma: ChromaSubsampling,
+    keep_metadata: bool,
+) -> Result<ResizedImage, MagickError> {
     if format == ImageFormat::Preserve {
         // yeah this isn't a MagickError, but we'd get one in the last line
         // anyways, so might as well make a better description for ourselves lol
@@ -41,10 +328,18 @@ pub fn resize_image(
         ));
     }
 
+    check_input_image_size(data)?;
+
     let mut wand = MagickWand::new();
 
     wand.read_image_blob(data)?;
 
+    if !keep_metadata {
+        // The orientation is metadata too, so turn the image upright before it's gone.
+        wand.auto_orient();
+        wand.strip_image()?;
+    }
+
     let mut transparent = PixelWand::new();
     transparent.set_alpha(0.0);
     wand.set_image_background_color(&transparent)?;
@@ -117,7 +412,7 @@ pub fn resize_image(
         ResizeType::Fit | ResizeType::ToSticker => {
             wand.fit(width, height);
         }
-        ResizeType::Crop | ResizeType::ToCustomEmoji => {
+        ResizeType::Crop | ResizeType::ToCustomEmoji | ResizeType::ToCustomEmojiGrid { .. } => {
             // We want to scale the image so that it completely covers the area,
             // where at least one dimension is exactly as big,
             // and then crop the other dimension.
@@ -168,6 +463,16 @@ pub fn resize_image(
             )?;
             wand.reset_image_page("")?;
         }
+        ResizeType::Mirror { .. } | ResizeType::Kaleidoscope { .. } => {
+            // These keep the size, unless it had to be scaled down to fit the limits.
+            // Mirroring itself is done by the signs below.
+            if (iwidth, iheight) != (width, height) {
+                wand.resize_image(width, height, FilterType::Lagrange)?;
+            }
+            if let ResizeType::Kaleidoscope { segments } = resize_type {
+                kaleidoscope(&mut wand, segments)?;
+            }
+        }
     }
 
     // Flip it according to the signs.
@@ -219,6 +524,19 @@ pub fn resize_image(
         }
     }
 
+    if !color.is_noop() {
+        wand.modulate_image(color.brightness, color.saturation, 100.0)?;
+        if color.contrast != 100.0 {
+            // This one takes a value between -100 and 100, where 0 is no change.
+            wand.brightness_contrast_image(0.0, color.contrast - 100.0)?;
+        }
+        if color.grayscale {
+            // Back to sRGB after, so that encoders and raw frames see the same kind of image.
+            wand.transform_image_colorspace(ColorspaceType::GRAY)?;
+            wand.transform_image_colorspace(ColorspaceType::sRGB)?;
+        }
+    }
+
     // Quality tends to behave in an exponential manner.
     // Normalize this to make it perceptually linear.
     let quality = quality.get() - 1; // From 0 to 99.
@@ -230,6 +548,7 @@ pub fn resize_image(
 
     wand.set_image_compression_quality(quality)?;
     wand.set_compression_quality(quality)?;
+    apply_chroma_subsampling(&wand, format, chroma)?;
 
     // Do we need to apply output size?
 
@@ -241,7 +560,7 @@ pub fn resize_image(
         }
 
         let compressible = match format {
-            ImageFormat::Jpeg | ImageFormat::Webp => true,
+            ImageFormat::Jpeg | ImageFormat::Webp | ImageFormat::Avif => true,
             ImageFormat::Bmp | ImageFormat::Png | ImageFormat::Preserve => false,
         };
 
@@ -261,7 +580,7 @@ pub fn resize_image(
                 wand.read_image_blob(compressed_blob)?;
             } else {
                 // Otherwise, just return that.
-                return Ok(compressed_blob);
+                return Ok(ResizedImage::Encoded(compressed_blob));
             }
         } else {
             // If this format is not compressible but we want it to be,
@@ -295,7 +614,81 @@ pub fn resize_image(
         }
     }
 
-    wand.write_image_blob(format.as_str())
+    Ok(ResizedImage::Wand(wand))
+}
+
+/// Replaces the image with `segments` copies of a wedge of it around its center,
+/// every other one mirrored, like in a kaleidoscope.
+///
+/// The wedge starts to the right of the center and goes clockwise,
+/// so with 4 segments it's the bottom right quadrant.
+fn kaleidoscope(wand: &mut MagickWand, segments: u8) -> Result<(), MagickError> {
+    let width = wand.get_image_width();
+    let height = wand.get_image_height();
+    let (center_x, center_y) = (width / 2, height / 2);
+
+    match segments {
+        // Simple enough to do by mirroring halves and quadrants, which is much faster.
+        2 => {
+            let half = wand.clone();
+            half.crop_image(width, height - center_y, 0, center_y as isize)?;
+            half.reset_image_page("")?;
+            half.flip_image()?;
+            let top = center_y as isize - half.get_image_height() as isize;
+            wand.compose_images(&half, CompositeOperator::Copy, false, 0, top)?;
+        }
+        4 => {
+            let quadrant = wand.clone();
+            quadrant.crop_image(
+                width - center_x,
+                height - center_y,
+                center_x as isize,
+                center_y as isize,
+            )?;
+            quadrant.reset_image_page("")?;
+
+            let (center_x, center_y) = (center_x as isize, center_y as isize);
+            let left = center_x - quadrant.get_image_width() as isize;
+            let top = center_y - quadrant.get_image_height() as isize;
+
+            quadrant.flop_image()?;
+            wand.compose_images(&quadrant, CompositeOperator::Copy, false, left, center_y)?;
+            quadrant.flip_image()?;
+            wand.compose_images(&quadrant, CompositeOperator::Copy, false, left, top)?;
+            quadrant.flop_image()?;
+            wand.compose_images(&quadrant, CompositeOperator::Copy, false, center_x, top)?;
+        }
+        _ => {
+            // Fold the angle of every pixel around the center into the wedge.
+            // Pixel coordinates are of their centers, hence the "-1".
+            let expression = format!(
+                concat!(
+                    "cx=(w-1)/2; cy=(h-1)/2; dx=i-cx; dy=j-cy; ",
+                    "s=2*pi/{}; a=atan2(dy,dx); a=a-2*s*floor(a/(2*s)); a=a>s?2*s-a:a; ",
+                    "r=hypot(dx,dy); p{{cx+r*cos(a),cy+r*sin(a)}}"
+                ),
+                segments
+            );
+            *wand = wand.fx(&expression)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How many bytes [`SplitIntoBmps`] may skip when looking for the next BMP
+/// header after finding garbage where one should be.
+const BMP_RESYNC_WINDOW: usize = 64 * 1024;
+
+/// Size of the biggest headers a BMP may have: the file header and a version 5 info header.
+const BMP_MAX_HEADER_SIZE: usize = 14 + 124;
+
+/// Biggest BMP [`SplitIntoBmps`] is willing to read: one with 32 bits per pixel
+/// that's [`MAX_OUTPUT_MEDIA_DIMENSION_SIZE`] on both sides. Its length comes from
+/// the stream, and a broken one could otherwise claim to be up to 4GiB.
+fn max_bmp_length() -> usize {
+    let side = MAX_OUTPUT_MEDIA_DIMENSION_SIZE as usize;
+    side * side * 4 + BMP_MAX_HEADER_SIZE
 }
 
 struct SplitIntoBmps<T: Read> {
@@ -337,16 +730,39 @@ impl<T: Read> Iterator for SplitIntoBmps<T> {
 
         unfail_read_exact!(self.item.read_exact(&mut self.buffer[0..6]));
 
-        let bmp_length = u32::from_le_bytes(
-            self.buffer[2..6]
-                .try_into()
-                .expect("Incorrect slice length... somehow."),
-        ) as usize;
+        let bmp_length = |buffer: &[u8]| {
+            u32::from_le_bytes(
+                buffer[2..6]
+                    .try_into()
+                    .expect("Incorrect slice length... somehow."),
+            ) as usize
+        };
+        let is_valid_header =
+            |buffer: &[u8]| buffer[0..2] == [0x42, 0x4D] && bmp_length(buffer) > 6;
+
+        if !is_valid_header(&self.buffer) {
+            // Something got garbled. Try to find the next BMP header
+            // by shifting through the stream one byte at a time.
+            let mut skipped = 0;
+            while !is_valid_header(&self.buffer) {
+                if skipped >= BMP_RESYNC_WINDOW {
+                    return Some(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Invalid BMP header",
+                    )));
+                }
+                self.buffer.copy_within(1..6, 0);
+                unfail_read_exact!(self.item.read_exact(&mut self.buffer[5..6]));
+                skipped += 1;
+            }
+            log::warn!("Skipped {} garbage bytes in a BMP stream.", skipped);
+        }
 
-        if self.buffer[0..2] != [0x42, 0x4D] || bmp_length <= 6 {
+        let bmp_length = bmp_length(&self.buffer);
+        if bmp_length > max_bmp_length() {
             return Some(Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
-                "Invalid BMP header",
+                format!("BMP is too big ({} bytes)", bmp_length),
             )));
         }
 
@@ -378,9 +794,88 @@ fn get_bmp_width_height(buffer: &[u8]) -> Option<(isize, isize)> {
     Some((width as isize, height as isize))
 }
 
+/// Get width and height of a PNG, JPEG, WebP, BMP or GIF image from just its header,
+/// without decoding it. Returns [`None`] if it's none of those, or if the header is broken.
+fn sniff_image_dimensions(data: &[u8]) -> Option<(u64, u64)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as u64);
+    let le16 = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as u64);
+    let be32 = |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as u64);
+    let le24 = |at: usize| Some(le16(at)? | (*data.get(at + 2)? as u64) << 16);
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // The IHDR chunk always comes first.
+        return Some((be32(16)?, be32(20)?));
+    }
+
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some((le16(6)?, le16(8)?));
+    }
+
+    if data.starts_with(b"BM") && data.len() >= 26 {
+        let (width, height) = get_bmp_width_height(data)?;
+        return Some((width as u64, height as u64));
+    }
+
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return match data.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?) as u64;
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        };
+    }
+
+    if data.starts_with(&[0xff, 0xd8]) {
+        // Walk the segments until a "start of frame" one, which has the dimensions.
+        let mut at = 2;
+        loop {
+            if *data.get(at)? != 0xff {
+                return None;
+            }
+            let marker = *data.get(at + 1)?;
+            match marker {
+                // Padding.
+                0xff => at += 1,
+                // Markers without a length.
+                0x01 | 0xd0..=0xd7 => at += 2,
+                // Start of frame, except for DHT, JPG and DAC which share the range.
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return Some((be16(at + 7)?, be16(at + 5)?));
+                }
+                // Start of scan or end of image before any frame. Something's off.
+                0xd9 | 0xda => return None,
+                _ => at += 2 + be16(at + 2)? as usize,
+            }
+        }
+    }
+
+    None
+}
+
+/// Error out if the image claims to have more than [`MAX_INPUT_IMAGE_PIXELS`] pixels.
+/// Meant to be used before giving it to ImageMagick to decode.
+///
+/// Images in formats that aren't recognized are let through.
+/// ImageMagick's resource limits, see [`init_magick_resource_limits`], are there for those.
+fn check_input_image_size(data: &[u8]) -> Result<(), MagickError> {
+    match sniff_image_dimensions(data) {
+        Some((width, height)) if width.saturating_mul(height) > MAX_INPUT_IMAGE_PIXELS => {
+            Err(MagickError(format!(
+                "{}: {}x{}",
+                INPUT_IMAGE_TOO_BIG_ERROR, width, height
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
 pub fn count_video_frames_and_framerate_and_audio_and_length(
     path: &std::path::Path,
     count_audio: bool,
+    deadline: Instant,
 ) -> Result<(u64, f64, bool, Duration), std::io::Error> {
     macro_rules! goodbye {
         ($desc: expr) => {
@@ -388,7 +883,7 @@ pub fn count_video_frames_and_framerate_and_audio_and_length(
         };
     }
 
-    let counter = Command::new("ffmpeg")
+    let mut counter = Command::new("ffmpeg")
         .args([
             OsStr::new("-stats"),
             OsStr::new("-i"),
@@ -403,8 +898,17 @@ pub fn count_video_frames_and_framerate_and_audio_and_length(
         .stderr(Stdio::piped())
         .spawn()?;
 
-    let output = counter.wait_with_output()?;
-    let Ok(output) = String::from_utf8(output.stderr) else {
+    // Read the output on the side, so that ffmpeg doesn't get stuck on a full pipe
+    // while we're waiting for it.
+    let mut stderr = counter.stderr.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stderr.read_to_end(&mut output).map(|_| output)
+    });
+
+    wait_until(&mut counter, deadline)?;
+    let output = reader.join().expect("Reader thread panicked")?;
+    let Ok(output) = String::from_utf8(output) else {
         goodbye!("Frame counter returned non UTF-8 response");
     };
 
@@ -469,6 +973,183 @@ pub fn count_video_frames_and_framerate_and_audio_and_length(
     Ok((frame_count, framerate, has_audio, length))
 }
 
+/// External programs that some commands can't work without.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExternalTool {
+    Ffmpeg,
+    Tesseract,
+}
+
+impl ExternalTool {
+    pub const ALL: [ExternalTool; 2] = [ExternalTool::Ffmpeg, ExternalTool::Tesseract];
+
+    pub fn program(self) -> &'static str {
+        match self {
+            ExternalTool::Ffmpeg => "ffmpeg",
+            ExternalTool::Tesseract => "tesseract",
+        }
+    }
+
+    /// Arguments that make this program print its version and exit successfully.
+    fn version_args(self) -> &'static [&'static str] {
+        match self {
+            ExternalTool::Ffmpeg => &["-version"],
+            ExternalTool::Tesseract => &["--version"],
+        }
+    }
+
+    /// Returns `true` if this program is installed and can be run.
+    fn probe(self) -> bool {
+        runs_successfully(self.program(), self.version_args())
+    }
+}
+
+/// Returns `true` if this program can be run with these arguments and exits successfully.
+fn runs_successfully(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|x| x.success())
+}
+
+static AVAILABLE_TOOLS: OnceLock<Vec<ExternalTool>> = OnceLock::new();
+
+/// Check which of [`ExternalTool`]s are installed, and remember that for
+/// [`is_tool_available`]. Meant to be called on startup. Returns the missing ones.
+pub fn check_external_tools() -> Vec<ExternalTool> {
+    let available = AVAILABLE_TOOLS.get_or_init(|| {
+        ExternalTool::ALL
+            .into_iter()
+            .filter(|tool| tool.probe())
+            .collect()
+    });
+
+    ExternalTool::ALL
+        .into_iter()
+        .filter(|x| !available.contains(x))
+        .collect()
+}
+
+/// Returns `false` if [`check_external_tools`] found this tool missing.
+/// If it wasn't called, every tool is assumed to be there.
+pub fn is_tool_available(tool: ExternalTool) -> bool {
+    AVAILABLE_TOOLS.get().is_none_or(|x| x.contains(&tool))
+}
+
+/// Most frames per second animated stickers are rendered at.
+const MAX_ANIMATED_STICKER_FRAMERATE: f64 = 30.0;
+
+static CAN_RENDER_ANIMATED_STICKERS: OnceLock<bool> = OnceLock::new();
+
+/// Check if `lottie_convert.py` from python-lottie is installed to render animated stickers with,
+/// and remember that for [`can_render_animated_stickers`]. Meant to be called on startup.
+pub fn check_animated_sticker_renderer() -> bool {
+    *CAN_RENDER_ANIMATED_STICKERS.get_or_init(find_animated_sticker_renderer)
+}
+
+/// Returns `true` if `lottie_convert.py` can be run.
+fn find_animated_sticker_renderer() -> bool {
+    runs_successfully("lottie_convert.py", &["--help"])
+}
+
+/// Returns `true` if [`check_animated_sticker_renderer`] found a renderer.
+pub fn can_render_animated_stickers() -> bool {
+    CAN_RENDER_ANIMATED_STICKERS.get().copied().unwrap_or(false)
+}
+
+/// Make [`can_render_animated_stickers`] return `true`, to test what's done with
+/// animated stickers without a renderer installed.
+#[cfg(test)]
+pub fn pretend_animated_stickers_can_be_rendered() {
+    let _ = CAN_RENDER_ANIMATED_STICKERS.set(true);
+}
+
+/// Animated stickers are TGS files, which are gzip-compressed Lottie JSON.
+fn looks_like_tgs(header: &[u8]) -> bool {
+    header.starts_with(&[0x1f, 0x8b])
+}
+
+/// Render an animated sticker into a GIF at its own framerate, capped at
+/// [`MAX_ANIMATED_STICKER_FRAMERATE`], for it to be worked with like any other video.
+pub fn render_animated_sticker(
+    inputfile: &Path,
+    deadline: Instant,
+) -> Result<NamedTempFile, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let mut header = [0u8; 2];
+    unfail!(std::fs::File::open(inputfile).and_then(|mut x| x.read_exact(&mut header)));
+    if !looks_like_tgs(&header) {
+        return Err("not an animated sticker".to_string());
+    }
+
+    let rendered = unfail!(tempfile::Builder::new().suffix(".gif").tempfile());
+    let mut renderer = unfail!(Command::new("lottie_convert.py")
+        .args([
+            OsStr::new("--input-format"),
+            OsStr::new("tgs"),
+            OsStr::new("--output-format"),
+            OsStr::new("gif"),
+            inputfile.as_os_str(),
+            rendered.path().as_os_str(),
+        ])
+        .stdout(Stdio::null())
+        .spawn());
+    let status = unfail!(wait_until(&mut renderer, deadline));
+    if !status.success() {
+        return Err(format!("animated sticker renderer failed: {}", status));
+    }
+
+    let (_, framerate, _, _) = unfail!(count_video_frames_and_framerate_and_audio_and_length(
+        rendered.path(),
+        false,
+        deadline
+    ));
+
+    // GIF frame delays are in hundredths of a second, so 30 FPS comes out as about 33.
+    // Leave that be, as it's as close as a GIF gets.
+    if framerate <= MAX_ANIMATED_STICKER_FRAMERATE * 1.15 {
+        return Ok(rendered);
+    }
+
+    let capped = unfail!(NamedTempFile::new());
+    let mut converter = unfail!(Command::new("ffmpeg")
+        .args([
+            OsStr::new("-y"),
+            OsStr::new("-loglevel"),
+            OsStr::new("error"),
+            OsStr::new("-i"),
+            rendered.path().as_os_str(),
+            OsStr::new("-vf"),
+            OsStr::new(&format!("fps={}", MAX_ANIMATED_STICKER_FRAMERATE)),
+            // Lossless, so that the frames are as rendered.
+            OsStr::new("-c:v"),
+            OsStr::new("ffv1"),
+            OsStr::new("-f"),
+            OsStr::new("matroska"),
+            capped.path().as_os_str(),
+        ])
+        .spawn());
+    let status = unfail!(wait_until(&mut converter, deadline));
+    if !status.success() {
+        return Err(format!(
+            "capping animated sticker framerate failed: {}",
+            status
+        ));
+    }
+
+    Ok(capped)
+}
+
 fn approx_same_aspect_ratio(
     (input_width, input_height): (f64, f64),
     (end_width, end_height): (f64, f64),
@@ -491,6 +1172,50 @@ fn approx_same_aspect_ratio(
     width_diff_is_insignificant && height_diff_is_insignificant
 }
 
+/// Get ffmpeg input arguments to keep only the `trim` range of a video this long
+/// with this many frames, and how many frames are left after that.
+///
+/// If ffmpeg couldn't tell how long the video is, it's trimmed as asked, with no
+/// checks against its end, and the frame count is left as is.
+fn trim_args(
+    trim: Option<(Duration, Duration)>,
+    input_length: Duration,
+    input_frame_count: u64,
+) -> Result<(Vec<String>, u64), String> {
+    let Some((start, end)) = trim else {
+        return Ok((Vec::new(), input_frame_count));
+    };
+    let input_length = (!input_length.is_zero()).then_some(input_length);
+
+    // Going past the end is the same as trimming until the end.
+    let end = match input_length {
+        Some(input_length) if start >= input_length => {
+            return Err(TRIM_OUT_OF_RANGE_ERROR.to_string());
+        }
+        Some(input_length) => end.min(input_length),
+        None => end,
+    };
+
+    // Seeking before the input is exact when decoding, like with `trim_media`.
+    let args = vec![
+        "-ss".to_string(),
+        start.as_secs_f64().to_string(),
+        "-to".to_string(),
+        end.as_secs_f64().to_string(),
+    ];
+
+    // Only the trimmed range is decoded, so curves should only span that.
+    let frame_count = match input_length {
+        Some(input_length) => {
+            let kept = (end - start).as_secs_f64() / input_length.as_secs_f64();
+            (input_frame_count as f64 * kept).round() as u64
+        }
+        None => input_frame_count,
+    };
+
+    Ok((args, frame_count))
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn resize_video(
     status_report: Sender<String>,
@@ -504,6 +1229,12 @@ pub fn resize_video(
     input_dimensions: (u32, u32),
     resize_curve: ResizeCurve,
     quality: NonZeroU8,
+    color: ColorAdjustments,
+    trim: Option<(Duration, Duration)>,
+    container: VideoContainer,
+    loops: u8,
+    has_alpha: bool,
+    deadline: Instant,
 ) -> Result<Vec<u8>, String> {
     macro_rules! unfail {
         ($thing: expr) => {
@@ -533,6 +1264,9 @@ pub fn resize_video(
     output_width += output_width % 2;
     output_height += output_height % 2;
 
+    // Only WebM can keep transparency, and JPEG compression would lose it anyway.
+    let keep_alpha = has_alpha && container == VideoContainer::Webm && quality.get() >= 100;
+
     // If we want JPEG compression, the frames should just be outputted in JPEG.
     // Otherwise, BMP is simplest.
     let format = if quality.get() < 100 {
@@ -572,16 +1306,43 @@ pub fn resize_video(
         false
     };
 
+    // Without compression, frames can be handed to the encoder as raw pixels,
+    // skipping encoding and decoding a BMP for each of them.
+    // That only works if every frame ends up being exactly the output size,
+    // which isn't the case if fitting without stretching or extending.
+    // Raw frames also have no transparency.
+    let raw_frames = format == ImageFormat::Bmp
+        && !keep_alpha
+        && (stretch_to_output_size || resize_type != ResizeType::Fit);
+
     let _ = status_report.send("Creating temp files...".to_string());
 
     let outputfile = unfail!(NamedTempFile::new());
 
     let _ = status_report.send("Counting frames...".to_string());
 
-    let (input_frame_count, input_frame_rate, has_audio, _input_length) = unfail!(
-        count_video_frames_and_framerate_and_audio_and_length(inputfile, false)
+    let (input_frame_count, input_frame_rate, has_audio, input_length) = unfail!(
+        count_video_frames_and_framerate_and_audio_and_length(inputfile, false, deadline)
     );
 
+    let (trim_args, input_frame_count) = trim_args(trim, input_length, input_frame_count)?;
+
+    // Looping decodes the input this many times over, like one long video.
+    let loops = loops.max(1);
+    let input_frame_count = input_frame_count * loops as u64;
+    let output_pixels = input_frame_count
+        .saturating_mul(output_width as u64)
+        .saturating_mul(output_height as u64);
+    if loops > 1 && output_pixels > MAX_LOOPED_VIDEO_PIXELS {
+        return Err(LOOPED_VIDEO_TOO_LONG_ERROR.to_string());
+    }
+    let extra_loops = (loops - 1).to_string();
+    let loop_args: &[&str] = if loops > 1 {
+        &["-stream_loop", extra_loops.as_str()]
+    } else {
+        &[]
+    };
+
     let converting_function = move |(count, frame): (_, Result<Vec<u8>, _>)| match frame {
         Ok(frame) => {
             let curved_width = resize_curve.apply_resize_for(
@@ -615,9 +1376,29 @@ pub fn resize_video(
                 && input_dimensions == Some((output_width as isize, output_height as isize))
                 && input_dimensions == Some((curved_width as isize, curved_height as isize))
                 && quality.get() >= 100
+                && color.is_noop()
+                && !matches!(resize_type, ResizeType::Kaleidoscope { .. })
             {
                 // It doesn't. Just return the same buffer directly.
-                Ok(frame)
+                if raw_frames {
+                    image_to_raw_bgr(&frame, (output_width, output_height))
+                } else {
+                    Ok(frame)
+                }
+            } else if raw_frames {
+                resize_image_to_raw_bgr(
+                    &frame,
+                    curved_width as isize,
+                    curved_height as isize,
+                    curved_rotation,
+                    resize_type,
+                    format,
+                    Some((output_width, output_height, stretch_to_output_size)),
+                    is_curved,
+                    curved_quality,
+                    color,
+                    (output_width, output_height),
+                )
             } else {
                 resize_image(
                     &frame,
@@ -629,6 +1410,9 @@ pub fn resize_video(
                     Some((output_width, output_height, stretch_to_output_size)),
                     is_curved, // Prevent bounds bouncing.
                     curved_quality,
+                    color,
+                    ChromaSubsampling::Auto,
+                    true, // Video frames carry no metadata.
                 )
             };
 
@@ -640,14 +1424,26 @@ pub fn resize_video(
         Err(e) => Err(e),
     };
 
+    // ffmpeg's own VP9 decoder ignores transparency, but libvpx's doesn't.
+    let (alpha_decoder_args, alpha_frame_args): (&[&str], &[&str]) = if keep_alpha {
+        (&["-c:v", "libvpx-vp9"], &["-pix_fmt", "bgra"])
+    } else {
+        (&[], &[])
+    };
+
     // We computed all the internal stuff. Now to actually do something useful.
     let decoder = Command::new("ffmpeg")
         .args([
             OsStr::new("-y"),
             OsStr::new("-loglevel"),
             OsStr::new("error"),
-            OsStr::new("-i"),
-            inputfile.as_ref(),
+        ])
+        .args(&trim_args)
+        .args(loop_args)
+        .args(alpha_decoder_args)
+        .args([OsStr::new("-i"), inputfile.as_ref()])
+        .args(alpha_frame_args)
+        .args([
             OsStr::new("-c:v"),
             OsStr::new("bmp"),
             OsStr::new("-vsync"),
@@ -682,6 +1478,7 @@ pub fn resize_video(
     for _ in 0..parallelisms {
         let decoded_receiver = decoded_receiver.clone();
         let resized_sender = resized_sender.clone();
+        let converting_function = converting_function.clone();
         converting_thread_handles.push(std::thread::spawn(move || {
             while let Ok(frame) = decoded_receiver.recv() {
                 let result = converting_function(frame);
@@ -705,34 +1502,80 @@ pub fn resize_video(
 
     let _ = status_report.send("Initializing encoder...".to_string());
 
-    let encoder = Command::new("ffmpeg")
-        .args([
-            OsStr::new("-y"),
-            OsStr::new("-loglevel"),
-            OsStr::new("error"),
+    let input_frame_rate_str = input_frame_rate.to_string();
+    let raw_frame_size_str = format!("{}x{}", output_width, output_height);
+
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+    ];
+
+    if raw_frames {
+        args.extend_from_slice(&[
+            OsStr::new("-f"),
+            OsStr::new("rawvideo"),
+            OsStr::new("-pix_fmt"),
+            OsStr::new("bgr24"),
+            OsStr::new("-s"),
+            OsStr::new(raw_frame_size_str.as_str()),
+        ]);
+    } else {
+        args.extend_from_slice(&[
             OsStr::new("-f"),
             OsStr::new("image2pipe"),
             OsStr::new("-vcodec"),
             OsStr::new(format.as_str_for_ffmpeg()),
-            OsStr::new("-framerate"),
-            OsStr::new(input_frame_rate.to_string().as_str()),
-            OsStr::new("-i"),
-            OsStr::new("-"),
-            OsStr::new("-vf"), // Pad uneven pixels with black.
-            OsStr::new("pad=ceil(iw/2)*2:ceil(ih/2)*2"),
-            // I'd prefer the crop filter instead, but it leaves
-            // a chance of cropping to 0 width/height and stuff breaking :(
-            //OsStr::new("crop=trunc(iw/2)*2:trunc(ih/2)*2"),
+        ]);
+    }
+
+    args.extend_from_slice(&[
+        OsStr::new("-framerate"),
+        OsStr::new(input_frame_rate_str.as_str()),
+        OsStr::new("-i"),
+        OsStr::new("-"),
+        OsStr::new("-vf"), // Pad uneven pixels with black, or with nothing if transparent.
+        OsStr::new(if keep_alpha {
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2:color=black@0"
+        } else {
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2"
+        }),
+        // I'd prefer the crop filter instead, but it leaves
+        // a chance of cropping to 0 width/height and stuff breaking :(
+        //OsStr::new("crop=trunc(iw/2)*2:trunc(ih/2)*2"),
+    ]);
+
+    match container {
+        VideoContainer::Mp4 => args.extend_from_slice(&[
             OsStr::new("-pix_fmt"),
             OsStr::new("yuv420p"),
             OsStr::new("-f"),
             OsStr::new("mp4"),
-            outputfile.path().as_os_str(),
-        ])
-        .stdin(Stdio::piped())
-        .spawn();
-    let mut encoder = unfail!(encoder);
-    let mut encoder_stdin = encoder.stdin.take().unwrap();
+        ]),
+        VideoContainer::Webm => args.extend_from_slice(&[
+            OsStr::new("-c:v"),
+            OsStr::new("libvpx-vp9"),
+            OsStr::new("-pix_fmt"),
+            OsStr::new(if keep_alpha { "yuva420p" } else { "yuv420p" }),
+            // Constant quality mode.
+            OsStr::new("-b:v"),
+            OsStr::new("0"),
+            OsStr::new("-crf"),
+            OsStr::new("32"),
+            OsStr::new("-row-mt"),
+            OsStr::new("1"),
+            OsStr::new("-f"),
+            OsStr::new("webm"),
+        ]),
+    }
+
+    let encoder = Command::new("ffmpeg")
+        .args(args)
+        .arg(outputfile.path().as_os_str())
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut encoder = unfail!(encoder);
+    let mut encoder_stdin = encoder.stdin.take().unwrap();
 
     //let mut writing_stream = resized_receiver
     //    .iter()
@@ -758,9 +1601,20 @@ pub fn resize_video(
     let mut next_frame_to_write = 0;
 
     loop {
-        let new_frame = resized_receiver.recv();
-        let new_frame_received = new_frame.is_ok();
-        if let Ok(new_frame) = new_frame {
+        let new_frame = match resized_receiver.recv_deadline(deadline) {
+            Ok(new_frame) => Some(new_frame),
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => None,
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                // Killing the decoder also stops the threads feeding us frames.
+                let _ = decoder.kill();
+                let _ = encoder.kill();
+                let _ = decoder.wait();
+                let _ = encoder.wait();
+                return Err(TIMED_OUT_ERROR.to_string());
+            }
+        };
+        let new_frame_received = new_frame.is_some();
+        if let Some(new_frame) = new_frame {
             frame_store.push(unfail!(new_frame));
             frames_received += 1;
 
@@ -795,8 +1649,8 @@ pub fn resize_video(
     for h in converting_thread_handles {
         h.join().unwrap();
     }
-    unfail!(decoder.wait());
-    unfail!(encoder.wait());
+    unfail!(wait_until(&mut decoder, deadline));
+    unfail!(wait_until(&mut encoder, deadline));
 
     let mut finalfile = if has_audio && !strip_audio {
         let _ = status_report.send("Writing audio...".to_string());
@@ -804,16 +1658,15 @@ pub fn resize_video(
         // It's probably possible to do some funny mapping shenanigans to add the audio
         // in at the same time as muxing the video, but I'm too lazy to figure that out right now.
         let muxfile = unfail!(NamedTempFile::new());
-        // Will exclude cases of 0.0, -0.0, and all the NaNs and infinities
-        let distort_audio = vibrato_hz.is_normal()
-            && vibrato_depth.is_normal()
-            && vibrato_hz >= 0.1
-            && vibrato_depth > 0.0;
 
         let mut args = vec![
             OsStr::new("-y"),
             OsStr::new("-loglevel"),
             OsStr::new("error"),
+        ];
+        args.extend(trim_args.iter().map(OsStr::new));
+        args.extend(loop_args.iter().map(OsStr::new));
+        args.extend_from_slice(&[
             OsStr::new("-i"),
             inputfile.as_ref(),
             OsStr::new("-i"),
@@ -824,28 +1677,13 @@ pub fn resize_video(
             OsStr::new("1:v:0"),
             OsStr::new("-map"),
             OsStr::new("0:a:0"),
-        ];
+        ]);
 
-        let mut vibrato_str_temp: String = String::new();
+        let vibrato = vibrato_filter(vibrato_hz, vibrato_depth);
 
-        if distort_audio {
+        if let Some(vibrato) = &vibrato {
             args.push(OsStr::new("-af"));
-
-            let mut vibrato_depth_left = vibrato_depth;
-            while vibrato_depth_left > 0.0 {
-                use std::fmt::Write;
-                write!(
-                    vibrato_str_temp,
-                    "vibrato=f={}:d={},aformat=s16p,",
-                    vibrato_hz.min(20000.0),
-                    vibrato_depth.min(1.0)
-                )
-                .expect("this literally cannot panic");
-
-                vibrato_depth_left -= 1.0;
-            }
-
-            args.push(vibrato_str_temp.as_ref());
+            args.push(vibrato.as_ref());
         }
 
         // Quality tends to behave in an exponential manner.
@@ -863,21 +1701,31 @@ pub fn resize_video(
         let bitrate = 20 + quality.saturating_add(quality / 4).min(125);
         let bitrate_str = format!("{}k", bitrate);
 
-        args.extend_from_slice(&[
-            OsStr::new("-b:a"),
-            bitrate_str.as_ref(),
-            OsStr::new("-f"),
-            OsStr::new("mp4"),
-            OsStr::new("-preset"),
-            OsStr::new("slow"),
-            OsStr::new("-movflags"),
-            OsStr::new("+faststart"),
-            muxfile.path().as_ref(),
-        ]);
+        args.extend_from_slice(&[OsStr::new("-b:a"), bitrate_str.as_ref()]);
+
+        match container {
+            VideoContainer::Mp4 => args.extend_from_slice(&[
+                OsStr::new("-f"),
+                OsStr::new("mp4"),
+                OsStr::new("-preset"),
+                OsStr::new("slow"),
+                OsStr::new("-movflags"),
+                OsStr::new("+faststart"),
+            ]),
+            // WebM can't have AAC audio.
+            VideoContainer::Webm => args.extend_from_slice(&[
+                OsStr::new("-c:a"),
+                OsStr::new("libopus"),
+                OsStr::new("-f"),
+                OsStr::new("webm"),
+            ]),
+        }
+
+        args.push(muxfile.path().as_ref());
 
         let audiomuxer = Command::new("ffmpeg").args(args).spawn();
 
-        unfail!(unfail!(audiomuxer).wait());
+        unfail!(wait_until(&mut unfail!(audiomuxer), deadline));
 
         muxfile
     } else {
@@ -892,9 +1740,61 @@ pub fn resize_video(
     Ok(output)
 }
 
+/// Builds an ffmpeg audio filter chain applying vibrato with given parameters.
+///
+/// Returns [`None`] if the parameters wouldn't distort the audio at all.
+fn vibrato_filter(vibrato_hz: f64, vibrato_depth: f64) -> Option<String> {
+    // Will exclude cases of 0.0, -0.0, and all the NaNs and infinities
+    let distort_audio = vibrato_hz.is_normal()
+        && vibrato_depth.is_normal()
+        && vibrato_hz >= 0.1
+        && vibrato_depth > 0.0;
+
+    if !distort_audio {
+        return None;
+    }
+
+    let mut vibrato_str = String::new();
+
+    let mut vibrato_depth_left = vibrato_depth;
+    while vibrato_depth_left > 0.0 {
+        use std::fmt::Write;
+        write!(
+            vibrato_str,
+            "vibrato=f={}:d={},aformat=s16p,",
+            vibrato_hz.min(20000.0),
+            vibrato_depth.min(1.0)
+        )
+        .expect("this literally cannot panic");
+
+        vibrato_depth_left -= 1.0;
+    }
+
+    Some(vibrato_str)
+}
+
+/// Sometimes, Tesseract reads I (uppercase i) as | (pipe). Replace those in-place,
+/// but only within words, as pipes on their own are likely real, like in tables or code.
+fn fix_misread_pipes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut previous = None;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let within_word = c == '|'
+            && previous.is_some_and(char::is_alphabetic)
+            && chars.peek().is_some_and(|x| x.is_alphabetic());
+        output.push(if within_word { 'I' } else { c });
+        previous = Some(c);
+    }
+
+    output
+}
+
 pub fn ocr_image(data: &[u8]) -> Result<String, MagickError> {
     // Use ImageMagick to normalize colors and export to PNG,
     // which Tesseract can read.
+    check_input_image_size(data)?;
     let wand = MagickWand::new();
     wand.read_image_blob(data)?;
     wand.normalize_image()?;
@@ -903,7 +1803,13 @@ pub fn ocr_image(data: &[u8]) -> Result<String, MagickError> {
 
     let mut result = String::new();
 
-    fn tesseract_it(data: &[u8], buffer: &mut String, grab_all_text: bool) {
+    fn tesseract_it(
+        data: &[u8],
+        buffer: &mut String,
+        grab_all_text: bool,
+    ) -> Result<(), MagickError> {
+        let failed = |e: std::io::Error| MagickError(format!("failed to run Tesseract: {}", e));
+
         let args_grab_all = &[
             OsStr::new("--psm"),
             // PSM mode 12's name sounds more attractive than 11,
@@ -926,23 +1832,19 @@ pub fn ocr_image(data: &[u8]) -> Result<String, MagickError> {
             .stdin(Stdio::piped())
             .stderr(Stdio::null()) // Tesseract is noisy and I don't want to make a config file lol
             .spawn()
-            .expect("Spawning tesseract failed!");
+            .map_err(failed)?;
 
         let mut stdin = tesseract.stdin.take().unwrap();
         let mut stdout = tesseract.stdout.take().unwrap();
 
-        stdin
-            .write_all(data)
-            .expect("Failed sending image to Tesseract!");
+        stdin.write_all(data).map_err(failed)?;
 
         drop(stdin);
 
-        stdout
-            .read_to_string(buffer)
-            .expect("Failed reading Tesseract's output!");
+        stdout.read_to_string(buffer).map_err(failed)?;
 
         // Might return an error. Bleh, we'll see from its output lol
-        let _ = tesseract.wait().expect("Failed closing Tesseract!");
+        let _ = tesseract.wait().map_err(failed)?;
 
         // Postprocess the text...
         let new = {
@@ -950,13 +1852,10 @@ pub fn ocr_image(data: &[u8]) -> Result<String, MagickError> {
             static OCR_REGEX_2: OnceLock<Regex> = OnceLock::new();
             let regex_1 = OCR_REGEX_1.get_or_init(|| Regex::new(r#"[ \t]{2,}"#).unwrap());
             let regex_2 = OCR_REGEX_2.get_or_init(|| Regex::new(r#"\s\s\s+"#).unwrap());
-            // Sometimes, Tesseract inserts | (pipe) instead of I (uppercase i).
-            // Replace those in-place, if they exist.
-            let regex_3 = OCR_REGEX_2.get_or_init(|| Regex::new(r#"\|"#).unwrap());
 
             let stripped = regex_1.replace_all(buffer, " ");
             let stripped = regex_2.replace_all(&stripped, "\n");
-            let stripped = regex_3.replace_all(&stripped, "I");
+            let stripped = fix_misread_pipes(&stripped);
 
             let stripped = stripped.trim();
 
@@ -982,21 +1881,105 @@ pub fn ocr_image(data: &[u8]) -> Result<String, MagickError> {
         };
         buffer.clear();
         buffer.push_str(&new);
+        Ok(())
     }
 
-    tesseract_it(&data, &mut result, false);
+    tesseract_it(&data, &mut result, false)?;
 
     if result.is_empty() {
-        tesseract_it(&data, &mut result, true);
+        tesseract_it(&data, &mut result, true)?;
     }
 
     Ok(result)
 }
 
+/// Side length of the square for each color in the swatch made by [`image_palette`].
+const PALETTE_SWATCH_SIZE: usize = 64;
+/// Images are shrunk to fit this size before finding their palette.
+/// Plenty to find the dominant colors, and much faster.
+const PALETTE_SAMPLE_SIZE: usize = 256;
+
+/// Finds up to `count` most dominant colors of an image.
+///
+/// Returns a PNG swatch of them, from most to least dominant left to right,
+/// along with their hex codes in the same order.
+pub fn image_palette(data: &[u8], count: u8) -> Result<(Vec<u8>, Vec<String>), MagickError> {
+    check_input_image_size(data)?;
+    let wand = MagickWand::new();
+    wand.read_image_blob(data)?;
+    // Transparent pixels would otherwise count as whatever color they hide.
+    wand.set_image_alpha_channel(AlphaChannelOption::Remove)?;
+
+    let (width, height) = (wand.get_image_width(), wand.get_image_height());
+    let scale = (PALETTE_SAMPLE_SIZE as f64 / width.max(height) as f64).min(1.0);
+    wand.sample_image(
+        ((width as f64 * scale) as usize).max(1),
+        ((height as f64 * scale) as usize).max(1),
+    )?;
+
+    wand.quantize_image(
+        count as usize,
+        ColorspaceType::sRGB,
+        0,
+        DitherMethod::No,
+        false,
+    )?;
+
+    let mut colors = wand
+        .get_image_histogram()
+        .ok_or("Failed to get the image's colors")?;
+    colors.sort_by_key(|x| std::cmp::Reverse(x.get_color_count()));
+    colors.truncate(count as usize);
+
+    let to_byte = |x: f64| (x * 255.0).round().clamp(0.0, 255.0) as u8;
+    let hex_codes = colors
+        .iter()
+        .map(|x| {
+            format!(
+                "#{:02X}{:02X}{:02X}",
+                to_byte(x.get_red()),
+                to_byte(x.get_green()),
+                to_byte(x.get_blue())
+            )
+        })
+        .collect();
+
+    let mut swatch = MagickWand::new();
+    swatch.new_image(
+        PALETTE_SWATCH_SIZE * colors.len().max(1),
+        PALETTE_SWATCH_SIZE,
+        &PixelWand::new(),
+    )?;
+
+    let mut drawing = DrawingWand::new();
+    for (i, color) in colors.iter().enumerate() {
+        let x = (i * PALETTE_SWATCH_SIZE) as f64;
+        drawing.set_fill_color(color);
+        drawing.draw_rectangle(
+            x,
+            0.0,
+            x + PALETTE_SWATCH_SIZE as f64 - 1.0,
+            PALETTE_SWATCH_SIZE as f64 - 1.0,
+        );
+    }
+    swatch.draw_image(&drawing)?;
+
+    Ok((swatch.write_image_blob("png")?, hex_codes))
+}
+
+/// Pick which of `count` amen breaks to use. The same seed always picks the same one.
+/// See [`crate::tasks::pick_seed`].
+fn amen_break_index(count: usize, seed: u64) -> usize {
+    use rand::Rng;
+    crate::tasks::seeded_rng(seed).gen_range(0..count)
+}
+
 pub fn amen_break_media(
     status_report: Sender<String>,
     inputfile: &Path,
     is_video: bool,
+    seed: u64,
+    deadline: Instant,
 ) -> Result<Vec<u8>, String> {
     macro_rules! unfail {
         ($thing: expr) => {
@@ -1011,24 +1994,25 @@ pub fn amen_break_media(
     let mut outputfile = unfail!(NamedTempFile::new());
 
     let _ = status_report.send("Choosing an amen break...".to_string());
-    let count = unfail!(std::fs::read_dir("amen-breaks")).count();
-    let mut rng = rand::thread_rng();
-    use rand::Rng;
-    let which_to_pick = rng.gen_range(0..count);
-    let Some(the_break) = unfail!(std::fs::read_dir("amen-breaks")).nth(which_to_pick) else {
+    // Sorted, as the order files are listed in can change, but the same seed
+    // should keep picking the same break.
+    let mut breaks = unfail!(unfail!(std::fs::read_dir("amen-breaks"))
+        .map(|x| x.map(|x| x.path()))
+        .collect::<Result<Vec<_>, _>>());
+    breaks.sort();
+    if breaks.is_empty() {
         return Err("Failed to pick an amen break!".to_string());
-    };
-
-    let break_path = unfail!(the_break).path();
+    }
+    let break_path = breaks.swap_remove(amen_break_index(breaks.len(), seed));
 
     let _ = status_report.send("Checking amen break length".to_string());
     let (_input_frame_count, _input_frame_rate, _has_audio, amen_break_length) = unfail!(
-        count_video_frames_and_framerate_and_audio_and_length(&break_path, true)
+        count_video_frames_and_framerate_and_audio_and_length(&break_path, true, deadline)
     );
 
     let _ = status_report.send("Checking video length...".to_string());
     let (_input_frame_count, _input_frame_rate, _has_audio, input_length) = unfail!(
-        count_video_frames_and_framerate_and_audio_and_length(inputfile, false)
+        count_video_frames_and_framerate_and_audio_and_length(inputfile, false, deadline)
     );
 
     let target_length = amen_break_length
@@ -1089,7 +2073,399 @@ pub fn amen_break_media(
         ])
         .spawn();
 
-    let converter_result = unfail!(converter).wait();
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+/// Builds an ffmpeg audio filter chain changing audio speed by this factor
+/// without changing its pitch.
+///
+/// Returns [`None`] if the factor wouldn't change the audio at all.
+fn atempo_filter(factor: f64) -> Option<String> {
+    if !factor.is_normal() || factor <= 0.0 || factor == 1.0 {
+        return None;
+    }
+
+    use std::fmt::Write;
+    let mut atempo_str = String::new();
+
+    // A single atempo filter only accepts factors between 0.5 and 2.0,
+    // so stack as many as needed to get the rest of the way.
+    let mut factor_left = factor;
+    while !(0.5..=2.0).contains(&factor_left) {
+        let step = if factor_left > 2.0 { 2.0 } else { 0.5 };
+        write!(atempo_str, "atempo={},", step).expect("this literally cannot panic");
+        factor_left /= step;
+    }
+    write!(atempo_str, "atempo={},", factor_left).expect("this literally cannot panic");
+
+    Some(atempo_str)
+}
+
+/// Builds an ffmpeg audio filter chain changing audio speed by this factor
+/// along with its pitch, by pretending it has a different sample rate.
+///
+/// Returns [`None`] if the factor wouldn't change the audio at all.
+fn pitch_shift_filter(factor: f64) -> Option<String> {
+    if !factor.is_normal() || factor <= 0.0 || factor == 1.0 {
+        return None;
+    }
+
+    // Resample to a known rate first, so that the new rate can be computed here.
+    const RATE: f64 = 48000.0;
+    Some(format!(
+        "aresample={},asetrate={},aresample={},",
+        RATE,
+        (RATE * factor).round(),
+        RATE
+    ))
+}
+
+/// Builds an ffmpeg audio filter chain changing audio speed by this factor,
+/// with its pitch changed as per `pitch`.
+///
+/// Returns [`None`] if the factor wouldn't change the audio at all.
+fn speed_filter(factor: f64, pitch: PitchMode) -> Option<String> {
+    match pitch {
+        PitchMode::Preserve => atempo_filter(factor),
+        PitchMode::Shift => pitch_shift_filter(factor),
+    }
+}
+
+/// Speeds up or slows down a video, along with its audio if it has any.
+pub fn change_video_speed(
+    status_report: Sender<String>,
+    inputfile: &Path,
+    factor: f64,
+    pitch: PitchMode,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let _ = status_report.send("Creating temp files...".to_string());
+    let mut outputfile = unfail!(NamedTempFile::new());
+
+    let _ = status_report.send("Checking video...".to_string());
+    let (_input_frame_count, _input_frame_rate, has_audio, _input_length) = unfail!(
+        count_video_frames_and_framerate_and_audio_and_length(inputfile, true, deadline)
+    );
+
+    // Pad uneven pixels with black, like in `amen_break_media`.
+    let video_filter = format!("setpts=PTS/{},pad=ceil(iw/2)*2:ceil(ih/2)*2", factor);
+    let audio_filter = speed_filter(factor, pitch);
+    let audio_filter = audio_filter.as_deref().map(|x| x.trim_end_matches(','));
+
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+        OsStr::new("-i"),
+        inputfile.as_ref(),
+        OsStr::new("-map"),
+        OsStr::new("0:v:0"),
+        OsStr::new("-vf"),
+        OsStr::new(&video_filter),
+    ];
+
+    if has_audio {
+        args.extend_from_slice(&[OsStr::new("-map"), OsStr::new("0:a:0")]);
+        if let Some(audio_filter) = audio_filter {
+            args.extend_from_slice(&[OsStr::new("-af"), OsStr::new(audio_filter)]);
+        }
+    }
+
+    args.extend_from_slice(&[
+        OsStr::new("-pix_fmt"),
+        OsStr::new("yuv420p"),
+        OsStr::new("-f"),
+        OsStr::new("mp4"),
+        OsStr::new("-preset"),
+        OsStr::new("slow"),
+        OsStr::new("-movflags"),
+        OsStr::new("+faststart"),
+        outputfile.path().as_os_str(),
+    ]);
+
+    let _ = status_report.send("Changing speed...".to_string());
+
+    let converter = Command::new("ffmpeg").args(args).spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+/// Inverts colors of an image, and its transparency too if `alpha` is true.
+pub fn negate_image(data: &[u8], alpha: bool, format: ImageFormat) -> Result<Vec<u8>, MagickError> {
+    check_input_image_size(data)?;
+    let mut wand = MagickWand::new();
+    wand.read_image_blob(data)?;
+
+    // `MagickWand::negate_image` only inverts gray pixels, so do it with an expression instead,
+    // limited to the channels we want.
+    wand.set_image_channel_mask(if alpha {
+        ChannelType::CompositeChannels
+    } else {
+        ChannelType::RGBChannels
+    });
+    let negated = wand.fx("1-u")?;
+
+    negated.write_image_blob(format.as_str())
+}
+
+/// Inverts colors of a video, outputting an MP4.
+pub fn negate_video(
+    status_report: Sender<String>,
+    inputfile: &Path,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let _ = status_report.send("Creating temp files...".to_string());
+    let mut outputfile = unfail!(NamedTempFile::new());
+
+    let _ = status_report.send("Checking video...".to_string());
+    let (_input_frame_count, _input_frame_rate, has_audio, _input_length) = unfail!(
+        count_video_frames_and_framerate_and_audio_and_length(inputfile, true, deadline)
+    );
+
+    // Pad uneven pixels with black, like in `amen_break_media`.
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+        OsStr::new("-i"),
+        inputfile.as_ref(),
+        OsStr::new("-map"),
+        OsStr::new("0:v:0"),
+        OsStr::new("-vf"),
+        OsStr::new("negate,pad=ceil(iw/2)*2:ceil(ih/2)*2"),
+    ];
+
+    if has_audio {
+        args.extend_from_slice(&[
+            OsStr::new("-map"),
+            OsStr::new("0:a:0"),
+            OsStr::new("-c:a"),
+            OsStr::new("aac"),
+        ]);
+    }
+
+    args.extend_from_slice(&[
+        OsStr::new("-pix_fmt"),
+        OsStr::new("yuv420p"),
+        OsStr::new("-f"),
+        OsStr::new("mp4"),
+        OsStr::new("-movflags"),
+        OsStr::new("+faststart"),
+        outputfile.path().as_os_str(),
+    ]);
+
+    let _ = status_report.send("Inverting colors...".to_string());
+
+    let converter = Command::new("ffmpeg").args(args).spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+/// Cuts the range from `start` to `end` (or to the end of the media if [`None`])
+/// out of a video or an audio file.
+///
+/// Videos are output as MP4. Unless `precise` is true, their streams are copied
+/// as is, meaning the cut happens at the nearest keyframes. Audio is always re-encoded,
+/// as OGG Opus if `as_voice` is true, suitable to send as a voice message, or MP3 otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn trim_media(
+    status_report: Sender<String>,
+    inputfile: &Path,
+    start: Duration,
+    end: Option<Duration>,
+    precise: bool,
+    is_sound: bool,
+    as_voice: bool,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let _ = status_report.send("Creating temp files...".to_string());
+    let mut outputfile = unfail!(NamedTempFile::new());
+
+    let duration = end.map(|end| end.saturating_sub(start).as_secs_f64().to_string());
+    let start = start.as_secs_f64().to_string();
+
+    // Seeking before the input is fast, and still exact when re-encoding.
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+        OsStr::new("-ss"),
+        OsStr::new(&start),
+        OsStr::new("-i"),
+        inputfile.as_ref(),
+    ];
+
+    if let Some(duration) = &duration {
+        args.extend_from_slice(&[OsStr::new("-t"), OsStr::new(duration)]);
+    }
+
+    if is_sound {
+        args.extend_from_slice(&[OsStr::new("-map"), OsStr::new("0:a:0")]);
+        if as_voice {
+            args.extend_from_slice(&[
+                OsStr::new("-c:a"),
+                OsStr::new("libopus"),
+                OsStr::new("-f"),
+                OsStr::new("ogg"),
+            ]);
+        } else {
+            args.extend_from_slice(&[
+                OsStr::new("-c:a"),
+                OsStr::new("libmp3lame"),
+                OsStr::new("-q:a"),
+                OsStr::new("2"),
+                OsStr::new("-f"),
+                OsStr::new("mp3"),
+            ]);
+        }
+    } else {
+        args.extend_from_slice(&[
+            OsStr::new("-map"),
+            OsStr::new("0:v:0"),
+            OsStr::new("-map"),
+            OsStr::new("0:a:0?"),
+        ]);
+        if precise {
+            // Pad uneven pixels with black, like in `amen_break_media`.
+            args.extend_from_slice(&[
+                OsStr::new("-vf"),
+                OsStr::new("pad=ceil(iw/2)*2:ceil(ih/2)*2"),
+                OsStr::new("-pix_fmt"),
+                OsStr::new("yuv420p"),
+                OsStr::new("-preset"),
+                OsStr::new("slow"),
+            ]);
+        } else {
+            args.extend_from_slice(&[OsStr::new("-c"), OsStr::new("copy")]);
+        }
+        args.extend_from_slice(&[
+            OsStr::new("-f"),
+            OsStr::new("mp4"),
+            OsStr::new("-movflags"),
+            OsStr::new("+faststart"),
+        ]);
+    }
+
+    args.push(outputfile.path().as_os_str());
+
+    let _ = status_report.send("Trimming...".to_string());
+
+    let converter = Command::new("ffmpeg").args(args).spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+/// Extracts the frame of a video at `timestamp` as a PNG.
+pub fn extract_frame(
+    inputfile: &Path,
+    timestamp: Duration,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let mut outputfile = unfail!(NamedTempFile::new());
+    let timestamp = timestamp.as_secs_f64().to_string();
+
+    let converter = Command::new("ffmpeg")
+        .args([
+            OsStr::new("-y"),
+            OsStr::new("-loglevel"),
+            OsStr::new("error"),
+            OsStr::new("-ss"),
+            OsStr::new(&timestamp),
+            OsStr::new("-i"),
+            inputfile.as_ref(),
+            OsStr::new("-map"),
+            OsStr::new("0:v:0"),
+            OsStr::new("-frames:v"),
+            OsStr::new("1"),
+            OsStr::new("-c:v"),
+            OsStr::new("png"),
+            OsStr::new("-f"),
+            OsStr::new("image2"),
+            outputfile.path().as_os_str(),
+        ])
+        .spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
     let converter_result = unfail!(converter_result);
     if !converter_result.success() {
         return Err("Converter returned an error.".to_string());
@@ -1100,5 +2476,1061 @@ pub fn amen_break_media(
     let mut output = Vec::new();
     unfail!(outputfile.read_to_end(&mut output));
 
+    if output.is_empty() {
+        return Err("Got no frame at this timestamp.".to_string());
+    }
+
     Ok(output)
 }
+
+/// Makes a contact sheet of `columns` by `rows` evenly spaced frames of a video
+/// that's `length` long, each with its timestamp written in the corner, as a JPEG.
+///
+/// The sheet is no bigger than [`MAX_OUTPUT_MEDIA_DIMENSION_SIZE`] on either side.
+pub fn frames_contact_sheet(
+    status_report: Sender<String>,
+    inputfile: &Path,
+    (columns, rows): (u8, u8),
+    length: Duration,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let (columns, rows) = (columns as usize, rows as usize);
+    let count = columns * rows;
+
+    let mut sheet = MagickWand::new();
+    let mut drawing = DrawingWand::new();
+    let mut cell_size = (0, 0);
+    let mut font_size = 0.0;
+
+    for i in 0..count {
+        let _ = status_report.send(format!("Grabbing frame {} of {}...", i + 1, count));
+
+        // Take each frame from the middle of its slice of the video,
+        // so that the last one is never past the end.
+        let timestamp = length.mul_f64((i as f64 + 0.5) / count as f64);
+        let frame = extract_frame(inputfile, timestamp, deadline)?;
+
+        let wand = MagickWand::new();
+        unfail!(wand.read_image_blob(&frame));
+
+        // The first frame decides the size of every cell and the style of the text.
+        if i == 0 {
+            let (width, height) = (wand.get_image_width(), wand.get_image_height());
+            let max = MAX_OUTPUT_MEDIA_DIMENSION_SIZE as f64;
+            let scale = (max / (width * columns) as f64)
+                .min(max / (height * rows) as f64)
+                .min(1.0);
+            cell_size = (
+                ((width as f64 * scale) as usize).max(1),
+                ((height as f64 * scale) as usize).max(1),
+            );
+
+            let mut background = PixelWand::new();
+            unfail!(background.set_color("black"));
+            unfail!(sheet.new_image(cell_size.0 * columns, cell_size.1 * rows, &background));
+
+            let mut text_color = PixelWand::new();
+            unfail!(text_color.set_color("white"));
+            let mut under_color = PixelWand::new();
+            unfail!(under_color.set_color("#00000099"));
+            drawing.set_fill_color(&text_color);
+            drawing.set_text_under_color(&under_color);
+            font_size = (cell_size.1 as f64 / 10.0).max(10.0);
+            drawing.set_font_size(font_size);
+        }
+
+        unfail!(wand.thumbnail_image(cell_size.0, cell_size.1));
+
+        let (x, y) = ((i % columns) * cell_size.0, (i / columns) * cell_size.1);
+        unfail!(sheet.compose_images(
+            &wand,
+            CompositeOperator::Over,
+            false,
+            x as isize,
+            y as isize
+        ));
+
+        unfail!(sheet.annotate_image(
+            &drawing,
+            x as f64 + font_size / 4.0,
+            y as f64 + font_size,
+            0.0,
+            &format_timestamp(timestamp),
+        ));
+    }
+
+    let _ = status_report.send("Encoding...".to_string());
+
+    unfail!(sheet.set_image_compression_quality(90));
+    Ok(unfail!(sheet.write_image_blob("jpeg")))
+}
+
+/// Framerate of joined videos if none of them says what theirs is.
+const CONCAT_DEFAULT_FRAMERATE: f64 = 30.0;
+/// Joined videos don't get a higher framerate than this, even if some of them have one.
+const CONCAT_MAX_FRAMERATE: f64 = 60.0;
+
+/// Size of the video that videos of these sizes are joined into: the biggest width and the
+/// biggest height among them, scaled down to fit [`MAX_OUTPUT_MEDIA_DIMENSION_SIZE`], and
+/// rounded up to even numbers, as that's what yuv420p needs.
+fn concat_canvas(sizes: &[(u32, u32)]) -> (u32, u32) {
+    let width = sizes.iter().map(|x| x.0).max().unwrap_or(1).max(1) as f64;
+    let height = sizes.iter().map(|x| x.1).max().unwrap_or(1).max(1) as f64;
+
+    let max = MAX_OUTPUT_MEDIA_DIMENSION_SIZE as f64;
+    let scale = (max / width).min(max / height).min(1.0);
+
+    let even = |x: f64| ((x * scale).round() as u32).max(1).div_ceil(2) * 2;
+    (even(width), even(height))
+}
+
+/// ffmpeg filter graph that joins inputs one after another into `[v]`, and `[a]` if any of
+/// them has audio. Each is scaled to fit `canvas` and padded to it with black.
+///
+/// `inputs` says if each input has audio, and how long it is, to fill it with silence if not.
+fn concat_filter(canvas: (u32, u32), framerate: f64, inputs: &[(bool, Duration)]) -> String {
+    use std::fmt::Write;
+
+    let (width, height) = canvas;
+    let with_audio = inputs.iter().any(|(has_audio, _)| *has_audio);
+
+    let mut filter = String::new();
+    let mut segments = String::new();
+    for (index, (has_audio, length)) in inputs.iter().enumerate() {
+        let _ = write!(
+            filter,
+            concat!(
+                "[{i}:v:0]scale={w}:{h}:force_original_aspect_ratio=decrease,",
+                "pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={r},format=yuv420p[v{i}];"
+            ),
+            i = index,
+            w = width,
+            h = height,
+            r = framerate
+        );
+        let _ = write!(segments, "[v{}]", index);
+
+        if !with_audio {
+            continue;
+        }
+        if *has_audio {
+            let _ = write!(filter, "[{}:a:0]", index);
+        } else {
+            let _ = write!(
+                filter,
+                "anullsrc=r=48000:cl=stereo,atrim=duration={},",
+                length.as_secs_f64()
+            );
+        }
+        let _ = write!(
+            filter,
+            "aresample=48000,aformat=sample_fmts=fltp:channel_layouts=stereo[a{}];",
+            index
+        );
+        let _ = write!(segments, "[a{}]", index);
+    }
+
+    let _ = write!(
+        filter,
+        "{}concat=n={}:v=1:a={}[v]",
+        segments,
+        inputs.len(),
+        u8::from(with_audio)
+    );
+    if with_audio {
+        filter.push_str("[a]");
+    }
+    filter
+}
+
+/// Joins videos one after another into one MP4. `inputs` are paths to the videos
+/// and their sizes. See [`concat_canvas`] and [`concat_filter`] for how videos of
+/// different sizes are joined.
+pub fn concat_videos(
+    status_report: Sender<String>,
+    inputs: &[(PathBuf, (u32, u32))],
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let _ = status_report.send("Creating temp files...".to_string());
+    let mut outputfile = unfail!(NamedTempFile::new());
+
+    let _ = status_report.send("Checking videos...".to_string());
+    let mut framerate: Option<f64> = None;
+    let mut audio = Vec::with_capacity(inputs.len());
+    for (path, _) in inputs {
+        let (_frame_count, frame_rate, has_audio, length) = unfail!(
+            count_video_frames_and_framerate_and_audio_and_length(path, true, deadline)
+        );
+        if frame_rate.is_finite() && frame_rate > 0.0 {
+            framerate = Some(framerate.unwrap_or(0.0).max(frame_rate));
+        }
+        audio.push((has_audio, length));
+    }
+    let framerate = framerate
+        .unwrap_or(CONCAT_DEFAULT_FRAMERATE)
+        .min(CONCAT_MAX_FRAMERATE);
+
+    let sizes: Vec<(u32, u32)> = inputs.iter().map(|(_, size)| *size).collect();
+    let filter = concat_filter(concat_canvas(&sizes), framerate, &audio);
+    let with_audio = audio.iter().any(|(has_audio, _)| *has_audio);
+
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+    ];
+    for (path, _) in inputs {
+        args.extend_from_slice(&[OsStr::new("-i"), path.as_os_str()]);
+    }
+    args.extend_from_slice(&[
+        OsStr::new("-filter_complex"),
+        OsStr::new(&filter),
+        OsStr::new("-map"),
+        OsStr::new("[v]"),
+    ]);
+    if with_audio {
+        args.extend_from_slice(&[
+            OsStr::new("-map"),
+            OsStr::new("[a]"),
+            OsStr::new("-c:a"),
+            OsStr::new("aac"),
+        ]);
+    }
+    args.extend_from_slice(&[
+        OsStr::new("-pix_fmt"),
+        OsStr::new("yuv420p"),
+        OsStr::new("-f"),
+        OsStr::new("mp4"),
+        OsStr::new("-movflags"),
+        OsStr::new("+faststart"),
+        outputfile.path().as_os_str(),
+    ]);
+
+    let _ = status_report.send("Joining videos...".to_string());
+
+    let converter = Command::new("ffmpeg").args(args).spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+/// Makes the audio of a video or an audio file louder by `gain_db` decibels,
+/// or normalizes its loudness with the `loudnorm` filter if it's [`None`].
+///
+/// Videos are output as MP4 with the video stream copied as is, and must have audio.
+/// Audio is output as OGG Opus if `as_voice` is true, suitable to send as a voice message,
+/// or MP3 otherwise.
+pub fn boost_audio(
+    status_report: Sender<String>,
+    inputfile: &Path,
+    gain_db: Option<f64>,
+    is_sound: bool,
+    as_voice: bool,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let _ = status_report.send("Creating temp files...".to_string());
+    let mut outputfile = unfail!(NamedTempFile::new());
+
+    let filter = match gain_db {
+        Some(gain_db) => format!("volume={}dB", gain_db),
+        None => "loudnorm".to_string(),
+    };
+
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+        OsStr::new("-i"),
+        inputfile.as_ref(),
+    ];
+
+    if !is_sound {
+        args.extend_from_slice(&[OsStr::new("-map"), OsStr::new("0:v:0")]);
+    }
+
+    args.extend_from_slice(&[
+        OsStr::new("-map"),
+        OsStr::new("0:a:0"),
+        OsStr::new("-af"),
+        OsStr::new(&filter),
+    ]);
+
+    // `loudnorm` upsamples to 192kHz, which is way more than anything needs.
+    if gain_db.is_none() {
+        args.extend_from_slice(&[OsStr::new("-ar"), OsStr::new("48000")]);
+    }
+
+    if !is_sound {
+        args.extend_from_slice(&[
+            OsStr::new("-c:v"),
+            OsStr::new("copy"),
+            OsStr::new("-f"),
+            OsStr::new("mp4"),
+            OsStr::new("-movflags"),
+            OsStr::new("+faststart"),
+        ]);
+    } else if as_voice {
+        args.extend_from_slice(&[
+            OsStr::new("-c:a"),
+            OsStr::new("libopus"),
+            OsStr::new("-f"),
+            OsStr::new("ogg"),
+        ]);
+    } else {
+        args.extend_from_slice(&[
+            OsStr::new("-c:a"),
+            OsStr::new("libmp3lame"),
+            OsStr::new("-q:a"),
+            OsStr::new("2"),
+            OsStr::new("-f"),
+            OsStr::new("mp3"),
+        ]);
+    }
+
+    args.push(outputfile.path().as_os_str());
+
+    let _ = status_report.send("Boosting audio...".to_string());
+
+    let converter = Command::new("ffmpeg").args(args).spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+/// Applies vibrato and a speed change to an audio file, with its pitch changed as per `pitch`.
+///
+/// Outputs OGG Opus if `as_voice` is true, suitable to send as a voice message,
+/// or MP3 otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn distort_audio(
+    status_report: Sender<String>,
+    inputfile: &Path,
+    vibrato_hz: f64,
+    vibrato_depth: f64,
+    speed: f64,
+    pitch: PitchMode,
+    as_voice: bool,
+    deadline: Instant,
+) -> Result<Vec<u8>, String> {
+    macro_rules! unfail {
+        ($thing: expr) => {
+            match $thing {
+                Ok(o) => o,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+    }
+
+    let _ = status_report.send("Creating temp files...".to_string());
+    let mut outputfile = unfail!(NamedTempFile::new());
+
+    let mut filter = vibrato_filter(vibrato_hz, vibrato_depth).unwrap_or_default();
+
+    if let Some(speed_filter) = speed_filter(speed, pitch) {
+        filter.push_str(&speed_filter);
+    }
+
+    // Each filter above leaves a trailing comma, which ffmpeg doesn't like.
+    let filter = filter.trim_end_matches(',');
+
+    let mut args = vec![
+        OsStr::new("-y"),
+        OsStr::new("-loglevel"),
+        OsStr::new("error"),
+        OsStr::new("-i"),
+        inputfile.as_ref(),
+        OsStr::new("-map"),
+        OsStr::new("0:a:0"),
+    ];
+
+    if !filter.is_empty() {
+        args.push(OsStr::new("-af"));
+        args.push(OsStr::new(filter));
+    }
+
+    if as_voice {
+        args.extend_from_slice(&[
+            OsStr::new("-c:a"),
+            OsStr::new("libopus"),
+            OsStr::new("-f"),
+            OsStr::new("ogg"),
+        ]);
+    } else {
+        args.extend_from_slice(&[
+            OsStr::new("-c:a"),
+            OsStr::new("libmp3lame"),
+            OsStr::new("-q:a"),
+            OsStr::new("2"),
+            OsStr::new("-f"),
+            OsStr::new("mp3"),
+        ]);
+    }
+
+    args.push(outputfile.path().as_os_str());
+
+    let _ = status_report.send("Distorting audio...".to_string());
+
+    let converter = Command::new("ffmpeg").args(args).spawn();
+
+    let converter_result = wait_until(&mut unfail!(converter), deadline);
+    let converter_result = unfail!(converter_result);
+    if !converter_result.success() {
+        return Err("Converter returned an error.".to_string());
+    }
+
+    unfail!(outputfile.reopen());
+
+    let mut output = Vec::new();
+    unfail!(outputfile.read_to_end(&mut output));
+
+    Ok(output)
+}
+
+#[test]
+fn raw_frames_are_identical_to_bmp_frames() {
+    magick_rust::magick_wand_genesis();
+
+    // Make up a small gradient image to work on.
+    let (w, h) = (64usize, 48usize);
+    let mut pixels = Vec::with_capacity(w * h * 3);
+    for y in 0..h {
+        for x in 0..w {
+            pixels.extend_from_slice(&[(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8]);
+        }
+    }
+    let mut wand = MagickWand::new();
+    wand.new_image(w, h, &PixelWand::new()).unwrap();
+    wand.import_image_pixels(0, 0, w, h, &pixels, "RGB")
+        .unwrap();
+    let bmp = wand.write_image_blob("BMP").unwrap();
+
+    let quality = NonZeroU8::new(100).unwrap();
+
+    for (width, height, rotation, resize_type) in [
+        (32isize, 24isize, 0.0, ResizeType::Stretch),
+        (-50, 70, 30.0, ResizeType::Crop),
+        (80, 60, 0.0, ResizeType::default_seam_carve()),
+    ] {
+        let output_size = (
+            width.unsigned_abs() + width.unsigned_abs() % 2,
+            height.unsigned_abs() + height.unsigned_abs() % 2,
+        );
+
+        let via_bmp = resize_image(
+            &bmp,
+            width,
+            height,
+            rotation,
+            resize_type,
+            ImageFormat::Bmp,
+            Some((output_size.0, output_size.1, false)),
+            false,
+            quality,
+            ColorAdjustments::default(),
+            ChromaSubsampling::Auto,
+            true,
+        )
+        .unwrap();
+        let via_bmp = image_to_raw_bgr(&via_bmp, output_size).unwrap();
+
+        let via_raw = resize_image_to_raw_bgr(
+            &bmp,
+            width,
+            height,
+            rotation,
+            resize_type,
+            ImageFormat::Bmp,
+            Some((output_size.0, output_size.1, false)),
+            false,
+            quality,
+            ColorAdjustments::default(),
+            output_size,
+        )
+        .unwrap();
+
+        assert_eq!(via_bmp, via_raw);
+    }
+}
+
+/// Make up a small BMP of a gradient to test things on.
+#[cfg(test)]
+fn gradient_bmp(width: usize, height: usize) -> Vec<u8> {
+    magick_rust::magick_wand_genesis();
+
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.extend_from_slice(&[(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8]);
+        }
+    }
+    let mut wand = MagickWand::new();
+    wand.new_image(width, height, &PixelWand::new()).unwrap();
+    wand.import_image_pixels(0, 0, width, height, &pixels, "RGB")
+        .unwrap();
+    wand.write_image_blob("BMP").unwrap()
+}
+
+/// Resize a gradient made by [`gradient_bmp`] with this resize type and get raw BGR pixels
+/// of the result, along with the pixels of the original.
+#[cfg(test)]
+fn gradient_effect((width, height): (isize, isize), resize_type: ResizeType) -> (Vec<u8>, Vec<u8>) {
+    let size = (width.unsigned_abs(), height.unsigned_abs());
+    let bmp = gradient_bmp(size.0, size.1);
+
+    let result = resize_image_to_raw_bgr(
+        &bmp,
+        width,
+        height,
+        0.0,
+        resize_type,
+        ImageFormat::Bmp,
+        None,
+        false,
+        NonZeroU8::new(100).unwrap(),
+        ColorAdjustments::default(),
+        size,
+    )
+    .unwrap();
+
+    (image_to_raw_bgr(&bmp, size).unwrap(), result)
+}
+
+#[test]
+fn metadata_is_stripped_unless_kept() {
+    const COMMENT: &str = "Taken at 55.7558, 37.6173";
+
+    let wand = MagickWand::new();
+    wand.read_image_blob(gradient_bmp(32, 24)).unwrap();
+    wand.set_image_property("comment", COMMENT).unwrap();
+    let jpeg = wand.write_image_blob("jpg").unwrap();
+    let has_comment = |data: &[u8]| data.windows(COMMENT.len()).any(|x| x == COMMENT.as_bytes());
+    assert!(has_comment(&jpeg));
+
+    // Both with and without compression, as those write the image in different places.
+    for quality in [100, 50] {
+        for keep_metadata in [false, true] {
+            let result = resize_image(
+                &jpeg,
+                32,
+                24,
+                0.0,
+                ResizeType::Stretch,
+                ImageFormat::Jpeg,
+                None,
+                false,
+                NonZeroU8::new(quality).unwrap(),
+                ColorAdjustments::default(),
+                ChromaSubsampling::Auto,
+                keep_metadata,
+            )
+            .unwrap();
+            assert_eq!(
+                has_comment(&result),
+                keep_metadata,
+                "quality: {}, keep_metadata: {}",
+                quality,
+                keep_metadata
+            );
+        }
+    }
+}
+
+#[test]
+fn mirror_test() {
+    let (width, height) = (40, 30);
+    let pixel = |data: &[u8], x: usize, y: usize| data[(y * width + x) * 3..][..3].to_vec();
+    let mirror = ResizeType::Mirror {
+        horizontal: true,
+        vertical: true,
+    };
+
+    let (original, result) = gradient_effect((-(width as isize), height as isize), mirror);
+    assert_ne!(original, result);
+    for (x, y) in [(0, 0), (5, 7), (39, 29)] {
+        assert_eq!(pixel(&result, x, y), pixel(&original, width - 1 - x, y));
+    }
+
+    let (original, result) = gradient_effect((width as isize, -(height as isize)), mirror);
+    for (x, y) in [(0, 0), (5, 7), (39, 29)] {
+        assert_eq!(pixel(&result, x, y), pixel(&original, x, height - 1 - y));
+    }
+}
+
+#[test]
+fn negate_test() {
+    let size = (40, 30);
+    let bmp = gradient_bmp(size.0, size.1);
+
+    let original = image_to_raw_bgr(&bmp, size).unwrap();
+    let negated = negate_image(&bmp, false, ImageFormat::Bmp).unwrap();
+    let negated = image_to_raw_bgr(&negated, size).unwrap();
+
+    assert_eq!(original.len(), negated.len());
+    for (original, negated) in original.iter().zip(&negated) {
+        assert_eq!(*negated, 255 - original);
+    }
+}
+
+#[test]
+fn kaleidoscope_test() {
+    let (width, height) = (40, 30);
+    let pixel = |data: &[u8], x: usize, y: usize| data[(y * width + x) * 3..][..3].to_vec();
+    let hash = |data: &[u8]| {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        data.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    for segments in [2, 4, 6, 8] {
+        let (original, result) = gradient_effect(
+            (width as isize, height as isize),
+            ResizeType::Kaleidoscope { segments },
+        );
+        assert_ne!(hash(&original), hash(&result), "segments: {}", segments);
+
+        // The wedge the rest is made from is left as is.
+        assert_eq!(
+            pixel(&result, width - 2, height / 2 + 1),
+            pixel(&original, width - 2, height / 2 + 1),
+            "segments: {}",
+            segments
+        );
+
+        // Every segment count here has the vertical halves mirrored.
+        for (x, y) in [(0, 0), (7, 3), (25, 10)] {
+            assert_eq!(
+                pixel(&result, x, y),
+                pixel(&result, x, height - 1 - y),
+                "segments: {}",
+                segments
+            );
+        }
+    }
+
+    // Quadrants are mirrored exactly, and doing it again changes nothing.
+    let (_, result) = gradient_effect(
+        (width as isize, height as isize),
+        ResizeType::Kaleidoscope { segments: 4 },
+    );
+    for (x, y) in [(0, 0), (7, 3), (25, 10)] {
+        assert_eq!(pixel(&result, x, y), pixel(&result, width - 1 - x, y));
+    }
+    let mut wand = MagickWand::new();
+    wand.new_image(width, height, &PixelWand::new()).unwrap();
+    wand.import_image_pixels(0, 0, width, height, &result, "BGR")
+        .unwrap();
+    kaleidoscope(&mut wand, 4).unwrap();
+    let again = wand
+        .export_image_pixels(0, 0, width, height, "BGR")
+        .unwrap();
+    assert_eq!(hash(&again), hash(&result));
+}
+
+#[test]
+fn absurd_image_headers_are_rejected() {
+    let too_big = |data: &[u8]| {
+        check_input_image_size(data)
+            .unwrap_err()
+            .0
+            .starts_with(INPUT_IMAGE_TOO_BIG_ERROR)
+    };
+
+    // A PNG signature and an IHDR claiming 30000x30000, with no actual pixel data.
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend(30000u32.to_be_bytes());
+    png.extend(30000u32.to_be_bytes());
+    png.extend([8, 6, 0, 0, 0]);
+    assert_eq!(sniff_image_dimensions(&png), Some((30000, 30000)));
+    assert!(too_big(&png));
+    assert_eq!(
+        check_input_image_size(&png).unwrap_err().0,
+        "input image is too big: 30000x30000"
+    );
+
+    // A JPEG with an APP0 segment, then a start of frame claiming 65535x65535.
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00];
+    jpeg.extend([0xff, 0xc0, 0x00, 0x11, 0x08, 0xff, 0xff, 0xff, 0xff, 0x03]);
+    assert_eq!(sniff_image_dimensions(&jpeg), Some((65535, 65535)));
+    assert!(too_big(&jpeg));
+
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend([0xff, 0xff, 0xff, 0xff]);
+    assert!(too_big(&gif));
+
+    let mut webp = b"RIFF\0\0\0\0WEBPVP8X\0\0\0\0\0\0\0\0".to_vec();
+    webp.extend([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(sniff_image_dimensions(&webp), Some((1 << 24, 1 << 24)));
+    assert!(too_big(&webp));
+
+    let mut bmp = b"BM".to_vec();
+    bmp.resize(18, 0);
+    bmp.extend(100000u32.to_le_bytes());
+    bmp.extend((-100000i32).to_le_bytes());
+    assert_eq!(sniff_image_dimensions(&bmp), Some((100000, 100000)));
+    assert!(too_big(&bmp));
+
+    // Reasonable sizes, truncated headers and unknown formats pass through.
+    let mut small = png[..16].to_vec();
+    small.extend(1000u32.to_be_bytes());
+    small.extend(1000u32.to_be_bytes());
+    assert_eq!(sniff_image_dimensions(&small), Some((1000, 1000)));
+    assert!(check_input_image_size(&small).is_ok());
+    assert!(check_input_image_size(&png[..18]).is_ok());
+    assert!(check_input_image_size(&jpeg[..12]).is_ok());
+    assert!(check_input_image_size(b"BM").is_ok());
+    assert!(check_input_image_size(b"hello").is_ok());
+    assert!(check_input_image_size(&[]).is_ok());
+}
+
+#[test]
+fn magick_resource_limits_test() {
+    assert_eq!(
+        magick_resource_limits(1024),
+        [
+            (ResourceType::Memory, 1 << 30),
+            (ResourceType::Map, 2 << 30),
+            (ResourceType::Disk, 4 << 30),
+            (ResourceType::Area, MAX_INPUT_IMAGE_PIXELS),
+        ]
+    );
+    assert_eq!(
+        magick_resource_limits(0)[0],
+        (ResourceType::Memory, 1 << 20)
+    );
+    assert_eq!(
+        magick_resource_limits(u64::MAX / 1024),
+        [
+            (ResourceType::Memory, u64::MAX),
+            (ResourceType::Map, u64::MAX),
+            (ResourceType::Disk, u64::MAX),
+            (ResourceType::Area, MAX_INPUT_IMAGE_PIXELS),
+        ]
+    );
+}
+
+#[test]
+fn resizing_decompression_bombs_fails() {
+    // A tiny PNG that claims to be 100000x100000. It shouldn't even get to ImageMagick.
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend(100000u32.to_be_bytes());
+    png.extend(100000u32.to_be_bytes());
+    png.extend([8, 6, 0, 0, 0]);
+
+    let result = resize_image(
+        &png,
+        100,
+        100,
+        0.0,
+        ResizeType::Fit,
+        ImageFormat::Png,
+        None,
+        false,
+        NonZeroU8::new(92).unwrap(),
+        ColorAdjustments::default(),
+        ChromaSubsampling::default(),
+        false,
+    );
+    assert_eq!(
+        result.unwrap_err().0,
+        "input image is too big: 100000x100000"
+    );
+}
+
+#[test]
+fn tgs_detection() {
+    assert!(looks_like_tgs(&[0x1f, 0x8b, 0x08, 0x00]));
+    assert!(!looks_like_tgs(b"{\"v\":\"5.5.2\"}"));
+    assert!(!looks_like_tgs(b"RIFF"));
+    assert!(!looks_like_tgs(&[]));
+}
+
+#[test]
+fn probing_external_tools() {
+    assert!(runs_successfully("true", &[]));
+    assert!(!runs_successfully("false", &[]));
+    assert!(!runs_successfully("teco_tools_bot_amogus", &["--version"]));
+
+    // Whatever's found is the same as running it by hand.
+    for tool in ExternalTool::ALL {
+        let runs = Command::new(tool.program())
+            .args(tool.version_args())
+            .output()
+            .is_ok_and(|x| x.status.success());
+        assert_eq!(tool.probe(), runs, "{}", tool.program());
+    }
+}
+
+#[test]
+fn rendering_animated_stickers() {
+    let deadline = Instant::now() + Duration::from_secs(60);
+
+    // Anything else is refused before the renderer is even run.
+    let mut not_tgs = NamedTempFile::new().unwrap();
+    not_tgs.write_all(b"{\"v\":\"5.5.2\"}").unwrap();
+    assert_eq!(
+        render_animated_sticker(not_tgs.path(), deadline).unwrap_err(),
+        "not an animated sticker"
+    );
+
+    // Without lottie_convert.py, there's nothing else to test.
+    if !find_animated_sticker_renderer() {
+        return;
+    }
+
+    // Half a second of a red square, at 60 FPS.
+    let lottie = serde_json::json!({
+        "v": "5.5.2", "fr": 60, "ip": 0, "op": 30, "w": 64, "h": 64,
+        "layers": [{
+            "ty": 4, "ip": 0, "op": 30, "st": 0,
+            "ks": {
+                "o": { "a": 0, "k": 100 },
+                "r": { "a": 0, "k": 0 },
+                "p": { "a": 0, "k": [32, 32, 0] },
+                "a": { "a": 0, "k": [0, 0, 0] },
+                "s": { "a": 0, "k": [100, 100, 100] },
+            },
+            "shapes": [
+                { "ty": "rc", "p": { "a": 0, "k": [0, 0] }, "s": { "a": 0, "k": [40, 40] },
+                  "r": { "a": 0, "k": 0 } },
+                { "ty": "fl", "c": { "a": 0, "k": [1, 0, 0, 1] }, "o": { "a": 0, "k": 100 } },
+            ],
+        }],
+    });
+    let mut gzip = Command::new("gzip")
+        .arg("-c")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    gzip.stdin
+        .take()
+        .unwrap()
+        .write_all(lottie.to_string().as_bytes())
+        .unwrap();
+    let tgs = gzip.wait_with_output().unwrap().stdout;
+    let mut sticker = NamedTempFile::new().unwrap();
+    sticker.write_all(&tgs).unwrap();
+
+    let rendered = render_animated_sticker(sticker.path(), deadline).unwrap();
+    let (frames, framerate, _, _) =
+        count_video_frames_and_framerate_and_audio_and_length(rendered.path(), false, deadline)
+            .unwrap();
+    assert!(frames > 1);
+    assert!(framerate <= MAX_ANIMATED_STICKER_FRAMERATE * 1.15);
+}
+
+#[test]
+fn fix_misread_pipes_test() {
+    assert_eq!(fix_misread_pipes("sta|e"), "staIe");
+    assert_eq!(fix_misread_pipes("M|SS|SS|PP|"), "MISSISSIPP|");
+    assert_eq!(fix_misread_pipes("a | b"), "a | b");
+    assert_eq!(fix_misread_pipes("|a|b|"), "|aIb|");
+    assert_eq!(fix_misread_pipes("x||y"), "x||y");
+    assert_eq!(fix_misread_pipes("1|2"), "1|2");
+    assert_eq!(fix_misread_pipes("пр|вет"), "прIвет");
+}
+
+#[test]
+fn pitch_shift_filter_test() {
+    assert_eq!(pitch_shift_filter(1.0), None);
+    assert_eq!(
+        pitch_shift_filter(1.5).as_deref(),
+        Some("aresample=48000,asetrate=72000,aresample=48000,")
+    );
+    assert_eq!(
+        speed_filter(0.5, PitchMode::Shift).as_deref(),
+        Some("aresample=48000,asetrate=24000,aresample=48000,")
+    );
+    assert_eq!(
+        speed_filter(0.5, PitchMode::Preserve).as_deref(),
+        Some("atempo=0.5,")
+    );
+}
+
+#[test]
+fn atempo_filter_test() {
+    assert_eq!(atempo_filter(1.0), None);
+    assert_eq!(atempo_filter(f64::NAN), None);
+    assert_eq!(atempo_filter(1.5).as_deref(), Some("atempo=1.5,"));
+    assert_eq!(
+        atempo_filter(10.0).as_deref(),
+        Some("atempo=2,atempo=2,atempo=2,atempo=1.25,")
+    );
+    assert_eq!(
+        atempo_filter(0.1).as_deref(),
+        Some("atempo=0.5,atempo=0.5,atempo=0.5,atempo=0.8,")
+    );
+}
+
+#[test]
+fn split_into_bmps_resync_test() {
+    // Not real images, but the splitter only cares about the header.
+    let make_bmp = |length: u32, fill: u8| {
+        let mut bmp = vec![0x42, 0x4D];
+        bmp.extend_from_slice(&length.to_le_bytes());
+        bmp.resize(length as usize, fill);
+        bmp
+    };
+    let first = make_bmp(32, 1);
+    let second = make_bmp(40, 2);
+
+    let mut stream = first.clone();
+    stream.extend_from_slice(b"\0\0garbageB\xffM");
+    stream.extend_from_slice(&second);
+
+    let bmps = SplitIntoBmps::new(stream.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(bmps, [first.clone(), second]);
+
+    // Too much garbage is still an error.
+    let mut stream = first.clone();
+    stream.resize(first.len() + BMP_RESYNC_WINDOW + 6, 0);
+    stream.extend_from_slice(&first);
+    let mut split = SplitIntoBmps::new(stream.as_slice());
+    assert!(split.next().unwrap().is_ok());
+    assert!(split.next().unwrap().is_err());
+
+    // So is a header claiming the BMP is huge.
+    let mut stream = first.clone();
+    stream.extend_from_slice(&[0x42, 0x4D]);
+    stream.extend_from_slice(&u32::MAX.to_le_bytes());
+    let mut split = SplitIntoBmps::new(stream.as_slice());
+    assert!(split.next().unwrap().is_ok());
+    assert!(split.next().unwrap().is_err());
+}
+
+#[test]
+fn wait_until_kills_on_deadline() {
+    let mut quick = Command::new("true").spawn().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    assert!(wait_until(&mut quick, deadline).unwrap().success());
+
+    let mut stuck = Command::new("sleep").arg("10").spawn().unwrap();
+    let started = Instant::now();
+    let error = wait_until(&mut stuck, started + Duration::from_millis(100)).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // It should have been killed and reaped.
+    assert!(stuck.try_wait().unwrap().is_some());
+}
+
+#[test]
+fn amen_break_index_is_seeded() {
+    for seed in [0, 1, 1234, u64::MAX] {
+        let index = amen_break_index(50, seed);
+        assert!(index < 50);
+        assert_eq!(amen_break_index(50, seed), index, "seed: {}", seed);
+    }
+
+    // Not every seed should pick the same break.
+    let picks: std::collections::HashSet<usize> =
+        (0..100).map(|seed| amen_break_index(50, seed)).collect();
+    assert!(picks.len() > 1);
+}
+
+#[test]
+fn concat_canvas_test() {
+    assert_eq!(concat_canvas(&[(640, 480), (480, 640)]), (640, 640));
+    assert_eq!(concat_canvas(&[(1280, 720), (1280, 720)]), (1280, 720));
+    assert_eq!(concat_canvas(&[(321, 241), (100, 100)]), (322, 242));
+    // Too big gets scaled down, keeping the aspect ratio.
+    assert_eq!(concat_canvas(&[(4096, 1024), (100, 100)]), (2048, 512));
+}
+
+#[test]
+fn concat_filter_test() {
+    let second = Duration::from_secs(1);
+
+    assert_eq!(
+        concat_filter((640, 480), 30.0, &[(false, second), (false, second)]),
+        concat!(
+            "[0:v:0]scale=640:480:force_original_aspect_ratio=decrease,",
+            "pad=640:480:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30,format=yuv420p[v0];",
+            "[1:v:0]scale=640:480:force_original_aspect_ratio=decrease,",
+            "pad=640:480:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30,format=yuv420p[v1];",
+            "[v0][v1]concat=n=2:v=1:a=0[v]"
+        )
+    );
+
+    // A video without audio gets silence of its length if the other one has audio.
+    let filter = concat_filter((2, 2), 25.0, &[(true, second), (false, second * 2)]);
+    assert!(filter.contains("[0:a:0]aresample=48000"));
+    assert!(filter.contains("anullsrc=r=48000:cl=stereo,atrim=duration=2,aresample=48000"));
+    assert!(filter.ends_with("[v0][a0][v1][a1]concat=n=2:v=1:a=1[v][a]"));
+}
+
+#[test]
+fn trim_args_test() {
+    let secs = Duration::from_secs;
+    let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(trim_args(None, secs(10), 100), Ok((Vec::new(), 100)));
+    assert_eq!(
+        trim_args(Some((secs(2), secs(4))), secs(10), 100),
+        Ok((args(&["-ss", "2", "-to", "4"]), 20))
+    );
+    // Past the end is until the end.
+    assert_eq!(
+        trim_args(Some((secs(5), secs(20))), secs(10), 100),
+        Ok((args(&["-ss", "5", "-to", "10"]), 50))
+    );
+    assert_eq!(
+        trim_args(Some((secs(10), secs(20))), secs(10), 100),
+        Err(TRIM_OUT_OF_RANGE_ERROR.to_string())
+    );
+
+    // Unknown lengths aren't checked against.
+    assert_eq!(
+        trim_args(Some((secs(10), secs(20))), Duration::ZERO, 0),
+        Ok((args(&["-ss", "10", "-to", "20"]), 0))
+    );
+}
@@ -1,463 +1,419 @@
 pub mod media_processing;
-use arch_bot_commons::{teloxide_retry, useful_methods::*};
-use html_escape::encode_text;
-use teloxide::{
-    payloads::{SendAnimationSetters, SendPhotoSetters, SendStickerSetters, SendVideoSetters},
-    requests::Requester,
-    types::InputFile,
-    ApiError, Bot, RequestError,
-};
+use std::time::{Duration, Instant};
+
+use arch_bot_commons::useful_methods::MessageMediaInfo;
+use teloxide::{Bot, RequestError};
 use tokio::sync::watch::Sender;
 
 use crate::{
-    tasks::{ResizeCurve, VideoTypePreference},
-    MAX_DOWNLOAD_SIZE_MEGABYTES, MAX_UPLOAD_SIZE_MEGABYTES,
+    strings::Lang, tasks::parsing::MAX_OUTPUT_MEDIA_DIMENSION_SIZE, MAX_DOWNLOAD_SIZE_MEGABYTES,
+};
+
+use super::{
+    taskman::{
+        database::{Database, TaskDatabaseInfo},
+        download_cache::DownloadCache,
+        governor::ResourceGovernor,
+    },
+    Task,
 };
 
-use super::{taskman::database::TaskDatabaseInfo, ImageFormat, Task};
+/// Stop completing the task, telling the user this.
+macro_rules! goodbye {
+    ($text:expr) => {
+        return Err($crate::tasks::completion::context::CompletionError::UserFacing($text.into()))
+    };
+}
+
+mod audio;
+mod context;
+mod negate;
+mod ocr;
+mod palette;
+mod resize;
+mod spoiler;
+mod video;
+
+use context::{CompletionError, CompletionResult, TaskContext};
 
 impl Task {
+    /// Complete this task, talking to the user in `lang`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn complete_task(
         &self,
         status_report: Sender<String>,
         bot: &Bot,
+        governor: &ResourceGovernor,
+        download_cache: &DownloadCache,
+        task_timeout: Duration,
         data: &TaskDatabaseInfo,
+        db: &Database,
+        lang: Lang,
     ) -> Result<(), RequestError> {
-        macro_rules! respond {
-            ($text:expr) => {
-                bot.archsendmsg(data.message.chat.id, $text, data.message.id)
-                    .await?;
-            };
-        }
-
-        macro_rules! goodbye {
-            ($text:expr) => {{
-                respond!($text);
-                return Ok(());
-            }};
+        let ctx = TaskContext {
+            task: self,
+            bot,
+            status_report,
+            governor,
+            download_cache,
+            task_timeout,
+            data,
+            db,
+            lang,
+        };
+
+        let result = match self {
+            Task::Amogus { amogus } => amogus_response(*amogus),
+            Task::ImageResize { .. } | Task::VideoResize { .. } => resize::complete(&ctx).await,
+            Task::Ocr => ocr::complete(&ctx).await,
+            Task::Palette { count } => palette::complete(&ctx, *count).await,
+            Task::AmenBreak { seed } => video::amen_break(&ctx, *seed).await,
+            Task::DistortAudio {
+                vibrato_hz,
+                vibrato_depth,
+                speed,
+            } => audio::distort(&ctx, *vibrato_hz, *vibrato_depth, *speed).await,
+            Task::SpeedChange { factor, pitch } => video::change_speed(&ctx, *factor, *pitch).await,
+            Task::Trim {
+                start,
+                end,
+                precise,
+            } => video::trim(&ctx, *start, *end, *precise).await,
+            Task::Boost { gain_db } => audio::boost(&ctx, *gain_db).await,
+            Task::Frame { timestamp, grid } => video::frame(&ctx, *timestamp, *grid).await,
+            Task::Negate { alpha } => negate::complete(&ctx, *alpha).await,
+            Task::Spoiler { caption } => spoiler::complete(&ctx, caption).await,
+            Task::Concat { clips } => video::concat(&ctx, clips).await,
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(CompletionError::UserFacing(text)) => ctx.respond(&text).await,
+            Err(CompletionError::Request(e)) => Err(e),
         }
+    }
 
-        #[allow(unused_macros)]
-        macro_rules! unfail_any {
-            ($thing:expr) => {{
-                match $thing {
-                    Ok(woot) => woot,
-                    Err(e) => {
-                        return Err(RequestError::Api(ApiError::Unknown(format!(
-                            "NOT TELEGRAM ERROR BUT: {:#?}",
-                            e
-                        ))))
-                    }
-                };
-            }};
-        }
+    /// Roughly estimates how much memory completing this task on the given media
+    /// will take, in bytes. See [`ResourceGovernor`].
+    fn memory_estimate(&self, media: &MessageMediaInfo) -> u64 {
+        // ImageMagick keeps 4 channels of 32-bit floats per pixel,
+        // while BMP frames passed around have 4 bytes per pixel at most.
+        const WAND_BYTES_PER_PIXEL: u64 = 16;
+        const FRAME_BYTES_PER_PIXEL: u64 = 4;
+        // Whatever ffmpeg needs for itself.
+        const FFMPEG_OVERHEAD: u64 = 64 * 1024 * 1024;
 
-        // Little handler for the downloading.
-        macro_rules! unerror_download {
-            ($download: expr) => {{
-                let result = $download;
-                if let Err(RequestError::Api(ApiError::Unknown(text))) = &result {
-                    if text.contains("file is temporarily unavailable") {
-                        goodbye!(concat!(
-                            "Error: the media file is unavailable for the bot. ",
-                            "This is likely a Telegram server issue. ",
-                            "Try reuploading and performing the command again."
-                        ));
-                    }
-                };
-                if let Err(RequestError::Network(_)) = &result {
-                    goodbye!(concat!(
-                        "Error: a networking error while downloading the file. ",
-                        "This is likely a Telegram server issue. ",
-                        "Try waiting some time, or reuploading the media, ",
-                        "and performing the command again."
-                    ));
-                };
-                result?
-            }};
-        }
+        let file_size = media.file.size as u64;
+        let input_pixels = media.width as u64 * media.height as u64;
 
         match self {
-            Task::Amogus { amogus } => {
-                let sign = amogus.signum();
-                let count = amogus.unsigned_abs();
-
-                let mut response;
-
-                let response_str = if count > 16 {
-                    if sign == -1 {
-                        "<b>TOO MUCH ANTIMOGUS</b>"
-                    } else {
-                        "<b>TOO MUCH AMOGUS</b>"
-                    }
+            // Sent back as is, without downloading it.
+            Task::Amogus { .. } | Task::Spoiler { .. } => 0,
+            Task::ImageResize { new_dimensions, .. } | Task::VideoResize { new_dimensions, .. } => {
+                let output_pixels =
+                    new_dimensions.0.unsigned_abs() as u64 * new_dimensions.1.unsigned_abs() as u64;
+                let pixels = input_pixels + output_pixels;
+
+                if media.is_video {
+                    let parallelisms = std::thread::available_parallelism()
+                        .map(|x| x.get() as u64)
+                        .unwrap_or(1);
+                    // A wand in each worker thread, plus frames in the channels between them
+                    // and waiting to be written in order. Then the whole output in memory.
+                    parallelisms * pixels * WAND_BYTES_PER_PIXEL
+                        + parallelisms * 3 * pixels * FRAME_BYTES_PER_PIXEL
+                        + file_size * 4
+                        + FFMPEG_OVERHEAD
                 } else {
-                    response = String::from("<b>");
-
-                    let response_single = match sign {
-                        -1 => "ANTIMOGUS ",
-                        1 => "AMOGUS ",
-                        0 => return Ok(()),
-                        _ => unreachable!(),
-                    };
-
-                    let length: usize = response_single.len() + 4; // for "</b>"
-                    response.reserve_exact(length);
-
-                    for _ in 0..count {
-                        response.push_str(response_single);
-                    }
-
-                    response.push_str("</b>");
-
-                    response.as_str()
-                };
+                    // A wand and a copy of it while processing, plus input and output files.
+                    pixels * WAND_BYTES_PER_PIXEL * 2 + file_size * 2
+                }
+            }
+            Task::Ocr | Task::Palette { .. } => input_pixels * WAND_BYTES_PER_PIXEL * 2 + file_size,
+            Task::Negate { .. } if media.is_video => file_size * 4 + FFMPEG_OVERHEAD,
+            // A wand and its inverted copy.
+            Task::Negate { .. } => input_pixels * WAND_BYTES_PER_PIXEL * 2 + file_size * 2,
+            // ffmpeg working on a file, with the result read whole into memory.
+            Task::AmenBreak { .. }
+            | Task::DistortAudio { .. }
+            | Task::SpeedChange { .. }
+            | Task::Trim { .. }
+            | Task::Boost { .. } => file_size * 4 + FFMPEG_OVERHEAD,
+            // ffmpeg decoding a frame at a time, plus a wand for the frame and the contact sheet.
+            Task::Frame { .. } => {
+                let sheet_pixels =
+                    MAX_OUTPUT_MEDIA_DIMENSION_SIZE as u64 * MAX_OUTPUT_MEDIA_DIMENSION_SIZE as u64;
+                (input_pixels + sheet_pixels) * WAND_BYTES_PER_PIXEL + file_size + FFMPEG_OVERHEAD
+            }
+            // Same as above, but for every video joined.
+            Task::Concat { clips } => {
+                let clips_size: u64 = clips.iter().map(|x| x.file.size as u64).sum();
+                clips_size * 4 + FFMPEG_OVERHEAD
+            }
+        }
+    }
 
-                goodbye!(response_str);
+    /// Checks whether this task can work with the given media at all,
+    /// using only what Telegram tells about it, so that obviously wrong
+    /// inputs can be rejected before downloading anything.
+    ///
+    /// # Errors
+    /// Returns a message to show to the user if the media is not accepted.
+    fn accepts(&self, media: &MessageMediaInfo) -> Result<(), String> {
+        match self {
+            Task::Amogus { .. } => (),
+            Task::ImageResize { .. } => {
+                if !media.is_raster() {
+                    return Err(
+                        "Error: can't work with animated stickers nor voice messages.".to_string(),
+                    );
+                }
+                if media.is_video {
+                    return Err(
+                        "Error: expected an image to resize, but found a video instead."
+                            .to_string(),
+                    );
+                }
             }
-            Task::ImageResize {
-                new_dimensions,
-                rotation,
-                percentage: _,
-                format: _,
-                resize_type,
-                quality,
+            Task::VideoResize { .. } => {
+                let renderable =
+                    media.is_animated_sticker && media_processing::can_render_animated_stickers();
+                if !media.is_raster() && !renderable {
+                    return Err(
+                        "Error: can't work with animated stickers nor voice messages.".to_string(),
+                    );
+                }
+                if !media.is_video && !renderable {
+                    return Err(
+                        "Error: expected a video to resize, but found an image instead."
+                            .to_string(),
+                    );
+                }
             }
-            | Task::VideoResize {
-                new_dimensions,
-                rotation,
-                percentage: _,
-                resize_type,
-                vibrato_hz: _,
-                vibrato_depth: _,
-                resize_curve: _,
-                type_pref: _,
-                quality,
-            } => {
-                let media = data.message.get_media_info();
-                let media = match media {
-                    Some(media) => {
-                        if !media.is_raster() {
-                            goodbye!(
-                                "Error: can't work with animated stickers nor voice messages."
-                            );
-                        }
-                        if media.file.size > MAX_DOWNLOAD_SIZE_MEGABYTES * 1000 * 1000 {
-                            goodbye!(format!(
-                                "Error: media is too large. The limit is {}MB.",
-                                MAX_DOWNLOAD_SIZE_MEGABYTES
-                            )
-                            .as_str());
-                        }
-                        media
-                    }
-                    None => goodbye!("Error: can't find the media.."),
-                };
-                let format = if let Task::ImageResize { format, .. } = self {
-                    if media.is_video {
-                        goodbye!("Error: expected an image to resize, but found a video instead.");
-                    }
-                    if *format == ImageFormat::Preserve {
-                        if media.is_sticker {
-                            ImageFormat::Webp
-                        } else {
-                            ImageFormat::Jpeg
-                        }
-                    } else {
-                        *format
-                    }
-                } else {
-                    if !media.is_video {
-                        goodbye!("Error: expected a video to resize, but found an image instead.");
-                    }
-                    ImageFormat::Preserve
-                };
-
-                let mut media_data: Vec<u8> = Vec::new();
-
-                let input_dimensions = (media.width, media.height);
-
-                let dimensions = (new_dimensions.0 as isize, new_dimensions.1 as isize);
-                let resize_type = *resize_type;
-                let rotation = *rotation;
-                let quality = *quality;
-
-                let (vibrato_hz, vibrato_depth, resize_curve) = if let Task::VideoResize {
-                    vibrato_hz,
-                    vibrato_depth,
-                    resize_curve,
-                    ..
-                } = self
-                {
-                    (*vibrato_hz, *vibrato_depth, *resize_curve)
-                } else {
-                    (7.0, 0.0, ResizeCurve::default())
-                };
-
-                let should_be_gif = if let Task::VideoResize { type_pref, .. } = self {
-                    match type_pref {
-                        VideoTypePreference::Preserve => media.is_gif || media.is_sticker,
-                        VideoTypePreference::Gif => true,
-                        VideoTypePreference::Video => false,
-                    }
-                } else {
-                    // Not a video lol
-                    false
-                };
-
-                let status_report_for_processing = status_report.clone();
-
-                // Variable just to hold the temporary file and drop it later.
-                let mut file = None;
-
-                let _ = status_report.send("Downloading media...".to_string());
-                let woot = if media.is_video {
-                    let download =
-                        unerror_download!(bot.download_file_to_temp_or_directly(media.file).await);
-                    let path = download.0;
-                    file = download.1;
-                    tokio::task::spawn_blocking(move || {
-                        media_processing::resize_video(
-                            status_report_for_processing,
-                            &path,
-                            dimensions,
-                            rotation,
-                            resize_type,
-                            should_be_gif,
-                            vibrato_hz,
-                            vibrato_depth,
-                            input_dimensions,
-                            resize_curve,
-                            quality,
-                        )
-                    })
-                } else {
-                    let download_result =
-                        bot.download_file_to_vec(media.file, &mut media_data).await;
-                    unerror_download!(download_result);
-
-                    tokio::task::spawn_blocking(move || {
-                        media_processing::resize_image(
-                            &media_data,
-                            dimensions.0,
-                            dimensions.1,
-                            rotation,
-                            resize_type,
-                            format,
-                            None,
-                            false,
-                            quality,
-                        )
-                        .map_err(|e| e.to_string())
-                    })
+            Task::Ocr | Task::Palette { .. } => {
+                if !media.is_image() {
+                    return Err(
+                        "Error: can't work with video nor animated nor video stickers.".to_string(),
+                    );
                 }
-                .await
-                .expect("Worker died!");
-
-                drop(file);
-
-                let media_data = match woot {
-                    Ok(m) => m,
-                    Err(e) => {
-                        log::error!("Error when resizing media: {}", e);
-                        goodbye!("Error: failed to process the media");
-                    }
-                };
-
-                if media_data.is_empty() {
-                    goodbye!(
-                        "Error: failed to process the media; got empty file as a result. Sorry!"
+            }
+            Task::AmenBreak { .. } => {
+                if !media.is_raster() {
+                    return Err(
+                        "Error: can't work with animated stickers nor voice messages.".to_string(),
                     );
                 }
-
-                if media_data.len() > MAX_UPLOAD_SIZE_MEGABYTES as usize * 1000 * 1000 {
-                    goodbye!(format!(
-                        "Error: the resulting media is too big ({:.3}MB, max is {}MB). Sorry!",
-                        media_data.len() as f64 / 1000.0 / 100.00,
-                        MAX_UPLOAD_SIZE_MEGABYTES
-                    )
-                    .as_str());
+                if media.is_sound {
+                    return Err("Error: can't work with audio messages.".to_string());
                 }
-
-                let should_be_sticker = !media.is_video && format.supports_alpha_transparency();
-
-                let _ = status_report.send("Uploading result...".to_string());
-
-                teloxide_retry!({
-                    let send = media_data.clone();
-                    let result = if media.is_video {
-                        if should_be_gif {
-                            // Sending as an "animation" requires that the file has a filename, else
-                            // it somehow ends up being a file document instead.
-                            bot.send_animation(
-                                data.message.chat.id,
-                                InputFile::memory(send).file_name("amogus.mp4"),
-                            )
-                            .reply_to_message_id(data.message.id)
-                            .await
-                        } else {
-                            bot.send_video(data.message.chat.id, InputFile::memory(send))
-                                .reply_to_message_id(data.message.id)
-                                .await
-                        }
-                    } else if should_be_sticker {
-                        bot.send_sticker(data.message.chat.id, InputFile::memory(send))
-                            .reply_to_message_id(data.message.id.0)
-                            .await
-                    } else {
-                        bot.send_photo(data.message.chat.id, InputFile::memory(send))
-                            .reply_to_message_id(data.message.id)
-                            .await
-                    };
-
-                    match &result {
-                        Err(RequestError::Api(teloxide::ApiError::RequestEntityTooLarge)) => {
-                            goodbye!(format!(
-                            "Error: the resulting media is too big ({:.3}MB, max is {}MB). Sorry!",
-                            media_data.len() as f64 / 1000.0 / 100.00,
-                            MAX_UPLOAD_SIZE_MEGABYTES
-                        )
-                            .as_str());
-                        }
-                        Err(RequestError::Api(teloxide::ApiError::Unknown(e))) => {
-                            if e.contains("PHOTO_INVALID_DIMENSIONS") {
-                                goodbye!(concat!(
-                                    "Error: the resulting image's dimensions are invalid. ",
-                                    "It's probably too small or the aspect ratio is too extreme. Sorry!"
-                                ));
-                            }
-                            result
-                        }
-                        _ => result,
-                    }
-                })?;
-                Ok(())
             }
-            Task::Ocr => {
-                let photo = data.message.get_media_info();
-                let photo = match photo {
-                    Some(photo) => {
-                        if !photo.is_image() {
-                            goodbye!(
-                                "Error: can't work with video nor animated nor video stickers."
-                            );
-                        }
-                        if photo.file.size > MAX_DOWNLOAD_SIZE_MEGABYTES * 1000 * 1000 {
-                            goodbye!(format!(
-                                "Error: image is too large. The limit is {}MB.",
-                                MAX_DOWNLOAD_SIZE_MEGABYTES
-                            )
-                            .as_str());
-                        }
-                        photo
-                    }
-                    None => goodbye!("Error: can't find an image. "),
-                };
-
-                let mut photo_data: Vec<u8> = Vec::new();
-                bot.download_file_to_vec(photo.file, &mut photo_data)
-                    .await?;
-
-                // Perform extraction.
-                let woot =
-                    tokio::task::spawn_blocking(move || media_processing::ocr_image(&photo_data))
-                        .await
-                        .expect("Worker died!");
-
-                let mut text = match woot {
-                    Ok(t) => t,
-                    Err(e) => {
-                        log::error!("Failed when OCRing: {}", e);
-                        goodbye!("Error: failed to process the media.");
-                    }
-                };
-
-                if text.is_empty() {
-                    goodbye!("Sorry, could not find any text.");
+            Task::DistortAudio { .. } => {
+                if !media.is_sound {
+                    return Err("Error: expected an audio or a voice message.".to_string());
                 }
-
-                text.push_str("\n\n(automatically generated caption)");
-
-                goodbye!(encode_text(&text).as_ref());
             }
-            Task::AmenBreak => {
-                let media = data.message.get_media_info();
-                let media = match media {
-                    Some(media) => {
-                        if !media.is_raster() {
-                            goodbye!(
-                                "Error: can't work with animated stickers nor voice messages."
-                            );
-                        }
-                        if media.is_sound {
-                            goodbye!("Error: can't work with audio messages.");
-                        }
-                        if media.file.size > MAX_DOWNLOAD_SIZE_MEGABYTES * 1000 * 1000 {
-                            goodbye!(format!(
-                                "Error: media is too large. The limit is {}MB.",
-                                MAX_DOWNLOAD_SIZE_MEGABYTES
-                            )
-                            .as_str());
-                        }
-                        media
-                    }
-                    None => goodbye!("Error: can't find the video or photo."),
-                };
-
-                let _ = status_report.send("Downloading media...".to_string());
-
-                let download =
-                    unerror_download!(bot.download_file_to_temp_or_directly(media.file).await);
-                let path = download.0;
-                let file = download.1;
-
-                let status_report_for_processing = status_report.clone();
-
-                let result = tokio::task::spawn_blocking(move || {
-                    media_processing::amen_break_media(
-                        status_report_for_processing,
-                        &path,
-                        media.is_video,
-                    )
-                })
-                .await
-                .expect("Worker died!");
-
-                drop(file);
-
-                let video_data = match result {
-                    Ok(m) => m,
-                    Err(e) => {
-                        log::error!("Error when amen breaking video: {}", e);
-                        goodbye!("Error: failed to amen break the video");
-                    }
-                };
-
-                if video_data.is_empty() {
-                    goodbye!(
-                        "Error: failed to amen break the video; got empty file as a result. Sorry!"
+            Task::SpeedChange { .. } | Task::Trim { .. } => {
+                let usable = media.is_sound || (media.is_video && media.is_raster());
+                if !usable {
+                    return Err(
+                        "Error: expected a video, a GIF, an audio or a voice message.".to_string(),
                     );
                 }
-
-                if video_data.len() > MAX_UPLOAD_SIZE_MEGABYTES as usize * 1000 * 1000 {
-                    goodbye!(format!(
-                        "Error: the resulting media is too big ({:.3}MB, max is {}MB). Sorry!",
-                        video_data.len() as f64 / 1000.0 / 100.00,
-                        MAX_UPLOAD_SIZE_MEGABYTES
-                    )
-                    .as_str());
+            }
+            Task::Frame { .. } => {
+                if !media.is_video || !media.is_raster() || media.is_sound {
+                    return Err("Error: expected a video or a GIF.".to_string());
+                }
+            }
+            Task::Negate { .. } => {
+                if !media.is_raster() || media.is_sound {
+                    return Err("Error: expected an image, a video or a GIF.".to_string());
+                }
+            }
+            Task::Spoiler { .. } => {
+                if media.is_sticker {
+                    return Err("Error: stickers can't be hidden under a spoiler.".to_string());
+                }
+                if !media.is_raster() || media.is_voice_or_video_note {
+                    return Err(
+                        "Error: only images, videos and GIFs can be hidden under a spoiler."
+                            .to_string(),
+                    );
+                }
+                // It's sent back without downloading it, so it can be of any size.
+                return Ok(());
+            }
+            Task::Concat { .. } => {
+                if !media.is_video || !media.is_raster() || media.is_sound {
+                    return Err("Error: expected a video or a GIF.".to_string());
+                }
+            }
+            Task::Boost { .. } => {
+                if media.is_gif {
+                    return Err("Error: GIFs don't have any audio to boost.".to_string());
+                }
+                let usable = media.is_sound || (media.is_video && media.is_raster());
+                if !usable {
+                    return Err("Error: expected a video, an audio or a voice message.".to_string());
                 }
+            }
+        }
 
-                let _ = status_report.send("Uploading result...".to_string());
+        if media.file.size > MAX_DOWNLOAD_SIZE_MEGABYTES * 1000 * 1000 {
+            return Err(format!(
+                "Error: media is too large. The limit is {}MB.",
+                MAX_DOWNLOAD_SIZE_MEGABYTES
+            ));
+        }
 
-                teloxide_retry!({
-                    let send = video_data.clone();
+        Ok(())
+    }
+}
 
-                    bot.send_video(data.message.chat.id, InputFile::memory(send))
-                        .reply_to_message_id(data.message.id)
-                        .await
-                })?;
-                Ok(())
-            }
+/// Complete a [`Task::Amogus`].
+fn amogus_response(amogus: i32) -> CompletionResult {
+    let sign = amogus.signum();
+    let count = amogus.unsigned_abs();
+
+    let mut response;
+
+    let response_str = if count > 16 {
+        if sign == -1 {
+            "<b>TOO MUCH ANTIMOGUS</b>"
+        } else {
+            "<b>TOO MUCH AMOGUS</b>"
+        }
+    } else {
+        response = String::from("<b>");
+
+        let response_single = match sign {
+            -1 => "ANTIMOGUS ",
+            1 => "AMOGUS ",
+            0 => return Ok(()),
+            _ => unreachable!(),
+        };
+
+        let length: usize = response_single.len() + 4; // for "</b>"
+        response.reserve_exact(length);
+
+        for _ in 0..count {
+            response.push_str(response_single);
         }
+
+        response.push_str("</b>");
+
+        response.as_str()
+    };
+
+    goodbye!(response_str);
+}
+
+/// If this is a [`media_processing::INPUT_IMAGE_TOO_BIG_ERROR`],
+/// make a message to tell the user about it.
+fn input_image_too_big_message(error: &str) -> Option<String> {
+    let dimensions = error.strip_prefix(media_processing::INPUT_IMAGE_TOO_BIG_ERROR)?;
+    Some(format!(
+        "Error: the image is too big to process ({} pixels, max is {} megapixels). Sorry!",
+        dimensions.trim_start_matches(": "),
+        media_processing::MAX_INPUT_IMAGE_PIXELS / 1_000_000
+    ))
+}
+
+/// Format a file size in bytes with a sensible unit, like "480KB" or "1.3MB".
+fn format_file_size(bytes: u64) -> String {
+    let (value, unit) = if bytes < 1000 {
+        return format!("{}B", bytes);
+    } else if bytes < 1000 * 1000 {
+        (bytes as f64 / 1000.0, "KB")
+    } else {
+        (bytes as f64 / 1000.0 / 1000.0, "MB")
+    };
+
+    if value < 10.0 {
+        format!("{:.1}{}", value, unit)
+    } else {
+        format!("{:.0}{}", value, unit)
+    }
+}
+
+/// Format a duration for humans, like "4.2s", "42s" or "2m 5s".
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 10.0 {
+        format!("{:.1}s", seconds)
+    } else if seconds < 60.0 {
+        format!("{:.0}s", seconds)
+    } else {
+        let seconds = duration.as_secs();
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    }
+}
+
+/// Make a line like "1.3MB → 480KB, processed in 42s" to put under a media result.
+fn stats_footer(original_size: u32, result_size: usize, grabbed_at: Option<Instant>) -> String {
+    let mut footer = format!(
+        "{} → {}",
+        format_file_size(original_size as u64),
+        format_file_size(result_size as u64)
+    );
+    if let Some(grabbed_at) = grabbed_at {
+        footer.push_str(", processed in ");
+        footer.push_str(&format_duration(grabbed_at.elapsed()));
     }
+    footer
+}
+
+#[test]
+fn stats_footer_formatting_test() {
+    assert_eq!(format_file_size(0), "0B");
+    assert_eq!(format_file_size(999), "999B");
+    assert_eq!(format_file_size(1000), "1.0KB");
+    assert_eq!(format_file_size(480_123), "480KB");
+    assert_eq!(format_file_size(1_300_000), "1.3MB");
+    assert_eq!(format_file_size(25_000_000), "25MB");
+    assert_eq!(format_file_size(4_000_000_000), "4000MB");
+
+    assert_eq!(format_duration(Duration::from_millis(4200)), "4.2s");
+    assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+    assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+
+    assert_eq!(stats_footer(1_300_000, 480_000, None), "1.3MB → 480KB");
+}
+
+#[test]
+fn animated_stickers_can_be_resized_and_distorted() {
+    use crate::tasks::{ImageFormat, ResizeType, VideoTypePreference};
+    use arch_bot_commons::useful_methods::MessageStuff;
+    use teloxide::types::Message;
+
+    media_processing::pretend_animated_stickers_can_be_rendered();
+
+    let message: Message = serde_json::from_value(serde_json::json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": { "id": -100, "type": "supergroup", "title": "amogus" },
+        "sticker": {
+            "file_id": "file",
+            "file_unique_id": "unique",
+            "file_size": 1000,
+            "width": 512,
+            "height": 512,
+            "type": "regular",
+            "is_animated": true,
+            "is_video": false,
+        },
+    }))
+    .unwrap();
+    let media = message.get_media_info().unwrap();
+    assert!(media.is_animated_sticker);
+
+    // They're rendered into a video first, so /resize and /distort both take them as one.
+    for resize_type in [ResizeType::Fit, ResizeType::default_seam_carve()] {
+        let task = Task::default_video_resize(512, 512, resize_type, VideoTypePreference::Preserve);
+        assert_eq!(task.accepts(&media), Ok(()));
+    }
+
+    // Resizing just one frame of it would lose the animation.
+    let task = Task::default_image_resize(512, 512, ResizeType::Fit, ImageFormat::Preserve);
+    assert!(task.accepts(&media).is_err());
 }
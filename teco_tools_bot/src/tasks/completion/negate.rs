@@ -0,0 +1,84 @@
+//! Inverting the colors of images and videos.
+
+use std::time::Instant;
+
+use crate::tasks::{
+    completion::{input_image_too_big_message, media_processing},
+    ImageFormat,
+};
+
+use super::context::{CompletionResult, DownloadedMedia, OutputKind, TaskContext};
+
+/// Complete a [`Task::Negate`](crate::tasks::Task::Negate).
+pub async fn complete(ctx: &TaskContext<'_>, alpha: bool) -> CompletionResult {
+    let media = ctx.media("Error: can't find the media.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    let is_video = media.is_video;
+    // Video stickers have no sound, so they're best sent back as GIFs.
+    let as_gif = media.is_gif || media.is_sticker;
+    let as_sticker = media.is_sticker;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                if is_video {
+                    media_processing::negate_video(status_report_for_processing, &path, deadline)
+                } else {
+                    let _ = status_report_for_processing.send("Inverting colors...".to_string());
+                    let format = if as_sticker {
+                        ImageFormat::Webp
+                    } else {
+                        ImageFormat::Jpeg
+                    };
+                    std::fs::read(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|data| {
+                            media_processing::negate_image(&data, alpha, format)
+                                .map_err(|e| e.to_string())
+                        })
+                }
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let media_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            if let Some(message) = input_image_too_big_message(&e) {
+                goodbye!(message);
+            }
+            log::error!("Error when inverting colors: {}", e);
+            goodbye!("Error: failed to invert the colors of the media.");
+        }
+    };
+
+    let kind = if is_video && as_gif {
+        OutputKind::Animation
+    } else if is_video {
+        OutputKind::Video
+    } else if as_sticker {
+        OutputKind::Sticker
+    } else {
+        OutputKind::Photo
+    };
+
+    ctx.upload(
+        &media,
+        kind,
+        &media_data,
+        concat!(
+            "Error: failed to invert the colors of the media; ",
+            "got empty file as a result. Sorry!"
+        ),
+    )
+    .await
+}
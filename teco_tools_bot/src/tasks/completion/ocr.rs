@@ -0,0 +1,48 @@
+//! Extracting text from images.
+
+use std::time::Instant;
+
+use html_escape::encode_text;
+
+use crate::tasks::completion::{input_image_too_big_message, media_processing};
+
+use super::context::{CompletionResult, TaskContext};
+
+/// Complete a [`Task::Ocr`](crate::tasks::Task::Ocr).
+pub async fn complete(ctx: &TaskContext<'_>) -> CompletionResult {
+    let photo = ctx.media("Error: can't find an image. ")?;
+    let resources = ctx.reserve_memory(&photo).await;
+
+    let mut photo_data: Vec<u8> = Vec::new();
+    ctx.download_cache
+        .download_file_to_vec(ctx.bot, photo.file, &mut photo_data)
+        .await?;
+
+    // Perform extraction.
+    let deadline = Instant::now() + ctx.task_timeout;
+    let woot = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || media_processing::ocr_image(&photo_data)),
+        )
+        .await?;
+
+    let mut text = match woot {
+        Ok(t) => t,
+        Err(e) => {
+            if let Some(message) = input_image_too_big_message(&e.to_string()) {
+                goodbye!(message);
+            }
+            log::error!("Failed when OCRing: {}", e);
+            goodbye!("Error: failed to process the media.");
+        }
+    };
+
+    if text.is_empty() {
+        goodbye!("Sorry, could not find any text.");
+    }
+
+    text.push_str("\n\n(automatically generated caption)");
+
+    goodbye!(encode_text(&text).into_owned());
+}
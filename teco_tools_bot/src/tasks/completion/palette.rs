@@ -0,0 +1,62 @@
+//! Finding the main colors of images.
+
+use std::time::Instant;
+
+use arch_bot_commons::teloxide_retry;
+use teloxide::{
+    payloads::SendPhotoSetters,
+    requests::Requester,
+    types::{InputFile, ParseMode},
+};
+
+use crate::tasks::completion::{input_image_too_big_message, media_processing};
+
+use super::context::{CompletionResult, TaskContext};
+
+/// Complete a [`Task::Palette`](crate::tasks::Task::Palette).
+pub async fn complete(ctx: &TaskContext<'_>, count: u8) -> CompletionResult {
+    let photo = ctx.media("Error: can't find an image. ")?;
+    let resources = ctx.reserve_memory(&photo).await;
+
+    let mut photo_data: Vec<u8> = Vec::new();
+    ctx.download_media_to_vec(&photo, &mut photo_data).await?;
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let woot = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || media_processing::image_palette(&photo_data, count)),
+        )
+        .await?;
+
+    let (swatch, hex_codes) = match woot {
+        Ok(x) => x,
+        Err(e) => {
+            if let Some(message) = input_image_too_big_message(&e.to_string()) {
+                goodbye!(message);
+            }
+            log::error!("Failed when making a palette: {}", e);
+            goodbye!("Error: failed to process the media.");
+        }
+    };
+
+    let caption = hex_codes
+        .iter()
+        .map(|x| format!("<code>{}</code>", x))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    ctx.status("Uploading result...");
+
+    // Not `send_result`, as the hex codes are formatted.
+    teloxide_retry!(
+        ctx.bot
+            .send_photo(ctx.data.message.chat.id, InputFile::memory(swatch.clone()))
+            .caption(caption.clone())
+            .parse_mode(ParseMode::Html)
+            .reply_to_message_id(ctx.data.message.id)
+            .await
+    )?;
+
+    Ok(())
+}
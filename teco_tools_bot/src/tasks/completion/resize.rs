@@ -0,0 +1,622 @@
+//! Resizing images, videos and albums of photos.
+
+use std::{collections::VecDeque, time::Instant};
+
+use arch_bot_commons::{teloxide_retry, useful_methods::*};
+use teloxide::{
+    payloads::{SendDocumentSetters, SendMediaGroupSetters, SendPhotoSetters},
+    requests::Requester,
+    types::{InputFile, InputMedia, InputMediaDocument, InputMediaPhoto},
+    ApiError, RequestError,
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    strings::Key,
+    tasks::{
+        completion::{input_image_too_big_message, media_processing, stats_footer},
+        AlbumPhoto, ChromaSubsampling, ImageFormat, ResizeCurve, ResizeType, Task, VideoContainer,
+        VideoTypePreference,
+    },
+    MAX_UPLOAD_SIZE_MEGABYTES,
+};
+
+use super::context::{
+    check_result, refused_as_photo, too_big_message, CompletionResult, DownloadedMedia, OutputKind,
+    TaskContext,
+};
+
+/// Complete a [`Task::ImageResize`] or a [`Task::VideoResize`].
+pub async fn complete(ctx: &TaskContext<'_>) -> CompletionResult {
+    let (Task::ImageResize {
+        new_dimensions,
+        rotation,
+        resize_type,
+        quality,
+        color,
+        ..
+    }
+    | Task::VideoResize {
+        new_dimensions,
+        rotation,
+        resize_type,
+        quality,
+        color,
+        ..
+    }) = ctx.task
+    else {
+        unreachable!("Not a resize task");
+    };
+
+    let mut media = ctx.media("Error: can't find the media..")?;
+    // Animated stickers are rendered into a video before being resized.
+    let is_animated_sticker = media.is_animated_sticker;
+    media.is_video |= is_animated_sticker;
+
+    if let Task::ImageResize {
+        format,
+        chroma,
+        keep_metadata,
+        album,
+        ..
+    } = ctx.task
+    {
+        if album.len() > 1 {
+            // Album items are plain photos, never stickers.
+            let format = if *format == ImageFormat::Preserve {
+                ImageFormat::Jpeg
+            } else {
+                *format
+            };
+            return resize_album(ctx, album, format, *chroma, *keep_metadata).await;
+        }
+    }
+
+    let resources = ctx.reserve_memory(&media).await;
+    let (chroma, keep_metadata) = if let Task::ImageResize {
+        chroma,
+        keep_metadata,
+        ..
+    } = ctx.task
+    {
+        (*chroma, *keep_metadata)
+    } else {
+        (ChromaSubsampling::Auto, false)
+    };
+    let format = if let Task::ImageResize { format, .. } = ctx.task {
+        if *format == ImageFormat::Preserve {
+            // Only static stickers end up here, as video and animated ones are
+            // rejected by `accepts` and resized as videos instead.
+            if media.is_sticker {
+                ImageFormat::Webp
+            } else {
+                ImageFormat::Jpeg
+            }
+        } else {
+            *format
+        }
+    } else {
+        ImageFormat::Preserve
+    };
+
+    let mut media_data: Vec<u8> = Vec::new();
+
+    let input_dimensions = (media.width, media.height);
+
+    let dimensions = (new_dimensions.0 as isize, new_dimensions.1 as isize);
+    let resize_type = *resize_type;
+    let rotation = *rotation;
+    let quality = *quality;
+    let color = *color;
+
+    let (vibrato_hz, vibrato_depth, resize_curve, trim, container, loops) =
+        if let Task::VideoResize {
+            vibrato_hz,
+            vibrato_depth,
+            resize_curve,
+            trim,
+            container,
+            loops,
+            ..
+        } = ctx.task
+        {
+            (
+                *vibrato_hz,
+                *vibrato_depth,
+                resize_curve.clone(),
+                *trim,
+                *container,
+                *loops,
+            )
+        } else {
+            (
+                7.0,
+                0.0,
+                ResizeCurve::default(),
+                None,
+                VideoContainer::default(),
+                1,
+            )
+        };
+
+    let should_be_gif = if let Task::VideoResize { type_pref, .. } = ctx.task {
+        match type_pref {
+            VideoTypePreference::Preserve => media.is_gif || media.is_sticker,
+            VideoTypePreference::Gif => true,
+            VideoTypePreference::Video => false,
+        }
+    } else {
+        // Not a video lol
+        false
+    };
+
+    if let ResizeType::ToCustomEmojiGrid { columns, rows } = resize_type {
+        ctx.download_media_to_vec(&media, &mut media_data).await?;
+
+        let deadline = Instant::now() + ctx.task_timeout;
+        let woot = ctx
+            .await_processing(
+                deadline,
+                resources.spawn_blocking(move || {
+                    media_processing::resize_image_into_custom_emoji_grid(
+                        &media_data,
+                        columns,
+                        rows,
+                        quality,
+                        color,
+                    )
+                }),
+            )
+            .await?;
+
+        let tiles = match woot {
+            Ok(t) => t,
+            Err(e) => {
+                log::error!("Error when making a custom emoji grid: {}", e);
+                goodbye!(concat!(
+                    "Error: failed to make one of the custom emoji in the grid, ",
+                    "so none were sent. Sorry!"
+                ));
+            }
+        };
+
+        ctx.status("Uploading result...");
+
+        // Albums can only have between 2 and 10 items, so split tiles evenly.
+        let album_count = tiles.len().div_ceil(10);
+        let album_size = tiles.len().div_ceil(album_count);
+
+        for (album_index, album) in tiles.chunks(album_size).enumerate() {
+            let album: Vec<InputMedia> = album
+                .iter()
+                .enumerate()
+                .map(|(i, tile)| {
+                    let index = album_index * album_size + i;
+                    let name = format!(
+                        "emoji_row{}_col{}.webp",
+                        index / columns as usize + 1,
+                        index % columns as usize + 1
+                    );
+                    InputMedia::Document(InputMediaDocument::new(
+                        InputFile::memory(tile.clone()).file_name(name),
+                    ))
+                })
+                .collect();
+
+            teloxide_retry!(
+                ctx.bot
+                    .send_media_group(ctx.data.message.chat.id, album.clone())
+                    .reply_to_message_id(ctx.data.message.id)
+                    .await
+            )?;
+        }
+
+        return Ok(());
+    }
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    // Variable just to hold the downloaded file and drop it later.
+    let mut file = None;
+
+    // Video stickers are always VP9 with transparency.
+    // Animated stickers are rendered into a GIF, which has no proper transparency.
+    let has_alpha = media.is_sticker && !is_animated_sticker;
+
+    let (deadline, handle) = if media.is_video {
+        let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+        file = Some(lease);
+        let deadline = Instant::now() + ctx.task_timeout;
+        let handle = resources.spawn_blocking(move || {
+            let rendered = if is_animated_sticker {
+                let _ = status_report_for_processing.send("Rendering the sticker...".to_string());
+                Some(media_processing::render_animated_sticker(&path, deadline)?)
+            } else {
+                None
+            };
+            let path = rendered.as_ref().map_or(path.as_path(), |x| x.path());
+
+            media_processing::resize_video(
+                status_report_for_processing,
+                path,
+                dimensions,
+                rotation,
+                resize_type,
+                should_be_gif,
+                vibrato_hz,
+                vibrato_depth,
+                input_dimensions,
+                resize_curve,
+                quality,
+                color,
+                trim,
+                container,
+                loops,
+                has_alpha,
+                deadline,
+            )
+        });
+        (deadline, handle)
+    } else {
+        ctx.download_media_to_vec(&media, &mut media_data).await?;
+
+        let deadline = Instant::now() + ctx.task_timeout;
+        let handle = resources.spawn_blocking(move || {
+            media_processing::resize_image(
+                &media_data,
+                dimensions.0,
+                dimensions.1,
+                rotation,
+                resize_type,
+                format,
+                None,
+                false,
+                quality,
+                color,
+                chroma,
+                keep_metadata,
+            )
+            .map_err(|e| e.to_string())
+        });
+        (deadline, handle)
+    };
+    let woot = ctx.await_processing(deadline, handle).await?;
+
+    drop(file);
+
+    let media_data = match woot {
+        Ok(m) => m,
+        Err(e) => {
+            if format == ImageFormat::Avif && e.to_lowercase().contains("delegate") {
+                goodbye!(concat!(
+                    "Error: AVIF output is not supported by this bot's ImageMagick. ",
+                    "Try <code>jpg</code> or <code>webp</code> instead. Sorry!"
+                ));
+            }
+            if let Some(message) = input_image_too_big_message(&e) {
+                goodbye!(message);
+            }
+            if e == media_processing::TRIM_OUT_OF_RANGE_ERROR {
+                goodbye!("Error: the trimmed range starts after the end of the video.");
+            }
+            if e == media_processing::LOOPED_VIDEO_TOO_LONG_ERROR {
+                goodbye!(concat!(
+                    "Error: the looped video would be too long at this size. ",
+                    "Try fewer loops or a smaller size."
+                ));
+            }
+            log::error!("Error when resizing media: {}", e);
+            goodbye!("Error: failed to process the media");
+        }
+    };
+
+    check_result(
+        &media_data,
+        "Error: failed to process the media; got empty file as a result. Sorry!",
+        ctx.lang,
+    )?;
+
+    // Telegram doesn't show AVIF as a photo nor a sticker, so send it as a file.
+    let should_be_document = !media.is_video && format == ImageFormat::Avif;
+    let should_be_sticker =
+        !media.is_video && !should_be_document && format.supports_alpha_transparency();
+
+    let stats = ctx.stats(&media, media_data.len());
+
+    ctx.status("Uploading result...");
+
+    let kind = if media.is_video {
+        if container == VideoContainer::Webm {
+            // Telegram only plays MP4 videos inline.
+            OutputKind::Document("amogus.webm".to_string())
+        } else if should_be_gif {
+            OutputKind::Animation
+        } else {
+            OutputKind::Video
+        }
+    } else if should_be_document {
+        OutputKind::Document("amogus.avif".to_string())
+    } else if should_be_sticker {
+        OutputKind::Sticker
+    } else {
+        OutputKind::Photo
+    };
+
+    let result = ctx.send_result(&kind, &media_data, &stats).await;
+
+    if let Err(RequestError::Api(ApiError::RequestEntityTooLarge)) = &result {
+        if media.is_video || should_be_document {
+            goodbye!(too_big_message(
+                Key::ResultMedia,
+                media_data.len(),
+                ctx.lang
+            ));
+        }
+    }
+
+    // If Telegram refuses the result as a photo or a sticker, try sending it as a file once.
+    if !refused_as_photo(&result) {
+        let sent = result?;
+        if should_be_sticker {
+            // Stickers can't have captions, so follow up with the stats instead.
+            // Failing to do that is not worth failing the whole task over.
+            let _ = ctx
+                .bot
+                .archsendmsg(ctx.data.message.chat.id, stats.as_str(), sent.id)
+                .await;
+        }
+        return Ok(());
+    }
+
+    let file_name = if container == VideoContainer::Webm {
+        "amogus.webm".to_string()
+    } else if media.is_video {
+        "amogus.mp4".to_string()
+    } else {
+        format!("amogus.{}", format.as_str().to_lowercase())
+    };
+
+    let result = ctx
+        .send_result(
+            &OutputKind::Document(file_name),
+            &media_data,
+            &format!(
+                "Sent as a file due to Telegram's limits on photos and stickers.\n{}",
+                stats
+            ),
+        )
+        .await;
+
+    if let Err(RequestError::Api(ApiError::RequestEntityTooLarge)) = &result {
+        goodbye!(too_big_message(
+            Key::ResultMedia,
+            media_data.len(),
+            ctx.lang
+        ));
+    }
+
+    result?;
+    Ok(())
+}
+
+/// Resize each photo of an album, and send them back as one.
+/// Photos that fail are skipped, and the user is told which ones at the end.
+async fn resize_album(
+    ctx: &TaskContext<'_>,
+    album: &[AlbumPhoto],
+    format: ImageFormat,
+    chroma: ChromaSubsampling,
+    keep_metadata: bool,
+) -> CompletionResult {
+    let Task::ImageResize {
+        rotation,
+        resize_type,
+        quality,
+        color,
+        ..
+    } = ctx.task
+    else {
+        unreachable!("Albums are only resized as images");
+    };
+    let rotation = *rotation;
+    let resize_type = *resize_type;
+    let quality = *quality;
+    let color = *color;
+
+    ctx.status("Working on the album...");
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let mut results: Vec<Option<Vec<u8>>> = vec![None; album.len()];
+    // Index of each photo being worked on, and its processing, which holds its memory.
+    type Resizing = JoinHandle<Result<Vec<u8>, String>>;
+    let mut in_progress: VecDeque<(usize, Resizing)> = VecDeque::new();
+
+    // Wait for the oldest photo being worked on, giving back its memory.
+    macro_rules! finish_oldest {
+        () => {{
+            let (index, handle) = in_progress.pop_front().expect("Nothing to finish");
+            match ctx.await_processing(deadline, handle).await? {
+                Ok(result) if !result.is_empty() => results[index] = Some(result),
+                Ok(_) => log::error!(
+                    "Got an empty file when resizing photo {} of an album",
+                    index
+                ),
+                Err(e) => log::error!("Error when resizing photo {} of an album: {}", index, e),
+            }
+        }};
+    }
+
+    for (index, photo) in album.iter().enumerate() {
+        let photo_media = MessageMediaInfo {
+            width: photo.width,
+            height: photo.height,
+            is_sticker: false,
+            is_gif: false,
+            is_video: false,
+            is_image: true,
+            is_sound: false,
+            is_voice_or_video_note: false,
+            is_vector_sticker: false,
+            is_animated_sticker: false,
+            file: &photo.file,
+        };
+        let mut single = ctx.task.clone();
+        if let Task::ImageResize {
+            new_dimensions,
+            album,
+            ..
+        } = &mut single
+        {
+            *new_dimensions = photo.new_dimensions;
+            album.clear();
+        }
+        let estimate = single.memory_estimate(&photo_media);
+
+        // Waiting on the governor while holding memory for earlier photos
+        // could mean waiting for ourselves forever, so finish those first.
+        let resources = loop {
+            if let Some(resources) = ctx.governor.try_acquire(estimate) {
+                break resources;
+            }
+            if in_progress.is_empty() {
+                break ctx
+                    .governor
+                    .acquire(estimate, || ctx.status("Waiting for resources..."))
+                    .await;
+            }
+            finish_oldest!();
+        };
+
+        let mut photo_data = Vec::new();
+        if let Err(e) = ctx
+            .download_cache
+            .download_file_to_vec(ctx.bot, &photo.file, &mut photo_data)
+            .await
+        {
+            log::error!("Error when downloading photo {} of an album: {}", index, e);
+            continue;
+        }
+
+        let dimensions = (
+            photo.new_dimensions.0 as isize,
+            photo.new_dimensions.1 as isize,
+        );
+        let handle = resources.spawn_blocking(move || {
+            media_processing::resize_image(
+                &photo_data,
+                dimensions.0,
+                dimensions.1,
+                rotation,
+                resize_type,
+                format,
+                None,
+                false,
+                quality,
+                color,
+                chroma,
+                keep_metadata,
+            )
+            .map_err(|e| e.to_string())
+        });
+        in_progress.push_back((index, handle));
+    }
+
+    while !in_progress.is_empty() {
+        finish_oldest!();
+    }
+
+    let failed: Vec<String> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| result.is_none())
+        .map(|(index, _)| (index + 1).to_string())
+        .collect();
+    let results: Vec<Vec<u8>> = results.into_iter().flatten().collect();
+
+    if results.is_empty() {
+        goodbye!("Error: failed to process any of the photos in the album. Sorry!");
+    }
+
+    let total_size: usize = results.iter().map(Vec::len).sum();
+    if total_size > MAX_UPLOAD_SIZE_MEGABYTES as usize * 1000 * 1000 {
+        goodbye!(too_big_message(Key::ResultAlbum, total_size, ctx.lang));
+    }
+
+    let original_size = album.iter().map(|x| x.file.size).sum();
+    let stats = stats_footer(original_size, total_size, ctx.data.grabbed_at);
+
+    // Telegram won't show these as photos, and albums can't have stickers.
+    let as_documents = format == ImageFormat::Avif || format.supports_alpha_transparency();
+
+    let items: Vec<InputMedia> = results
+        .into_iter()
+        .enumerate()
+        .map(|(index, result)| {
+            let caption = if index == 0 {
+                stats.clone()
+            } else {
+                String::new()
+            };
+            if as_documents {
+                let name = format!("amogus_{}.{}", index + 1, format.as_str().to_lowercase());
+                InputMedia::Document(
+                    InputMediaDocument::new(InputFile::memory(result).file_name(name))
+                        .caption(caption),
+                )
+            } else {
+                InputMedia::Photo(InputMediaPhoto::new(InputFile::memory(result)).caption(caption))
+            }
+        })
+        .collect();
+
+    ctx.status("Uploading result...");
+
+    let chat_id = ctx.data.message.chat.id;
+    let reply_to = ctx.data.message.id;
+    let result = if let [item] = items.as_slice() {
+        // Albums need at least 2 items.
+        match item.clone() {
+            InputMedia::Photo(photo) => teloxide_retry!(
+                ctx.bot
+                    .send_photo(chat_id, photo.media.clone())
+                    .caption(stats.clone())
+                    .reply_to_message_id(reply_to)
+                    .await
+            )
+            .map(|_| ()),
+            InputMedia::Document(document) => teloxide_retry!(
+                ctx.bot
+                    .send_document(chat_id, document.media.clone())
+                    .caption(stats.clone())
+                    .reply_to_message_id(reply_to)
+                    .await
+            )
+            .map(|_| ()),
+            _ => unreachable!(),
+        }
+    } else {
+        // Telegram albums have at most 10 items, so this always fits in one.
+        teloxide_retry!(
+            ctx.bot
+                .send_media_group(chat_id, items.clone())
+                .reply_to_message_id(reply_to)
+                .await
+        )
+        .map(|_| ())
+    };
+
+    if let Err(RequestError::Api(ApiError::RequestEntityTooLarge)) = &result {
+        goodbye!(too_big_message(Key::ResultAlbum, total_size, ctx.lang));
+    }
+    result?;
+
+    if !failed.is_empty() {
+        let response = format!(
+            "Failed to process photos number {} of the album. Sorry!",
+            failed.join(", ")
+        );
+        ctx.respond(response.as_str()).await?;
+    }
+
+    Ok(())
+}
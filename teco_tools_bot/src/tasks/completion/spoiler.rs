@@ -0,0 +1,48 @@
+//! Sending media back hidden under a spoiler.
+
+use arch_bot_commons::teloxide_retry;
+use teloxide::{
+    payloads::{SendAnimationSetters, SendPhotoSetters, SendVideoSetters},
+    requests::Requester,
+    types::InputFile,
+};
+
+use super::context::{CompletionResult, TaskContext};
+
+/// Complete a [`Task::Spoiler`](crate::tasks::Task::Spoiler).
+pub async fn complete(ctx: &TaskContext<'_>, caption: &str) -> CompletionResult {
+    let media = ctx.media("Error: can't find an image or a video.")?;
+
+    ctx.status("Uploading result...");
+
+    // Telegram takes the same file again by its ID, so nothing needs to be downloaded.
+    let chat_id = ctx.data.message.chat.id;
+    let reply_to = ctx.data.message.id;
+    teloxide_retry!({
+        let send = InputFile::file_id(media.file.id.clone());
+        if media.is_gif {
+            ctx.bot
+                .send_animation(chat_id, send)
+                .caption(caption)
+                .has_spoiler(true)
+                .reply_to_message_id(reply_to)
+                .await
+        } else if media.is_video {
+            ctx.bot
+                .send_video(chat_id, send)
+                .caption(caption)
+                .has_spoiler(true)
+                .reply_to_message_id(reply_to)
+                .await
+        } else {
+            ctx.bot
+                .send_photo(chat_id, send)
+                .caption(caption)
+                .has_spoiler(true)
+                .reply_to_message_id(reply_to)
+                .await
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,404 @@
+//! Tasks that work on videos, and the ones of them that work on audio too.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    strings::Key,
+    tasks::{
+        completion::{media_processing, stats_footer},
+        format_timestamp, pick_seed, PitchMode, VideoClip,
+    },
+};
+
+use super::context::{
+    check_result, refused_as_photo, CompletionResult, DownloadedMedia, OutputKind, TaskContext,
+};
+
+/// Complete a [`Task::AmenBreak`](crate::tasks::Task::AmenBreak).
+pub async fn amen_break(ctx: &TaskContext<'_>, seed: Option<u64>) -> CompletionResult {
+    let media = ctx.media("Error: can't find the video or photo.")?;
+    let seed = pick_seed(seed);
+    let resources = ctx.reserve_memory(&media).await;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let is_video = media.is_video;
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::amen_break_media(
+                    status_report_for_processing,
+                    &path,
+                    is_video,
+                    seed,
+                    deadline,
+                )
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let video_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when amen breaking video: {}", e);
+            goodbye!("Error: failed to amen break the video");
+        }
+    };
+
+    check_result(
+        &video_data,
+        "Error: failed to amen break the video; got empty file as a result. Sorry!",
+        ctx.lang,
+    )?;
+
+    ctx.status(Key::StatusUploading.get(ctx.lang));
+
+    // The seed is shown so that the same break can be picked again.
+    let caption = format!("Seed: {}\n{}", seed, ctx.stats(&media, video_data.len()));
+    ctx.send_result(&OutputKind::Video, &video_data, &caption)
+        .await?;
+    Ok(())
+}
+
+/// Complete a [`Task::Concat`](crate::tasks::Task::Concat).
+pub async fn concat(ctx: &TaskContext<'_>, clips: &[VideoClip]) -> CompletionResult {
+    let media = ctx.media("Error: can't find the videos.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    // Leases keep the files around until the end.
+    let mut downloaded = Vec::with_capacity(clips.len());
+    for clip in clips {
+        downloaded.push(ctx.download_file(&clip.file).await?);
+    }
+    let inputs: Vec<_> = downloaded
+        .iter()
+        .zip(clips)
+        .map(|(file, clip)| (file.path.clone(), (clip.width, clip.height)))
+        .collect();
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::concat_videos(status_report_for_processing, &inputs, deadline)
+            }),
+        )
+        .await?;
+
+    drop(downloaded);
+
+    let video_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when joining videos: {}", e);
+            goodbye!("Error: failed to join the videos.");
+        }
+    };
+
+    check_result(
+        &video_data,
+        "Error: failed to join the videos; got empty file as a result. Sorry!",
+        ctx.lang,
+    )?;
+
+    ctx.status(Key::StatusUploading.get(ctx.lang));
+
+    let original_size = clips.iter().map(|x| x.file.size).sum();
+    let stats = stats_footer(original_size, video_data.len(), ctx.data.grabbed_at);
+    ctx.send_result(&OutputKind::Video, &video_data, &stats)
+        .await?;
+    Ok(())
+}
+
+/// How to send back media that was made from a video, a GIF, an audio or a voice message.
+fn same_kind_as_input(is_sound: bool, as_voice: bool, as_gif: bool) -> OutputKind {
+    if as_voice {
+        OutputKind::Voice
+    } else if is_sound {
+        OutputKind::Audio
+    } else if as_gif {
+        OutputKind::Animation
+    } else {
+        OutputKind::Video
+    }
+}
+
+/// Complete a [`Task::SpeedChange`](crate::tasks::Task::SpeedChange).
+pub async fn change_speed(
+    ctx: &TaskContext<'_>,
+    factor: f64,
+    pitch: PitchMode,
+) -> CompletionResult {
+    let media = ctx.media("Error: can't find the video or audio.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    let is_sound = media.is_sound;
+    // Voice messages should come back as voice messages.
+    let as_voice = media.is_sound && media.is_voice_or_video_note;
+    let as_gif = media.is_gif;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                if is_sound {
+                    media_processing::distort_audio(
+                        status_report_for_processing,
+                        &path,
+                        0.0,
+                        0.0,
+                        factor,
+                        pitch,
+                        as_voice,
+                        deadline,
+                    )
+                } else {
+                    media_processing::change_video_speed(
+                        status_report_for_processing,
+                        &path,
+                        factor,
+                        pitch,
+                        deadline,
+                    )
+                }
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let media_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when changing media speed: {}", e);
+            goodbye!("Error: failed to change the speed of the media.");
+        }
+    };
+
+    ctx.upload(
+        &media,
+        same_kind_as_input(is_sound, as_voice, as_gif),
+        &media_data,
+        concat!(
+            "Error: failed to change the speed of the media; ",
+            "got empty file as a result. Sorry!"
+        ),
+    )
+    .await
+}
+
+/// Complete a [`Task::Trim`](crate::tasks::Task::Trim).
+pub async fn trim(
+    ctx: &TaskContext<'_>,
+    start: Duration,
+    mut end: Option<Duration>,
+    precise: bool,
+) -> CompletionResult {
+    let media = ctx.media("Error: can't find the video or audio.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    let is_sound = media.is_sound;
+    // Voice messages should come back as voice messages.
+    let as_voice = media.is_sound && media.is_voice_or_video_note;
+    let as_gif = media.is_gif;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    ctx.status("Checking media length...");
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let path_for_counting = path.clone();
+    let counted = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::count_video_frames_and_framerate_and_audio_and_length(
+                    &path_for_counting,
+                    is_sound,
+                    deadline,
+                )
+            }),
+        )
+        .await?;
+
+    let length = match counted {
+        Ok((_, _, _, length)) => length,
+        Err(e) => {
+            log::error!("Failed to get media length: {}", e);
+            goodbye!("Error: failed to read the media.");
+        }
+    };
+
+    if start >= length {
+        goodbye!(format!(
+            "Error: the media is only {} long, so it can't be trimmed from {}.",
+            format_timestamp(length),
+            format_timestamp(start)
+        ));
+    }
+
+    // Going past the end is the same as not specifying the end.
+    if end.is_some_and(|end| end >= length) {
+        end = None;
+    }
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::trim_media(
+                    status_report_for_processing,
+                    &path,
+                    start,
+                    end,
+                    precise,
+                    is_sound,
+                    as_voice,
+                    deadline,
+                )
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let media_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when trimming media: {}", e);
+            goodbye!("Error: failed to trim the media.");
+        }
+    };
+
+    ctx.upload(
+        &media,
+        same_kind_as_input(is_sound, as_voice, as_gif),
+        &media_data,
+        "Error: failed to trim the media; got empty file as a result. Sorry!",
+    )
+    .await
+}
+
+/// Complete a [`Task::Frame`](crate::tasks::Task::Frame).
+pub async fn frame(
+    ctx: &TaskContext<'_>,
+    timestamp: Duration,
+    grid: Option<(u8, u8)>,
+) -> CompletionResult {
+    let media = ctx.media("Error: can't find a video.")?;
+    let resources = ctx.reserve_memory(&media).await;
+
+    let DownloadedMedia { path, lease } = ctx.download_media(&media).await?;
+
+    ctx.status("Checking media length...");
+
+    let deadline = Instant::now() + ctx.task_timeout;
+    let path_for_counting = path.clone();
+    let counted = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                media_processing::count_video_frames_and_framerate_and_audio_and_length(
+                    &path_for_counting,
+                    false,
+                    deadline,
+                )
+            }),
+        )
+        .await?;
+
+    let length = match counted {
+        Ok((_, _, _, length)) => length,
+        Err(e) => {
+            log::error!("Failed to get media length: {}", e);
+            goodbye!("Error: failed to read the media.");
+        }
+    };
+
+    if grid.is_none() && timestamp >= length {
+        goodbye!(format!(
+            "Error: the video is only {} long, so it has no frame at {}.",
+            format_timestamp(length),
+            format_timestamp(timestamp)
+        ));
+    }
+
+    let status_report_for_processing = ctx.status_report.clone();
+
+    let result = ctx
+        .await_processing(
+            deadline,
+            resources.spawn_blocking(move || {
+                if let Some(grid) = grid {
+                    media_processing::frames_contact_sheet(
+                        status_report_for_processing,
+                        &path,
+                        grid,
+                        length,
+                        deadline,
+                    )
+                } else {
+                    let _ = status_report_for_processing.send("Grabbing the frame...".to_string());
+                    media_processing::extract_frame(&path, timestamp, deadline)
+                }
+            }),
+        )
+        .await?;
+
+    drop(lease);
+
+    let image_data = match result {
+        Ok(m) => m,
+        Err(e) => {
+            log::error!("Error when grabbing frames: {}", e);
+            goodbye!("Error: failed to grab frames from the video.");
+        }
+    };
+
+    let stats = ctx.stats(&media, image_data.len());
+
+    ctx.status("Uploading result...");
+
+    let result = ctx
+        .send_result(&OutputKind::Photo, &image_data, &stats)
+        .await;
+
+    // Big frames can be over Telegram's limits on photos, but fine as a file.
+    if !refused_as_photo(&result) {
+        result?;
+        return Ok(());
+    }
+
+    let file_name = if grid.is_some() {
+        "amogus.jpg"
+    } else {
+        "amogus.png"
+    };
+
+    ctx.send_result(
+        &OutputKind::Document(file_name.to_string()),
+        &image_data,
+        &format!(
+            "Sent as a file due to Telegram's limits on photos.\n{}",
+            stats
+        ),
+    )
+    .await?;
+    Ok(())
+}
@@ -2,19 +2,22 @@ pub mod completion;
 pub mod parsing;
 pub mod taskman;
 
-use std::{f64::consts::TAU, fmt::Display, num::NonZeroU8, str::FromStr};
+use std::{f64::consts::TAU, fmt::Display, num::NonZeroU8, str::FromStr, time::Duration};
 
 use serde::{Deserialize, Serialize};
 use teloxide::{
-    types::{Me, Message},
+    types::{FileMeta, Me, Message},
     Bot,
 };
 
-use crate::handlers::commands::{TaskFuture, TaskParams};
+use crate::{
+    handlers::commands::{TaskFuture, TaskParams},
+    strings::{fill, Key, Lang},
+};
 
 use taskman::Taskman;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub enum ResizeCurve {
     #[default]
     Constant,
@@ -22,11 +25,21 @@ pub enum ResizeCurve {
     Falling,
     Loop,
     LoopB,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Bounce,
+    /// Keyframes of progress through the video and the blend factor at it, both as percentages,
+    /// sorted by progress. Factors are interpolated linearly between them.
+    Custom(Vec<(f64, f64)>),
 }
 
+/// Biggest blend factor a custom curve keyframe can have, as a percentage.
+pub const MAX_CUSTOM_CURVE_FACTOR: f64 = 200.0;
+
 impl ResizeCurve {
     pub fn apply_resize_for(
-        self,
+        &self,
         current_frame: usize,
         total_frames: u64,
         start: f64,
@@ -40,15 +53,115 @@ impl ResizeCurve {
             Self::Falling => 1.0 - progress,
             Self::Loop => f64::sin((progress - 0.25) * TAU) * 0.5 + 0.5,
             Self::LoopB => f64::sin((progress - 0.75) * TAU) * 0.5 + 0.5,
+            Self::EaseIn => progress * progress,
+            Self::EaseOut => 1.0 - (1.0 - progress) * (1.0 - progress),
+            Self::EaseInOut => {
+                if progress < 0.5 {
+                    2.0 * progress * progress
+                } else {
+                    1.0 - (2.0 - 2.0 * progress).powi(2) / 2.0
+                }
+            }
+            Self::Bounce => bounce(progress),
+            Self::Custom(keyframes) => interpolate_keyframes(keyframes, progress * 100.0) / 100.0,
         };
 
         start + (end - start) * factor
     }
 }
 
+/// Ease out like a ball dropped on the floor, bouncing a few times before it settles at 1.
+fn bounce(progress: f64) -> f64 {
+    const N: f64 = 7.5625;
+    const D: f64 = 2.75;
+
+    if progress < 1.0 / D {
+        N * progress * progress
+    } else if progress < 2.0 / D {
+        let x = progress - 1.5 / D;
+        N * x * x + 0.75
+    } else if progress < 2.5 / D {
+        let x = progress - 2.25 / D;
+        N * x * x + 0.9375
+    } else {
+        let x = progress - 2.625 / D;
+        N * x * x + 0.984375
+    }
+}
+
+/// Linearly interpolate between sorted keyframes. Before the first and after the last one,
+/// their factors are kept.
+fn interpolate_keyframes(keyframes: &[(f64, f64)], progress: f64) -> f64 {
+    let Some(&(first_progress, first_factor)) = keyframes.first() else {
+        return 100.0;
+    };
+    if progress <= first_progress {
+        return first_factor;
+    }
+
+    for pair in keyframes.windows(2) {
+        let [(from_progress, from_factor), (to_progress, to_factor)] = *pair else {
+            unreachable!()
+        };
+        if progress <= to_progress {
+            let t = (progress - from_progress) / (to_progress - from_progress);
+            return from_factor + (to_factor - from_factor) * t;
+        }
+    }
+
+    // Past the last keyframe.
+    keyframes[keyframes.len() - 1].1
+}
+
+/// Parse keyframes like `0=100%,50=20%,100=150%`, where the first number is
+/// the percentage of progress through the video, and the second is the blend factor.
+fn parse_custom_curve(s: &str) -> Result<Vec<(f64, f64)>, ()> {
+    let parse_percentage = |x: &str| -> Result<f64, ()> {
+        let x = x.trim();
+        let value: f64 = x.strip_suffix('%').unwrap_or(x).parse().map_err(|_| ())?;
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err(())
+        }
+    };
+
+    let mut keyframes = Vec::new();
+
+    for keyframe in s.split(',') {
+        let (progress, factor) = keyframe.split_once('=').ok_or(())?;
+        let progress = parse_percentage(progress)?;
+        let factor = parse_percentage(factor)?;
+
+        if !(0.0..=100.0).contains(&progress) || !(0.0..=MAX_CUSTOM_CURVE_FACTOR).contains(&factor)
+        {
+            return Err(());
+        }
+
+        keyframes.push((progress, factor));
+    }
+
+    // Keyframes must be in order, and not share positions.
+    if keyframes.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err(());
+    }
+
+    Ok(keyframes)
+}
+
 impl FromStr for ResizeCurve {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((kind, keyframes)) = s.split_once(':') {
+            if kind.eq_ignore_ascii_case("custom") {
+                return parse_custom_curve(keyframes).map(Self::Custom);
+            }
+            return Err(());
+        }
+
+        // Allow "ease-in" and "ease_in" as well as "easein".
+        let s = s.replace(['-', '_'], "");
+
         if s.eq_ignore_ascii_case("constant") {
             Ok(Self::Constant)
         } else if s.eq_ignore_ascii_case("rising") {
@@ -59,6 +172,14 @@ impl FromStr for ResizeCurve {
             Ok(Self::Loop)
         } else if s.eq_ignore_ascii_case("loopb") {
             Ok(Self::LoopB)
+        } else if s.eq_ignore_ascii_case("easein") {
+            Ok(Self::EaseIn)
+        } else if s.eq_ignore_ascii_case("easeout") {
+            Ok(Self::EaseOut)
+        } else if s.eq_ignore_ascii_case("easeinout") {
+            Ok(Self::EaseInOut)
+        } else if s.eq_ignore_ascii_case("bounce") {
+            Ok(Self::Bounce)
         } else {
             Err(())
         }
@@ -73,13 +194,25 @@ impl ResizeCurve {
             Self::Falling => "Falling",
             Self::Loop => "Loop",
             Self::LoopB => "LoopB",
+            Self::EaseIn => "EaseIn",
+            Self::EaseOut => "EaseOut",
+            Self::EaseInOut => "EaseInOut",
+            Self::Bounce => "Bounce",
+            Self::Custom(_) => "Custom",
         }
     }
 }
 
 impl Display for ResizeCurve {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.as_str())
+        write!(f, "{}", self.as_str())?;
+        if let Self::Custom(keyframes) = self {
+            for (i, (progress, factor)) in keyframes.iter().enumerate() {
+                let separator = if i == 0 { ": " } else { ", " };
+                write!(f, "{}{}%={}%", separator, progress, factor)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -90,7 +223,25 @@ pub enum ResizeType {
     Crop,
     ToSticker,
     ToCustomEmoji,
-    SeamCarve { delta_x: f64, rigidity: f64 },
+    /// Image cropped to fill a grid of custom emoji, to be split into them.
+    ToCustomEmojiGrid {
+        columns: u8,
+        rows: u8,
+    },
+    SeamCarve {
+        delta_x: f64,
+        rigidity: f64,
+    },
+    /// Media mirrored without resizing it, by negating its dimensions.
+    Mirror {
+        horizontal: bool,
+        vertical: bool,
+    },
+    /// Media made of mirrored copies of a wedge of it around its center.
+    Kaleidoscope {
+        /// An even number between 2 and 16.
+        segments: u8,
+    },
 }
 
 impl ResizeType {
@@ -133,6 +284,18 @@ impl Display for ResizeType {
             }
             Self::ToSticker => write!(f, "To sticker"),
             Self::ToCustomEmoji => write!(f, "To custom emoji"),
+            Self::ToCustomEmojiGrid { columns, rows } => {
+                write!(f, "To {}x{} custom emoji", columns, rows)
+            }
+            Self::Mirror {
+                horizontal,
+                vertical,
+            } => match (horizontal, vertical) {
+                (true, true) => write!(f, "Mirror both ways"),
+                (false, true) => write!(f, "Mirror vertically"),
+                _ => write!(f, "Mirror horizontally"),
+            },
+            Self::Kaleidoscope { segments } => write!(f, "Kaleidoscope of {} segments", segments),
         }
     }
 }
@@ -144,6 +307,7 @@ pub enum ImageFormat {
     Jpeg,
     Bmp,
     Png,
+    Avif,
 }
 
 impl ImageFormat {
@@ -156,6 +320,7 @@ impl ImageFormat {
             Self::Jpeg => false,
             Self::Bmp => false,
             Self::Png => true,
+            Self::Avif => true,
         }
     }
 
@@ -166,6 +331,7 @@ impl ImageFormat {
             Self::Jpeg => "JPEG",
             Self::Bmp => "BMP",
             Self::Png => "PNG",
+            Self::Avif => "AVIF",
         }
     }
 
@@ -181,6 +347,7 @@ impl ImageFormat {
             Self::Jpeg => "jpegls",
             Self::Bmp => "bmp",
             Self::Png => "png",
+            Self::Avif => "libaom-av1",
         }
     }
 }
@@ -200,6 +367,8 @@ impl FromStr for ImageFormat {
             Ok(Self::Webp)
         } else if s.eq_ignore_ascii_case("jpeg") || s.eq_ignore_ascii_case("jpg") {
             Ok(Self::Jpeg)
+        } else if s.eq_ignore_ascii_case("avif") {
+            Ok(Self::Avif)
         } else {
             // BMP and PNG are intentionally ignored as they're for internal purposes only
             Err(())
@@ -247,6 +416,199 @@ impl Display for VideoTypePreference {
     }
 }
 
+/// Container, and with that the codec, of a resized video.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub enum VideoContainer {
+    /// H.264 in MP4, which Telegram plays inline.
+    #[default]
+    Mp4,
+    /// VP9 in WebM, which can keep transparency.
+    Webm,
+}
+
+impl VideoContainer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "MP4",
+            Self::Webm => "WebM",
+        }
+    }
+}
+
+impl FromStr for VideoContainer {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("mp4") {
+            Ok(Self::Mp4)
+        } else if s.eq_ignore_ascii_case("webm") {
+            Ok(Self::Webm)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Display for VideoContainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Chroma subsampling to use for JPEG output.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub enum ChromaSubsampling {
+    /// Let ImageMagick choose.
+    #[default]
+    Auto,
+    Chroma444,
+    Chroma420,
+}
+
+impl ChromaSubsampling {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auto => "Auto",
+            Self::Chroma444 => "4:4:4",
+            Self::Chroma420 => "4:2:0",
+        }
+    }
+
+    /// Sampling factors to give to ImageMagick, or [`None`] to not touch them.
+    pub fn sampling_factors(&self) -> Option<&'static [f64]> {
+        match self {
+            Self::Auto => None,
+            Self::Chroma444 => Some(&[1.0, 1.0]),
+            Self::Chroma420 => Some(&[2.0, 2.0]),
+        }
+    }
+}
+
+impl FromStr for ChromaSubsampling {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(Self::Auto)
+        } else if s == "444" || s == "4:4:4" {
+            Ok(Self::Chroma444)
+        } else if s == "420" || s == "4:2:0" {
+            Ok(Self::Chroma420)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Display for ChromaSubsampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// What happens to the pitch of audio when its speed is changed.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub enum PitchMode {
+    /// Keep the original pitch, with the `atempo` filter.
+    #[default]
+    Preserve,
+    /// Let the pitch follow the speed, like a record played faster or slower.
+    Shift,
+}
+
+impl PitchMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Preserve => "Preserve",
+            Self::Shift => "Shift",
+        }
+    }
+}
+
+impl FromStr for PitchMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("preserve") {
+            Ok(Self::Preserve)
+        } else if s.eq_ignore_ascii_case("shift") {
+            Ok(Self::Shift)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Display for PitchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A photo of an album that's resized along with the one the command was used on.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AlbumPhoto {
+    pub message_id: i32,
+    pub width: u32,
+    pub height: u32,
+    /// What to resize this photo to. Signed for the same reason as in [`Task::ImageResize`].
+    pub new_dimensions: (i32, i32),
+    pub file: FileMeta,
+}
+
+/// A video or a GIF joined with others by [`Task::Concat`].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct VideoClip {
+    pub message_id: i32,
+    pub width: u32,
+    pub height: u32,
+    pub file: FileMeta,
+}
+
+impl VideoClip {
+    /// The video or the GIF in this message itself, not in the message it replies to.
+    pub fn from_message(message: &Message) -> Option<VideoClip> {
+        let (width, height, file) = if let Some(video) = message.video() {
+            (video.width, video.height, &video.file)
+        } else if let Some(animation) = message.animation() {
+            (animation.width, animation.height, &animation.file)
+        } else {
+            return None;
+        };
+
+        Some(VideoClip {
+            message_id: message.id.0,
+            width,
+            height,
+            file: file.clone(),
+        })
+    }
+}
+
+/// Color adjustments applied to resized media, in percentages. 100 means no change.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ColorAdjustments {
+    pub brightness: f64,
+    pub contrast: f64,
+    pub saturation: f64,
+    #[serde(default)]
+    pub grayscale: bool,
+}
+
+impl Default for ColorAdjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 100.0,
+            contrast: 100.0,
+            saturation: 100.0,
+            grayscale: false,
+        }
+    }
+}
+
+impl ColorAdjustments {
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Task {
     Amogus {
@@ -262,6 +624,18 @@ pub enum Task {
         resize_type: ResizeType,
         /// Between 1 and 100.
         quality: NonZeroU8,
+        #[serde(default)]
+        color: ColorAdjustments,
+        /// Only matters for JPEG output.
+        #[serde(default)]
+        chroma: ChromaSubsampling,
+        /// Keep EXIF and other metadata of the input image instead of stripping it.
+        #[serde(default)]
+        keep_metadata: bool,
+        /// All photos of the album the input photo is in, including it.
+        /// Empty if it's not in an album.
+        #[serde(default)]
+        album: Vec<AlbumPhoto>,
     },
     VideoResize {
         /// Signed integer to allow specifying negative resolutions
@@ -278,10 +652,99 @@ pub enum Task {
         type_pref: VideoTypePreference,
         /// Between 1 and 100.
         quality: NonZeroU8,
+        #[serde(default)]
+        color: ColorAdjustments,
+        /// Only this range of the video is kept, if set.
+        ///
+        /// Only resizing and distorting videos trims them, see
+        /// [`completion::media_processing::resize_video`]. There's no `reencode_video`
+        /// for plain re-encoding, so nothing else takes this into account.
+        #[serde(default)]
+        trim: Option<(Duration, Duration)>,
+        #[serde(default)]
+        container: VideoContainer,
+        /// How many times the video is played in a row in the output.
+        #[serde(default = "default_loops")]
+        loops: u8,
     },
     /// Optical Character Recognition, i.e. extracting text from an image
     Ocr,
-    AmenBreak,
+    AmenBreak {
+        /// Seed for picking the break, see [`pick_seed`]. Random if [`None`].
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    /// Vibrato and speed change applied to an audio file or a voice message.
+    DistortAudio {
+        vibrato_hz: f64,
+        vibrato_depth: f64,
+        speed: f64,
+    },
+    /// Speeding up or slowing down a video, a GIF or audio.
+    SpeedChange {
+        factor: f64,
+        #[serde(default)]
+        pitch: PitchMode,
+    },
+    /// Extracting the most dominant colors of an image.
+    Palette {
+        /// Between 2 and 16.
+        count: u8,
+    },
+    /// Cutting a range out of a video, a GIF or audio.
+    Trim {
+        start: Duration,
+        /// Until the end of the media if [`None`].
+        end: Option<Duration>,
+        /// Re-encode to cut exactly at the given timestamps,
+        /// instead of copying streams and cutting at the nearest keyframes.
+        precise: bool,
+    },
+    /// Making the audio of a video or an audio file louder.
+    Boost {
+        /// Normalize loudness with ffmpeg's `loudnorm` filter if [`None`].
+        gain_db: Option<f64>,
+    },
+    /// Grabbing a single frame of a video, or a contact sheet of evenly spaced frames.
+    Frame {
+        /// Which frame to grab. Unused if `grid` is set.
+        timestamp: Duration,
+        /// Columns and rows of frames in the contact sheet, if making one.
+        grid: Option<(u8, u8)>,
+    },
+    /// Inverting colors of an image, a video or a GIF.
+    Negate {
+        /// Invert transparency too. Only matters for images.
+        alpha: bool,
+    },
+    /// Sending an image, a video or a GIF back as is, but hidden under a spoiler.
+    Spoiler {
+        /// Empty for no caption.
+        caption: String,
+    },
+    /// Joining videos or GIFs one after another.
+    Concat {
+        /// The videos, in the order they're joined in. At least 2.
+        clips: Vec<VideoClip>,
+    },
+}
+
+/// Tasks from before looping was a thing play their video once.
+fn default_loops() -> u8 {
+    1
+}
+
+/// Seed for the randomness of a task, from its `seed` parameter, or a new random one if
+/// that's not set. It should be shown to the user, so that they can get the same result again.
+pub fn pick_seed(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(rand::random)
+}
+
+/// Random number generator for a task, which gives the same numbers for the same seed.
+/// See [`pick_seed`].
+pub fn seeded_rng(seed: u64) -> rand::rngs::StdRng {
+    use rand::SeedableRng;
+    rand::rngs::StdRng::seed_from_u64(seed)
 }
 
 impl Task {
@@ -340,6 +803,10 @@ impl Task {
                 resize_curve: _,
                 type_pref: _,
                 quality,
+                color,
+                trim: _,
+                container: _,
+                loops: _,
             }
             | Task::ImageResize {
                 new_dimensions,
@@ -348,6 +815,10 @@ impl Task {
                 format: _,
                 resize_type,
                 quality,
+                color,
+                chroma: _,
+                keep_metadata: _,
+                album: _,
             } => {
                 if let ResizeType::ToSticker | ResizeType::ToCustomEmoji = resize_type {
                     return Ok(());
@@ -355,8 +826,32 @@ impl Task {
 
                 write_header!();
 
-                if let Task::ImageResize { format, .. } = self {
+                if let ResizeType::ToCustomEmojiGrid { columns, rows } = resize_type {
+                    return writeln!(output, "<b>Grid</b>: {}x{}", columns, rows);
+                }
+
+                if let ResizeType::Mirror { .. } | ResizeType::Kaleidoscope { .. } = resize_type {
+                    return write_param!("Effect", resize_type);
+                }
+
+                if let Task::ImageResize {
+                    format,
+                    chroma,
+                    keep_metadata,
+                    album,
+                    ..
+                } = self
+                {
+                    if album.len() > 1 {
+                        writeln!(output, "<b>Album</b>: {} photos", album.len())?;
+                    }
                     write_param!("Format", format)?;
+                    if *chroma != ChromaSubsampling::Auto {
+                        write_param!("Chroma subsampling", chroma)?;
+                    }
+                    if *keep_metadata {
+                        writeln!(output, "<b>Metadata</b>: kept")?;
+                    }
                 }
                 if *resize_type == ResizeType::Fit {
                     write!(
@@ -383,19 +878,119 @@ impl Task {
                     vibrato_depth,
                     resize_curve,
                     type_pref,
+                    trim,
+                    container,
+                    loops,
                     ..
                 } = self
                 {
                     write_param!("Media type", type_pref)?;
+                    write_param!("Container", container)?;
                     wp!(vibrato_hz)?;
                     wp!(vibrato_depth)?;
                     write_param!("Resize curve", resize_curve)?;
+                    if let Some((start, end)) = trim {
+                        writeln!(
+                            output,
+                            "<b>Trim</b>: {}-{}",
+                            format_timestamp(*start),
+                            format_timestamp(*end)
+                        )?;
+                    }
+                    if *loops > 1 {
+                        writeln!(output, "<b>Loops</b>: {}", loops)?;
+                    }
                 };
 
+                if !color.is_noop() {
+                    writeln!(output, "<b>Brightness</b>: {}%", color.brightness)?;
+                    writeln!(output, "<b>Contrast</b>: {}%", color.contrast)?;
+                    writeln!(output, "<b>Saturation</b>: {}%", color.saturation)?;
+                    if color.grayscale {
+                        writeln!(output, "<b>Grayscale</b>: yes")?;
+                    }
+                }
+
                 writeln!(output, "<b>Quality</b>: {}%", quality)
             }
             Task::Ocr => Ok(()),
-            Task::AmenBreak => Ok(()),
+            Task::AmenBreak { seed } => {
+                write_header!();
+                if let Some(seed) = seed {
+                    writeln!(output, "<b>Seed</b>: {}", seed)
+                } else {
+                    writeln!(output, "<b>Seed</b>: random")
+                }
+            }
+            Task::DistortAudio {
+                vibrato_hz,
+                vibrato_depth,
+                speed,
+            } => {
+                write_header!();
+                wp!(vibrato_hz)?;
+                wp!(vibrato_depth)?;
+                wp!(speed)
+            }
+            Task::SpeedChange { factor, pitch } => {
+                write_header!();
+                writeln!(output, "<b>Speed</b>: {}x", factor)?;
+                write_param!("Pitch", pitch)
+            }
+            Task::Palette { count } => {
+                write_header!();
+                write_param!("Colors", count)
+            }
+            Task::Trim {
+                start,
+                end,
+                precise,
+            } => {
+                write_header!();
+                writeln!(output, "<b>Start</b>: {}", format_timestamp(*start))?;
+                if let Some(end) = end {
+                    writeln!(output, "<b>End</b>: {}", format_timestamp(*end))?;
+                } else {
+                    writeln!(output, "<b>End</b>: end of the media")?;
+                }
+                wp!(precise)
+            }
+            Task::Boost { gain_db } => {
+                write_header!();
+                if let Some(gain_db) = gain_db {
+                    writeln!(output, "<b>Gain</b>: {:+}dB", gain_db)
+                } else {
+                    writeln!(output, "<b>Gain</b>: automatic (loudness normalization)")
+                }
+            }
+            Task::Frame { timestamp, grid } => {
+                write_header!();
+                if let Some((columns, rows)) = grid {
+                    writeln!(output, "<b>Grid</b>: {}x{}", columns, rows)
+                } else {
+                    writeln!(output, "<b>Timestamp</b>: {}", format_timestamp(*timestamp))
+                }
+            }
+            Task::Negate { alpha } => {
+                write_header!();
+                write_param!("Invert transparency", alpha)
+            }
+            Task::Spoiler { caption } => {
+                write_header!();
+                if caption.is_empty() {
+                    writeln!(output, "<b>Caption</b>: none")
+                } else {
+                    writeln!(
+                        output,
+                        "<b>Caption</b>: {}",
+                        html_escape::encode_text(caption)
+                    )
+                }
+            }
+            Task::Concat { clips } => {
+                write_header!();
+                writeln!(output, "<b>Videos</b>: {}", clips.len())
+            }
         }
     }
 
@@ -405,6 +1000,7 @@ impl Task {
         &self,
         queue_size: Option<u32>,
         progress_info: Option<&str>,
+        lang: Lang,
     ) -> String {
         //let mut response = if queue_size == 0 {
         //    if let Some(progress) = progress_info {
@@ -419,19 +1015,22 @@ impl Task {
         let mut response = match queue_size {
             Some(0) => {
                 if let Some(progress) = progress_info {
-                    format!("Working on your task now... {}\n", progress)
+                    format!("{} {}\n", Key::QueueWorking.get(lang), progress)
                 } else {
-                    String::from("Working on your task now...\n")
+                    format!("{}\n", Key::QueueWorking.get(lang))
                 }
             }
-            Some(s) => format!("Task accepted. Position in queue: {}\n", s),
-            None => "Task accepted. Waiting for this chat's slow mode...\n".to_string(),
+            Some(s) => format!("{}\n", fill(Key::QueuePosition.get(lang), &[&s])),
+            None => format!("{}\n", Key::QueueSlowMode.get(lang)),
         };
 
         self.write_params(&mut response, true, queue_size != Some(0))
             .unwrap();
 
-        response += "\n<a href=\"https://boosty.to/architector_4\">(Consider supporting? 👉👈)</a>";
+        response += &format!(
+            "\n<a href=\"https://boosty.to/architector_4\">{}</a>",
+            Key::SupportLink.get(lang)
+        );
         response
     }
 }
@@ -449,6 +1048,10 @@ impl Task {
             format: ImageFormat::Webp,
             resize_type: ResizeType::ToSticker,
             quality: NonZeroU8::new(92).unwrap(),
+            color: ColorAdjustments::default(),
+            chroma: ChromaSubsampling::default(),
+            keep_metadata: false,
+            album: Vec::new(),
         }
     }
     pub fn default_to_custom_emoji() -> Task {
@@ -459,6 +1062,24 @@ impl Task {
             format: ImageFormat::Webp,
             resize_type: ResizeType::ToCustomEmoji,
             quality: NonZeroU8::new(92).unwrap(),
+            color: ColorAdjustments::default(),
+            chroma: ChromaSubsampling::default(),
+            keep_metadata: false,
+            album: Vec::new(),
+        }
+    }
+    pub fn default_to_custom_emoji_grid(columns: u8, rows: u8) -> Task {
+        Task::ImageResize {
+            new_dimensions: (100 * columns as i32, 100 * rows as i32),
+            rotation: 0.0,
+            percentage: None,
+            format: ImageFormat::Webp,
+            resize_type: ResizeType::ToCustomEmojiGrid { columns, rows },
+            quality: NonZeroU8::new(92).unwrap(),
+            color: ColorAdjustments::default(),
+            chroma: ChromaSubsampling::default(),
+            keep_metadata: false,
+            album: Vec::new(),
         }
     }
     pub fn default_amogus() -> Task {
@@ -477,6 +1098,10 @@ impl Task {
             format,
             resize_type,
             quality: NonZeroU8::new(92).unwrap(),
+            color: ColorAdjustments::default(),
+            chroma: ChromaSubsampling::default(),
+            keep_metadata: false,
+            album: Vec::new(),
         }
     }
     pub fn default_video_resize(
@@ -503,12 +1128,260 @@ impl Task {
             resize_curve: ResizeCurve::default(),
             type_pref,
             quality: NonZeroU8::new(100).unwrap(),
+            color: ColorAdjustments::default(),
+            trim: None,
+            container: VideoContainer::default(),
+            loops: default_loops(),
+        }
+    }
+    /// Makes a video resize default to WebM instead of MP4, for inputs
+    /// like video stickers that would lose their transparency otherwise.
+    /// Parsed parameters can still ask for MP4.
+    pub fn prefer_webm(mut self) -> Task {
+        if let Task::VideoResize { container, .. } = &mut self {
+            *container = VideoContainer::Webm;
+        }
+        self
+    }
+    /// Makes a resize use this quality, unless parameters say otherwise.
+    /// Returns [`None`] if this task has no quality to speak of.
+    pub fn with_default_quality(&self, new_quality: NonZeroU8) -> Option<Task> {
+        let mut task = self.clone();
+        match &mut task {
+            Task::ImageResize { quality, .. } | Task::VideoResize { quality, .. } => {
+                *quality = new_quality;
+                Some(task)
+            }
+            _ => None,
+        }
+    }
+    /// Makes a resize start off of the parameters of the `previous` resize,
+    /// for media that was made by it, keeping the dimensions of this one.
+    ///
+    /// Returns [`None`] if `previous` isn't the same kind of resize of the same kind of media.
+    pub fn inherit_resize(&self, previous: &Task) -> Option<Task> {
+        let same_kind = |a: &ResizeType, b: &ResizeType| {
+            let plain = |x: &ResizeType| {
+                matches!(x, ResizeType::Fit | ResizeType::Stretch | ResizeType::Crop)
+            };
+            (a.is_seam_carve() && b.is_seam_carve()) || (plain(a) && plain(b))
+        };
+
+        let mut task = previous.clone();
+        match (self, &mut task) {
+            (
+                Task::ImageResize {
+                    new_dimensions,
+                    resize_type,
+                    ..
+                },
+                Task::ImageResize {
+                    new_dimensions: previous_dimensions,
+                    percentage,
+                    resize_type: previous_type,
+                    album,
+                    ..
+                },
+            ) if same_kind(resize_type, previous_type) => {
+                *previous_dimensions = *new_dimensions;
+                *percentage = Some(100.0);
+                album.clear();
+            }
+            (
+                Task::VideoResize {
+                    new_dimensions,
+                    resize_type,
+                    ..
+                },
+                Task::VideoResize {
+                    new_dimensions: previous_dimensions,
+                    percentage,
+                    resize_type: previous_type,
+                    trim,
+                    ..
+                },
+            ) if same_kind(resize_type, previous_type) => {
+                *previous_dimensions = *new_dimensions;
+                *percentage = Some(100.0);
+                // The media it made is already trimmed.
+                *trim = None;
+            }
+            _ => return None,
+        }
+        Some(task)
+    }
+    /// The size parameter to resize media by as much as this resize did,
+    /// like `50%`, or its exact output size if it wasn't by a percentage.
+    pub fn repeat_size_param(&self) -> Option<String> {
+        match self {
+            Task::ImageResize {
+                new_dimensions,
+                percentage,
+                ..
+            }
+            | Task::VideoResize {
+                new_dimensions,
+                percentage,
+                ..
+            } => Some(match percentage {
+                Some(percentage) => format!("{}%", percentage),
+                None => format!("{}x{}", new_dimensions.0, new_dimensions.1),
+            }),
+            _ => None,
         }
     }
     pub fn default_ocr() -> Task {
         Task::Ocr
     }
     pub fn default_amenbreak() -> Task {
-        Task::AmenBreak
+        Task::AmenBreak { seed: None }
+    }
+    pub fn default_distort_audio() -> Task {
+        Task::DistortAudio {
+            vibrato_hz: 7.0,
+            vibrato_depth: 1.0,
+            speed: 1.0,
+        }
+    }
+    pub fn default_speed_change() -> Task {
+        Task::SpeedChange {
+            factor: 2.0,
+            pitch: PitchMode::default(),
+        }
+    }
+    pub fn default_palette() -> Task {
+        Task::Palette { count: 5 }
+    }
+    pub fn default_trim() -> Task {
+        Task::Trim {
+            start: Duration::ZERO,
+            end: None,
+            precise: false,
+        }
+    }
+    pub fn default_boost() -> Task {
+        Task::Boost { gain_db: None }
+    }
+    pub fn default_frame() -> Task {
+        Task::Frame {
+            timestamp: Duration::ZERO,
+            grid: None,
+        }
+    }
+    pub fn default_frames() -> Task {
+        Task::Frame {
+            timestamp: Duration::ZERO,
+            grid: Some((3, 3)),
+        }
+    }
+    pub fn default_negate() -> Task {
+        Task::Negate { alpha: false }
+    }
+    pub fn default_spoiler() -> Task {
+        Task::Spoiler {
+            caption: String::new(),
+        }
+    }
+    pub fn default_concat() -> Task {
+        Task::Concat { clips: Vec::new() }
+    }
+}
+
+/// Format a timestamp like "1:02:03.5", "2:03" or "0:05".
+pub fn format_timestamp(timestamp: Duration) -> String {
+    let total_seconds = timestamp.as_secs();
+    let (hours, minutes, seconds) = (
+        total_seconds / 3600,
+        total_seconds / 60 % 60,
+        total_seconds % 60,
+    );
+
+    let mut output = if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    };
+
+    let millis = timestamp.subsec_millis();
+    if millis > 0 {
+        let fraction = format!(".{:03}", millis);
+        output.push_str(fraction.trim_end_matches('0'));
+    }
+
+    output
+}
+
+#[test]
+fn format_timestamp_test() {
+    assert_eq!(format_timestamp(Duration::ZERO), "0:00");
+    assert_eq!(format_timestamp(Duration::from_secs(5)), "0:05");
+    assert_eq!(format_timestamp(Duration::from_secs(123)), "2:03");
+    assert_eq!(
+        format_timestamp(Duration::from_millis(3723500)),
+        "1:02:03.5"
+    );
+    assert_eq!(format_timestamp(Duration::from_millis(1250)), "0:01.25");
+}
+
+#[test]
+fn resize_curve_test() {
+    let approx = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    // Frame 50 of 100 is halfway through.
+    let at = |curve: &ResizeCurve, frame: usize| curve.apply_resize_for(frame, 100, 0.0, 1.0);
+
+    for curve in ["easein", "ease-out", "Ease_In_Out", "bounce"] {
+        let curve: ResizeCurve = curve.parse().unwrap();
+        assert!(approx(at(&curve, 0), 0.0), "{}", curve);
+        assert!(approx(at(&curve, 100), 1.0), "{}", curve);
+    }
+    assert!(approx(at(&ResizeCurve::EaseIn, 50), 0.25));
+    assert!(approx(at(&ResizeCurve::EaseOut, 50), 0.75));
+    assert!(approx(at(&ResizeCurve::EaseInOut, 25), 0.125));
+    assert!(approx(at(&ResizeCurve::EaseInOut, 50), 0.5));
+    assert!(approx(at(&ResizeCurve::EaseInOut, 75), 0.875));
+
+    let custom: ResizeCurve = "custom:0=100%,50=20%,100=150%".parse().unwrap();
+    assert_eq!(
+        custom,
+        ResizeCurve::Custom(vec![(0.0, 100.0), (50.0, 20.0), (100.0, 150.0)])
+    );
+    assert_eq!(custom.to_string(), "Custom: 0%=100%, 50%=20%, 100%=150%");
+
+    // Exactly on keyframes.
+    assert!(approx(at(&custom, 0), 1.0));
+    assert!(approx(at(&custom, 50), 0.2));
+    assert!(approx(at(&custom, 100), 1.5));
+    // Between them.
+    assert!(approx(at(&custom, 25), 0.6));
+    assert!(approx(at(&custom, 75), 0.85));
+    // Blends between the start and end values.
+    assert!(approx(
+        custom.apply_resize_for(50, 100, 100.0, 200.0),
+        120.0
+    ));
+
+    // Before the first and after the last keyframe, their values are kept.
+    let custom: ResizeCurve = "CUSTOM:20=0,80%=100%".parse().unwrap();
+    assert!(approx(at(&custom, 0), 0.0));
+    assert!(approx(at(&custom, 10), 0.0));
+    assert!(approx(at(&custom, 50), 0.5));
+    assert!(approx(at(&custom, 90), 1.0));
+    assert!(approx(at(&custom, 100), 1.0));
+
+    for bad in [
+        "custom:",
+        "custom:50",
+        "custom:50=",
+        "custom:150=50%",
+        "custom:50=-10%",
+        "custom:50=250%",
+        "custom:50=10%,50=20%",
+        "custom:60=10%,40=20%",
+        "custom:nan=10%",
+        "rising:0=10%",
+        "sideways",
+    ] {
+        assert!(bad.parse::<ResizeCurve>().is_err(), "{}", bad);
     }
 }
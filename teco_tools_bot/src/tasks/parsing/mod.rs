@@ -1,14 +1,35 @@
 pub mod tokenizer;
 
 use super::*;
+use crate::strings::{fill, localize_help, Key, Lang};
 use html_escape::encode_text;
 use tokenizer::{Token, Tokenizer};
 
 pub static MAX_OUTPUT_MEDIA_DIMENSION_SIZE: u32 = 2048;
+/// Maximum amount of columns or rows in a grid of custom emoji.
+pub static MAX_CUSTOM_EMOJI_GRID_SIZE: u8 = 8;
+/// Range of how many colors `/palette` can extract.
+pub static PALETTE_COLOR_RANGE: std::ops::RangeInclusive<u8> = 2..=16;
+/// Range of how many segments `/kaleidoscope` can make. Only even numbers are allowed.
+pub static KALEIDOSCOPE_SEGMENT_RANGE: std::ops::RangeInclusive<u8> = 2..=16;
+/// Range of gain in decibels `/boost` can apply. Anything louder is just noise.
+pub static BOOST_GAIN_RANGE_DB: std::ops::RangeInclusive<f64> = -20.0..=30.0;
+/// Maximum amount of columns or rows in a contact sheet made by `/frames`.
+pub static MAX_FRAME_GRID_SIZE: u8 = 6;
+/// How many times a video can be played in a row with the `loop` parameter.
+pub static LOOP_COUNT_RANGE: std::ops::RangeInclusive<u8> = 1..=20;
+/// Telegram doesn't allow media captions longer than this many characters.
+pub static MAX_CAPTION_LENGTH: usize = 1024;
 
 #[derive(Debug)]
 pub enum TaskError {
     Error(String),
+    /// Same as [`TaskError::Error`], but for output media that would be too big.
+    /// Can be fixed by asking for the biggest allowed size instead.
+    TooBig(String),
+    /// Same as [`TaskError::Error`], but for parameters that couldn't be parsed.
+    /// Can be helped with by showing all the parameters of the command.
+    BadParam(String),
     Descriptory(String),
     Cancel(String),
 }
@@ -23,7 +44,7 @@ impl TaskError {
     }
     pub fn is_empty(&self) -> bool {
         match self {
-            Self::Error(e) => e.is_empty(),
+            Self::Error(e) | Self::TooBig(e) | Self::BadParam(e) => e.is_empty(),
             Self::Descriptory(d) => d.is_empty(),
             Self::Cancel(c) => c.is_empty(),
         }
@@ -33,32 +54,36 @@ impl TaskError {
     }
 }
 
-impl std::fmt::Display for TaskError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl TaskError {
+    /// Text of this error to show to the user, in this language.
+    /// The message inside is used as is.
+    pub fn localized(&self, lang: Lang) -> String {
         if self.is_empty() {
-            Ok(())
-        } else {
-            match self {
-                Self::Error(e) => {
-                    writeln!(f, "Error: {}", e)
-                }
-                Self::Cancel(c) => {
-                    writeln!(f, "Cancelling task: {}", c)
-                }
-                Self::Descriptory(d) => {
-                    writeln!(f, "{}", d)
-                }
+            return String::new();
+        }
+        match self {
+            Self::Error(e) | Self::TooBig(e) | Self::BadParam(e) => {
+                format!("{}{}\n", Key::ErrorPrefix.get(lang), e)
             }
+            Self::Cancel(c) => format!("{}{}\n", Key::CancelPrefix.get(lang), c),
+            Self::Descriptory(d) => format!("{}\n", d),
         }
     }
 }
 
+impl std::fmt::Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.localized(Lang::En))
+    }
+}
+
 /// Returns true if this isn't a plain parameter,
 /// false if it is but failed to parse, or continues if it succeeds.
 macro_rules! parse_plain_param_with_parser_optional {
     ($input: expr, $name: expr, $parser: expr) => {{
         #[allow(clippy::redundant_closure_call)]
-        if let Token::Plain(value) = $input {
+        if let Token::Plain(value) = &$input {
+            let value: &str = value;
             if let Ok(value) = $parser(value) {
                 $name = value;
                 continue;
@@ -79,9 +104,10 @@ macro_rules! parse_plain_param_optional {
 macro_rules! parse_plain_param_with_parser_mandatory {
     ($input: expr, $name: expr, $parser: expr, $help: expr) => {
         #[allow(clippy::redundant_closure_call)]
-        if let Token::Plain(value) = $input {
+        if let Token::Plain(value) = &$input {
+            let value: &str = value;
             let Ok(value) = $parser(value) else {
-                return Err(TaskError::Error(format!(
+                return Err(TaskError::BadParam(format!(
                     "the value <code>{}</code> is incorrect for parameter <code>{}</code>.\n{}",
                     encode_text(value),
                     encode_text(stringify!($name)),
@@ -101,19 +127,25 @@ macro_rules! parse_plain_param {
 
 macro_rules! parse_keyval_param_with_parser {
     ($input: expr, $name: expr, $parser: expr, $help: expr) => {
-        let (key, value) = match $input {
+        let (key, value): (&str, &str) = match &$input {
             Token::KeyVal(key, value) => (key, value),
             Token::Plain(plain) => {
-                return Err(TaskError::Error(format!(
+                return Err(TaskError::BadParam(format!(
                     "can't parse <code>{}</code> as a parameter.\n{}",
                     encode_text(plain),
                     $help
                 )));
             }
+            Token::Unterminated(snippet) => return Err(unterminated_quote_error(snippet, $help)),
         };
 
         if key == stringify!($name).to_lowercase().trim_start_matches("r#") {
-            parse_plain_param_with_parser_mandatory!(Token::Plain(value), $name, $parser, $help);
+            parse_plain_param_with_parser_mandatory!(
+                Token::Plain(value.into()),
+                $name,
+                $parser,
+                $help
+            );
             continue;
         }
     };
@@ -126,7 +158,7 @@ macro_rules! parse_keyval_param {
 }
 macro_rules! parse_stop {
     ($input: expr, $help: expr) => {
-        let response = match $input {
+        let response = match &$input {
             Token::KeyVal(key, val) => format!(
                 "unexpected parameter <code>{}</code> with value <code>{}</code>\n{}",
                 encode_text(key),
@@ -138,12 +170,21 @@ macro_rules! parse_stop {
                 encode_text(plain),
                 $help
             ),
+            Token::Unterminated(snippet) => return Err(unterminated_quote_error(snippet, $help)),
         };
 
-        return Err(TaskError::Error(response));
+        return Err(TaskError::BadParam(response));
     };
 }
 
+fn unterminated_quote_error(snippet: &str, help: &str) -> TaskError {
+    TaskError::Error(format!(
+        "a quote is never closed in <code>{}</code>.\n{}",
+        encode_text(snippet),
+        help
+    ))
+}
+
 impl Task {
     pub fn param_help(&self) -> &'static str {
         match self {
@@ -160,7 +201,40 @@ impl Task {
             }
             Task::ImageResize { resize_type, ..} | Task::VideoResize { resize_type, ..}=> {
                 match resize_type {
-                    ResizeType::ToSticker | ResizeType::ToCustomEmoji => "",
+                    ResizeType::ToSticker => "",
+                    ResizeType::ToCustomEmoji | ResizeType::ToCustomEmojiGrid { .. } =>
+                        concat!(
+                            "<b>Possible parameters for this command:</b>\n",
+                            "<code>grid</code>: Split the image into a grid of this many custom emoji, ",
+                            "as <code>COLUMNSxROWS</code>. Can't be bigger than 8x8. Default is 1x1.\n",
+                            "\n\n",
+                            "<b>Examples:</b>\n",
+                            "• <code>/to_custom_emoji</code>\n",
+                            "• <code>/to_custom_emoji grid:3x3</code>\n",
+                            "• <code>/to_custom_emoji grid:4x2</code>\n",
+                            ),
+                    ResizeType::Mirror { .. } =>
+                        concat!(
+                            "<b>Possible parameters for this command:</b>\n",
+                            "<code>direction</code>: Which way to mirror the media. Can be \"h\" for horizontally ",
+                            "(default for /mirror), \"v\" for vertically (default for /flip) or \"both\".\n",
+                            "\n\n",
+                            "<b>Examples:</b>\n",
+                            "• <code>/mirror</code>\n",
+                            "• <code>/mirror v</code> (same as <code>/flip</code>)\n",
+                            "• <code>/flip both</code>\n",
+                            ),
+                    ResizeType::Kaleidoscope { .. } =>
+                        concat!(
+                            "<b>Possible parameters for this command:</b>\n",
+                            "<code>segments</code>: How many mirrored copies of a wedge of the media to make. ",
+                            "Can only be an even number between 2 and 16. Default is 4.\n",
+                            "\n\n",
+                            "<b>Examples:</b>\n",
+                            "• <code>/kaleidoscope</code>\n",
+                            "• <code>/kaleidoscope 8</code>\n",
+                            "• <code>/kaleidoscope segments:6</code>\n",
+                            ),
                     ResizeType::SeamCarve { .. } =>
                         concat!(
                             "<b>Possible parameters for this command:</b>\n",
@@ -180,16 +254,33 @@ impl Task {
                             "Can't be less than -1024 or bigger than 1024.\n",
                             "<code>quality</code>: Quality level, between 1% and 100%. ",
                             "For videos, this compresses each frame to JPG before encoding to create a compressed effect.\n",
+                            "<code>brightness</code>, <code>contrast</code>, <code>saturation</code>: ",
+                            "Color adjustments, between 0% and 200%. Default is 100%, meaning no change.\n",
+                            "<code>bw</code>: Make the media grayscale, before applying quality.\n",
                             "\n",
                             "Only for images:\n",
-                            "<code>format</code>: Output image format. Can be \"webp\" or \"jpg\".\n",
+                            "<code>format</code>: Output image format. Can be \"webp\", \"jpg\" or \"avif\".\n",
                             "\n",
                             "Only for videos:\n",
                             "<code>vibrato_hz</code>: Frequency of vibrato applied to audio. ",
                             "Can only be between 0.1 or 20000.0. Default is 7.\n",
                             "<code>vibrato_depth</code>: Vibrato depth. Can only be between 0.0 and 1000.0. Default is 1.\n",
                             "<code>curve</code>: Curve that defines the blend between original and distorted size and rotation. ",
-                            "Can be \"constant\" (default), \"rising\", \"falling\", \"loop\" or \"loopb\".\n",
+                            "Can be \"constant\" (default), \"rising\", \"falling\", \"loop\", \"loopb\", ",
+                            "\"easein\", \"easeout\", \"easeinout\", \"bounce\", or keyframes like ",
+                            "<code>curve:custom:0=100%,50=20%,100=150%</code>, meaning the blend is 100% at the start, ",
+                            "20% halfway through, and 150% at the end. Blends can be between 0% and 200%.\n",
+                            "<code>trim</code>: Keep only this range of the video, like <code>0:05,0:12</code>.\n",
+                            "<code>loop</code>: Play the video this many times in a row, between 1 and 20. ",
+                            "Can't be used together with <code>trim</code>.\n",
+                            "<code>container</code>: Can be \"mp4\" (default) or \"webm\" (default for video stickers), which is sent as a file ",
+                            "and keeps the transparency of video stickers at 100% quality.\n",
+                            "\n",
+                            "Also works on audio and voice messages, applying just the vibrato. ",
+                            "Send <code>/distort help</code> as a reply to one to see parameters for that.\n",
+                            "\n",
+                            "Used on media this bot made, it starts off of the parameters that made it, ",
+                            "so a plain <code>/distort</code> distorts it again by as much.\n",
                             "\n\n",
                             "<b>Examples:</b>\n",
                             "• <code>/distort</code> (same as <code>/distort 50%</code> or <code>/distort 50%x50%</code>)\n",
@@ -197,7 +288,9 @@ impl Task {
                             "• <code>/distort 1:1 50% delta_x:4 rigidity:-50</code>\n",
                             "• <code>/distort 200%x50% rot:45deg vibrato_hz:220</code> (videos only)\n",
                             "• <code>/distort 10% rising</code> (videos only)\n",
+                            "• <code>/distort trim:1:30,1:45</code> (videos only)\n",
                             "• <code>/distort 30%x-512 45deg webp</code> (images only)\n",
+                            "• <code>/distort bw</code>\n",
                             ),
                     ResizeType::Stretch | ResizeType::Fit | ResizeType::Crop =>
                         concat!(
@@ -215,16 +308,35 @@ impl Task {
                             "<code>method</code>: Resize method. Can only be \"fit\" (default), \"stretch\" or \"crop\".\n",
                             "<code>quality</code>: Quality level, between 1% and 100%. ",
                             "For videos, this compresses each frame to JPG before encoding to create a compressed effect.\n",
+                            "<code>brightness</code>, <code>contrast</code>, <code>saturation</code>: ",
+                            "Color adjustments, between 0% and 200%. Default is 100%, meaning no change.\n",
+                            "<code>bw</code>: Make the media grayscale, before applying quality.\n",
                             "\n",
                             "Only for images:\n",
-                            "<code>format</code>: Output image format. Can be \"webp\" or \"jpg\".\n",
+                            "<code>format</code>: Output image format. Can be \"webp\", \"jpg\" or \"avif\".\n",
+                            "<code>chroma</code>: Chroma subsampling for JPEG output. Can be \"444\" to keep colors sharp, ",
+                            "\"420\" to save space, or \"auto\" (default).\n",
+                            "<code>keepmeta</code>: Keep EXIF and other metadata of the image, like location and camera info. ",
+                            "It's removed by default.\n",
+                            "Replying to a photo in an album resizes every photo in it by the same amount.\n",
                             "\n",
                             "Only for videos:\n",
                             "<code>vibrato_hz</code>: Frequency of vibrato applied to audio. ",
                             "Can only be between 0.1 or 20000.0. Default is 7.\n",
                             "<code>vibrato_depth</code>: Vibrato depth. Can only be between 0.0 and 1000.0. Default is 0.\n",
                             "<code>curve</code>: Curve that defines the blend between original and distorted size and rotation. ",
-                            "Can be \"constant\" (default), \"rising\", \"falling\", \"loop\" or \"loopb\".\n",
+                            "Can be \"constant\" (default), \"rising\", \"falling\", \"loop\", \"loopb\", ",
+                            "\"easein\", \"easeout\", \"easeinout\", \"bounce\", or keyframes like ",
+                            "<code>curve:custom:0=100%,50=20%,100=150%</code>, meaning the blend is 100% at the start, ",
+                            "20% halfway through, and 150% at the end. Blends can be between 0% and 200%.\n",
+                            "<code>trim</code>: Keep only this range of the video, like <code>0:05,0:12</code>.\n",
+                            "<code>loop</code>: Play the video this many times in a row, between 1 and 20. ",
+                            "Can't be used together with <code>trim</code>.\n",
+                            "<code>container</code>: Can be \"mp4\" (default) or \"webm\" (default for video stickers), which is sent as a file ",
+                            "and keeps the transparency of video stickers at 100% quality.\n",
+                            "\n",
+                            "Used on media this bot made, it starts off of the parameters that made it, ",
+                            "so a plain <code>/resize</code> resizes it again by as much.\n",
                             "\n\n",
                             "<b>Examples:</b>\n",
                             "• <code>/resize</code> (same as <code>/resize 50%</code> or <code>/resize 50%x50%</code>)\n",
@@ -232,41 +344,220 @@ impl Task {
                             "• <code>/resize 16:9 crop</code>\n",
                             "• <code>/resize 200%x100% stretch</code>\n",
                             "• <code>/resize 100% 360deg rising</code> (videos only)\n",
+                            "• <code>/resize 200% curve:custom:0=0,50=100%,100=0</code> (videos only)\n",
+                            "• <code>/resize trim:0:05,0:12</code> (videos only)\n",
+                            "• <code>/resize loop:4</code> (videos only)\n",
+                            "• <code>/resize 50% container:webm</code> (videos only)\n",
                             "• <code>/resize 30%x-512 45deg webp</code> (images only)\n",
+                            "• <code>/resize 512x512 avif</code> (images only)\n",
+                            "• <code>/resize jpg chroma:444</code> (images only)\n",
+                            "• <code>/resize 100% keepmeta</code> (images only)\n",
+                            "• <code>/resize saturation:200 brightness:80</code>\n",
+                            "• <code>/resize bw 90deg quality:20</code>\n",
                             ),
                 }
             },
         Task::Ocr => "",
-        Task::AmenBreak => ""
+        Task::AmenBreak { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>seed</code>: A number that decides which amen break is picked. ",
+            "The same seed picks the same break. Default is a random one, ",
+            "which is shown with the result.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/amenbreak</code>\n",
+            "• <code>/amenbreak 1234</code>\n",
+            "• <code>/amenbreak seed:1234</code>\n",
+            ),
+        Task::DistortAudio { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>vibrato_hz</code>: Frequency of vibrato applied to audio. ",
+            "Can only be between 0.1 or 20000.0. Default is 7.\n",
+            "<code>vibrato_depth</code>: Vibrato depth. Can only be between 0.0 and 1000.0. Default is 1.\n",
+            "<code>speed</code>: Playback speed multiplier. Can only be between 0.5 and 4.0. Default is 1.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/distort</code>\n",
+            "• <code>/distort vibrato_hz:220 vibrato_depth:3</code>\n",
+            "• <code>/distort speed:1.5</code>\n",
+            ),
+        Task::Palette { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>count</code>: How many colors to extract. ",
+            "Can only be between 2 and 16. Default is 5.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/palette</code>\n",
+            "• <code>/palette 8</code>\n",
+            "• <code>/palette count:3</code>\n",
+            ),
+        Task::Trim { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>START-END</code>: Range to keep, as timestamps like <code>1:02:03.5</code>, ",
+            "<code>0:05</code> or plain seconds. Either side can be left out ",
+            "to trim from the start or until the end.\n",
+            "<code>start</code>: Timestamp to start at. Default is the start of the media.\n",
+            "<code>end</code>: Timestamp to end at. Default is the end of the media.\n",
+            "<code>duration</code>: How long the result should be, instead of specifying the end.\n",
+            "<code>precise</code>: If \"true\", re-encode the video to cut exactly at the timestamps. ",
+            "Otherwise, the cut happens at the nearest keyframes, which is much faster. Default is false.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/trim 0:05-0:37</code>\n",
+            "• <code>/trim 10-</code> (everything after 10 seconds)\n",
+            "• <code>/trim start:1:30 duration:15 precise:true</code>\n",
+            ),
+        Task::SpeedChange { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>factor</code>: How many times faster the media should play, ",
+            "optionally followed by \"x\". Can only be between 0.1 and 10. Default is 2.\n",
+            "<code>pitch</code>: Can be \"preserve\" to keep the original pitch of the audio, ",
+            "or \"shift\" to have it go up or down along with the speed. Default is preserve.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/speed</code> (same as <code>/speed 2x</code>)\n",
+            "• <code>/speed 0.5</code>\n",
+            "• <code>/speed factor:3.5x</code>\n",
+            "• <code>/speed 1.5x pitch:shift</code>\n",
+            ),
+        Task::Boost { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>gain</code>: How many decibels louder the audio should be, ",
+            "optionally followed by \"dB\". Can only be between -20 and 30. ",
+            "Default is \"auto\", which normalizes the loudness instead.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/boost</code>\n",
+            "• <code>/boost 10dB</code>\n",
+            "• <code>/boost gain:-3</code>\n",
+            ),
+        Task::Frame { grid: None, .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>at</code>: Timestamp of the frame, like <code>1:02:03.5</code>, ",
+            "<code>0:05</code> or plain seconds. Default is the first frame.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/frame</code>\n",
+            "• <code>/frame 0:42</code>\n",
+            "• <code>/frame at:12.5</code>\n",
+            ),
+        Task::Frame { grid: Some(_), .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>grid</code>: How many columns and rows of evenly spaced frames to put ",
+            "in the contact sheet. Each can only be between 1 and 6. Default is 3x3.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/frames</code>\n",
+            "• <code>/frames 4x2</code>\n",
+            "• <code>/frames grid:2x3</code>\n",
+            ),
+        Task::Negate { .. } => concat!(
+            "<b>Possible parameters for this command:</b>\n",
+            "<code>alpha</code>: Invert transparency too, making see-through parts solid ",
+            "and the other way around. Only for images and stickers.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/negate</code>\n",
+            "• <code>/negate alpha</code>\n",
+            ),
+        Task::Spoiler { .. } => concat!(
+            "<b>This command has no parameters.</b>\n",
+            "Everything written after it is used as the caption, as is.\n",
+            "\n\n",
+            "<b>Examples:</b>\n",
+            "• <code>/spoiler</code>\n",
+            "• <code>/spoiler ending of the movie</code>\n",
+            ),
+        Task::Concat { .. } => "",
         }
     }
 
     pub fn parse_params(&self, params: &TaskParams<'_>) -> Result<Task, TaskError> {
-        self.parse_params_inner(
+        self.parse_params_text(params, params.get_params())
+    }
+
+    /// Same as [`Self::parse_params`], but parsing `text` as the parameters
+    /// instead of the ones written after the command.
+    ///
+    /// Resizes start off of the default quality of the chat, if it has one.
+    pub fn parse_params_text(
+        &self,
+        params: &TaskParams<'_>,
+        text: &str,
+    ) -> Result<Task, TaskError> {
+        let chat_quality = params
+            .taskman
+            .default_quality_for(params.message.chat.id)
+            .and_then(|x| self.with_default_quality(x));
+        chat_quality
+            .as_ref()
+            .unwrap_or(self)
+            .parse_inherited_params_text(params, text)
+    }
+
+    /// Same as [`Self::parse_params_text`], but for a task inherited from the one that
+    /// made the media, see [`Task::inherit_resize`]. It keeps its quality rather than
+    /// starting off of the default one of the chat.
+    pub fn parse_inherited_params_text(
+        &self,
+        params: &TaskParams<'_>,
+        text: &str,
+    ) -> Result<Task, TaskError> {
+        self.parse_params_localized(
             params.command(),
-            params.get_params(),
+            text,
             params.message.chat.is_private(),
+            params.lang(),
         )
     }
 
+    /// Same as [`Self::parse_params_localized`], in English.
+    #[cfg(test)]
     fn parse_params_inner(
         &self,
         command: &str,
         params: &str,
         show_full_help: bool,
     ) -> Result<Task, TaskError> {
-        let help_inner;
+        self.parse_params_localized(command, params, show_full_help, Lang::En)
+    }
+
+    /// Parse `params` written after `command`, with errors and help in this language.
+    fn parse_params_localized(
+        &self,
+        command: &str,
+        params: &str,
+        show_full_help: bool,
+        lang: Lang,
+    ) -> Result<Task, TaskError> {
         let help = if show_full_help {
-            self.param_help()
+            localize_help(self.param_help(), lang)
         } else {
-            help_inner = format!(
-                "Send <code>{} help</code> for a full list of parameters for this command.",
-                encode_text(command)
-            );
-            help_inner.as_str()
+            fill(Key::FullHelpHint.get(lang), &[&encode_text(command)])
         };
+        let help = help.as_str();
+
+        if let Task::Spoiler { .. } = self {
+            // The caption is everything after the command as written, quotes and colons included.
+            let caption = params.trim();
+            if caption.chars().count() > MAX_CAPTION_LENGTH {
+                return Err(TaskError::Error(format!(
+                    "the caption can't be longer than {} characters.",
+                    MAX_CAPTION_LENGTH
+                )));
+            }
+            return Ok(Task::Spoiler {
+                caption: caption.to_string(),
+            });
+        }
 
-        let params = Tokenizer::new(params);
+        let params: Vec<Token> = Tokenizer::new(params).collect();
+
+        if let Some(Token::Unterminated(snippet)) =
+            params.iter().find(|x| matches!(x, Token::Unterminated(_)))
+        {
+            return Err(unterminated_quote_error(snippet, help));
+        }
 
         match self {
             Task::Amogus { amogus } => {
@@ -291,6 +582,10 @@ impl Task {
                 format: _,
                 mut resize_type,
                 mut quality,
+                color,
+                chroma: _,
+                keep_metadata: _,
+                album: _,
             }
             | Task::VideoResize {
                 new_dimensions: original_dimensions,
@@ -302,20 +597,133 @@ impl Task {
                 resize_curve: _,
                 type_pref: _,
                 mut quality,
+                color,
+                trim: _,
+                container: _,
+                loops: _,
             } => {
-                if let ResizeType::ToSticker | ResizeType::ToCustomEmoji = resize_type {
+                if let ResizeType::ToCustomEmoji | ResizeType::ToCustomEmojiGrid { .. } =
+                    resize_type
+                {
+                    let mut grid = (1, 1);
+
+                    for param in params {
+                        parse_keyval_param_with_parser!(
+                            param,
+                            grid,
+                            grid_parser(MAX_CUSTOM_EMOJI_GRID_SIZE),
+                            help
+                        );
+                        parse_stop!(param, help);
+                    }
+
+                    if grid == (1, 1) {
+                        return Ok(Task::default_to_custom_emoji());
+                    }
+                    return Ok(Task::default_to_custom_emoji_grid(grid.0, grid.1));
+                }
+
+                if let ResizeType::ToSticker = resize_type {
                     return Ok(self.clone());
                 }
 
+                if let ResizeType::Mirror { .. } | ResizeType::Kaleidoscope { .. } = resize_type {
+                    let (width, height, percentage) = unresized_dimensions(*original_dimensions);
+                    let mut new_dimensions = (width, height);
+
+                    match &mut resize_type {
+                        ResizeType::Mirror {
+                            horizontal,
+                            vertical,
+                        } => {
+                            let mut direction = (*horizontal, *vertical);
+                            for param in params {
+                                parse_plain_param_with_parser_mandatory!(
+                                    param,
+                                    direction,
+                                    mirror_direction_parser,
+                                    help
+                                );
+                                parse_keyval_param_with_parser!(
+                                    param,
+                                    direction,
+                                    mirror_direction_parser,
+                                    help
+                                );
+                                parse_stop!(param, help);
+                            }
+                            (*horizontal, *vertical) = direction;
+
+                            // Mirroring is what negative dimensions do anyway.
+                            if direction.0 {
+                                new_dimensions.0 = -width;
+                            }
+                            if direction.1 {
+                                new_dimensions.1 = -height;
+                            }
+                        }
+                        ResizeType::Kaleidoscope { segments: default } => {
+                            let mut segments = *default;
+                            let segments_parser = |input: &str| {
+                                input
+                                    .parse::<u8>()
+                                    .ok()
+                                    .filter(|x| {
+                                        KALEIDOSCOPE_SEGMENT_RANGE.contains(x) && x % 2 == 0
+                                    })
+                                    .ok_or(())
+                            };
+                            for param in params {
+                                parse_plain_param_with_parser_mandatory!(
+                                    param,
+                                    segments,
+                                    segments_parser,
+                                    help
+                                );
+                                parse_keyval_param_with_parser!(
+                                    param,
+                                    segments,
+                                    segments_parser,
+                                    help
+                                );
+                                parse_stop!(param, help);
+                            }
+                            *default = segments;
+                        }
+                        _ => unreachable!(),
+                    }
+
+                    let mut task = self.clone();
+                    if let Task::ImageResize {
+                        new_dimensions: task_dimensions,
+                        percentage: task_percentage,
+                        resize_type: task_resize_type,
+                        ..
+                    }
+                    | Task::VideoResize {
+                        new_dimensions: task_dimensions,
+                        percentage: task_percentage,
+                        resize_type: task_resize_type,
+                        ..
+                    } = &mut task
+                    {
+                        *task_dimensions = new_dimensions;
+                        *task_percentage = Some(percentage);
+                        *task_resize_type = resize_type;
+                    }
+                    return Ok(task);
+                }
+
                 let mut old_dimensions = (original_dimensions.0, original_dimensions.1);
 
-                let (is_video, mut format, video_type_pref, mut curve) =
-                    if let Task::ImageResize { format, .. } = self {
+                let (is_video, mut format, video_type_pref, mut curve, mut chroma) =
+                    if let Task::ImageResize { format, chroma, .. } = self {
                         (
                             false,
                             *format,
                             VideoTypePreference::Preserve,
                             ResizeCurve::default(),
+                            *chroma,
                         )
                     } else if let Task::VideoResize {
                         type_pref,
@@ -323,42 +731,81 @@ impl Task {
                         ..
                     } = self
                     {
-                        (true, ImageFormat::Preserve, *type_pref, *resize_curve)
+                        (
+                            true,
+                            ImageFormat::Preserve,
+                            *type_pref,
+                            resize_curve.clone(),
+                            ChromaSubsampling::default(),
+                        )
                     } else {
                         unreachable!()
                     };
 
-                let (mut vibrato_hz, mut vibrato_depth) = if resize_type.is_seam_carve() {
-                    (7.0, 1.0)
+                let mut trim = if let Task::VideoResize { trim, .. } = self {
+                    *trim
                 } else {
-                    (7.0, 0.0)
+                    None
+                };
+
+                let mut keepmeta = if let Task::ImageResize { keep_metadata, .. } = self {
+                    *keep_metadata
+                } else {
+                    false
+                };
+
+                // Rename this variable to a more human friendly name,
+                // because the variable name is used by the parsing macros.
+                let mut r#loop = if let Task::VideoResize { loops, .. } = self {
+                    *loops
+                } else {
+                    1
+                };
+                let loop_parser = |input: &str| {
+                    input
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|x| LOOP_COUNT_RANGE.contains(x))
+                        .ok_or(())
+                };
+
+                let mut container = if let Task::VideoResize { container, .. } = self {
+                    *container
+                } else {
+                    VideoContainer::default()
+                };
+
+                let (mut vibrato_hz, mut vibrato_depth) = match self {
+                    Task::VideoResize {
+                        vibrato_hz,
+                        vibrato_depth,
+                        ..
+                    } if resize_type.is_seam_carve() => (*vibrato_hz, *vibrato_depth),
+                    _ if resize_type.is_seam_carve() => (7.0, 1.0),
+                    _ => (7.0, 0.0),
                 };
 
                 let mut rot = *rotation;
+                let ColorAdjustments {
+                    mut brightness,
+                    mut contrast,
+                    mut saturation,
+                    mut grayscale,
+                } = *color;
                 // Width, height, and percentage.
                 let mut new_dimensions: Option<(i32, i32)> = None;
                 let ResizeType::SeamCarve {
                     mut delta_x,
                     mut rigidity,
-                } = ResizeType::default_seam_carve()
+                } = (if resize_type.is_seam_carve() {
+                    resize_type
+                } else {
+                    ResizeType::default_seam_carve()
+                })
                 else {
                     unreachable!();
                 };
 
-                // This closure returns a closure that parses a
-                // string to a float within specified range inclusively lol
-                let sanitized_f64_parser = |min: f64, max: f64| {
-                    move |val: &str| -> Result<f64, ()> {
-                        let result: f64 = val.parse().map_err(|_| ())?;
-
-                        if result.is_finite() && (min..=max).contains(&result) {
-                            Ok(result)
-                        } else {
-                            Err(())
-                        }
-                    }
-                };
-
                 let quality_parser = |input: &str| -> Result<NonZeroU8, ()> {
                     let tmp: u8 = input.trim_end_matches('%').parse().map_err(|_| ())?;
                     if tmp > 100 {
@@ -421,6 +868,7 @@ impl Task {
                         parse_plain_param_optional!(param, curve, help);
                     } else {
                         parse_plain_param_optional!(param, format, help);
+                        parse_plain_param_with_parser_optional!(param, keepmeta, keepmeta_parser);
                     }
 
                     parse_plain_param_with_parser_optional!(
@@ -432,6 +880,8 @@ impl Task {
                     parse_plain_param_with_parser_optional!(param, new_dimensions, max_parser);
                     parse_plain_param_with_parser_optional!(param, new_dimensions, max_fit_parser);
 
+                    parse_plain_param_with_parser_optional!(param, grayscale, grayscale_parser);
+
                     parse_plain_param_with_parser_optional!(param, rot, |x| {
                         if let Some((rotation, true)) = rotation_parser(x) {
                             Ok(rotation)
@@ -472,14 +922,38 @@ impl Task {
                             help
                         );
                         parse_keyval_param!(param, curve, help);
+                        parse_keyval_param_with_parser!(param, trim, trim_keyval_parser, help);
+                        parse_keyval_param_with_parser!(param, r#loop, loop_parser, help);
+                        parse_keyval_param!(param, container, help);
                     } else {
                         parse_keyval_param!(param, format, help);
+                        parse_keyval_param!(param, chroma, help);
+                        parse_keyval_param!(param, keepmeta, help);
                     }
 
                     parse_keyval_param_with_parser!(param, quality, quality_parser, help);
+                    parse_keyval_param_with_parser!(
+                        param,
+                        brightness,
+                        sanitized_f64_parser(0.0, 200.0),
+                        help
+                    );
+                    parse_keyval_param_with_parser!(
+                        param,
+                        contrast,
+                        sanitized_f64_parser(0.0, 200.0),
+                        help
+                    );
+                    parse_keyval_param_with_parser!(
+                        param,
+                        saturation,
+                        sanitized_f64_parser(0.0, 200.0),
+                        help
+                    );
+                    parse_keyval_param!(param, grayscale, help);
 
-                    if let Token::KeyVal(k, v) = param {
-                        let v = (k, v);
+                    if let Token::KeyVal(k, v) = &param {
+                        let v = (*k, &**v);
                         // Try to parse it as an aspect ratio lol
                         if let Some(parse) = aspect_ratio_parser(v, old_dimensions) {
                             new_dimensions = Some((parse.0, parse.1));
@@ -506,7 +980,7 @@ impl Task {
                         > MAX_OUTPUT_MEDIA_DIMENSION_SIZE
                         || new_dimensions.1.unsigned_abs() > MAX_OUTPUT_MEDIA_DIMENSION_SIZE;
                     if media_too_big {
-                        return Err(TaskError::Error(format!(
+                        return Err(TaskError::TooBig(format!(
                             concat!(
                             "output size <b>{}x{}</b> is too big. ",
                             "This bot only allows generating media no bigger than <b>{}x{}</b>.\n",
@@ -588,6 +1062,24 @@ impl Task {
                     *rg = rigidity;
                 }
 
+                let color = ColorAdjustments {
+                    brightness,
+                    contrast,
+                    saturation,
+                    grayscale,
+                };
+
+                // Where a trimmed range would start and end over looped input is murky.
+                if trim.is_some() && r#loop > 1 {
+                    return Err(TaskError::Error(format!(
+                        concat!(
+                            "can't loop a trimmed video. ",
+                            "Trim it first, then loop the result.\n{}"
+                        ),
+                        help
+                    )));
+                }
+
                 if is_video {
                     Ok(Task::VideoResize {
                         new_dimensions: (new_dimensions.0, new_dimensions.1),
@@ -599,6 +1091,10 @@ impl Task {
                         type_pref: r#type,
                         resize_curve: curve,
                         quality,
+                        color,
+                        trim,
+                        container,
+                        loops: r#loop,
                     })
                 } else {
                     Ok(Task::ImageResize {
@@ -608,11 +1104,249 @@ impl Task {
                         resize_type,
                         format,
                         quality,
+                        color,
+                        chroma,
+                        keep_metadata: keepmeta,
+                        album: Vec::new(),
                     })
                 }
             }
             Task::Ocr => Ok(Task::Ocr),
-            Task::AmenBreak => Ok(Task::AmenBreak),
+            Task::AmenBreak { seed } => {
+                let mut seed = *seed;
+                let seed_parser = |input: &str| input.parse::<u64>().map(Some);
+
+                for param in params {
+                    parse_plain_param_with_parser_mandatory!(param, seed, seed_parser, help);
+                    parse_keyval_param_with_parser!(param, seed, seed_parser, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::AmenBreak { seed })
+            }
+            Task::DistortAudio {
+                vibrato_hz,
+                vibrato_depth,
+                speed,
+            } => {
+                let mut vibrato_hz = *vibrato_hz;
+                let mut vibrato_depth = *vibrato_depth;
+                let mut speed = *speed;
+
+                for param in params {
+                    parse_keyval_param_with_parser!(
+                        param,
+                        vibrato_hz,
+                        sanitized_f64_parser(0.1, 20000.0),
+                        help
+                    );
+                    parse_keyval_param_with_parser!(
+                        param,
+                        vibrato_depth,
+                        sanitized_f64_parser(0.0, 1000.0),
+                        help
+                    );
+                    parse_keyval_param_with_parser!(
+                        param,
+                        speed,
+                        sanitized_f64_parser(0.5, 4.0),
+                        help
+                    );
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::DistortAudio {
+                    vibrato_hz,
+                    vibrato_depth,
+                    speed,
+                })
+            }
+            Task::SpeedChange { factor, pitch } => {
+                let mut factor = *factor;
+                let mut pitch = *pitch;
+
+                let factor_parser = |input: &str| {
+                    sanitized_f64_parser(0.1, 10.0)(input.trim_end_matches(['x', 'X']))
+                };
+
+                for param in params {
+                    parse_plain_param_with_parser_mandatory!(param, factor, factor_parser, help);
+                    parse_keyval_param_with_parser!(param, factor, factor_parser, help);
+                    parse_keyval_param!(param, pitch, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::SpeedChange { factor, pitch })
+            }
+            Task::Palette { count } => {
+                let mut count = *count;
+
+                // Out of range counts are clamped rather than rejected.
+                let count_parser = |input: &str| {
+                    input.parse::<i64>().map(|x| {
+                        x.clamp(
+                            *PALETTE_COLOR_RANGE.start() as i64,
+                            *PALETTE_COLOR_RANGE.end() as i64,
+                        ) as u8
+                    })
+                };
+
+                for param in params {
+                    parse_plain_param_with_parser_mandatory!(param, count, count_parser, help);
+                    parse_keyval_param_with_parser!(param, count, count_parser, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::Palette { count })
+            }
+            Task::Trim {
+                start: default_start,
+                end: default_end,
+                precise,
+            } => {
+                let mut precise = *precise;
+                let mut range: Option<(Duration, Option<Duration>)> = None;
+                let mut start: Option<Duration> = None;
+                let mut end: Option<Duration> = None;
+                let mut duration: Option<Duration> = None;
+
+                let range_parser = |x: &str| trim_range_parser(x).map(Some);
+                let optional_timestamp_parser = |x: &str| timestamp_parser(x).map(Some);
+
+                for param in params {
+                    // Timestamps like "0:05" or ranges like "-1:00" look like a key and a value
+                    // to the tokenizer.
+                    let param = match param {
+                        Token::KeyVal(key, value)
+                            if !key.is_empty()
+                                && key
+                                    .bytes()
+                                    .all(|x| x.is_ascii_digit() || b".-".contains(&x)) =>
+                        {
+                            Token::Plain(format!("{}:{}", key, value).into())
+                        }
+                        param => param,
+                    };
+
+                    parse_plain_param_with_parser_mandatory!(param, range, range_parser, help);
+                    parse_keyval_param_with_parser!(param, start, optional_timestamp_parser, help);
+                    parse_keyval_param_with_parser!(param, end, optional_timestamp_parser, help);
+                    parse_keyval_param_with_parser!(
+                        param,
+                        duration,
+                        optional_timestamp_parser,
+                        help
+                    );
+                    parse_keyval_param!(param, precise, help);
+                    parse_stop!(param, help);
+                }
+
+                // Explicit start and end override the range.
+                let (range_start, range_end) = range.unwrap_or((*default_start, *default_end));
+                let start = start.unwrap_or(range_start);
+                let mut end = end.or(range_end);
+
+                if let Some(duration) = duration {
+                    if end.is_some() {
+                        return Err(TaskError::Error(format!(
+                            "can't specify both the end and the duration.\n{}",
+                            help
+                        )));
+                    }
+                    end = Some(start.checked_add(duration).ok_or_else(|| {
+                        TaskError::Error(format!(
+                            "the start and the duration are too long together.\n{}",
+                            help
+                        ))
+                    })?);
+                }
+
+                if let Some(end) = end {
+                    if end <= start {
+                        return Err(TaskError::Error(format!(
+                            "the end of the range must be after its start.\n{}",
+                            help
+                        )));
+                    }
+                }
+
+                Ok(Task::Trim {
+                    start,
+                    end,
+                    precise,
+                })
+            }
+            Task::Boost { gain_db } => {
+                let mut gain = *gain_db;
+
+                for param in params {
+                    parse_plain_param_with_parser_mandatory!(param, gain, gain_parser, help);
+                    parse_keyval_param_with_parser!(param, gain, gain_parser, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::Boost { gain_db: gain })
+            }
+            Task::Negate { alpha } => {
+                let mut alpha = *alpha;
+
+                for param in params {
+                    parse_plain_param_with_parser_optional!(param, alpha, alpha_parser);
+                    parse_keyval_param!(param, alpha, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::Negate { alpha })
+            }
+            Task::Spoiler { .. } => unreachable!("Parsed before tokenizing"),
+            Task::Concat { clips } => Ok(Task::Concat {
+                clips: clips.clone(),
+            }),
+            Task::Frame {
+                timestamp,
+                grid: Some(grid),
+            } => {
+                let mut grid = *grid;
+                let grid_parser = grid_parser(MAX_FRAME_GRID_SIZE);
+
+                for param in params {
+                    parse_plain_param_with_parser_mandatory!(param, grid, grid_parser, help);
+                    parse_keyval_param_with_parser!(param, grid, grid_parser, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::Frame {
+                    timestamp: *timestamp,
+                    grid: Some(grid),
+                })
+            }
+            Task::Frame {
+                timestamp,
+                grid: None,
+            } => {
+                let mut at = *timestamp;
+
+                for param in params {
+                    // Timestamps like "0:05" look like a key and a value to the tokenizer.
+                    let param = match param {
+                        Token::KeyVal(key, value)
+                            if !key.is_empty() && key.bytes().all(|x| x.is_ascii_digit()) =>
+                        {
+                            Token::Plain(format!("{}:{}", key, value).into())
+                        }
+                        param => param,
+                    };
+
+                    parse_plain_param_with_parser_mandatory!(param, at, timestamp_parser, help);
+                    parse_keyval_param_with_parser!(param, at, timestamp_parser, help);
+                    parse_stop!(param, help);
+                }
+
+                Ok(Task::Frame {
+                    timestamp: at,
+                    grid: None,
+                })
+            }
         }
     }
 }
@@ -645,15 +1379,847 @@ fn image_resize_parse_test() -> Result<(), TaskError> {
     assert_eq!(rotation, 86.0);
     assert_eq!(format, ImageFormat::Webp);
 
-    Ok(())
-}
+    let result = default.parse_params_inner("/resize", "saturation:200 brightness:80", false)?;
+    let Task::ImageResize { color, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(color.saturation, 200.0);
+    assert_eq!(color.brightness, 80.0);
+    assert_eq!(color.contrast, 100.0);
+    assert!(!color.grayscale);
+
+    for params in ["bw", "--grayscale", "grayscale:true", "BW 90deg quality:20"] {
+        let result = default.parse_params_inner("/resize", params, false)?;
+        let Task::ImageResize {
+            color,
+            rotation,
+            quality,
+            ..
+        } = result
+        else {
+            unreachable!()
+        };
+        assert!(color.grayscale, "params: {:?}", params);
+        assert!(!color.is_noop());
+        if params.contains("90deg") {
+            assert_eq!(rotation, 90.0);
+            assert_eq!(quality.get(), 20);
+        }
+    }
 
-///////////////////////
-////////// HELPER FUNCTIONS
-//////////////////////
+    assert!(default
+        .parse_params_inner("/resize", "contrast:300", false)
+        .is_err());
 
-/// Given a `percentage` and a `input`, sanitize `percentage` and
-/// compute a value that is that much percentage of that input.
+    let result = default.parse_params_inner("/resize", "jpg chroma:444", false)?;
+    let Task::ImageResize { format, chroma, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(format, ImageFormat::Jpeg);
+    assert_eq!(chroma, ChromaSubsampling::Chroma444);
+
+    assert!(default
+        .parse_params_inner("/resize", "chroma:422", false)
+        .is_err());
+
+    let Task::ImageResize { keep_metadata, .. } =
+        default.parse_params_inner("/resize", "", false)?
+    else {
+        unreachable!()
+    };
+    assert!(!keep_metadata);
+    for params in ["keepmeta", "100% KeepMeta", "keepmeta:true"] {
+        let Task::ImageResize { keep_metadata, .. } =
+            default.parse_params_inner("/resize", params, false)?
+        else {
+            unreachable!()
+        };
+        assert!(keep_metadata, "{}", params);
+    }
+
+    let result = default.parse_params_inner("/resize", "format:\"webp\"", false)?;
+    let Task::ImageResize { format, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(format, ImageFormat::Webp);
+
+    let Err(TaskError::Error(e)) = default.parse_params_inner("/resize", "2x \"webp", false) else {
+        panic!("unterminated quote should be an error");
+    };
+    assert!(e.contains("<code>\"webp</code>"));
+
+    // These errors get quick fix buttons, so they need to be told apart.
+    assert!(matches!(
+        default.parse_params_inner("/resize", "5000x5000", false),
+        Err(TaskError::TooBig(_))
+    ));
+    assert!(matches!(
+        default.parse_params_inner("/resize", "amogus", false),
+        Err(TaskError::BadParam(_))
+    ));
+    assert!(matches!(
+        default.parse_params_inner("/resize", "quality:sus", false),
+        Err(TaskError::BadParam(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn distort_audio_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_distort_audio();
+
+    let result = default.parse_params_inner("/distort", "vibrato_hz:220 speed:1.5", false)?;
+    let Task::DistortAudio {
+        vibrato_hz,
+        vibrato_depth,
+        speed,
+    } = result
+    else {
+        unreachable!()
+    };
+    assert_eq!(vibrato_hz, 220.0);
+    assert_eq!(vibrato_depth, 1.0);
+    assert_eq!(speed, 1.5);
+
+    assert!(default
+        .parse_params_inner("/distort", "speed:10", false)
+        .is_err());
+    assert!(default
+        .parse_params_inner("/distort", "vibrato_depth:-1", false)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn custom_emoji_grid_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_to_custom_emoji();
+
+    let result = default.parse_params_inner("/to_custom_emoji", "grid:3x2", false)?;
+    let Task::ImageResize {
+        new_dimensions,
+        resize_type,
+        ..
+    } = result
+    else {
+        unreachable!()
+    };
+    assert_eq!(new_dimensions, (300, 200));
+    assert_eq!(
+        resize_type,
+        ResizeType::ToCustomEmojiGrid {
+            columns: 3,
+            rows: 2
+        }
+    );
+
+    let result = default.parse_params_inner("/to_custom_emoji", "grid:1x1", false)?;
+    let Task::ImageResize { resize_type, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(resize_type, ResizeType::ToCustomEmoji);
+
+    for bad in ["grid:9x1", "grid:0x3", "grid:3", "3x3"] {
+        assert!(default
+            .parse_params_inner("/to_custom_emoji", bad, false)
+            .is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn speed_change_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_speed_change();
+
+    for (params, expected, expected_pitch) in [
+        ("", 2.0, PitchMode::Preserve),
+        ("0.5", 0.5, PitchMode::Preserve),
+        ("3x", 3.0, PitchMode::Preserve),
+        ("factor:10X", 10.0, PitchMode::Preserve),
+        ("1.5x pitch:shift", 1.5, PitchMode::Shift),
+        ("pitch:Preserve", 2.0, PitchMode::Preserve),
+    ] {
+        let result = default.parse_params_inner("/speed", params, false)?;
+        let Task::SpeedChange { factor, pitch } = result else {
+            unreachable!()
+        };
+        assert_eq!(factor, expected);
+        assert_eq!(pitch, expected_pitch);
+    }
+
+    for bad in ["0.05", "11x", "fast", "NaN", "pitch:chipmunk"] {
+        assert!(default.parse_params_inner("/speed", bad, false).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn boost_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_boost();
+
+    for (params, expected) in [
+        ("", None),
+        ("auto", None),
+        ("10", Some(10.0)),
+        ("+6dB", Some(6.0)),
+        ("gain:-3db", Some(-3.0)),
+        ("100", Some(30.0)),
+        ("-50dB", Some(-20.0)),
+    ] {
+        let result = default.parse_params_inner("/boost", params, false)?;
+        let Task::Boost { gain_db } = result else {
+            unreachable!()
+        };
+        assert_eq!(gain_db, expected, "params: {:?}", params);
+    }
+
+    for bad in ["loud", "NaN", "inf", "volume:5"] {
+        assert!(default.parse_params_inner("/boost", bad, false).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn amenbreak_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_amenbreak();
+
+    for (params, expected) in [("", None), ("1234", Some(1234)), ("seed:0", Some(0))] {
+        let result = default.parse_params_inner("/amenbreak", params, false)?;
+        let Task::AmenBreak { seed } = result else {
+            unreachable!()
+        };
+        assert_eq!(seed, expected, "params: {:?}", params);
+    }
+
+    for bad in ["-1", "random", "seed:1.5"] {
+        assert!(default
+            .parse_params_inner("/amenbreak", bad, false)
+            .is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn negate_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_negate();
+
+    for (params, expected) in [
+        ("", false),
+        ("alpha", true),
+        ("ALPHA", true),
+        ("alpha:true", true),
+        ("alpha:false", false),
+    ] {
+        let result = default.parse_params_inner("/negate", params, false)?;
+        let Task::Negate { alpha } = result else {
+            unreachable!()
+        };
+        assert_eq!(alpha, expected, "params: {:?}", params);
+    }
+
+    for bad in ["beta", "alpha:maybe", "50%"] {
+        assert!(default.parse_params_inner("/negate", bad, false).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn spoiler_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_spoiler();
+
+    for (params, expected) in [
+        ("", ""),
+        ("ending of the movie", "ending of the movie"),
+        ("  kept  as   is  ", "kept  as   is"),
+        ("\"quotes\" stay", "\"quotes\" stay"),
+        ("an \"unterminated quote", "an \"unterminated quote"),
+        ("spoiler: it dies", "spoiler: it dies"),
+    ] {
+        let result = default.parse_params_inner("/spoiler", params, false)?;
+        let Task::Spoiler { caption } = result else {
+            unreachable!()
+        };
+        assert_eq!(caption, expected, "params: {:?}", params);
+    }
+
+    let too_long = "a".repeat(MAX_CAPTION_LENGTH + 1);
+    assert!(default
+        .parse_params_inner("/spoiler", &too_long, false)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn localized_param_errors() {
+    let default = Task::default_amogus();
+
+    let error = default
+        .parse_params_localized("/amogus", "sus", false, Lang::Ru)
+        .unwrap_err();
+    let text = error.localized(Lang::Ru);
+    assert!(text.starts_with("Ошибка: "), "{}", text);
+    assert!(
+        text.contains("Отправьте <code>/amogus help</code>"),
+        "{}",
+        text
+    );
+
+    // In private chats, the full help is shown, with headers translated.
+    let error = default
+        .parse_params_localized("/amogus", "sus", true, Lang::Ru)
+        .unwrap_err();
+    let text = error.localized(Lang::Ru);
+    assert!(
+        text.contains("<b>Возможные параметры этой команды:</b>"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("<code>amogus</code>: How much amogus"),
+        "{}",
+        text
+    );
+
+    // English stays as it always was.
+    let error = default
+        .parse_params_inner("/amogus", "sus", false)
+        .unwrap_err();
+    assert_eq!(error.localized(Lang::En), error.to_string());
+    assert!(error.to_string().starts_with("Error: "));
+}
+
+#[test]
+fn mirror_and_kaleidoscope_parse_test() -> Result<(), TaskError> {
+    let mirror = |horizontal, vertical| {
+        Task::default_image_resize(
+            640,
+            480,
+            ResizeType::Mirror {
+                horizontal,
+                vertical,
+            },
+            ImageFormat::Preserve,
+        )
+    };
+
+    for (default, params, expected) in [
+        (mirror(true, false), "", (-640, 480)),
+        (mirror(false, true), "", (640, -480)),
+        (mirror(true, false), "v", (640, -480)),
+        (mirror(false, true), "direction:both", (-640, -480)),
+        (mirror(true, false), "horizontal", (-640, 480)),
+    ] {
+        let result = default.parse_params_inner("/mirror", params, false)?;
+        let Task::ImageResize {
+            new_dimensions,
+            percentage,
+            ..
+        } = result
+        else {
+            unreachable!()
+        };
+        assert_eq!(new_dimensions, expected, "params: {:?}", params);
+        assert_eq!(percentage, Some(100.0));
+    }
+
+    for bad in ["sideways", "50%", "up"] {
+        assert!(mirror(true, false)
+            .parse_params_inner("/mirror", bad, false)
+            .is_err());
+    }
+
+    // Too big media is scaled down, but still mirrored.
+    let result = Task::default_video_resize(
+        4096,
+        1024,
+        ResizeType::Mirror {
+            horizontal: true,
+            vertical: false,
+        },
+        VideoTypePreference::Preserve,
+    )
+    .parse_params_inner("/mirror", "", false)?;
+    let Task::VideoResize { new_dimensions, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(new_dimensions, (-2048, 512));
+
+    let kaleidoscope = Task::default_image_resize(
+        640,
+        480,
+        ResizeType::Kaleidoscope { segments: 4 },
+        ImageFormat::Preserve,
+    );
+
+    for (params, expected) in [("", 4), ("8", 8), ("segments:16", 16), ("2", 2)] {
+        let result = kaleidoscope.parse_params_inner("/kaleidoscope", params, false)?;
+        let Task::ImageResize {
+            new_dimensions,
+            resize_type,
+            ..
+        } = result
+        else {
+            unreachable!()
+        };
+        assert_eq!(new_dimensions, (640, 480));
+        assert_eq!(resize_type, ResizeType::Kaleidoscope { segments: expected });
+    }
+
+    for bad in ["0", "5", "18", "lots"] {
+        assert!(kaleidoscope
+            .parse_params_inner("/kaleidoscope", bad, false)
+            .is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn video_trim_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_video_resize(
+        640,
+        480,
+        ResizeType::default_seam_carve(),
+        VideoTypePreference::Preserve,
+    );
+
+    let result = default.parse_params_inner("/distort", "trim:0:05,0:12.5", false)?;
+    let Task::VideoResize { trim, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(
+        trim,
+        Some((Duration::from_secs(5), Duration::from_millis(12500)))
+    );
+
+    let result = default.parse_params_inner("/distort", "", false)?;
+    let Task::VideoResize { trim, .. } = result else {
+        unreachable!()
+    };
+    assert_eq!(trim, None);
+
+    for bad in ["trim:0:12,0:05", "trim:5,5", "trim:5", "trim:lol,10"] {
+        assert!(
+            default.parse_params_inner("/distort", bad, false).is_err(),
+            "params: {:?}",
+            bad
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn video_container_parse_test() -> Result<(), TaskError> {
+    let default =
+        Task::default_video_resize(640, 480, ResizeType::Fit, VideoTypePreference::Preserve);
+
+    for (params, expected) in [
+        ("", VideoContainer::Mp4),
+        ("50% container:webm", VideoContainer::Webm),
+        ("container:MP4", VideoContainer::Mp4),
+    ] {
+        let result = default.parse_params_inner("/resize", params, false)?;
+        let Task::VideoResize { container, .. } = result else {
+            unreachable!()
+        };
+        assert_eq!(container, expected, "params: {:?}", params);
+    }
+
+    assert!(default
+        .parse_params_inner("/resize", "container:mkv", false)
+        .is_err());
+
+    // Video stickers start out as WebM, but can still be turned into MP4.
+    let sticker = default.clone().prefer_webm();
+    for (params, expected) in [
+        ("", VideoContainer::Webm),
+        ("container:mp4", VideoContainer::Mp4),
+    ] {
+        let result = sticker.parse_params_inner("/resize", params, false)?;
+        let Task::VideoResize { container, .. } = result else {
+            unreachable!()
+        };
+        assert_eq!(container, expected, "params: {:?}", params);
+    }
+
+    // Images have their own formats instead.
+    let image = Task::default_image_resize(640, 480, ResizeType::Fit, ImageFormat::Preserve);
+    assert!(image
+        .parse_params_inner("/resize", "container:webm", false)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn video_loop_parse_test() -> Result<(), TaskError> {
+    let default =
+        Task::default_video_resize(640, 480, ResizeType::Fit, VideoTypePreference::Preserve);
+
+    for (params, expected) in [("", 1), ("loop:4", 4), ("50% loop:20", 20), ("loop:1", 1)] {
+        let result = default.parse_params_inner("/resize", params, false)?;
+        let Task::VideoResize { loops, .. } = result else {
+            unreachable!()
+        };
+        assert_eq!(loops, expected, "params: {:?}", params);
+    }
+
+    // The plain "loop" is still a resize curve.
+    let result = default.parse_params_inner("/resize", "loop loop:2", false)?;
+    let Task::VideoResize {
+        loops,
+        resize_curve,
+        ..
+    } = result
+    else {
+        unreachable!()
+    };
+    assert_eq!((loops, resize_curve), (2, ResizeCurve::Loop));
+
+    for bad in [
+        "loop:0",
+        "loop:21",
+        "loop:-1",
+        "loop:lots",
+        "loop:2 trim:0:01,0:02",
+    ] {
+        assert!(
+            default.parse_params_inner("/resize", bad, false).is_err(),
+            "params: {:?}",
+            bad
+        );
+    }
+
+    let image = Task::default_image_resize(640, 480, ResizeType::Fit, ImageFormat::Preserve);
+    assert!(image
+        .parse_params_inner("/resize", "loop:2", false)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn palette_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_palette();
+
+    for (params, expected) in [
+        ("", 5),
+        ("8", 8),
+        ("count:3", 3),
+        ("1", 2),
+        ("-5", 2),
+        ("100", 16),
+    ] {
+        let result = default.parse_params_inner("/palette", params, false)?;
+        let Task::Palette { count } = result else {
+            unreachable!()
+        };
+        assert_eq!(count, expected);
+    }
+
+    for bad in ["lots", "5.5", "colors:5"] {
+        assert!(default.parse_params_inner("/palette", bad, false).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn trim_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_trim();
+    let secs = Duration::from_secs;
+    let millis = Duration::from_millis;
+
+    for (params, expected_start, expected_end, expected_precise) in [
+        ("", secs(0), None, false),
+        ("0:05-0:37", secs(5), Some(secs(37)), false),
+        ("10-", secs(10), None, false),
+        ("-1:00", secs(0), Some(secs(60)), false),
+        ("7.5", millis(7500), None, false),
+        ("start:1:30 duration:15", secs(90), Some(secs(105)), false),
+        ("5-20 end:30 precise:true", secs(5), Some(secs(30)), true),
+        ("end:1:02:03.25", secs(0), Some(millis(3723250)), false),
+    ] {
+        let result = default.parse_params_inner("/trim", params, false)?;
+        let Task::Trim {
+            start,
+            end,
+            precise,
+        } = result
+        else {
+            unreachable!()
+        };
+        assert_eq!(start, expected_start, "params: {:?}", params);
+        assert_eq!(end, expected_end, "params: {:?}", params);
+        assert_eq!(precise, expected_precise, "params: {:?}", params);
+    }
+
+    for bad in [
+        "0:37-0:05",
+        "5-5",
+        "start:10 end:5",
+        "end:10 duration:5",
+        "start:1e19 duration:1e19",
+        "precise:maybe",
+        "1:2:3:4",
+        "lol",
+    ] {
+        assert!(
+            default.parse_params_inner("/trim", bad, false).is_err(),
+            "params: {:?}",
+            bad
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn frame_parse_test() -> Result<(), TaskError> {
+    let default = Task::default_frame();
+    let secs = Duration::from_secs;
+
+    for (params, expected) in [
+        ("", secs(0)),
+        ("0:42", secs(42)),
+        ("at:1:02:03", secs(3723)),
+        ("12.5", Duration::from_millis(12500)),
+    ] {
+        let result = default.parse_params_inner("/frame", params, false)?;
+        let Task::Frame {
+            timestamp,
+            grid: None,
+        } = result
+        else {
+            unreachable!()
+        };
+        assert_eq!(timestamp, expected, "params: {:?}", params);
+    }
+
+    for bad in ["-5", "lol", "grid:3x3", "1:2:3:4"] {
+        assert!(
+            default.parse_params_inner("/frame", bad, false).is_err(),
+            "params: {:?}",
+            bad
+        );
+    }
+
+    let default = Task::default_frames();
+
+    for (params, expected) in [("", (3, 3)), ("4x2", (4, 2)), ("grid:1x6", (1, 6))] {
+        let result = default.parse_params_inner("/frames", params, false)?;
+        let Task::Frame { grid, .. } = result else {
+            unreachable!()
+        };
+        assert_eq!(grid, Some(expected), "params: {:?}", params);
+    }
+
+    for bad in ["7x7", "0x3", "3", "at:0:05"] {
+        assert!(
+            default.parse_params_inner("/frames", bad, false).is_err(),
+            "params: {:?}",
+            bad
+        );
+    }
+
+    Ok(())
+}
+
+///////////////////////
+////////// HELPER FUNCTIONS
+//////////////////////
+
+/// Returns a closure that parses a string to a
+/// float within specified range inclusively lol
+fn sanitized_f64_parser(min: f64, max: f64) -> impl Fn(&str) -> Result<f64, ()> {
+    move |val: &str| -> Result<f64, ()> {
+        let result: f64 = val.parse().map_err(|_| ())?;
+
+        if result.is_finite() && (min..=max).contains(&result) {
+            Ok(result)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Parses a timestamp like `1:02:03.5`, `2:03` or plain seconds like `7.5`.
+fn timestamp_parser(data: &str) -> Result<Duration, ()> {
+    let mut parts = data.rsplit(':');
+
+    let seconds: f64 = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(());
+    }
+
+    let minutes: Option<u64> = parts.next().map(str::parse).transpose().map_err(|_| ())?;
+    let hours: Option<u64> = parts.next().map(str::parse).transpose().map_err(|_| ())?;
+
+    if parts.next().is_some() {
+        return Err(());
+    }
+
+    // Only the biggest unit may overflow, like in "90:00".
+    if minutes.is_some() && seconds >= 60.0 || hours.is_some() && minutes >= Some(60) {
+        return Err(());
+    }
+
+    let total =
+        seconds + minutes.unwrap_or(0) as f64 * 60.0 + hours.unwrap_or(0) as f64 * 60.0 * 60.0;
+
+    Duration::try_from_secs_f64(total).map_err(|_| ())
+}
+
+#[test]
+fn timestamp_parser_test() {
+    let secs = Duration::from_secs;
+    let millis = Duration::from_millis;
+
+    assert_eq!(timestamp_parser("5"), Ok(secs(5)));
+    assert_eq!(timestamp_parser("7.5"), Ok(millis(7500)));
+    assert_eq!(timestamp_parser("0:05"), Ok(secs(5)));
+    assert_eq!(timestamp_parser("2:03"), Ok(secs(123)));
+    assert_eq!(timestamp_parser("90:00"), Ok(secs(5400)));
+    assert_eq!(timestamp_parser("1:02:03.250"), Ok(millis(3723250)));
+    assert_eq!(timestamp_parser("00:00:00"), Ok(secs(0)));
+
+    for bad in [
+        "", "-5", "inf", "NaN", "1:", ":5", "1:60", "1:60:00", "1:2:3:4", "a:05", "1.5:00",
+    ] {
+        assert_eq!(timestamp_parser(bad), Err(()), "input: {:?}", bad);
+    }
+}
+
+/// Parses a range of timestamps like `0:05-0:37`, where either side may be omitted,
+/// or a single timestamp to start from.
+fn trim_range_parser(data: &str) -> Result<(Duration, Option<Duration>), ()> {
+    let Some((start, end)) = data.split_once('-') else {
+        return Ok((timestamp_parser(data)?, None));
+    };
+
+    if start.is_empty() && end.is_empty() {
+        return Err(());
+    }
+
+    let start = if start.is_empty() {
+        Duration::ZERO
+    } else {
+        timestamp_parser(start)?
+    };
+
+    let end = if end.is_empty() {
+        None
+    } else {
+        Some(timestamp_parser(end)?)
+    };
+
+    Ok((start, end))
+}
+
+/// Parses a range of a video to keep, like `0:05,0:12`. The end must be after the start.
+fn trim_keyval_parser(data: &str) -> Result<Option<(Duration, Duration)>, ()> {
+    let (start, end) = data.split_once(',').ok_or(())?;
+    let start = timestamp_parser(start)?;
+    let end = timestamp_parser(end)?;
+
+    if end <= start {
+        return Err(());
+    }
+
+    Ok(Some((start, end)))
+}
+
+/// Parses the plain parameter for keeping metadata of images.
+fn keepmeta_parser(val: &str) -> Result<bool, ()> {
+    ["keepmeta", "keep_meta"]
+        .iter()
+        .any(|x| val.eq_ignore_ascii_case(x))
+        .then_some(true)
+        .ok_or(())
+}
+
+fn alpha_parser(val: &str) -> Result<bool, ()> {
+    val.eq_ignore_ascii_case("alpha").then_some(true).ok_or(())
+}
+
+/// Parses the plain parameter for making media grayscale.
+fn grayscale_parser(val: &str) -> Result<bool, ()> {
+    ["bw", "grayscale", "greyscale"]
+        .iter()
+        .any(|x| val.trim_start_matches('-').eq_ignore_ascii_case(x))
+        .then_some(true)
+        .ok_or(())
+}
+
+/// Parses a gain like "6", "+6dB" or "-3db" for `/boost`, clamped to
+/// [`BOOST_GAIN_RANGE_DB`], or "auto" for loudness normalization as [`None`].
+fn gain_parser(val: &str) -> Result<Option<f64>, ()> {
+    if ["auto", "loudnorm"]
+        .iter()
+        .any(|x| val.eq_ignore_ascii_case(x))
+    {
+        return Ok(None);
+    }
+
+    let val = val
+        .len()
+        .checked_sub(2)
+        .filter(|&x| val.is_char_boundary(x) && val[x..].eq_ignore_ascii_case("db"))
+        .map_or(val, |x| &val[..x]);
+
+    let gain: f64 = val.parse().map_err(|_| ())?;
+    if !gain.is_finite() {
+        return Err(());
+    }
+
+    Ok(Some(gain.clamp(
+        *BOOST_GAIN_RANGE_DB.start(),
+        *BOOST_GAIN_RANGE_DB.end(),
+    )))
+}
+
+/// Parses which way to mirror media, as whether to do it horizontally and vertically.
+fn mirror_direction_parser(val: &str) -> Result<(bool, bool), ()> {
+    let is_any = |options: &[&str]| options.iter().any(|x| val.eq_ignore_ascii_case(x));
+
+    if is_any(&["h", "horizontal", "horizontally"]) {
+        Ok((true, false))
+    } else if is_any(&["v", "vertical", "vertically"]) {
+        Ok((false, true))
+    } else if is_any(&["both", "hv", "vh"]) {
+        Ok((true, true))
+    } else {
+        Err(())
+    }
+}
+
+/// Returns a closure that parses a grid size like "3x2" into columns and rows,
+/// each between 1 and `max`.
+fn grid_parser(max: u8) -> impl Fn(&str) -> Result<(u8, u8), ()> {
+    move |val: &str| -> Result<(u8, u8), ()> {
+        let (columns, rows) = val.split_once(['x', 'X', '*']).ok_or(())?;
+        let columns: u8 = columns.parse().map_err(|_| ())?;
+        let rows: u8 = rows.parse().map_err(|_| ())?;
+
+        let range = 1..=max;
+        if range.contains(&columns) && range.contains(&rows) {
+            Ok((columns, rows))
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Given a `percentage` and a `input`, sanitize `percentage` and
+/// compute a value that is that much percentage of that input.
 fn perc_calc(percentage: f32, input: i32) -> Option<i32> {
     let factor = percentage / 100.0;
 
@@ -676,6 +2242,74 @@ fn perc_calc_test() {
     assert_eq!(perc_calc(0.0, 144), Some(0));
 }
 
+/// Make every photo of an album get resized the same way as the photo the command was used on,
+/// which was `input_dimensions` and is to be resized to `new_dimensions`.
+pub fn scale_album_dimensions(
+    album: &mut [AlbumPhoto],
+    input_dimensions: (u32, u32),
+    new_dimensions: (i32, i32),
+) -> Result<(), TaskError> {
+    let factor = (
+        new_dimensions.0 as f64 / input_dimensions.0 as f64,
+        new_dimensions.1 as f64 / input_dimensions.1 as f64,
+    );
+    let scale = |size: u32, factor: f64| {
+        // Don't let it go down to 0, but keep the sign, as that's mirroring.
+        let scaled = (size as f64 * factor).round().abs().max(1.0) as i32;
+        if factor.is_sign_negative() {
+            -scaled
+        } else {
+            scaled
+        }
+    };
+
+    for (index, photo) in album.iter_mut().enumerate() {
+        photo.new_dimensions = (scale(photo.width, factor.0), scale(photo.height, factor.1));
+
+        if photo.new_dimensions.0.unsigned_abs() > MAX_OUTPUT_MEDIA_DIMENSION_SIZE
+            || photo.new_dimensions.1.unsigned_abs() > MAX_OUTPUT_MEDIA_DIMENSION_SIZE
+        {
+            return Err(TaskError::TooBig(format!(
+                concat!(
+                    "photo number {} of the album would be resized to <b>{}x{}</b>, ",
+                    "which is too big. This bot only allows generating media no bigger ",
+                    "than <b>{}x{}</b>."
+                ),
+                index + 1,
+                photo.new_dimensions.0,
+                photo.new_dimensions.1,
+                MAX_OUTPUT_MEDIA_DIMENSION_SIZE,
+                MAX_OUTPUT_MEDIA_DIMENSION_SIZE,
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn scale_album_dimensions_test() {
+    let photo = |width, height| AlbumPhoto {
+        message_id: 0,
+        width,
+        height,
+        new_dimensions: (0, 0),
+        file: teloxide::types::FileMeta {
+            id: String::new(),
+            unique_id: String::new(),
+            size: 0,
+        },
+    };
+    let mut album = vec![photo(1000, 500), photo(300, 300), photo(1, 1)];
+
+    scale_album_dimensions(&mut album, (1000, 500), (500, -250)).unwrap();
+    assert_eq!(album[0].new_dimensions, (500, -250));
+    assert_eq!(album[1].new_dimensions, (150, -150));
+    assert_eq!(album[2].new_dimensions, (1, -1));
+
+    assert!(scale_album_dimensions(&mut album, (300, 300), (1500, 1500)).is_err());
+}
+
 /// Returns rotation in degrees, and a boolean denoting if there
 /// was any indication that this value is specifically a rotation.
 fn rotation_parser(data: &str) -> Option<(f64, bool)> {
@@ -956,6 +2590,50 @@ fn width_height_parser_test() {
     assert_eq!(the_fn("x", (100, 150)), Some((100, 150)));
 }
 
+/// Check if this word of command parameters sets the output size.
+fn is_size_param(word: &str) -> bool {
+    ["max", "maxfit", "max_fit"]
+        .iter()
+        .any(|x| word.eq_ignore_ascii_case(x))
+        || dimensions_parser(word, (512, 512)).is_some()
+}
+
+/// Check if any of these command parameters set the output size.
+pub fn has_size_param(params: &str) -> bool {
+    params.split_whitespace().any(is_size_param)
+}
+
+/// Replace all parameters setting the output size in these command parameters with `size`,
+/// like `max` or `maxfit`, leaving the rest as they are.
+pub fn replace_size_params(params: &str, size: &str) -> String {
+    let mut result: Vec<&str> = params
+        .split_whitespace()
+        .filter(|x| !is_size_param(x))
+        .collect();
+    result.push(size);
+    result.join(" ")
+}
+
+#[test]
+fn replace_size_params_test() {
+    assert_eq!(replace_size_params("5000x5000", "max"), "max");
+    assert_eq!(
+        replace_size_params("webp 300% rot:90 x4000", "maxfit"),
+        "webp rot:90 maxfit"
+    );
+    assert_eq!(replace_size_params("", "max"), "max");
+    assert_eq!(replace_size_params("16:9 MAX", "maxfit"), "16:9 maxfit");
+}
+
+#[test]
+fn has_size_param_test() {
+    assert!(has_size_param("webp 300% rot:90"));
+    assert!(has_size_param("640x480"));
+    assert!(has_size_param("Max_Fit"));
+    assert!(!has_size_param(""));
+    assert!(!has_size_param("webp rot:90 16:9 quality:50"));
+}
+
 /// Given either a percentage or width/height specification
 /// and starting dimensions, parse, compute, return output dimensions.
 /// Also computes a percentage value of starting dimensions, if applicable.
@@ -971,6 +2649,26 @@ fn dimensions_parser(
     }
 }
 
+/// Dimensions to keep media at, for effects that don't resize it,
+/// and which percentage of the original size they are.
+/// Only scales it down if it's bigger than [`MAX_OUTPUT_MEDIA_DIMENSION_SIZE`].
+fn unresized_dimensions(dimensions: (i32, i32)) -> (i32, i32, f32) {
+    let (width, height) = (dimensions.0.abs(), dimensions.1.abs());
+
+    if width.unsigned_abs() <= MAX_OUTPUT_MEDIA_DIMENSION_SIZE
+        && height.unsigned_abs() <= MAX_OUTPUT_MEDIA_DIMENSION_SIZE
+    {
+        return (width, height, 100.0);
+    }
+
+    let percentage = biggest_percentage_that_can_fit((width, height));
+    (
+        perc_calc(percentage, width).unwrap_or(width).max(1),
+        perc_calc(percentage, height).unwrap_or(height).max(1),
+        percentage,
+    )
+}
+
 /// Given a width and a height, compute the maximum factor, as a percentage (*100),
 /// that can fit within a square with length side of [`MAX_OUTPUT_MEDIA_DIMENSION_SIZE`].
 fn biggest_percentage_that_can_fit((width, height): (i32, i32)) -> f32 {
@@ -981,3 +2679,111 @@ fn biggest_percentage_that_can_fit((width, height): (i32, i32)) -> f32 {
     let biggest_percent = u32::min(smallest_width_percent, smallest_height_percent);
     biggest_percent as f32
 }
+
+#[test]
+fn inherited_resize_test() -> Result<(), TaskError> {
+    let seam_carve = ResizeType::default_seam_carve();
+    let previous = Task::default_video_resize(1000, 500, seam_carve, VideoTypePreference::Preserve)
+        .parse_params_inner(
+            "/distort",
+            "20% delta_x:3 vibrato_hz:100 trim:0:01,0:02",
+            false,
+        )?;
+    assert_eq!(previous.repeat_size_param().as_deref(), Some("20%"));
+
+    // Doing it again on the result compounds.
+    let defaults = Task::default_video_resize(200, 100, seam_carve, VideoTypePreference::Preserve);
+    let inherited = defaults.inherit_resize(&previous).unwrap();
+    let result = inherited.parse_params_inner("/distort", "20%", false)?;
+    let Task::VideoResize {
+        new_dimensions,
+        resize_type,
+        vibrato_hz,
+        trim,
+        ..
+    } = result
+    else {
+        unreachable!()
+    };
+    assert_eq!(new_dimensions, (40, 20));
+    assert_eq!(
+        resize_type,
+        ResizeType::SeamCarve {
+            delta_x: 3.0,
+            rigidity: 0.0
+        }
+    );
+    assert_eq!(vibrato_hz, 100.0);
+    assert_eq!(trim, None);
+
+    // Given parameters still win.
+    let result = inherited.parse_params_inner("/distort", "delta_x:1", false)?;
+    let Task::VideoResize {
+        new_dimensions,
+        resize_type,
+        ..
+    } = result
+    else {
+        unreachable!()
+    };
+    assert_eq!(new_dimensions, (100, 50));
+    assert_eq!(
+        resize_type,
+        ResizeType::SeamCarve {
+            delta_x: 1.0,
+            rigidity: 0.0
+        }
+    );
+
+    // Only the same kind of resize of the same kind of media is inherited from.
+    let fit = Task::default_video_resize(200, 100, ResizeType::Fit, VideoTypePreference::Preserve);
+    assert!(fit.inherit_resize(&previous).is_none());
+    let image = Task::default_image_resize(200, 100, seam_carve, ImageFormat::Preserve);
+    assert!(image.inherit_resize(&previous).is_none());
+
+    let previous = Task::default_image_resize(300, 300, ResizeType::Fit, ImageFormat::Preserve)
+        .parse_params_inner("/resize", "crop 200x100", false)?;
+    assert_eq!(previous.repeat_size_param().as_deref(), Some("200x100"));
+    let defaults = Task::default_image_resize(200, 100, ResizeType::Fit, ImageFormat::Preserve);
+    let Some(Task::ImageResize { resize_type, .. }) = defaults.inherit_resize(&previous) else {
+        panic!("Expected a resize to be inherited from another one");
+    };
+    assert_eq!(resize_type, ResizeType::Crop);
+
+    Ok(())
+}
+
+#[test]
+fn default_quality_test() -> Result<(), TaskError> {
+    let quality = |task: Task| match task {
+        Task::ImageResize { quality, .. } | Task::VideoResize { quality, .. } => quality.get(),
+        _ => unreachable!(),
+    };
+    let forty = NonZeroU8::new(40).unwrap();
+
+    let image = Task::default_image_resize(200, 100, ResizeType::Fit, ImageFormat::Preserve)
+        .with_default_quality(forty)
+        .unwrap();
+    assert_eq!(
+        quality(image.parse_params_inner("/resize", "50%", false)?),
+        40
+    );
+    // Given quality still wins.
+    assert_eq!(
+        quality(image.parse_params_inner("/resize", "50% quality:80", false)?),
+        80
+    );
+
+    let video =
+        Task::default_video_resize(200, 100, ResizeType::Fit, VideoTypePreference::Preserve)
+            .with_default_quality(forty)
+            .unwrap();
+    assert_eq!(quality(video.parse_params_inner("/resize", "", false)?), 40);
+
+    // Tasks without a quality aren't affected.
+    assert!(Task::Amogus { amogus: 1 }
+        .with_default_quality(forty)
+        .is_none());
+
+    Ok(())
+}
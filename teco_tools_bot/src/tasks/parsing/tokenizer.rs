@@ -1,60 +1,153 @@
+use std::borrow::Cow;
+
 pub struct Tokenizer<'a>(&'a str);
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Token<'a> {
-    Plain(&'a str),
-    KeyVal(&'a str, &'a str),
+    Plain(Cow<'a, str>),
+    KeyVal(&'a str, Cow<'a, str>),
+    /// A quote that was never closed. Holds the input from where this token starts.
+    Unterminated(&'a str),
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn new(input: &str) -> Tokenizer {
+    pub fn new(input: &str) -> Tokenizer<'_> {
         Tokenizer(input)
     }
 
-    fn chomp_until_whitespace(&mut self) -> &'a str {
-        self.0 = self.0.trim_start();
+    /// Chomp a value up to the next whitespace that's not in quotes.
+    ///
+    /// Quotes are stripped, and inside them, backslashes escape the next character.
+    /// Returns [`Err`] if a quote is never closed.
+    fn chomp_value(&mut self) -> Result<Cow<'a, str>, ()> {
+        let input = self.0;
+
+        // Only allocated once a quote is found. Until then, the value is just a slice.
+        let mut unquoted: Option<String> = None;
+        let mut in_quotes = false;
+        let mut escaped = false;
+        let mut end = input.len();
 
-        let whitespace = self.0.find(char::is_whitespace);
+        for (i, c) in input.char_indices() {
+            if in_quotes {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                    continue;
+                } else if c == '"' {
+                    in_quotes = false;
+                    continue;
+                }
+            } else if c.is_whitespace() {
+                end = i;
+                break;
+            } else if c == '"' {
+                in_quotes = true;
+                unquoted.get_or_insert_with(|| input[..i].to_string());
+                continue;
+            }
 
-        if let Some(s) = whitespace {
-            let result = &self.0[0..s];
-            self.0 = &self.0[s..];
-            result
-        } else {
-            let result = self.0;
-            // somewhat more logically correct but who cares
-            //self.0 = &self.0[self.0.len()..];
+            if let Some(unquoted) = &mut unquoted {
+                unquoted.push(c);
+            }
+        }
+
+        if in_quotes {
             self.0 = "";
-            result
+            return Err(());
         }
+
+        self.0 = &input[end..];
+
+        Ok(match unquoted {
+            Some(unquoted) => Cow::Owned(unquoted),
+            None => Cow::Borrowed(&input[..end]),
+        })
     }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
     type Item = Token<'a>;
     fn next(&mut self) -> Option<Self::Item> {
-        let this_param = self.chomp_until_whitespace();
+        self.0 = self.0.trim_start();
 
-        if this_param.is_empty() {
+        if self.0.is_empty() {
             return None;
         }
 
-        let keyval_separator = this_param.find(':');
+        let start = self.0;
+
+        // It's a key if there's a colon before any whitespace or quotes.
+        let key_end = start.find(|c: char| c == ':' || c == '"' || c.is_whitespace());
 
-        if let Some(kvs) = keyval_separator {
-            let (a, b) = this_param.split_at(kvs);
-            let mut b = &b[1..];
+        let token = match key_end {
+            Some(kvs) if start[kvs..].starts_with(':') => {
+                let key = &start[..kvs];
+                self.0 = &start[kvs + 1..];
 
-            if b.is_empty() {
-                // Someone wrote "param: aawagga" and we got
-                // the empty slice right after : but before the space
-                // Eat one more word!
-                b = self.chomp_until_whitespace();
+                if self.0.starts_with(char::is_whitespace) {
+                    // Someone wrote "param: aawagga" and we got
+                    // the empty slice right after : but before the space
+                    // Eat one more word!
+                    self.0 = self.0.trim_start();
+                }
+
+                self.chomp_value().map(|value| Token::KeyVal(key, value))
             }
+            _ => self.chomp_value().map(Token::Plain),
+        };
 
-            Some(Token::KeyVal(a, b))
-        } else {
-            Some(Token::Plain(this_param))
-        }
+        Some(token.unwrap_or(Token::Unterminated(start.trim_end())))
+    }
+}
+
+#[test]
+fn tokenizer_test() {
+    use Token::*;
+
+    fn plain(x: &str) -> Token<'_> {
+        Plain(x.into())
+    }
+    fn keyval<'a>(k: &'a str, v: &'a str) -> Token<'a> {
+        KeyVal(k, v.into())
+    }
+
+    let cases: &[(&str, &[Token])] = &[
+        ("", &[]),
+        ("   ", &[]),
+        ("50%", &[plain("50%")]),
+        (" 2x  bicubic\n", &[plain("2x"), plain("bicubic")]),
+        ("format:jpg", &[keyval("format", "jpg")]),
+        ("format: jpg 50%", &[keyval("format", "jpg"), plain("50%")]),
+        ("format:", &[keyval("format", "")]),
+        ("a:b:c", &[keyval("a", "b:c")]),
+        (":lol", &[keyval("", "lol")]),
+        ("back\\slash", &[plain("back\\slash")]),
+        // Quotes.
+        ("\"hello there\"", &[plain("hello there")]),
+        (
+            "caption:\"hello there world\" 2x",
+            &[keyval("caption", "hello there world"), plain("2x")],
+        ),
+        ("caption: \"a b\"", &[keyval("caption", "a b")]),
+        ("\"a:b\"", &[plain("a:b")]),
+        ("ab\"c d\"ef", &[plain("abc def")]),
+        ("\"say \\\"hi\\\" \\\\o/\"", &[plain("say \"hi\" \\o/")]),
+        // Empty quotes.
+        ("\"\"", &[plain("")]),
+        ("caption:\"\" 2x", &[keyval("caption", ""), plain("2x")]),
+        // Unterminated quotes.
+        ("2x \"oops", &[plain("2x"), Unterminated("\"oops")]),
+        (
+            "caption:\"oops lol  ",
+            &[Unterminated("caption:\"oops lol")],
+        ),
+        ("\"escaped\\\"", &[Unterminated("\"escaped\\\"")]),
+    ];
+
+    for (input, expected) in cases {
+        let tokens: Vec<Token> = Tokenizer::new(input).collect();
+        assert_eq!(&tokens, expected, "input: {:?}", input);
     }
 }
@@ -0,0 +1,234 @@
+//! Remembering photos and videos of recently seen albums, so that a command replying
+//! to one item of an album can work on all of them.
+//!
+//! Telegram sends each item of an album as its own message, and the Bot API has no way
+//! to fetch the rest of an album later, so the only way to know them is to see them arrive.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use arch_bot_commons::useful_methods::MessageStuff;
+use teloxide::types::{ChatId, Message};
+
+use crate::tasks::{AlbumPhoto, VideoClip};
+
+/// Albums last seen longer ago than this are forgotten.
+const ALBUM_MEMORY_TIME: Duration = Duration::from_secs(60 * 60);
+
+/// What's remembered about an album.
+struct Album {
+    /// When an item was last added to it.
+    last_seen: Instant,
+    photos: Vec<AlbumPhoto>,
+    videos: Vec<VideoClip>,
+}
+
+#[derive(Default)]
+pub struct AlbumCache {
+    /// Albums by chat and media group ID.
+    albums: Mutex<HashMap<(ChatId, String), Album>>,
+}
+
+impl AlbumCache {
+    /// Remember this message if it's a photo or a video in an album.
+    pub fn remember(&self, message: &Message) {
+        let Some(media_group_id) = message.media_group_id() else {
+            return;
+        };
+        let photo = message.find_biggest_photo();
+        let video = VideoClip::from_message(message);
+        if photo.is_none() && video.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut albums = self.albums.lock().unwrap();
+        albums.retain(|_, album| now.duration_since(album.last_seen) < ALBUM_MEMORY_TIME);
+
+        let album = albums
+            .entry((message.chat.id, media_group_id.to_string()))
+            .or_insert_with(|| Album {
+                last_seen: now,
+                photos: Vec::new(),
+                videos: Vec::new(),
+            });
+        album.last_seen = now;
+
+        if let Some(photo) = photo {
+            if album.photos.iter().any(|x| x.message_id == message.id.0) {
+                return;
+            }
+            album.photos.push(AlbumPhoto {
+                message_id: message.id.0,
+                width: photo.width,
+                height: photo.height,
+                new_dimensions: (photo.width as i32, photo.height as i32),
+                file: photo.file.clone(),
+            });
+            album.photos.sort_by_key(|x| x.message_id);
+        }
+
+        if let Some(video) = video {
+            if album
+                .videos
+                .iter()
+                .any(|x| x.message_id == video.message_id)
+            {
+                return;
+            }
+            album.videos.push(video);
+            album.videos.sort_by_key(|x| x.message_id);
+        }
+    }
+
+    /// Get the photos of the album this message is in, in the order they were sent.
+    /// Empty if it's not in an album, or if the album was not seen.
+    pub fn get(&self, message: &Message) -> Vec<AlbumPhoto> {
+        self.with_album(message, |album| album.photos.clone())
+    }
+
+    /// Same as [`Self::get`], but for videos and GIFs of the album.
+    pub fn get_videos(&self, message: &Message) -> Vec<VideoClip> {
+        self.with_album(message, |album| album.videos.clone())
+    }
+
+    fn with_album<T: Default>(&self, message: &Message, f: impl FnOnce(&Album) -> T) -> T {
+        let Some(media_group_id) = message.media_group_id() else {
+            return T::default();
+        };
+
+        self.albums
+            .lock()
+            .unwrap()
+            .get(&(message.chat.id, media_group_id.to_string()))
+            .map(f)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::parsing::scale_album_dimensions;
+
+    /// Make a message in an album with this media in it, or not in one if `album` is empty.
+    fn album_message(chat: i64, id: i32, album: &str, media: serde_json::Value) -> Message {
+        let mut message = serde_json::json!({
+            "message_id": id,
+            "date": 0,
+            "chat": { "id": chat, "type": "supergroup", "title": "amogus" },
+        });
+        if !album.is_empty() {
+            message["media_group_id"] = album.into();
+        }
+        message.as_object_mut().unwrap().extend(
+            media
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        serde_json::from_value(message).unwrap()
+    }
+
+    fn file(id: i32, width: u32, height: u32) -> serde_json::Value {
+        serde_json::json!({
+            "file_id": format!("file{}", id),
+            "file_unique_id": format!("unique{}", id),
+            "file_size": 1000,
+            "width": width,
+            "height": height,
+        })
+    }
+
+    fn photo(chat: i64, id: i32, album: &str, width: u32, height: u32) -> Message {
+        let small = file(id * 10, width / 10, height / 10);
+        let big = file(id, width, height);
+        album_message(
+            chat,
+            id,
+            album,
+            serde_json::json!({ "photo": [small, big] }),
+        )
+    }
+
+    #[test]
+    fn resizing_a_remembered_album() {
+        let cache = AlbumCache::default();
+        // Items may arrive out of order, and more than once.
+        let messages = [
+            photo(-100, 3, "album", 300, 300),
+            photo(-100, 1, "album", 1000, 500),
+            photo(-100, 2, "album", 500, 1000),
+            photo(-100, 3, "album", 300, 300),
+            photo(-100, 4, "other", 100, 100),
+            photo(-200, 5, "album", 100, 100),
+        ];
+        for message in &messages {
+            cache.remember(message);
+        }
+
+        // Any message of the album gets all of it.
+        let mut album = cache.get(&messages[2]);
+        assert_eq!(cache.get(&messages[0]), album);
+        let ids: Vec<i32> = album.iter().map(|x| x.message_id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!((album[0].width, album[0].height), (1000, 500));
+        assert_eq!(album[0].file.id, "file1");
+
+        // Halving the first one and mirroring it halves and mirrors all of them.
+        scale_album_dimensions(&mut album, (1000, 500), (-500, 250)).unwrap();
+        let dimensions: Vec<(i32, i32)> = album.iter().map(|x| x.new_dimensions).collect();
+        assert_eq!(dimensions, [(-500, 250), (-250, 500), (-150, 150)]);
+
+        // Albums are separate per chat, and messages outside of albums have none.
+        assert_eq!(cache.get(&messages[5]).len(), 1);
+        let lone = photo(-100, 6, "", 100, 100);
+        cache.remember(&lone);
+        assert_eq!(cache.get(&lone), []);
+    }
+
+    #[test]
+    fn remembering_videos() {
+        let cache = AlbumCache::default();
+        let video = |id, width, height| {
+            let mut video = file(id, width, height);
+            video["duration"] = 5.into();
+            video["mime_type"] = "video/mp4".into();
+            album_message(-100, id, "album", serde_json::json!({ "video": video }))
+        };
+        let messages = [
+            video(4, 640, 360),
+            photo(-100, 1, "album", 1000, 500),
+            video(2, 1280, 720),
+            video(4, 640, 360),
+            photo(-100, 3, "album", 300, 300),
+        ];
+        for message in &messages {
+            cache.remember(message);
+        }
+
+        // Photos and videos of the same album are kept apart, in order, without repeats.
+        let videos = cache.get_videos(&messages[1]);
+        let clips: Vec<(i32, u32, u32, &str)> = videos
+            .iter()
+            .map(|x| (x.message_id, x.width, x.height, x.file.id.as_str()))
+            .collect();
+        assert_eq!(clips, [(2, 1280, 720, "file2"), (4, 640, 360, "file4")]);
+        assert_eq!(cache.get_videos(&messages[0]), videos);
+        let photos: Vec<i32> = cache
+            .get(&messages[0])
+            .iter()
+            .map(|x| x.message_id)
+            .collect();
+        assert_eq!(photos, [1, 3]);
+
+        // An album of only photos has no videos.
+        let other = photo(-100, 5, "other", 100, 100);
+        cache.remember(&other);
+        assert_eq!(cache.get_videos(&other), []);
+    }
+}
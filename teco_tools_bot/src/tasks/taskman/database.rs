@@ -1,4 +1,4 @@
-use std::{pin::Pin, str::FromStr, sync::atomic::AtomicBool};
+use std::{num::NonZeroU8, pin::Pin, str::FromStr, sync::atomic::AtomicBool, time::Instant};
 
 use chrono::{DateTime, Utc};
 use sqlx::sqlite::SqliteRow;
@@ -11,11 +11,32 @@ use sqlx::{
 use teloxide::types::{ChatId, Message, MessageId, UserId};
 use tokio_stream::Stream;
 
-use crate::{tasks::Task, OWNER_ID};
+use crate::{strings::Lang, tasks::Task, OWNER_ID};
 
 type Pool = sqlx::Pool<Sqlite>;
 const DB_PATH: &str = "sqlite:teco_tools.sqlite";
 static WAS_CONSTRUCTED: AtomicBool = AtomicBool::new(false);
+/// How long after a task is completed editing its request message runs it again.
+pub const RERUN_ON_EDIT_TIME: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+/// How long the quick fix buttons under parameter errors keep working.
+pub const PARAM_FIX_TIME: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+/// How long to remember what task resulted in a message, for commands used on it.
+pub const RESULT_MESSAGE_TIME: chrono::TimeDelta = chrono::TimeDelta::days(30);
+/// How many completed tasks of each user are remembered for `/history` and `/redo`.
+pub const TASK_HISTORY_LENGTH: u32 = 5;
+
+/// Read a task as stored in the database.
+///
+/// Tasks that had no parameters once, like [`Task::AmenBreak`], were stored as just their name,
+/// so that is read as that task with default parameters.
+fn deserialize_task(json: &str) -> Task {
+    serde_json::from_str(json)
+        .or_else(|e| match serde_json::from_str::<String>(json) {
+            Ok(_) => serde_json::from_str(&format!("{{{}: {{}}}}", json)),
+            Err(_) => Err(e),
+        })
+        .unwrap()
+}
 
 #[allow(dead_code)] // Intentionally allow unused fields here.
 #[derive(Debug, Clone)]
@@ -31,6 +52,9 @@ pub struct TaskDatabaseInfo {
     pub in_progress: bool,
     pub premium: bool,
     pub delay_processing_until: Option<DateTime<Utc>>,
+    /// When this task was grabbed for completion by [`Database::grab_task`].
+    /// Not stored in the database.
+    pub grabbed_at: Option<Instant>,
 }
 
 impl TaskDatabaseInfo {
@@ -38,7 +62,7 @@ impl TaskDatabaseInfo {
         TaskDatabaseInfo {
             taskid: row.get(0),
             userid: UserId(row.get::<i64, _>(1) as u64),
-            task: serde_json::from_str(row.get(2)).unwrap(),
+            task: deserialize_task(row.get(2)),
             message: serde_json::from_str(row.get(3)).unwrap(),
             queue_message_chat_id: ChatId(row.get(4)),
             queue_message_id: MessageId(row.get(5)),
@@ -47,6 +71,7 @@ impl TaskDatabaseInfo {
             in_progress: row.get(8),
             premium: row.get(9),
             delay_processing_until: row.get(10),
+            grabbed_at: None,
         }
     }
 }
@@ -56,19 +81,27 @@ pub struct Database {
 }
 
 impl Database {
-    pub async fn new() -> Result<Self, Error> {
-        assert!(
-            !WAS_CONSTRUCTED.swap(true, std::sync::atomic::Ordering::SeqCst),
-            "Second database was constructed. This is not allowed."
-        );
+    pub fn new() -> impl std::future::Future<Output = Result<Self, Error>> + Send {
+        Self::new_by_path(DB_PATH, true)
+    }
 
-        if !Sqlite::database_exists(DB_PATH).await.unwrap_or(false) {
-            Sqlite::create_database(DB_PATH).await?;
+    /// Create a new database with specified path. Will check if it's a unique database if `unique`
+    /// is set.
+    pub(crate) async fn new_by_path(path: &str, unique: bool) -> Result<Self, Error> {
+        if unique {
+            assert!(
+                !WAS_CONSTRUCTED.swap(true, std::sync::atomic::Ordering::SeqCst),
+                "Second database was constructed. This is not allowed."
+            );
+        }
+
+        if !Sqlite::database_exists(path).await.unwrap_or(false) {
+            Sqlite::create_database(path).await?;
         }
         let pool = SqlitePoolOptions::new()
             .max_connections(32)
             .connect_with(
-                SqliteConnectOptions::from_str(DB_PATH)
+                SqliteConnectOptions::from_str(path)
                     .unwrap()
                     .pragma("cache_size", "-32768")
                     .busy_timeout(std::time::Duration::from_secs(600)),
@@ -91,6 +124,7 @@ impl Database {
         // in_progress (0 for no, 1 for yes)
         // premium (0 for no, 1 for yes),
         // delay_processing_until (date+time in UTC in RFC3339 format)
+        // command (text of the command the task was parsed from, like "/resize 50%", may be NULL)
         pool.execute(sqlx::query(
             "CREATE TABLE IF NOT EXISTS tasks (
                 taskid INTEGER PRIMARY KEY NOT NULL,
@@ -105,7 +139,76 @@ impl Database {
                 edit_response_message_id INTEGER NULL,
                 in_progress INTEGER NOT NULL,
                 premium INTEGER NOT NULL,
-                delay_processing_until TEXT NULL
+                delay_processing_until TEXT NULL,
+                command TEXT NULL
+            ) STRICT;",
+        ))
+        .await?;
+
+        // COMPLETED_TASKS:
+        //      Request messages of tasks completed within RERUN_ON_EDIT_TIME,
+        //      so that editing them runs the task again.
+        // request_message_chat_id (i64),
+        // request_message_id (i32 (because telegram bot api is just like that)),
+        // completed_at (date+time in UTC in RFC3339 format)
+        pool.execute(sqlx::query(
+            "CREATE TABLE IF NOT EXISTS completed_tasks (
+                request_message_chat_id INTEGER NOT NULL,
+                request_message_id INTEGER NOT NULL,
+                completed_at TEXT NOT NULL,
+                PRIMARY KEY (request_message_chat_id, request_message_id)
+            ) STRICT;",
+        ))
+        .await?;
+
+        // TASK_HISTORY:
+        //      Last TASK_HISTORY_LENGTH completed tasks of each user.
+        // userid (i64 because sqlite doesn't support u64)
+        // command (text of the command the task was parsed from, like "/resize 50%")
+        // task (task object serialized in JSON)
+        // completed_at (date+time in UTC in RFC3339 format)
+        pool.execute(sqlx::query(
+            "CREATE TABLE IF NOT EXISTS task_history (
+                userid INTEGER NOT NULL,
+                command TEXT NOT NULL,
+                task TEXT NOT NULL,
+                completed_at TEXT NOT NULL
+            ) STRICT;",
+        ))
+        .await?;
+
+        // RESULT_MESSAGES:
+        //      Messages with the media tasks resulted in, for up to RESULT_MESSAGE_TIME,
+        //      so that commands used on them can start off of what made them.
+        // result_chat_id (i64),
+        // result_message_id (i32 (because telegram bot api is just like that)),
+        // task (task object serialized in JSON)
+        // created_at (date+time in UTC in RFC3339 format)
+        pool.execute(sqlx::query(
+            "CREATE TABLE IF NOT EXISTS result_messages (
+                result_chat_id INTEGER NOT NULL,
+                result_message_id INTEGER NOT NULL,
+                task TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (result_chat_id, result_message_id)
+            ) STRICT;",
+        ))
+        .await?;
+
+        // PARAM_FIXES:
+        //      Request messages of commands that failed to parse, whose error responses
+        //      have quick fix buttons under them, for up to PARAM_FIX_TIME.
+        // response_chat_id (i64),
+        // response_message_id (i32 (because telegram bot api is just like that)),
+        // message (message that requested the task, serialized in JSON)
+        // created_at (date+time in UTC in RFC3339 format)
+        pool.execute(sqlx::query(
+            "CREATE TABLE IF NOT EXISTS param_fixes (
+                response_chat_id INTEGER NOT NULL,
+                response_message_id INTEGER NOT NULL,
+                message TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (response_chat_id, response_message_id)
             ) STRICT;",
         ))
         .await?;
@@ -119,6 +222,31 @@ impl Database {
         ))
         .await?;
 
+        // USER_LANGUAGES:
+        //      Languages users picked with /lang, instead of the one of their Telegram app.
+        // userid (key, u64)
+        // lang (language code, like "ru")
+        pool.execute(sqlx::query(
+            "CREATE TABLE IF NOT EXISTS user_languages (
+                userid INTEGER PRIMARY KEY NOT NULL,
+                lang TEXT NOT NULL
+            ) STRICT;",
+        ))
+        .await?;
+
+        // CHAT_QUALITIES:
+        //      Quality picked with /setquality_default for media resized in a chat,
+        //      if none is given in the command.
+        // chatid (key, i64)
+        // quality (between 1 and 100)
+        pool.execute(sqlx::query(
+            "CREATE TABLE IF NOT EXISTS chat_qualities (
+                chatid INTEGER PRIMARY KEY NOT NULL,
+                quality INTEGER NOT NULL
+            ) STRICT;",
+        ))
+        .await?;
+
         let _ = sqlx::query("CREATE INDEX tasks_userid ON tasks(userid);")
             .execute(&pool)
             .await;
@@ -128,6 +256,9 @@ impl Database {
         let _ = sqlx::query("CREATE INDEX tasks_in_progress ON tasks(in_progress);")
             .execute(&pool)
             .await;
+        let _ = sqlx::query("CREATE INDEX task_history_userid ON task_history(userid);")
+            .execute(&pool)
+            .await;
         let _ = sqlx::query(concat!(
             "CREATE INDEX tasks_request_message ON ",
             "tasks(request_message_chat_id, request_message_id);"
@@ -143,6 +274,12 @@ impl Database {
                 ADD COLUMN delay_processing_until TEXT NULL;",
             ))
             .await;
+        let _ = pool
+            .execute(sqlx::query(
+                "ALTER TABLE tasks
+                ADD COLUMN command TEXT NULL;",
+            ))
+            .await;
 
         // We're just starting, so nothing could be in progress.
         pool.execute(sqlx::query("UPDATE tasks SET in_progress=0;"))
@@ -168,10 +305,14 @@ impl Database {
     }
 
     /// Returns the new task's position in queue.
+    ///
+    /// `command` is the text the task was parsed from, to be remembered
+    /// in the user's history once it's done. See [`Self::get_recent_tasks_for_user`].
     pub(super) async fn add_task(
         &self,
         user: Option<UserId>,
         task: Task,
+        command: &str,
         request_message: &Message,
         queue_message: &Message,
         delay_processing_until: Option<DateTime<Utc>>,
@@ -203,8 +344,9 @@ impl Database {
                 queue_message_id,
                 in_progress,
                 premium,
-                delay_processing_until
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, 0, ?, ?);",
+                delay_processing_until,
+                command
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, 0, ?, ?, ?);",
         )
         .bind(user.map(|x| x.0 as i64))
         .bind(task_ser)
@@ -215,12 +357,18 @@ impl Database {
         .bind(queue_message_id)
         .bind(premium)
         .bind(delay_processing_until)
+        .bind(command)
         .execute(&self.pool)
         .await?;
 
         Ok(queue_size + 1)
     }
 
+    /// Returns true if the database responds to queries.
+    pub async fn is_alive(&self) -> bool {
+        sqlx::query("SELECT 1;").execute(&self.pool).await.is_ok()
+    }
+
     pub async fn idle_cleanup(&self) {
         let _ = sqlx::query("VACUUM;").execute(&self.pool).await;
         let _ = sqlx::query("ANALYZE;").execute(&self.pool).await;
@@ -294,6 +442,45 @@ impl Database {
         .await
     }
 
+    /// Get tasks requested by this user, along with their positions in queue, oldest first.
+    /// If `chat` is specified, only get tasks requested in that chat.
+    ///
+    /// Specifying the chat is useful for anonymous senders, which all share the same user.
+    pub async fn list_user_tasks(
+        &self,
+        user: Option<UserId>,
+        chat: Option<ChatId>,
+    ) -> Result<Vec<(u32, TaskDatabaseInfo)>, Error> {
+        sqlx::query(
+            "SELECT
+                taskid,
+                userid,
+                task,
+                message,
+                queue_message_chat_id,
+                queue_message_id,
+                edit_response_chat_id,
+                edit_response_message_id,
+                in_progress,
+                premium,
+                delay_processing_until,
+                (SELECT COUNT(*) FROM tasks AS earlier
+                    WHERE earlier.taskid < tasks.taskid AND earlier.premium=tasks.premium)
+            FROM tasks
+            WHERE userid IS ? AND (? IS NULL OR request_message_chat_id=?)
+            ORDER BY taskid;",
+        )
+        .bind(user.map(|x| x.0 as i64))
+        .bind(chat.map(|x| x.0))
+        .bind(chat.map(|x| x.0))
+        .map(|row: SqliteRow| {
+            let position: u32 = row.get(11);
+            (position, TaskDatabaseInfo::from_sqlite_row(row))
+        })
+        .fetch_all(&self.pool)
+        .await
+    }
+
     pub async fn get_task_by_request_message(
         &self,
         request_message: &Message,
@@ -341,42 +528,280 @@ impl Database {
         Ok(())
     }
 
+    /// Replace the task, the command it was parsed from, and its request message.
+    ///
+    /// Returns false without doing anything if the task is already in progress,
+    /// including if it was grabbed by [`Self::grab_task`] just before this.
     pub async fn edit_task(
         &self,
         taskid: i64,
         task: &Task,
+        command: &str,
         request_message: &Message,
-    ) -> Result<(), Error> {
+    ) -> Result<bool, Error> {
         let task_ser = serde_json::to_string(&task).unwrap();
         let request_message_ser = serde_json::to_string(request_message).unwrap();
 
         sqlx::query(
             "UPDATE tasks SET
                 task=?,
+                command=?,
                 message=?,
                 edit_response_chat_id=NULL,
                 edit_response_message_id=NULL
-            WHERE taskid=?",
+            WHERE taskid=? AND in_progress=0",
         )
         .bind(task_ser)
+        .bind(command)
         .bind(request_message_ser)
         //.bind(edit_response_chat_id.map(|x| x.0))
         //.bind(edit_response_message_id.map(|x| x.0))
         .bind(taskid)
         .execute(&self.pool)
-        .await?;
-        Ok(())
+        .await
+        .map(|x| x.rows_affected() > 0)
+    }
+
+    /// Delete task due to its cancellation.
+    ///
+    /// Returns false without doing anything if the task is already in progress,
+    /// including if it was grabbed by [`Self::grab_task`] just before this.
+    pub async fn cancel_task(&self, taskid: i64) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM tasks WHERE taskid=? AND in_progress=0;")
+            .bind(taskid)
+            .execute(&self.pool)
+            .await
+            .map(|x| x.rows_affected() > 0)
     }
 
-    /// Delete task, due to either completion or cancellation.
-    pub async fn delete_task(&self, taskid: i64) -> Result<(), Error> {
+    /// Delete task due to its completion, remember its request message
+    /// for [`RERUN_ON_EDIT_TIME`], and add it to its user's history.
+    /// Also forgets older completed tasks.
+    pub async fn complete_task(&self, taskid: i64, request_message: &Message) -> Result<(), Error> {
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await?;
+
+        // Tasks without a user, like ones from channel posts, have no history to go to.
+        // Anonymous admins do all share one user, but `/history` and `/redo` refuse them.
+        sqlx::query(
+            "INSERT INTO task_history (userid, command, task, completed_at)
+            SELECT userid, command, task, ? FROM tasks
+            WHERE taskid=? AND userid IS NOT NULL AND command IS NOT NULL;",
+        )
+        .bind(now)
+        .bind(taskid)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query(
+            "DELETE FROM task_history
+            WHERE userid=(SELECT userid FROM tasks WHERE taskid=?)
+            AND rowid NOT IN (
+                SELECT rowid FROM task_history
+                WHERE userid=(SELECT userid FROM tasks WHERE taskid=?)
+                ORDER BY rowid DESC LIMIT ?
+            );",
+        )
+        .bind(taskid)
+        .bind(taskid)
+        .bind(TASK_HISTORY_LENGTH)
+        .execute(&mut *transaction)
+        .await?;
+
         sqlx::query("DELETE FROM tasks WHERE taskid=?;")
             .bind(taskid)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query("DELETE FROM completed_tasks WHERE completed_at<?;")
+            .bind(now - RERUN_ON_EDIT_TIME)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO completed_tasks (
+                request_message_chat_id,
+                request_message_id,
+                completed_at
+            ) VALUES (?, ?, ?)
+            ON CONFLICT DO UPDATE SET completed_at=excluded.completed_at;",
+        )
+        .bind(request_message.chat.id.0)
+        .bind(request_message.id.0)
+        .bind(now)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await
+    }
+
+    /// Remember that this message is what this task resulted in for [`RESULT_MESSAGE_TIME`],
+    /// so that it's known what made it after the task is completed.
+    pub async fn set_task_result_message(
+        &self,
+        taskid: i64,
+        result: &Message,
+    ) -> Result<(), Error> {
+        let now = Utc::now();
+
+        // Forget about ones too old to matter.
+        sqlx::query("DELETE FROM result_messages WHERE created_at<?;")
+            .bind(now - RESULT_MESSAGE_TIME)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO result_messages (
+                result_chat_id,
+                result_message_id,
+                task,
+                created_at
+            )
+            SELECT ?, ?, task, ? FROM tasks WHERE taskid=?
+            ON CONFLICT DO UPDATE SET task=excluded.task, created_at=excluded.created_at;",
+        )
+        .bind(result.chat.id.0)
+        .bind(result.id.0)
+        .bind(now)
+        .bind(taskid)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the task whose result is this message, if it's remembered.
+    pub async fn get_task_by_result_message(
+        &self,
+        result: &Message,
+    ) -> Result<Option<Task>, Error> {
+        sqlx::query(
+            "SELECT task FROM result_messages
+            WHERE result_chat_id=? AND result_message_id=? AND created_at>=?;",
+        )
+        .bind(result.chat.id.0)
+        .bind(result.id.0)
+        .bind(Utc::now() - RESULT_MESSAGE_TIME)
+        .map(|row: SqliteRow| deserialize_task(row.get(0)))
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Returns true if a task requested by this message was completed
+    /// within [`RERUN_ON_EDIT_TIME`].
+    pub async fn was_task_recently_completed(
+        &self,
+        request_message: &Message,
+    ) -> Result<bool, Error> {
+        sqlx::query(
+            "SELECT 1 FROM completed_tasks
+            WHERE request_message_chat_id=? AND request_message_id=? AND completed_at>=?;",
+        )
+        .bind(request_message.chat.id.0)
+        .bind(request_message.id.0)
+        .bind(Utc::now() - RERUN_ON_EDIT_TIME)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|x| x.is_some())
+    }
+
+    /// Like [`Self::was_task_recently_completed`], but also forgets about that task,
+    /// so that only one caller gets true for it.
+    pub async fn take_recently_completed_task(
+        &self,
+        request_message: &Message,
+    ) -> Result<bool, Error> {
+        sqlx::query(
+            "DELETE FROM completed_tasks
+            WHERE request_message_chat_id=? AND request_message_id=? AND completed_at>=?
+            RETURNING 1;",
+        )
+        .bind(request_message.chat.id.0)
+        .bind(request_message.id.0)
+        .bind(Utc::now() - RERUN_ON_EDIT_TIME)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|x| x.is_some())
+    }
+
+    /// Remember the request message of a command that failed to parse,
+    /// for the quick fix buttons under its error response for [`PARAM_FIX_TIME`].
+    pub async fn add_param_fix(&self, response: &Message, request: &Message) -> Result<(), Error> {
+        let now = Utc::now();
+
+        // Forget about ones whose buttons don't work anymore anyway.
+        sqlx::query("DELETE FROM param_fixes WHERE created_at<?;")
+            .bind(now - PARAM_FIX_TIME)
             .execute(&self.pool)
             .await?;
+
+        sqlx::query(
+            "INSERT INTO param_fixes (
+                response_chat_id,
+                response_message_id,
+                message,
+                created_at
+            ) VALUES (?, ?, ?, ?)
+            ON CONFLICT DO NOTHING;",
+        )
+        .bind(response.chat.id.0)
+        .bind(response.id.0)
+        .bind(serde_json::to_string(request).unwrap())
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
         Ok(())
     }
 
+    /// Get the request message of the command whose error response is this message,
+    /// if its quick fix buttons still work.
+    pub async fn get_param_fix(&self, response: &Message) -> Result<Option<Message>, Error> {
+        sqlx::query(
+            "SELECT message FROM param_fixes
+            WHERE response_chat_id=? AND response_message_id=? AND created_at>=?;",
+        )
+        .bind(response.chat.id.0)
+        .bind(response.id.0)
+        .bind(Utc::now() - PARAM_FIX_TIME)
+        .map(|row: SqliteRow| serde_json::from_str(row.get(0)).unwrap())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Forget about the quick fix buttons under this error response,
+    /// so that only one caller gets true for it.
+    pub async fn take_param_fix(&self, response: &Message) -> Result<bool, Error> {
+        sqlx::query(
+            "DELETE FROM param_fixes
+            WHERE response_chat_id=? AND response_message_id=?
+            RETURNING 1;",
+        )
+        .bind(response.chat.id.0)
+        .bind(response.id.0)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|x| x.is_some())
+    }
+
+    /// Get up to `n` last completed tasks of this user, newest first,
+    /// along with the text of the commands they were parsed from.
+    pub async fn get_recent_tasks_for_user(
+        &self,
+        user: UserId,
+        n: u32,
+    ) -> Result<Vec<(String, Task)>, Error> {
+        sqlx::query(
+            "SELECT command, task FROM task_history
+            WHERE userid=?
+            ORDER BY rowid DESC LIMIT ?;",
+        )
+        .bind(user.0 as i64)
+        .bind(n)
+        .map(|row: SqliteRow| (row.get::<String, _>(0), deserialize_task(row.get(1))))
+        .fetch_all(&self.pool)
+        .await
+    }
+
     pub async fn grab_task(&self, premium: bool) -> Result<Option<TaskDatabaseInfo>, Error> {
         let now = Utc::now();
         // Select a task. Find a fitting one to complete,
@@ -406,7 +831,11 @@ impl Database {
             return Ok(None);
         };
 
-        self.get_task_by_id(taskid).await
+        let task = self.get_task_by_id(taskid).await?;
+        Ok(task.map(|task| TaskDatabaseInfo {
+            grabbed_at: Some(Instant::now()),
+            ..task
+        }))
     }
 
     pub async fn user_has_too_much_tasks(&self, user: Option<UserId>) -> Result<bool, Error> {
@@ -439,6 +868,74 @@ impl Database {
         Ok(())
     }
 
+    /// Get languages users picked with `/lang`.
+    pub async fn get_user_languages(&self) -> Result<Vec<(UserId, Lang)>, Error> {
+        Ok(sqlx::query("SELECT userid, lang FROM user_languages;")
+            .map(|row: SqliteRow| (row.get::<i64, _>(0), row.get::<String, _>(1)))
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .filter_map(|(user, lang)| Some((UserId(user as u64), Lang::parse(&lang)?)))
+            .collect())
+    }
+
+    /// Remember the language a user picked, or forget it if it's [`None`].
+    pub async fn set_user_language(&self, user: UserId, lang: Option<Lang>) -> Result<(), Error> {
+        if let Some(lang) = lang {
+            sqlx::query(
+                "INSERT INTO user_languages(userid, lang) VALUES (?, ?)
+                    ON CONFLICT(userid) DO UPDATE SET lang=excluded.lang;",
+            )
+            .bind(user.0 as i64)
+            .bind(lang.code())
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM user_languages WHERE userid=?;")
+                .bind(user.0 as i64)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get default qualities of all chats that have one set.
+    pub async fn get_chat_default_qualities(&self) -> Result<Vec<(ChatId, NonZeroU8)>, Error> {
+        Ok(sqlx::query("SELECT chatid, quality FROM chat_qualities;")
+            .map(|row: SqliteRow| (row.get::<i64, _>(0), row.get::<u8, _>(1)))
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .filter_map(|(chat, quality)| Some((ChatId(chat), NonZeroU8::new(quality)?)))
+            .collect())
+    }
+
+    /// Set the default quality of media resized in this chat, or forget it if it's [`None`].
+    pub async fn set_chat_default_quality(
+        &self,
+        chat: ChatId,
+        quality: Option<NonZeroU8>,
+    ) -> Result<(), Error> {
+        if let Some(quality) = quality {
+            sqlx::query(
+                "INSERT INTO chat_qualities(chatid, quality) VALUES (?, ?)
+                    ON CONFLICT(chatid) DO UPDATE SET quality=excluded.quality;",
+            )
+            .bind(chat.0)
+            .bind(quality.get())
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM chat_qualities WHERE chatid=?;")
+                .bind(chat.0)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
     /// Returns how long is left until at least one delayed task's delay expires.
     ///
     /// Returns `None` if there are no delayed tasks,
@@ -466,3 +963,374 @@ impl Database {
         ))
     }
 }
+
+#[test]
+fn old_tasks_are_read() {
+    assert!(matches!(
+        deserialize_task("\"AmenBreak\""),
+        Task::AmenBreak { seed: None }
+    ));
+    assert!(matches!(deserialize_task("\"Ocr\""), Task::Ocr));
+    let task = serde_json::to_string(&Task::AmenBreak { seed: Some(5) }).unwrap();
+    assert!(matches!(
+        deserialize_task(&task),
+        Task::AmenBreak { seed: Some(5) }
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Ret = Result<(), Error>;
+
+    pub fn new_temp() -> impl std::future::Future<Output = Result<Database, Error>> + Send {
+        Database::new_by_path("sqlite::memory:", false)
+    }
+
+    fn message(chat: i64, id: i32, text: &str) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": id,
+            "date": 0,
+            "chat": { "id": chat, "type": "supergroup", "title": "amogus" },
+            "text": text,
+        }))
+        .unwrap()
+    }
+
+    /// Add a task to the queue and grab it right away, returning its ID.
+    async fn add_and_grab(
+        db: &Database,
+        user: Option<UserId>,
+        task: Task,
+        command: &str,
+        request: &Message,
+    ) -> Result<i64, Error> {
+        let queue = message(request.chat.id.0, request.id.0 + 1000, "queued");
+        db.add_task(user, task, command, request, &queue, None)
+            .await?;
+        Ok(db.grab_task(false).await?.unwrap().taskid)
+    }
+
+    #[tokio::test]
+    async fn create_db() -> Ret {
+        new_temp().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn param_fixes() -> Ret {
+        let db = new_temp().await?;
+        let request = message(-100, 1, "/resize 5000x5000");
+        let response = message(-100, 2, "too big");
+        let other_response = message(-200, 2, "too big");
+
+        assert!(db.get_param_fix(&response).await?.is_none());
+        assert!(!db.take_param_fix(&response).await?);
+
+        db.add_param_fix(&response, &request).await?;
+        let found = db.get_param_fix(&response).await?.unwrap();
+        assert_eq!(found.id, request.id);
+        assert_eq!(found.text(), request.text());
+        // Same message ID in another chat is another message.
+        assert!(db.get_param_fix(&other_response).await?.is_none());
+
+        // Only one press gets to use it.
+        assert!(db.take_param_fix(&response).await?);
+        assert!(!db.take_param_fix(&response).await?);
+        assert!(db.get_param_fix(&response).await?.is_none());
+
+        // Ones that expired are not found, but can't be taken either.
+        let expired = message(-100, 3, "too big");
+        sqlx::query(
+            "INSERT INTO param_fixes (
+                response_chat_id,
+                response_message_id,
+                message,
+                created_at
+            ) VALUES (?, ?, ?, ?);",
+        )
+        .bind(expired.chat.id.0)
+        .bind(expired.id.0)
+        .bind(serde_json::to_string(&request).unwrap())
+        .bind(Utc::now() - PARAM_FIX_TIME - chrono::TimeDelta::minutes(1))
+        .execute(&db.pool)
+        .await?;
+        assert!(db.get_param_fix(&expired).await?.is_none());
+
+        // Adding another one cleans expired ones up.
+        db.add_param_fix(&response, &request).await?;
+        assert!(!db.take_param_fix(&expired).await?);
+        assert!(db.get_param_fix(&response).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn result_messages() -> Ret {
+        let db = new_temp().await?;
+        let user = Some(UserId(5));
+        let seeded = |seed| Task::AmenBreak { seed: Some(seed) };
+
+        let request = message(-100, 1, "/amenbreak");
+        let result = message(-100, 2, "");
+        let taskid = add_and_grab(&db, user, seeded(1), "/amenbreak", &request).await?;
+
+        assert!(db.get_task_by_result_message(&result).await?.is_none());
+        db.set_task_result_message(taskid, &result).await?;
+        let found = db.get_task_by_result_message(&result).await?;
+        assert!(matches!(found, Some(Task::AmenBreak { seed: Some(1) })));
+        // Same message ID in another chat is another message.
+        let elsewhere = message(-200, 2, "");
+        assert!(db.get_task_by_result_message(&elsewhere).await?.is_none());
+
+        // It's still known after the task is done and in the history.
+        db.complete_task(taskid, &request).await?;
+        let found = db.get_task_by_result_message(&result).await?;
+        assert!(matches!(found, Some(Task::AmenBreak { seed: Some(1) })));
+        let history = db.get_recent_tasks_for_user(UserId(5), 10).await?;
+        assert_eq!(history.len(), 1);
+        assert!(matches!(history[0].1, Task::AmenBreak { seed: Some(1) }));
+
+        // Tasks of anonymous users don't need a history entry for it.
+        let anonymous_request = message(-100, 3, "/amenbreak");
+        let anonymous_result = message(-100, 4, "");
+        let taskid = add_and_grab(&db, None, seeded(2), "/amenbreak", &anonymous_request).await?;
+        db.set_task_result_message(taskid, &anonymous_result)
+            .await?;
+        db.complete_task(taskid, &anonymous_request).await?;
+        let found = db.get_task_by_result_message(&anonymous_result).await?;
+        assert!(matches!(found, Some(Task::AmenBreak { seed: Some(2) })));
+
+        // Nor is it forgotten once the history moves on.
+        for id in 0..TASK_HISTORY_LENGTH as i32 + 1 {
+            let request = message(-100, 100 + id, "/amenbreak");
+            let taskid = add_and_grab(&db, user, seeded(3), "/amenbreak", &request).await?;
+            db.complete_task(taskid, &request).await?;
+        }
+        let history = db.get_recent_tasks_for_user(UserId(5), 10).await?;
+        assert_eq!(history.len(), TASK_HISTORY_LENGTH as usize);
+        assert!(history
+            .iter()
+            .all(|x| matches!(x.1, Task::AmenBreak { seed: Some(3) })));
+        let found = db.get_task_by_result_message(&result).await?;
+        assert!(matches!(found, Some(Task::AmenBreak { seed: Some(1) })));
+
+        // Old ones are forgotten.
+        sqlx::query("UPDATE result_messages SET created_at=?;")
+            .bind(Utc::now() - RESULT_MESSAGE_TIME - chrono::TimeDelta::days(1))
+            .execute(&db.pool)
+            .await?;
+        assert!(db.get_task_by_result_message(&result).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn task_history() -> Ret {
+        let db = new_temp().await?;
+        let (user, other) = (UserId(5), UserId(6));
+        let seeded = |seed| Task::AmenBreak { seed: Some(seed) };
+        let commands = |history: Vec<(String, Task)>| -> Vec<String> {
+            history.into_iter().map(|x| x.0).collect()
+        };
+
+        // Only completed tasks are remembered, newest first.
+        let request = message(-100, 1, "/amenbreak 1");
+        let taskid = add_and_grab(&db, Some(user), seeded(1), "/amenbreak 1", &request).await?;
+        assert!(db.get_recent_tasks_for_user(user, 10).await?.is_empty());
+        db.complete_task(taskid, &request).await?;
+        let request = message(-100, 2, "/redo");
+        // A `/redo` is queued with the command it redid, see `commands::redone_command`.
+        let taskid = add_and_grab(&db, Some(user), seeded(1), "/amenbreak 1", &request).await?;
+        db.complete_task(taskid, &request).await?;
+        let request = message(-100, 3, "/amenbreak 2");
+        let taskid = add_and_grab(&db, Some(other), seeded(2), "/amenbreak 2", &request).await?;
+        db.complete_task(taskid, &request).await?;
+
+        let history = db.get_recent_tasks_for_user(user, 10).await?;
+        assert_eq!(commands(history), ["/amenbreak 1", "/amenbreak 1"]);
+        assert_eq!(
+            commands(db.get_recent_tasks_for_user(other, 10).await?),
+            ["/amenbreak 2"]
+        );
+        assert_eq!(db.get_recent_tasks_for_user(user, 1).await?.len(), 1);
+
+        // Editing a queued task changes what it's remembered as.
+        let request = message(-100, 4, "/amenbreak 3");
+        let queue = message(-100, 1004, "queued");
+        db.add_task(
+            Some(user),
+            seeded(3),
+            "/amenbreak 3",
+            &request,
+            &queue,
+            None,
+        )
+        .await?;
+        let taskid = db
+            .get_task_by_request_message(&request)
+            .await?
+            .unwrap()
+            .taskid;
+        let edited = message(-100, 4, "/amenbreak 4");
+        assert!(
+            db.edit_task(taskid, &seeded(4), "/amenbreak 4", &edited)
+                .await?
+        );
+        assert_eq!(db.grab_task(false).await?.unwrap().taskid, taskid);
+        db.complete_task(taskid, &edited).await?;
+        let history = db.get_recent_tasks_for_user(user, 10).await?;
+        assert_eq!(history[0].0, "/amenbreak 4");
+        assert!(matches!(history[0].1, Task::AmenBreak { seed: Some(4) }));
+
+        // Cancelled tasks and tasks without a user aren't remembered.
+        let request = message(-100, 5, "/amenbreak 5");
+        db.add_task(
+            Some(user),
+            seeded(5),
+            "/amenbreak 5",
+            &request,
+            &queue,
+            None,
+        )
+        .await?;
+        let taskid = db
+            .get_task_by_request_message(&request)
+            .await?
+            .unwrap()
+            .taskid;
+        assert!(db.cancel_task(taskid).await?);
+        let request = message(-100, 6, "/amenbreak 6");
+        let taskid = add_and_grab(&db, None, seeded(6), "/amenbreak 6", &request).await?;
+        db.complete_task(taskid, &request).await?;
+        assert_eq!(db.get_recent_tasks_for_user(user, 10).await?.len(), 3);
+
+        // Only the last few are kept, without forgetting those of others.
+        for id in 0..TASK_HISTORY_LENGTH as i32 {
+            let request = message(-100, 100 + id, "/amenbreak 7");
+            let taskid = add_and_grab(&db, Some(user), seeded(7), "/amenbreak 7", &request).await?;
+            db.complete_task(taskid, &request).await?;
+        }
+        let history = db
+            .get_recent_tasks_for_user(user, TASK_HISTORY_LENGTH + 10)
+            .await?;
+        assert_eq!(history.len(), TASK_HISTORY_LENGTH as usize);
+        assert!(history.iter().all(|x| x.0 == "/amenbreak 7"));
+        assert_eq!(db.get_recent_tasks_for_user(other, 10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_languages() -> Ret {
+        let db = new_temp().await?;
+        assert_eq!(db.get_user_languages().await?, []);
+
+        db.set_user_language(UserId(5), Some(Lang::Ru)).await?;
+        db.set_user_language(UserId(6), Some(Lang::Ru)).await?;
+        db.set_user_language(UserId(6), Some(Lang::En)).await?;
+        let mut languages = db.get_user_languages().await?;
+        languages.sort_by_key(|x| x.0 .0);
+        assert_eq!(languages, [(UserId(5), Lang::Ru), (UserId(6), Lang::En)]);
+
+        // Forgetting one that isn't there is fine.
+        db.set_user_language(UserId(5), None).await?;
+        db.set_user_language(UserId(5), None).await?;
+        // Languages this version doesn't know about are skipped.
+        sqlx::query("INSERT INTO user_languages(userid, lang) VALUES (7, 'tlh');")
+            .execute(&db.pool)
+            .await?;
+        assert_eq!(db.get_user_languages().await?, [(UserId(6), Lang::En)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_default_qualities() -> Ret {
+        let db = new_temp().await?;
+        assert_eq!(db.get_chat_default_qualities().await?, []);
+
+        let quality = |x| NonZeroU8::new(x).unwrap();
+        db.set_chat_default_quality(ChatId(-100), Some(quality(40)))
+            .await?;
+        db.set_chat_default_quality(ChatId(-200), Some(quality(40)))
+            .await?;
+        db.set_chat_default_quality(ChatId(-200), Some(quality(90)))
+            .await?;
+        let mut qualities = db.get_chat_default_qualities().await?;
+        qualities.sort_by_key(|x| -x.0 .0);
+        assert_eq!(
+            qualities,
+            [(ChatId(-100), quality(40)), (ChatId(-200), quality(90))]
+        );
+
+        // Forgetting one that isn't there is fine.
+        db.set_chat_default_quality(ChatId(-100), None).await?;
+        db.set_chat_default_quality(ChatId(-100), None).await?;
+        assert_eq!(
+            db.get_chat_default_qualities().await?,
+            [(ChatId(-200), quality(90))]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn losing_races_for_tasks() -> Ret {
+        let db = new_temp().await?;
+        let user = Some(UserId(5));
+        let seeded = |seed| Task::AmenBreak { seed: Some(seed) };
+
+        // A task grabbed for completion can't be edited or cancelled anymore.
+        let request = message(-100, 1, "/amenbreak 1");
+        let taskid = add_and_grab(&db, user, seeded(1), "/amenbreak 1", &request).await?;
+        let edited = message(-100, 1, "/amenbreak 2");
+        assert!(
+            !db.edit_task(taskid, &seeded(2), "/amenbreak 2", &edited)
+                .await?
+        );
+        assert!(!db.cancel_task(taskid).await?);
+        let info = db.get_task_by_id(taskid).await?.unwrap();
+        assert!(info.in_progress);
+        assert!(matches!(info.task, Task::AmenBreak { seed: Some(1) }));
+
+        // Nor can a task that's already completed...
+        db.complete_task(taskid, &request).await?;
+        assert!(
+            !db.edit_task(taskid, &seeded(2), "/amenbreak 2", &edited)
+                .await?
+        );
+        assert!(!db.cancel_task(taskid).await?);
+        // ...and completing it again doesn't remember it twice.
+        db.complete_task(taskid, &request).await?;
+        assert_eq!(db.get_recent_tasks_for_user(UserId(5), 10).await?.len(), 1);
+
+        // Completing a task that was cancelled in the meantime doesn't remember it at all.
+        let request = message(-100, 2, "/amenbreak 3");
+        let queue = message(-100, 1002, "queued");
+        db.add_task(user, seeded(3), "/amenbreak 3", &request, &queue, None)
+            .await?;
+        let taskid = db
+            .get_task_by_request_message(&request)
+            .await?
+            .unwrap()
+            .taskid;
+        assert!(db.cancel_task(taskid).await?);
+        assert!(!db.cancel_task(taskid).await?);
+        db.complete_task(taskid, &request).await?;
+        assert_eq!(db.get_recent_tasks_for_user(UserId(5), 10).await?.len(), 1);
+
+        // Only one of simultaneous edits of a completed task's request reruns it.
+        let request = message(-100, 1, "/amenbreak 1");
+        let (first, second) = tokio::join!(
+            db.take_recently_completed_task(&request),
+            db.take_recently_completed_task(&request)
+        );
+        assert!(first? ^ second?);
+        assert!(!db.take_recently_completed_task(&request).await?);
+        assert!(!db.was_task_recently_completed(&request).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+//! Caching downloaded media on disk, so that running several tasks on the same file
+//! only downloads it from Telegram once.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use arch_bot_commons::useful_methods::FileStuff;
+use teloxide::{net::Download, requests::Requester, types::FileMeta, Bot, RequestError};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncReadExt;
+
+/// Environment variable to set the cache directory with.
+const CACHE_DIR_ENV_VAR: &str = "TECO_DOWNLOAD_CACHE_DIR";
+/// Cache directory to use if none is set.
+const DEFAULT_CACHE_DIR: &str = "download_cache";
+/// Environment variable to set the cache size with, in megabytes. 0 disables the cache.
+const CACHE_SIZE_ENV_VAR: &str = "TECO_DOWNLOAD_CACHE_MB";
+/// Cache size to use if none is set, in megabytes.
+const DEFAULT_CACHE_SIZE_MB: u64 = 1024;
+/// Files cached longer ago than this are deleted on startup.
+const MAX_CACHED_FILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A directory of files downloaded from Telegram, named by their unique IDs.
+///
+/// When the files take up more than the maximum size, the least recently used
+/// ones are deleted, except for ones that tasks are still working on.
+pub struct DownloadCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// A lock for each file being downloaded, so that tasks wanting the same file
+    /// wait for one download instead of each doing their own.
+    in_flight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// How many tasks are working on each cached file.
+    in_use: Mutex<HashMap<String, usize>>,
+}
+
+/// Keeps a downloaded file around while a task is working on it.
+pub enum DownloadLease<'a> {
+    /// The file is on this machine already, thanks to a local Bot API server.
+    Local,
+    /// The file wasn't cached, and is deleted when this is dropped.
+    Temp(#[allow(dead_code)] NamedTempFile),
+    /// The file is cached, and won't be evicted while this exists.
+    Cached(&'a DownloadCache, String),
+}
+
+impl Drop for DownloadLease<'_> {
+    fn drop(&mut self) {
+        if let DownloadLease::Cached(cache, key) = self {
+            let mut in_use = cache.in_use.lock().unwrap();
+            if let Some(count) = in_use.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    in_use.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl DownloadCache {
+    /// Make a cache in this directory, creating it if needed and deleting files
+    /// in it older than a day. `max_bytes` of 0 disables caching.
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        let cache = Self {
+            dir: dir.into(),
+            max_bytes,
+            in_flight: Mutex::new(HashMap::new()),
+            in_use: Mutex::new(HashMap::new()),
+        };
+
+        if max_bytes > 0 {
+            if let Err(e) = std::fs::create_dir_all(&cache.dir) {
+                log::error!(
+                    "Failed to create download cache directory {}: {}",
+                    cache.dir.display(),
+                    e
+                );
+            }
+            cache.clean(MAX_CACHED_FILE_AGE);
+        }
+
+        cache
+    }
+
+    /// Make a cache with the directory taken from the `TECO_DOWNLOAD_CACHE_DIR` environment
+    /// variable and the size from `TECO_DOWNLOAD_CACHE_MB`, or `download_cache` and
+    /// 1024 megabytes if they're not set.
+    pub fn from_env() -> Self {
+        let dir = std::env::var(CACHE_DIR_ENV_VAR).unwrap_or_else(|_| DEFAULT_CACHE_DIR.into());
+        let size_mb = match std::env::var(CACHE_SIZE_ENV_VAR) {
+            Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+                log::warn!(
+                    "Bad value for {}: {:?}, using {}MB instead.",
+                    CACHE_SIZE_ENV_VAR,
+                    value,
+                    DEFAULT_CACHE_SIZE_MB
+                );
+                DEFAULT_CACHE_SIZE_MB
+            }),
+            Err(_) => DEFAULT_CACHE_SIZE_MB,
+        };
+
+        log::info!("Download cache is {}MB in {}.", size_mb, dir);
+        Self::new(dir, cache_size_bytes(size_mb))
+    }
+
+    /// Like [`arch_bot_commons::useful_methods::BotStuff::download_file_to_temp_or_directly`],
+    /// but takes the file from the cache if it's there, and puts it there otherwise.
+    ///
+    /// The returned lease must be kept for as long as the file is used.
+    pub async fn download_file_to_temp_or_directly(
+        &self,
+        bot: &Bot,
+        file: &FileMeta,
+    ) -> Result<(PathBuf, DownloadLease<'_>), RequestError> {
+        let file = bot.get_file(&file.id).await?;
+        if file.is_local() {
+            return Ok((PathBuf::from(file.path), DownloadLease::Local));
+        }
+
+        let file_path = &file.path;
+        self.get_or_fetch(&file.unique_id, file.size.into(), |mut to| async move {
+            bot.download_file(file_path, &mut to).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Like [`arch_bot_commons::useful_methods::BotStuff::download_file_to_vec`],
+    /// but going through the cache.
+    pub async fn download_file_to_vec(
+        &self,
+        bot: &Bot,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+    ) -> Result<(), RequestError> {
+        let (path, _lease) = self.download_file_to_temp_or_directly(bot, file).await?;
+        let mut file = tokio::fs::File::open(path).await?;
+        file.read_to_end(to).await?;
+        Ok(())
+    }
+
+    /// Get the file with this unique ID and size from the cache, or download it there
+    /// by writing it with `fetch`. If someone else is already downloading it, wait for them.
+    ///
+    /// Files that are too big for the cache, or if it's disabled, go to a temporary file instead.
+    async fn get_or_fetch<F, Fut>(
+        &self,
+        unique_id: &str,
+        expected_size: u64,
+        fetch: F,
+    ) -> Result<(PathBuf, DownloadLease<'_>), RequestError>
+    where
+        F: FnOnce(tokio::fs::File) -> Fut,
+        Fut: Future<Output = Result<(), RequestError>>,
+    {
+        // Unique IDs are just letters, digits, dashes and underscores,
+        // but check anyway so it's never a weird path.
+        let sane_id = !unique_id.is_empty()
+            && unique_id
+                .bytes()
+                .all(|x| x.is_ascii_alphanumeric() || x == b'-' || x == b'_');
+
+        if !sane_id || expected_size == 0 || expected_size > self.max_bytes {
+            let tempfile = NamedTempFile::new()?;
+            fetch(tokio::fs::File::from_std(tempfile.reopen()?)).await?;
+            return Ok((tempfile.path().to_path_buf(), DownloadLease::Temp(tempfile)));
+        }
+
+        let lock = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(unique_id.to_string())
+            .or_default()
+            .clone();
+
+        let result = {
+            let _guard = lock.lock().await;
+            self.get_or_fetch_locked(unique_id, expected_size, fetch)
+                .await
+        };
+
+        // Forget the lock if nobody else is waiting on it.
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if Arc::strong_count(&lock) == 2 {
+            in_flight.remove(unique_id);
+        }
+
+        result
+    }
+
+    async fn get_or_fetch_locked<F, Fut>(
+        &self,
+        unique_id: &str,
+        expected_size: u64,
+        fetch: F,
+    ) -> Result<(PathBuf, DownloadLease<'_>), RequestError>
+    where
+        F: FnOnce(tokio::fs::File) -> Fut,
+        Fut: Future<Output = Result<(), RequestError>>,
+    {
+        let path = self.dir.join(unique_id);
+        // Taken before checking, so that it can't be evicted in the meantime.
+        let lease = self.lease(unique_id);
+
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.len() == expected_size => {
+                log::debug!("Download cache hit for {}.", unique_id);
+                touch(&path);
+                return Ok((path, lease));
+            }
+            Ok(metadata) => {
+                log::warn!(
+                    "Cached file {} is {} bytes instead of {}, downloading it again.",
+                    unique_id,
+                    metadata.len(),
+                    expected_size
+                );
+                let _ = std::fs::remove_file(&path);
+            }
+            Err(_) => (),
+        }
+
+        let partial = NamedTempFile::new_in(&self.dir)?;
+        fetch(tokio::fs::File::from_std(partial.reopen()?)).await?;
+
+        let size = partial.as_file().metadata()?.len();
+        if size != expected_size {
+            log::warn!(
+                "Downloaded file {} is {} bytes instead of {}, not caching it.",
+                unique_id,
+                size,
+                expected_size
+            );
+            return Ok((partial.path().to_path_buf(), DownloadLease::Temp(partial)));
+        }
+
+        partial.persist(&path).map_err(|e| e.error)?;
+        self.evict();
+
+        Ok((path, lease))
+    }
+
+    fn lease(&self, unique_id: &str) -> DownloadLease<'_> {
+        *self
+            .in_use
+            .lock()
+            .unwrap()
+            .entry(unique_id.to_string())
+            .or_default() += 1;
+        DownloadLease::Cached(self, unique_id.to_string())
+    }
+
+    /// Files in the cache, as their name, size and last time they were used.
+    /// Temporary files of downloads in progress are not included.
+    fn cached_files(&self) -> Vec<(String, u64, SystemTime)> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                let metadata = entry.metadata().ok()?;
+                if name.starts_with('.') || !metadata.is_file() {
+                    return None;
+                }
+                Some((name, metadata.len(), metadata.modified().ok()?))
+            })
+            .collect()
+    }
+
+    /// Delete least recently used files not used by any tasks
+    /// until the cache fits in its maximum size.
+    fn evict(&self) {
+        let mut files = self.cached_files();
+        let mut total: u64 = files.iter().map(|x| x.1).sum();
+        if total <= self.max_bytes {
+            return;
+        }
+
+        files.sort_by_key(|x| x.2);
+        let in_use = self.in_use.lock().unwrap();
+
+        for (name, size, _) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if in_use.contains_key(&name) {
+                continue;
+            }
+            if std::fs::remove_file(self.dir.join(&name)).is_ok() {
+                log::debug!("Evicted {} from the download cache.", name);
+                total -= size;
+            }
+        }
+    }
+
+    /// Delete files last used longer ago than `max_age`, as well as
+    /// leftovers of downloads that never finished.
+    fn clean(&self, max_age: Duration) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+
+        let now = SystemTime::now();
+        let mut removed = 0;
+
+        for entry in entries.flatten() {
+            let unfinished = entry.file_name().to_string_lossy().starts_with('.');
+            let too_old = entry
+                .metadata()
+                .and_then(|x| x.modified())
+                .map(|x| now.duration_since(x).unwrap_or_default() > max_age)
+                .unwrap_or(true);
+
+            if (unfinished || too_old) && std::fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            log::info!("Deleted {} old files from the download cache.", removed);
+        }
+    }
+}
+
+/// How many bytes a cache of this many megabytes may take.
+/// Absurdly big sizes are as good as no limit, so don't overflow on them.
+fn cache_size_bytes(size_mb: u64) -> u64 {
+    size_mb.saturating_mul(1024 * 1024)
+}
+
+/// Mark this file as just used, for eviction purposes.
+fn touch(path: &Path) {
+    let result = std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|x| x.set_modified(SystemTime::now()));
+    if let Err(e) = result {
+        log::warn!("Failed to touch cached file {}: {}", path.display(), e);
+    }
+}
+
+#[tokio::test]
+async fn download_cache_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncWriteExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = DownloadCache::new(dir.path(), 100);
+    let downloads = AtomicUsize::new(0);
+
+    let fetch = |contents: &'static [u8]| {
+        let downloads = &downloads;
+        move |mut to: tokio::fs::File| async move {
+            downloads.fetch_add(1, Ordering::SeqCst);
+            // Give others a chance to try downloading the same thing at once.
+            tokio::task::yield_now().await;
+            to.write_all(contents).await?;
+            to.flush().await?;
+            Ok(())
+        }
+    };
+
+    // Concurrent tasks wanting the same file share one download.
+    let (a, b) = tokio::join!(
+        cache.get_or_fetch("a", 40, fetch(&[1; 40])),
+        cache.get_or_fetch("a", 40, fetch(&[1; 40])),
+    );
+    let ((path_a, lease_a), (path_b, lease_b)) = (a.unwrap(), b.unwrap());
+    assert_eq!(path_a, path_b);
+    assert_eq!(std::fs::read(&path_a).unwrap(), [1; 40]);
+    assert_eq!(downloads.load(Ordering::SeqCst), 1);
+    drop((lease_a, lease_b));
+
+    // And later ones take it from the cache.
+    let (_, lease) = cache.get_or_fetch("a", 40, fetch(&[1; 40])).await.unwrap();
+    assert_eq!(downloads.load(Ordering::SeqCst), 1);
+    drop(lease);
+
+    // A file of the wrong size is downloaded again.
+    std::fs::write(dir.path().join("b"), [0; 10]).unwrap();
+    let (path_b, lease_b) = cache.get_or_fetch("b", 40, fetch(&[2; 40])).await.unwrap();
+    assert_eq!(std::fs::read(&path_b).unwrap(), [2; 40]);
+    assert_eq!(downloads.load(Ordering::SeqCst), 2);
+
+    // Going over the size evicts the least recently used file not in use.
+    let (path_c, lease_c) = cache.get_or_fetch("c", 40, fetch(&[3; 40])).await.unwrap();
+    assert!(!path_a.exists());
+    assert!(path_b.exists() && path_c.exists());
+
+    // Files in use are never evicted.
+    let (path_d, lease_d) = cache.get_or_fetch("d", 40, fetch(&[4; 40])).await.unwrap();
+    assert!(path_b.exists() && path_c.exists() && path_d.exists());
+    drop((lease_b, lease_c, lease_d));
+
+    // Too big files aren't cached at all.
+    let (path_e, lease_e) = cache
+        .get_or_fetch("e", 200, fetch(&[5; 200]))
+        .await
+        .unwrap();
+    assert_ne!(path_e.parent(), Some(dir.path()));
+    drop(lease_e);
+    assert!(!path_e.exists());
+
+    // Old files are cleaned on startup.
+    cache.clean(Duration::ZERO);
+    assert!(cache.cached_files().is_empty());
+}
+
+#[test]
+fn cache_size_bytes_test() {
+    assert_eq!(cache_size_bytes(0), 0);
+    assert_eq!(cache_size_bytes(1024), 1 << 30);
+    assert_eq!(cache_size_bytes(u64::MAX / 1024), u64::MAX);
+}
@@ -0,0 +1,157 @@
+//! Limiting how much memory all running tasks may use at once.
+
+use std::sync::Arc;
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+
+/// Environment variable to set the memory budget with, in megabytes.
+const MEMORY_BUDGET_ENV_VAR: &str = "TECO_MEMORY_BUDGET_MB";
+/// Memory budget to use if none is set, in megabytes.
+const DEFAULT_MEMORY_BUDGET_MB: u32 = 2048;
+
+/// Keeps track of how much memory running tasks estimate to use,
+/// and makes tasks wait if starting them would go over the budget.
+///
+/// Internally this is a semaphore with a permit per megabyte.
+pub struct ResourceGovernor {
+    semaphore: Arc<Semaphore>,
+    budget_mb: u32,
+}
+
+impl ResourceGovernor {
+    pub fn new(budget_mb: u32) -> Self {
+        let budget_mb = budget_mb.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(budget_mb as usize)),
+            budget_mb,
+        }
+    }
+
+    /// Make a governor with the budget taken from the `TECO_MEMORY_BUDGET_MB`
+    /// environment variable, or 2048 megabytes if it's not set.
+    pub fn from_env() -> Self {
+        let budget_mb = match std::env::var(MEMORY_BUDGET_ENV_VAR) {
+            Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+                log::warn!(
+                    "Bad value for {}: {:?}, using {}MB instead.",
+                    MEMORY_BUDGET_ENV_VAR,
+                    value,
+                    DEFAULT_MEMORY_BUDGET_MB
+                );
+                DEFAULT_MEMORY_BUDGET_MB
+            }),
+            Err(_) => DEFAULT_MEMORY_BUDGET_MB,
+        };
+
+        log::info!("Memory budget for tasks is {}MB.", budget_mb);
+        Self::new(budget_mb)
+    }
+
+    /// How many megabytes a task estimated to use this many bytes will take from the budget.
+    ///
+    /// Tasks estimated to need more than the whole budget take all of it,
+    /// meaning that they still run, but only while nothing else is running.
+    fn megabytes_for(&self, estimate_bytes: u64) -> u32 {
+        estimate_bytes
+            .div_ceil(1024 * 1024)
+            .clamp(1, self.budget_mb as u64) as u32
+    }
+
+    /// Take this many bytes from the budget if it's immediately available.
+    pub fn try_acquire(&self, estimate_bytes: u64) -> Option<MemoryReservation> {
+        self.semaphore
+            .clone()
+            .try_acquire_many_owned(self.megabytes_for(estimate_bytes))
+            .ok()
+            .map(|permit| MemoryReservation(Arc::new(permit)))
+    }
+
+    /// Take this many bytes from the budget, waiting for other tasks to give back enough
+    /// if needed. `on_wait` is called once if waiting is needed.
+    ///
+    /// The budget is given back when the returned reservation is dropped.
+    pub async fn acquire(&self, estimate_bytes: u64, on_wait: impl FnOnce()) -> MemoryReservation {
+        if let Some(permit) = self.try_acquire(estimate_bytes) {
+            return permit;
+        }
+
+        on_wait();
+
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_many_owned(self.megabytes_for(estimate_bytes))
+            .await
+            .expect("The semaphore is never closed");
+        MemoryReservation(Arc::new(permit))
+    }
+}
+
+/// Memory taken from the budget of a [`ResourceGovernor`], given back once this
+/// and all processing spawned with it are done.
+pub struct MemoryReservation(Arc<OwnedSemaphorePermit>);
+
+impl MemoryReservation {
+    /// Run blocking processing that uses this memory.
+    ///
+    /// The processing keeps the memory reserved until it's done, even if the task
+    /// stops waiting for it, like when it times out.
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let reservation = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = f();
+            drop(reservation);
+            result
+        })
+    }
+}
+
+#[tokio::test]
+async fn resource_governor_test() {
+    const MB: u64 = 1024 * 1024;
+    let governor = ResourceGovernor::new(100);
+
+    let a = governor.try_acquire(60 * MB).unwrap();
+    assert!(governor.try_acquire(60 * MB).is_none());
+    let b = governor.try_acquire(40 * MB).unwrap();
+    drop(a);
+    assert!(governor.try_acquire(60 * MB).is_some());
+    drop(b);
+
+    // Too big tasks still run, but only on their own.
+    let huge = governor.try_acquire(500 * MB).unwrap();
+    assert!(governor.try_acquire(1).is_none());
+    drop(huge);
+
+    // Waiting works out once the budget is given back.
+    let a = governor.try_acquire(100 * MB).unwrap();
+    let mut waited = false;
+    let (b, ()) = tokio::join!(governor.acquire(50 * MB, || waited = true), async {
+        tokio::task::yield_now().await;
+        drop(a);
+    });
+    assert!(waited);
+    drop(b);
+
+    // Processing keeps its memory even if the reservation is dropped before it's done.
+    let (started_sender, started) = std::sync::mpsc::channel();
+    let (finish, finish_receiver) = std::sync::mpsc::channel::<()>();
+    let a = governor.try_acquire(100 * MB).unwrap();
+    let handle = a.spawn_blocking(move || {
+        started_sender.send(()).unwrap();
+        finish_receiver.recv().unwrap();
+    });
+    started.recv().unwrap();
+    drop(a);
+    assert!(governor.try_acquire(1).is_none());
+    finish.send(()).unwrap();
+    handle.await.unwrap();
+    assert!(governor.try_acquire(100 * MB).is_some());
+}
@@ -1,17 +1,25 @@
 use std::{
-    sync::{atomic::AtomicBool, Arc, Weak},
+    collections::HashMap,
+    num::NonZeroU8,
+    sync::{atomic::AtomicBool, Arc, RwLock, Weak},
     time::Duration,
 };
 
+pub mod album_cache;
 pub mod database;
+pub mod download_cache;
+pub mod governor;
+use album_cache::AlbumCache;
 use arch_bot_commons::{teloxide_retry, useful_methods::BotArchSendMsg};
 use chrono::{DateTime, Utc};
 use database::Database;
+use download_cache::DownloadCache;
+use governor::ResourceGovernor;
 use html_escape::encode_text;
 use teloxide::{
     payloads::EditMessageTextSetters,
     requests::Requester,
-    types::{Message, UserId},
+    types::{ChatId, Message, UserId},
     ApiError, Bot, RequestError,
 };
 use tokio::{
@@ -21,13 +29,52 @@ use tokio::{
 use tokio_stream::StreamExt;
 
 use super::Task;
-use crate::OWNER_ID;
+use crate::{
+    strings::{Key, Lang},
+    OWNER_ID,
+};
 
 pub struct Taskman {
     pub db: Arc<Database>,
     bot: Bot,
     // Arc is so that taskman can be dropped independently of notify
     notify: Arc<Notify>,
+    governor: ResourceGovernor,
+    download_cache: DownloadCache,
+    /// Photos of recently seen albums.
+    pub albums: AlbumCache,
+    /// How long media processing of a single task may take before it's given up on.
+    task_timeout: Duration,
+    /// Languages users picked with `/lang`, kept in memory to not ask the database
+    /// about every message.
+    user_languages: RwLock<HashMap<UserId, Lang>>,
+    /// Qualities chats picked with `/setquality_default`, kept in memory for the same reason.
+    chat_qualities: RwLock<HashMap<ChatId, NonZeroU8>>,
+}
+
+/// Environment variable to set the task timeout with, in seconds.
+const TASK_TIMEOUT_ENV_VAR: &str = "TECO_TASK_TIMEOUT_SECS";
+/// Task timeout to use if none is set, in seconds.
+const DEFAULT_TASK_TIMEOUT_SECS: u64 = 10 * 60;
+
+/// Read the task timeout from the `TECO_TASK_TIMEOUT_SECS` environment variable,
+/// or use 10 minutes if it's not set.
+fn task_timeout_from_env() -> Duration {
+    let seconds = match std::env::var(TASK_TIMEOUT_ENV_VAR) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!(
+                "Bad value for {}: {:?}, using {} seconds instead.",
+                TASK_TIMEOUT_ENV_VAR,
+                value,
+                DEFAULT_TASK_TIMEOUT_SECS
+            );
+            DEFAULT_TASK_TIMEOUT_SECS
+        }),
+        Err(_) => DEFAULT_TASK_TIMEOUT_SECS,
+    };
+
+    log::info!("Task timeout is {} seconds.", seconds);
+    Duration::from_secs(seconds)
 }
 
 static WAS_CONSTRUCTED: AtomicBool = AtomicBool::new(false);
@@ -46,11 +93,7 @@ impl Taskman {
         );
 
         #[allow(clippy::let_and_return)]
-        let taskman = Arc::new(Self {
-            db,
-            bot,
-            notify: Arc::new(Notify::new()),
-        });
+        let taskman = Arc::new(Self::without_workers(db, bot, DownloadCache::from_env()).await);
 
         tokio::task::spawn(queue_counter_spinjob(Arc::downgrade(&taskman)));
 
@@ -68,7 +111,7 @@ impl Taskman {
             spawntask(false);
             spawntask(true);
         }
-        if parallelisms % 2 > 0 {
+        if !parallelisms.is_multiple_of(2) {
             // If we have an odd amount of parallelisms, spawn an extra task for that one
             spawntask(true);
         }
@@ -76,11 +119,95 @@ impl Taskman {
         taskman
     }
 
+    /// Make a taskman with what's remembered in the database loaded,
+    /// without starting the jobs that complete tasks.
+    async fn without_workers(db: Arc<Database>, bot: Bot, download_cache: DownloadCache) -> Self {
+        let user_languages = db
+            .get_user_languages()
+            .await
+            .expect("Database died!")
+            .into_iter()
+            .collect();
+        let chat_qualities = db
+            .get_chat_default_qualities()
+            .await
+            .expect("Database died!")
+            .into_iter()
+            .collect();
+
+        Self {
+            db,
+            bot,
+            notify: Arc::new(Notify::new()),
+            governor: ResourceGovernor::from_env(),
+            download_cache,
+            albums: AlbumCache::default(),
+            task_timeout: task_timeout_from_env(),
+            user_languages: RwLock::new(user_languages),
+            chat_qualities: RwLock::new(chat_qualities),
+        }
+    }
+
+    /// Language to talk to the sender of this message in: the one they picked with `/lang`,
+    /// or else the one of their Telegram app, or else English.
+    pub fn lang_for(&self, message: &Message) -> Lang {
+        let Some(from) = message.from() else {
+            return Lang::default();
+        };
+        if let Some(lang) = self.user_languages.read().unwrap().get(&from.id) {
+            return *lang;
+        }
+        Lang::from_code(from.language_code.as_deref())
+    }
+
+    /// Remember the language a user picked with `/lang`,
+    /// or go back to the one of their Telegram app if it's [`None`].
+    pub async fn set_user_language(
+        &self,
+        user: UserId,
+        lang: Option<Lang>,
+    ) -> Result<(), database::Error> {
+        self.db.set_user_language(user, lang).await?;
+        let mut user_languages = self.user_languages.write().unwrap();
+        if let Some(lang) = lang {
+            user_languages.insert(user, lang);
+        } else {
+            user_languages.remove(&user);
+        }
+        Ok(())
+    }
+
+    /// Quality media resized in this chat gets if the command doesn't say,
+    /// if one was picked with `/setquality_default`.
+    pub fn default_quality_for(&self, chat: ChatId) -> Option<NonZeroU8> {
+        self.chat_qualities.read().unwrap().get(&chat).copied()
+    }
+
+    /// Remember the default quality picked for this chat with `/setquality_default`,
+    /// or go back to the usual ones if it's [`None`].
+    pub async fn set_chat_default_quality(
+        &self,
+        chat: ChatId,
+        quality: Option<NonZeroU8>,
+    ) -> Result<(), database::Error> {
+        self.db.set_chat_default_quality(chat, quality).await?;
+        let mut chat_qualities = self.chat_qualities.write().unwrap();
+        if let Some(quality) = quality {
+            chat_qualities.insert(chat, quality);
+        } else {
+            chat_qualities.remove(&chat);
+        }
+        Ok(())
+    }
+
     /// Returns the new task's position in queue, and if it's delayed.
+    ///
+    /// `command` is the text the task was parsed from, see [`Database::add_task`].
     pub async fn add_task(
         &self,
         user: Option<UserId>,
         task: Task,
+        command: &str,
         request_message: &Message,
         queue_response_message: &Message,
         delay_processing_until: Option<DateTime<Utc>>,
@@ -90,6 +217,7 @@ impl Taskman {
             .add_task(
                 user,
                 task,
+                command,
                 request_message,
                 queue_response_message,
                 delay_processing_until,
@@ -152,11 +280,13 @@ pub async fn task_completion_spinjob(taskman: Weak<Taskman>, premium: bool) {
             continue;
         };
 
+        let lang = taskman.lang_for(&task_data.message);
+
         // Inform the user that we're doing the task.
         macro_rules! produce_queue_message {
             ($task: expr, $taskman:expr, $progress: expr) => {
                 // Inform the user that we're doing the task.
-                let response = $task.produce_queue_message(Some(0), $progress);
+                let response = $task.produce_queue_message(Some(0), $progress, lang);
                 let _ = $taskman
                     .bot
                     .edit_message_text(
@@ -195,7 +325,16 @@ pub async fn task_completion_spinjob(taskman: Weak<Taskman>, premium: bool) {
         let result = teloxide_retry!(
             task_data
                 .task
-                .complete_task(sender.clone(), &taskman.bot, &task_data)
+                .complete_task(
+                    sender.clone(),
+                    &taskman.bot,
+                    &taskman.governor,
+                    &taskman.download_cache,
+                    taskman.task_timeout,
+                    &task_data,
+                    &taskman.db,
+                    lang,
+                )
                 .await
         );
 
@@ -225,10 +364,7 @@ pub async fn task_completion_spinjob(taskman: Weak<Taskman>, premium: bool) {
                     .bot
                     .archsendmsg(
                         task_data.message.chat.id,
-                        concat!(
-                            "An error has occurred while processing this task. ",
-                            "The bot's owner will be notified to fix this."
-                        ),
+                        Key::TaskFailed.get(lang),
                         task_data.message.id,
                     )
                     .await;
@@ -266,7 +402,7 @@ pub async fn task_completion_spinjob(taskman: Weak<Taskman>, premium: bool) {
 
         taskman
             .db
-            .delete_task(task_data.taskid)
+            .complete_task(task_data.taskid, &task_data.message)
             .await
             .expect("Database died!");
 
@@ -325,9 +461,11 @@ pub async fn queue_counter_spinjob(taskman: Weak<Taskman>) {
                 Some(queue_size)
             };
 
-            let response = taskdata
-                .task
-                .produce_queue_message(queue_size_if_not_delayed, None);
+            let response = taskdata.task.produce_queue_message(
+                queue_size_if_not_delayed,
+                None,
+                taskman.lang_for(&taskdata.message),
+            );
 
             if taskman
                 .bot
@@ -353,3 +491,136 @@ pub async fn queue_counter_spinjob(taskman: Weak<Taskman>) {
         //sleep(Duration::from_secs(5)).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn idle_taskman(db: Arc<Database>) -> Taskman {
+        // A cache of 0 bytes doesn't touch the disk.
+        let download_cache = DownloadCache::new("download_cache", 0);
+        Taskman::without_workers(db, Bot::new("0:test"), download_cache).await
+    }
+
+    /// Make a message from this user, or from nobody, like a channel post.
+    fn message(user: Option<u64>, language_code: Option<&str>) -> Message {
+        let mut message = serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": -100, "type": "supergroup", "title": "amogus" },
+            "text": "/amogus",
+        });
+        if let Some(user) = user {
+            message["from"] = serde_json::json!({
+                "id": user,
+                "is_bot": false,
+                "first_name": "Amogus",
+                "language_code": language_code,
+            });
+        }
+        serde_json::from_value(message).unwrap()
+    }
+
+    #[tokio::test]
+    async fn languages_of_users() {
+        let db = Arc::new(
+            Database::new_by_path("sqlite::memory:", false)
+                .await
+                .unwrap(),
+        );
+        let taskman = idle_taskman(db.clone()).await;
+        let (russian, english) = (
+            message(Some(5), Some("ru")),
+            message(Some(6), Some("en-US")),
+        );
+
+        // Taken from their Telegram app by default.
+        assert_eq!(taskman.lang_for(&russian), Lang::Ru);
+        assert_eq!(taskman.lang_for(&english), Lang::En);
+        assert_eq!(taskman.lang_for(&message(Some(7), Some("uk"))), Lang::En);
+        assert_eq!(taskman.lang_for(&message(Some(7), None)), Lang::En);
+        assert_eq!(taskman.lang_for(&message(None, None)), Lang::En);
+
+        // Picked ones win.
+        taskman
+            .set_user_language(UserId(5), Some(Lang::En))
+            .await
+            .unwrap();
+        taskman
+            .set_user_language(UserId(6), Some(Lang::Ru))
+            .await
+            .unwrap();
+        assert_eq!(taskman.lang_for(&russian), Lang::En);
+        assert_eq!(taskman.lang_for(&english), Lang::Ru);
+
+        // And are remembered across restarts.
+        taskman.set_user_language(UserId(6), None).await.unwrap();
+        let restarted = idle_taskman(db).await;
+        assert_eq!(restarted.lang_for(&russian), Lang::En);
+        assert_eq!(restarted.lang_for(&english), Lang::En);
+    }
+
+    #[tokio::test]
+    async fn chat_default_qualities() {
+        use crate::{
+            handlers::commands::TaskParams,
+            tasks::{ImageFormat, ResizeType},
+        };
+        use teloxide::types::Me;
+
+        let db = Arc::new(
+            Database::new_by_path("sqlite::memory:", false)
+                .await
+                .unwrap(),
+        );
+        let taskman = idle_taskman(db.clone()).await;
+        let quality = |task: Task| match task {
+            Task::ImageResize { quality, .. } => quality.get(),
+            _ => unreachable!(),
+        };
+        let forty = NonZeroU8::new(40).unwrap();
+
+        let bot = Bot::new("0:test");
+        let me: Me = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": "Teco",
+            "username": "Teco_Tools_Bot",
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }))
+        .unwrap();
+        let mut message = serde_json::to_value(message(Some(5), None)).unwrap();
+        message["text"] = "/resize 50%".into();
+        let message: Message = serde_json::from_value(message).unwrap();
+        let tp = TaskParams::new(&taskman, &bot, &me, &message).unwrap();
+
+        let task = Task::default_image_resize(200, 100, ResizeType::Fit, ImageFormat::Preserve);
+        let usual = quality(task.parse_params(&tp).unwrap());
+        assert_ne!(usual, 40);
+
+        taskman
+            .set_chat_default_quality(message.chat.id, Some(forty))
+            .await
+            .unwrap();
+        assert_eq!(quality(task.parse_params(&tp).unwrap()), 40);
+
+        // A task inherited from what made the media keeps its own quality.
+        let inherited = task
+            .with_default_quality(NonZeroU8::new(90).unwrap())
+            .unwrap();
+        let parsed = inherited.parse_inherited_params_text(&tp, tp.get_params());
+        assert_eq!(quality(parsed.unwrap()), 90);
+
+        // Remembered across restarts.
+        let restarted = idle_taskman(db).await;
+        assert_eq!(restarted.default_quality_for(message.chat.id), Some(forty));
+        taskman
+            .set_chat_default_quality(message.chat.id, None)
+            .await
+            .unwrap();
+        assert_eq!(taskman.default_quality_for(message.chat.id), None);
+        assert_eq!(quality(task.parse_params(&tp).unwrap()), usual);
+    }
+}